@@ -16,6 +16,7 @@
 
 use digest::Digest;
 use typenum::Unsigned;
+use core::marker::PhantomData;
 use serde::{Serialize, Deserialize};
 use crate::primitives::{H256, Uint, Signature, ValidatorId};
 
@@ -51,6 +52,49 @@ impl BLSConfig for BLSNoVerification {
 	}
 }
 
+/// Run bls for blocks whose proposer signatures were already verified in
+/// batch, and which are known to be valid. Proposer, randao and attestation
+/// signatures are skipped. Others, such as deposit proofs of possession, are
+/// still verified with `BLS`, as a block can validly include deposits with
+/// invalid signatures, which must then be skipped.
+pub struct BLSBatchVerified<C, BLS>(PhantomData<(C, BLS)>);
+
+impl<C, BLS> Default for BLSBatchVerified<C, BLS> {
+	fn default() -> Self {
+		BLSBatchVerified(PhantomData)
+	}
+}
+
+impl<C, BLS> Clone for BLSBatchVerified<C, BLS> {
+	fn clone(&self) -> Self {
+		BLSBatchVerified(PhantomData)
+	}
+}
+
+impl<C: Config, BLS: BLSConfig> BLSBatchVerified<C, BLS> {
+	fn skipped(domain: u64) -> bool {
+		let domain_type = (domain & 0xffff_ffff) as u32;
+		domain_type == C::domain_beacon_proposer() ||
+			domain_type == C::domain_randao() ||
+			domain_type == C::domain_beacon_attester()
+	}
+}
+
+impl<C: Config, BLS: BLSConfig> BLSConfig for BLSBatchVerified<C, BLS> {
+	fn verify(pubkey: &ValidatorId, message: &H256, signature: &Signature, domain: u64) -> bool {
+		Self::skipped(domain) || BLS::verify(pubkey, message, signature, domain)
+	}
+	fn aggregate_pubkeys(pubkeys: &[ValidatorId]) -> ValidatorId {
+		BLS::aggregate_pubkeys(pubkeys)
+	}
+	fn aggregate_signatures(signatures: &[Signature]) -> Signature {
+		BLS::aggregate_signatures(signatures)
+	}
+	fn verify_multiple(pubkeys: &[ValidatorId], messages: &[H256], signature: &Signature, domain: u64) -> bool {
+		Self::skipped(domain) || BLS::verify_multiple(pubkeys, messages, signature, domain)
+	}
+}
+
 /// Constants used in beacon block.
 pub trait Config: Default + Clone + PartialEq + Eq + core::fmt::Debug + Send + Sync + 'static {
 	/// Digest hash function.
@@ -123,6 +167,8 @@ pub trait Config: Default + Clone + PartialEq + Eq + core::fmt::Debug + Send + S
 	fn bls_withdrawal_prefix_byte() -> u8;
 
 	// == Time parameters ==
+	/// Seconds per slot.
+	fn seconds_per_slot() -> Uint;
 	/// Minimum attestation inclusion delay.
 	fn min_attestation_inclusion_delay() -> Uint;
 	/// Slots per epoch.
@@ -133,6 +179,10 @@ pub trait Config: Default + Clone + PartialEq + Eq + core::fmt::Debug + Send + S
 	fn max_seed_lookahead() -> Uint;
 	/// Slots per eth1 voting period.
 	fn slots_per_eth1_voting_period() -> Uint { Self::SlotsPerEth1VotingPeriod::to_u64() }
+	/// Eth1 blocks an eth1 data vote stays behind the eth1 head.
+	fn eth1_follow_distance() -> Uint;
+	/// Expected seconds between eth1 blocks.
+	fn seconds_per_eth1_block() -> Uint;
 	/// Slots per historical root.
 	fn slots_per_historical_root() -> Uint { Self::SlotsPerHistoricalRoot::to_u64() }
 	/// Minimal validator withdrawability delay.
@@ -249,9 +299,12 @@ impl Config for MinimalConfig {
 	fn bls_withdrawal_prefix_byte() -> u8 { 0x00 }
 
 	// == Time parameters ==
+	fn seconds_per_slot() -> Uint { 6 }
 	fn min_attestation_inclusion_delay() -> Uint { 1 }
 	fn min_seed_lookahead() -> Uint { 1 }
 	fn max_seed_lookahead() -> Uint { 4 }
+	fn eth1_follow_distance() -> Uint { 16 }
+	fn seconds_per_eth1_block() -> Uint { 14 }
 	fn min_validator_withdrawability_delay() -> Uint { 256 }
 	fn persistent_committee_period() -> Uint { 2048 }
 	fn max_epochs_per_crosslink() -> Uint { 4 }
@@ -311,9 +364,12 @@ impl Config for MainnetConfig {
 	fn bls_withdrawal_prefix_byte() -> u8 { 0x00 }
 
 	// == Time parameters ==
+	fn seconds_per_slot() -> Uint { 12 }
 	fn min_attestation_inclusion_delay() -> Uint { 1 }
 	fn min_seed_lookahead() -> Uint { 1 }
 	fn max_seed_lookahead() -> Uint { 4 }
+	fn eth1_follow_distance() -> Uint { 1024 }
+	fn seconds_per_eth1_block() -> Uint { 14 }
 	fn min_validator_withdrawability_delay() -> Uint { 256 }
 	fn persistent_committee_period() -> Uint { 2048 }
 	fn max_epochs_per_crosslink() -> Uint { 64 }
@@ -373,9 +429,12 @@ impl Config for SapphireConfig {
 	fn bls_withdrawal_prefix_byte() -> u8 { 0x00 }
 
 	// == Time parameters ==
+	fn seconds_per_slot() -> Uint { 12 }
 	fn min_attestation_inclusion_delay() -> Uint { 1 }
 	fn min_seed_lookahead() -> Uint { 1 }
 	fn max_seed_lookahead() -> Uint { 4 }
+	fn eth1_follow_distance() -> Uint { 1024 }
+	fn seconds_per_eth1_block() -> Uint { 14 }
 	fn min_validator_withdrawability_delay() -> Uint { 256 }
 	fn persistent_committee_period() -> Uint { 2048 }
 	fn max_epochs_per_crosslink() -> Uint { 4 }
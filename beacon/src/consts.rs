@@ -30,3 +30,8 @@ pub type DepositContractTreeDepth = typenum::U32;
 pub const SECONDS_PER_DAY: Uint = 86400;
 /// Justification bits length;
 pub type JustificationBitsLength = typenum::U4;
+/// Proposer score boost, in percentage of committee weight.
+pub const PROPOSER_SCORE_BOOST: Uint = 40;
+/// Maximum length in bytes of a shard blob.
+#[cfg(feature = "shards")]
+pub const MAX_SHARD_BLOB_LENGTH: Uint = 1 << 20;
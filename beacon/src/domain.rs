@@ -0,0 +1,90 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Signing domains and signing roots, shared by block production, state
+//! transition and signature verification.
+
+use crate::primitives::{Epoch, Uint, Version, H256};
+use crate::types::{
+	Fork, AttestationData, AttestationDataAndCustodyBit, BeaconBlockHeader,
+	SigningBeaconBlockHeader, UnsealedBeaconBlock, Block, VoluntaryExit,
+	SigningVoluntaryExit, DepositData, SigningDepositData,
+};
+use crate::Config;
+use bm_le::{tree_root, IntoTree};
+
+/// Fork version in effect at `epoch`.
+pub fn fork_version(fork: &Fork, epoch: Epoch) -> Version {
+	if epoch < fork.epoch {
+		fork.previous_version
+	} else {
+		fork.current_version
+	}
+}
+
+/// Domain of `domain_type` under the given fork version.
+pub fn compute_domain(domain_type: u32, fork_version: Version) -> Uint {
+	let mut bytes = [0u8; 8];
+	(&mut bytes[0..4]).copy_from_slice(&domain_type.to_le_bytes()[..]);
+	(&mut bytes[4..8]).copy_from_slice(fork_version.as_ref());
+
+	u64::from_le_bytes(bytes)
+}
+
+/// Domain of `domain_type` for a message of `epoch`.
+pub fn domain(domain_type: u32, fork: &Fork, epoch: Epoch) -> Uint {
+	compute_domain(domain_type, fork_version(fork, epoch))
+}
+
+/// Domain of `domain_type` independent of forks, used for deposits.
+pub fn genesis_domain(domain_type: u32) -> Uint {
+	compute_domain(domain_type, Default::default())
+}
+
+/// Signing root of an object.
+pub fn signing_root<C: Config, T: IntoTree>(object: &T) -> H256 {
+	tree_root::<C::Digest, _>(object)
+}
+
+/// Signing root of a block, signed by its proposer.
+pub fn block_signing_root<C: Config, B: Block<Config=C>>(block: &B) -> H256 {
+	signing_root::<C, _>(&UnsealedBeaconBlock::from(block))
+}
+
+/// Signing root of a block header.
+pub fn header_signing_root<C: Config>(header: &BeaconBlockHeader) -> H256 {
+	signing_root::<C, _>(&SigningBeaconBlockHeader::from(header.clone()))
+}
+
+/// Signing root of a RANDAO reveal for `epoch`.
+pub fn randao_signing_root<C: Config>(epoch: Epoch) -> H256 {
+	signing_root::<C, _>(&epoch)
+}
+
+/// Signing root of attestation data with a custody bit.
+pub fn attestation_signing_root<C: Config>(data: &AttestationData, custody_bit: bool) -> H256 {
+	signing_root::<C, _>(&AttestationDataAndCustodyBit { data: data.clone(), custody_bit })
+}
+
+/// Signing root of a voluntary exit.
+pub fn voluntary_exit_signing_root<C: Config>(exit: &VoluntaryExit) -> H256 {
+	signing_root::<C, _>(&SigningVoluntaryExit::from(exit.clone()))
+}
+
+/// Signing root of deposit data.
+pub fn deposit_signing_root<C: Config>(data: &DepositData) -> H256 {
+	signing_root::<C, _>(&SigningDepositData::from(data.clone()))
+}
@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
+use core::fmt;
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 /// Error type for beacon chain.
 pub enum Error {
@@ -123,10 +125,100 @@ pub enum Error {
 	TooManyAttestations,
 	/// Too many deposits in a block.
 	TooManyDeposits,
+	/// Block does not include all pending deposits.
+	MissingDeposits,
 	/// Too many voluntary exits in a block.
 	TooManyVoluntaryExits,
 	/// Too many transfers in a block.
 	TooManyTransfers,
 	/// Invalid eth1 data.
 	InvalidEth1Data,
+	/// Shard blob commitment is for a shard out of range.
+	#[cfg(feature = "shards")]
+	ShardOutOfRange,
+	/// Shard blob commitment is not for the block slot.
+	#[cfg(feature = "shards")]
+	ShardBlobSlotMismatch,
+	/// Shard blob commitments are not sorted by shard, or duplicated.
+	#[cfg(feature = "shards")]
+	ShardBlobCommitmentsUnsorted,
+	/// Shard blob is longer than allowed.
+	#[cfg(feature = "shards")]
+	ShardBlobTooLong,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let message = match self {
+			Error::DepositIndexMismatch => "Deposit index mismatch",
+			Error::DepositMerkleInvalid => "Deposit merkle is invalid",
+			Error::DepositProofInvalid => "Deposit proof is invalid",
+			Error::DepositWithdrawalCredentialsMismatch => "Deposit withdrawal credentials do not match",
+			Error::DuplicateIndexes => "Duplicate indexes",
+			Error::DuplicateTransfer => "Duplicate transfer",
+			Error::IndexOutOfRange => "Index is out of range",
+			Error::EpochOutOfRange => "Epoch is out of range",
+			Error::SlotOutOfRange => "Slot is out of range",
+			Error::AttestationShardInvalid => "Attestation shard is invalid",
+			Error::AttestationBitFieldInvalid => "Attestation bitfield is invalid",
+			Error::ValidatorNotWithdrawable => "Validator is not yet withdrawable",
+			Error::ValidatorAttestationNotFound => "Validator's attestation not found",
+			Error::BlockStateRootInvalid => "Block state root is invalid",
+			Error::BlockSlotInvalid => "Block slot is invalid",
+			Error::BlockProposerSlashed => "Block proposer has been slashed",
+			Error::BlockPreviousRootInvalid => "Block previous root is invalid",
+			Error::BlockSignatureInvalid => "Block signature is invalid",
+			Error::RandaoSignatureInvalid => "Randao signature is invalid",
+			Error::ProposerSlashingInvalidProposerIndex => "Proposer slashing contains invalid proposer index",
+			Error::ProposerSlashingInvalidSlot => "Proposer slashing contains invalid slot",
+			Error::ProposerSlashingSameHeader => "Proposer slashing is on same header",
+			Error::ProposerSlashingAlreadySlashed => "Proposer slashing has already been slashed",
+			Error::ProposerSlashingInvalidSignature => "Proposer slashing contains invalid signature",
+			Error::AttesterSlashingSameAttestation => "Attester slashing is on same attestation",
+			Error::AttesterSlashingNotSlashable => "Attester slashing is not slashable",
+			Error::AttesterSlashingInvalid => "Attester slashing is invalid",
+			Error::AttesterSlashingEmptyIndices => "Attester slashing is on empty indices",
+			Error::AttestationTooFarInHistory => "Attestation is too far in the past",
+			Error::AttestationSubmittedTooQuickly => "Attestation submitted too quickly",
+			Error::AttestationIncorrectJustifiedEpochOrBlockRoot => "Attestation contains incorrect justified epoch or block root",
+			Error::AttestationIncorrectCrosslinkData => "Attestation contains incorrect crosslink data",
+			Error::AttestationEmptyAggregation => "Attestation has empty aggregation",
+			Error::AttestationEmptyCustody => "Attestation has empty custody",
+			Error::AttestationInvalidData => "Attestation data is invalid",
+			Error::AttestationInvalidShard => "Attestation is on invalid shard",
+			Error::AttestationInvalidCustody => "Attestation has invalid custody",
+			Error::AttestationInvalidSignature => "Attestation has invalid signature",
+			Error::AttestationInvalidCrosslink => "Attestation has invalid crosslink",
+			Error::VoluntaryExitAlreadyExited => "Voluntary exit has already exited",
+			Error::VoluntaryExitAlreadyInitiated => "Voluntary exit has already been initiated",
+			Error::VoluntaryExitNotYetValid => "Voluntary exit is not yet valid",
+			Error::VoluntaryExitNotLongEnough => "Voluntary exit is not long enough",
+			Error::VoluntaryExitInvalidSignature => "Voluntary exit contains invalid signature",
+			Error::TransferNoFund => "Transfer does not have enough funds",
+			Error::TransferNotValidSlot => "Transfer is not on valid slot",
+			Error::TransferNotWithdrawable => "Transfer is not withdrawable",
+			Error::TransferInvalidPublicKey => "Transfer has invalid public key",
+			Error::TransferInvalidSignature => "Transfer has invalid signature",
+			Error::TooManyProposerSlashings => "Too many proposer slashings in a block",
+			Error::TooManyAttesterSlashings => "Too many attester slashings in a block",
+			Error::TooManyAttestations => "Too many attestations in a block",
+			Error::TooManyDeposits => "Too many deposits in a block",
+			Error::MissingDeposits => "Block does not include all pending deposits",
+			Error::TooManyVoluntaryExits => "Too many voluntary exits in a block",
+			Error::TooManyTransfers => "Too many transfers in a block",
+			Error::InvalidEth1Data => "Invalid eth1 data",
+			#[cfg(feature = "shards")]
+			Error::ShardOutOfRange => "Shard blob commitment is for a shard out of range",
+			#[cfg(feature = "shards")]
+			Error::ShardBlobSlotMismatch => "Shard blob commitment is not for the block slot",
+			#[cfg(feature = "shards")]
+			Error::ShardBlobCommitmentsUnsorted => "Shard blob commitments are not sorted by shard, or duplicated",
+			#[cfg(feature = "shards")]
+			Error::ShardBlobTooLong => "Shard blob is longer than allowed",
+		};
+		f.write_str(message)
+	}
 }
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error { }
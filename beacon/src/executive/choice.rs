@@ -15,8 +15,16 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::primitives::H256;
-use crate::types::BeaconBlock;
-use crate::{Config, BeaconExecutive, Error};
+use crate::types::{Attestation, BeaconBlock, Checkpoint, IndexedAttestation};
+use crate::components::Justifier;
+use crate::{Config, BeaconExecutive, BLSConfig, Error, consts, utils};
+
+fn vote_targets<C: Config>(indexed: &IndexedAttestation<C>) -> Vec<(u64, H256)> {
+	indexed.custody_bit_0_indices.iter().cloned()
+		.chain(indexed.custody_bit_1_indices.iter().cloned())
+		.map(|v| (v, indexed.data.target.root))
+		.collect()
+}
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Get justified active validators from current state.
@@ -25,16 +33,113 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		self.active_validator_indices(current_justified_epoch)
 	}
 
-	/// Get block attestation vote targets.
-	pub fn block_vote_targets(&self, block: &BeaconBlock<C>) -> Result<Vec<(u64, H256)>, Error> {
+	/// Get the proposer boost score, as a fraction of the weight of a single
+	/// committee of justified active validators.
+	pub fn proposer_boost_score(&self) -> u64 {
+		let committee_weight = self.justified_active_validators().len() as u64 /
+			C::slots_per_epoch();
+		committee_weight * consts::PROPOSER_SCORE_BOOST / 100
+	}
+
+	/// Get the justified and finalized checkpoints the state would reach if
+	/// justification and finalization were processed now, without waiting for
+	/// the epoch boundary.
+	pub fn unrealized_checkpoints(&self) -> Result<(Checkpoint, Checkpoint), Error> {
+		let current_epoch = self.current_epoch();
+		if current_epoch <= C::genesis_epoch() + 1 ||
+			self.slot == utils::start_slot_of_epoch::<C>(current_epoch)
+		{
+			return Ok((
+				self.current_justified_checkpoint.clone(),
+				self.finalized_checkpoint.clone(),
+			))
+		}
+
+		let previous_epoch = self.previous_epoch();
+		let previous_checkpoint = Checkpoint {
+			epoch: previous_epoch,
+			root: self.block_root(previous_epoch)?,
+		};
+		let current_checkpoint = Checkpoint {
+			epoch: current_epoch,
+			root: self.block_root(current_epoch)?,
+		};
+
+		let mut processor = Justifier {
+			justification_bits: self.justification_bits.clone(),
+			current_justified_checkpoint: self.current_justified_checkpoint.clone(),
+			previous_justified_checkpoint: self.previous_justified_checkpoint.clone(),
+			finalized_checkpoint: self.finalized_checkpoint.clone(),
+		};
+
+		processor.process(previous_checkpoint, current_checkpoint, self)?;
+
+		Ok((processor.current_justified_checkpoint, processor.finalized_checkpoint))
+	}
+
+	/// Get block attestation vote targets, with the slots they attest.
+	pub fn block_vote_targets(&self, block: &BeaconBlock<C>) -> Result<Vec<(u64, u64, H256)>, Error> {
 		let mut ret = Vec::new();
 		for attestation in block.body.attestations.iter() {
-			let indexed = self.indexed_attestation(attestation.clone())?;
+			let slot = attestation.data.slot;
+			for (v, target) in self.attestation_vote_targets(attestation)? {
+				ret.push((slot, v, target));
+			}
+		}
+
+		Ok(ret)
+	}
+
+	/// Get vote targets of a single attestation.
+	pub fn attestation_vote_targets(
+		&self,
+		attestation: &Attestation<C>
+	) -> Result<Vec<(u64, H256)>, Error> {
+		let indexed = self.indexed_attestation(attestation.clone())?;
+
+		Ok(vote_targets(&indexed))
+	}
+
+	/// Get vote targets of an attestation received outside of a block, after
+	/// checking its signature.
+	pub fn verified_attestation_vote_targets<BLS: BLSConfig>(
+		&self,
+		attestation: &Attestation<C>
+	) -> Result<Vec<(u64, H256)>, Error> {
+		let indexed = self.indexed_attestation(attestation.clone())?;
+		if !self.is_valid_indexed_attestation::<BLS>(&indexed) {
+			return Err(Error::AttestationInvalidSignature)
+		}
+
+		Ok(vote_targets(&indexed))
+	}
+
+	/// Get validators proven to equivocate by the attester slashings of a
+	/// block.
+	pub fn block_equivocating_validators(
+		&self,
+		block: &BeaconBlock<C>
+	) -> Result<Vec<u64>, Error> {
+		let mut ret = Vec::new();
+		for slashing in block.body.attester_slashings.iter() {
+			let attestation_1 = &slashing.attestation_1;
+			let attestation_2 = &slashing.attestation_2;
+
+			if !attestation_1.data.is_slashable(&attestation_2.data) {
+				return Err(Error::AttesterSlashingNotSlashable)
+			}
+
+			let indices_2 = attestation_2.custody_bit_0_indices.iter()
+				.chain(attestation_2.custody_bit_1_indices.iter())
+				.cloned()
+				.collect::<Vec<_>>();
 
-			for v in indexed.custody_bit_0_indices.iter().cloned()
-				.chain(indexed.custody_bit_1_indices.iter().cloned())
+			for index in attestation_1.custody_bit_0_indices.iter()
+				.chain(attestation_1.custody_bit_1_indices.iter())
 			{
-				ret.push((v, indexed.data.target.root));
+				if indices_2.contains(index) && !ret.contains(index) {
+					ret.push(*index);
+				}
 			}
 		}
 
@@ -16,7 +16,7 @@
 
 use crate::types::{AttestationData, Attestation, IndexedAttestation};
 use crate::primitives::{Epoch, H256, Uint, ValidatorIndex, Gwei, Slot};
-use crate::{BeaconExecutive, Config, Error, utils};
+use crate::{BeaconExecutive, Config, Error, utils, domain};
 use core::cmp::{max, min};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
@@ -72,7 +72,11 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 	/// Get active validator length.
 	pub fn active_validator_len(&self, epoch: Uint) -> usize {
-		self.active_validator_indices(epoch).len()
+		match &self.participation {
+			Some(participation) if participation.current_epoch == epoch =>
+				participation.active_validator_count,
+			_ => self.active_validator_indices(epoch).len(),
+		}
 	}
 
 	/// Get churn limit for validator exits.
@@ -132,6 +136,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 			&self.slot.to_le_bytes()[..8]
 		]);
 		let indices = self.active_validator_indices(epoch);
+		let max_effective_balance = C::max_effective_balance();
 
 		let mut i = 0;
 		loop {
@@ -148,7 +153,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 			])[(i % 32) as usize];
 			let effective_balance = self.validators[candidate_index as usize].effective_balance;
 			if effective_balance * u8::max_value() as u64 >=
-				C::max_effective_balance() * random_byte as u64
+				max_effective_balance * random_byte as u64
 			{
 				return Ok(candidate_index)
 			}
@@ -169,19 +174,17 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 	/// Get total balance of active validators.
 	pub fn total_active_balance(&self) -> Gwei {
-		self.total_balance(&self.active_validator_indices(self.current_epoch()))
+		match &self.participation {
+			Some(participation) if participation.current_epoch == self.current_epoch() =>
+				participation.total_active_balance,
+			_ => self.total_balance(&self.active_validator_indices(self.current_epoch())),
+		}
 	}
 
 	/// Get signing domain, given domain type and message epoch.
 	pub fn domain(&self, domain_type: u32, message_epoch: Option<Uint>) -> Uint {
 		let epoch = message_epoch.unwrap_or(self.current_epoch());
-		let fork_version = if epoch < self.fork.epoch {
-			self.fork.previous_version
-		} else {
-			self.fork.current_version
-		};
-
-		utils::bls_domain(domain_type, fork_version)
+		domain::domain(domain_type, &self.fork, epoch)
 	}
 
 	/// Convert an attestation to indexed attestation.
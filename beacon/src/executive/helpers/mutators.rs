@@ -30,6 +30,12 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 			self.balances[index as usize].saturating_sub(delta);
 	}
 
+	/// Apply rewards and penalties to all validators at once, both indexed by
+	/// validator.
+	pub fn apply_balance_deltas(&mut self, rewards: &[Gwei], penalties: &[Gwei]) {
+		utils::apply_balance_deltas(&mut self.state.balances[..], rewards, penalties);
+	}
+
 	/// Initiate validator exit.
 	pub fn initiate_validator_exit(&mut self, index: ValidatorIndex) {
 		if self.validators[index as usize].exit_epoch !=
@@ -14,9 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{IndexedAttestation, AttestationDataAndCustodyBit};
-use crate::{BeaconExecutive, Config, BLSConfig};
-use bm_le::tree_root;
+use crate::types::IndexedAttestation;
+use crate::{BeaconExecutive, Config, BLSConfig, domain};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Check if ``indexed_attestation`` has valid indices and signature.
@@ -68,14 +67,8 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 				),
 			],
 			&[
-				tree_root::<C::Digest, _>(&AttestationDataAndCustodyBit {
-					data: indexed_attestation.data.clone(),
-					custody_bit: false,
-				}),
-				tree_root::<C::Digest, _>(&AttestationDataAndCustodyBit {
-					data: indexed_attestation.data.clone(),
-					custody_bit: true,
-				}),
+				domain::attestation_signing_root::<C>(&indexed_attestation.data, false),
+				domain::attestation_signing_root::<C>(&indexed_attestation.data, true),
 			],
 			&indexed_attestation.signature,
 			self.domain(
@@ -20,6 +20,7 @@ mod choice;
 mod assignment;
 
 pub use self::assignment::CommitteeAssignment;
+pub use self::transition::{ParticipationCache, ValidatorParticipation, Inclusion};
 
 use core::ops::Deref;
 #[cfg(feature = "serde")]
@@ -42,6 +43,7 @@ pub struct BeaconExecutive<'a, C: Config> {
 
 	active_validator_indices: Option<Vec<ValidatorIndex>>,
 	total_active_balance: Option<Gwei>,
+	participation: Option<ParticipationCache>,
 }
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
@@ -52,6 +54,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 			active_validator_indices: None,
 			total_active_balance: None,
+			participation: None,
 		}
 	}
 }
@@ -98,6 +101,12 @@ impl<'a, C: Config> JustifierRegistry for BeaconExecutive<'a, C> {
 		&self,
 		checkpoint: &Self::Checkpoint
 	) -> Result<u64, Self::Error> {
+		if let Some(balance) = self.participation.as_ref()
+			.and_then(|participation| participation.target_balance(checkpoint.epoch))
+		{
+			return Ok(balance)
+		}
+
 		self.attesting_balance(&self.matching_target_attestations(checkpoint.epoch)?)
 	}
 }
@@ -230,7 +239,8 @@ pub struct BeaconState<C: Config> {
 	pub block_roots: VecArray<H256, C::SlotsPerHistoricalRoot>,
 	/// Past state roots.
 	pub state_roots: VecArray<H256, C::SlotsPerHistoricalRoot>,
-	/// Past historical roots.
+	/// Past historical roots. Historical root proofs rely on this being
+	/// the seventh field, at index 6.
 	pub historical_roots: MaxVec<H256, C::HistoricalRootsLimit>,
 
 	// == Eth1 ==
@@ -17,9 +17,12 @@
 mod per_block;
 mod per_epoch;
 
+pub use self::per_epoch::{ParticipationCache, ValidatorParticipation, Inclusion};
+
 use crate::primitives::{Uint, H256};
 use crate::types::{Block, SigningBeaconBlockHeader};
 use crate::{Error, Config, BeaconExecutive, BLSConfig};
+use crate::trace::{Tracer, TraceOperation, NoTracer};
 use bm_le::tree_root;
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
@@ -28,8 +31,19 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		&mut self,
 		block: &B,
 	) -> Result<(), Error> {
+		self.state_transition_with::<_, BLS, _>(block, &mut NoTracer)
+	}
+
+	/// Execute state transition, reporting each step to the tracer.
+	pub fn state_transition_with<B: Block<Config=C>, BLS: BLSConfig, T: Tracer<C>>(
+		&mut self,
+		block: &B,
+		tracer: &mut T,
+	) -> Result<(), Error> {
+		let from = self.slot;
 		self.process_slots(block.slot())?;
-		self.process_block::<_, BLS>(block)?;
+		tracer.step(TraceOperation::Slots { from, to: self.slot }, self.state);
+		self.process_block_with::<_, BLS, _>(block, tracer)?;
 
 		if !(block.state_root() == &tree_root::<C::Digest, _>(self.state)) {
 			return Err(Error::BlockStateRootInvalid)
@@ -14,10 +14,27 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{UnsealedBeaconBlock, SigningBeaconBlockHeader, BeaconBlockHeader, Block};
-use crate::{Config, BeaconExecutive, Error, BLSConfig};
+use crate::types::{
+	UnsealedBeaconBlock, SigningBeaconBlockHeader, BeaconBlockHeader, BeaconBlock, Block,
+};
+use crate::{Config, BeaconExecutive, Error, BLSConfig, ProposerSignature, utils, domain};
+use crate::primitives::H256;
 use bm_le::tree_root;
 
+/// Signing roots of blocks, hashed in parallel with the `parallel` feature.
+#[cfg(feature = "parallel")]
+fn signing_roots<C: Config>(blocks: &[BeaconBlock<C>]) -> Vec<H256> {
+	use rayon::prelude::*;
+
+	blocks.par_iter().map(|block| domain::block_signing_root::<C, _>(block)).collect()
+}
+
+/// Signing roots of blocks, hashed in parallel with the `parallel` feature.
+#[cfg(not(feature = "parallel"))]
+fn signing_roots<C: Config>(blocks: &[BeaconBlock<C>]) -> Vec<H256> {
+	blocks.iter().map(|block| domain::block_signing_root::<C, _>(block)).collect()
+}
+
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process a block header.
 	pub fn process_block_header<'b, B: Block, BLS: BLSConfig>(
@@ -53,7 +70,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		if let Some(signature) = block.signature() {
 			if !BLS::verify(
 				&proposer.pubkey,
-				&tree_root::<C::Digest, _>(&UnsealedBeaconBlock::from(block)),
+				&domain::signing_root::<C, _>(&UnsealedBeaconBlock::from(block)),
 				signature,
 				self.domain(C::domain_beacon_proposer(), None)
 			) {
@@ -63,4 +80,41 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 		Ok(())
 	}
+
+	/// Collect proposer signatures of blocks within a single epoch, in
+	/// ascending slot order, without processing the blocks. Slots are
+	/// processed up to the first block.
+	pub fn proposer_signatures(
+		&mut self,
+		blocks: &[BeaconBlock<C>],
+	) -> Result<Vec<ProposerSignature>, Error> {
+		let first_slot = match blocks.first() {
+			Some(block) => block.slot,
+			None => return Ok(Vec::new()),
+		};
+		self.process_slots(first_slot)?;
+		let epoch = self.current_epoch();
+
+		let messages = signing_roots(blocks);
+		let mut signatures = Vec::new();
+		for (block, message) in blocks.iter().zip(messages) {
+			if block.slot < self.slot || utils::epoch_of_slot::<C>(block.slot) != epoch {
+				return Err(Error::BlockSlotInvalid)
+			}
+			// Proposers of an epoch only depend on the state at its start.
+			self.state.slot = block.slot;
+
+			let proposer = &self.validators[
+				self.beacon_proposer_index()? as usize
+			];
+			signatures.push(ProposerSignature {
+				pubkey: proposer.pubkey.clone(),
+				message,
+				signature: block.signature.clone(),
+				domain: self.domain(C::domain_beacon_proposer(), None),
+			});
+		}
+
+		Ok(signatures)
+	}
 }
@@ -18,8 +18,11 @@ mod header;
 mod randao;
 mod eth1;
 mod operations;
+#[cfg(feature = "shards")]
+mod shard;
 
 use crate::types::{Block, UnsealedBeaconBlock};
+use crate::trace::{Tracer, TraceOperation, NoTracer};
 use crate::{Config, BLSConfig, BeaconExecutive, Error};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
@@ -29,11 +32,30 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		block: &'b B,
 	) -> Result<(), Error> where
 		UnsealedBeaconBlock<C>: From<&'b B>,
+	{
+		self.process_block_with::<_, BLS, _>(block, &mut NoTracer)
+	}
+
+	/// Process a block, reporting each step to the tracer.
+	pub fn process_block_with<'b, B: Block<Config=C>, BLS: BLSConfig, T: Tracer<C>>(
+		&mut self,
+		block: &'b B,
+		tracer: &mut T,
+	) -> Result<(), Error> where
+		UnsealedBeaconBlock<C>: From<&'b B>,
 	{
 		self.process_block_header::<_, BLS>(block)?;
+		tracer.step(TraceOperation::BlockHeader, self.state);
 		self.process_randao::<BLS>(block.body())?;
+		tracer.step(TraceOperation::Randao, self.state);
 		self.process_eth1_data(block.body());
-		self.process_operations::<BLS>(block.body())?;
+		tracer.step(TraceOperation::Eth1Data, self.state);
+		self.process_operations_with::<BLS, _>(block.body(), tracer)?;
+		#[cfg(feature = "shards")]
+		{
+			self.process_shard_blob_commitments(block.slot(), block.body())?;
+			tracer.step(TraceOperation::ShardBlobCommitments, self.state);
+		}
 
 		Ok(())
 	}
@@ -14,8 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{Deposit, Validator, SigningDepositData};
-use crate::{Config, BeaconExecutive, Error, BLSConfig, utils, consts};
+use crate::types::{Deposit, Validator};
+use crate::{Config, BeaconExecutive, Error, BLSConfig, utils, consts, domain};
 use bm_le::tree_root;
 use core::cmp::min;
 
@@ -45,7 +45,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 			// included on-chain, but must not be processed.
 			if !BLS::verify(
 				&pubkey,
-				&tree_root::<C::Digest, _>(&SigningDepositData::from(deposit.data.clone())),
+				&domain::deposit_signing_root::<C>(&deposit.data),
 				&deposit.data.signature,
 				self.domain(C::domain_deposit(), None)
 			) {
@@ -21,6 +21,7 @@ mod deposit;
 mod voluntary_exit;
 
 use crate::types::BeaconBlockBody;
+use crate::trace::{Tracer, TraceOperation, NoTracer};
 use crate::{Config, BLSConfig, BeaconExecutive, Error};
 use core::cmp::min;
 
@@ -29,6 +30,15 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 	pub fn process_operations<BLS: BLSConfig>(
 		&mut self,
 		body: &BeaconBlockBody<C>
+	) -> Result<(), Error> {
+		self.process_operations_with::<BLS, _>(body, &mut NoTracer)
+	}
+
+	/// Process block operations, reporting each one to the tracer.
+	pub fn process_operations_with<BLS: BLSConfig, T: Tracer<C>>(
+		&mut self,
+		body: &BeaconBlockBody<C>,
+		tracer: &mut T,
 	) -> Result<(), Error> {
 		// Verify that outstanding deposits are processed up to the maximum
 		// number of deposits
@@ -43,36 +53,41 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		if body.proposer_slashings.len() > C::max_proposer_slashings() as usize {
 			return Err(Error::TooManyProposerSlashings)
 		}
-		for slashing in body.proposer_slashings.iter() {
+		for (i, slashing) in body.proposer_slashings.iter().enumerate() {
 			self.process_proposer_slashing::<BLS>(slashing.clone())?;
+			tracer.step(TraceOperation::ProposerSlashing(i), self.state);
 		}
 
 		if body.attester_slashings.len() > C::max_attester_slashings() as usize {
 			return Err(Error::TooManyAttesterSlashings)
 		}
-		for slashing in body.attester_slashings.iter() {
+		for (i, slashing) in body.attester_slashings.iter().enumerate() {
 			self.process_attester_slashing::<BLS>(slashing.clone())?;
+			tracer.step(TraceOperation::AttesterSlashing(i), self.state);
 		}
 
 		if body.attestations.len() > C::max_attestations() as usize {
 			return Err(Error::TooManyAttestations)
 		}
-		for attestation in body.attestations.iter() {
+		for (i, attestation) in body.attestations.iter().enumerate() {
 			self.process_attestation::<BLS>(attestation.clone())?;
+			tracer.step(TraceOperation::Attestation(i), self.state);
 		}
 
 		if body.deposits.len() > C::max_deposits() as usize {
 			return Err(Error::TooManyDeposits)
 		}
-		for deposit in body.deposits.iter() {
+		for (i, deposit) in body.deposits.iter().enumerate() {
 			self.process_deposit::<BLS>(deposit.clone())?;
+			tracer.step(TraceOperation::Deposit(i), self.state);
 		}
 
 		if body.voluntary_exits.len() > C::max_voluntary_exits() as usize {
 			return Err(Error::TooManyVoluntaryExits)
 		}
-		for voluntary_exit in body.voluntary_exits.iter() {
+		for (i, voluntary_exit) in body.voluntary_exits.iter().enumerate() {
 			self.process_voluntary_exit::<BLS>(voluntary_exit.clone())?;
+			tracer.step(TraceOperation::VoluntaryExit(i), self.state);
 		}
 
 		Ok(())
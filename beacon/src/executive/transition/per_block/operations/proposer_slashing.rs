@@ -14,9 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{ProposerSlashing, SigningBeaconBlockHeader};
-use crate::{Config, BeaconExecutive, Error, BLSConfig, utils};
-use bm_le::tree_root;
+use crate::types::ProposerSlashing;
+use crate::{Config, BeaconExecutive, Error, BLSConfig, utils, domain};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Push a new `ProposerSlashing` to the state.
@@ -53,7 +52,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 				if !BLS::verify(
 					&proposer.pubkey,
-					&tree_root::<C::Digest, _>(&SigningBeaconBlockHeader::from((*header).clone())),
+					&domain::header_signing_root::<C>(header),
 					&header.signature,
 					domain,
 				) {
@@ -14,9 +14,8 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::types::{VoluntaryExit, SigningVoluntaryExit};
-use crate::{Config, BeaconExecutive, Error, BLSConfig, consts};
-use bm_le::tree_root;
+use crate::types::VoluntaryExit;
+use crate::{Config, BeaconExecutive, Error, BLSConfig, consts, domain};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Push a new `VoluntaryExit` to the state.
@@ -53,7 +52,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 			);
 			if !BLS::verify(
 				&validator.pubkey,
-				&tree_root::<C::Digest, _>(&SigningVoluntaryExit::from(exit.clone())),
+				&domain::voluntary_exit_signing_root::<C>(&exit),
 				&exit.signature,
 				domain
 			) {
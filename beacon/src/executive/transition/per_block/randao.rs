@@ -15,8 +15,7 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::types::BeaconBlockBody;
-use crate::{Config, BeaconExecutive, Error, BLSConfig};
-use bm_le::tree_root;
+use crate::{Config, BeaconExecutive, Error, BLSConfig, domain};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process randao information given in a block.
@@ -27,7 +26,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 
 		if !BLS::verify(
 			&proposer.pubkey,
-			&tree_root::<C::Digest, _>(&self.current_epoch()),
+			&domain::randao_signing_root::<C>(self.current_epoch()),
 			&body.randao_reveal,
 			self.domain(C::domain_randao(), None)
 		) {
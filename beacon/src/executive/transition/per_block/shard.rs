@@ -0,0 +1,50 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use typenum::Unsigned;
+use crate::types::BeaconBlockBody;
+use crate::consts::MAX_SHARD_BLOB_LENGTH;
+use crate::primitives::Slot;
+use crate::{Config, BeaconExecutive, Error};
+
+impl<'a, C: Config> BeaconExecutive<'a, C> {
+	/// Process shard blob commitments given in a block. Commitments are only
+	/// checked for well-formedness, and do not affect the state.
+	pub fn process_shard_blob_commitments(
+		&mut self,
+		slot: Slot,
+		body: &BeaconBlockBody<C>,
+	) -> Result<(), Error> {
+		let mut last_shard = None;
+		for commitment in body.shard_blob_commitments.iter() {
+			if commitment.shard >= C::ShardCount::to_u64() {
+				return Err(Error::ShardOutOfRange)
+			}
+			if commitment.slot != slot {
+				return Err(Error::ShardBlobSlotMismatch)
+			}
+			if commitment.length > MAX_SHARD_BLOB_LENGTH {
+				return Err(Error::ShardBlobTooLong)
+			}
+			if last_shard.map(|last| commitment.shard <= last).unwrap_or(false) {
+				return Err(Error::ShardBlobCommitmentsUnsorted)
+			}
+			last_shard = Some(commitment.shard);
+		}
+
+		Ok(())
+	}
+}
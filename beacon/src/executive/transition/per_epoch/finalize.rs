@@ -18,6 +18,7 @@ use crate::types::HistoricalBatch;
 use crate::{Config, BeaconExecutive, Error};
 use bm_le::tree_root;
 use core::cmp::min;
+use super::helpers::map_validators;
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process final updates
@@ -31,17 +32,26 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		}
 
 		// Update effective balances with hysteresis
-		for index in 0..(self.validators.len() as u64) {
-			let validator = &mut self.state.validators[index as usize];
-			let balance = self.state.balances[index as usize];
-			let half_increment = C::effective_balance_increment() / 2;
+		let increment = C::effective_balance_increment();
+		let max_effective_balance = C::max_effective_balance();
+		let half_increment = increment / 2;
+		let effective_balances = map_validators(self.validators.len(), |index| {
+			let validator = &self.validators[index as usize];
+			let balance = self.balances[index as usize];
 			if balance < validator.effective_balance ||
 				validator.effective_balance + 3 * half_increment < balance
 			{
-				validator.effective_balance = min(
-					balance - balance % C::effective_balance_increment(),
-					C::max_effective_balance()
-				);
+				Some(min(
+					balance - balance % increment,
+					max_effective_balance
+				))
+			} else {
+				None
+			}
+		});
+		for (index, effective_balance) in effective_balances.into_iter().enumerate() {
+			if let Some(effective_balance) = effective_balance {
+				self.state.validators[index].effective_balance = effective_balance;
 			}
 		}
 
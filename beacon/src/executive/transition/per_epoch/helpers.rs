@@ -18,6 +18,27 @@ use crate::primitives::{Epoch, Gwei, ValidatorIndex};
 use crate::types::PendingAttestation;
 use crate::{Config, BeaconExecutive, Error};
 
+/// Map each validator index in `0..len`, in parallel with the `parallel`
+/// feature.
+#[cfg(feature = "parallel")]
+pub fn map_validators<T, F>(len: usize, f: F) -> Vec<T> where
+	T: Send,
+	F: Fn(ValidatorIndex) -> T + Send + Sync,
+{
+	use rayon::prelude::*;
+
+	(0..len).into_par_iter().map(|i| f(i as ValidatorIndex)).collect()
+}
+
+/// Map each validator index in `0..len`, in parallel with the `parallel`
+/// feature.
+#[cfg(not(feature = "parallel"))]
+pub fn map_validators<T, F>(len: usize, f: F) -> Vec<T> where
+	F: Fn(ValidatorIndex) -> T,
+{
+	(0..len).map(|i| f(i as ValidatorIndex)).collect()
+}
+
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Get attestations with matching source at given epoch.
 	pub fn matching_source_attestations(
@@ -20,12 +20,25 @@ mod reward;
 mod registry;
 mod slashing;
 mod finalize;
+mod participation;
+
+pub use self::participation::{ParticipationCache, ValidatorParticipation, Inclusion};
 
 use crate::{Config, BeaconExecutive, Error};
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process an epoch.
 	pub fn process_epoch(&mut self) -> Result<(), Error> {
+		if self.current_epoch() > C::genesis_epoch() {
+			self.participation = Some(self.participation_cache()?);
+		}
+
+		let result = self.process_epoch_with_participation();
+		self.participation = None;
+		result
+	}
+
+	fn process_epoch_with_participation(&mut self) -> Result<(), Error> {
 		self.process_justification_and_finalization()?;
 		self.process_rewards_and_penalties()?;
 		self.process_registry_updates()?;
@@ -0,0 +1,154 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::primitives::{Epoch, Gwei, ValidatorIndex};
+use crate::{Config, BeaconExecutive, Error};
+use core::cmp::max;
+use alloc::borrow::Cow;
+
+/// Inclusion of a validator's attestation with the minimal inclusion delay.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Inclusion {
+	/// Inclusion delay of the attestation.
+	pub delay: u64,
+	/// Proposer that included the attestation.
+	pub proposer_index: ValidatorIndex,
+}
+
+/// Previous epoch participation of an unslashed validator.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct ValidatorParticipation {
+	/// Attested with matching source.
+	pub source: bool,
+	/// Attested with matching target.
+	pub target: bool,
+	/// Attested with matching head.
+	pub head: bool,
+	/// Inclusion of the earliest included matching source attestation.
+	pub inclusion: Option<Inclusion>,
+}
+
+/// Participation computed once per epoch transition, shared by justification,
+/// rewards and registry updates.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ParticipationCache {
+	/// Current epoch.
+	pub current_epoch: Epoch,
+	/// Previous epoch.
+	pub previous_epoch: Epoch,
+	/// Number of validators active in the current epoch.
+	pub active_validator_count: usize,
+	/// Total effective balance of validators active in the current epoch.
+	pub total_active_balance: Gwei,
+	/// Previous epoch participation, by validator index.
+	pub validators: Vec<ValidatorParticipation>,
+	/// Unslashed balance attesting with matching source in the previous epoch.
+	pub previous_source_balance: Gwei,
+	/// Unslashed balance attesting with matching target in the previous epoch.
+	pub previous_target_balance: Gwei,
+	/// Unslashed balance attesting with matching head in the previous epoch.
+	pub previous_head_balance: Gwei,
+	/// Unslashed balance attesting with matching target in the current epoch.
+	pub current_target_balance: Gwei,
+}
+
+impl ParticipationCache {
+	/// Unslashed balance attesting with matching target at the given epoch.
+	pub fn target_balance(&self, epoch: Epoch) -> Option<Gwei> {
+		if epoch == self.current_epoch {
+			Some(self.current_target_balance)
+		} else if epoch == self.previous_epoch {
+			Some(self.previous_target_balance)
+		} else {
+			None
+		}
+	}
+}
+
+impl<'a, C: Config> BeaconExecutive<'a, C> {
+	/// Compute participation of the previous and current epoch, scanning
+	/// pending attestations once.
+	pub fn participation_cache(&self) -> Result<ParticipationCache, Error> {
+		let current_epoch = self.current_epoch();
+		let previous_epoch = self.previous_epoch();
+		let active_validator_indices = self.active_validator_indices(current_epoch);
+		let previous_target_root = self.block_root(previous_epoch)?;
+
+		let mut validators = vec![ValidatorParticipation::default(); self.validators.len()];
+		for attestation in self.matching_source_attestations(previous_epoch)? {
+			let target = attestation.data.target.root == previous_target_root;
+			let head = attestation.data.beacon_block_root ==
+				self.block_root_at_slot(attestation.data.slot)?;
+
+			for index in self.attesting_indices(&attestation.data, &attestation.aggregation_bits)? {
+				if self.validators[index as usize].slashed {
+					continue
+				}
+
+				let participation = &mut validators[index as usize];
+				participation.source = true;
+				participation.target |= target;
+				participation.head |= head;
+				if participation.inclusion.map(|i| attestation.inclusion_delay < i.delay)
+					.unwrap_or(true)
+				{
+					participation.inclusion = Some(Inclusion {
+						delay: attestation.inclusion_delay,
+						proposer_index: attestation.proposer_index,
+					});
+				}
+			}
+		}
+
+		let balance = |f: fn(&ValidatorParticipation) -> bool| max(
+			validators.iter().enumerate()
+				.filter(|(_, p)| f(p))
+				.fold(0, |sum, (index, _)| sum + self.validators[index].effective_balance),
+			1
+		);
+		let previous_source_balance = balance(|p| p.source);
+		let previous_target_balance = balance(|p| p.target);
+		let previous_head_balance = balance(|p| p.head);
+
+		let current_target_balance = if current_epoch == previous_epoch {
+			previous_target_balance
+		} else {
+			self.attesting_balance(&self.matching_target_attestations(current_epoch)?)?
+		};
+
+		Ok(ParticipationCache {
+			current_epoch,
+			previous_epoch,
+			active_validator_count: active_validator_indices.len(),
+			total_active_balance: self.total_balance(&active_validator_indices),
+			validators,
+			previous_source_balance,
+			previous_target_balance,
+			previous_head_balance,
+			current_target_balance,
+		})
+	}
+
+	/// Participation cached for the current epoch transition, or computed
+	/// if there is none.
+	pub fn participation(&self) -> Result<Cow<ParticipationCache>, Error> {
+		match &self.participation {
+			Some(participation) if participation.current_epoch == self.current_epoch() =>
+				Ok(Cow::Borrowed(participation)),
+			_ => Ok(Cow::Owned(self.participation_cache()?)),
+		}
+	}
+}
@@ -20,26 +20,29 @@ use core::cmp::min;
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process registry updates
 	pub fn process_registry_updates(&mut self) -> Result<(), Error> {
+		let max_effective_balance = C::max_effective_balance();
+		let ejection_balance = C::ejection_balance();
 		for index in 0..self.validators.len() {
 			if self.validators[index].activation_eligibility_epoch == consts::FAR_FUTURE_EPOCH &&
-				self.validators[index].effective_balance == C::max_effective_balance()
+				self.validators[index].effective_balance == max_effective_balance
 			{
 				self.state.validators[index].activation_eligibility_epoch = self.current_epoch();
 			}
 
 			if self.validators[index].is_active(self.current_epoch()) &&
-				self.validators[index].effective_balance <= C::ejection_balance()
+				self.validators[index].effective_balance <= ejection_balance
 			{
 				self.initiate_validator_exit(index as u64);
 			}
 		}
 
+		let finalized_exit_epoch =
+			utils::activation_exit_epoch::<C>(self.finalized_checkpoint.epoch);
 		let mut activation_queue = self.validators.iter()
 			.enumerate()
 			.filter(|(_, v)| {
 				v.activation_eligibility_epoch != consts::FAR_FUTURE_EPOCH &&
-					v.activation_epoch >=
-					utils::activation_exit_epoch::<C>(self.finalized_checkpoint.epoch)
+					v.activation_epoch >= finalized_exit_epoch
 			})
 			.map(|(i, _)| i as u64)
 			.collect::<Vec<_>>();
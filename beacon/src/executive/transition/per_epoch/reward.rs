@@ -16,95 +16,95 @@
 
 use crate::primitives::{ValidatorIndex, Gwei};
 use crate::{Config, BeaconExecutive, Error, utils, consts};
+use super::helpers::map_validators;
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
-	fn base_reward(&self, index: ValidatorIndex) -> Gwei {
-		let total_balance = self.total_active_balance();
-
+	fn base_reward(
+		&self,
+		index: ValidatorIndex,
+		base_reward_factor: Gwei,
+		sqrt_total_balance: Gwei,
+	) -> Gwei {
 		let effective_balance =
 			self.validators[index as usize].effective_balance;
 
-		effective_balance * C::base_reward_factor() /
-			utils::integer_squareroot(total_balance) /
+		effective_balance * base_reward_factor /
+			sqrt_total_balance /
 			consts::BASE_REWARDS_PER_EPOCH
 	}
 
 	fn attestation_deltas(&self) -> Result<(Vec<Gwei>, Vec<Gwei>), Error> {
-		let previous_epoch = self.previous_epoch();
-		let total_balance = self.total_active_balance();
-		let mut rewards = (0..self.validators.len())
-			.map(|_| 0).collect::<Vec<_>>();
-		let mut penalties = (0..self.validators.len())
-			.map(|_| 0).collect::<Vec<_>>();
-		let eligible_validator_indices = self.validators.iter()
-			.enumerate()
-			.filter(|(_, v)| {
-				v.is_active(previous_epoch) ||
-					(v.slashed && previous_epoch + 1 < v.withdrawable_epoch)
-			})
-			.map(|(i, _)| i as u64)
-			.collect::<Vec<_>>();
-
-		// Micro-incentives for matching FFG source, FFG target, and head
-		let matching_source_attestations =
-			self.matching_source_attestations(previous_epoch)?;
-		let matching_target_attestations =
-			self.matching_target_attestations(previous_epoch)?;
-		let matching_head_attestations =
-			self.matching_head_attestations(previous_epoch)?;
-		for attestations in &[&matching_source_attestations,
-							  &matching_target_attestations,
-							  &matching_head_attestations]
-		{
-			let unslashed_attesting_indices =
-				self.unslashed_attesting_indices(attestations)?;
-			let attesting_balance = self.total_balance(&unslashed_attesting_indices);
-			for index in &eligible_validator_indices {
-				if unslashed_attesting_indices.contains(index) {
-					rewards[*index as usize] += self.base_reward(*index) *
-						attesting_balance / total_balance;
-				} else {
-					penalties[*index as usize] += self.base_reward(*index);
+		let participation = self.participation()?;
+		let previous_epoch = participation.previous_epoch;
+		let total_balance = participation.total_active_balance;
+		let finality_delay = previous_epoch - self.finalized_checkpoint.epoch;
+
+		// Config values and the balance root are read once here rather than
+		// per validator, as runtime specs go through a shared lookup.
+		let base_reward_factor = C::base_reward_factor();
+		let sqrt_total_balance = utils::integer_squareroot(total_balance);
+		let proposer_reward_quotient = C::proposer_reward_quotient();
+		let inactivity_leak = finality_delay > C::min_epochs_to_inactivity_penalty();
+		let inactivity_penalty_quotient = C::inactivity_penalty_quotient();
+
+		// Deltas are computed per validator, and proposer rewards are
+		// collected to be applied in the final pass.
+		let deltas = map_validators(self.validators.len(), |index| {
+			let validator = &self.validators[index as usize];
+			let validator_participation = &participation.validators[index as usize];
+			let base_reward = self.base_reward(index, base_reward_factor, sqrt_total_balance);
+			let mut reward = 0;
+			let mut penalty = 0;
+			let mut proposer_reward = None;
+
+			let eligible = validator.is_active(previous_epoch) ||
+				(validator.slashed && previous_epoch + 1 < validator.withdrawable_epoch);
+
+			// Micro-incentives for matching FFG source, FFG target, and head
+			if eligible {
+				for (attested, attesting_balance) in &[
+					(validator_participation.source, participation.previous_source_balance),
+					(validator_participation.target, participation.previous_target_balance),
+					(validator_participation.head, participation.previous_head_balance),
+				] {
+					if *attested {
+						reward += base_reward * attesting_balance / total_balance;
+					} else {
+						penalty += base_reward;
+					}
 				}
 			}
-		}
 
-		// Proposer and inclusion delay micro-rewards
-		for index in self.unslashed_attesting_indices(&matching_source_attestations)? {
-			let attestation = matching_source_attestations.iter()
-				.map(|a| Ok((
-					a,
-					self.attesting_indices(&a.data, &a.aggregation_bits)?
-						.contains(&index)
-				)))
-				.collect::<Result<Vec<_>, _>>()?
-				.into_iter()
-				.filter(|(_, c)| *c)
-				.map(|(a, _)| a)
-				.fold(matching_source_attestations[0].clone(), |a, b| {
-					if a.inclusion_delay < b.inclusion_delay { a } else { b.clone() }
-				});
-
-			let proposer_reward = self.base_reward(index) / C::proposer_reward_quotient();
-			rewards[attestation.proposer_index as usize] += proposer_reward;
-			let max_attester_reward = self.base_reward(index) - proposer_reward;
-			rewards[index as usize] += max_attester_reward / attestation.inclusion_delay;
-		}
+			// Proposer and inclusion delay micro-rewards
+			if let Some(inclusion) = validator_participation.inclusion {
+				let reward_to_proposer = base_reward / proposer_reward_quotient;
+				proposer_reward = Some((inclusion.proposer_index, reward_to_proposer));
+				let max_attester_reward = base_reward - reward_to_proposer;
+				reward += max_attester_reward / inclusion.delay;
+			}
 
-		// Inactivity penalty
-		let finality_delay = previous_epoch - self.finalized_checkpoint.epoch;
-		if finality_delay > C::min_epochs_to_inactivity_penalty() {
-			let matching_target_attesting_indices =
-				self.unslashed_attesting_indices(&matching_target_attestations)?;
-			for index in &eligible_validator_indices {
-				penalties[*index as usize] += consts::BASE_REWARDS_PER_EPOCH *
-					self.base_reward(*index);
-				if !matching_target_attesting_indices.contains(index) {
-					penalties[*index as usize] +=
-						self.validators[*index as usize].effective_balance *
-						finality_delay / C::inactivity_penalty_quotient();
+			// Inactivity penalty
+			if eligible && inactivity_leak {
+				penalty += consts::BASE_REWARDS_PER_EPOCH * base_reward;
+				if !validator_participation.target {
+					penalty += validator.effective_balance *
+						finality_delay / inactivity_penalty_quotient;
 				}
 			}
+
+			(reward, penalty, proposer_reward)
+		});
+
+		let mut rewards = Vec::with_capacity(deltas.len());
+		let mut penalties = Vec::with_capacity(deltas.len());
+		let mut proposer_rewards = Vec::new();
+		for (reward, penalty, proposer_reward) in deltas {
+			rewards.push(reward);
+			penalties.push(penalty);
+			proposer_rewards.extend(proposer_reward);
+		}
+		for (proposer_index, reward) in proposer_rewards {
+			rewards[proposer_index as usize] += reward;
 		}
 
 		Ok((rewards, penalties))
@@ -117,10 +117,7 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		}
 
 		let (rewards, penalties) = self.attestation_deltas()?;
-		for i in 0..self.validators.len() {
-			self.increase_balance(i as u64, rewards[i]);
-			self.decrease_balance(i as u64, penalties[i]);
-		}
+		self.apply_balance_deltas(&rewards, &penalties);
 
 		Ok(())
 	}
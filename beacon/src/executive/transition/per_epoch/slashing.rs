@@ -16,6 +16,7 @@
 
 use crate::{Config, BeaconExecutive};
 use core::cmp::min;
+use super::helpers::map_validators;
 
 impl<'a, C: Config> BeaconExecutive<'a, C> {
 	/// Process slashings
@@ -23,25 +24,25 @@ impl<'a, C: Config> BeaconExecutive<'a, C> {
 		let current_epoch = self.current_epoch();
 		let total_balance = self.total_active_balance();
 
-		for index in 0..(self.validators.len() as u64) {
-			let penalty = {
-				let validator = &self.validators[index as usize];
-				if validator.slashed &&
-					current_epoch + C::epochs_per_slashings_vector() / 2 ==
-					validator.withdrawable_epoch
-				{
-					let increment = C::effective_balance_increment();
-					let penalty_numerator = validator.effective_balance / increment *
-						min(self.slashings.iter().fold(0, |acc, x| acc + *x) * 3, total_balance);
-					let penalty = penalty_numerator / total_balance * increment;
-
-					Some(penalty)
-				} else {
-					None
-				}
-			};
+		let total_slashings = self.slashings.iter().fold(0, |acc, x| acc + *x);
+		let slashing_epoch = current_epoch + C::epochs_per_slashings_vector() / 2;
+		let increment = C::effective_balance_increment();
+
+		let penalties = map_validators(self.validators.len(), |index| {
+			let validator = &self.validators[index as usize];
+			if validator.slashed && slashing_epoch == validator.withdrawable_epoch {
+				let penalty_numerator = validator.effective_balance / increment *
+					min(total_slashings * 3, total_balance);
+				let penalty = penalty_numerator / total_balance * increment;
+
+				Some(penalty)
+			} else {
+				None
+			}
+		});
+		for (index, penalty) in penalties.into_iter().enumerate() {
 			if let Some(penalty) = penalty {
-				self.decrease_balance(index, penalty);
+				self.decrease_balance(index as u64, penalty);
 			}
 		}
 	}
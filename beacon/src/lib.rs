@@ -30,21 +30,32 @@ pub mod consts;
 pub mod utils;
 /// Components for reuse.
 pub mod components;
+/// Signing domains and signing roots.
+pub mod domain;
+/// Tracing of block state transitions.
+pub mod trace;
 
 mod error;
 mod config;
 mod executive;
 mod genesis;
+mod reader;
+mod limits;
 
 pub use self::error::Error;
 pub use self::config::{
-	BLSConfig, BLSNoVerification,
+	BLSConfig, BLSNoVerification, BLSBatchVerified,
 	Config, MinimalConfig, MainnetConfig, SapphireConfig,
 };
-pub use self::executive::{BeaconState, BeaconExecutive};
+pub use self::executive::{
+	BeaconState, BeaconExecutive, CommitteeAssignment,
+	ParticipationCache, ValidatorParticipation, Inclusion,
+};
+pub use self::reader::StateReader;
+pub use self::limits::{BlockLimits, TransactionKind, remaining_deposits};
 pub use self::genesis::{genesis, genesis_beacon_state};
 
-use self::primitives::{H256, H768};
+use self::primitives::{H256, H768, Signature, ValidatorId};
 use self::types::{
 	Attestation, UnsealedBeaconBlock, BeaconBlockBody, BeaconBlock, VoluntaryExit, Deposit,
 	Block, AttesterSlashing, ProposerSlashing, Eth1Data, SigningBeaconBlockHeader,
@@ -61,9 +72,78 @@ pub fn execute_block<C: Config, BLS: BLSConfig>(
 	executive.state_transition::<_, BLS>(block)
 }
 
+/// Proposer signature of a block, for batch verification.
+pub struct ProposerSignature {
+	/// Public key of the proposer.
+	pub pubkey: ValidatorId,
+	/// Signed message.
+	pub message: H256,
+	/// Signature.
+	pub signature: Signature,
+	/// Signing domain.
+	pub domain: u64,
+}
+
+/// Collect proposer signatures of blocks within a single epoch, given the
+/// parent state of the first block. The state is advanced, and must be
+/// discarded afterwards.
+pub fn proposer_signatures<C: Config>(
+	blocks: &[BeaconBlock<C>],
+	state: &mut BeaconState<C>,
+) -> Result<Vec<ProposerSignature>, Error> {
+	let mut executive = BeaconExecutive::new(state);
+	executive.proposer_signatures(blocks)
+}
+
+/// Number of proposer signatures aggregated into one verification.
+pub const PROPOSER_SIGNATURES_PER_AGGREGATE: usize = 8;
+
+/// Verify proposer signatures in batch, aggregating up to
+/// `PROPOSER_SIGNATURES_PER_AGGREGATE` signatures of the same domain. With
+/// the `parallel` feature, aggregates are verified in parallel.
+pub fn verify_proposer_signatures<BLS: BLSConfig>(signatures: &[ProposerSignature]) -> bool {
+	let mut domains = signatures.iter().map(|s| s.domain).collect::<Vec<_>>();
+	domains.sort();
+	domains.dedup();
+
+	let mut aggregates = Vec::new();
+	for domain in domains {
+		let signatures = signatures.iter().filter(|s| s.domain == domain).collect::<Vec<_>>();
+		aggregates.extend(
+			signatures.chunks(PROPOSER_SIGNATURES_PER_AGGREGATE).map(|chunk| chunk.to_vec())
+		);
+	}
+
+	all_aggregates(&aggregates, |signatures| {
+		let pubkeys = signatures.iter().map(|s| s.pubkey.clone()).collect::<Vec<_>>();
+		let messages = signatures.iter().map(|s| s.message).collect::<Vec<_>>();
+		let aggregated = BLS::aggregate_signatures(
+			&signatures.iter().map(|s| s.signature.clone()).collect::<Vec<_>>()
+		);
+
+		BLS::verify_multiple(&pubkeys, &messages, &aggregated, signatures[0].domain)
+	})
+}
+
+#[cfg(feature = "parallel")]
+fn all_aggregates<F>(aggregates: &[Vec<&ProposerSignature>], f: F) -> bool where
+	F: Fn(&[&ProposerSignature]) -> bool + Send + Sync,
+{
+	use rayon::prelude::*;
+
+	aggregates.par_iter().all(|signatures| f(signatures))
+}
+
+#[cfg(not(feature = "parallel"))]
+fn all_aggregates<F>(aggregates: &[Vec<&ProposerSignature>], f: F) -> bool where
+	F: Fn(&[&ProposerSignature]) -> bool,
+{
+	aggregates.iter().all(|signatures| f(signatures))
+}
+
 /// Get genesis domain.
 pub fn genesis_domain(domain_type: u32) -> u64 {
-	utils::bls_domain(domain_type, Default::default())
+	domain::genesis_domain(domain_type)
 }
 
 /// Beacon block inherent.
@@ -133,40 +213,42 @@ pub fn apply_transaction<C: Config, BLS: BLSConfig>(
 	state: &mut BeaconState<C>,
 	extrinsic: Transaction<C>,
 ) -> Result<(), Error> {
+	apply_transaction_with_limits::<C, BLS>(block, state, extrinsic, &BlockLimits::default())
+}
+
+/// Apply a transaction to the block, if it is within the given limits.
+pub fn apply_transaction_with_limits<C: Config, BLS: BLSConfig>(
+	block: &mut UnsealedBeaconBlock<C>,
+	state: &mut BeaconState<C>,
+	extrinsic: Transaction<C>,
+	limits: &BlockLimits,
+) -> Result<(), Error> {
+	limits.check(&block.body, extrinsic.kind())?;
+
 	let mut executive = BeaconExecutive::new(state);
 	match extrinsic {
 		Transaction::ProposerSlashing(slashing) => {
-			if block.body.proposer_slashings.len() >= C::max_proposer_slashings() as usize {
-				return Err(Error::TooManyProposerSlashings)
-			}
 			executive.process_proposer_slashing::<BLS>(slashing.clone())?;
 			block.body.proposer_slashings.push(slashing);
 		},
 		Transaction::AttesterSlashing(slashing) => {
-			if block.body.attester_slashings.len() >= C::max_attester_slashings() as usize {
-				return Err(Error::TooManyAttesterSlashings)
-			}
 			executive.process_attester_slashing::<BLS>(slashing.clone())?;
 			block.body.attester_slashings.push(slashing);
 		},
 		Transaction::Attestation(attestation) => {
-			if block.body.attestations.len() >= C::max_attestations() as usize {
-				return Err(Error::TooManyAttestations)
-			}
 			executive.process_attestation::<BLS>(attestation.clone())?;
 			block.body.attestations.push(attestation);
 		},
 		Transaction::Deposit(deposit) => {
-			if block.body.deposits.len() >= C::max_deposits() as usize {
+			if remaining_deposits(
+				&block.body, executive.eth1_data.deposit_count, executive.eth1_deposit_index,
+			) == 0 {
 				return Err(Error::TooManyDeposits)
 			}
 			executive.process_deposit::<BLS>(deposit.clone())?;
 			block.body.deposits.push(deposit);
 		},
 		Transaction::VoluntaryExit(voluntary_exit) => {
-			if block.body.voluntary_exits.len() >= C::max_voluntary_exits() as usize {
-				return Err(Error::TooManyVoluntaryExits)
-			}
 			executive.process_voluntary_exit::<BLS>(voluntary_exit.clone())?;
 			block.body.voluntary_exits.push(voluntary_exit);
 		},
@@ -178,22 +260,40 @@ pub fn apply_transaction<C: Config, BLS: BLSConfig>(
 pub fn finalize_block<C: Config, BLS: BLSConfig>(
 	block: &mut UnsealedBeaconBlock<C>,
 	state: &mut BeaconState<C>
+) -> Result<(), Error> {
+	finalize_block_with::<C, BLS, _>(block, state, |state| tree_root::<C::Digest, _>(state))
+}
+
+/// Finalize an unsealed block, computing the post state root with
+/// `state_root`, so that builders can reuse work between attempts.
+pub fn finalize_block_with<C: Config, BLS: BLSConfig, F: FnOnce(&BeaconState<C>) -> H256>(
+	block: &mut UnsealedBeaconBlock<C>,
+	state: &mut BeaconState<C>,
+	state_root: F,
 ) -> Result<(), Error> {
 	if state.eth1_data.deposit_count < state.eth1_deposit_index {
 		return Err(Error::InvalidEth1Data)
 	}
 
-	if block.body.deposits.len() != min(
+	limits::check_body(&block.body)?;
+	#[cfg(feature = "shards")]
+	BeaconExecutive::new(state).process_shard_blob_commitments(block.slot, &block.body)?;
+
+	let required_deposits = min(
 		C::max_deposits(),
 		state.eth1_data.deposit_count - state.eth1_deposit_index
-	) as usize {
+	) as usize;
+	if block.body.deposits.len() > required_deposits {
 		return Err(Error::TooManyDeposits)
 	}
+	if block.body.deposits.len() < required_deposits {
+		return Err(Error::MissingDeposits)
+	}
 
 	let mut executive = BeaconExecutive::new(state);
 	executive.process_block_header::<_, BLS>(block)?;
 
-	block.state_root = tree_root::<C::Digest, _>(state);
+	block.state_root = state_root(state);
 
 	Ok(())
 }
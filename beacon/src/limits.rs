@@ -0,0 +1,163 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::types::BeaconBlockBody;
+use crate::{Config, Error, Transaction};
+use core::cmp::min;
+
+/// Kind of a beacon block transaction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransactionKind {
+	/// Proposer slashing.
+	ProposerSlashing,
+	/// Attester slashing.
+	AttesterSlashing,
+	/// Attestation.
+	Attestation,
+	/// Deposit.
+	Deposit,
+	/// Voluntary exit.
+	VoluntaryExit,
+}
+
+impl<C: Config> Transaction<C> {
+	/// Kind of the transaction.
+	pub fn kind(&self) -> TransactionKind {
+		match self {
+			Transaction::ProposerSlashing(_) => TransactionKind::ProposerSlashing,
+			Transaction::AttesterSlashing(_) => TransactionKind::AttesterSlashing,
+			Transaction::Attestation(_) => TransactionKind::Attestation,
+			Transaction::Deposit(_) => TransactionKind::Deposit,
+			Transaction::VoluntaryExit(_) => TransactionKind::VoluntaryExit,
+		}
+	}
+}
+
+impl TransactionKind {
+	/// Protocol maximum of transactions of this kind in a block.
+	pub fn max<C: Config>(self) -> usize {
+		(match self {
+			TransactionKind::ProposerSlashing => C::max_proposer_slashings(),
+			TransactionKind::AttesterSlashing => C::max_attester_slashings(),
+			TransactionKind::Attestation => C::max_attestations(),
+			TransactionKind::Deposit => C::max_deposits(),
+			TransactionKind::VoluntaryExit => C::max_voluntary_exits(),
+		}) as usize
+	}
+
+	/// Number of transactions of this kind in a block body.
+	pub fn count<C: Config>(self, body: &BeaconBlockBody<C>) -> usize {
+		match self {
+			TransactionKind::ProposerSlashing => body.proposer_slashings.len(),
+			TransactionKind::AttesterSlashing => body.attester_slashings.len(),
+			TransactionKind::Attestation => body.attestations.len(),
+			TransactionKind::Deposit => body.deposits.len(),
+			TransactionKind::VoluntaryExit => body.voluntary_exits.len(),
+		}
+	}
+
+	/// Error of exceeding the limit of this kind.
+	pub fn too_many(self) -> Error {
+		match self {
+			TransactionKind::ProposerSlashing => Error::TooManyProposerSlashings,
+			TransactionKind::AttesterSlashing => Error::TooManyAttesterSlashings,
+			TransactionKind::Attestation => Error::TooManyAttestations,
+			TransactionKind::Deposit => Error::TooManyDeposits,
+			TransactionKind::VoluntaryExit => Error::TooManyVoluntaryExits,
+		}
+	}
+}
+
+/// Limits on transactions a block builder includes in a block. Limits above
+/// the protocol maxima have no effect. Deposits are not limited, as blocks
+/// must include all pending deposits up to the protocol maximum.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockLimits {
+	/// Maximum proposer slashings.
+	pub proposer_slashings: usize,
+	/// Maximum attester slashings.
+	pub attester_slashings: usize,
+	/// Maximum attestations.
+	pub attestations: usize,
+	/// Maximum voluntary exits.
+	pub voluntary_exits: usize,
+}
+
+impl Default for BlockLimits {
+	fn default() -> Self {
+		Self {
+			proposer_slashings: usize::max_value(),
+			attester_slashings: usize::max_value(),
+			attestations: usize::max_value(),
+			voluntary_exits: usize::max_value(),
+		}
+	}
+}
+
+impl BlockLimits {
+	/// Effective limit of transactions of `kind` in a block.
+	pub fn limit<C: Config>(&self, kind: TransactionKind) -> usize {
+		let configured = match kind {
+			TransactionKind::ProposerSlashing => self.proposer_slashings,
+			TransactionKind::AttesterSlashing => self.attester_slashings,
+			TransactionKind::Attestation => self.attestations,
+			TransactionKind::Deposit => usize::max_value(),
+			TransactionKind::VoluntaryExit => self.voluntary_exits,
+		};
+		min(configured, kind.max::<C>())
+	}
+
+	/// Number of transactions of `kind` that can still be added to a block
+	/// body.
+	pub fn remaining<C: Config>(&self, body: &BeaconBlockBody<C>, kind: TransactionKind) -> usize {
+		self.limit::<C>(kind).saturating_sub(kind.count(body))
+	}
+
+	/// Check that another transaction of `kind` fits in a block body.
+	pub fn check<C: Config>(&self, body: &BeaconBlockBody<C>, kind: TransactionKind) -> Result<(), Error> {
+		if self.remaining(body, kind) == 0 {
+			return Err(kind.too_many())
+		}
+		Ok(())
+	}
+}
+
+/// Number of deposits a block body still has to include, given the state's
+/// eth1 data and deposit index.
+pub fn remaining_deposits<C: Config>(
+	body: &BeaconBlockBody<C>,
+	deposit_count: u64,
+	deposit_index: u64,
+) -> usize {
+	let required = min(C::max_deposits(), deposit_count.saturating_sub(deposit_index)) as usize;
+	required.saturating_sub(body.deposits.len())
+}
+
+/// Check that a block body is within protocol maxima.
+pub fn check_body<C: Config>(body: &BeaconBlockBody<C>) -> Result<(), Error> {
+	for kind in &[
+		TransactionKind::ProposerSlashing,
+		TransactionKind::AttesterSlashing,
+		TransactionKind::Attestation,
+		TransactionKind::Deposit,
+		TransactionKind::VoluntaryExit,
+	] {
+		if kind.count(body) > kind.max::<C>() {
+			return Err(kind.too_many())
+		}
+	}
+	Ok(())
+}
@@ -0,0 +1,174 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::primitives::{Epoch, Gwei, Slot, Uint, ValidatorIndex, ValidatorId, H256};
+use crate::types::{Validator, Checkpoint};
+use crate::{Config, BeaconState, BeaconExecutive, CommitteeAssignment, Error, utils};
+
+/// Read-only accessors over a beacon state. All accessors are bounds-checked
+/// and return errors instead of panicking on out-of-range input.
+///
+/// The reader borrows the state mutably because it is backed by an executive,
+/// but never modifies it.
+pub struct StateReader<'a, C: Config> {
+	executive: BeaconExecutive<'a, C>,
+}
+
+impl<'a, C: Config> StateReader<'a, C> {
+	/// Create a reader from a state reference.
+	pub fn new(state: &'a mut BeaconState<C>) -> Self {
+		Self { executive: BeaconExecutive::new(state) }
+	}
+
+	/// Slot of the state.
+	pub fn slot(&self) -> Slot {
+		self.executive.slot
+	}
+
+	/// Current epoch of the state.
+	pub fn current_epoch(&self) -> Epoch {
+		self.executive.current_epoch()
+	}
+
+	/// Current justified checkpoint.
+	pub fn current_justified_checkpoint(&self) -> Checkpoint {
+		self.executive.current_justified_checkpoint.clone()
+	}
+
+	/// Finalized checkpoint.
+	pub fn finalized_checkpoint(&self) -> Checkpoint {
+		self.executive.finalized_checkpoint.clone()
+	}
+
+	/// Number of validators in the registry.
+	pub fn validator_count(&self) -> usize {
+		self.executive.validators.len()
+	}
+
+	/// Validator at index.
+	pub fn validator(&self, index: ValidatorIndex) -> Result<&Validator, Error> {
+		self.executive.validators.get(index as usize).ok_or(Error::IndexOutOfRange)
+	}
+
+	/// Index of the validator with the given public key.
+	pub fn validator_index(&self, pubkey: &ValidatorId) -> Option<ValidatorIndex> {
+		self.executive.validators.iter()
+			.position(|v| &v.pubkey == pubkey)
+			.map(|i| i as ValidatorIndex)
+	}
+
+	/// Public key of the validator at index.
+	pub fn validator_pubkey(&self, index: ValidatorIndex) -> Result<ValidatorId, Error> {
+		Ok(self.validator(index)?.pubkey.clone())
+	}
+
+	/// Balance of the validator at index.
+	pub fn balance(&self, index: ValidatorIndex) -> Result<Gwei, Error> {
+		self.executive.balances.get(index as usize).cloned().ok_or(Error::IndexOutOfRange)
+	}
+
+	/// Balances of all validators.
+	pub fn balances(&self) -> &[Gwei] {
+		&self.executive.balances[..]
+	}
+
+	/// Effective balances of all validators, indexed by validator.
+	pub fn effective_balances(&self) -> Vec<Gwei> {
+		self.executive.validators.iter().map(|v| v.effective_balance).collect()
+	}
+
+	/// Block root at the start slot of epoch.
+	pub fn block_root(&self, epoch: Epoch) -> Result<H256, Error> {
+		self.executive.block_root(epoch)
+	}
+
+	/// Block root at slot.
+	pub fn block_root_at_slot(&self, slot: Slot) -> Result<H256, Error> {
+		self.executive.block_root_at_slot(slot)
+	}
+
+	/// Randao mix at epoch, if it is still within the historical vector.
+	pub fn randao_mix(&self, epoch: Epoch) -> Result<H256, Error> {
+		let current_epoch = self.current_epoch();
+		if epoch > current_epoch ||
+			epoch + C::epochs_per_historical_vector() <= current_epoch
+		{
+			return Err(Error::EpochOutOfRange)
+		}
+
+		Ok(self.executive.randao_mix(epoch))
+	}
+
+	/// Signing domain, given domain type and message epoch.
+	pub fn domain(&self, domain_type: u32, message_epoch: Option<Epoch>) -> Uint {
+		self.executive.domain(domain_type, message_epoch)
+	}
+
+	/// Number of validators active at epoch.
+	pub fn active_validator_count(&self, epoch: Epoch) -> Result<usize, Error> {
+		self.check_committee_epoch(epoch)?;
+		Ok(self.executive.active_validator_len(epoch))
+	}
+
+	/// Number of committees at slot.
+	pub fn committee_count_at_slot(&self, slot: Slot) -> Result<Uint, Error> {
+		self.check_committee_epoch(utils::epoch_of_slot::<C>(slot))?;
+		Ok(self.executive.committee_count_at_slot(slot))
+	}
+
+	/// Beacon committee at slot and committee index.
+	pub fn beacon_committee(
+		&self,
+		slot: Slot,
+		index: Uint,
+	) -> Result<Vec<ValidatorIndex>, Error> {
+		if index >= self.committee_count_at_slot(slot)? {
+			return Err(Error::IndexOutOfRange)
+		}
+
+		self.executive.beacon_committee(slot, index)
+	}
+
+	/// Committee assignment of a validator at epoch.
+	pub fn committee_assignment(
+		&self,
+		epoch: Epoch,
+		index: ValidatorIndex,
+	) -> Result<Option<CommitteeAssignment>, Error> {
+		self.check_committee_epoch(epoch)?;
+		self.validator(index)?;
+		self.executive.committee_assignment(epoch, index)
+	}
+
+	/// Beacon proposer index at the state slot.
+	pub fn beacon_proposer_index(&self) -> Result<ValidatorIndex, Error> {
+		self.executive.beacon_proposer_index()
+	}
+
+	/// Committees can be computed up to the next epoch, and as long as the
+	/// randao mix of their seed is still within the historical vector.
+	fn check_committee_epoch(&self, epoch: Epoch) -> Result<(), Error> {
+		let current_epoch = self.current_epoch();
+		if epoch > current_epoch + 1 ||
+			epoch + C::epochs_per_historical_vector() <=
+			current_epoch + C::min_seed_lookahead() + 1
+		{
+			return Err(Error::EpochOutOfRange)
+		}
+
+		Ok(())
+	}
+}
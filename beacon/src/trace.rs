@@ -0,0 +1,137 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Step-by-step tracing of block state transitions.
+//!
+//! A traced transition records every sub-operation applied to the state,
+//! with the state root and the balance changes after it. It is meant for
+//! diagnosing consensus failures, and computes a state root per step.
+
+use alloc::vec::Vec;
+use bm_le::tree_root;
+use crate::primitives::{Gwei, Slot, ValidatorIndex, H256};
+use crate::types::BeaconBlock;
+use crate::{BeaconState, BeaconExecutive, BLSConfig, Config, Error, utils};
+
+/// Sub-operation of a state transition.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TraceOperation {
+	/// Slots processed up to the block slot, including epoch transitions.
+	Slots {
+		/// Slot before processing.
+		from: Slot,
+		/// Slot after processing.
+		to: Slot,
+	},
+	/// Block header.
+	BlockHeader,
+	/// Randao reveal.
+	Randao,
+	/// Eth1 data vote.
+	Eth1Data,
+	/// Proposer slashing at the given index in the block.
+	ProposerSlashing(usize),
+	/// Attester slashing at the given index in the block.
+	AttesterSlashing(usize),
+	/// Attestation at the given index in the block.
+	Attestation(usize),
+	/// Deposit at the given index in the block.
+	Deposit(usize),
+	/// Voluntary exit at the given index in the block.
+	VoluntaryExit(usize),
+	/// Shard blob commitments.
+	ShardBlobCommitments,
+}
+
+/// Observer of state transition steps.
+pub trait Tracer<C: Config> {
+	/// Note that an operation was applied to the state.
+	fn step(&mut self, _operation: TraceOperation, _state: &BeaconState<C>) { }
+}
+
+/// Tracer ignoring all steps.
+pub struct NoTracer;
+
+impl<C: Config> Tracer<C> for NoTracer { }
+
+/// A traced step.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceStep {
+	/// Applied operation.
+	pub operation: TraceOperation,
+	/// State root after the operation.
+	pub state_root: H256,
+	/// Balance changes of the operation, by validator index. Validators
+	/// added by the operation are listed with their full balance.
+	pub balance_deltas: Vec<(ValidatorIndex, i128)>,
+}
+
+/// Trace of a block state transition.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockTrace {
+	/// Root of the parent state.
+	pub pre_state_root: H256,
+	/// Applied steps, in order.
+	pub steps: Vec<TraceStep>,
+	/// Error the transition failed with, if any.
+	pub error: Option<Error>,
+	balances: Vec<Gwei>,
+}
+
+impl BlockTrace {
+	/// Start a trace from the parent state.
+	pub fn new<C: Config>(state: &BeaconState<C>) -> Self {
+		Self {
+			pre_state_root: tree_root::<C::Digest, _>(state),
+			steps: Vec::new(),
+			error: None,
+			balances: state.balances.iter().cloned().collect(),
+		}
+	}
+
+	/// Root of the state after the last step.
+	pub fn post_state_root(&self) -> H256 {
+		self.steps.last().map(|step| step.state_root).unwrap_or(self.pre_state_root)
+	}
+}
+
+impl<C: Config> Tracer<C> for BlockTrace {
+	fn step(&mut self, operation: TraceOperation, state: &BeaconState<C>) {
+		let balance_deltas = utils::balance_deltas(&self.balances, &state.balances[..]);
+		self.balances.clear();
+		self.balances.extend_from_slice(&state.balances[..]);
+
+		self.steps.push(TraceStep {
+			operation,
+			state_root: tree_root::<C::Digest, _>(state),
+			balance_deltas,
+		});
+	}
+}
+
+/// Execute a block on its parent state, tracing each step. The trace is
+/// returned whether or not the transition succeeds.
+pub fn trace_block<C: Config, BLS: BLSConfig>(
+	block: &BeaconBlock<C>,
+	state: &mut BeaconState<C>,
+) -> BlockTrace {
+	let mut trace = BlockTrace::new(state);
+	let mut executive = BeaconExecutive::new(state);
+	if let Err(e) = executive.state_transition_with::<_, BLS, _>(block, &mut trace) {
+		trace.error = Some(e);
+	}
+	trace
+}
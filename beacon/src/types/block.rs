@@ -24,6 +24,8 @@ use crate::Config;
 use crate::primitives::{H256, H768, Uint, Signature};
 use super::operation::{AttesterSlashing, ProposerSlashing, Attestation, Deposit, VoluntaryExit};
 use super::misc::Eth1Data;
+#[cfg(feature = "shards")]
+use super::shard::ShardBlobCommitment;
 
 #[derive(Codec, Encode, Decode, IntoTree, FromTree, Clone, PartialEq, Eq, Default, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(deny_unknown_fields))]
@@ -47,6 +49,9 @@ pub struct BeaconBlockBody<C: Config> {
 	pub deposits: MaxVec<Deposit, C::MaxDeposits>,
 	/// Voluntary exits.
 	pub voluntary_exits: MaxVec<VoluntaryExit, C::MaxVoluntaryExits>,
+	/// Shard blob commitments, at most one per shard.
+	#[cfg(feature = "shards")]
+	pub shard_blob_commitments: MaxVec<ShardBlobCommitment, C::ShardCount>,
 }
 
 /// Sealed or unsealed block.
@@ -134,14 +139,15 @@ impl<C: Config> Block for UnsealedBeaconBlock<C> {
 }
 
 impl<C: Config> UnsealedBeaconBlock<C> {
-	/// Fake sealing a beacon block, with empty signature.
-	pub fn fake_seal(self) -> BeaconBlock<C> {
+	/// Seal a finalized beacon block with the proposer signature over its
+	/// signing root.
+	pub fn seal(self, signature: Signature) -> BeaconBlock<C> {
 		BeaconBlock {
 			slot: self.slot,
 			parent_root: self.parent_root,
 			state_root: self.state_root,
 			body: self.body,
-			signature: Default::default(),
+			signature,
 		}
 	}
 }
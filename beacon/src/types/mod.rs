@@ -17,6 +17,8 @@
 mod misc;
 mod operation;
 mod block;
+#[cfg(feature = "shards")]
+mod shard;
 
 pub use self::misc::{
 	Fork, Checkpoint, Validator, AttestationData, AttestationDataAndCustodyBit,
@@ -31,3 +33,5 @@ pub use self::operation::{
 pub use self::block::{
 	BeaconBlockBody, BeaconBlock, UnsealedBeaconBlock, Block,
 };
+#[cfg(feature = "shards")]
+pub use self::shard::ShardBlobCommitment;
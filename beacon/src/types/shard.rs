@@ -0,0 +1,42 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Shard blob commitments, for experimentation with data shards.
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+use ssz::{Codec, Encode, Decode};
+use bm_le::{IntoTree, FromTree};
+use crate::primitives::{Uint, H256};
+
+#[derive(Codec, Encode, Decode, IntoTree, FromTree, Clone, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(deny_unknown_fields))]
+#[cfg_attr(feature = "parity-codec", derive(parity_codec::Encode, parity_codec::Decode))]
+/// Commitment to a shard blob, included in the beacon block body. The blob
+/// itself is distributed separately.
+pub struct ShardBlobCommitment {
+	/// Shard of the blob.
+	#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::utils::deserialize_uint"))]
+	pub shard: Uint,
+	/// Slot of the blob, equal to the slot of the including block.
+	#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::utils::deserialize_uint"))]
+	pub slot: Uint,
+	/// Root of the blob data.
+	pub data_root: H256,
+	/// Length of the blob data in bytes.
+	#[cfg_attr(feature = "serde", serde(deserialize_with = "crate::utils::deserialize_uint"))]
+	pub length: Uint,
+}
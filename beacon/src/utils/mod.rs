@@ -21,7 +21,7 @@ mod serde;
 pub use self::serde::*;
 
 use crate::{Config, Error};
-use crate::primitives::{H256, Uint, ValidatorIndex, Version};
+use crate::primitives::{Gwei, H256, Uint, ValidatorIndex, Version};
 use core::cmp::max;
 use core::convert::TryInto;
 
@@ -160,9 +160,32 @@ pub fn is_valid_merkle_branch<C: Config>(
 
 /// BLS signing domain given a domain type and fork version.
 pub fn bls_domain(domain_type: u32, fork_version: Version) -> u64 {
-	let mut bytes = [0u8; 8];
-	(&mut bytes[0..4]).copy_from_slice(&domain_type.to_le_bytes()[..]);
-	(&mut bytes[4..8]).copy_from_slice(fork_version.as_ref());
+	crate::domain::compute_domain(domain_type, fork_version)
+}
+
+/// Apply rewards and penalties to a list of balances, all indexed by
+/// validator. Each balance is rewarded before being penalized, and never
+/// drops below zero.
+pub fn apply_balance_deltas(balances: &mut [Gwei], rewards: &[Gwei], penalties: &[Gwei]) {
+	debug_assert!(rewards.len() == balances.len() && penalties.len() == balances.len());
+
+	for ((balance, reward), penalty) in balances.iter_mut().zip(rewards).zip(penalties) {
+		*balance = (*balance + *reward).saturating_sub(*penalty);
+	}
+}
 
-	u64::from_le_bytes(bytes)
+/// Signed balance changes from `previous` to `current`, for validators whose
+/// balance changed. Validators missing from `previous` start at zero.
+pub fn balance_deltas(previous: &[Gwei], current: &[Gwei]) -> Vec<(ValidatorIndex, i128)> {
+	current.iter()
+		.enumerate()
+		.filter_map(|(index, balance)| {
+			let previous = previous.get(index).cloned().unwrap_or(0);
+			if *balance != previous {
+				Some((index as ValidatorIndex, *balance as i128 - previous as i128))
+			} else {
+				None
+			}
+		})
+		.collect()
 }
@@ -0,0 +1,41 @@
+// Bulk balance updates, checked against per-validator updates.
+
+use beacon::{BeaconState, BeaconExecutive, MinimalConfig};
+use beacon::utils::{apply_balance_deltas, balance_deltas};
+use bm_le::MaxVec;
+
+#[test]
+fn deltas_are_applied_as_reward_then_penalty() {
+	let mut balances = vec![32, 10, 5, 0];
+	apply_balance_deltas(&mut balances, &[1, 0, 3, 0], &[0, 12, 7, 1]);
+
+	assert_eq!(balances, vec![33, 0, 1, 0]);
+}
+
+#[test]
+fn bulk_deltas_match_per_validator_updates() {
+	let balances = vec![32_000_000_000, 31_000_000_000, 5, 0, 17, 1_000];
+	let rewards = vec![1_000, 0, 3, 0, 0, 24];
+	let penalties = vec![500, 2_000, 9, 1, 17, 0];
+
+	let mut bulk = BeaconState::<MinimalConfig>::default();
+	bulk.balances = MaxVec::from(balances.clone());
+	let mut single = bulk.clone();
+
+	BeaconExecutive::new(&mut bulk).apply_balance_deltas(&rewards, &penalties);
+	let mut executive = BeaconExecutive::new(&mut single);
+	for index in 0..balances.len() {
+		executive.increase_balance(index as u64, rewards[index]);
+		executive.decrease_balance(index as u64, penalties[index]);
+	}
+
+	assert_eq!(bulk.balances, single.balances);
+}
+
+#[test]
+fn balance_deltas_list_changed_validators() {
+	let previous = vec![32, 10, 5];
+	let current = vec![33, 10, 1, 7];
+
+	assert_eq!(balance_deltas(&previous, &current), vec![(0, 1), (2, -4), (3, 7)]);
+}
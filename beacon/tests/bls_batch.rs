@@ -0,0 +1,44 @@
+// Signatures skipped and kept when executing batch verified blocks.
+
+use beacon::{BLSConfig, BLSBatchVerified, Config, MinimalConfig};
+use beacon::domain::compute_domain;
+use beacon::primitives::{H256, Signature, ValidatorId};
+
+/// Rejects every signature.
+#[derive(Default, Clone)]
+struct BLSReject;
+
+impl BLSConfig for BLSReject {
+	fn verify(_pubkey: &ValidatorId, _message: &H256, _signature: &Signature, _domain: u64) -> bool {
+		false
+	}
+	fn aggregate_pubkeys(_pubkeys: &[ValidatorId]) -> ValidatorId {
+		ValidatorId::default()
+	}
+	fn aggregate_signatures(_signatures: &[Signature]) -> Signature {
+		Signature::default()
+	}
+	fn verify_multiple(_pubkeys: &[ValidatorId], _messages: &[H256], _signature: &Signature, _domain: u64) -> bool {
+		false
+	}
+}
+
+type BLS = BLSBatchVerified<MinimalConfig, BLSReject>;
+
+fn verify(domain_type: u32) -> bool {
+	let domain = compute_domain(domain_type, [1, 0, 0, 0].into());
+	BLS::verify(&ValidatorId::default(), &H256::default(), &Signature::default(), domain)
+}
+
+#[test]
+fn proposer_randao_and_attestation_signatures_are_skipped() {
+	assert!(verify(MinimalConfig::domain_beacon_proposer()));
+	assert!(verify(MinimalConfig::domain_randao()));
+	assert!(verify(MinimalConfig::domain_beacon_attester()));
+}
+
+#[test]
+fn deposit_and_exit_signatures_are_verified() {
+	assert!(!verify(MinimalConfig::domain_deposit()));
+	assert!(!verify(MinimalConfig::domain_voluntary_exit()));
+}
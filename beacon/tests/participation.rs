@@ -0,0 +1,156 @@
+// Rewards from the participation cache, checked against the per-attestation
+// computation of the spec.
+
+use beacon::{BeaconState, BeaconExecutive, Config, MinimalConfig};
+use beacon::consts::{BASE_REWARDS_PER_EPOCH, FAR_FUTURE_EPOCH};
+use beacon::primitives::H256;
+use beacon::types::{AttestationData, Checkpoint, PendingAttestation, Validator};
+use beacon::utils::{apply_balance_deltas, integer_squareroot};
+use bm_le::MaxVec;
+
+type C = MinimalConfig;
+
+const VALIDATORS: u64 = 64;
+
+fn block_root(slot: u64) -> H256 {
+	H256::repeat_byte(slot as u8 + 1)
+}
+
+/// State at the last slot of `current_epoch`, with several previous epoch
+/// attestations per validator, at different inclusion delays and with
+/// different target and head votes.
+fn state(current_epoch: u64, finalized_epoch: u64) -> BeaconState<C> {
+	let slots_per_epoch = C::slots_per_epoch();
+	let mut state = BeaconState::<C>::default();
+	state.slot = (current_epoch + 1) * slots_per_epoch - 1;
+	state.finalized_checkpoint.epoch = finalized_epoch;
+	for slot in 0..state.slot {
+		state.block_roots[(slot % C::slots_per_historical_root()) as usize] = block_root(slot);
+	}
+	for i in 0..VALIDATORS {
+		state.validators.push(Validator {
+			effective_balance: C::max_effective_balance() - (i % 3) * 1_000_000_000,
+			slashed: i == 5,
+			exit_epoch: if i == 63 { 1 } else { FAR_FUTURE_EPOCH },
+			withdrawable_epoch: FAR_FUTURE_EPOCH,
+			..Default::default()
+		});
+		state.balances.push(C::max_effective_balance() + i);
+	}
+
+	let previous_epoch = current_epoch - 1;
+	let target_root = block_root(previous_epoch * slots_per_epoch);
+	let mut attestations = Vec::new();
+	{
+		let executive = BeaconExecutive::new(&mut state);
+		for slot in (previous_epoch * slots_per_epoch)..(current_epoch * slots_per_epoch) {
+			for index in 0..executive.committee_count_at_slot(slot) {
+				let committee = executive.beacon_committee(slot, index).unwrap();
+				let attestation = |bits: &dyn Fn(usize) -> bool, target: H256, head: H256, delay: u64| {
+					PendingAttestation::<C> {
+						aggregation_bits: MaxVec::from(
+							(0..committee.len()).map(|i| bits(i)).collect::<Vec<_>>()
+						),
+						data: AttestationData {
+							slot,
+							index,
+							beacon_block_root: head,
+							source: Checkpoint::default(),
+							target: Checkpoint { epoch: previous_epoch, root: target },
+						},
+						inclusion_delay: delay,
+						proposer_index: (slot * 7 + delay) % VALIDATORS,
+					}
+				};
+
+				// Late, but with matching target and head.
+				attestations.push(attestation(&|i| i % 4 != 0, target_root, block_root(slot), 4));
+				// Earlier, with a wrong head.
+				attestations.push(attestation(&|i| i % 2 == 0, target_root, H256::repeat_byte(0xee), 1 + slot % 3));
+				// Earliest, with a wrong target.
+				attestations.push(attestation(&|i| i % 3 == 1, H256::repeat_byte(0xdd), block_root(slot), 1));
+				// Same delay as the previous one, included by another proposer.
+				attestations.push(attestation(&|i| i % 3 == 1, target_root, block_root(slot), 1));
+			}
+		}
+	}
+	state.previous_epoch_attestations = MaxVec::from(attestations);
+	state
+}
+
+/// Attestation deltas as specified, scanning attestations per component.
+fn spec_deltas(executive: &BeaconExecutive<C>) -> (Vec<u64>, Vec<u64>) {
+	let previous_epoch = executive.previous_epoch();
+	let total_balance = executive.total_active_balance();
+	let base_reward = |index: u64| {
+		executive.validators[index as usize].effective_balance * C::base_reward_factor() /
+			integer_squareroot(total_balance) / BASE_REWARDS_PER_EPOCH
+	};
+	let eligible = (0..executive.validators.len() as u64).filter(|index| {
+		let validator = &executive.validators[*index as usize];
+		validator.is_active(previous_epoch) ||
+			(validator.slashed && previous_epoch + 1 < validator.withdrawable_epoch)
+	}).collect::<Vec<_>>();
+	let mut rewards = vec![0; executive.validators.len()];
+	let mut penalties = vec![0; executive.validators.len()];
+
+	let source = executive.matching_source_attestations(previous_epoch).unwrap();
+	let target = executive.matching_target_attestations(previous_epoch).unwrap();
+	let head = executive.matching_head_attestations(previous_epoch).unwrap();
+	for attestations in &[&source, &target, &head] {
+		let unslashed = executive.unslashed_attesting_indices(attestations).unwrap();
+		let attesting_balance = executive.total_balance(&unslashed);
+		for index in &eligible {
+			if unslashed.contains(index) {
+				rewards[*index as usize] += base_reward(*index) * attesting_balance / total_balance;
+			} else {
+				penalties[*index as usize] += base_reward(*index);
+			}
+		}
+	}
+
+	for index in executive.unslashed_attesting_indices(&source).unwrap() {
+		let attestation = source.iter()
+			.filter(|a| executive.attesting_indices(&a.data, &a.aggregation_bits).unwrap().contains(&index))
+			.min_by_key(|a| a.inclusion_delay)
+			.unwrap();
+		let proposer_reward = base_reward(index) / C::proposer_reward_quotient();
+		rewards[attestation.proposer_index as usize] += proposer_reward;
+		rewards[index as usize] += (base_reward(index) - proposer_reward) / attestation.inclusion_delay;
+	}
+
+	let finality_delay = previous_epoch - executive.finalized_checkpoint.epoch;
+	if finality_delay > C::min_epochs_to_inactivity_penalty() {
+		let target_indices = executive.unslashed_attesting_indices(&target).unwrap();
+		for index in &eligible {
+			penalties[*index as usize] += BASE_REWARDS_PER_EPOCH * base_reward(*index);
+			if !target_indices.contains(index) {
+				penalties[*index as usize] += executive.validators[*index as usize].effective_balance *
+					finality_delay / C::inactivity_penalty_quotient();
+			}
+		}
+	}
+
+	(rewards, penalties)
+}
+
+fn check_rewards(current_epoch: u64, finalized_epoch: u64) {
+	let mut cached = state(current_epoch, finalized_epoch);
+	let mut expected = cached.balances.iter().cloned().collect::<Vec<_>>();
+	let (rewards, penalties) = spec_deltas(&BeaconExecutive::new(&mut cached.clone()));
+	apply_balance_deltas(&mut expected, &rewards, &penalties);
+
+	BeaconExecutive::new(&mut cached).process_rewards_and_penalties().unwrap();
+
+	assert_eq!(cached.balances.iter().cloned().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn cached_rewards_match_per_attestation_rewards() {
+	check_rewards(3, 1);
+}
+
+#[test]
+fn cached_rewards_match_per_attestation_rewards_during_inactivity_leak() {
+	check_rewards(7, 0);
+}
@@ -0,0 +1,56 @@
+// Proposer signatures verified as several aggregates.
+
+use beacon::{
+	BLSConfig, ProposerSignature, verify_proposer_signatures, PROPOSER_SIGNATURES_PER_AGGREGATE,
+};
+use beacon::primitives::{H256, Signature, ValidatorId};
+
+const INVALID: u8 = 0xff;
+
+/// Rejects aggregates that are too large or contain an invalid message.
+struct BLSInvalidMessage;
+
+impl BLSConfig for BLSInvalidMessage {
+	fn verify(_pubkey: &ValidatorId, message: &H256, _signature: &Signature, _domain: u64) -> bool {
+		*message != H256::repeat_byte(INVALID)
+	}
+	fn aggregate_pubkeys(_pubkeys: &[ValidatorId]) -> ValidatorId {
+		ValidatorId::default()
+	}
+	fn aggregate_signatures(_signatures: &[Signature]) -> Signature {
+		Signature::default()
+	}
+	fn verify_multiple(pubkeys: &[ValidatorId], messages: &[H256], _signature: &Signature, _domain: u64) -> bool {
+		pubkeys.len() == messages.len() &&
+			messages.len() <= PROPOSER_SIGNATURES_PER_AGGREGATE &&
+			!messages.contains(&H256::repeat_byte(INVALID))
+	}
+}
+
+fn signatures(count: usize) -> Vec<ProposerSignature> {
+	(0..count).map(|i| ProposerSignature {
+		pubkey: ValidatorId::default(),
+		message: H256::repeat_byte(i as u8),
+		signature: Signature::default(),
+		domain: (i % 2) as u64,
+	}).collect()
+}
+
+#[test]
+fn valid_signatures_across_aggregates_verify() {
+	assert!(verify_proposer_signatures::<BLSInvalidMessage>(&[]));
+	assert!(verify_proposer_signatures::<BLSInvalidMessage>(&signatures(1)));
+	assert!(verify_proposer_signatures::<BLSInvalidMessage>(
+		&signatures(5 * PROPOSER_SIGNATURES_PER_AGGREGATE + 3)
+	));
+}
+
+#[test]
+fn invalid_signature_in_any_aggregate_fails() {
+	let count = 5 * PROPOSER_SIGNATURES_PER_AGGREGATE + 3;
+	for invalid in &[0, PROPOSER_SIGNATURES_PER_AGGREGATE, count - 1] {
+		let mut signatures = signatures(count);
+		signatures[*invalid].message = H256::repeat_byte(INVALID);
+		assert!(!verify_proposer_signatures::<BLSInvalidMessage>(&signatures));
+	}
+}
@@ -0,0 +1,270 @@
+// Golden SSZ encodings and tree roots of the beacon containers, filled with
+// fixed contents. Any difference is consensus breaking. When a change is
+// intended, rewrite the golden file with
+// `SSZ_GOLDEN_BLESS=1 cargo test --test ssz_golden` and review its diff.
+
+use std::collections::HashMap;
+use std::fs;
+use beacon::{BeaconState, Config, MinimalConfig};
+use beacon::primitives::{H32, H256, H384, H768};
+use beacon::types::*;
+use bm_le::{tree_root, IntoTree, MaxVec};
+use ssz::Encode;
+use typenum::Unsigned;
+use vecarray::VecArray;
+
+type C = MinimalConfig;
+
+const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/minimal.txt");
+
+fn hex(bytes: &[u8]) -> String {
+	let mut ret = String::from("0x");
+	for byte in bytes {
+		ret.push_str(&format!("{:02x}", byte));
+	}
+	ret
+}
+
+fn filled<T: Clone, L: Unsigned>(value: T) -> VecArray<T, L> {
+	VecArray::try_from(vec![value; L::to_usize()]).expect("Length matches; qed")
+}
+
+fn fork() -> Fork {
+	Fork { previous_version: H32::repeat_byte(1), current_version: H32::repeat_byte(2), epoch: 3 }
+}
+
+fn checkpoint(epoch: u64, root: u8) -> Checkpoint {
+	Checkpoint { epoch, root: H256::repeat_byte(root) }
+}
+
+fn validator() -> Validator {
+	Validator {
+		pubkey: H384::repeat_byte(6),
+		withdrawal_credentials: H256::repeat_byte(7),
+		effective_balance: 32_000_000_000,
+		slashed: true,
+		activation_eligibility_epoch: 8,
+		activation_epoch: 9,
+		exit_epoch: u64::max_value(),
+		withdrawable_epoch: 10,
+	}
+}
+
+fn attestation_data() -> AttestationData {
+	AttestationData {
+		slot: 11,
+		index: 12,
+		beacon_block_root: H256::repeat_byte(13),
+		source: checkpoint(14, 15),
+		target: checkpoint(16, 17),
+	}
+}
+
+fn bits() -> MaxVec<bool, <C as Config>::MaxValidatorsPerCommittee> {
+	MaxVec::from(vec![true, false, true, true, false])
+}
+
+fn indexed_attestation() -> IndexedAttestation<C> {
+	IndexedAttestation {
+		custody_bit_0_indices: MaxVec::from(vec![1, 3, 5]),
+		custody_bit_1_indices: MaxVec::from(vec![2]),
+		data: attestation_data(),
+		signature: H768::repeat_byte(18),
+	}
+}
+
+fn pending_attestation() -> PendingAttestation<C> {
+	PendingAttestation {
+		aggregation_bits: bits(),
+		data: attestation_data(),
+		inclusion_delay: 19,
+		proposer_index: 20,
+	}
+}
+
+fn eth1_data() -> Eth1Data {
+	Eth1Data { deposit_root: H256::repeat_byte(21), deposit_count: 22, block_hash: H256::repeat_byte(23) }
+}
+
+fn deposit_data() -> DepositData {
+	DepositData {
+		pubkey: H384::repeat_byte(26),
+		withdrawal_credentials: H256::repeat_byte(27),
+		amount: 32_000_000_000,
+		signature: H768::repeat_byte(28),
+	}
+}
+
+fn block_header() -> BeaconBlockHeader {
+	BeaconBlockHeader {
+		slot: 29,
+		parent_root: H256::repeat_byte(30),
+		state_root: H256::repeat_byte(31),
+		body_root: H256::repeat_byte(32),
+		signature: H768::repeat_byte(33),
+	}
+}
+
+fn proposer_slashing() -> ProposerSlashing {
+	ProposerSlashing {
+		proposer_index: 34,
+		header_1: block_header(),
+		header_2: BeaconBlockHeader { state_root: H256::repeat_byte(35), ..block_header() },
+	}
+}
+
+fn attester_slashing() -> AttesterSlashing<C> {
+	let mut attestation_2 = indexed_attestation();
+	attestation_2.data.beacon_block_root = H256::repeat_byte(36);
+	AttesterSlashing { attestation_1: indexed_attestation(), attestation_2 }
+}
+
+fn attestation() -> Attestation<C> {
+	Attestation {
+		aggregation_bits: bits(),
+		data: attestation_data(),
+		custody_bits: MaxVec::from(vec![false, false, true, false, false]),
+		signature: H768::repeat_byte(37),
+	}
+}
+
+fn deposit() -> Deposit {
+	Deposit { proof: filled(H256::repeat_byte(38)), data: deposit_data() }
+}
+
+fn voluntary_exit() -> VoluntaryExit {
+	VoluntaryExit { epoch: 39, validator_index: 40, signature: H768::repeat_byte(41) }
+}
+
+#[cfg(not(feature = "shards"))]
+fn block_body() -> BeaconBlockBody<C> {
+	BeaconBlockBody {
+		randao_reveal: H768::repeat_byte(42),
+		eth1_data: eth1_data(),
+		graffiti: H256::repeat_byte(43),
+		proposer_slashings: MaxVec::from(vec![proposer_slashing()]),
+		attester_slashings: MaxVec::from(vec![attester_slashing()]),
+		attestations: MaxVec::from(vec![attestation()]),
+		deposits: MaxVec::from(vec![deposit()]),
+		voluntary_exits: MaxVec::from(vec![voluntary_exit()]),
+	}
+}
+
+#[cfg(not(feature = "shards"))]
+fn block() -> BeaconBlock<C> {
+	BeaconBlock {
+		slot: 44,
+		parent_root: H256::repeat_byte(45),
+		state_root: H256::repeat_byte(46),
+		body: block_body(),
+		signature: H768::repeat_byte(47),
+	}
+}
+
+fn state() -> BeaconState<C> {
+	BeaconState {
+		genesis_time: 48,
+		slot: 49,
+		fork: fork(),
+		latest_block_header: block_header(),
+		block_roots: filled(H256::repeat_byte(50)),
+		state_roots: filled(H256::repeat_byte(51)),
+		historical_roots: MaxVec::from(vec![H256::repeat_byte(52)]),
+		eth1_data: eth1_data(),
+		eth1_data_votes: MaxVec::from(vec![eth1_data()]),
+		eth1_deposit_index: 53,
+		validators: MaxVec::from(vec![validator()]),
+		balances: MaxVec::from(vec![32_000_000_000]),
+		randao_mixes: filled(H256::repeat_byte(54)),
+		slashings: filled(55),
+		previous_epoch_attestations: MaxVec::from(vec![pending_attestation()]),
+		current_epoch_attestations: MaxVec::from(Vec::new()),
+		justification_bits: VecArray::try_from(vec![true, false, true, false])
+			.expect("Length matches; qed"),
+		previous_justified_checkpoint: checkpoint(56, 57),
+		current_justified_checkpoint: checkpoint(58, 59),
+		finalized_checkpoint: checkpoint(60, 61),
+	}
+}
+
+struct Snapshot {
+	name: &'static str,
+	encoding: String,
+	root: String,
+}
+
+fn snapshot<T: Encode + IntoTree>(name: &'static str, value: T) -> Snapshot {
+	Snapshot {
+		name,
+		encoding: hex(&value.encode()),
+		root: hex(tree_root::<<C as Config>::Digest, _>(&value).as_ref()),
+	}
+}
+
+fn snapshots() -> Vec<Snapshot> {
+	let mut snapshots = vec![
+		snapshot("Fork", fork()),
+		snapshot("Checkpoint", checkpoint(4, 5)),
+		snapshot("Validator", validator()),
+		snapshot("AttestationData", attestation_data()),
+		snapshot("AttestationDataAndCustodyBit", AttestationDataAndCustodyBit {
+			data: attestation_data(),
+			custody_bit: true,
+		}),
+		snapshot("IndexedAttestation", indexed_attestation()),
+		snapshot("SigningIndexedAttestation", SigningIndexedAttestation::from(indexed_attestation())),
+		snapshot("PendingAttestation", pending_attestation()),
+		snapshot("Eth1Data", eth1_data()),
+		snapshot("HistoricalBatch", HistoricalBatch::<C> {
+			block_roots: filled(H256::repeat_byte(24)),
+			state_roots: filled(H256::repeat_byte(25)),
+		}),
+		snapshot("DepositData", deposit_data()),
+		snapshot("SigningDepositData", SigningDepositData::from(deposit_data())),
+		snapshot("BeaconBlockHeader", block_header()),
+		snapshot("SigningBeaconBlockHeader", SigningBeaconBlockHeader::from(block_header())),
+		snapshot("ProposerSlashing", proposer_slashing()),
+		snapshot("AttesterSlashing", attester_slashing()),
+		snapshot("Attestation", attestation()),
+		snapshot("SigningAttestation", SigningAttestation::from(attestation())),
+		snapshot("Deposit", deposit()),
+		snapshot("VoluntaryExit", voluntary_exit()),
+		snapshot("SigningVoluntaryExit", SigningVoluntaryExit::from(voluntary_exit())),
+	];
+	#[cfg(not(feature = "shards"))]
+	snapshots.extend(vec![
+		snapshot("BeaconBlockBody", block_body()),
+		snapshot("BeaconBlock", block()),
+		snapshot("UnsealedBeaconBlock", UnsealedBeaconBlock::from(&block())),
+	]);
+	snapshots.push(snapshot("BeaconState", state()));
+	snapshots
+}
+
+#[test]
+fn encodings_and_roots_match_golden() {
+	let snapshots = snapshots();
+
+	if std::env::var_os("SSZ_GOLDEN_BLESS").is_some() {
+		let golden = snapshots.iter()
+			.map(|snapshot| format!("{} {} {}\n", snapshot.name, snapshot.encoding, snapshot.root))
+			.collect::<String>();
+		fs::write(GOLDEN, golden).expect("Writing golden file failed");
+		return
+	}
+
+	let golden = fs::read_to_string(GOLDEN).expect("Reading golden file failed");
+	let golden = golden.lines()
+		.filter_map(|line| {
+			let mut parts = line.split_whitespace();
+			Some((parts.next()?, (parts.next()?, parts.next()?)))
+		})
+		.collect::<HashMap<_, _>>();
+
+	for snapshot in snapshots {
+		let (encoding, root) = golden.get(snapshot.name)
+			.unwrap_or_else(|| panic!("{} has no golden entry", snapshot.name));
+		assert_eq!(&snapshot.encoding, encoding, "Encoding of {} changed", snapshot.name);
+		assert_eq!(&snapshot.root, root, "Tree root of {} changed", snapshot.name);
+	}
+}
@@ -0,0 +1,378 @@
+// Properties of the SSZ encodings and tree roots of the beacon containers,
+// over generated contents.
+
+use std::fmt::Debug;
+use beacon::MinimalConfig;
+use beacon::primitives::{H32, H256, H384, H768};
+use beacon::types::*;
+use bm_le::{tree_root, IntoTree, MaxVec};
+use proptest::prelude::*;
+use proptest::collection::vec;
+use proptest::test_runner::TestCaseError;
+use sha2::{Digest, Sha256};
+use ssz::{Encode, Decode};
+use vecarray::VecArray;
+
+type C = MinimalConfig;
+
+fn h32() -> impl Strategy<Value = H32> {
+	any::<[u8; 4]>().prop_map(H32::from)
+}
+
+fn h256() -> impl Strategy<Value = H256> {
+	any::<[u8; 32]>().prop_map(H256::from)
+}
+
+fn h384() -> impl Strategy<Value = H384> {
+	vec(any::<u8>(), 48).prop_map(|bytes| H384::from_slice(&bytes))
+}
+
+fn h768() -> impl Strategy<Value = H768> {
+	vec(any::<u8>(), 96).prop_map(|bytes| H768::from_slice(&bytes))
+}
+
+fn bits() -> impl Strategy<Value = MaxVec<bool, <C as beacon::Config>::MaxValidatorsPerCommittee>> {
+	vec(any::<bool>(), 0..64).prop_map(MaxVec::from)
+}
+
+fn indices() -> impl Strategy<Value = MaxVec<u64, <C as beacon::Config>::MaxValidatorsPerCommittee>> {
+	vec(any::<u64>(), 0..16).prop_map(MaxVec::from)
+}
+
+prop_compose! {
+	fn fork()(previous_version in h32(), current_version in h32(), epoch in any::<u64>()) -> Fork {
+		Fork { previous_version, current_version, epoch }
+	}
+}
+
+prop_compose! {
+	fn checkpoint()(epoch in any::<u64>(), root in h256()) -> Checkpoint {
+		Checkpoint { epoch, root }
+	}
+}
+
+prop_compose! {
+	fn validator()(
+		pubkey in h384(),
+		withdrawal_credentials in h256(),
+		effective_balance in any::<u64>(),
+		slashed in any::<bool>(),
+		epochs in any::<[u64; 4]>(),
+	) -> Validator {
+		Validator {
+			pubkey, withdrawal_credentials, effective_balance, slashed,
+			activation_eligibility_epoch: epochs[0],
+			activation_epoch: epochs[1],
+			exit_epoch: epochs[2],
+			withdrawable_epoch: epochs[3],
+		}
+	}
+}
+
+prop_compose! {
+	fn attestation_data()(
+		slot in any::<u64>(),
+		index in any::<u64>(),
+		beacon_block_root in h256(),
+		source in checkpoint(),
+		target in checkpoint(),
+	) -> AttestationData {
+		AttestationData { slot, index, beacon_block_root, source, target }
+	}
+}
+
+prop_compose! {
+	fn indexed_attestation()(
+		custody_bit_0_indices in indices(),
+		custody_bit_1_indices in indices(),
+		data in attestation_data(),
+		signature in h768(),
+	) -> IndexedAttestation<C> {
+		IndexedAttestation { custody_bit_0_indices, custody_bit_1_indices, data, signature }
+	}
+}
+
+prop_compose! {
+	fn pending_attestation()(
+		aggregation_bits in bits(),
+		data in attestation_data(),
+		inclusion_delay in any::<u64>(),
+		proposer_index in any::<u64>(),
+	) -> PendingAttestation<C> {
+		PendingAttestation { aggregation_bits, data, inclusion_delay, proposer_index }
+	}
+}
+
+prop_compose! {
+	fn eth1_data()(deposit_root in h256(), deposit_count in any::<u64>(), block_hash in h256()) -> Eth1Data {
+		Eth1Data { deposit_root, deposit_count, block_hash }
+	}
+}
+
+prop_compose! {
+	fn deposit_data()(
+		pubkey in h384(),
+		withdrawal_credentials in h256(),
+		amount in any::<u64>(),
+		signature in h768(),
+	) -> DepositData {
+		DepositData { pubkey, withdrawal_credentials, amount, signature }
+	}
+}
+
+prop_compose! {
+	fn block_header()(
+		slot in any::<u64>(),
+		parent_root in h256(),
+		state_root in h256(),
+		body_root in h256(),
+		signature in h768(),
+	) -> BeaconBlockHeader {
+		BeaconBlockHeader { slot, parent_root, state_root, body_root, signature }
+	}
+}
+
+prop_compose! {
+	fn proposer_slashing()(
+		proposer_index in any::<u64>(),
+		header_1 in block_header(),
+		header_2 in block_header(),
+	) -> ProposerSlashing {
+		ProposerSlashing { proposer_index, header_1, header_2 }
+	}
+}
+
+prop_compose! {
+	fn attester_slashing()(
+		attestation_1 in indexed_attestation(),
+		attestation_2 in indexed_attestation(),
+	) -> AttesterSlashing<C> {
+		AttesterSlashing { attestation_1, attestation_2 }
+	}
+}
+
+prop_compose! {
+	fn attestation()(
+		aggregation_bits in bits(),
+		data in attestation_data(),
+		custody_bits in bits(),
+		signature in h768(),
+	) -> Attestation<C> {
+		Attestation { aggregation_bits, data, custody_bits, signature }
+	}
+}
+
+prop_compose! {
+	fn deposit()(proof in vec(h256(), 33), data in deposit_data()) -> Deposit {
+		Deposit { proof: VecArray::try_from(proof).expect("Length matches; qed"), data }
+	}
+}
+
+prop_compose! {
+	fn voluntary_exit()(epoch in any::<u64>(), validator_index in any::<u64>(), signature in h768()) -> VoluntaryExit {
+		VoluntaryExit { epoch, validator_index, signature }
+	}
+}
+
+#[cfg(not(feature = "shards"))]
+prop_compose! {
+	fn block()(
+		slot in any::<u64>(),
+		parent_root in h256(),
+		state_root in h256(),
+		randao_reveal in h768(),
+		eth1_data in eth1_data(),
+		graffiti in h256(),
+		proposer_slashings in vec(proposer_slashing(), 0..2),
+		attester_slashings in vec(attester_slashing(), 0..=1),
+		attestations in vec(attestation(), 0..3),
+		deposits in vec(deposit(), 0..2),
+		voluntary_exits in vec(voluntary_exit(), 0..3),
+		signature in h768(),
+	) -> BeaconBlock<C> {
+		BeaconBlock {
+			slot, parent_root, state_root,
+			body: BeaconBlockBody {
+				randao_reveal, eth1_data, graffiti,
+				proposer_slashings: MaxVec::from(proposer_slashings),
+				attester_slashings: MaxVec::from(attester_slashings),
+				attestations: MaxVec::from(attestations),
+				deposits: MaxVec::from(deposits),
+				voluntary_exits: MaxVec::from(voluntary_exits),
+			},
+			signature,
+		}
+	}
+}
+
+fn round_trips<T: Encode + Decode + PartialEq + Debug>(value: T) -> Result<(), TestCaseError> {
+	let encoded = value.encode();
+	prop_assert_eq!(T::decode(&encoded).ok(), Some(value));
+	Ok(())
+}
+
+fn rejects_resized<T: Encode + Decode + Debug>(
+	value: T,
+	cut: usize,
+	extra: Vec<u8>,
+) -> Result<(), TestCaseError> {
+	let encoded = value.encode();
+	prop_assert!(T::decode(&encoded[..(cut % encoded.len())]).is_err());
+
+	let mut overlong = encoded;
+	overlong.extend(extra);
+	prop_assert!(T::decode(&overlong).is_err());
+	Ok(())
+}
+
+fn chunk(bytes: &[u8]) -> [u8; 32] {
+	let mut chunk = [0u8; 32];
+	chunk[..bytes.len()].copy_from_slice(bytes);
+	chunk
+}
+
+fn root_chunk<T: IntoTree>(value: &T) -> [u8; 32] {
+	chunk(tree_root::<Sha256, _>(value).as_ref())
+}
+
+fn merkleize(mut chunks: Vec<[u8; 32]>) -> H256 {
+	chunks.resize(chunks.len().next_power_of_two(), [0u8; 32]);
+	while chunks.len() > 1 {
+		chunks = chunks.chunks(2).map(|pair| {
+			let mut hasher = Sha256::new();
+			hasher.input(&pair[0]);
+			hasher.input(&pair[1]);
+			chunk(hasher.result().as_slice())
+		}).collect();
+	}
+	H256::from(chunks[0])
+}
+
+fn packed(encoded: &[u8]) -> Vec<[u8; 32]> {
+	encoded.chunks(32).map(chunk).collect()
+}
+
+proptest! {
+	#[test]
+	fn fixed_containers_round_trip(
+		fork in fork(),
+		checkpoint in checkpoint(),
+		validator in validator(),
+		data in attestation_data(),
+		eth1_data in eth1_data(),
+		header in block_header(),
+		slashing in proposer_slashing(),
+		deposit in deposit(),
+		exit in voluntary_exit(),
+	) {
+		round_trips(fork)?;
+		round_trips(checkpoint)?;
+		round_trips(validator)?;
+		round_trips(data)?;
+		round_trips(eth1_data)?;
+		round_trips(header)?;
+		round_trips(slashing)?;
+		round_trips(deposit)?;
+		round_trips(exit)?;
+	}
+
+	#[test]
+	fn variable_containers_round_trip(
+		indexed in indexed_attestation(),
+		pending in pending_attestation(),
+		slashing in attester_slashing(),
+		attestation in attestation(),
+	) {
+		round_trips(indexed)?;
+		round_trips(pending)?;
+		round_trips(slashing)?;
+		round_trips(attestation)?;
+	}
+
+	#[cfg(not(feature = "shards"))]
+	#[test]
+	fn blocks_round_trip(block in block()) {
+		round_trips(block)?;
+	}
+
+	#[test]
+	fn fixed_containers_reject_resized_input(
+		checkpoint in checkpoint(),
+		validator in validator(),
+		data in attestation_data(),
+		header in block_header(),
+		deposit in deposit(),
+		exit in voluntary_exit(),
+		cut in any::<usize>(),
+		extra in vec(any::<u8>(), 1..64),
+	) {
+		rejects_resized(checkpoint, cut, extra.clone())?;
+		rejects_resized(validator, cut, extra.clone())?;
+		rejects_resized(data, cut, extra.clone())?;
+		rejects_resized(header, cut, extra.clone())?;
+		rejects_resized(deposit, cut, extra.clone())?;
+		rejects_resized(exit, cut, extra)?;
+	}
+
+	#[test]
+	fn byte_vector_roots_merkleize_serialized_chunks(pubkey in h384(), signature in h768()) {
+		prop_assert_eq!(tree_root::<Sha256, _>(&pubkey), merkleize(packed(&pubkey.encode())));
+		prop_assert_eq!(tree_root::<Sha256, _>(&signature), merkleize(packed(&signature.encode())));
+	}
+
+	#[test]
+	fn basic_container_roots_merkleize_serialized_chunks(
+		fork in fork(),
+		checkpoint in checkpoint(),
+		eth1_data in eth1_data(),
+		header in block_header(),
+		exit in voluntary_exit(),
+	) {
+		prop_assert_eq!(tree_root::<Sha256, _>(&fork), merkleize(vec![
+			chunk(&fork.previous_version.encode()),
+			chunk(&fork.current_version.encode()),
+			chunk(&fork.epoch.encode()),
+		]));
+		prop_assert_eq!(tree_root::<Sha256, _>(&checkpoint), merkleize(vec![
+			chunk(&checkpoint.epoch.encode()),
+			chunk(&checkpoint.root.encode()),
+		]));
+		prop_assert_eq!(tree_root::<Sha256, _>(&eth1_data), merkleize(vec![
+			chunk(&eth1_data.deposit_root.encode()),
+			chunk(&eth1_data.deposit_count.encode()),
+			chunk(&eth1_data.block_hash.encode()),
+		]));
+
+		let header = SigningBeaconBlockHeader::from(header);
+		prop_assert_eq!(tree_root::<Sha256, _>(&header), merkleize(vec![
+			chunk(&header.slot.encode()),
+			chunk(&header.parent_root.encode()),
+			chunk(&header.state_root.encode()),
+			chunk(&header.body_root.encode()),
+		]));
+
+		let exit = SigningVoluntaryExit::from(exit);
+		prop_assert_eq!(tree_root::<Sha256, _>(&exit), merkleize(vec![
+			chunk(&exit.epoch.encode()),
+			chunk(&exit.validator_index.encode()),
+		]));
+	}
+
+	#[test]
+	fn container_roots_merkleize_field_roots(data in attestation_data(), header in block_header()) {
+		prop_assert_eq!(tree_root::<Sha256, _>(&data), merkleize(vec![
+			chunk(&data.slot.encode()),
+			chunk(&data.index.encode()),
+			chunk(&data.beacon_block_root.encode()),
+			root_chunk(&data.source),
+			root_chunk(&data.target),
+		]));
+		prop_assert_eq!(tree_root::<Sha256, _>(&header), merkleize(vec![
+			chunk(&header.slot.encode()),
+			chunk(&header.parent_root.encode()),
+			chunk(&header.state_root.encode()),
+			chunk(&header.body_root.encode()),
+			root_chunk(&header.signature),
+		]));
+	}
+}
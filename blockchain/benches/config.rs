@@ -0,0 +1,92 @@
+//! State transition hot paths under a compiled preset, whose accessors
+//! return literals, and under a runtime spec restating the same preset,
+//! whose accessors read the installed spec.
+//!
+//! Run with `cargo bench -p shasper-blockchain --bench config`.
+
+use std::time::{Duration, Instant};
+use beacon::{BeaconState, BeaconExecutive, Config, MainnetConfig};
+use beacon::consts::FAR_FUTURE_EPOCH;
+use beacon::types::Validator;
+use shasper_blockchain::spec::{self, ChainSpec, CustomConfig};
+
+const VALIDATORS: u64 = 16384;
+const ITERATIONS: u32 = 10;
+
+fn state<C: Config>() -> BeaconState<C> {
+	let mut state = BeaconState::<C>::default();
+	for _ in 0..VALIDATORS {
+		state.validators.push(Validator {
+			effective_balance: C::max_effective_balance(),
+			activation_eligibility_epoch: 0,
+			activation_epoch: 0,
+			exit_epoch: FAR_FUTURE_EPOCH,
+			withdrawable_epoch: FAR_FUTURE_EPOCH,
+			..Default::default()
+		});
+		state.balances.push(C::max_effective_balance());
+	}
+	// Past the genesis epochs, so that rewards and penalties are processed.
+	state.slot = C::slots_per_epoch() * 4 - 1;
+	state
+}
+
+fn committees<C: Config>(state: &mut BeaconState<C>) {
+	let start = state.slot - state.slot % C::slots_per_epoch();
+	let executive = BeaconExecutive::new(state);
+	for slot in start..(start + C::slots_per_epoch()) {
+		for index in 0..executive.committee_count_at_slot(slot) {
+			executive.beacon_committee(slot, index).expect("Committee index is in range");
+		}
+	}
+}
+
+fn proposers<C: Config>(state: &mut BeaconState<C>) {
+	let last = state.slot;
+	for slot in (last + 1 - C::slots_per_epoch())..=last {
+		state.slot = slot;
+		BeaconExecutive::new(state).beacon_proposer_index().expect("Validators are active");
+	}
+}
+
+fn epoch<C: Config>(state: &mut BeaconState<C>) {
+	let mut state = state.clone();
+	BeaconExecutive::new(&mut state).process_epoch().expect("Epoch processing succeeds");
+}
+
+/// Average duration of `f` over `ITERATIONS` runs, after a warm-up run.
+fn time<C: Config>(f: fn(&mut BeaconState<C>)) -> Duration {
+	let mut state = state::<C>();
+	f(&mut state);
+	let start = Instant::now();
+	for _ in 0..ITERATIONS {
+		f(&mut state);
+	}
+	start.elapsed() / ITERATIONS
+}
+
+fn compare(name: &str, compiled: Duration, runtime: Duration) {
+	println!(
+		"{:<12} compiled {:>12?}  runtime spec {:>12?}  ratio {:.3}",
+		name, compiled, runtime, runtime.as_secs_f64() / compiled.as_secs_f64(),
+	);
+}
+
+fn main() {
+	spec::install(ChainSpec::from_config::<MainnetConfig>("mainnet"))
+		.expect("Spec is installed once");
+
+	println!("{} validators, mainnet preset", VALIDATORS);
+	compare(
+		"committees",
+		time::<MainnetConfig>(committees), time::<CustomConfig<MainnetConfig>>(committees),
+	);
+	compare(
+		"proposers",
+		time::<MainnetConfig>(proposers), time::<CustomConfig<MainnetConfig>>(proposers),
+	);
+	compare(
+		"epoch",
+		time::<MainnetConfig>(epoch), time::<CustomConfig<MainnetConfig>>(epoch),
+	);
+}
@@ -15,11 +15,12 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 use core::marker::PhantomData;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use blockchain::{Block, Auxiliary};
 use blockchain::backend::{Store, ChainQuery, SharedCommittable, ChainSettlement, Operation};
 use parity_codec::{Encode, Decode};
-use rocksdb::{DB, Options};
+use rocksdb::{DB, Options, WriteBatch};
 
 use super::{RocksState, Error};
 use super::settlement::RocksSettlement;
@@ -29,6 +30,8 @@ pub struct RocksBackend<B: Block, A: Auxiliary<B>, S> {
 	db: Arc<DB>,
 	head: Arc<RwLock<B::Identifier>>,
 	genesis: Arc<B::Identifier>,
+	/// Fork choice store to be written with the next commit.
+	pending_fork_choice: Arc<Mutex<Option<Vec<u8>>>>,
 	_marker: PhantomData<(B, A, S)>,
 }
 
@@ -44,12 +47,61 @@ impl<B: Block, A: Auxiliary<B>, S> RocksBackend<B, A, S> where
 	}
 }
 
+/// Outcome of a compaction.
+#[derive(Clone, Debug)]
+pub struct CompactionStats {
+	/// Time the compaction took.
+	pub duration: Duration,
+	/// Size of the table files before the compaction, in bytes.
+	pub size_before: u64,
+	/// Size of the table files after the compaction, in bytes.
+	pub size_after: u64,
+}
+
+impl CompactionStats {
+	/// Bytes reclaimed by the compaction.
+	pub fn reclaimed(&self) -> u64 {
+		self.size_before.saturating_sub(self.size_after)
+	}
+}
+
+impl<B: Block, A: Auxiliary<B>, S> RocksBackend<B, A, S> {
+	/// Compact all columns, dropping the tombstones left by deletions.
+	/// Blocks until the compaction is finished.
+	pub fn compact(&self) -> Result<CompactionStats, Error> {
+		let size_before = self.table_files_size()?;
+		let start = Instant::now();
+		for column in COLUMNS {
+			let cf = self.db.cf_handle(column).ok_or(Error::Corrupted)?;
+			self.db.compact_range_cf(cf, None::<&[u8]>, None::<&[u8]>);
+		}
+
+		Ok(CompactionStats {
+			duration: start.elapsed(),
+			size_before,
+			size_after: self.table_files_size()?,
+		})
+	}
+
+	/// Total size of the table files of all columns, in bytes.
+	pub fn table_files_size(&self) -> Result<u64, Error> {
+		let mut total = 0;
+		for column in COLUMNS {
+			let cf = self.db.cf_handle(column).ok_or(Error::Corrupted)?;
+			total += self.db.property_int_value_cf(cf, "rocksdb.total-sst-files-size")?
+				.unwrap_or(0);
+		}
+		Ok(total)
+	}
+}
+
 impl<B: Block, A: Auxiliary<B>, S> Clone for RocksBackend<B, A, S> {
 	fn clone(&self) -> Self {
 		Self {
 			db: self.db.clone(),
 			head: self.head.clone(),
 			genesis: self.genesis.clone(),
+			pending_fork_choice: self.pending_fork_choice.clone(),
 			_marker: PhantomData,
 		}
 	}
@@ -161,8 +213,24 @@ impl<B: Block, A: Auxiliary<B>, S: RocksState> SharedCommittable for RocksBacken
 		let mut settlement = RocksSettlement::new(self);
 		operation.settle(&mut settlement)?;
 
+		let fork_choice = self.pending_fork_choice.lock().expect("Lock is poisoned").take();
+		if let Some(fork_choice) = fork_choice.clone() {
+			settlement.set_fork_choice(fork_choice);
+		}
+
 		let mut head = self.head.write().expect("Lock is poisoned");
-		let new_head = settlement.commit()?;
+		let new_head = match settlement.commit() {
+			Ok(new_head) => new_head,
+			Err(e) => {
+				// Keep the fork choice store for the next commit, unless a
+				// newer one was staged meanwhile.
+				let mut pending = self.pending_fork_choice.lock().expect("Lock is poisoned");
+				if pending.is_none() {
+					*pending = fork_choice;
+				}
+				return Err(e)
+			},
+		};
 
 		if let Some(new_head) = new_head {
 			*head = new_head;
@@ -182,9 +250,7 @@ impl<B: Block, A: Auxiliary<B>, S: RocksState> RocksBackend<B, A, S> where
 		F: FnOnce(Arc<DB>) -> Result<(B, S), Error>
 	{
 		let db_opts = Self::options();
-		let db = Arc::new(DB::open_cf(&db_opts, path, &[
-			COLUMN_BLOCKS, COLUMN_CANON_DEPTH_MAPPINGS, COLUMN_AUXILIARIES, COLUMN_INFO,
-		])?);
+		let db = Arc::new(DB::open_cf(&db_opts, path, COLUMNS)?);
 
 		let head = fetch_head(&db)?;
 		let genesis = fetch_genesis(&db)?;
@@ -195,6 +261,7 @@ impl<B: Block, A: Auxiliary<B>, S: RocksState> RocksBackend<B, A, S> where
 					db: db,
 					head: Arc::new(RwLock::new(head)),
 					genesis: Arc::new(genesis),
+					pending_fork_choice: Arc::new(Mutex::new(None)),
 					_marker: PhantomData,
 				})
 			},
@@ -210,6 +277,7 @@ impl<B: Block, A: Auxiliary<B>, S: RocksState> RocksBackend<B, A, S> where
 					db: db,
 					head: Arc::new(RwLock::new(head.clone())),
 					genesis: Arc::new(genesis.clone()),
+					pending_fork_choice: Arc::new(Mutex::new(None)),
 					_marker: PhantomData,
 				};
 
@@ -249,6 +317,44 @@ impl<B: Block, A: Auxiliary<B>, S: RocksState> RocksBackend<B, A, S> where
 		Self::open_or_create(path, |_| Err(Error::Corrupted))
 	}
 
+	/// Encoded fork choice store, if one was stored.
+	pub fn fork_choice(&self) -> Result<Option<Vec<u8>>, Error> {
+		fetch_fork_choice(&self.db)
+	}
+
+	/// Store the encoded fork choice store.
+	pub fn set_fork_choice(&self, data: Vec<u8>) -> Result<(), Error> {
+		let cf = self.db.cf_handle(COLUMN_INFO).ok_or(Error::Corrupted)?;
+		self.db.put_cf(cf, KEY_FORK_CHOICE.encode(), data)?;
+		Ok(())
+	}
+
+	/// Stage the encoded fork choice store to be written in the same batch as
+	/// the next commit, replacing any previously staged one.
+	pub fn stage_fork_choice(&self, data: Vec<u8>) {
+		*self.pending_fork_choice.lock().expect("Lock is poisoned") = Some(data);
+	}
+
+	/// Raw value in the indices column.
+	pub fn index(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		let cf = self.db.cf_handle(COLUMN_INDICES).ok_or(Error::Corrupted)?;
+		Ok(self.db.get_cf(cf, key)?.map(|raw| raw.to_vec()))
+	}
+
+	/// Atomically write changes to the indices column. `None` removes the key.
+	pub fn write_indices(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<(), Error> {
+		let cf = self.db.cf_handle(COLUMN_INDICES).ok_or(Error::Corrupted)?;
+		let mut batch = WriteBatch::default();
+		for (key, value) in changes {
+			match value {
+				Some(value) => batch.put_cf(cf, key, value)?,
+				None => batch.delete_cf(cf, key)?,
+			}
+		}
+		self.db.write(batch)?;
+		Ok(())
+	}
+
 	pub(crate) fn db(&self) -> &DB {
 		self.db.as_ref()
 	}
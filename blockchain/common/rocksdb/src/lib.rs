@@ -17,7 +17,7 @@ mod utils;
 mod settlement;
 mod backend;
 
-pub use self::backend::RocksBackend;
+pub use self::backend::{RocksBackend, CompactionStats};
 
 use std::{fmt, error as stderror};
 use std::sync::Arc;
@@ -26,6 +26,7 @@ use rocksdb::DB;
 use blockchain::backend::OperationError;
 
 #[derive(Debug)]
+#[non_exhaustive]
 /// RocksDB backend errors
 pub enum Error {
 	/// Invalid Operation
@@ -58,11 +59,24 @@ impl OperationError for Error {
 
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "{:?}", self)
+		match self {
+			Error::InvalidOperation => write!(f, "Invalid operation"),
+			Error::IsGenesis => write!(f, "Block is genesis"),
+			Error::NotExist => write!(f, "Queried data does not exist"),
+			Error::Corrupted => write!(f, "Database is corrupted"),
+			Error::Rocks(e) => write!(f, "RocksDB error: {}", e),
+		}
 	}
 }
 
-impl stderror::Error for Error { }
+impl stderror::Error for Error {
+	fn source(&self) -> Option<&(dyn stderror::Error + 'static)> {
+		match self {
+			Error::Rocks(e) => Some(e),
+			_ => None,
+		}
+	}
+}
 
 pub trait RocksState {
 	type Raw: Encode + Decode;
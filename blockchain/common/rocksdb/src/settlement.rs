@@ -259,6 +259,14 @@ impl<'a, B: Block, A: Auxiliary<B>, S: RocksState> RocksSettlement<'a, B, A, S>
 		self.changes.insert((COLUMN_INFO, KEY_GENESIS.encode()), Some(genesis.encode()));
 	}
 
+	pub(crate) fn set_fork_choice(&mut self, data: Vec<u8>) {
+		if self.last_error.is_some() {
+			return
+		}
+
+		self.changes.insert((COLUMN_INFO, KEY_FORK_CHOICE.encode()), Some(data));
+	}
+
 	pub(crate) fn commit(self) -> Result<Option<B::Identifier>, Error> {
 		if let Some(error) = self.last_error {
 			return Err(error)
@@ -22,8 +22,14 @@ pub const COLUMN_BLOCKS: &str = "blocks";
 pub const COLUMN_CANON_DEPTH_MAPPINGS: &str = "canon_depth_mappings";
 pub const COLUMN_AUXILIARIES: &str = "auxiliaries";
 pub const COLUMN_INFO: &str = "info";
+pub const COLUMN_INDICES: &str = "indices";
+/// All columns of the database.
+pub const COLUMNS: &[&str] = &[
+	COLUMN_BLOCKS, COLUMN_CANON_DEPTH_MAPPINGS, COLUMN_AUXILIARIES, COLUMN_INFO, COLUMN_INDICES,
+];
 pub const KEY_HEAD: &str = "head";
 pub const KEY_GENESIS: &str = "genesis";
+pub const KEY_FORK_CHOICE: &str = "fork_choice";
 
 #[derive(Encode, Decode)]
 pub struct BlockData<B: Block, S> {
@@ -67,3 +73,8 @@ pub fn fetch_genesis<I: Decode>(db: &DB) -> Result<Option<I>, Error> {
 	};
 	Ok(Some(I::decode(&mut raw.as_ref()).ok_or(Error::Corrupted)?))
 }
+
+pub fn fetch_fork_choice(db: &DB) -> Result<Option<Vec<u8>>, Error> {
+	let cf = db.cf_handle(COLUMN_INFO).ok_or(Error::Corrupted)?;
+	Ok(db.get_cf(cf, KEY_FORK_CHOICE.encode())?.map(|raw| raw.to_vec()))
+}
@@ -13,14 +13,21 @@
 
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
-use std::collections::HashMap;
+use core::fmt::Debug;
 use core::hash::Hash;
-use core::mem;
+use std::sync::{Arc, Mutex};
 use blockchain::{Block, Auxiliary, BlockExecutor, AsExternalities};
-use blockchain::import::{BlockImporter, RawImporter, ImportAction};
+use blockchain::import::{BlockImporter, SharedBlockImporter, RawImporter, ImportAction};
 use blockchain::backend::{Store, SharedCommittable, ImportOperation, ChainQuery, ImportLock, Operation};
 use log::*;
+use parity_codec::{Encode, Decode};
 use crate::JustifiableExecutor;
+use crate::queue::{AttestationQueue, Equivocations, LatestMessages, ProposerBoost};
+use crate::clock::SlotClock;
+use crate::checkpoint::{Checkpoint, CheckpointStore};
+use crate::persist::{ForkChoicePersistence, PersistedForkChoice};
+use crate::validity::{ValidityQuery, ValidityFilter};
+use crate::reorg::{self, Reorg, SharedReorgStats, DEFAULT_REORG_ALERT_DEPTH};
 
 pub trait AncestorQuery: Store {
 	fn ancestor_at(
@@ -63,49 +70,88 @@ impl<'a, Ba: ChainQuery> AncestorQuery for NoCacheAncestorQuery<'a, Ba> {
 
 pub struct ArchiveGhost<Ba: Store, VI: Eq + Hash> {
 	backend: Ba,
-	votes: HashMap<VI, <Ba::Block as Block>::Identifier>,
-	overlayed_votes: HashMap<VI, <Ba::Block as Block>::Identifier>,
+	votes: LatestMessages<VI, <Ba::Block as Block>::Identifier>,
+	queue: AttestationQueue<VI, <Ba::Block as Block>::Identifier>,
+	equivocations: Equivocations<VI>,
+	proposer_boost: ProposerBoost<<Ba::Block as Block>::Identifier>,
+	validity: Option<ValidityFilter<<Ba::Block as Block>::Identifier>>,
 }
 
 impl<Ba: AncestorQuery + ChainQuery, VI: Eq + Hash> ArchiveGhost<Ba, VI> {
 	pub fn new(backend: Ba) -> Self {
 		Self {
 			backend,
-			votes: Default::default(),
-			overlayed_votes: Default::default(),
+			votes: LatestMessages::new(),
+			queue: AttestationQueue::new(),
+			equivocations: Equivocations::new(),
+			proposer_boost: ProposerBoost::new(),
+			validity: None,
 		}
 	}
 
-	pub fn update_overlay(
+	/// Boost the block with the given score, on top of its votes, until the
+	/// boost is reset at the next slot.
+	pub fn set_proposer_boost(
 		&mut self,
+		block: <Ba::Block as Block>::Identifier,
+		slot: u64,
+		score: usize,
+	) {
+		self.proposer_boost.set(block, slot, score);
+	}
+
+	/// Reset the proposer boost if it was given in a slot before
+	/// `current_slot`.
+	pub fn reset_proposer_boost(
+		&mut self,
+		current_slot: u64,
+	) {
+		self.proposer_boost.reset(current_slot);
+	}
+
+	/// Queue a vote attested at `slot`. It only takes effect once
+	/// `process_queue` is called with a later slot.
+	pub fn queue_vote(
+		&mut self,
+		slot: u64,
 		validator_id: VI,
 		target_root: <Ba::Block as Block>::Identifier
 	) {
-		self.overlayed_votes.insert(validator_id, target_root);
+		self.queue.push(slot, validator_id, target_root);
 	}
 
-	pub fn commit_overlay(
-		&mut self
+	/// Apply all queued votes attested before `current_slot`. A vote only
+	/// replaces the latest message of its validator if its target epoch,
+	/// given by `epoch_at_slot`, is later.
+	pub fn process_queue<F: Fn(u64) -> u64>(
+		&mut self,
+		current_slot: u64,
+		epoch_at_slot: F,
 	) {
-		let mut overlayed_votes = HashMap::new();
-		mem::swap(&mut overlayed_votes, &mut self.overlayed_votes);
+		for (slot, validator_id, target_root) in self.queue.drain_ready(current_slot) {
+			if self.equivocations.contains(&validator_id) {
+				continue
+			}
 
-		for (k, v) in overlayed_votes {
-			self.votes.insert(k, v);
+			self.votes.insert(validator_id, epoch_at_slot(slot), target_root);
 		}
 	}
 
-	pub fn reset_overlay(
-		&mut self
+	/// Note a validator as equivocating, discarding its current and future
+	/// votes.
+	pub fn note_equivocation(
+		&mut self,
+		validator_id: VI,
 	) {
-		self.overlayed_votes = HashMap::new();
+		self.votes.remove(&validator_id);
+		self.equivocations.insert(validator_id);
 	}
 
 	pub fn update_active(
 		&mut self,
 		active_validators: &[VI]
 	) {
-		self.votes.retain(|v, _| {
+		self.votes.retain(|v| {
 			active_validators.contains(v)
 		});
 	}
@@ -116,13 +162,15 @@ impl<Ba: AncestorQuery + ChainQuery, VI: Eq + Hash> ArchiveGhost<Ba, VI> {
 		block_depth: usize
 	) -> Result<usize, Ba::Error> {
 		let mut total = 0;
-		for (_, target) in &self.overlayed_votes {
-			if self.backend.ancestor_at(target, block_depth)? == *block {
-				total += 1;
+		if let Some((boosted, score)) = self.proposer_boost.boosted() {
+			if self.backend.depth_at(boosted)? >= block_depth &&
+				self.backend.ancestor_at(boosted, block_depth)? == *block
+			{
+				total += score;
 			}
 		}
-		for (v, target) in &self.votes {
-			if !self.overlayed_votes.keys().any(|k| k == v) &&
+		for (v, _, target) in self.votes.iter() {
+			if !self.equivocations.contains(v) &&
 				self.backend.ancestor_at(target, block_depth)? == *block
 			{
 				total += 1;
@@ -131,71 +179,420 @@ impl<Ba: AncestorQuery + ChainQuery, VI: Eq + Hash> ArchiveGhost<Ba, VI> {
 		Ok(total)
 	}
 
+	/// Blocks of the tree rooted at `root`, in breadth-first order, with
+	/// their children and whether their subtree is viable, that is, contains
+	/// a leaf that may be chosen as head. Such a leaf descends from the
+	/// finalized checkpoint and its voting source agrees with the justified
+	/// checkpoint. Subtrees of invalid blocks are never viable.
+	///
+	/// Viability is computed once, bottom-up, rather than recursively for
+	/// every block on the way to the head, so that it is linear in the size
+	/// of the tree and does not recurse along long unfinalized chains.
+	fn viable_tree(
+		&self,
+		root: &<Ba::Block as Block>::Identifier,
+		root_depth: usize,
+		checkpoints: &CheckpointStore<<Ba::Block as Block>::Identifier>,
+		current_epoch: u64,
+	) -> Result<Vec<ViableBlock<<Ba::Block as Block>::Identifier>>, Ba::Error> {
+		let mut tree = vec![ViableBlock {
+			id: root.clone(),
+			parent: None,
+			children: Vec::new(),
+			depth: root_depth,
+			viable: false,
+		}];
+		let mut i = 0;
+		while i < tree.len() {
+			for child in self.backend.children_at(&tree[i].id)? {
+				let index = tree.len();
+				let depth = tree[i].depth + 1;
+				tree[i].children.push(index);
+				tree.push(ViableBlock {
+					id: child, parent: Some(i), children: Vec::new(), depth, viable: false,
+				});
+			}
+			i += 1;
+		}
+
+		let finalized = checkpoints.finalized();
+		let finalized_depth = self.backend.depth_at(&finalized.root)?;
+		// Children always come after their parent, so walking backwards
+		// visits every subtree before its root.
+		for i in (0..tree.len()).rev() {
+			let block = &tree[i].id;
+			let viable = if self.validity.as_ref().map(|v| v.is_excluded(block)).unwrap_or(false) {
+				false
+			} else if tree[i].children.is_empty() {
+				let depth = tree[i].depth;
+				self.validity.as_ref().map(|v| v.is_allowed_head(block)).unwrap_or(true) &&
+					depth >= finalized_depth &&
+					self.backend.ancestor_at(block, finalized_depth)? == finalized.root &&
+					checkpoints.is_viable_source(block, current_epoch)
+			} else {
+				tree[i].viable
+			};
+			tree[i].viable = viable;
+			if let (true, Some(parent)) = (viable, tree[i].parent) {
+				tree[parent].viable = true;
+			}
+		}
+
+		Ok(tree)
+	}
+
 	pub fn head(
 		&self,
-		justified: &<Ba::Block as Block>::Identifier,
+		checkpoints: &CheckpointStore<<Ba::Block as Block>::Identifier>,
+		current_epoch: u64,
 	) -> Result<<Ba::Block as Block>::Identifier, Ba::Error> {
-		let mut head = justified.clone();
+		let justified = &checkpoints.justified().root;
 		let mut head_depth = self.backend.depth_at(justified)?;
+		let tree = self.viable_tree(justified, head_depth, checkpoints, current_epoch)?;
+		let mut head = 0;
 		loop {
-			let children = self.backend.children_at(&head)?;
-			if children.len() == 0 {
-				return Ok(head)
-			}
-			let mut best = children[0].clone();
+			let mut best = None;
 			let mut best_score = 0;
-			for child in children {
-				let vote_count = self.vote_count(&child, head_depth + 1)?;
-				if vote_count > best_score {
-					best = child;
+			for &child in &tree[head].children {
+				if !tree[child].viable {
+					continue
+				}
+				let vote_count = self.vote_count(&tree[child].id, head_depth + 1)?;
+				if best.is_none() || vote_count > best_score {
+					best = Some(child);
 					best_score = vote_count;
 				}
 			}
-			head = best;
-			head_depth += 1;
+			match best {
+				Some(best) => {
+					head = best;
+					head_depth += 1;
+				},
+				None => return Ok(tree[head].id.clone()),
+			}
 		}
 	}
 }
 
+/// Block of the tree searched for the head.
+struct ViableBlock<Id> {
+	id: Id,
+	parent: Option<usize>,
+	children: Vec<usize>,
+	depth: usize,
+	viable: bool,
+}
+
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
+	/// Block to import is genesis.
 	IsGenesis,
+	/// Persisted fork choice could not be decoded.
+	CorruptedPersistence,
+	/// Backend or persistence failed.
 	Backend(Box<dyn std::error::Error>),
+	/// Block execution failed.
 	Executor(Box<dyn std::error::Error>),
 }
 
 impl std::fmt::Display for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{:?}", self)
+		match self {
+			Error::IsGenesis => write!(f, "Block is genesis"),
+			Error::CorruptedPersistence => write!(f, "Persisted fork choice is corrupted"),
+			Error::Backend(e) => write!(f, "Backend failed: {}", e),
+			Error::Executor(e) => write!(f, "Block execution failed: {}", e),
+		}
 	}
 }
 
-impl std::error::Error for Error { }
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Backend(e) | Error::Executor(e) => Some(e.as_ref()),
+			_ => None,
+		}
+	}
+}
 
 pub struct ArchiveGhostImporter<E: BlockExecutor, Ba: Store<Block=E::Block>> where
 	E: JustifiableExecutor,
 	Ba::Auxiliary: Auxiliary<E::Block>
 {
 	ghost: ArchiveGhost<Ba, E::ValidatorIndex>,
+	checkpoints: CheckpointStore<<E::Block as Block>::Identifier>,
+	current_epoch: u64,
+	slot_clock: Option<Box<dyn SlotClock + Send + Sync>>,
+	persistence: Option<Box<dyn ForkChoicePersistence + Send + Sync>>,
+	reorg_stats: Option<SharedReorgStats>,
+	reorg_alert_depth: usize,
 	import_lock: ImportLock,
 	executor: E,
 }
 
 impl<E: BlockExecutor, Ba: SharedCommittable + Store<Block=E::Block>> ArchiveGhostImporter<E, Ba> where
 	E: JustifiableExecutor,
+	<E::Block as Block>::Identifier: Debug,
 	Ba: AncestorQuery + ChainQuery,
 	Ba::Auxiliary: Auxiliary<E::Block>
 {
 	pub fn new(executor: E, backend: Ba, import_lock: ImportLock) -> Self {
 		Self {
 			executor, import_lock,
+			checkpoints: CheckpointStore::new(backend.genesis()),
+			current_epoch: 0,
 			ghost: ArchiveGhost::new(backend),
+			slot_clock: None,
+			persistence: None,
+			reorg_stats: None,
+			reorg_alert_depth: DEFAULT_REORG_ALERT_DEPTH,
+		}
+	}
+
+	/// Record reorgs into `stats`, warning about those reverting at least
+	/// `alert_depth` blocks or built on the finalized block.
+	pub fn with_reorg_alerts(mut self, stats: SharedReorgStats, alert_depth: usize) -> Self {
+		self.reorg_stats = Some(stats);
+		self.reorg_alert_depth = alert_depth;
+		self
+	}
+
+	/// Find the reorg that moving the head to `new_head` would cause.
+	fn find_reorg(
+		&self,
+		new_head: &<E::Block as Block>::Identifier,
+	) -> Option<Reorg<<E::Block as Block>::Identifier>> {
+		let old_head = self.ghost.backend.head();
+		match reorg::find_reorg(&self.ghost.backend, &old_head, new_head) {
+			Ok(reorg) => reorg,
+			Err(e) => {
+				debug!("Failed to check head change from {:?} to {:?} for reorgs: {:?}", old_head, new_head, e);
+				None
+			},
+		}
+	}
+
+	/// Log and record a reorg that happened.
+	fn note_reorg(&self, reorg: Reorg<<E::Block as Block>::Identifier>) {
+		let reverts_finalized_child = reorg.common_ancestor == self.checkpoints.finalized.root;
+		let alert = reorg.depth >= self.reorg_alert_depth || reverts_finalized_child;
+		if alert {
+			warn!(
+				"DEEP REORG: {} blocks reverted from head {:?} to {:?}, common ancestor {:?}{}",
+				reorg.depth, reorg.old_head, reorg.new_head, reorg.common_ancestor,
+				if reverts_finalized_child { ", reverting the child of the finalized block" } else { "" },
+			);
+		} else {
+			info!(
+				"Reorg of {} blocks from head {:?} to {:?}, common ancestor {:?}",
+				reorg.depth, reorg.old_head, reorg.new_head, reorg.common_ancestor,
+			);
+		}
+		if let Some(stats) = self.reorg_stats.as_ref() {
+			stats.lock().expect("Lock is poisoned").note(reorg.depth, alert);
+		}
+	}
+
+	/// Use the given slot clock. Without a slot clock, no block is considered
+	/// timely and proposer boost is disabled.
+	pub fn with_slot_clock<T: SlotClock + Send + Sync + 'static>(mut self, slot_clock: T) -> Self {
+		self.slot_clock = Some(Box::new(slot_clock));
+		self
+	}
+
+	/// Filter heads by execution validity of blocks from `query`. Optimistic
+	/// blocks are chosen as head only if `allow_optimistic_head` is set.
+	pub fn with_validity<Q>(mut self, query: Q, allow_optimistic_head: bool) -> Self where
+		Q: ValidityQuery<<E::Block as Block>::Identifier> + Send + Sync + 'static,
+	{
+		self.ghost.validity = Some(ValidityFilter::new(query, allow_optimistic_head));
+		self
+	}
+
+	/// Current justified and finalized checkpoints.
+	pub fn checkpoints(&self) -> &CheckpointStore<<E::Block as Block>::Identifier> {
+		&self.checkpoints
+	}
+
+	fn genesis_checkpoint(
+		&self,
+		checkpoint: Option<Checkpoint<<E::Block as Block>::Identifier>>,
+	) -> Checkpoint<<E::Block as Block>::Identifier> {
+		match checkpoint {
+			Some(checkpoint) => checkpoint,
+			None => Checkpoint { epoch: 0, root: self.ghost.backend.genesis() },
+		}
+	}
+}
+
+impl<E: BlockExecutor, Ba: SharedCommittable + Store<Block=E::Block>> ArchiveGhostImporter<E, Ba> where
+	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode,
+	Ba: AncestorQuery + ChainQuery,
+	Ba::Auxiliary: Auxiliary<E::Block>
+{
+	/// Persist the fork choice store with the given storage, restoring any
+	/// previously stored one.
+	pub fn with_persistence<T: ForkChoicePersistence + Send + Sync + 'static>(
+		mut self,
+		persistence: T
+	) -> Result<Self, Error> {
+		if let Some(raw) = persistence.load().map_err(Error::Backend)? {
+			let persisted = PersistedForkChoice::decode(&mut raw.as_ref())
+				.ok_or(Error::CorruptedPersistence)?;
+			self.restore(persisted);
+		}
+		self.persistence = Some(Box::new(persistence));
+		Ok(self)
+	}
+
+	/// Snapshot of the current fork choice store.
+	pub fn persisted(&self) -> PersistedForkChoice<E::ValidatorIndex, <E::Block as Block>::Identifier> {
+		PersistedForkChoice {
+			votes: self.ghost.votes.iter()
+				.map(|(v, epoch, t)| (v.clone(), epoch, t.clone()))
+				.collect(),
+			queued: self.ghost.queue.iter()
+				.map(|(slot, v, t)| (slot, v.clone(), t.clone()))
+				.collect(),
+			equivocations: self.ghost.equivocations.iter().cloned().collect(),
+			justified: self.checkpoints.justified.clone(),
+			finalized: self.checkpoints.finalized.clone(),
+			unrealized_justified: self.checkpoints.unrealized_justified.clone(),
+			unrealized_finalized: self.checkpoints.unrealized_finalized.clone(),
+			voting_sources: self.checkpoints.voting_sources.iter()
+				.map(|(id, (epoch, source))| (id.clone(), *epoch, source.clone()))
+				.collect(),
+			current_epoch: self.current_epoch,
+		}
+	}
+
+	/// Replace the fork choice store with a persisted one.
+	pub fn restore(
+		&mut self,
+		persisted: PersistedForkChoice<E::ValidatorIndex, <E::Block as Block>::Identifier>
+	) {
+		self.ghost.votes = LatestMessages::new();
+		for (v, epoch, t) in persisted.votes {
+			self.ghost.votes.insert(v, epoch, t);
+		}
+		self.ghost.queue = AttestationQueue::new();
+		for (slot, v, t) in persisted.queued {
+			self.ghost.queue.push(slot, v, t);
+		}
+		self.ghost.equivocations = Equivocations::new();
+		for v in persisted.equivocations {
+			self.ghost.equivocations.insert(v);
+		}
+		self.ghost.proposer_boost.clear();
+
+		self.checkpoints.justified = persisted.justified;
+		self.checkpoints.finalized = persisted.finalized;
+		self.checkpoints.unrealized_justified = persisted.unrealized_justified;
+		self.checkpoints.unrealized_finalized = persisted.unrealized_finalized;
+		self.checkpoints.voting_sources = persisted.voting_sources.into_iter()
+			.map(|(id, epoch, source)| (id, (epoch, source)))
+			.collect();
+		self.current_epoch = persisted.current_epoch;
+		self.ghost.votes.prune(self.checkpoints.finalized.epoch);
+	}
+
+	/// Write the fork choice store to the persistence storage, if any.
+	/// Failures are logged and otherwise ignored.
+	pub fn persist(&self) {
+		if let Some(persistence) = self.persistence.as_ref() {
+			if let Err(e) = persistence.store(self.persisted().encode()) {
+				warn!("Failed to persist fork choice store: {:?}", e);
+			}
+		}
+	}
+
+	/// Stage the fork choice store to be written with the next commit, so
+	/// that it never gets out of sync with the block store.
+	fn stage_persist(&self) {
+		if let Some(persistence) = self.persistence.as_ref() {
+			if let Err(e) = persistence.stage(self.persisted().encode()) {
+				warn!("Failed to persist fork choice store: {:?}", e);
+			}
+		}
+	}
+
+	/// Prune votes below finality if the finalized epoch advanced past
+	/// `finalized_epoch`. Returns whether it did.
+	fn on_finalized(&mut self, finalized_epoch: u64) -> bool {
+		if self.checkpoints.finalized.epoch > finalized_epoch {
+			self.ghost.votes.prune(self.checkpoints.finalized.epoch);
+			true
+		} else {
+			false
 		}
 	}
 }
 
+impl<E: BlockExecutor, Ba: Store<Block=E::Block>> ArchiveGhostImporter<E, Ba> where
+	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode + Debug,
+	Ba: AncestorQuery + ChainQuery,
+	Ba: SharedCommittable<Operation=Operation<E::Block, <Ba as Store>::State, <Ba as Store>::Auxiliary>>,
+	Ba::Auxiliary: Auxiliary<E::Block>,
+{
+	/// Queue attestation votes seen at `slot`, typically from gossip. They are
+	/// applied on the first `on_slot` call, or block import, after that slot.
+	pub fn queue_votes(
+		&mut self,
+		slot: u64,
+		votes: Vec<(E::ValidatorIndex, <E::Block as Block>::Identifier)>,
+	) {
+		for (k, v) in votes {
+			self.ghost.queue_vote(slot, k, v);
+		}
+	}
+
+	/// Apply queued votes at a slot boundary, pull up unrealized checkpoints
+	/// at an epoch boundary, and update the head.
+	pub fn on_slot(&mut self, current_slot: u64) -> Result<(), Error> {
+		let finalized_epoch = self.checkpoints.finalized.epoch;
+		let current_epoch = self.executor.epoch_at_slot(current_slot);
+		let new_epoch = current_epoch > self.current_epoch;
+		if new_epoch {
+			self.current_epoch = current_epoch;
+			self.checkpoints.on_epoch();
+			self.on_finalized(finalized_epoch);
+		}
+
+		self.ghost.reset_proposer_boost(current_slot);
+		let executor = &self.executor;
+		self.ghost.process_queue(current_slot, |slot| executor.epoch_at_slot(slot));
+
+		let new_head = self.ghost.head(&self.checkpoints, self.current_epoch)
+			.map_err(|e| Error::Backend(Box::new(e)))?;
+		let reorg = self.find_reorg(&new_head);
+
+		// The store grows with the chain, so it is only written once per
+		// epoch, on finalization and on shutdown.
+		if new_epoch {
+			self.stage_persist();
+		}
+
+		let mut importer = ImportAction::new(
+			&self.ghost.backend, self.import_lock.lock()
+		);
+		importer.set_head(new_head);
+		importer.commit().map_err(|e| Error::Backend(Box::new(e)))?;
+		if let Some(reorg) = reorg {
+			self.note_reorg(reorg);
+		}
+		Ok(())
+	}
+}
+
 impl<E: BlockExecutor, Ba: Store<Block=E::Block>> BlockImporter for ArchiveGhostImporter<E, Ba> where
 	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode + Debug,
 	Ba: ChainQuery + AncestorQuery,
 	Ba: SharedCommittable<Operation=Operation<E::Block, <Ba as Store>::State, <Ba as Store>::Auxiliary>>,
 	Ba::Auxiliary: Auxiliary<E::Block>,
@@ -217,8 +614,79 @@ impl<E: BlockExecutor, Ba: Store<Block=E::Block>> BlockImporter for ArchiveGhost
 	}
 }
 
+/// Fork choice importer shared between block import, attestation gossip and
+/// the slot ticker.
+pub struct SharedArchiveGhostImporter<E: BlockExecutor, Ba: Store<Block=E::Block>> where
+	E: JustifiableExecutor,
+	Ba::Auxiliary: Auxiliary<E::Block>
+{
+	importer: Arc<Mutex<ArchiveGhostImporter<E, Ba>>>,
+}
+
+impl<E: BlockExecutor, Ba: Store<Block=E::Block>> Clone for SharedArchiveGhostImporter<E, Ba> where
+	E: JustifiableExecutor,
+	Ba::Auxiliary: Auxiliary<E::Block>
+{
+	fn clone(&self) -> Self {
+		Self { importer: self.importer.clone() }
+	}
+}
+
+impl<E: BlockExecutor, Ba: Store<Block=E::Block>> SharedArchiveGhostImporter<E, Ba> where
+	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode + Debug,
+	Ba: AncestorQuery + ChainQuery,
+	Ba: SharedCommittable<Operation=Operation<E::Block, <Ba as Store>::State, <Ba as Store>::Auxiliary>>,
+	Ba::Auxiliary: Auxiliary<E::Block>,
+{
+	/// Share the given importer.
+	pub fn new(importer: ArchiveGhostImporter<E, Ba>) -> Self {
+		Self { importer: Arc::new(Mutex::new(importer)) }
+	}
+
+	/// Queue attestation votes seen at `slot`. See
+	/// `ArchiveGhostImporter::queue_votes`.
+	pub fn queue_votes(
+		&self,
+		slot: u64,
+		votes: Vec<(E::ValidatorIndex, <E::Block as Block>::Identifier)>,
+	) {
+		self.importer.lock().expect("Lock is poisoned").queue_votes(slot, votes)
+	}
+
+	/// Notify fork choice of a new slot. See `ArchiveGhostImporter::on_slot`.
+	pub fn on_slot(&self, current_slot: u64) -> Result<(), Error> {
+		self.importer.lock().expect("Lock is poisoned").on_slot(current_slot)
+	}
+
+	/// Write the fork choice store to the persistence storage, if any.
+	pub fn persist(&self) {
+		self.importer.lock().expect("Lock is poisoned").persist()
+	}
+}
+
+impl<E: BlockExecutor, Ba: Store<Block=E::Block>> SharedBlockImporter for SharedArchiveGhostImporter<E, Ba> where
+	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode + Debug,
+	Ba: ChainQuery + AncestorQuery,
+	Ba: SharedCommittable<Operation=Operation<E::Block, <Ba as Store>::State, <Ba as Store>::Auxiliary>>,
+	Ba::Auxiliary: Auxiliary<E::Block>,
+	Ba::State: AsExternalities<E::Externalities>,
+{
+	type Block = Ba::Block;
+	type Error = Error;
+
+	fn import_block(&self, block: Ba::Block) -> Result<(), Self::Error> {
+		self.importer.lock().expect("Lock is poisoned").import_block(block)
+	}
+}
+
 impl<E: BlockExecutor, Ba: Store<Block=E::Block>> RawImporter for ArchiveGhostImporter<E, Ba> where
 	E: JustifiableExecutor,
+	E::ValidatorIndex: Encode + Decode + Clone,
+	<E::Block as Block>::Identifier: Encode + Decode + Debug,
 	Ba: AncestorQuery + ChainQuery,
 	Ba: SharedCommittable<Operation=Operation<E::Block, <Ba as Store>::State, <Ba as Store>::Auxiliary>>,
 	Ba::Auxiliary: Auxiliary<E::Block>,
@@ -231,21 +699,43 @@ impl<E: BlockExecutor, Ba: Store<Block=E::Block>> RawImporter for ArchiveGhostIm
 		&mut self,
 		mut raw: ImportOperation<Ba::Block, Ba::State>
 	) -> Result<(), Self::Error> {
-		let (justified_active_validators, justified_block_id, votes) = {
+		let block_id = raw.block.id();
+		let block_slot = self.executor.slot(&raw.block);
+		let block_epoch = self.executor.epoch_at_slot(block_slot);
+		let timely = self.slot_clock.as_ref()
+			.map(|clock| clock.is_timely(block_slot))
+			.unwrap_or(false);
+		let current_slot = self.slot_clock.as_ref()
+			.and_then(|clock| clock.current_slot())
+			.map(|slot| core::cmp::max(slot, block_slot))
+			.unwrap_or(block_slot);
+		let finalized_epoch = self.checkpoints.finalized.epoch;
+		let current_epoch = self.executor.epoch_at_slot(current_slot);
+		if current_epoch > self.current_epoch {
+			self.current_epoch = current_epoch;
+			self.checkpoints.on_epoch();
+		}
+
+		let (justified_active_validators, checkpoints, unrealized_checkpoints, votes, equivocations, boost_score) = {
 			let externalities = raw.state.as_externalities();
 			let justified_active_validators = self.executor
 				.justified_active_validators(externalities)
 				.map_err(|e| Error::Executor(Box::new(e)))?;
-			let justified_block_id = match self.executor
-				.justified_block_id(externalities)
-				.map_err(|e| Error::Executor(Box::new(e)))?
-			{
-				Some(value) => value,
-				None => self.ghost.backend.genesis(),
-			};
+			let (justified, finalized) = self.executor
+				.checkpoints(externalities)
+				.map_err(|e| Error::Executor(Box::new(e)))?;
+			let (unrealized_justified, unrealized_finalized) = self.executor
+				.unrealized_checkpoints(externalities)
+				.map_err(|e| Error::Executor(Box::new(e)))?;
 			let votes = self.executor
 				.votes(&raw. block, externalities)
 				.map_err(|e| Error::Executor(Box::new(e)))?;
+			let equivocations = self.executor
+				.equivocations(&raw.block, externalities)
+				.map_err(|e| Error::Executor(Box::new(e)))?;
+			let boost_score = self.executor
+				.proposer_boost_score(externalities)
+				.map_err(|e| Error::Executor(Box::new(e)))?;
 
 			let mut importer = ImportAction::new(
 				&self.ghost.backend, self.import_lock.lock()
@@ -253,31 +743,50 @@ impl<E: BlockExecutor, Ba: Store<Block=E::Block>> RawImporter for ArchiveGhostIm
 			importer.import_raw(raw);
 			importer.commit().map_err(|e| Error::Backend(Box::new(e)))?;
 
-			(justified_active_validators, justified_block_id, votes)
+			(
+				justified_active_validators,
+				(self.genesis_checkpoint(justified), self.genesis_checkpoint(finalized)),
+				(self.genesis_checkpoint(unrealized_justified), self.genesis_checkpoint(unrealized_finalized)),
+				votes, equivocations, boost_score,
+			)
 		};
 
-		for (k, v) in votes {
-			self.ghost.update_overlay(k, v);
+		self.ghost.reset_proposer_boost(current_slot);
+		if timely {
+			self.ghost.set_proposer_boost(block_id.clone(), block_slot, boost_score);
 		}
+
+		for k in equivocations {
+			self.ghost.note_equivocation(k);
+		}
+		// Block votes go through the queue like gossip ones, so that votes
+		// attesting the current slot only count from the next one.
+		for (slot, k, v) in votes {
+			self.ghost.queue_vote(slot, k, v);
+		}
+		let executor = &self.executor;
+		self.ghost.process_queue(current_slot, |slot| executor.epoch_at_slot(slot));
 		self.ghost.update_active(&justified_active_validators);
-		let new_head = match self.ghost.head(&justified_block_id) {
-			Ok(value) => value,
-			Err(e) => {
-				self.ghost.reset_overlay();
-				return Err(Error::Backend(Box::new(e)))
-			},
-		};
+		self.checkpoints.on_block(
+			block_id, block_epoch, self.current_epoch,
+			checkpoints, unrealized_checkpoints,
+		);
+		if self.on_finalized(finalized_epoch) {
+			self.stage_persist();
+		}
+		let new_head = self.ghost.head(&self.checkpoints, self.current_epoch)
+			.map_err(|e| Error::Backend(Box::new(e)))?;
+		let reorg = self.find_reorg(&new_head);
 
 		let mut importer = ImportAction::new(
 			&self.ghost.backend, self.import_lock.lock()
 		);
 		importer.set_head(new_head);
 
-		match importer.commit() {
-			Ok(()) => { self.ghost.commit_overlay(); },
-			Err(_) => { self.ghost.reset_overlay(); },
+		importer.commit().map_err(|e| Error::Backend(Box::new(e)))?;
+		if let Some(reorg) = reorg {
+			self.note_reorg(reorg);
 		}
-
 		Ok(())
 	}
 }
@@ -0,0 +1,150 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use core::hash::Hash;
+use parity_codec::{Encode, Decode};
+
+/// Epoch and block identifier pair.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, Debug)]
+pub struct Checkpoint<Id> {
+	pub epoch: u64,
+	pub root: Id,
+}
+
+/// Justified and finalized checkpoints known to fork choice, together with
+/// their unrealized counterparts.
+pub struct CheckpointStore<Id: Eq + Hash> {
+	pub(crate) justified: Checkpoint<Id>,
+	pub(crate) finalized: Checkpoint<Id>,
+	pub(crate) unrealized_justified: Checkpoint<Id>,
+	pub(crate) unrealized_finalized: Checkpoint<Id>,
+	/// Voting sources of blocks, with the epochs of the blocks.
+	pub(crate) voting_sources: HashMap<Id, (u64, Checkpoint<Id>)>,
+}
+
+impl<Id: Eq + Hash + Clone> CheckpointStore<Id> {
+	/// Create a new store with all checkpoints at genesis.
+	pub fn new(genesis: Id) -> Self {
+		let checkpoint = Checkpoint { epoch: 0, root: genesis };
+
+		Self {
+			justified: checkpoint.clone(),
+			finalized: checkpoint.clone(),
+			unrealized_justified: checkpoint.clone(),
+			unrealized_finalized: checkpoint,
+			voting_sources: HashMap::new(),
+		}
+	}
+
+	/// Current justified checkpoint.
+	pub fn justified(&self) -> &Checkpoint<Id> {
+		&self.justified
+	}
+
+	/// Current finalized checkpoint.
+	pub fn finalized(&self) -> &Checkpoint<Id> {
+		&self.finalized
+	}
+
+	/// Best unrealized justified checkpoint seen so far.
+	pub fn unrealized_justified(&self) -> &Checkpoint<Id> {
+		&self.unrealized_justified
+	}
+
+	/// Best unrealized finalized checkpoint seen so far.
+	pub fn unrealized_finalized(&self) -> &Checkpoint<Id> {
+		&self.unrealized_finalized
+	}
+
+	/// Justified checkpoint votes for `block` are counted against, if known.
+	pub fn voting_source(&self, block: &Id) -> Option<&Checkpoint<Id>> {
+		self.voting_sources.get(block).map(|(_, source)| source)
+	}
+
+	/// Update justified and finalized checkpoints, if they are newer.
+	pub fn update(&mut self, justified: Checkpoint<Id>, finalized: Checkpoint<Id>) {
+		if justified.epoch > self.justified.epoch {
+			self.justified = justified;
+		}
+		if finalized.epoch > self.finalized.epoch {
+			self.finalized = finalized;
+			self.prune();
+		}
+	}
+
+	/// Forget voting sources of blocks below the finalized checkpoint, which
+	/// can no longer be chosen as head.
+	fn prune(&mut self) {
+		let finalized_epoch = self.finalized.epoch;
+		self.voting_sources.retain(|_, (block_epoch, _)| *block_epoch >= finalized_epoch);
+	}
+
+	/// Number of blocks whose voting source is known.
+	pub fn voting_source_count(&self) -> usize {
+		self.voting_sources.len()
+	}
+
+	/// Update unrealized justified and finalized checkpoints, if they are
+	/// newer.
+	pub fn update_unrealized(&mut self, justified: Checkpoint<Id>, finalized: Checkpoint<Id>) {
+		if justified.epoch > self.unrealized_justified.epoch {
+			self.unrealized_justified = justified;
+		}
+		if finalized.epoch > self.unrealized_finalized.epoch {
+			self.unrealized_finalized = finalized;
+		}
+	}
+
+	/// Note checkpoints of a newly imported block. Blocks from a past epoch
+	/// have their unrealized checkpoints pulled up immediately, as the epoch
+	/// boundary has already been crossed.
+	pub fn on_block(
+		&mut self,
+		block: Id,
+		block_epoch: u64,
+		current_epoch: u64,
+		(justified, finalized): (Checkpoint<Id>, Checkpoint<Id>),
+		(unrealized_justified, unrealized_finalized): (Checkpoint<Id>, Checkpoint<Id>),
+	) {
+		self.update_unrealized(unrealized_justified.clone(), unrealized_finalized.clone());
+
+		if block_epoch < current_epoch {
+			self.voting_sources.insert(block, (block_epoch, unrealized_justified.clone()));
+			self.update(unrealized_justified, unrealized_finalized);
+		} else {
+			self.voting_sources.insert(block, (block_epoch, justified.clone()));
+			self.update(justified, finalized);
+		}
+	}
+
+	/// Pull up unrealized checkpoints at an epoch boundary.
+	pub fn on_epoch(&mut self) {
+		self.update(self.unrealized_justified.clone(), self.unrealized_finalized.clone());
+	}
+
+	/// Whether a leaf block with the given voting source may be chosen as
+	/// head.
+	pub fn is_viable_source(&self, block: &Id, current_epoch: u64) -> bool {
+		match self.voting_sources.get(block) {
+			Some((_, source)) =>
+				self.justified.epoch == 0 ||
+				source.epoch == self.justified.epoch ||
+				source.epoch + 2 >= current_epoch,
+			None => true,
+		}
+	}
+}
@@ -0,0 +1,115 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use core::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of intervals a slot is divided into for fork choice timing.
+pub const INTERVALS_PER_SLOT: u32 = 3;
+
+/// Source of the current slot.
+pub trait SlotClock {
+	/// Current slot. `None` if genesis has not yet happened.
+	fn current_slot(&self) -> Option<u64>;
+	/// Time elapsed since the start of the current slot.
+	fn slot_elapsed(&self) -> Option<Duration>;
+	/// Duration of a single slot.
+	fn slot_duration(&self) -> Duration;
+
+	/// Time into a slot after which something for it is no longer timely.
+	fn timely_cutoff(&self) -> Duration {
+		self.slot_duration() / INTERVALS_PER_SLOT
+	}
+
+	/// Time until the start of `slot`. `None` if it has already started, or
+	/// if genesis has not yet happened.
+	fn duration_to_slot(&self, slot: u64) -> Option<Duration> {
+		let current_slot = self.current_slot()?;
+		if slot <= current_slot {
+			return None
+		}
+		let elapsed = self.slot_elapsed()?;
+		(self.slot_duration() * (slot - current_slot) as u32).checked_sub(elapsed)
+	}
+
+	/// Whether something for `slot` arriving now is timely, that is, it
+	/// arrives before the timely cutoff of that very slot.
+	fn is_timely(&self, slot: u64) -> bool {
+		match (self.current_slot(), self.slot_elapsed()) {
+			(Some(current_slot), Some(elapsed)) =>
+				current_slot == slot && elapsed < self.timely_cutoff(),
+			_ => false,
+		}
+	}
+}
+
+/// Slot clock based on system time.
+#[derive(Clone, Debug)]
+pub struct SystemSlotClock {
+	genesis_time: Duration,
+	slot_duration: Duration,
+	timely_cutoff: Option<Duration>,
+}
+
+impl SystemSlotClock {
+	/// Create a new clock given the genesis Unix timestamp and the slot
+	/// duration. `None` if the slot duration is shorter than the one
+	/// millisecond resolution of the clock.
+	pub fn new(genesis_time: u64, slot_duration: Duration) -> Option<Self> {
+		if slot_duration.as_millis() == 0 {
+			return None
+		}
+
+		Some(Self {
+			genesis_time: Duration::from_secs(genesis_time),
+			slot_duration,
+			timely_cutoff: None,
+		})
+	}
+
+	/// Override the timely cutoff, which defaults to the first interval of
+	/// the slot.
+	pub fn with_timely_cutoff(mut self, cutoff: Duration) -> Self {
+		self.timely_cutoff = Some(cutoff);
+		self
+	}
+
+	fn since_genesis(&self) -> Option<Duration> {
+		let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+		now.checked_sub(self.genesis_time)
+	}
+}
+
+impl SlotClock for SystemSlotClock {
+	fn current_slot(&self) -> Option<u64> {
+		let since_genesis = self.since_genesis()?;
+		Some((since_genesis.as_millis() / self.slot_duration.as_millis()) as u64)
+	}
+
+	fn slot_elapsed(&self) -> Option<Duration> {
+		let since_genesis = self.since_genesis()?;
+		let elapsed = since_genesis.as_millis() % self.slot_duration.as_millis();
+		Some(Duration::from_millis(elapsed as u64))
+	}
+
+	fn slot_duration(&self) -> Duration {
+		self.slot_duration
+	}
+
+	fn timely_cutoff(&self) -> Duration {
+		self.timely_cutoff.unwrap_or(self.slot_duration / INTERVALS_PER_SLOT)
+	}
+}
@@ -14,9 +14,16 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 pub mod archive;
+pub mod queue;
+pub mod clock;
+pub mod checkpoint;
+pub mod persist;
+pub mod validity;
+pub mod reorg;
 
 use blockchain::{Block, BlockExecutor};
 use core::hash::Hash;
+use crate::checkpoint::Checkpoint;
 
 pub trait JustifiableExecutor: BlockExecutor {
 	type ValidatorIndex: Eq + Hash;
@@ -25,13 +32,35 @@ pub trait JustifiableExecutor: BlockExecutor {
 		&self,
 		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
 	) -> Result<Vec<Self::ValidatorIndex>, Self::Error>;
-	fn justified_block_id(
+	/// Votes included in a block, with the slots they attest.
+	fn votes(
 		&self,
+		block: &Self::Block,
 		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
-	) -> Result<Option<<Self::Block as Block>::Identifier>, Self::Error>;
-	fn votes(
+	) -> Result<Vec<(u64, Self::ValidatorIndex, <Self::Block as Block>::Identifier)>, Self::Error>;
+	fn slot(
 		&self,
 		block: &Self::Block,
+	) -> u64;
+	fn epoch_at_slot(
+		&self,
+		slot: u64,
+	) -> u64;
+	fn checkpoints(
+		&self,
+		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
+	) -> Result<(Option<Checkpoint<<Self::Block as Block>::Identifier>>, Option<Checkpoint<<Self::Block as Block>::Identifier>>), Self::Error>;
+	fn unrealized_checkpoints(
+		&self,
 		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
-	) -> Result<Vec<(Self::ValidatorIndex, <Self::Block as Block>::Identifier)>, Self::Error>;
+	) -> Result<(Option<Checkpoint<<Self::Block as Block>::Identifier>>, Option<Checkpoint<<Self::Block as Block>::Identifier>>), Self::Error>;
+	fn proposer_boost_score(
+		&self,
+		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
+	) -> Result<usize, Self::Error>;
+	fn equivocations(
+		&self,
+		block: &Self::Block,
+		state: &mut Self::Externalities, // FIXME: replace `&mut` with `&`.
+	) -> Result<Vec<Self::ValidatorIndex>, Self::Error>;
 }
@@ -0,0 +1,69 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use parity_codec::{Encode, Decode};
+use crate::checkpoint::Checkpoint;
+
+/// Storage for the encoded fork choice store, so that it survives restarts.
+pub trait ForkChoicePersistence {
+	/// Load the previously stored fork choice store, if any.
+	fn load(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>>;
+	/// Store the encoded fork choice store, replacing any previous one.
+	fn store(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>>;
+	/// Stage the encoded fork choice store to be written together with the
+	/// next block commit. Defaults to storing it immediately.
+	fn stage(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+		self.store(data)
+	}
+}
+
+impl<T: ForkChoicePersistence + ?Sized> ForkChoicePersistence for Box<T> {
+	fn load(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+		(**self).load()
+	}
+
+	fn store(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+		(**self).store(data)
+	}
+
+	fn stage(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+		(**self).stage(data)
+	}
+}
+
+/// Fork choice store as written to disk. Proposer boost is transient and not
+/// persisted.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, Debug)]
+pub struct PersistedForkChoice<VI, Id> {
+	/// Latest votes of validators, with their target epochs.
+	pub votes: Vec<(VI, u64, Id)>,
+	/// Votes waiting in the attestation queue, with their slots.
+	pub queued: Vec<(u64, VI, Id)>,
+	/// Validators known to equivocate.
+	pub equivocations: Vec<VI>,
+	/// Justified checkpoint.
+	pub justified: Checkpoint<Id>,
+	/// Finalized checkpoint.
+	pub finalized: Checkpoint<Id>,
+	/// Best unrealized justified checkpoint.
+	pub unrealized_justified: Checkpoint<Id>,
+	/// Best unrealized finalized checkpoint.
+	pub unrealized_finalized: Checkpoint<Id>,
+	/// Voting sources of imported blocks, with the epochs of the blocks.
+	pub voting_sources: Vec<(Id, u64, Checkpoint<Id>)>,
+	/// Current epoch as last seen by fork choice.
+	pub current_epoch: u64,
+}
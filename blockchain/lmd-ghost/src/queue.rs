@@ -0,0 +1,189 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use core::hash::Hash;
+
+/// Attestation votes waiting for their slot to pass before they are allowed to
+/// influence fork choice.
+pub struct AttestationQueue<VI, Id> {
+	queued: BTreeMap<u64, Vec<(VI, Id)>>,
+}
+
+impl<VI, Id> AttestationQueue<VI, Id> {
+	/// Create a new empty queue.
+	pub fn new() -> Self {
+		Self { queued: BTreeMap::new() }
+	}
+
+	/// Queue a vote of `validator` for `target`, attested at `slot`.
+	pub fn push(&mut self, slot: u64, validator: VI, target: Id) {
+		self.queued.entry(slot).or_insert_with(Vec::new).push((validator, target));
+	}
+
+	/// Take all votes that are attested strictly before `current_slot`, in
+	/// slot order, with the slots they are attested at.
+	pub fn drain_ready(&mut self, current_slot: u64) -> Vec<(u64, VI, Id)> {
+		let pending = self.queued.split_off(&current_slot);
+		let ready = core::mem::replace(&mut self.queued, pending);
+
+		ready.into_iter()
+			.flat_map(|(slot, votes)| votes.into_iter().map(move |(v, t)| (slot, v, t)))
+			.collect()
+	}
+
+	/// Iterate over all queued votes, with the slots they are attested at.
+	pub fn iter(&self) -> impl Iterator<Item=(u64, &VI, &Id)> {
+		self.queued.iter()
+			.flat_map(|(slot, votes)| votes.iter().map(move |(v, t)| (*slot, v, t)))
+	}
+
+	/// Number of votes still queued.
+	pub fn len(&self) -> usize {
+		self.queued.values().map(|votes| votes.len()).sum()
+	}
+
+	/// Whether the queue is empty.
+	pub fn is_empty(&self) -> bool {
+		self.queued.is_empty()
+	}
+}
+
+/// Latest messages of validators, with the target epochs they are cast for.
+pub struct LatestMessages<VI: Eq + Hash, Id> {
+	messages: HashMap<VI, (u64, Id)>,
+}
+
+impl<VI: Eq + Hash, Id> LatestMessages<VI, Id> {
+	/// Create a new empty set of latest messages.
+	pub fn new() -> Self {
+		Self { messages: HashMap::new() }
+	}
+
+	/// Record a vote of `validator` for `target` with the given target epoch,
+	/// unless the validator already voted for the same or a later epoch.
+	/// Returns whether the vote replaced the latest message.
+	pub fn insert(&mut self, validator: VI, epoch: u64, target: Id) -> bool {
+		if self.messages.get(&validator).map(|(latest, _)| *latest >= epoch).unwrap_or(false) {
+			return false
+		}
+		self.messages.insert(validator, (epoch, target));
+		true
+	}
+
+	/// Latest message of a validator, with its target epoch.
+	pub fn get(&self, validator: &VI) -> Option<(u64, &Id)> {
+		self.messages.get(validator).map(|(epoch, target)| (*epoch, target))
+	}
+
+	/// Forget the latest message of a validator.
+	pub fn remove(&mut self, validator: &VI) {
+		self.messages.remove(validator);
+	}
+
+	/// Only keep latest messages of validators matching `f`.
+	pub fn retain<F: FnMut(&VI) -> bool>(&mut self, mut f: F) {
+		self.messages.retain(|v, _| f(v));
+	}
+
+	/// Forget latest messages with target epochs before `min_epoch`. Such
+	/// votes are below finality and cannot affect the head.
+	pub fn prune(&mut self, min_epoch: u64) {
+		self.messages.retain(|_, (epoch, _)| *epoch >= min_epoch);
+	}
+
+	/// Iterate over all latest messages, with their target epochs.
+	pub fn iter(&self) -> impl Iterator<Item=(&VI, u64, &Id)> {
+		self.messages.iter().map(|(v, (epoch, target))| (v, *epoch, target))
+	}
+
+	/// Number of validators with a latest message.
+	pub fn len(&self) -> usize {
+		self.messages.len()
+	}
+}
+
+/// Validators that have been proven to equivocate. Their votes are ignored by
+/// fork choice.
+pub struct Equivocations<VI: Eq + Hash> {
+	validators: HashSet<VI>,
+}
+
+impl<VI: Eq + Hash> Equivocations<VI> {
+	/// Create a new empty equivocation set.
+	pub fn new() -> Self {
+		Self { validators: HashSet::new() }
+	}
+
+	/// Note a validator as equivocating.
+	pub fn insert(&mut self, validator: VI) -> bool {
+		self.validators.insert(validator)
+	}
+
+	/// Whether the validator is known to equivocate.
+	pub fn contains(&self, validator: &VI) -> bool {
+		self.validators.contains(validator)
+	}
+
+	/// Iterate over all known equivocating validators.
+	pub fn iter(&self) -> impl Iterator<Item=&VI> {
+		self.validators.iter()
+	}
+
+	/// Number of known equivocating validators.
+	pub fn len(&self) -> usize {
+		self.validators.len()
+	}
+}
+
+/// Score boost of a timely block, lasting until the end of its slot.
+pub struct ProposerBoost<Id> {
+	boosted: Option<(Id, u64, usize)>,
+}
+
+impl<Id> ProposerBoost<Id> {
+	/// Create a new boost, with no block boosted.
+	pub fn new() -> Self {
+		Self { boosted: None }
+	}
+
+	/// Boost `block` of `slot` with the given score, replacing a boost of
+	/// an earlier slot. Only the first timely block of a slot is boosted, so
+	/// this does nothing if a block of `slot` is already boosted.
+	pub fn set(&mut self, block: Id, slot: u64, score: usize) {
+		if self.boosted.as_ref().map(|(_, boosted_slot, _)| *boosted_slot == slot).unwrap_or(false) {
+			return
+		}
+		self.boosted = Some((block, slot, score));
+	}
+
+	/// Remove the boost if it was given in a slot before `current_slot`.
+	pub fn reset(&mut self, current_slot: u64) {
+		if self.boosted.as_ref().map(|(_, slot, _)| *slot < current_slot).unwrap_or(false) {
+			self.boosted = None;
+		}
+	}
+
+	/// Remove the boost unconditionally.
+	pub fn clear(&mut self) {
+		self.boosted = None;
+	}
+
+	/// Boosted block and its score, if any.
+	pub fn boosted(&self) -> Option<(&Id, usize)> {
+		self.boosted.as_ref().map(|(block, _, score)| (block, *score))
+	}
+}
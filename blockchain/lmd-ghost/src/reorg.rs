@@ -0,0 +1,161 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection and statistics of chain reorganizations.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use blockchain::Block;
+use blockchain::backend::ChainQuery;
+use crate::archive::AncestorQuery;
+
+/// Default depth from which a reorg is alerted on.
+pub const DEFAULT_REORG_ALERT_DEPTH: usize = 3;
+/// Window over which the reorg frequency is measured.
+pub const REORG_FREQUENCY_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// A head change to a block that does not descend from the previous head.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Reorg<Id> {
+	/// Previous head.
+	pub old_head: Id,
+	/// New head.
+	pub new_head: Id,
+	/// Latest block shared by both chains.
+	pub common_ancestor: Id,
+	/// Number of blocks of the previous chain reverted.
+	pub depth: usize,
+}
+
+/// Find the reorg caused by moving the head from `old_head` to `new_head`,
+/// if any.
+pub fn find_reorg<Ba: AncestorQuery + ChainQuery>(
+	backend: &Ba,
+	old_head: &<Ba::Block as Block>::Identifier,
+	new_head: &<Ba::Block as Block>::Identifier,
+) -> Result<Option<Reorg<<Ba::Block as Block>::Identifier>>, Ba::Error> {
+	if old_head == new_head {
+		return Ok(None)
+	}
+
+	let old_depth = backend.depth_at(old_head)?;
+	let new_depth = backend.depth_at(new_head)?;
+	let depth = core::cmp::min(old_depth, new_depth);
+	let mut old_ancestor = backend.ancestor_at(old_head, depth)?;
+	let mut new_ancestor = backend.ancestor_at(new_head, depth)?;
+	if &old_ancestor == old_head {
+		return Ok(None)
+	}
+
+	let mut common_depth = depth;
+	while old_ancestor != new_ancestor {
+		match (backend.block_at(&old_ancestor)?.parent_id(), backend.block_at(&new_ancestor)?.parent_id()) {
+			(Some(old_parent), Some(new_parent)) => {
+				old_ancestor = old_parent;
+				new_ancestor = new_parent;
+				common_depth -= 1;
+			},
+			_ => break,
+		}
+	}
+
+	Ok(Some(Reorg {
+		old_head: old_head.clone(),
+		new_head: new_head.clone(),
+		common_ancestor: old_ancestor,
+		depth: old_depth - common_depth,
+	}))
+}
+
+/// Reorg statistics.
+#[derive(Default, Debug)]
+pub struct ReorgStats {
+	reorgs: u64,
+	alerts: u64,
+	total_depth: u64,
+	max_depth: usize,
+	last_depth: usize,
+	recent: VecDeque<Instant>,
+}
+
+/// Reorg statistics shared with the importer.
+pub type SharedReorgStats = Arc<Mutex<ReorgStats>>;
+
+impl ReorgStats {
+	/// Create empty statistics.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Record a reorg of `depth` blocks, which was alerted on if `alert`.
+	pub fn note(&mut self, depth: usize, alert: bool) {
+		self.reorgs += 1;
+		self.total_depth += depth as u64;
+		self.max_depth = core::cmp::max(self.max_depth, depth);
+		self.last_depth = depth;
+		if alert {
+			self.alerts += 1;
+		}
+
+		let now = Instant::now();
+		self.recent.push_back(now);
+		while self.recent.front().map(|t| now.duration_since(*t) > REORG_FREQUENCY_WINDOW).unwrap_or(false) {
+			self.recent.pop_front();
+		}
+	}
+
+	/// Total number of reorgs.
+	pub fn reorgs(&self) -> u64 {
+		self.reorgs
+	}
+
+	/// Deepest reorg seen.
+	pub fn max_depth(&self) -> usize {
+		self.max_depth
+	}
+
+	/// Number of reorgs within the last `REORG_FREQUENCY_WINDOW`.
+	pub fn recent(&self) -> usize {
+		let now = Instant::now();
+		self.recent.iter().filter(|t| now.duration_since(**t) <= REORG_FREQUENCY_WINDOW).count()
+	}
+
+	/// Statistics in the Prometheus text format.
+	pub fn encode(&self) -> String {
+		let mut out = String::new();
+		let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			let _ = writeln!(out, "{} {}", name, value);
+		};
+
+		metric("beacon_reorgs_total", "counter", "Chain reorganizations", self.reorgs.to_string());
+		metric("beacon_reorg_alerts_total", "counter",
+			   "Reorganizations deep enough to be alerted on", self.alerts.to_string());
+		metric("beacon_reorg_depth_total", "counter",
+			   "Blocks reverted by reorganizations", self.total_depth.to_string());
+		metric("beacon_reorg_depth_max", "gauge",
+			   "Deepest reorganization", self.max_depth.to_string());
+		metric("beacon_reorg_depth_last", "gauge",
+			   "Depth of the latest reorganization", self.last_depth.to_string());
+		metric("beacon_reorgs_recent", "gauge",
+			   "Reorganizations within the last hour", self.recent().to_string());
+
+		out
+	}
+}
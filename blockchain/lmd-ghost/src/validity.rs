@@ -0,0 +1,72 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Execution validity of blocks, for optimistic import.
+//!
+//! Blocks whose execution could not be verified at import are imported
+//! optimistically, and later marked valid or invalid. Fork choice never
+//! picks a head within the subtree of an invalid block, and may be told to
+//! not pick optimistic heads either.
+
+use parity_codec::{Encode, Decode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+/// Execution validity of a block.
+pub enum BlockValidity {
+	/// Execution is verified valid.
+	Valid,
+	/// Imported without execution being verified.
+	Optimistic,
+	/// Execution is verified invalid.
+	Invalid,
+}
+
+/// Source of block validity.
+pub trait ValidityQuery<Id> {
+	/// Validity of a block. Blocks never imported optimistically are valid.
+	fn validity(&self, id: &Id) -> BlockValidity;
+}
+
+/// Head filter based on block validity.
+pub struct ValidityFilter<Id> {
+	query: Box<dyn ValidityQuery<Id> + Send + Sync>,
+	allow_optimistic_head: bool,
+}
+
+impl<Id> ValidityFilter<Id> {
+	/// Create a new filter. Optimistic blocks may be chosen as head only if
+	/// `allow_optimistic_head` is set.
+	pub fn new<Q: ValidityQuery<Id> + Send + Sync + 'static>(
+		query: Q,
+		allow_optimistic_head: bool,
+	) -> Self {
+		Self { query: Box::new(query), allow_optimistic_head }
+	}
+
+	/// Whether the block and its descendants are excluded from fork choice.
+	pub fn is_excluded(&self, id: &Id) -> bool {
+		self.query.validity(id) == BlockValidity::Invalid
+	}
+
+	/// Whether the block may be chosen as head.
+	pub fn is_allowed_head(&self, id: &Id) -> bool {
+		match self.query.validity(id) {
+			BlockValidity::Valid => true,
+			BlockValidity::Optimistic => self.allow_optimistic_head,
+			BlockValidity::Invalid => false,
+		}
+	}
+}
@@ -0,0 +1,22 @@
+use lmd_ghost::checkpoint::{Checkpoint, CheckpointStore};
+
+fn checkpoint(epoch: u64, root: &'static str) -> Checkpoint<&'static str> {
+	Checkpoint { epoch, root }
+}
+
+#[test]
+fn voting_sources_below_finality_are_pruned() {
+	let genesis = (checkpoint(0, "g"), checkpoint(0, "g"));
+	let mut store = CheckpointStore::new("g");
+	store.on_block("a", 1, 1, genesis.clone(), genesis.clone());
+	store.on_block("b", 2, 2, genesis.clone(), genesis.clone());
+	assert_eq!(store.voting_source_count(), 2);
+
+	let finalized = (checkpoint(2, "b"), checkpoint(2, "b"));
+	store.on_block("c", 3, 3, finalized.clone(), finalized);
+
+	assert_eq!(store.finalized(), &checkpoint(2, "b"));
+	assert!(store.voting_source(&"a").is_none());
+	assert!(store.voting_source(&"b").is_some());
+	assert_eq!(store.voting_source_count(), 2);
+}
@@ -0,0 +1,44 @@
+use lmd_ghost::queue::{AttestationQueue, LatestMessages};
+
+#[test]
+fn older_vote_does_not_replace_newer_one() {
+	let mut messages = LatestMessages::new();
+	assert!(messages.insert(1, 5, "b"));
+	assert!(!messages.insert(1, 4, "a"));
+	assert!(!messages.insert(1, 5, "c"));
+
+	assert_eq!(messages.get(&1), Some((5, &"b")));
+}
+
+#[test]
+fn newer_vote_replaces_older_one() {
+	let mut messages = LatestMessages::new();
+	messages.insert(1, 4, "a");
+	assert!(messages.insert(1, 5, "b"));
+
+	assert_eq!(messages.get(&1), Some((5, &"b")));
+	assert_eq!(messages.len(), 1);
+}
+
+#[test]
+fn ready_votes_keep_their_slots() {
+	let mut queue = AttestationQueue::new();
+	queue.push(3, 1, "a");
+	queue.push(1, 2, "b");
+	queue.push(4, 3, "c");
+
+	assert_eq!(queue.drain_ready(4), vec![(1, 2, "b"), (3, 1, "a")]);
+	assert_eq!(queue.len(), 1);
+}
+
+#[test]
+fn prune_drops_votes_below_finality() {
+	let mut messages = LatestMessages::new();
+	messages.insert(1, 3, "a");
+	messages.insert(2, 5, "b");
+	messages.prune(4);
+
+	assert_eq!(messages.get(&1), None);
+	assert_eq!(messages.get(&2), Some((5, &"b")));
+	assert_eq!(messages.len(), 1);
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+use lmd_ghost::clock::SystemSlotClock;
+use lmd_ghost::queue::ProposerBoost;
+
+#[test]
+fn boost_lasts_until_the_end_of_its_slot() {
+	let mut boost = ProposerBoost::new();
+	boost.set("a", 5, 40);
+
+	boost.reset(5);
+	assert_eq!(boost.boosted(), Some((&"a", 40)));
+
+	boost.reset(6);
+	assert_eq!(boost.boosted(), None);
+}
+
+#[test]
+fn newer_boost_replaces_older_one() {
+	let mut boost = ProposerBoost::new();
+	boost.set("a", 5, 40);
+	boost.reset(6);
+	boost.set("b", 6, 40);
+
+	assert_eq!(boost.boosted(), Some((&"b", 40)));
+}
+
+#[test]
+fn only_first_block_of_a_slot_is_boosted() {
+	let mut boost = ProposerBoost::new();
+	boost.set("a", 5, 40);
+	boost.set("b", 5, 40);
+
+	assert_eq!(boost.boosted(), Some((&"a", 40)));
+}
+
+#[test]
+fn zero_slot_duration_is_rejected() {
+	assert!(SystemSlotClock::new(0, Duration::from_secs(0)).is_none());
+	assert!(SystemSlotClock::new(0, Duration::from_micros(500)).is_none());
+	assert!(SystemSlotClock::new(0, Duration::from_secs(12)).is_some());
+}
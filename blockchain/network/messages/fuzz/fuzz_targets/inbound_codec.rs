@@ -0,0 +1,35 @@
+//! Requests from a hostile peer: arbitrary bytes on an inbound substream of
+//! any protocol, arriving in arbitrary pieces.
+
+#![no_main]
+
+use beacon::MinimalConfig;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio::codec::Decoder;
+use shasper_network_messages::{Encoding, InboundCodec, RPCRegistry};
+
+fuzz_target!(|data: &[u8]| {
+	if data.len() < 2 {
+		return
+	}
+
+	let registry = RPCRegistry::<MinimalConfig>::default();
+	let protocols = registry.protocols(&Encoding::all());
+	let protocol = protocols[data[0] as usize % protocols.len()];
+	let method = registry.method(protocol.typ).expect("Protocol of a registered method");
+	let mut codec = InboundCodec::new(method, protocol);
+
+	let mut src = BytesMut::new();
+	for piece in data[2..].chunks(data[1] as usize + 1) {
+		src.extend_from_slice(piece);
+		loop {
+			match codec.decode(&mut src) {
+				Ok(Some(_)) => (),
+				Ok(None) => break,
+				Err(_) => return,
+			}
+		}
+	}
+	let _ = codec.decode_eof(&mut src);
+});
@@ -0,0 +1,62 @@
+//! Responses from a hostile peer: arbitrary bytes on an outbound substream
+//! of any protocol, after any request, arriving in arbitrary pieces.
+
+#![no_main]
+
+use beacon::MinimalConfig;
+use beacon::primitives::H256;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio::codec::Decoder;
+use shasper_network_messages::{
+	BeaconBlocksRequest, Encoding, GoodbyeReason, HelloMessage, OutboundCodec, RPCRegistry,
+	RPCRequest, RPCType, RecentBeaconBlocksRequest,
+};
+
+/// Request of the given type, with its size or range taken from `seed`.
+fn request(typ: RPCType, seed: u8) -> RPCRequest {
+	match typ {
+		RPCType::Hello => RPCRequest::Hello(HelloMessage {
+			fork_version: Default::default(),
+			finalized_root: H256::default(),
+			finalized_epoch: 0,
+			head_root: H256::default(),
+			head_slot: 0,
+		}),
+		RPCType::Goodbye => RPCRequest::Goodbye(GoodbyeReason::Fault),
+		RPCType::BeaconBlocks => RPCRequest::BeaconBlocks(BeaconBlocksRequest {
+			head_block_root: H256::default(),
+			start_slot: seed as u64,
+			count: seed as u64 % 64,
+			step: 1,
+		}),
+		RPCType::RecentBeaconBlocks => RPCRequest::RecentBeaconBlocks(RecentBeaconBlocksRequest {
+			block_roots: vec![H256::default(); seed as usize % 64],
+		}),
+	}
+}
+
+fuzz_target!(|data: &[u8]| {
+	if data.len() < 3 {
+		return
+	}
+
+	let registry = RPCRegistry::<MinimalConfig>::default();
+	let protocols = registry.protocols(&Encoding::all());
+	let protocol = protocols[data[0] as usize % protocols.len()];
+	let method = registry.method(protocol.typ).expect("Protocol of a registered method");
+	let mut codec = OutboundCodec::new(method, protocol, &request(protocol.typ, data[1]));
+
+	let mut src = BytesMut::new();
+	for piece in data[3..].chunks(data[2] as usize + 1) {
+		src.extend_from_slice(piece);
+		loop {
+			match codec.decode(&mut src) {
+				Ok(Some(_)) => (),
+				Ok(None) => break,
+				Err(_) => return,
+			}
+		}
+	}
+	let _ = codec.decode_eof(&mut src);
+});
@@ -1,21 +1,51 @@
 use core::marker::PhantomData;
+use core::mem;
+use std::sync::Arc;
 use tokio::codec::{Encoder, Decoder};
 use bytes::{BufMut, Bytes, BytesMut};
 use beacon::Config;
-use ssz::{Encode, Decode};
+use beacon::types::BeaconBlock;
+use ssz::Decode;
 use log::*;
 use unsigned_varint::codec::UviBytes;
-use crate::{RPCType, RPCRequest, RPCResponse};
+use crate::{
+	Encoding, RPCMethod, RPCProtocolId, RPCRequest, RPCResponse, ResponseKind, ResponseLimit,
+	MAX_CHUNK_SIZE, RPC_DECODE_CONTEXT,
+};
+
+/// Codec of chunk length prefixes. The prefix is the length of the encoded
+/// chunk, which for snappy may exceed the limit on the decoded length.
+fn length_codec(encoding: Encoding) -> UviBytes {
+	let mut uvi = UviBytes::default();
+	uvi.set_max_len(encoding.max_encoded_len(MAX_CHUNK_SIZE));
+	uvi
+}
+
+/// Encode a chunk payload, prefixed with its encoded length.
+fn encode_chunk(
+	encoding: Encoding,
+	uvi: &mut UviBytes,
+	bytes: Vec<u8>,
+	dst: &mut BytesMut,
+) -> Result<(), ssz::Error> {
+	if bytes.len() > MAX_CHUNK_SIZE {
+		return Err(ssz::Error::TooLarge)
+	}
+	uvi.encode(Bytes::from(encoding.encode(bytes)?), dst)?;
+	Ok(())
+}
 
 pub struct InboundCodec<C: Config> {
-	typ: RPCType,
+	method: Arc<RPCMethod<C>>,
+	encoding: Encoding,
 	uvi: UviBytes,
 	_marker: PhantomData<C>,
 }
 
 impl<C: Config> InboundCodec<C> {
-	pub fn new(typ: RPCType) -> Self {
-		Self { typ, uvi: UviBytes::default(), _marker: PhantomData }
+	pub fn new(method: Arc<RPCMethod<C>>, protocol: RPCProtocolId) -> Self {
+		let uvi = length_codec(protocol.encoding);
+		Self { method, encoding: protocol.encoding, uvi, _marker: PhantomData }
 	}
 }
 
@@ -26,53 +56,90 @@ impl<C: Config> Encoder for InboundCodec<C> {
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
 		trace!("inbound encode item: {:?}", item);
 
-		match item {
-			RPCResponse::Unknown(id, _) => dst.put(id),
-			_ => dst.put(0u8),
-		}
-
-		let bytes = match item {
-			RPCResponse::Hello(item) => item.encode(),
-			RPCResponse::BeaconBlocks(item) => item.encode(),
-			RPCResponse::RecentBeaconBlocks(item) => item.encode(),
-			RPCResponse::Unknown(_, value) => value,
+		let code = match item {
+			RPCResponse::Unknown(code, _) => code,
+			_ => 0,
 		};
-
-		self.uvi.encode(Bytes::from(bytes), dst)?;
+		for bytes in item.ssz_chunks() {
+			dst.put(code);
+			encode_chunk(self.encoding, &mut self.uvi, bytes, dst)?;
+		}
 
 		Ok(())
 	}
 }
 
 impl<C: Config> Decoder for InboundCodec<C> {
-	type Item = RPCRequest;
+	/// Request, with the raw bytes of the substream it was read from.
+	type Item = (RPCRequest, usize);
 	type Error = ssz::Error;
 
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
 		trace!("inbound decode src len: {}", src.len());
 
-		match self.uvi.decode(src)? {
-			Some(bytes) => Ok(Some(match self.typ {
-				RPCType::Hello => RPCRequest::Hello(Decode::decode(&bytes[..])?),
-				RPCType::Goodbye => RPCRequest::Goodbye(Decode::decode(&bytes[..])?),
-				RPCType::BeaconBlocks => RPCRequest::BeaconBlocks(Decode::decode(&bytes[..])?),
-				RPCType::RecentBeaconBlocks =>
-					RPCRequest::RecentBeaconBlocks(Decode::decode(&bytes[..])?),
-			})),
-			None => Ok(None),
-		}
+		let available = src.len();
+		let bytes = match self.uvi.decode(src)? {
+			Some(bytes) => self.encoding.decode(&bytes[..], MAX_CHUNK_SIZE)?,
+			None => return Ok(None),
+		};
+		let received = available - src.len();
+
+		Ok(Some(((self.method.decode_request)(&bytes[..])?, received)))
 	}
 }
 
 pub struct OutboundCodec<C: Config> {
-	typ: RPCType,
+	method: Arc<RPCMethod<C>>,
+	encoding: Encoding,
 	uvi: UviBytes,
-	_marker: PhantomData<C>,
+	limit: Option<ResponseLimit>,
+	/// Response code of the chunk being read.
+	code: Option<u8>,
+	/// Blocks of a block stream received so far.
+	blocks: Vec<BeaconBlock<C>>,
+	/// Whether the response was returned.
+	done: bool,
 }
 
 impl<C: Config> OutboundCodec<C> {
-	pub fn new(typ: RPCType) -> Self {
-		Self { typ, uvi: UviBytes::default(), _marker: PhantomData }
+	pub fn new(method: Arc<RPCMethod<C>>, protocol: RPCProtocolId, request: &RPCRequest) -> Self {
+		let uvi = length_codec(protocol.encoding);
+		let limit = match &method.response {
+			ResponseKind::Blocks { limit, .. } => Some(limit(request)),
+			ResponseKind::None | ResponseKind::Single(_) => None,
+		};
+		Self {
+			method,
+			encoding: protocol.encoding,
+			uvi,
+			limit,
+			code: None,
+			blocks: Vec::new(),
+			done: false,
+		}
+	}
+
+	/// Read the next complete chunk, with its response code.
+	fn next_chunk(&mut self, src: &mut BytesMut) -> Result<Option<(u8, Vec<u8>)>, ssz::Error> {
+		if self.code.is_none() {
+			if src.is_empty() {
+				return Ok(None)
+			}
+			self.code = Some(src.split_to(1)[0]);
+		}
+
+		match self.decode_chunk(src)? {
+			Some(bytes) => Ok(self.code.take().map(|code| (code, bytes))),
+			None => Ok(None),
+		}
+	}
+
+	/// Read the next chunk payload, decoded from the negotiated encoding.
+	fn decode_chunk(&mut self, src: &mut BytesMut) -> Result<Option<Vec<u8>>, ssz::Error> {
+		match self.uvi.decode(src)? {
+			Some(bytes) => Ok(Some(self.encoding.decode(&bytes[..], MAX_CHUNK_SIZE)?)),
+			None => Ok(None),
+		}
 	}
 }
 
@@ -81,17 +148,13 @@ impl<C: Config> Encoder for OutboundCodec<C> {
 	type Error = ssz::Error;
 
 	fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-		trace!("outbound encode type: {:?}, item: {:?}", self.typ, item);
-
-		let bytes = match (self.typ, item) {
-			(RPCType::Hello, RPCRequest::Hello(item)) => item.encode(),
-			(RPCType::Goodbye, RPCRequest::Goodbye(item)) => item.encode(),
-			(RPCType::BeaconBlocks, RPCRequest::BeaconBlocks(item)) => item.encode(),
-			(RPCType::RecentBeaconBlocks, RPCRequest::RecentBeaconBlocks(item)) => item.encode(),
-			_ => return Err(ssz::Error::Other("outbound codec invalid type")),
-		};
+		trace!("outbound encode type: {:?}, item: {:?}", self.method.typ, item);
+
+		if item.rpc_type() != self.method.typ {
+			return Err(ssz::Error::Other("outbound codec invalid type"))
+		}
 
-		self.uvi.encode(Bytes::from(bytes), dst)?;
+		encode_chunk(self.encoding, &mut self.uvi, item.ssz_data(), dst)?;
 
 		Ok(())
 	}
@@ -104,79 +167,72 @@ impl<C: Config> Decoder for OutboundCodec<C> {
 	fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
 		trace!("outbound decode src len: {}", src.len());
 
-		if src.is_empty() {
+		if self.done {
 			return Ok(None)
 		}
 
-		Ok(Some(match self.typ {
-			RPCType::Hello => {
-				let code = src.split_to(1)[0];
-				let bytes = match self.uvi.decode(src)? {
-					Some(bytes) => bytes,
+		let method = self.method.clone();
+		match &method.response {
+			ResponseKind::None => {
+				let (code, bytes) = match self.next_chunk(src)? {
+					Some(chunk) => chunk,
+					None => return Ok(None),
+				};
+
+				self.done = true;
+				Ok(Some(RPCResponse::Unknown(code, bytes)))
+			},
+			ResponseKind::Single(decode) => {
+				let (code, bytes) = match self.next_chunk(src)? {
+					Some(chunk) => chunk,
 					None => return Ok(None),
 				};
 
+				self.done = true;
 				if code == 0 {
-					RPCResponse::Hello(Decode::decode(&bytes[..])?)
+					Ok(Some(decode(&bytes[..])?))
 				} else {
-					RPCResponse::Unknown(code, bytes.to_vec())
+					Ok(Some(RPCResponse::Unknown(code, bytes)))
 				}
 			},
-			RPCType::Goodbye => {
-				let code = src.split_to(1)[0];
-				let bytes = match self.uvi.decode(src)? {
-					Some(bytes) => bytes,
+			// Blocks are streamed one chunk each, and returned together once
+			// the responder closes the stream, or sends an error.
+			ResponseKind::Blocks { collect, .. } => loop {
+				let (code, bytes) = match self.next_chunk(src)? {
+					Some(chunk) => chunk,
 					None => return Ok(None),
 				};
 
-				RPCResponse::Unknown(code, bytes.to_vec())
-			},
-			RPCType::BeaconBlocks => {
-				let mut result = Vec::new();
-
-				loop {
-					if src.is_empty() {
-						break
-					}
-
-					let code = src.split_to(1)[0];
-					if code != 0 {
-						break
+				if code != 0 {
+					self.done = true;
+					if self.blocks.is_empty() {
+						return Ok(Some(RPCResponse::Unknown(code, bytes)))
 					}
-
-					let bytes = match self.uvi.decode(src)? {
-						Some(bytes) => bytes,
-						None => break,
-					};
-
-					result.push(Decode::decode(&bytes[..])?);
+					return Ok(Some(collect(mem::replace(&mut self.blocks, Vec::new()))))
 				}
 
-				RPCResponse::BeaconBlocks(result)
+				let block: BeaconBlock<C> = Decode::decode_with(&bytes[..], RPC_DECODE_CONTEXT)?;
+				if let Some(limit) = self.limit.as_mut() {
+					limit.note_block(&block)?;
+				}
+				self.blocks.push(block);
 			},
-			RPCType::RecentBeaconBlocks => {
-				let mut result = Vec::new();
-
-				loop {
-					if src.is_empty() {
-						break
-					}
-
-					let code = src.split_to(1)[0];
-					if code != 0 {
-						break
-					}
-
-					let bytes = match self.uvi.decode(src)? {
-						Some(bytes) => bytes,
-						None => break,
-					};
+		}
+	}
 
-					result.push(Decode::decode(&bytes[..])?);
-				}
+	fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+		if let Some(response) = self.decode(src)? {
+			return Ok(Some(response))
+		}
+		if self.done {
+			return Ok(None)
+		}
 
-				RPCResponse::RecentBeaconBlocks(result)
-			},
-		}))
+		self.done = true;
+		match &self.method.response {
+			ResponseKind::Blocks { collect, .. } =>
+				Ok(Some(collect(mem::replace(&mut self.blocks, Vec::new())))),
+			ResponseKind::None | ResponseKind::Single(_) => Ok(None),
+		}
 	}
 }
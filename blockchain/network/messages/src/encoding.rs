@@ -0,0 +1,104 @@
+use crate::RPCType;
+
+/// Encoding of network messages.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Encoding {
+	/// Plain ssz.
+	Ssz,
+	/// Ssz compressed with snappy.
+	SszSnappy,
+}
+
+impl Encoding {
+	/// All supported encodings, in order of preference.
+	pub fn all() -> Vec<Self> {
+		vec![Encoding::SszSnappy, Encoding::Ssz]
+	}
+
+	/// Suffix of protocol names and gossip topics using this encoding.
+	pub fn suffix(&self) -> &'static str {
+		match self {
+			Encoding::Ssz => "ssz",
+			Encoding::SszSnappy => "ssz_snappy",
+		}
+	}
+
+	/// Parse an encoding from its suffix.
+	pub fn from_suffix(suffix: &str) -> Option<Self> {
+		match suffix {
+			"ssz" => Some(Encoding::Ssz),
+			"ssz_snappy" => Some(Encoding::SszSnappy),
+			_ => None,
+		}
+	}
+
+	/// Encode ssz data.
+	pub fn encode(&self, data: Vec<u8>) -> Result<Vec<u8>, ssz::Error> {
+		match self {
+			Encoding::Ssz => Ok(data),
+			Encoding::SszSnappy => snap::Encoder::new().compress_vec(&data)
+				.map_err(|_| ssz::Error::Other("Snappy compression failed")),
+		}
+	}
+
+	/// Maximum length of data encoding ssz data at most `max_len` bytes long.
+	pub fn max_encoded_len(&self, max_len: usize) -> usize {
+		match self {
+			Encoding::Ssz => max_len,
+			Encoding::SszSnappy => snap::max_compress_len(max_len),
+		}
+	}
+
+	/// Decode into ssz data, at most `max_len` bytes long.
+	pub fn decode(&self, data: &[u8], max_len: usize) -> Result<Vec<u8>, ssz::Error> {
+		match self {
+			Encoding::Ssz => {
+				if data.len() > max_len {
+					return Err(ssz::Error::TooLarge)
+				}
+				Ok(data.to_vec())
+			},
+			Encoding::SszSnappy => {
+				let len = snap::decompress_len(data)
+					.map_err(|_| ssz::Error::Other("Invalid snappy data"))?;
+				if len > max_len {
+					return Err(ssz::Error::TooLarge)
+				}
+				snap::Decoder::new().decompress_vec(data)
+					.map_err(|_| ssz::Error::Other("Invalid snappy data"))
+			},
+		}
+	}
+}
+
+impl Default for Encoding {
+	fn default() -> Self {
+		Encoding::Ssz
+	}
+}
+
+/// RPC protocol, identified by the RPC type and the encoding used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct RPCProtocolId {
+	/// RPC type.
+	pub typ: RPCType,
+	/// Encoding of requests and responses.
+	pub encoding: Encoding,
+	/// Protocol name negotiated for the type and encoding.
+	pub name: &'static str,
+}
+
+impl RPCProtocolId {
+	/// Create a new protocol id.
+	pub fn new(typ: RPCType, encoding: Encoding, name: &'static str) -> Self {
+		Self { typ, encoding, name }
+	}
+}
+
+impl libp2p_rpc::RPCType for RPCProtocolId { }
+
+impl libp2p::core::ProtocolName for RPCProtocolId {
+	fn protocol_name(&self) -> &[u8] {
+		self.name.as_bytes()
+	}
+}
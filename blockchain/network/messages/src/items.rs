@@ -82,8 +82,8 @@ impl ssz::Encode for GoodbyeReason {
 }
 
 impl ssz::Decode for GoodbyeReason {
-	fn decode(value: &[u8]) -> Result<Self, ssz::Error> {
-		u64::decode(value).map(Into::into)
+	fn decode_with(value: &[u8], context: ssz::DecodeContext) -> Result<Self, ssz::Error> {
+		u64::decode_with(value, context).map(Into::into)
 	}
 }
 
@@ -1,16 +1,34 @@
 mod items;
 mod codec;
+mod encoding;
+mod registry;
 
 pub use items::{HelloMessage, GoodbyeReason, BeaconBlocksRequest, RecentBeaconBlocksRequest};
 pub use codec::{InboundCodec, OutboundCodec};
+pub use encoding::{Encoding, RPCProtocolId};
+pub use registry::{RPCMethod, RPCRegistry, ResponseKind, ResponseLimit};
 
 use beacon::{
 	Config, types::{BeaconBlock, Attestation, VoluntaryExit, ProposerSlashing, AttesterSlashing},
 };
 use libp2p::gossipsub;
 
+/// Maximum size of an RPC request or response chunk, in bytes.
+pub const MAX_CHUNK_SIZE: usize = 1 << 20;
+/// Maximum size of a gossip message, in bytes.
+pub const GOSSIP_MAX_SIZE: usize = 1 << 20;
+/// Maximum nesting depth of decoded network messages.
+pub const MAX_DECODE_DEPTH: usize = 16;
+
+/// Decode limits for RPC chunks.
+pub const RPC_DECODE_CONTEXT: ssz::DecodeContext =
+	ssz::DecodeContext::new(MAX_CHUNK_SIZE, MAX_DECODE_DEPTH);
+/// Decode limits for gossip messages.
+pub const GOSSIP_DECODE_CONTEXT: ssz::DecodeContext =
+	ssz::DecodeContext::new(GOSSIP_MAX_SIZE, MAX_DECODE_DEPTH);
+
 /// RPC type.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum RPCType {
 	Hello = 0,
 	Goodbye = 1,
@@ -18,8 +36,9 @@ pub enum RPCType {
 	RecentBeaconBlocks = 3,
 }
 
-impl libp2p_rpc::RPCType for RPCType {
-	fn all() -> Vec<Self> {
+impl RPCType {
+	/// All RPC types.
+	pub fn all() -> Vec<Self> {
 		vec![
 			RPCType::Hello, RPCType::Goodbye,
 			RPCType::BeaconBlocks, RPCType::RecentBeaconBlocks,
@@ -27,17 +46,6 @@ impl libp2p_rpc::RPCType for RPCType {
 	}
 }
 
-impl libp2p::core::ProtocolName for RPCType {
-	fn protocol_name(&self) -> &[u8] {
-		match self {
-			RPCType::Hello => b"/eth2/beacon_chain/req/status/1/ssz",
-			RPCType::Goodbye => b"/eth2/beacon_chain/req/goodbye/1/ssz",
-			RPCType::BeaconBlocks => b"/eth2/beacon_chain/req/beacon_blocks_by_range/1/ssz",
-			RPCType::RecentBeaconBlocks => b"/eth2/beacon_chain/req/beacon_blocks_by_root/1/ssz",
-		}
-	}
-}
-
 /// Possible RPC requests.
 #[derive(Debug, Clone)]
 pub enum RPCRequest {
@@ -47,7 +55,7 @@ pub enum RPCRequest {
 	RecentBeaconBlocks(RecentBeaconBlocksRequest),
 }
 
-impl libp2p_rpc::RPCRequest<RPCType> for RPCRequest {
+impl libp2p_rpc::RPCRequest<RPCProtocolId> for RPCRequest {
 	fn is_goodbye(&self) -> bool {
 		match self {
 			RPCRequest::Goodbye(_) => true,
@@ -61,8 +69,11 @@ impl libp2p_rpc::RPCRequest<RPCType> for RPCRequest {
 			_ => true,
 		}
 	}
+}
 
-	fn typ(&self) -> RPCType {
+impl RPCRequest {
+	/// RPC type of the request.
+	pub fn rpc_type(&self) -> RPCType {
 		match self {
 			Self::Hello(_) => RPCType::Hello,
 			Self::Goodbye(_) => RPCType::Goodbye,
@@ -70,8 +81,21 @@ impl libp2p_rpc::RPCRequest<RPCType> for RPCRequest {
 			Self::RecentBeaconBlocks(_) => RPCType::RecentBeaconBlocks,
 		}
 	}
+
+	/// Ssz encoded request payload.
+	pub fn ssz_data(&self) -> Vec<u8> {
+		match self {
+			Self::Hello(item) => ssz::Encode::encode(item),
+			Self::Goodbye(item) => ssz::Encode::encode(item),
+			Self::BeaconBlocks(item) => ssz::Encode::encode(item),
+			Self::RecentBeaconBlocks(item) => ssz::Encode::encode(item),
+		}
+	}
 }
 
+/// Response code of requests rejected for exceeding a rate limit.
+pub const RESPONSE_CODE_RATE_LIMITED: u8 = 139;
+
 /// Corresponding RPC responses.
 #[derive(Debug, Clone)]
 pub enum RPCResponse<C: Config> {
@@ -81,8 +105,30 @@ pub enum RPCResponse<C: Config> {
 	Unknown(u8, Vec<u8>),
 }
 
+impl<C: Config> RPCResponse<C> {
+	/// RPC type of the response, or `None` for error responses.
+	pub fn typ(&self) -> Option<RPCType> {
+		match self {
+			Self::Hello(_) => Some(RPCType::Hello),
+			Self::BeaconBlocks(_) => Some(RPCType::BeaconBlocks),
+			Self::RecentBeaconBlocks(_) => Some(RPCType::RecentBeaconBlocks),
+			Self::Unknown(_, _) => None,
+		}
+	}
+
+	/// Ssz encoded response chunks, one for each block of block responses.
+	pub fn ssz_chunks(&self) -> Vec<Vec<u8>> {
+		match self {
+			Self::Hello(item) => vec![ssz::Encode::encode(item)],
+			Self::BeaconBlocks(blocks) | Self::RecentBeaconBlocks(blocks) =>
+				blocks.iter().map(ssz::Encode::encode).collect(),
+			Self::Unknown(_, value) => vec![value.clone()],
+		}
+	}
+}
+
 /// Pubsub type.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub enum PubsubType {
 	Block,
 	Attestation,
@@ -92,38 +138,98 @@ pub enum PubsubType {
 }
 
 impl PubsubType {
-	pub fn from_gossipsub_topic_hash(topic: &gossipsub::TopicHash) -> Option<Self> {
-		match topic.as_str() {
-			"/eth2/beacon_block/ssz" => Some(Self::Block),
-			"/eth2/beacon_attestation/ssz" => Some(Self::Attestation),
-			"/eth2/voluntary_exit/ssz" => Some(Self::VoluntaryExit),
-			"/eth2/proposer_slashing/ssz" => Some(Self::ProposerSlashing),
-			"/eth2/attester_slashing/ssz" => Some(Self::AttesterSlashing),
+	/// All pubsub types.
+	pub fn all() -> Vec<Self> {
+		vec![
+			Self::Block, Self::Attestation, Self::VoluntaryExit,
+			Self::ProposerSlashing, Self::AttesterSlashing,
+		]
+	}
+
+	/// Maximum size of a decoded message of this type, in bytes.
+	pub fn max_size(&self) -> usize {
+		match self {
+			Self::Block => GOSSIP_MAX_SIZE,
+			Self::Attestation => 16 * 1024,
+			Self::VoluntaryExit => 1024,
+			Self::ProposerSlashing => 1024,
+			Self::AttesterSlashing => 128 * 1024,
+		}
+	}
+
+	/// Maximum size of an encoded message of this type, in bytes.
+	pub fn max_encoded_size(&self, encoding: Encoding) -> usize {
+		encoding.max_encoded_len(self.max_size())
+	}
+
+	/// Name of the gossip topic of this type, without encoding.
+	pub fn topic_name(&self) -> &'static str {
+		match self {
+			Self::Block => "beacon_block",
+			Self::Attestation => "beacon_attestation",
+			Self::VoluntaryExit => "voluntary_exit",
+			Self::ProposerSlashing => "proposer_slashing",
+			Self::AttesterSlashing => "attester_slashing",
+		}
+	}
+
+	/// Parse a pubsub type from its topic name.
+	pub fn from_topic_name(name: &str) -> Option<Self> {
+		Self::all().into_iter().find(|typ| typ.topic_name() == name)
+	}
+
+	fn topic(&self, encoding: Encoding) -> String {
+		format!("/eth2/{}/{}", self.topic_name(), encoding.suffix())
+	}
+
+	/// Parse a gossipsub topic into its pubsub type and encoding. Attestation
+	/// subnet topics parse as attestations.
+	pub fn from_gossipsub_topic_hash(topic: &gossipsub::TopicHash) -> Option<(Self, Encoding)> {
+		let mut parts = topic.as_str().split('/');
+		match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+			(Some(""), Some("eth2"), Some(name), Some(suffix), None) => {
+				let typ = Self::from_topic_name(name)
+					.or_else(|| attestation_subnet_of(name).map(|_| Self::Attestation))?;
+				let encoding = Encoding::from_suffix(suffix)?;
+				Some((typ, encoding))
+			},
 			_ => None,
 		}
 	}
 
-	pub fn gossipsub_topic_hash(&self) -> gossipsub::TopicHash {
-		gossipsub::TopicHash::from_raw(match self {
-			Self::Block => "/eth2/beacon_block/ssz".to_string(),
-			Self::Attestation => "/eth2/beacon_attestation/ssz".to_string(),
-			Self::VoluntaryExit => "/eth2/voluntary_exit/ssz".to_string(),
-			Self::ProposerSlashing => "/eth2/proposer_slashing/ssz".to_string(),
-			Self::AttesterSlashing => "/eth2/attester_slashing/ssz".to_string(),
-		})
+	pub fn gossipsub_topic_hash(&self, encoding: Encoding) -> gossipsub::TopicHash {
+		gossipsub::TopicHash::from_raw(self.topic(encoding))
 	}
 
-	pub fn gossipsub_topic(&self) -> gossipsub::Topic {
-		gossipsub::Topic::new(match self {
-			Self::Block => "/eth2/beacon_block/ssz".to_string(),
-			Self::Attestation => "/eth2/beacon_attestation/ssz".to_string(),
-			Self::VoluntaryExit => "/eth2/voluntary_exit/ssz".to_string(),
-			Self::ProposerSlashing => "/eth2/proposer_slashing/ssz".to_string(),
-			Self::AttesterSlashing => "/eth2/attester_slashing/ssz".to_string(),
-		})
+	pub fn gossipsub_topic(&self, encoding: Encoding) -> gossipsub::Topic {
+		gossipsub::Topic::new(self.topic(encoding))
+	}
+}
+
+fn attestation_subnet_of(name: &str) -> Option<u64> {
+	let prefix = PubsubType::Attestation.topic_name();
+	if !name.starts_with(prefix) || !name[prefix.len()..].starts_with('_') {
+		return None
+	}
+	name[prefix.len() + 1..].parse().ok()
+}
+
+/// Attestation subnet of a gossipsub topic, if it is a subnet topic.
+pub fn attestation_subnet_of_topic(topic: &gossipsub::TopicHash) -> Option<u64> {
+	let mut parts = topic.as_str().split('/');
+	match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some(""), Some("eth2"), Some(name), Some(_), None) => attestation_subnet_of(name),
+		_ => None,
 	}
 }
 
+/// Gossipsub topic of an attestation subnet.
+pub fn attestation_subnet_topic(subnet: u64, encoding: Encoding) -> gossipsub::Topic {
+	gossipsub::Topic::new(
+		format!("/eth2/{}_{}/{}", PubsubType::Attestation.topic_name(), subnet, encoding.suffix())
+	)
+}
+
 impl<'a, C: Config> From<&'a PubsubMessage<C>> for PubsubType {
 	fn from(message: &'a PubsubMessage<C>) -> PubsubType {
 		match message {
@@ -153,6 +259,23 @@ pub enum PubsubMessage<C: Config> {
 }
 
 impl<C: Config> PubsubMessage<C> {
+	/// Message data in the given encoding.
+	pub fn encoded_data(&self, encoding: Encoding) -> Result<Vec<u8>, ssz::Error> {
+		encoding.encode(self.ssz_data())
+	}
+
+	/// Decode a message from data in the given encoding.
+	pub fn from_encoded_data(
+		typ: PubsubType,
+		encoding: Encoding,
+		data: &[u8],
+	) -> Result<Self, ssz::Error> {
+		if data.len() > typ.max_encoded_size(encoding) {
+			return Err(ssz::Error::TooLarge)
+		}
+		Self::from_ssz_data(typ, &encoding.decode(data, typ.max_size())?)
+	}
+
 	pub fn ssz_data(&self) -> Vec<u8> {
 		match self {
 			Self::Block(item) => ssz::Encode::encode(item),
@@ -163,13 +286,16 @@ impl<C: Config> PubsubMessage<C> {
 		}
 	}
 
-	pub fn from_ssz_data(typ: PubsubType, mut data: &[u8]) -> Result<Self, ssz::Error> {
+	pub fn from_ssz_data(typ: PubsubType, data: &[u8]) -> Result<Self, ssz::Error> {
+		let context = ssz::DecodeContext::new(typ.max_size(), MAX_DECODE_DEPTH);
 		Ok(match typ {
-			PubsubType::Block => Self::Block(ssz::Decode::decode(&mut data)?),
-			PubsubType::Attestation => Self::Attestation(ssz::Decode::decode(&mut data)?),
-			PubsubType::VoluntaryExit => Self::VoluntaryExit(ssz::Decode::decode(&mut data)?),
-			PubsubType::ProposerSlashing => Self::ProposerSlashing(ssz::Decode::decode(&mut data)?),
-			PubsubType::AttesterSlashing => Self::AttesterSlashing(ssz::Decode::decode(&mut data)?),
+			PubsubType::Block => Self::Block(ssz::Decode::decode_with(data, context)?),
+			PubsubType::Attestation => Self::Attestation(ssz::Decode::decode_with(data, context)?),
+			PubsubType::VoluntaryExit => Self::VoluntaryExit(ssz::Decode::decode_with(data, context)?),
+			PubsubType::ProposerSlashing =>
+				Self::ProposerSlashing(ssz::Decode::decode_with(data, context)?),
+			PubsubType::AttesterSlashing =>
+				Self::AttesterSlashing(ssz::Decode::decode_with(data, context)?),
 		})
 	}
 }
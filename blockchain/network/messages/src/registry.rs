@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use beacon::Config;
+use beacon::primitives::Slot;
+use beacon::types::BeaconBlock;
+use ssz::Decode;
+use crate::{
+	Encoding, RPCType, RPCProtocolId, RPCRequest, RPCResponse, RPC_DECODE_CONTEXT,
+};
+
+/// Protocol names of a beacon chain RPC method, in every encoding.
+macro_rules! protocol_names {
+	($name:literal, $version:literal) => {
+		&[
+			(Encoding::Ssz, concat!("/eth2/beacon_chain/req/", $name, "/", $version, "/ssz")),
+			(Encoding::SszSnappy, concat!("/eth2/beacon_chain/req/", $name, "/", $version, "/ssz_snappy")),
+		]
+	}
+}
+
+/// Bounds on the blocks a peer may send in response to a request.
+pub struct ResponseLimit {
+	/// Maximum number of response chunks.
+	max_chunks: usize,
+	/// Slot at which the requested range ends, exclusive.
+	end_slot: Option<Slot>,
+	/// Number of response chunks received so far.
+	received: usize,
+	/// Slot of the last received block.
+	last_slot: Option<Slot>,
+}
+
+impl ResponseLimit {
+	/// Allow at most `max_chunks` blocks, before `end_slot` and in ascending
+	/// slot order if given.
+	pub fn new(max_chunks: usize, end_slot: Option<Slot>) -> Self {
+		Self { max_chunks, end_slot, received: 0, last_slot: None }
+	}
+
+	/// Check a newly received block against the request. Blocks before the
+	/// start slot are allowed, as responders roll back to a common ancestor
+	/// when the requested head is not canonical on their side.
+	pub fn note_block<C: Config>(&mut self, block: &BeaconBlock<C>) -> Result<(), ssz::Error> {
+		if self.received >= self.max_chunks {
+			return Err(ssz::Error::Other("More response chunks than requested"))
+		}
+		if self.end_slot.map(|end_slot| block.slot >= end_slot).unwrap_or(false) {
+			return Err(ssz::Error::Other("Block outside of requested range"))
+		}
+		if self.end_slot.is_some() && self.last_slot.map(|last| block.slot <= last).unwrap_or(false) {
+			return Err(ssz::Error::Other("Blocks not in ascending slot order"))
+		}
+
+		self.received += 1;
+		self.last_slot = Some(block.slot);
+		Ok(())
+	}
+}
+
+/// Shape of the responses of an RPC method.
+pub enum ResponseKind<C: Config> {
+	/// No response is expected. Anything received is returned as an unknown
+	/// response.
+	None,
+	/// A single response chunk, decoded by the given function.
+	Single(fn(&[u8]) -> Result<RPCResponse<C>, ssz::Error>),
+	/// A stream of block chunks, checked against the limit derived from the
+	/// request and collected into one response.
+	Blocks {
+		/// Limit on the blocks responding to a request.
+		limit: fn(&RPCRequest) -> ResponseLimit,
+		/// Build the response from the received blocks.
+		collect: fn(Vec<BeaconBlock<C>>) -> RPCResponse<C>,
+	},
+}
+
+/// An RPC method, describing how its protocols are named and how its
+/// requests and responses are decoded.
+pub struct RPCMethod<C: Config> {
+	/// RPC type of the method.
+	pub typ: RPCType,
+	/// Protocol name of the method in each encoding.
+	pub protocols: &'static [(Encoding, &'static str)],
+	/// Decode a request payload.
+	pub decode_request: fn(&[u8]) -> Result<RPCRequest, ssz::Error>,
+	/// Shape of the responses.
+	pub response: ResponseKind<C>,
+}
+
+impl<C: Config> RPCMethod<C> {
+	/// Protocol of the method in the given encoding, if supported.
+	pub fn protocol(&self, encoding: Encoding) -> Option<RPCProtocolId> {
+		self.protocols.iter()
+			.find(|(protocol_encoding, _)| *protocol_encoding == encoding)
+			.map(|(_, name)| RPCProtocolId::new(self.typ, encoding, *name))
+	}
+}
+
+fn decode<T: Decode>(data: &[u8]) -> Result<T, ssz::Error> {
+	T::decode_with(data, RPC_DECODE_CONTEXT)
+}
+
+/// Table of the RPC methods spoken, consulted by the codecs.
+pub struct RPCRegistry<C: Config> {
+	methods: Vec<Arc<RPCMethod<C>>>,
+}
+
+impl<C: Config> RPCRegistry<C> {
+	/// Create a registry without any method.
+	pub fn empty() -> Self {
+		Self { methods: Vec::new() }
+	}
+
+	/// Register a method, replacing any previous method of the same type.
+	pub fn register(&mut self, method: RPCMethod<C>) {
+		self.methods.retain(|registered| registered.typ != method.typ);
+		self.methods.push(Arc::new(method));
+	}
+
+	/// Method of an RPC type, if registered.
+	pub fn method(&self, typ: RPCType) -> Option<Arc<RPCMethod<C>>> {
+		self.methods.iter().find(|method| method.typ == typ).cloned()
+	}
+
+	/// Protocols of all methods in the given encodings, grouped by method and
+	/// in the order of the encodings.
+	pub fn protocols(&self, encodings: &[Encoding]) -> Vec<RPCProtocolId> {
+		self.methods.iter()
+			.flat_map(|method| encodings.iter().filter_map(move |encoding| method.protocol(*encoding)))
+			.collect()
+	}
+}
+
+impl<C: Config> Default for RPCRegistry<C> {
+	/// Registry of the beacon chain RPC methods.
+	fn default() -> Self {
+		let mut registry = Self::empty();
+		registry.register(RPCMethod {
+			typ: RPCType::Hello,
+			protocols: protocol_names!("status", "1"),
+			decode_request: |data| Ok(RPCRequest::Hello(decode(data)?)),
+			response: ResponseKind::Single(|data| Ok(RPCResponse::Hello(decode(data)?))),
+		});
+		registry.register(RPCMethod {
+			typ: RPCType::Goodbye,
+			protocols: protocol_names!("goodbye", "1"),
+			decode_request: |data| Ok(RPCRequest::Goodbye(decode(data)?)),
+			response: ResponseKind::None,
+		});
+		registry.register(RPCMethod {
+			typ: RPCType::BeaconBlocks,
+			protocols: protocol_names!("beacon_blocks_by_range", "1"),
+			decode_request: |data| Ok(RPCRequest::BeaconBlocks(decode(data)?)),
+			response: ResponseKind::Blocks {
+				limit: |request| match request {
+					RPCRequest::BeaconBlocks(request) => ResponseLimit::new(
+						request.count as usize,
+						Some(request.start_slot.saturating_add(
+							request.count.saturating_mul(request.step)
+						)),
+					),
+					_ => ResponseLimit::new(0, None),
+				},
+				collect: RPCResponse::BeaconBlocks,
+			},
+		});
+		registry.register(RPCMethod {
+			typ: RPCType::RecentBeaconBlocks,
+			protocols: protocol_names!("beacon_blocks_by_root", "1"),
+			decode_request: |data| Ok(RPCRequest::RecentBeaconBlocks(decode(data)?)),
+			response: ResponseKind::Blocks {
+				limit: |request| match request {
+					RPCRequest::RecentBeaconBlocks(request) =>
+						ResponseLimit::new(request.block_roots.len(), None),
+					_ => ResponseLimit::new(0, None),
+				},
+				collect: RPCResponse::RecentBeaconBlocks,
+			},
+		});
+		registry
+	}
+}
@@ -0,0 +1,264 @@
+// Properties of the RPC codecs: messages round trip through the codecs of
+// both sides, and arbitrary streams from a hostile peer are rejected
+// without panicking or buffering past the chunk limit.
+
+use beacon::MinimalConfig;
+use beacon::primitives::{H256, Version};
+use beacon::types::BeaconBlock;
+use bytes::BytesMut;
+use proptest::prelude::*;
+use proptest::collection::vec;
+use proptest::test_runner::TestCaseError;
+use tokio::codec::{Decoder, Encoder};
+use shasper_network_messages::{
+	BeaconBlocksRequest, Encoding, HelloMessage, InboundCodec, OutboundCodec,
+	RPCProtocolId, RPCRegistry, RPCRequest, RPCResponse, RecentBeaconBlocksRequest, MAX_CHUNK_SIZE,
+};
+
+type C = MinimalConfig;
+
+fn h256() -> impl Strategy<Value = H256> {
+	any::<[u8; 32]>().prop_map(H256::from)
+}
+
+fn encoding() -> impl Strategy<Value = Encoding> {
+	prop_oneof![Just(Encoding::Ssz), Just(Encoding::SszSnappy)]
+}
+
+prop_compose! {
+	fn hello()(
+		fork_version in any::<[u8; 4]>(),
+		finalized_root in h256(),
+		finalized_epoch in any::<u64>(),
+		head_root in h256(),
+		head_slot in any::<u64>(),
+	) -> HelloMessage {
+		HelloMessage {
+			fork_version: Version::from(fork_version),
+			finalized_root, finalized_epoch, head_root, head_slot,
+		}
+	}
+}
+
+fn request() -> impl Strategy<Value = RPCRequest> {
+	prop_oneof![
+		hello().prop_map(RPCRequest::Hello),
+		any::<u64>().prop_map(|reason| RPCRequest::Goodbye(reason.into())),
+		(h256(), any::<u64>(), any::<u64>(), any::<u64>()).prop_map(|(head_block_root, start_slot, count, step)| {
+			RPCRequest::BeaconBlocks(BeaconBlocksRequest { head_block_root, start_slot, count, step })
+		}),
+		vec(h256(), 0..32).prop_map(|block_roots| {
+			RPCRequest::RecentBeaconBlocks(RecentBeaconBlocksRequest { block_roots })
+		}),
+	]
+}
+
+fn protocol(registry: &RPCRegistry<C>, request: &RPCRequest, encoding: Encoding) -> RPCProtocolId {
+	registry.method(request.rpc_type()).and_then(|method| method.protocol(encoding))
+		.expect("All request types are registered in every encoding")
+}
+
+fn codecs(request: &RPCRequest, encoding: Encoding) -> (OutboundCodec<C>, InboundCodec<C>) {
+	let registry = RPCRegistry::<C>::default();
+	let protocol = protocol(&registry, request, encoding);
+	let method = registry.method(protocol.typ).expect("Protocol of a registered method");
+	(OutboundCodec::new(method.clone(), protocol, request), InboundCodec::new(method, protocol))
+}
+
+/// Feed `data` to a decoder in pieces of at most `step` bytes, as a stream
+/// would arrive, collecting decoded items until the end of the stream or
+/// the first error.
+fn feed<D: Decoder>(decoder: &mut D, data: &[u8], step: usize) -> Result<Vec<D::Item>, D::Error> {
+	let mut src = BytesMut::new();
+	let mut items = Vec::new();
+	for piece in data.chunks(step.max(1)) {
+		src.extend_from_slice(piece);
+		while let Some(item) = decoder.decode(&mut src)? {
+			items.push(item);
+		}
+	}
+	while let Some(item) = decoder.decode_eof(&mut src)? {
+		items.push(item);
+	}
+	Ok(items)
+}
+
+fn blocks(slots: &[u64]) -> Vec<BeaconBlock<C>> {
+	slots.iter().map(|slot| BeaconBlock { slot: *slot, ..Default::default() }).collect()
+}
+
+fn chunks_of(response: &RPCResponse<C>) -> Vec<Vec<u8>> {
+	match response {
+		RPCResponse::Unknown(code, value) => vec![vec![*code], value.clone()],
+		response => response.ssz_chunks(),
+	}
+}
+
+fn responses_match(a: &[RPCResponse<C>], b: &[RPCResponse<C>]) -> Result<(), TestCaseError> {
+	prop_assert_eq!(a.len(), b.len());
+	for (a, b) in a.iter().zip(b) {
+		prop_assert_eq!(a.typ(), b.typ());
+		prop_assert_eq!(chunks_of(a), chunks_of(b));
+	}
+	Ok(())
+}
+
+proptest! {
+	#[test]
+	fn requests_round_trip(request in request(), encoding in encoding(), step in 1usize..64) {
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let mut data = BytesMut::new();
+		outbound.encode(request.clone(), &mut data).unwrap();
+
+		let decoded = feed(&mut inbound, &data, step).unwrap();
+		prop_assert_eq!(decoded.len(), 1);
+		prop_assert_eq!(decoded[0].0.rpc_type(), request.rpc_type());
+		prop_assert_eq!(decoded[0].0.ssz_data(), request.ssz_data());
+		prop_assert_eq!(decoded[0].1, data.len());
+	}
+
+	#[test]
+	fn hello_responses_round_trip(hello in hello(), encoding in encoding(), step in 1usize..64) {
+		let request = RPCRequest::Hello(hello.clone());
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let response = RPCResponse::Hello(hello);
+		let mut data = BytesMut::new();
+		inbound.encode(response.clone(), &mut data).unwrap();
+
+		responses_match(&feed(&mut outbound, &data, step).unwrap(), &[response])?;
+	}
+
+	#[test]
+	fn block_responses_round_trip(
+		start_slot in 0u64..1024,
+		gaps in vec(1u64..4, 0..8),
+		encoding in encoding(),
+		step in 1usize..512,
+	) {
+		let slots = gaps.iter().scan(start_slot, |slot, gap| {
+			*slot += gap;
+			Some(*slot)
+		}).collect::<Vec<_>>();
+		let request = RPCRequest::BeaconBlocks(BeaconBlocksRequest {
+			head_block_root: H256::default(),
+			start_slot,
+			count: 32,
+			step: 1,
+		});
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let response = RPCResponse::BeaconBlocks(blocks(&slots));
+		let mut data = BytesMut::new();
+		inbound.encode(response.clone(), &mut data).unwrap();
+
+		responses_match(&feed(&mut outbound, &data, step).unwrap(), &[response])?;
+	}
+
+	#[test]
+	fn error_responses_round_trip(
+		code in 1u8..=255,
+		message in vec(any::<u8>(), 0..256),
+		encoding in encoding(),
+		step in 1usize..64,
+	) {
+		let request = RPCRequest::BeaconBlocks(BeaconBlocksRequest {
+			head_block_root: H256::default(),
+			start_slot: 0,
+			count: 32,
+			step: 1,
+		});
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let response = RPCResponse::Unknown(code, message);
+		let mut data = BytesMut::new();
+		inbound.encode(response.clone(), &mut data).unwrap();
+
+		responses_match(&feed(&mut outbound, &data, step).unwrap(), &[response])?;
+	}
+
+	#[test]
+	fn arbitrary_streams_do_not_panic(
+		request in request(),
+		encoding in encoding(),
+		data in vec(any::<u8>(), 0..2048),
+		step in 1usize..256,
+	) {
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let _ = feed(&mut inbound, &data, step);
+		let _ = feed(&mut outbound, &data, step);
+	}
+
+	#[test]
+	fn oversized_chunks_are_rejected_early(
+		request in request(),
+		encoding in encoding(),
+		excess in 1u64..(1 << 40),
+	) {
+		// A length prefix above the encoded chunk limit, followed by a few
+		// bytes of payload, must fail before the payload is buffered.
+		let max_len = encoding.max_encoded_len(MAX_CHUNK_SIZE) as u64;
+		let mut prefix = unsigned_varint::encode::u64_buffer();
+		let prefix = unsigned_varint::encode::u64(max_len + excess, &mut prefix);
+		let mut data = prefix.to_vec();
+		data.extend_from_slice(&[0u8; 64]);
+
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		prop_assert!(feed(&mut inbound, &data, data.len()).is_err());
+
+		let mut response = vec![0u8];
+		response.extend_from_slice(&data);
+		prop_assert!(feed(&mut outbound, &response, response.len()).is_err());
+	}
+}
+
+#[test]
+fn blocks_beyond_the_request_are_rejected() {
+	let request = RPCRequest::BeaconBlocks(BeaconBlocksRequest {
+		head_block_root: H256::default(),
+		start_slot: 0,
+		count: 2,
+		step: 1,
+	});
+	for encoding in Encoding::all() {
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let mut data = BytesMut::new();
+		inbound.encode(RPCResponse::BeaconBlocks(blocks(&[0, 1, 2])), &mut data).unwrap();
+		assert!(feed(&mut outbound, &data, data.len()).is_err());
+	}
+}
+
+/// Bytes that do not compress, from a xorshift generator.
+fn incompressible(len: usize) -> Vec<u8> {
+	let mut state = 0x2545_f491_4f6c_dd1du64;
+	(0..len).map(|_| {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		state as u8
+	}).collect()
+}
+
+#[test]
+fn incompressible_chunks_up_to_the_limit_round_trip() {
+	let request = RPCRequest::BeaconBlocks(BeaconBlocksRequest {
+		head_block_root: H256::default(),
+		start_slot: 0,
+		count: 2,
+		step: 1,
+	});
+	for encoding in Encoding::all() {
+		let (mut outbound, mut inbound) = codecs(&request, encoding);
+		let response = RPCResponse::Unknown(1, incompressible(MAX_CHUNK_SIZE));
+		let mut data = BytesMut::new();
+		inbound.encode(response.clone(), &mut data).unwrap();
+		if encoding == Encoding::SszSnappy {
+			// Code, length prefix, and a payload longer than the chunk.
+			assert!(data.len() > 1 + 3 + MAX_CHUNK_SIZE);
+		}
+
+		let decoded = feed(&mut outbound, &data, data.len()).unwrap();
+		assert_eq!(decoded.len(), 1);
+		assert_eq!(chunks_of(&decoded[0]), chunks_of(&response));
+
+		let oversized = RPCResponse::Unknown(1, incompressible(MAX_CHUNK_SIZE + 1));
+		assert!(inbound.encode(oversized, &mut BytesMut::new()).is_err());
+	}
+}
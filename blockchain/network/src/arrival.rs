@@ -0,0 +1,194 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use libp2p::PeerId;
+use log::*;
+
+/// Upper bounds of arrival delay histogram buckets, in milliseconds. A last
+/// bucket counts everything above.
+pub const ARRIVAL_BUCKETS_MS: &[u64] = &[250, 500, 1000, 2000, 4000, 8000];
+/// Number of recent slots to keep per-slot arrival records for.
+pub const MAX_TRACKED_SLOTS: usize = 64;
+
+/// Arrival statistics shared with other components, such as the node API.
+pub type SharedArrivalStats = Arc<RwLock<ArrivalStats>>;
+
+/// Kind of gossiped object.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArrivalKind {
+	/// Beacon block.
+	Block,
+	/// Attestation.
+	Attestation,
+}
+
+impl ArrivalKind {
+	fn name(&self) -> &'static str {
+		match self {
+			ArrivalKind::Block => "block",
+			ArrivalKind::Attestation => "attestation",
+		}
+	}
+}
+
+/// Histogram of arrival delays relative to slot start.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ArrivalHistogram {
+	/// Counts per bucket of `ARRIVAL_BUCKETS_MS`, plus one for larger delays.
+	pub buckets: Vec<u64>,
+	/// Number of objects arriving before their slot started.
+	pub early: u64,
+	/// Sum of all delays, in milliseconds.
+	pub total_ms: u64,
+	/// Number of recorded objects.
+	pub count: u64,
+}
+
+impl ArrivalHistogram {
+	fn new() -> Self {
+		Self { buckets: vec![0; ARRIVAL_BUCKETS_MS.len() + 1], ..Default::default() }
+	}
+
+	fn record(&mut self, delay: Option<Duration>) {
+		self.count += 1;
+		let delay_ms = match delay {
+			Some(delay) => delay.as_millis() as u64,
+			None => {
+				self.early += 1;
+				0
+			},
+		};
+		self.total_ms += delay_ms;
+		let bucket = ARRIVAL_BUCKETS_MS.iter()
+			.position(|bound| delay_ms <= *bound)
+			.unwrap_or(ARRIVAL_BUCKETS_MS.len());
+		self.buckets[bucket] += 1;
+	}
+
+	/// Mean delay, if anything was recorded.
+	pub fn mean(&self) -> Option<Duration> {
+		if self.count == 0 {
+			None
+		} else {
+			Some(Duration::from_millis(self.total_ms / self.count))
+		}
+	}
+}
+
+/// Arrivals within a single slot.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SlotArrivals {
+	/// Delay of the first block of the slot.
+	pub first_block: Option<Duration>,
+	/// Number of attestations for the slot.
+	pub attestations: u64,
+	/// Largest attestation delay.
+	pub max_attestation: Option<Duration>,
+}
+
+/// Tracker of gossip arrival delays relative to slot start, to diagnose
+/// propagation and peering problems.
+pub struct ArrivalStats {
+	genesis_time: u64,
+	slot_duration: Duration,
+	blocks: ArrivalHistogram,
+	attestations: ArrivalHistogram,
+	slots: BTreeMap<u64, SlotArrivals>,
+	csv: Option<BufWriter<File>>,
+}
+
+impl ArrivalStats {
+	/// Create a new tracker for the chain of the given genesis time.
+	pub fn new(genesis_time: u64, slot_duration: Duration) -> Self {
+		Self {
+			genesis_time,
+			slot_duration,
+			blocks: ArrivalHistogram::new(),
+			attestations: ArrivalHistogram::new(),
+			slots: BTreeMap::new(),
+			csv: None,
+		}
+	}
+
+	/// Also append each arrival to a CSV file at `path`.
+	pub fn with_csv<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+		let mut csv = BufWriter::new(File::create(path)?);
+		writeln!(csv, "kind,slot,delay_ms,peer")?;
+		self.csv = Some(csv);
+		Ok(self)
+	}
+
+	/// Record the arrival of an object of `slot` from `peer`, now.
+	pub fn record(&mut self, kind: ArrivalKind, slot: u64, peer: &PeerId) {
+		self.record_at(kind, slot, peer, SystemTime::now())
+	}
+
+	/// Record the arrival of an object of `slot` from `peer` at `time`.
+	pub fn record_at(&mut self, kind: ArrivalKind, slot: u64, peer: &PeerId, time: SystemTime) {
+		let slot_start = UNIX_EPOCH + Duration::from_secs(self.genesis_time) +
+			self.slot_duration * slot as u32;
+		let delay = time.duration_since(slot_start).ok();
+
+		let entry = self.slots.entry(slot).or_insert_with(Default::default);
+		match kind {
+			ArrivalKind::Block => {
+				self.blocks.record(delay);
+				if entry.first_block.is_none() {
+					entry.first_block = Some(delay.unwrap_or_default());
+				}
+			},
+			ArrivalKind::Attestation => {
+				self.attestations.record(delay);
+				entry.attestations += 1;
+				entry.max_attestation = entry.max_attestation.max(delay);
+			},
+		}
+		while self.slots.len() > MAX_TRACKED_SLOTS {
+			let oldest = *self.slots.keys().next().expect("Length is checked above; qed");
+			self.slots.remove(&oldest);
+		}
+
+		if let Some(csv) = self.csv.as_mut() {
+			let delay_ms = delay.map(|d| d.as_millis() as i128)
+				.unwrap_or_else(|| -(slot_start.duration_since(time).unwrap_or_default().as_millis() as i128));
+			let result = writeln!(csv, "{},{},{},{}", kind.name(), slot, delay_ms, peer.to_base58())
+				.and_then(|()| csv.flush());
+			if let Err(e) = result {
+				warn!("Writing arrival CSV failed, disabling it: {:?}", e);
+				self.csv = None;
+			}
+		}
+	}
+
+	/// Histogram of arrival delays of the given kind.
+	pub fn histogram(&self, kind: ArrivalKind) -> &ArrivalHistogram {
+		match kind {
+			ArrivalKind::Block => &self.blocks,
+			ArrivalKind::Attestation => &self.attestations,
+		}
+	}
+
+	/// Arrivals of recent slots, oldest first.
+	pub fn slots(&self) -> impl Iterator<Item=(&u64, &SlotArrivals)> {
+		self.slots.iter()
+	}
+}
@@ -0,0 +1,148 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use network_messages::{RPCType, PubsubType};
+
+/// Protocol inbound bytes are accounted to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Protocol {
+	/// Request of an RPC protocol. Responses to our own requests are not
+	/// accounted.
+	Rpc(RPCType),
+	/// Gossip message on a topic.
+	Gossip(PubsubType),
+}
+
+impl Protocol {
+	/// Label of the protocol in metrics.
+	pub fn label(&self) -> String {
+		match self {
+			Protocol::Rpc(typ) => format!("rpc_{:?}", typ),
+			Protocol::Gossip(typ) => format!("gossip_{:?}", typ),
+		}
+	}
+}
+
+/// Inbound bandwidth quota of a single peer.
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+	/// Maximum number of bytes received within one window.
+	pub max_bytes: u64,
+	/// Length of the accounting window.
+	pub window: Duration,
+}
+
+#[derive(Default)]
+struct PeerBandwidth {
+	window_start: Option<Instant>,
+	window_bytes: u64,
+	totals: HashMap<Protocol, u64>,
+}
+
+/// Inbound bandwidth accounting, shared with the metrics writer.
+pub type SharedBandwidth = Arc<RwLock<BandwidthTracker>>;
+
+/// Inbound bandwidth accounting per peer and per protocol.
+pub struct BandwidthTracker {
+	quota: Quota,
+	peers: HashMap<PeerId, PeerBandwidth>,
+	totals: HashMap<Protocol, u64>,
+}
+
+impl BandwidthTracker {
+	/// Create a new tracker with the given per-peer quota.
+	pub fn new(quota: Quota) -> Self {
+		Self { quota, peers: HashMap::new(), totals: HashMap::new() }
+	}
+
+	/// Record bytes received from a peer. Returns whether the peer is over
+	/// its quota for the current window.
+	pub fn record(&mut self, peer: &PeerId, protocol: Protocol, bytes: u64) -> bool {
+		let now = Instant::now();
+		let quota = self.quota;
+		let peer = self.peers.entry(peer.clone()).or_default();
+
+		match peer.window_start {
+			Some(start) if now.duration_since(start) < quota.window => (),
+			_ => {
+				peer.window_start = Some(now);
+				peer.window_bytes = 0;
+			},
+		}
+
+		peer.window_bytes = peer.window_bytes.saturating_add(bytes);
+		*peer.totals.entry(protocol).or_insert(0) += bytes;
+		*self.totals.entry(protocol).or_insert(0) += bytes;
+
+		peer.window_bytes > quota.max_bytes
+	}
+
+	/// Whether a peer is over its quota for the current window.
+	pub fn is_over_quota(&self, peer: &PeerId) -> bool {
+		self.peers.get(peer).map(|peer| {
+			peer.window_start.map(|start| start.elapsed() < self.quota.window).unwrap_or(false) &&
+				peer.window_bytes > self.quota.max_bytes
+		}).unwrap_or(false)
+	}
+
+	/// Total bytes received from a peer, per protocol.
+	pub fn peer_totals(&self, peer: &PeerId) -> Option<&HashMap<Protocol, u64>> {
+		self.peers.get(peer).map(|peer| &peer.totals)
+	}
+
+	/// Total bytes received from all peers, per protocol.
+	pub fn totals(&self) -> &HashMap<Protocol, u64> {
+		&self.totals
+	}
+
+	/// Total bytes received from all peers.
+	pub fn total(&self) -> u64 {
+		self.totals.values().sum()
+	}
+
+	/// Forget a disconnected peer. Its bytes remain in the totals.
+	pub fn remove_peer(&mut self, peer: &PeerId) {
+		self.peers.remove(peer);
+	}
+
+	/// Encode the totals in the Prometheus text format.
+	pub fn encode(&self) -> String {
+		let mut totals = self.totals.iter()
+			.map(|(protocol, bytes)| (protocol.label(), *bytes))
+			.collect::<Vec<_>>();
+		totals.sort();
+
+		let mut out = String::new();
+		let name = "network_inbound_bytes_total";
+		let _ = writeln!(out, "# HELP {} Bytes received from peers, per protocol", name);
+		let _ = writeln!(out, "# TYPE {} counter", name);
+		for (protocol, bytes) in totals {
+			let _ = writeln!(out, "{}{{protocol=\"{}\"}} {}", name, protocol, bytes);
+		}
+		let _ = writeln!(out, "# HELP network_inbound_peers_over_quota Connected peers over their inbound quota");
+		let _ = writeln!(out, "# TYPE network_inbound_peers_over_quota gauge");
+		let _ = writeln!(
+			out, "network_inbound_peers_over_quota {}",
+			self.peers.keys().filter(|peer| self.is_over_quota(peer)).count(),
+		);
+		out
+	}
+}
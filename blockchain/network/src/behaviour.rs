@@ -16,26 +16,37 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::discovery::Discovery;
+use crate::bandwidth::{Protocol, SharedBandwidth};
+use crate::gossip_limit::{GossipRateLimiter, GossipRejection};
+use crate::nat::ObservedAddresses;
+use crate::publish::{SeenMessages, PublishQueue, SharedPublishStats};
+use crate::mesh::SharedMeshStats;
+use crate::subnets::{SubnetBackbone, attnets_bitfield};
 use crate::{Libp2pEvent, Error, NetworkConfig};
-use crate::rpc::{RPC, RPCMessage, RPCEvent};
+use crate::rpc::{RPC, RPCMessage, RPCEvent, RPCProtocol};
 use futures01::prelude::*;
 use libp2p::{
     core::identity::Keypair,
     discv5::Discv5Event,
-    gossipsub::{Gossipsub, GossipsubEvent, Topic},
+    gossipsub::{Gossipsub, GossipsubEvent, Topic, TopicHash},
     identify::{Identify, IdentifyEvent},
-    ping::{Ping, PingConfig, PingEvent},
+    ping::{Ping, PingConfig, PingEvent, PingFailure, PingSuccess},
     swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess},
     tokio_io::{AsyncRead, AsyncWrite},
     NetworkBehaviour, PeerId,
 };
-use network_messages::{PubsubType, PubsubMessage};
+use network_messages::{
+    Encoding, PubsubType, PubsubMessage, attestation_subnet_topic, attestation_subnet_of_topic,
+};
 use beacon::Config;
 use log::*;
+use std::collections::HashSet;
 use std::num::NonZeroU32;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 const MAX_IDENTIFY_ADDRESSES: usize = 20;
+/// How long to search for peers on a newly joined backbone subnet.
+const SUBNET_PEER_SEARCH_TIMEOUT: Duration = Duration::from_secs(120);
 
 /// Builds the network behaviour that manages the core protocols of eth2.
 /// This core behaviour is managed by `Behaviour` which adds peer management to all core
@@ -58,40 +69,145 @@ pub struct Behaviour<C: Config, TSubstream: AsyncRead + AsyncWrite> {
     #[behaviour(ignore)]
     /// The events generated by this behaviour to be consumed in the swarm poll.
     events: Vec<Libp2pEvent<C>>,
+    #[behaviour(ignore)]
+    /// Inbound bandwidth accounting.
+    bandwidth: SharedBandwidth,
+    #[behaviour(ignore)]
+    /// Inbound gossip rate limits.
+    gossip_limiter: GossipRateLimiter,
+    #[behaviour(ignore)]
+    /// Our address as observed by peers.
+    observed_addresses: ObservedAddresses,
+    #[behaviour(ignore)]
+    /// Encodings of gossip topics messages are published to.
+    gossip_encodings: Vec<Encoding>,
+    #[behaviour(ignore)]
+    /// Recently published or received gossip, to drop duplicates.
+    seen_messages: SeenMessages,
+    #[behaviour(ignore)]
+    /// Published gossip waiting for topic peers.
+    publish_queue: PublishQueue,
+    #[behaviour(ignore)]
+    /// Whether own blocks are sent to all topic peers instead of the mesh.
+    flood_publish: bool,
+    #[behaviour(ignore)]
+    /// Target number of gossipsub mesh peers per topic.
+    mesh_n: usize,
+    #[behaviour(ignore)]
+    /// Counters of own published blocks.
+    publish_stats: SharedPublishStats,
+    #[behaviour(ignore)]
+    /// Peers and message counters of gossip topics.
+    mesh_stats: SharedMeshStats,
+    #[behaviour(ignore)]
+    /// Long-lived attestation subnet subscriptions, if enabled.
+    subnet_backbone: Option<SubnetBackbone>,
 }
 
 impl<C: Config, TSubstream: AsyncRead + AsyncWrite> Behaviour<C, TSubstream> {
     pub fn new(
         local_key: &Keypair,
         net_conf: &NetworkConfig,
+        publish_stats: SharedPublishStats,
+        mesh_stats: SharedMeshStats,
+        bandwidth: SharedBandwidth,
     ) -> Result<Self, Error> {
-        let local_peer_id = local_key.public().clone().into_peer_id();
-
+        // Pings do not keep idle connections alive, but disconnect peers that
+        // stopped responding.
         let ping_config = PingConfig::new()
-            .with_timeout(Duration::from_secs(30))
-            .with_interval(Duration::from_secs(20))
-            .with_max_failures(NonZeroU32::new(2).expect("2 != 0"))
+            .with_timeout(Duration::from_secs(net_conf.ping_timeout))
+            .with_interval(Duration::from_secs(net_conf.ping_interval))
+            .with_max_failures(NonZeroU32::new(net_conf.ping_max_failures.max(1)).expect("max(1) != 0"))
             .with_keep_alive(false);
 
         let identify = Identify::new(
             "shasper/libp2p".into(),
-			crate::VERSION.into(),
+            net_conf.client_version.clone(),
             local_key.public(),
         );
 
+        let discovery = Discovery::new(local_key, net_conf)?;
+        let subnet_backbone = if net_conf.subnet_backbone {
+            Some(SubnetBackbone::new(discovery.local_enr().node_id().raw()))
+        } else {
+            None
+        };
+
+        let mut gs_config = net_conf.gs_config.clone();
+        gs_config.validation_mode = net_conf.gossip_signing.validation_mode();
+
         Ok(Behaviour {
-            rpc: RPC::new(),
-            gossipsub: Gossipsub::new(local_peer_id.clone(), net_conf.gs_config.clone()),
-            discovery: Discovery::new(local_key, net_conf)?,
+            rpc: RPC::with_protocol(RPCProtocol::new(net_conf.rpc_encodings.clone()))
+                .with_timeouts(
+                    Duration::from_secs(net_conf.idle_timeout),
+                    Duration::from_secs(net_conf.substream_timeout),
+                ),
+            gossipsub: Gossipsub::new(net_conf.gossip_signing.authenticity(local_key), gs_config),
+            discovery,
             ping: Ping::new(ping_config),
             identify,
             events: Vec::new(),
+            bandwidth,
+            gossip_limiter: GossipRateLimiter::new(net_conf.gossip_rate_limits.clone()),
+            observed_addresses: ObservedAddresses::new(),
+            gossip_encodings: net_conf.gossip_encodings.clone(),
+            seen_messages: SeenMessages::new(),
+            publish_queue: PublishQueue::new(),
+            flood_publish: net_conf.flood_publish,
+            mesh_n: net_conf.gs_config.mesh_n,
+            publish_stats,
+            mesh_stats,
+            subnet_backbone,
         })
     }
 
     pub fn discovery(&self) -> &Discovery<TSubstream> {
         &self.discovery
     }
+
+    /// Search for peers on an attestation subnet, dialing matches.
+    pub fn discover_subnet_peers(&mut self, subnet: u64, min_count: usize, deadline: Instant) {
+        self.discovery.discover_subnet_peers(subnet, min_count, deadline);
+    }
+
+    /// Account inbound bytes of a peer. Returns `false`, and notes the peer
+    /// for the peer manager, if the message should be dropped because the
+    /// peer is over its quota.
+    fn account(&mut self, peer: &PeerId, protocol: Protocol, bytes: usize) -> bool {
+        if self.bandwidth.write().expect("Lock is poisoned").record(peer, protocol, bytes as u64) {
+            debug!("Peer {:?} is over its inbound quota, dropping {:?} message", peer, protocol);
+            self.events.push(Libp2pEvent::QuotaExceeded(peer.clone()));
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Reject a gossip message received on `topic`, noting the peer for the
+    /// peer manager.
+    fn reject_gossip(
+        &mut self,
+        peer: &PeerId,
+        topic: &TopicHash,
+        typ: PubsubType,
+        rejection: GossipRejection,
+    ) {
+        debug!("Rejecting {:?} gossip message from {:?}: {}", typ, peer, rejection.reason());
+        self.mesh_stats.write().expect("Lock is poisoned").note_rejected(topic);
+        self.events.push(Libp2pEvent::GossipRejected(peer.clone(), typ, rejection));
+    }
+
+    /// Update the peer count of a gossip topic from its subscriptions.
+    fn update_topic_peers(&self, topic: &TopicHash) {
+        self.mesh_stats.write().expect("Lock is poisoned")
+            .set_peers(topic, self.publish_queue.peer_count(topic));
+    }
+
+    /// Note that we subscribed to or unsubscribed from a gossip topic.
+    fn note_local_subscription(&self, topic: &Topic, subscribed: bool) {
+        self.mesh_stats.write().expect("Lock is poisoned")
+            .note_local_subscription(&topic.hash(), subscribed);
+    }
 }
 
 // Implement the NetworkBehaviourEventProcess trait so that we can derive NetworkBehaviour for Behaviour
@@ -100,32 +216,73 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
 {
     fn inject_event(&mut self, event: GossipsubEvent) {
         match event {
-            GossipsubEvent::Message(_, gs_msg) => {
+            GossipsubEvent::Message(propagation_source, gs_msg) => {
                 trace!("Received GossipEvent");
 
-				let typ = match gs_msg.topics.iter()
-					.map(|v| PubsubType::from_gossipsub_topic_hash(v))
-					.filter(|v| v.is_some())
+				let (received_topic, typ, encoding) = match gs_msg.topics.iter()
+					.filter_map(|v| {
+						PubsubType::from_gossipsub_topic_hash(v).map(|(typ, encoding)| (v.clone(), typ, encoding))
+					})
 					.next()
 				{
-					Some(Some(typ)) => typ,
-					_ => {
+					Some(found) => found,
+					None => {
 						warn!("Unknown gossipsub type");
 						return
 					},
 				};
-				let msg = match PubsubMessage::from_ssz_data(typ, &gs_msg.data) {
+				self.mesh_stats.write().expect("Lock is poisoned")
+					.note_received(&received_topic, Instant::now());
+				if !self.account(&propagation_source, Protocol::Gossip(typ), gs_msg.data.len()) {
+					return
+				}
+				let topic = typ.gossipsub_topic(encoding).hash();
+				if self.seen_messages.insert(&topic, &gs_msg.data, Instant::now()) {
+					trace!("Dropping duplicate {:?} gossip from {:?}", typ, propagation_source);
+					self.mesh_stats.write().expect("Lock is poisoned").note_duplicate(&received_topic);
+					return
+				}
+				if self.gossip_limiter.record(&propagation_source, typ, Instant::now()) {
+					self.reject_gossip(&propagation_source, &received_topic, typ, GossipRejection::RateLimited);
+					return
+				}
+				if gs_msg.data.len() > typ.max_encoded_size(encoding) {
+					self.reject_gossip(&propagation_source, &received_topic, typ, GossipRejection::Oversized);
+					return
+				}
+				let msg = match PubsubMessage::from_encoded_data(typ, encoding, &gs_msg.data) {
 					Ok(msg) => msg,
+					Err(ssz::Error::TooLarge) => {
+						self.reject_gossip(&propagation_source, &received_topic, typ, GossipRejection::Oversized);
+						return
+					},
 					Err(_) => {
 						warn!("Uninterpretable gossipsub message");
+						self.reject_gossip(&propagation_source, &received_topic, typ, GossipRejection::Invalid);
 						return
 					},
 				};
 
                 self.events.push(Libp2pEvent::Pubsub(gs_msg.source, msg));
             }
-            GossipsubEvent::Subscribed { .. } => {}
-            GossipsubEvent::Unsubscribed { .. } => {}
+            GossipsubEvent::Subscribed { peer_id, topic } => {
+                if let Some(subnet) = attestation_subnet_of_topic(&topic) {
+                    self.events.push(Libp2pEvent::SubnetSubscription {
+                        peer: peer_id.clone(), subnet, subscribed: true,
+                    });
+                }
+                self.publish_queue.note_subscribed(peer_id, topic.clone());
+                self.update_topic_peers(&topic);
+            }
+            GossipsubEvent::Unsubscribed { peer_id, topic } => {
+                if let Some(subnet) = attestation_subnet_of_topic(&topic) {
+                    self.events.push(Libp2pEvent::SubnetSubscription {
+                        peer: peer_id.clone(), subnet, subscribed: false,
+                    });
+                }
+                self.publish_queue.note_unsubscribed(&peer_id, &topic);
+                self.update_topic_peers(&topic);
+            }
         }
     }
 }
@@ -139,10 +296,33 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
                 self.events.push(Libp2pEvent::PeerDialed(peer_id))
             }
             RPCMessage::PeerDisconnected(peer_id) => {
+                self.bandwidth.write().expect("Lock is poisoned").remove_peer(&peer_id);
+                self.gossip_limiter.remove_peer(&peer_id);
+                self.observed_addresses.remove(&peer_id);
+                self.publish_queue.note_disconnected(&peer_id);
+                let topics = self.mesh_stats.read().expect("Lock is poisoned")
+                    .topics()
+                    .map(|(topic, _)| TopicHash::from_raw(topic.clone()))
+                    .collect::<Vec<_>>();
+                for topic in topics {
+                    self.update_topic_peers(&topic);
+                }
                 self.events.push(Libp2pEvent::PeerDisconnected(peer_id))
             }
-            RPCMessage::Event(peer_id, rpc_event) => {
-                self.events.push(Libp2pEvent::RPC(peer_id, rpc_event))
+            RPCMessage::Event(peer_id, rpc_event, received) => {
+                // Responses are to our own requests, and are not held
+                // against the peer's quota.
+                let accepted = match &rpc_event {
+                    RPCEvent::Request(_, request) => self.account(
+                        &peer_id, Protocol::Rpc(request.rpc_type()), received,
+                    ),
+                    RPCEvent::Response(_, _) | RPCEvent::StreamChunk(_, _) | RPCEvent::EndStream(_) |
+                    RPCEvent::ChunkSent(_) | RPCEvent::Error(_, _) => true,
+                };
+
+                if accepted {
+                    self.events.push(Libp2pEvent::RPC(peer_id, rpc_event))
+                }
             }
         }
     }
@@ -151,8 +331,13 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
 impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess<PingEvent>
     for Behaviour<C, TSubstream>
 {
-    fn inject_event(&mut self, _event: PingEvent) {
-        // not interested in ping responses at the moment.
+    fn inject_event(&mut self, event: PingEvent) {
+        match event.result {
+            Ok(PingSuccess::Ping { rtt }) => trace!("Ping to {:?} took {:?}", event.peer, rtt),
+            Ok(PingSuccess::Pong) => (),
+            Err(PingFailure::Timeout) => debug!("Ping to {:?} timed out", event.peer),
+            Err(PingFailure::Other { error }) => debug!("Ping to {:?} failed: {}", event.peer, error),
+        }
     }
 }
 
@@ -175,8 +360,12 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
     fn inject_event(&mut self, event: IdentifyEvent) {
         match event {
 			IdentifyEvent::Received {
-                peer_id, mut info, ..
+                peer_id, mut info, observed_addr,
             } => {
+                if let Some(ip) = self.observed_addresses.note(peer_id.clone(), &observed_addr) {
+                    self.discovery.update_external_address(ip);
+                }
+
                 if info.listen_addrs.len() > MAX_IDENTIFY_ADDRESSES {
                     debug!(
                         "More than 20 addresses have been identified, truncating"
@@ -191,6 +380,12 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
 					info.listen_addrs,
 					info.protocols,
                 );
+				self.events.push(Libp2pEvent::PeerIdentified {
+					peer: peer_id.clone(),
+					agent_version: info.agent_version,
+					protocol_version: info.protocol_version,
+					listen_addrs: info.listen_addrs,
+				});
 				self.events.push(Libp2pEvent::PeerDialed(peer_id));
             },
 			IdentifyEvent::Sent { .. } => (),
@@ -209,16 +404,111 @@ impl<C: Config, TSubstream: AsyncRead + AsyncWrite> NetworkBehaviourEventProcess
 
 /// Implements the combined behaviour for the libp2p service.
 impl<C: Config, TSubstream: AsyncRead + AsyncWrite> Behaviour<C, TSubstream> {
+    /// Rotate the attestation subnet backbone subscriptions for `epoch`,
+    /// advertising the new subnets in our ENR.
+    pub fn update_subnet_backbone(&mut self, epoch: u64) {
+        let backbone = match self.subnet_backbone.as_mut() {
+            Some(backbone) => backbone,
+            None => return,
+        };
+        let (left, joined) = match backbone.update::<C>(epoch) {
+            Some(changes) => changes,
+            None => return,
+        };
+        if left.is_empty() && joined.is_empty() {
+            return
+        }
+
+        for encoding in &self.gossip_encodings {
+            for subnet in &left {
+                let topic = attestation_subnet_topic(*subnet, *encoding);
+                self.note_local_subscription(&topic, false);
+                self.gossipsub.unsubscribe(topic);
+            }
+            for subnet in &joined {
+                let topic = attestation_subnet_topic(*subnet, *encoding);
+                self.note_local_subscription(&topic, true);
+                self.gossipsub.subscribe(topic);
+            }
+        }
+        let deadline = Instant::now() + SUBNET_PEER_SEARCH_TIMEOUT;
+        for subnet in &joined {
+            self.discovery.discover_subnet_peers(*subnet, self.mesh_n, deadline);
+        }
+        self.discovery.set_attnets(attnets_bitfield(backbone.subnets()));
+        info!("Attestation subnet backbone at epoch {} is {:?}", epoch, backbone.subnets());
+    }
+
+    /// Attestation subnets we need peers on: those of the subnet backbone,
+    /// and those peers were searched for, until the search deadline.
+    pub fn needed_subnets(&self) -> HashSet<u64> {
+        let mut subnets = self.discovery.needed_subnets(Instant::now()).collect::<HashSet<_>>();
+        if let Some(backbone) = self.subnet_backbone.as_ref() {
+            subnets.extend(backbone.subnets().iter().cloned());
+        }
+        subnets
+    }
+
     /// Subscribes to a gossipsub topic.
     pub fn subscribe(&mut self, topic: Topic) -> bool {
+        self.note_local_subscription(&topic, true);
         self.gossipsub.subscribe(topic)
     }
 
-    /// Publishes a message on the pubsub (gossipsub) behaviour.
+    /// Publishes a message on the pubsub (gossipsub) behaviour, on the topic
+    /// of each gossip encoding. The message is noted as seen, so that echoes
+    /// are dropped, and is queued if no peer is subscribed to the topic yet.
+    ///
+    /// Messages published here are our own, so blocks are flood published if
+    /// enabled, reaching every topic peer without waiting for mesh hops.
     pub fn publish(&mut self, message: PubsubMessage<C>) {
-        let data = message.ssz_data();
 		let typ = PubsubType::from(&message);
-		self.gossipsub.publish(&typ.gossipsub_topic(), data);
+		let now = Instant::now();
+		for encoding in &self.gossip_encodings {
+			let data = match message.encoded_data(*encoding) {
+				Ok(data) => data,
+				Err(e) => {
+					warn!("Encoding gossip message as {:?} failed: {:?}", encoding, e);
+					continue
+				},
+			};
+			let topic = typ.gossipsub_topic(*encoding);
+			self.seen_messages.insert(&topic.hash(), &data, now);
+			let peers = self.publish_queue.peer_count(&topic.hash());
+			if peers > 0 {
+				let flood = self.flood_publish && typ == PubsubType::Block;
+				if flood {
+					self.gossipsub.flood_publish(&topic, data);
+				} else {
+					self.gossipsub.publish(&topic, data);
+				}
+				self.mesh_stats.write().expect("Lock is poisoned").note_published(&topic.hash());
+				if typ == PubsubType::Block {
+					let fanout = if flood { peers } else { peers.min(self.mesh_n) };
+					debug!("Published block on {} to {} peers", topic, fanout);
+					self.publish_stats.write().expect("Lock is poisoned").note_block(fanout, flood);
+				}
+			} else {
+				debug!("No peers on topic {}, queueing {:?} message", topic, typ);
+				self.publish_queue.push(topic, data, now);
+			}
+		}
+    }
+
+    /// Publish queued messages whose topic now has peers, in one batch.
+    pub fn retry_publish(&mut self, now: Instant) {
+		if self.publish_queue.is_empty() {
+			return
+		}
+		let (ready, dropped) = self.publish_queue.take_ready(now);
+		if dropped > 0 {
+			warn!("Dropped {} gossip messages published while no peer was on their topic", dropped);
+		}
+		for (topic, data) in ready {
+			trace!("Publishing queued message on {}", topic);
+			self.mesh_stats.write().expect("Lock is poisoned").note_published(&topic.hash());
+			self.gossipsub.publish(&topic, data);
+		}
     }
 
     /// Sends an RPC Request/Response via the RPC protocol.
@@ -16,11 +16,94 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
 use enr::Enr;
-use libp2p::gossipsub::{GossipsubConfig, GossipsubConfigBuilder};
+use network_messages::{Encoding, PubsubType};
+use crate::gossip_limit::GossipRateLimits;
+use libp2p::gossipsub::{
+    GossipsubConfig, GossipsubConfigBuilder, GossipsubMessage, MessageAuthenticity, MessageId,
+    ValidationMode,
+};
+use libp2p::core::identity::Keypair;
 use libp2p::Multiaddr;
+use libp2p::multiaddr::Protocol;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
 use std::time::Duration;
 
+/// Signing policy of gossip messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GossipSigning {
+    /// Messages carry the publishing peer, a sequence number and its
+    /// signature, as libp2p does by default.
+    Signed,
+    /// Messages carry no author, sequence number or signature, and received
+    /// messages with any of them are rejected. This is the `StrictNoSign`
+    /// policy required by the eth2 networking spec.
+    Anonymous,
+}
+
+impl GossipSigning {
+    /// Parse a signing policy from its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "signed" => Some(GossipSigning::Signed),
+            "anonymous" => Some(GossipSigning::Anonymous),
+            _ => None,
+        }
+    }
+
+    /// Authenticity of messages we publish.
+    pub fn authenticity(&self, local_key: &Keypair) -> MessageAuthenticity {
+        match self {
+            GossipSigning::Signed => MessageAuthenticity::Signed(local_key.clone()),
+            GossipSigning::Anonymous => MessageAuthenticity::Anonymous,
+        }
+    }
+
+    /// Validation of messages we receive.
+    pub fn validation_mode(&self) -> ValidationMode {
+        match self {
+            GossipSigning::Signed => ValidationMode::Strict,
+            GossipSigning::Anonymous => ValidationMode::Anonymous,
+        }
+    }
+}
+
+/// Message id domain of gossip whose data decodes.
+pub const MESSAGE_DOMAIN_VALID_SNAPPY: [u8; 4] = [1, 0, 0, 0];
+/// Message id domain of gossip whose data does not decode.
+pub const MESSAGE_DOMAIN_INVALID_SNAPPY: [u8; 4] = [0, 0, 0, 0];
+/// Length of gossip message ids, in bytes.
+const MESSAGE_ID_LEN: usize = 20;
+
+/// Gossip message id computed from the message content, as the eth2
+/// networking spec requires. Anonymous messages have no author and sequence
+/// number to derive the default id from.
+///
+/// The id hashes the decompressed data, so that it does not depend on how
+/// the publisher compressed it. Data that does not decompress is hashed as
+/// is under a distinct domain, so that it cannot collide with valid data.
+pub fn gossip_message_id(message: &GossipsubMessage) -> MessageId {
+    let decoded = message.topics.iter()
+        .filter_map(PubsubType::from_gossipsub_topic_hash)
+        .next()
+        .and_then(|(typ, encoding)| encoding.decode(&message.data, typ.max_size()).ok());
+
+    let mut hasher = Sha256::new();
+    match decoded {
+        Some(data) => {
+            hasher.input(&MESSAGE_DOMAIN_VALID_SNAPPY);
+            hasher.input(&data);
+        },
+        None => {
+            hasher.input(&MESSAGE_DOMAIN_INVALID_SNAPPY);
+            hasher.input(&message.data);
+        },
+    }
+    let hash = hasher.result();
+    MessageId(base64::encode_config(&hash[..MESSAGE_ID_LEN], base64::URL_SAFE_NO_PAD))
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(default)]
 /// Network configuration for lighthouse.
@@ -37,24 +120,107 @@ pub struct Config {
     /// UDP port that discovery listens on.
     pub discovery_port: u16,
 
+    /// IPv6 address to additionally listen on, for dual-stack operation.
+    pub listen_address_v6: Option<std::net::Ipv6Addr>,
+
+    /// The TCP port that libp2p listens on for IPv6.
+    pub libp2p_port_v6: u16,
+
+    /// The IPv6 address to broadcast to peers. Defaults to the IPv6 listen
+    /// address if that is not unspecified.
+    pub discovery_address_v6: Option<std::net::Ipv6Addr>,
+
     /// Target number of connected peers.
     pub max_peers: usize,
 
-    /// Gossipsub configuration parameters.
+    /// Minimum number of peers we dialed to keep among `max_peers`. Inbound
+    /// peers are limited to the remaining slots, so that peers connecting to
+    /// us cannot eclipse the node.
+    pub min_outbound_peers: usize,
+
+    /// Gossipsub configuration parameters. The validation mode is set from
+    /// `gossip_signing`.
     #[serde(skip)]
     pub gs_config: GossipsubConfig,
 
+    /// Signing policy of gossip messages.
+    pub gossip_signing: GossipSigning,
+
     /// List of nodes to initially connect to.
     pub boot_nodes: Vec<Enr>,
 
     /// List of libp2p nodes to initially connect to.
     pub libp2p_nodes: Vec<Multiaddr>,
 
-    /// Client version
+    /// Trusted peers, redialed on disconnect and never banned. Addresses
+    /// should contain the `/p2p` peer id.
+    pub trusted_peers: Vec<Multiaddr>,
+
+    /// Client version, advertised as the identify agent version.
     pub client_version: String,
 
+    /// Encodings supported for RPC, in order of preference. The first one
+    /// supported by a peer is negotiated.
+    #[serde(skip)]
+    pub rpc_encodings: Vec<Encoding>,
+
+    /// Encodings of gossip topics to subscribe and publish to. Messages are
+    /// published on every topic, so that peers supporting only one of the
+    /// encodings still receive them.
+    #[serde(skip)]
+    pub gossip_encodings: Vec<Encoding>,
+
+    /// Default gossip topics to subscribe to, each on every gossip encoding.
+    /// Messages of other types are still published, but not received.
+    #[serde(skip)]
+    pub gossip_types: Vec<PubsubType>,
+
     /// List of extra topics to initially subscribe to as strings.
     pub topics: Vec<String>,
+
+    /// Directory to persist the network key in. A new key is generated on
+    /// every start if not set.
+    pub network_dir: Option<PathBuf>,
+
+    /// Maximum number of bytes accepted from a single peer per quota window.
+    pub inbound_quota: u64,
+
+    /// Length of the inbound quota window, in seconds.
+    pub inbound_quota_window: u64,
+
+    /// Map the libp2p and discovery ports on the gateway using UPnP.
+    pub upnp: bool,
+
+    /// Maximum number of gossip messages per topic accepted from a single
+    /// peer.
+    #[serde(skip)]
+    pub gossip_rate_limits: GossipRateLimits,
+
+    /// Flood publish own blocks, sending them to every peer subscribed to
+    /// the block topic rather than only to mesh peers, to minimize their
+    /// propagation latency.
+    pub flood_publish: bool,
+
+    /// Stay subscribed to the attestation subnets derived from our node id,
+    /// taking part in the attestation subnet backbone.
+    pub subnet_backbone: bool,
+
+    /// Time in seconds without any RPC substream after which a connection
+    /// is no longer kept alive.
+    pub idle_timeout: u64,
+
+    /// Time in seconds an RPC substream may go without progress before it is
+    /// dropped.
+    pub substream_timeout: u64,
+
+    /// Interval in seconds between pings sent to each peer.
+    pub ping_interval: u64,
+
+    /// Time in seconds to wait for a ping response.
+    pub ping_timeout: u64,
+
+    /// Number of consecutive failed pings after which a peer is disconnected.
+    pub ping_max_failures: u32,
 }
 
 impl Default for Config {
@@ -65,17 +231,39 @@ impl Default for Config {
             libp2p_port: 9000,
             discovery_address: "127.0.0.1".parse().expect("valid ip address"),
             discovery_port: 9000,
+            listen_address_v6: None,
+            libp2p_port_v6: 9001,
+            discovery_address_v6: None,
             max_peers: 10,
+            min_outbound_peers: 3,
             // Note: The topics by default are sent as plain strings. Hashes are an optional
             // parameter.
             gs_config: GossipsubConfigBuilder::new()
                 .max_transmit_size(1_048_576)
                 .heartbeat_interval(Duration::from_secs(20))
+                .message_id_fn(gossip_message_id)
                 .build(),
+            gossip_signing: GossipSigning::Anonymous,
             boot_nodes: vec![],
             libp2p_nodes: vec![],
-            client_version: crate::VERSION.to_string(),
+            trusted_peers: vec![],
+            client_version: format!("shasper/{}", env!("CARGO_PKG_VERSION")),
+            rpc_encodings: Encoding::all(),
+            gossip_encodings: vec![Encoding::Ssz],
+            gossip_types: PubsubType::all(),
             topics: Vec::new(),
+            network_dir: None,
+            inbound_quota: 64 * 1024 * 1024,
+            inbound_quota_window: 60,
+            upnp: false,
+            gossip_rate_limits: GossipRateLimits::default(),
+            flood_publish: true,
+            subnet_backbone: true,
+            idle_timeout: 30,
+            substream_timeout: 10,
+            ping_interval: 20,
+            ping_timeout: 30,
+            ping_max_failures: 2,
         }
     }
 }
@@ -85,4 +273,24 @@ impl Config {
     pub fn new() -> Self {
         Config::default()
     }
+
+    /// Multiaddrs libp2p listens on.
+    pub fn listen_multiaddrs(&self) -> Vec<Multiaddr> {
+        let mut addrs = vec![
+            Multiaddr::from(self.listen_address).with(Protocol::Tcp(self.libp2p_port))
+        ];
+        if let Some(listen_address_v6) = self.listen_address_v6 {
+            addrs.push(
+                Multiaddr::from(listen_address_v6).with(Protocol::Tcp(self.libp2p_port_v6))
+            );
+        }
+        addrs
+    }
+
+    /// IPv6 address advertised to peers, if any.
+    pub fn advertised_address_v6(&self) -> Option<std::net::Ipv6Addr> {
+        self.discovery_address_v6.or_else(|| {
+            self.listen_address_v6.filter(|address| !address.is_unspecified())
+        })
+    }
 }
@@ -21,6 +21,7 @@
 //!
 
 use crate::{Error, NetworkConfig};
+use crate::subnets::attnets_has_subnet;
 use futures01::prelude::*;
 use libp2p::core::{identity::Keypair, ConnectedPoint, Multiaddr, PeerId};
 use libp2p::discv5::{Discv5, Discv5Event};
@@ -28,7 +29,9 @@ use libp2p::enr::{Enr, EnrBuilder, NodeId};
 use libp2p::multiaddr::Protocol;
 use libp2p::swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters, ProtocolsHandler};
 use log::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::mpsc::Receiver;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_timer::Delay;
@@ -37,6 +40,27 @@ use tokio_timer::Delay;
 const MAX_TIME_BETWEEN_PEER_SEARCHES: u64 = 60;
 /// Initial delay between peer searches.
 const INITIAL_SEARCH_DELAY: u64 = 5;
+/// Maximum number of remembered IPv6 addresses or ENRs of discovered peers.
+const MAX_EXTRA_ADDRESSES: usize = 1024;
+/// ENR key of the attestation subnet bitfield.
+pub const ENR_ATTNETS_KEY: &str = "attnets";
+/// Number of attestation subnets.
+pub const ATTESTATION_SUBNET_COUNT: u64 = 64;
+
+/// Whether an ENR advertises the attestation subnet, according to its
+/// `attnets` bitvector.
+pub fn enr_has_subnet(enr: &Enr, subnet: u64) -> bool {
+    enr.get(ENR_ATTNETS_KEY)
+        .map(|attnets| attnets_has_subnet(attnets, subnet))
+        .unwrap_or(false)
+}
+
+/// Targeted search for peers on an attestation subnet.
+struct SubnetQuery {
+    subnet: u64,
+    min_count: usize,
+    deadline: Instant,
+}
 
 /// Lighthouse discovery behaviour. This provides peer management and discovery using the Discv5
 /// libp2p protocol.
@@ -44,9 +68,19 @@ pub struct Discovery<TSubstream> {
     /// The peers currently connected to libp2p streams.
     connected_peers: HashSet<PeerId>,
 
+    /// The connected peers we dialed.
+    outbound_peers: HashSet<PeerId>,
+
     /// The target number of connected peers on the libp2p interface.
     max_peers: usize,
 
+    /// The minimum number of connected peers we dialed. Peers are searched
+    /// for and dialed below it even if the target number of peers is reached.
+    min_outbound_peers: usize,
+
+    /// Trusted peers, not counted towards the target number of peers.
+    trusted_peers: HashSet<PeerId>,
+
     /// The delay between peer discovery searches.
     peer_discovery_delay: Delay,
 
@@ -59,8 +93,36 @@ pub struct Discovery<TSubstream> {
     //TODO: Improve NAT handling limit the above restriction
     tcp_port: u16,
 
+    /// The TCP port for libp2p over IPv6.
+    tcp_port_v6: u16,
+
     /// The discovery behaviour used to discover new peers.
     discovery: Discv5<TSubstream>,
+
+    /// External addresses learned from UPnP, if enabled.
+    upnp: Option<Receiver<Ipv4Addr>>,
+
+    /// External addresses yet to be reported to the swarm.
+    pending_addresses: VecDeque<Multiaddr>,
+
+    /// Whether we listen on IPv6, and can dial IPv6 addresses of peers.
+    dial_v6: bool,
+
+    /// IPv6 addresses of discovered peers, which discv5 does not track.
+    addresses_v6: HashMap<PeerId, Multiaddr>,
+
+    /// ENRs of discovered peers, persisted in the peer database.
+    enrs: HashMap<PeerId, Enr>,
+
+    /// Active subnet peer searches.
+    subnet_queries: Vec<SubnetQuery>,
+
+    /// Subnets peers were searched for, until the deadline of the search.
+    /// Peers on them are kept when pruning, even once the search is done.
+    needed_subnets: HashMap<u64, Instant>,
+
+    /// Peers found by subnet searches, yet to be dialed.
+    pending_dials: VecDeque<PeerId>,
 }
 
 impl<TSubstream> Discovery<TSubstream> {
@@ -75,7 +137,7 @@ impl<TSubstream> Discovery<TSubstream> {
         debug!("Discv5 Node ID Initialised {}", local_enr.node_id());
 
         let mut discovery = Discv5::new(local_enr, local_key.clone(), config.listen_address, false)
-            .map_err(|e| format!("Discv5 service failed. Error: {:?}", e))?;
+            .map_err(|e| Error::Discovery(format!("{:?}", e)))?;
 
         // Add bootnodes to routing table
         for bootnode_enr in config.boot_nodes.clone() {
@@ -88,11 +150,27 @@ impl<TSubstream> Discovery<TSubstream> {
 
         Ok(Self {
             connected_peers: HashSet::new(),
+            outbound_peers: HashSet::new(),
             max_peers: config.max_peers,
+            min_outbound_peers: config.min_outbound_peers,
+            trusted_peers: crate::trusted::trusted_peer_ids(&config.trusted_peers),
             peer_discovery_delay: Delay::new(Instant::now()),
             past_discovery_delay: INITIAL_SEARCH_DELAY,
             tcp_port: config.libp2p_port,
+            tcp_port_v6: config.libp2p_port_v6,
             discovery,
+            upnp: if config.upnp {
+                Some(crate::nat::spawn_upnp(config.libp2p_port, config.discovery_port))
+            } else {
+                None
+            },
+            pending_addresses: VecDeque::new(),
+            dial_v6: config.listen_address_v6.is_some(),
+            addresses_v6: HashMap::new(),
+            enrs: HashMap::new(),
+            subnet_queries: Vec::new(),
+            needed_subnets: HashMap::new(),
+            pending_dials: VecDeque::new(),
         })
     }
 
@@ -107,6 +185,112 @@ impl<TSubstream> Discovery<TSubstream> {
         self.find_peers();
     }
 
+    /// Update the external address advertised in the local ENR, and report it
+    /// to the swarm.
+    pub fn update_external_address(&mut self, ip: IpAddr) {
+        let octets = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+        let key = match ip {
+            IpAddr::V4(_) => "ip",
+            IpAddr::V6(_) => "ip6",
+        };
+        match self.discovery.enr_insert(key, octets) {
+            Ok(_) => info!("ENR address updated (IP: {})", ip),
+            Err(e) => warn!("Could not update ENR address: {:?}", e),
+        }
+
+        let tcp_port = match ip {
+            IpAddr::V4(_) => self.tcp_port,
+            IpAddr::V6(_) => self.tcp_port_v6,
+        };
+        let mut address = Multiaddr::from(ip);
+        address.push(Protocol::Tcp(tcp_port));
+        self.pending_addresses.push_back(address);
+    }
+
+    /// Advertise the attestation subnets we are subscribed to in the local
+    /// ENR.
+    pub fn set_attnets(&mut self, attnets: Vec<u8>) {
+        match self.discovery.enr_insert(ENR_ATTNETS_KEY, attnets) {
+            Ok(_) => debug!("ENR attnets updated"),
+            Err(e) => warn!("Could not update ENR attnets: {:?}", e),
+        }
+    }
+
+    /// ENR of a discovered peer, if known.
+    pub fn enr_of(&self, peer_id: &PeerId) -> Option<&Enr> {
+        self.enrs.get(peer_id)
+    }
+
+    /// Search for peers on an attestation subnet until `min_count` connected
+    /// peers advertise it, or until `deadline`. Matching peers are dialed
+    /// even if the target number of peers is reached.
+    pub fn discover_subnet_peers(&mut self, subnet: u64, min_count: usize, deadline: Instant) {
+        if subnet >= ATTESTATION_SUBNET_COUNT {
+            warn!("Ignoring search for peers on invalid subnet {}", subnet);
+            return
+        }
+        let now = Instant::now();
+        self.needed_subnets.retain(|_, needed_until| *needed_until > now);
+        let needed_until = self.needed_subnets.entry(subnet).or_insert(deadline);
+        *needed_until = std::cmp::max(*needed_until, deadline);
+
+        if self.subnet_peer_count(subnet) >= min_count {
+            return
+        }
+
+        debug!("Searching for peers on subnet {}", subnet);
+        match self.subnet_queries.iter_mut().find(|query| query.subnet == subnet) {
+            Some(query) => {
+                query.min_count = std::cmp::max(query.min_count, min_count);
+                query.deadline = std::cmp::max(query.deadline, deadline);
+            },
+            None => self.subnet_queries.push(SubnetQuery { subnet, min_count, deadline }),
+        }
+
+        // Known peers may already be on the subnet.
+        let known = self.enrs.iter()
+            .filter(|(peer_id, enr)| {
+                !self.connected_peers.contains(peer_id) && enr_has_subnet(enr, subnet)
+            })
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect::<Vec<_>>();
+        self.pending_dials.extend(known);
+        self.discovery.find_node(NodeId::random());
+    }
+
+    /// Subnets peers were searched for, whose search deadline is not past.
+    pub fn needed_subnets(&self, now: Instant) -> impl Iterator<Item=u64> + '_ {
+        self.needed_subnets.iter()
+            .filter(move |(_, needed_until)| **needed_until > now)
+            .map(|(subnet, _)| *subnet)
+    }
+
+    /// Number of connected peers advertising an attestation subnet.
+    pub fn subnet_peer_count(&self, subnet: u64) -> usize {
+        self.connected_peers.iter()
+            .filter(|peer_id| {
+                self.enrs.get(peer_id).map(|enr| enr_has_subnet(enr, subnet)).unwrap_or(false)
+            })
+            .count()
+    }
+
+    /// Drop subnet searches that are satisfied or past their deadline.
+    fn prune_subnet_queries(&mut self, now: Instant) {
+        let queries = std::mem::replace(&mut self.subnet_queries, Vec::new());
+        for query in queries {
+            if self.subnet_peer_count(query.subnet) >= query.min_count {
+                debug!("Found enough peers on subnet {}", query.subnet);
+            } else if query.deadline <= now {
+                debug!("Search for peers on subnet {} expired", query.subnet);
+            } else {
+                self.subnet_queries.push(query);
+            }
+        }
+    }
+
     /// Add an Enr to the routing table of the discovery mechanism.
     pub fn add_enr(&mut self, enr: Enr) {
         self.discovery.add_enr(enr);
@@ -117,6 +301,23 @@ impl<TSubstream> Discovery<TSubstream> {
         self.connected_peers.len()
     }
 
+    /// The current number of connected libp2p peers, excluding trusted peers.
+    fn untrusted_connected_peers(&self) -> usize {
+        self.connected_peers.difference(&self.trusted_peers).count()
+    }
+
+    /// Whether we dialed a connected peer.
+    pub fn is_outbound(&self, peer_id: &PeerId) -> bool {
+        self.outbound_peers.contains(peer_id)
+    }
+
+    /// Whether more peers should be dialed, either to reach the target
+    /// number of peers or the minimum number of outbound peers.
+    fn needs_peers(&self) -> bool {
+        self.untrusted_connected_peers() < self.max_peers ||
+            self.outbound_peers.difference(&self.trusted_peers).count() < self.min_outbound_peers
+    }
+
     /// The current number of connected libp2p peers.
     pub fn connected_peer_set(&self) -> &HashSet<PeerId> {
         &self.connected_peers
@@ -157,24 +358,43 @@ where
 
     fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
         // Let discovery track possible known peers.
-        self.discovery.addresses_of_peer(peer_id)
+        let mut addresses = self.discovery.addresses_of_peer(peer_id);
+        if let Some(address) = self.addresses_v6.get(peer_id) {
+            addresses.push(address.clone());
+        }
+        if addresses.is_empty() {
+            // Peers found by subnet searches may not be in the routing table.
+            if let Some((ip, tcp)) = self.enrs.get(peer_id).and_then(|enr| Some((enr.ip()?, enr.tcp()?))) {
+                addresses.push(Multiaddr::from(ip).with(Protocol::Tcp(tcp)));
+            }
+        }
+        addresses
     }
 
-    fn inject_connected(&mut self, peer_id: PeerId, _endpoint: ConnectedPoint) {
+    fn inject_connected(&mut self, peer_id: PeerId, endpoint: ConnectedPoint) {
+        if endpoint.is_dialer() {
+            self.outbound_peers.insert(peer_id.clone());
+        }
         self.connected_peers.insert(peer_id);
     }
 
     fn inject_disconnected(&mut self, peer_id: &PeerId, _endpoint: ConnectedPoint) {
         self.connected_peers.remove(peer_id);
+        self.outbound_peers.remove(peer_id);
     }
 
     fn inject_replaced(
         &mut self,
-        _peer_id: PeerId,
+        peer_id: PeerId,
         _closed: ConnectedPoint,
-        _opened: ConnectedPoint,
+        opened: ConnectedPoint,
     ) {
-        // discv5 doesn't implement
+        // discv5 doesn't implement, only track the new direction
+        if opened.is_dialer() {
+            self.outbound_peers.insert(peer_id);
+        } else {
+            self.outbound_peers.remove(&peer_id);
+        }
     }
 
     fn inject_node_event(
@@ -194,11 +414,33 @@ where
             Self::OutEvent,
         >,
     > {
+        // pick up external addresses from the gateway
+        let upnp_addresses = self.upnp.as_ref()
+            .map(|upnp| upnp.try_iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        for ip in upnp_addresses {
+            self.update_external_address(IpAddr::V4(ip));
+        }
+
+        if let Some(address) = self.pending_addresses.pop_front() {
+            return Async::Ready(NetworkBehaviourAction::ReportObservedAddr { address });
+        }
+
+        if !self.subnet_queries.is_empty() {
+            self.prune_subnet_queries(Instant::now());
+        }
+        while let Some(peer_id) = self.pending_dials.pop_front() {
+            if !self.connected_peers.contains(&peer_id) {
+                debug!("Dialing subnet peer {:?}", peer_id);
+                return Async::Ready(NetworkBehaviourAction::DialPeer { peer_id });
+            }
+        }
+
         // search for peers if it is time
         loop {
             match self.peer_discovery_delay.poll() {
                 Ok(Async::Ready(_)) => {
-                    if self.connected_peers.len() < self.max_peers {
+                    if self.needs_peers() {
                         self.find_peers();
                     }
                 }
@@ -214,9 +456,32 @@ where
             match self.discovery.poll(params) {
                 Async::Ready(NetworkBehaviourAction::GenerateEvent(event)) => {
                     match event {
-                        Discv5Event::Discovered(_enr) => {
+                        Discv5Event::Discovered(enr) => {
+                            let peer_id = enr.peer_id();
+                            if !self.connected_peers.contains(&peer_id)
+                                && self.subnet_queries.iter().any(|query| enr_has_subnet(&enr, query.subnet))
+                            {
+                                self.pending_dials.push_back(peer_id.clone());
+                            }
+                            if self.enrs.len() < MAX_EXTRA_ADDRESSES
+                                || self.enrs.contains_key(&peer_id)
+                            {
+                                self.enrs.insert(peer_id, enr.clone());
+                            }
                             // not concerned about FINDNODE results, rather the result of an entire
-                            // query.
+                            // query, other than remembering IPv6 addresses to dial.
+                            if self.dial_v6 {
+                                if let (Some(ip6), Some(tcp6)) = (enr.ip6(), enr.tcp6()) {
+                                    let peer_id = enr.peer_id();
+                                    if self.addresses_v6.len() < MAX_EXTRA_ADDRESSES
+                                        || self.addresses_v6.contains_key(&peer_id)
+                                    {
+                                        let address = Multiaddr::from(ip6)
+                                            .with(Protocol::Tcp(tcp6));
+                                        self.addresses_v6.insert(peer_id, address);
+                                    }
+                                }
+                            }
                         }
                         Discv5Event::SocketUpdated(socket) => {
                             info!("Address updated (IP: {})", socket.ip());
@@ -232,9 +497,13 @@ where
                             if closer_peers.is_empty() {
                                 debug!("Discovery random query found no peers");
                             }
+                            // Keep searching while subnet queries are unsatisfied.
+                            if !self.subnet_queries.is_empty() {
+                                self.discovery.find_node(NodeId::random());
+                            }
                             for peer_id in closer_peers {
                                 // if we need more peers, attempt a connection
-                                if self.connected_peers.len() < self.max_peers
+                                if self.needs_peers()
                                     && self.connected_peers.get(&peer_id).is_none()
                                 {
                                     debug!("Peer discovered {:?}", peer_id);
@@ -264,16 +533,23 @@ where
 fn load_enr(
     local_key: &Keypair,
     config: &NetworkConfig,
-) -> Result<Enr, String> {
+) -> Result<Enr, Error> {
     // Build the local ENR.
     // Note: Discovery should update the ENR record's IP to the external IP as seen by the
     // majority of our peers.
-    let local_enr = EnrBuilder::new("v4")
+    let mut builder = EnrBuilder::new("v4");
+    builder
         .ip(config.discovery_address)
         .tcp(config.libp2p_port)
-        .udp(config.discovery_port)
+        .udp(config.discovery_port);
+    if let Some(address_v6) = config.advertised_address_v6() {
+        builder
+            .ip(IpAddr::V6(address_v6))
+            .tcp6(config.libp2p_port_v6);
+    }
+    let local_enr = builder
         .build(&local_key)
-        .map_err(|e| format!("Could not build Local ENR: {:?}", e))?;
+        .map_err(|e| Error::LocalEnr(format!("{:?}", e)))?;
 
     Ok(local_enr)
 }
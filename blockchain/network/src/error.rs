@@ -14,29 +14,54 @@
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
+use libp2p::Multiaddr;
+
 #[derive(Debug)]
+#[non_exhaustive]
+/// Network errors.
 pub enum Error {
-	IO(std::io::Error),
+	/// I/O error.
+	Io(std::io::Error),
+	/// Libp2p transport error.
 	Libp2p(Box<dyn std::error::Error + Sync + Send + 'static>),
-	Other(String),
+	/// Listening on the address failed.
+	Listen(Multiaddr),
+	/// Discovery service failed to start.
+	Discovery(String),
+	/// Local ENR could not be built.
+	LocalEnr(String),
+	/// Peer address or ENR is invalid.
+	InvalidPeer(String),
+	/// Network service shut down.
+	Shutdown,
 }
 
 impl std::fmt::Display for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{:?}", self)
+		match self {
+			Error::Io(e) => write!(f, "I/O error: {}", e),
+			Error::Libp2p(e) => write!(f, "Libp2p error: {}", e),
+			Error::Listen(address) => write!(f, "Unable to listen on {}", address),
+			Error::Discovery(e) => write!(f, "Discovery service failed: {}", e),
+			Error::LocalEnr(e) => write!(f, "Could not build local ENR: {}", e),
+			Error::InvalidPeer(e) => write!(f, "Invalid peer: {}", e),
+			Error::Shutdown => write!(f, "Network service shut down"),
+		}
 	}
 }
 
-impl std::error::Error for Error { }
-
-impl From<std::io::Error> for Error {
-	fn from(err: std::io::Error) -> Error {
-		Error::IO(err)
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			Error::Libp2p(e) => Some(e.as_ref()),
+			_ => None,
+		}
 	}
 }
 
-impl From<String> for Error {
-	fn from(s: String) -> Error {
-		Error::Other(s)
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Error {
+		Error::Io(err)
 	}
 }
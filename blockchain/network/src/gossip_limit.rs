@@ -0,0 +1,116 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use network_messages::PubsubType;
+
+/// Maximum number of gossip messages per topic accepted from a single peer
+/// within one window.
+#[derive(Clone, Debug)]
+pub struct GossipRateLimits {
+	/// Length of the rate limit window.
+	pub window: Duration,
+	/// Maximum number of messages per pubsub type within one window.
+	pub max_messages: HashMap<PubsubType, u32>,
+}
+
+impl Default for GossipRateLimits {
+	fn default() -> Self {
+		let max_messages = PubsubType::all().into_iter().map(|typ| {
+			let max = match typ {
+				PubsubType::Block => 32,
+				PubsubType::Attestation => 4096,
+				PubsubType::VoluntaryExit => 128,
+				PubsubType::ProposerSlashing => 16,
+				PubsubType::AttesterSlashing => 16,
+			};
+			(typ, max)
+		}).collect();
+
+		Self { window: Duration::from_secs(12), max_messages }
+	}
+}
+
+#[derive(Default)]
+struct Window {
+	start: Option<Instant>,
+	count: u32,
+}
+
+/// Gossip message rate limiter per peer and pubsub type.
+pub struct GossipRateLimiter {
+	limits: GossipRateLimits,
+	windows: HashMap<(PeerId, PubsubType), Window>,
+}
+
+impl GossipRateLimiter {
+	/// Create a new rate limiter with the given limits.
+	pub fn new(limits: GossipRateLimits) -> Self {
+		Self { limits, windows: HashMap::new() }
+	}
+
+	/// Record a message from a peer. Returns whether the peer is over its
+	/// rate limit for the pubsub type, in which case the message should be
+	/// dropped.
+	pub fn record(&mut self, peer: &PeerId, typ: PubsubType, now: Instant) -> bool {
+		let max = match self.limits.max_messages.get(&typ) {
+			Some(max) => *max,
+			None => return false,
+		};
+		let window_length = self.limits.window;
+		let window = self.windows.entry((peer.clone(), typ)).or_default();
+
+		match window.start {
+			Some(start) if now.duration_since(start) < window_length => (),
+			_ => {
+				window.start = Some(now);
+				window.count = 0;
+			},
+		}
+
+		window.count = window.count.saturating_add(1);
+		window.count > max
+	}
+
+	/// Forget a disconnected peer.
+	pub fn remove_peer(&mut self, peer: &PeerId) {
+		self.windows.retain(|(p, _), _| p != peer);
+	}
+}
+
+/// Reason a gossip message was rejected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GossipRejection {
+	/// The message is larger than allowed for its topic.
+	Oversized,
+	/// The peer sent too many messages on the topic.
+	RateLimited,
+	/// The message failed to decode.
+	Invalid,
+}
+
+impl GossipRejection {
+	/// Reason given to the peer manager.
+	pub fn reason(&self) -> &'static str {
+		match self {
+			GossipRejection::Oversized => "oversized gossip message",
+			GossipRejection::RateLimited => "gossip rate limit exceeded",
+			GossipRejection::Invalid => "invalid gossip message",
+		}
+	}
+}
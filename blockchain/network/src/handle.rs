@@ -0,0 +1,208 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use beacon::Config;
+use blockchain::import::SharedBlockImporter;
+use futures::channel::{mpsc, oneshot};
+use std::time::Instant;
+use libp2p::PeerId;
+use libp2p::gossipsub::Topic;
+use network_messages::PubsubMessage;
+use shasper_runtime::Block;
+use log::*;
+use crate::Error;
+use crate::rpc::{RPCRequest, RPCResponse, RequestId};
+use crate::requests::{ResponseSender, ResponseReceiver};
+
+/// Commands for outbound actions, processed by the network service.
+#[derive(Debug)]
+pub enum NetworkMessage<C: Config> {
+	/// Publish a message on the gossip topic of each gossip encoding.
+	Publish(PubsubMessage<C>),
+	/// Send an RPC request to a peer.
+	SendRpcRequest(PeerId, RequestId, RPCRequest),
+	/// Send an RPC request to a peer, routing its response to the sender.
+	Request(PeerId, RPCRequest, ResponseSender<C>),
+	/// Respond to an RPC request of a peer.
+	SendRpcResponse(PeerId, RequestId, RPCResponse<C>),
+	/// Disconnect a peer, without banning it.
+	DisconnectPeer(PeerId),
+	/// Subscribe to an additional gossip topic.
+	Subscribe(Topic),
+	/// Search for peers on an attestation subnet until enough are
+	/// connected, or until the deadline.
+	DiscoverSubnetPeers {
+		/// Attestation subnet.
+		subnet: u64,
+		/// Number of connected peers wanted on the subnet.
+		min_count: usize,
+		/// Time after which the search is abandoned.
+		deadline: Instant,
+	},
+}
+
+/// Receiving end of commands sent through a `NetworkSender`, drained by the
+/// network service.
+pub type NetworkReceiver<C> = mpsc::UnboundedReceiver<NetworkMessage<C>>;
+
+/// Sender of commands to the network service.
+pub struct NetworkSender<C: Config> {
+	sender: mpsc::UnboundedSender<NetworkMessage<C>>,
+}
+
+impl<C: Config> Clone for NetworkSender<C> {
+	fn clone(&self) -> Self {
+		Self { sender: self.sender.clone() }
+	}
+}
+
+impl<C: Config> NetworkSender<C> {
+	/// Create a new sender. The returned receiver is to be passed to the
+	/// network service.
+	pub fn new() -> (Self, NetworkReceiver<C>) {
+		let (sender, receiver) = mpsc::unbounded();
+		(Self { sender }, receiver)
+	}
+
+	/// Send a command to the network service.
+	pub fn send(&self, message: NetworkMessage<C>) -> Result<(), Error> {
+		self.sender.unbounded_send(message).map_err(|_| Error::Shutdown)
+	}
+
+	/// Publish a gossip message.
+	pub fn publish(&self, message: PubsubMessage<C>) -> Result<(), Error> {
+		self.send(NetworkMessage::Publish(message))
+	}
+
+	/// Send an RPC request to a peer.
+	pub fn send_rpc_request(
+		&self,
+		peer: PeerId,
+		request_id: RequestId,
+		request: RPCRequest,
+	) -> Result<(), Error> {
+		self.send(NetworkMessage::SendRpcRequest(peer, request_id, request))
+	}
+
+	/// Send an RPC request to a peer, returning a receiver resolving to its
+	/// response, or to an error on timeout, disconnection or stream reset.
+	pub fn request(
+		&self,
+		peer: PeerId,
+		request: RPCRequest,
+	) -> Result<ResponseReceiver<C>, Error> {
+		let (sender, receiver) = oneshot::channel();
+		self.send(NetworkMessage::Request(peer, request, sender))?;
+		Ok(receiver)
+	}
+
+	/// Search for peers on an attestation subnet, to be connected before
+	/// `deadline`, such as the slot of an aggregation duty.
+	pub fn discover_subnet_peers(
+		&self,
+		subnet: u64,
+		min_count: usize,
+		deadline: Instant,
+	) -> Result<(), Error> {
+		self.send(NetworkMessage::DiscoverSubnetPeers { subnet, min_count, deadline })
+	}
+
+	/// Respond to an RPC request of a peer.
+	pub fn send_rpc_response(
+		&self,
+		peer: PeerId,
+		request_id: RequestId,
+		response: RPCResponse<C>,
+	) -> Result<(), Error> {
+		self.send(NetworkMessage::SendRpcResponse(peer, request_id, response))
+	}
+
+	/// Disconnect a peer.
+	pub fn disconnect_peer(&self, peer: PeerId) -> Result<(), Error> {
+		self.send(NetworkMessage::DisconnectPeer(peer))
+	}
+
+	/// Subscribe to a gossip topic.
+	pub fn subscribe(&self, topic: Topic) -> Result<(), Error> {
+		self.send(NetworkMessage::Subscribe(topic))
+	}
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+/// Block publication errors.
+pub enum PublishError<E> {
+	/// Importing the block locally failed, so it was not published.
+	Import(E),
+	/// Network service is no longer running.
+	Shutdown,
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for PublishError<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			PublishError::Import(e) => write!(f, "Local block import failed: {}", e),
+			PublishError::Shutdown => write!(f, "Network service shut down"),
+		}
+	}
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for PublishError<E> {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			PublishError::Import(e) => Some(e),
+			PublishError::Shutdown => None,
+		}
+	}
+}
+
+/// Handle for publishing locally produced messages to the network.
+pub struct NetworkHandle<C: Config, I> {
+	importer: I,
+	sender: NetworkSender<C>,
+}
+
+impl<C: Config, I: Clone> Clone for NetworkHandle<C, I> {
+	fn clone(&self) -> Self {
+		Self { importer: self.importer.clone(), sender: self.sender.clone() }
+	}
+}
+
+impl<C: Config, I: SharedBlockImporter<Block=Block<C>>> NetworkHandle<C, I> {
+	/// Create a new handle importing published blocks with `importer`. The
+	/// returned receiver is to be passed to the network service.
+	pub fn new(importer: I) -> (Self, NetworkReceiver<C>) {
+		let (sender, receiver) = NetworkSender::new();
+		(Self { importer, sender }, receiver)
+	}
+
+	/// Sender of commands to the network service.
+	pub fn sender(&self) -> &NetworkSender<C> {
+		&self.sender
+	}
+
+	/// Import a locally built block, so that the next proposer builds on
+	/// it, and publish it on the beacon block topic. The block is only
+	/// published if it is imported successfully.
+	pub fn publish_block(&self, block: Block<C>) -> Result<(), PublishError<I::Error>> {
+		let slot = block.slot();
+		self.importer.import_block(block.clone()).map_err(PublishError::Import)?;
+		self.sender.publish(PubsubMessage::Block(block.0))
+			.map_err(|_| PublishError::Shutdown)?;
+		debug!("Queued block at slot {} for publication", slot);
+		Ok(())
+	}
+}
@@ -1,27 +1,28 @@
 use core::marker::PhantomData;
 use core::cmp;
-use blockchain::{Auxiliary, Block as BlockT, AsExternalities};
+use blockchain::{Auxiliary, AsExternalities};
 use blockchain::backend::{Store, SharedCommittable, ChainQuery, ImportLock};
 use beacon::{Config, types::BeaconBlock, primitives::H256};
 use network_messages::{HelloMessage, BeaconBlocksRequest};
-use shasper_runtime::{StateExternalities, Block};
+use shasper_runtime::{StateExternalities, Block, SlotQuery, HeaderQuery, StoreIterators, SharedChainInfo};
 use log::*;
 
 pub struct Handler<C, Ba> {
 	import_lock: ImportLock,
 	backend: Ba,
+	chain_info: SharedChainInfo,
 	_marker: PhantomData<C>,
 }
 
 impl<C, Ba> Handler<C, Ba> where
 	C: Config,
-	Ba: Store<Block=Block<C>> + SharedCommittable + ChainQuery,
+	Ba: Store<Block=Block<C>> + SharedCommittable + ChainQuery + SlotQuery + HeaderQuery,
 	Ba::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
 	Ba::Auxiliary: Auxiliary<Block<C>>,
 {
-	pub fn new(backend: Ba, import_lock: ImportLock) -> Self {
+	pub fn new(backend: Ba, import_lock: ImportLock, chain_info: SharedChainInfo) -> Self {
 		Self {
-			import_lock, backend,
+			import_lock, backend, chain_info,
 			_marker: PhantomData,
 		}
 	}
@@ -29,10 +30,11 @@ impl<C, Ba> Handler<C, Ba> where
 	pub fn status(&self) -> HelloMessage {
 		let head_hash = self.backend.head();
 		let head_state = self.backend.state_at(&head_hash).unwrap();
-		let head_slot = head_state.state().slot;
+		let head_slot = head_state.slot();
 		let finalized_root = head_state.state().finalized_checkpoint.root;
 		let finalized_epoch = head_state.state().finalized_checkpoint.epoch;
-		let fork_version = head_state.state().fork.current_version.clone();
+		let fork_version = self.chain_info.read().expect("Lock is poisoned")
+			.fork.current_version;
 
 		HelloMessage {
 			fork_version,
@@ -43,10 +45,15 @@ impl<C, Ba> Handler<C, Ba> where
 		}
 	}
 
+	/// Whether a peer's status is of the same chain as ours.
+	pub fn is_relevant(&self, hello: &HelloMessage) -> bool {
+		hello.fork_version == self.chain_info.read().expect("Lock is poisoned").fork.current_version
+	}
+
 	pub fn head_request(&self, count: usize) -> BeaconBlocksRequest {
 		let head_hash = self.backend.head();
-		let head_state = self.backend.state_at(&head_hash).unwrap();
-		let head_slot = head_state.state().slot;
+		let head_slot = self.backend.get_header(&head_hash).unwrap()
+			.expect("Head always exists; qed").slot;
 
 		info!("Chain head: {:?} (slot: {})", head_hash, head_slot);
 
@@ -59,18 +66,11 @@ impl<C, Ba> Handler<C, Ba> where
 	}
 
 	fn blocks_by_depth_no_lock(&self, start_depth: usize, count: usize) -> Vec<BeaconBlock<C>> {
-		let mut ret = Vec::new();
-		for d in start_depth..(start_depth + count) {
-			match self.backend.lookup_canon_depth(d as usize) {
-				Ok(Some(hash)) => {
-					let block = self.backend.block_at(&hash)
-						.expect("Found hash cannot fail");
-					ret.push(block);
-				},
-				_ => break,
-			}
-		}
-		ret.into_iter().map(Into::into).collect()
+		self.backend.iter_blocks_by_depth(start_depth, count)
+			.take_while(Result::is_ok)
+			.filter_map(Result::ok)
+			.map(Into::into)
+			.collect()
 	}
 
 	pub fn blocks_by_depth(&self, start_depth: usize, count: usize) -> Vec<BeaconBlock<C>> {
@@ -79,37 +79,67 @@ impl<C, Ba> Handler<C, Ba> where
 	}
 
 	pub fn blocks_by_slot(
-		&self, mut start_hash: H256, mut start_slot: u64, count: usize
+		&self, start_hash: H256, start_slot: u64, count: usize
 	) -> Vec<BeaconBlock<C>> {
 		let _ = self.import_lock.lock();
 
-		if !self.backend.contains(&start_hash).unwrap() || start_hash == H256::default() {
-			self.blocks_by_depth_no_lock(1, count)
-		} else {
-			if !self.backend.is_canon(&start_hash).unwrap() {
-				return Vec::new();
-			}
-
-			let mut start_state = match self.backend.state_at(&start_hash) {
-				Ok(state) => state,
-				Err(_) => return Vec::new(),
-			};
-
-			while start_state.state().slot > start_slot {
-				start_hash = match self.backend.block_at(&start_hash).unwrap().parent_id() {
-					Some(id) => id,
-					None => break,
-				};
-
-				start_state = match self.backend.state_at(&start_hash) {
-					Ok(state) => state,
-					Err(_) => return Vec::new(),
-				};
-			}
-
-			let start_depth = self.backend.depth_at(&start_hash).unwrap();
-
-			self.blocks_by_depth_no_lock(start_depth, count)
+		match self.range_start_no_lock(start_hash, start_slot) {
+			Some(start_depth) => self.blocks_by_depth_no_lock(start_depth, count),
+			None => Vec::new(),
+		}
+	}
+
+	/// Roots of the blocks a range request of `count` blocks starting at
+	/// `start_slot` of the chain of `start_hash` is served, oldest first, or
+	/// `None` if nothing is served. The roots are those of the canonical
+	/// chain at the time of the call, so that a response streamed from them
+	/// stays on one chain across reorgs.
+	pub fn range_roots(&self, start_hash: H256, start_slot: u64, count: usize) -> Option<Vec<H256>> {
+		let _ = self.import_lock.lock();
+		let start_depth = self.range_start_no_lock(start_hash, start_slot)?;
+		if count == 0 {
+			return Some(Vec::new())
+		}
+
+		let head_depth = self.backend.depth_at(&self.backend.head()).ok()?;
+		if start_depth > head_depth {
+			return Some(Vec::new())
+		}
+		let end_depth = cmp::min(start_depth.saturating_add(count - 1), head_depth);
+		let end_hash = self.backend.lookup_canon_depth(end_depth).ok()??;
+
+		let mut roots = self.backend.iter_ancestor_roots(end_hash)
+			.take(end_depth - start_depth + 1)
+			.take_while(Result::is_ok)
+			.filter_map(Result::ok)
+			.map(|(root, _)| root)
+			.collect::<Vec<_>>();
+		roots.reverse();
+		Some(roots)
+	}
+
+	/// Depth a range request starts at, or `None` if nothing is served,
+	/// including when the backend fails.
+	fn range_start_no_lock(&self, start_hash: H256, start_slot: u64) -> Option<usize> {
+		if !self.backend.contains(&start_hash).ok()? || start_hash == H256::default() {
+			return Some(1)
+		}
+		if !self.backend.is_canon(&start_hash).ok()? {
+			return None
+		}
+
+		let (head_slot, _) = self.backend.slot_at(&self.backend.head()).ok()?;
+		let end_slot = cmp::min(start_slot, head_slot).saturating_add(1);
+		let (_, start_hash) = self.backend.iter_canon_roots(0..end_slot).next_back()?.ok()?;
+
+		self.backend.depth_at(&start_hash).ok()
+	}
+
+	/// Block of a root, if stored.
+	pub fn block(&self, root: &H256) -> Option<BeaconBlock<C>> {
+		if !self.backend.contains(root).unwrap() {
+			return None
 		}
+		self.backend.block_at(root).ok().map(Into::into)
 	}
 }
@@ -21,9 +21,23 @@ mod error;
 mod rpc;
 mod service;
 mod handler;
+mod peer_manager;
+mod bandwidth;
+mod nat;
+mod trusted;
+mod sync_status;
+mod handle;
+mod requests;
+mod arrival;
+mod gossip_limit;
+mod peer_db;
+mod publish;
+mod mesh;
+mod subnets;
+mod serve_queue;
 
 pub use behaviour::Behaviour;
-pub use config::Config as NetworkConfig;
+pub use config::{Config as NetworkConfig, GossipSigning};
 pub use libp2p::enr::Enr;
 pub use libp2p::multiaddr;
 pub use libp2p::Multiaddr;
@@ -34,20 +48,52 @@ pub use libp2p::{
 pub use error::Error;
 pub use service::Service;
 pub use handler::Handler;
+pub use peer_manager::{ConnectionDirection, PeerManager, PeerInfo, SharedPeerManager};
+pub use peer_db::{PeerDb, PeerRecord, MAX_PERSISTED_PEERS, MAX_SEEDED_PEERS};
+pub use discovery::{enr_has_subnet, ENR_ATTNETS_KEY, ATTESTATION_SUBNET_COUNT};
+pub use subnets::{compute_subscribed_subnets, EPOCHS_PER_SUBNET_SUBSCRIPTION, SUBNETS_PER_NODE};
+pub use bandwidth::{BandwidthTracker, Protocol as BandwidthProtocol, Quota, SharedBandwidth};
+pub use trusted::parse_trusted_peer;
+pub use network_messages::{Encoding, PubsubType};
+pub use sync_status::{SyncState, SyncStatus, SyncProgress, SharedSyncState};
+pub use handle::{NetworkHandle, NetworkSender, NetworkMessage, NetworkReceiver, PublishError};
+pub use rpc::{RPCRequest, RPCResponse, RPCError, RequestId};
+pub use requests::{RequestManager, RequestError, RequestResult, ResponseReceiver, REQUEST_TIMEOUT};
+pub use gossip_limit::{GossipRateLimits, GossipRateLimiter, GossipRejection};
+pub use arrival::{
+	ArrivalStats, ArrivalKind, ArrivalHistogram, SlotArrivals, SharedArrivalStats,
+	ARRIVAL_BUCKETS_MS, MAX_TRACKED_SLOTS,
+};
+pub use publish::{PublishStats, SharedPublishStats};
+pub use mesh::{MeshStats, TopicStats, SharedMeshStats, RATE_WINDOW};
+pub use serve_queue::{
+	MAX_QUEUED_RANGE_REQUESTS, MAX_QUEUED_RANGE_REQUESTS_PER_PEER, MAX_ACTIVE_STREAMS, MAX_RANGE_RESPONSE_BLOCKS,
+};
 
 use log::*;
 use core::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
 use libp2p::identity;
-use futures01::{Async, stream::Stream};
+use futures01::{Async, Future as _, stream::Stream};
 use futures::{Poll, StreamExt as _};
 use blockchain::{Auxiliary, AsExternalities};
 use blockchain::backend::{Store, SharedCommittable, ChainQuery, ImportLock};
 use blockchain::import::BlockImporter;
 use blockchain_network::sync::{NetworkSync, SyncConfig, SyncEvent};
 use beacon::Config;
-use shasper_runtime::{Block, StateExternalities};
+use beacon::types::Attestation;
+use shasper_runtime::{Block, StateExternalities, SlotQuery, HeaderQuery, SharedChainInfo};
 use network_messages::{HelloMessage, PubsubMessage};
-use crate::rpc::{RPCEvent, RPCRequest, RPCResponse};
+use crate::rpc::RPCEvent;
+use crate::peer_db::PEER_DB_SAVE_INTERVAL;
+use crate::serve_queue::ServeQueue;
+use crate::peer_manager::{
+	INVALID_RESPONSE_PENALTY, QUOTA_EXCEEDED_PENALTY, GOSSIP_RATE_LIMITED_PENALTY, INVALID_GOSSIP_PENALTY,
+};
 
 pub const VERSION: &str = "v0.1";
 
@@ -60,18 +106,54 @@ pub enum Libp2pEvent<C: Config> {
     PeerDialed(PeerId),
     /// A peer has disconnected.
     PeerDisconnected(PeerId),
+    /// Received identify information of a peer.
+    PeerIdentified {
+        /// Identified peer.
+        peer: PeerId,
+        /// Agent version of the peer.
+        agent_version: String,
+        /// Protocol version of the peer.
+        protocol_version: String,
+        /// Listen addresses of the peer.
+        listen_addrs: Vec<Multiaddr>,
+    },
     /// Received pubsub message.
     Pubsub(PeerId, PubsubMessage<C>),
+    /// A peer exceeded its inbound bandwidth quota.
+    QuotaExceeded(PeerId),
+    /// A gossip message from a peer was rejected.
+    GossipRejected(PeerId, PubsubType, GossipRejection),
+    /// A peer subscribed to or unsubscribed from an attestation subnet.
+    SubnetSubscription {
+        /// Subscribing peer.
+        peer: PeerId,
+        /// Attestation subnet.
+        subnet: u64,
+        /// Whether the peer subscribed, rather than unsubscribed.
+        subscribed: bool,
+    },
 }
 
+/// Run the network service until `shutdown` is set. Returns once the
+/// service and its runtime have stopped.
 pub fn start_network_simple_sync<C, Ba, I>(
 	backend: Ba,
 	import_lock: ImportLock,
 	importer: I,
-	config: NetworkConfig,
+	mut config: NetworkConfig,
+	peer_manager: SharedPeerManager,
+	sync_state: SharedSyncState,
+	chain_info: SharedChainInfo,
+	arrival_stats: SharedArrivalStats,
+	publish_stats: SharedPublishStats,
+	mesh_stats: SharedMeshStats,
+	bandwidth: SharedBandwidth,
+	attestation_sender: Option<SyncSender<Attestation<C>>>,
+	mut network_receiver: NetworkReceiver<C>,
+	shutdown: &'static AtomicBool,
 ) -> Result<(), Error> where
 	C: Config,
-	Ba: Store<Block=Block<C>> + SharedCommittable + ChainQuery + Send + Sync + 'static,
+	Ba: Store<Block=Block<C>> + SharedCommittable + ChainQuery + SlotQuery + HeaderQuery + Send + Sync + 'static,
 	Ba::Block: Unpin + Send + Sync,
 	Ba::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
 	Ba::Auxiliary: Auxiliary<Block<C>> + Unpin,
@@ -88,8 +170,10 @@ pub fn start_network_simple_sync<C, Ba, I>(
 		request_timeout: 4,
 	};
 
-	let handler = Handler::<C, Ba>::new(backend, import_lock);
+	let genesis_time = chain_info.read().expect("Lock is poisoned").genesis_time;
+	let handler = Handler::<C, Ba>::new(backend, import_lock, chain_info);
 	let head_status = handler.status();
+	sync_state.write().expect("Lock is poisoned").note_head(head_status.head_slot);
 	let mut sync = NetworkSync::<PeerId, HelloMessage, I>::new(
 		head_status,
 		importer,
@@ -97,39 +181,230 @@ pub fn start_network_simple_sync<C, Ba, I>(
 		sync_config
 	);
 
-	let mut service = Service::new(config)?;
+	peer_manager.write().expect("Lock is poisoned")
+		.set_trusted(trusted::trusted_peer_ids(&config.trusted_peers));
+
+	let peer_db = config.network_dir.as_ref().map(|dir| PeerDb::new(dir));
+	if let Some(peer_db) = peer_db.as_ref() {
+		let records = peer_db.load();
+		let mut peer_manager = peer_manager.write().expect("Lock is poisoned");
+		peer_manager.import(&records);
+
+		let mut seeded = 0;
+		for record in records.iter().filter(|record| record.is_good()).take(MAX_SEEDED_PEERS) {
+			let peer = match PeerId::from_str(&record.peer_id) {
+				Ok(peer) => peer,
+				Err(_) => continue,
+			};
+			if let Some(enr) = record.enr.as_ref().and_then(|enr| Enr::from_str(enr).ok()) {
+				config.boot_nodes.push(enr);
+			}
+			config.libp2p_nodes.extend(
+				record.addresses.iter().filter_map(|address| Multiaddr::from_str(address).ok())
+			);
+			peer_manager.note_dialing(&peer);
+			seeded += 1;
+		}
+		info!("Loaded {} known peers, dialing {}", records.len(), seeded);
+	}
+	let max_peers = config.max_peers;
+	let min_outbound_peers = config.min_outbound_peers;
+	let mut service = Service::new(config, publish_stats, mesh_stats, bandwidth)?;
 
 	let mut listening = false;
+	let mut requests = RequestManager::<C>::new();
+	// Sync batch requests awaiting a response, by peer.
+	let mut batches = HashMap::<PeerId, Instant>::new();
+	let mut peer_db_saved = Instant::now();
+	let mut serve_queue = ServeQueue::new();
 
 	let poll = futures::future::poll_fn::<Result<(), ()>, _>(move |ctx| {
+		// Sync's update interval wakes this future at least once a second.
+		if shutdown.load(Ordering::SeqCst) {
+			info!("Stopping network service");
+			return Poll::Ready(Ok(()))
+		}
+
+		let now = Instant::now();
+		requests.expire(now);
+		batches.retain(|peer, sent| {
+			if now.duration_since(*sent) < REQUEST_TIMEOUT {
+				true
+			} else {
+				debug!("Sync batch request to {:?} timed out", peer);
+				peer_manager.write().expect("Lock is poisoned").note_rpc_failure(peer);
+				false
+			}
+		});
+		if let Some(peer_db) = peer_db.as_ref() {
+			if now.duration_since(peer_db_saved) >= PEER_DB_SAVE_INTERVAL {
+				peer_db_saved = now;
+				let records = peer_manager.read().expect("Lock is poisoned").records();
+				if let Err(e) = peer_db.save(records) {
+					warn!("Could not save peer database: {}", e);
+				}
+			}
+		}
+
+		service.swarm.update_subnet_backbone(subnets::current_epoch::<C>(genesis_time));
+
+		loop {
+			match network_receiver.poll_next_unpin(ctx) {
+				Poll::Pending | Poll::Ready(None) => break,
+				Poll::Ready(Some(NetworkMessage::Publish(message))) => {
+					trace!("Publishing local message {:?}", message);
+					service.swarm.publish(message);
+				},
+				Poll::Ready(Some(NetworkMessage::SendRpcRequest(peer, request_id, request))) => {
+					service.swarm.send_rpc(peer, RPCEvent::Request(request_id, request));
+				},
+				Poll::Ready(Some(NetworkMessage::Request(peer, request, sender))) => {
+					let request_id = requests.register(peer.clone(), sender, Instant::now());
+					service.swarm.send_rpc(peer, RPCEvent::Request(request_id, request));
+				},
+				Poll::Ready(Some(NetworkMessage::SendRpcResponse(peer, request_id, response))) => {
+					service.swarm.send_rpc(peer, RPCEvent::Response(request_id, response));
+				},
+				Poll::Ready(Some(NetworkMessage::DisconnectPeer(peer))) => {
+					debug!("Disconnecting peer {:?}", peer);
+					// Banning closes all connections to the peer, lift it
+					// right away so that the peer may reconnect later.
+					libp2p::Swarm::ban_peer_id(&mut service.swarm, peer.clone());
+					libp2p::Swarm::unban_peer_id(&mut service.swarm, peer);
+				},
+				Poll::Ready(Some(NetworkMessage::DiscoverSubnetPeers { subnet, min_count, deadline })) => {
+					service.swarm.discover_subnet_peers(subnet, min_count, deadline);
+				},
+				Poll::Ready(Some(NetworkMessage::Subscribe(topic))) => {
+					if service.swarm.subscribe(topic.clone()) {
+						info!("Subscribed to topic {}", topic);
+					} else {
+						warn!("Could not subscribe to topic {}", topic);
+					}
+				},
+			}
+		}
+
 		loop {
 			match service.poll().expect("Error while polling swarm") {
 				Async::Ready(Some(message)) => {
 					match message {
 						Libp2pEvent::PeerDialed(peer) => {
 							trace!("Peer noted to be dialed: {:?}", peer);
+							{
+								let mut peer_manager = peer_manager.write().expect("Lock is poisoned");
+								let direction = if service.swarm.discovery().is_outbound(&peer) {
+									ConnectionDirection::Outbound
+								} else {
+									ConnectionDirection::Inbound
+								};
+								peer_manager.note_connected(&peer, direction);
+								if let Some(enr) = service.swarm.discovery().enr_of(&peer) {
+									peer_manager.note_enr(&peer, enr.to_base64());
+									peer_manager.note_attnets(
+										&peer,
+										enr.get(ENR_ATTNETS_KEY).map(|attnets| attnets.to_vec()).unwrap_or_default(),
+									);
+								}
+								let needed_subnets = service.swarm.needed_subnets();
+								for pruned in peer_manager.peers_to_prune(
+									max_peers, min_outbound_peers, &needed_subnets, Instant::now(),
+								) {
+									debug!(
+										"Pruning {:?} peer {:?} over the peer limit",
+										peer_manager.peer(&pruned).and_then(|info| info.direction), pruned,
+									);
+									libp2p::Swarm::ban_peer_id(&mut service.swarm, pruned.clone());
+									libp2p::Swarm::unban_peer_id(&mut service.swarm, pruned);
+								}
+							}
 							sync.note_connected(peer);
 						},
 						Libp2pEvent::PeerDisconnected(peer) => {
 							trace!("Peer noted to disconnect: {:?}", peer);
+							peer_manager.write().expect("Lock is poisoned").note_disconnected(&peer);
+							requests.on_disconnected(&peer);
+							serve_queue.remove_peer(&peer);
+							if batches.remove(&peer).is_some() {
+								peer_manager.write().expect("Lock is poisoned").note_rpc_failure(&peer);
+							}
+							sync_state.write().expect("Lock is poisoned").note_disconnected(&peer);
 							sync.note_disconnected(peer);
 						},
+						Libp2pEvent::SubnetSubscription { peer, subnet, subscribed } => {
+							peer_manager.write().expect("Lock is poisoned")
+								.note_subnet_subscription(&peer, subnet, subscribed);
+						},
+						Libp2pEvent::PeerIdentified { peer, agent_version, protocol_version, listen_addrs } => {
+							peer_manager.write().expect("Lock is poisoned")
+								.note_identified(&peer, agent_version, protocol_version, listen_addrs);
+						},
+						Libp2pEvent::QuotaExceeded(peer) => {
+							let ban = peer_manager.write().expect("Lock is poisoned")
+								.downscore(&peer, QUOTA_EXCEEDED_PENALTY, "inbound quota exceeded");
+							if ban {
+								libp2p::Swarm::ban_peer_id(&mut service.swarm, peer.clone());
+								sync.note_disconnected(peer);
+							}
+						},
+						Libp2pEvent::GossipRejected(peer, _, rejection) => {
+							let penalty = match rejection {
+								GossipRejection::RateLimited => GOSSIP_RATE_LIMITED_PENALTY,
+								GossipRejection::Oversized | GossipRejection::Invalid => INVALID_GOSSIP_PENALTY,
+							};
+							let ban = peer_manager.write().expect("Lock is poisoned")
+								.downscore(&peer, penalty, rejection.reason());
+							if ban {
+								libp2p::Swarm::ban_peer_id(&mut service.swarm, peer.clone());
+								sync.note_disconnected(peer);
+							}
+						},
 						Libp2pEvent::Pubsub(peer, message) => {
-							warn!("Unhandled pubsub message {:?}, {:?}", peer, message);
+							match &message {
+								PubsubMessage::Block(block) => arrival_stats.write()
+									.expect("Lock is poisoned")
+									.record(ArrivalKind::Block, block.slot, &peer),
+								PubsubMessage::Attestation(attestation) => arrival_stats.write()
+									.expect("Lock is poisoned")
+									.record(ArrivalKind::Attestation, attestation.data.slot, &peer),
+								_ => (),
+							}
+							match message {
+								PubsubMessage::Attestation(attestation) => {
+									if let Some(sender) = attestation_sender.as_ref() {
+										match sender.try_send(attestation) {
+											Ok(()) => (),
+											Err(TrySendError::Full(_)) =>
+												debug!("Attestation queue is full, dropping gossip attestation"),
+											Err(TrySendError::Disconnected(_)) =>
+												debug!("Attestation receiver is gone, dropping gossip attestation"),
+										}
+									}
+								},
+								message => warn!("Unhandled pubsub message {:?}, {:?}", peer, message),
+							}
 						},
 						Libp2pEvent::RPC(peer, event) => {
 							trace!("Received RPC event {:?}, {:?}", peer, event);
+							let event = match event {
+								RPCEvent::Response(id, response) => match requests.on_response(&peer, id, response) {
+									Some(response) => RPCEvent::Response(id, response),
+									None => continue,
+								},
+								RPCEvent::Error(id, error) => match requests.on_error(&peer, id, error) {
+									Some(error) => RPCEvent::Error(id, error),
+									None => continue,
+								},
+								event => event,
+							};
 							match event {
 								RPCEvent::Request(request_id, RPCRequest::BeaconBlocks(request)) => {
-									service.swarm.send_rpc(peer, RPCEvent::Response(
-										request_id, RPCResponse::BeaconBlocks(
-											handler.blocks_by_slot(
-												request.head_block_root,
-												request.start_slot,
-												1, // TODO: request.count as usize,
-											)
-										)
-									));
+									if !serve_queue.push(peer.clone(), request_id, request) {
+										debug!("Rejecting range request of {:?}, serve queue is full", peer);
+										service.swarm.send_rpc(peer, RPCEvent::Response(
+											request_id, rpc::rate_limited_response(),
+										));
+									}
 								},
 								RPCEvent::Request(request_id, RPCRequest::Hello(hello)) => {
 									service.swarm.send_rpc(peer.clone(), RPCEvent::Response(
@@ -137,17 +412,57 @@ pub fn start_network_simple_sync<C, Ba, I>(
 											handler.status()
 										)
 									));
+									if !handler.is_relevant(&hello) {
+										debug!("Ignoring status of peer {:?} on fork {:?}", peer, hello.fork_version);
+										continue
+									}
+									sync_state.write().expect("Lock is poisoned")
+										.note_peer_head(peer.clone(), hello.head_slot);
 									sync.note_peer_status(peer, hello);
 								},
 								RPCEvent::Response(_, RPCResponse::Hello(hello)) => {
+									if !handler.is_relevant(&hello) {
+										debug!("Ignoring status of peer {:?} on fork {:?}", peer, hello.fork_version);
+										continue
+									}
+									sync_state.write().expect("Lock is poisoned")
+										.note_peer_head(peer.clone(), hello.head_slot);
 									sync.note_peer_status(peer, hello);
 								},
 								RPCEvent::Response(_, RPCResponse::BeaconBlocks(blocks)) => {
+									if let Some(sent) = batches.remove(&peer) {
+										peer_manager.write().expect("Lock is poisoned")
+											.note_rpc_success(&peer, sent.elapsed());
+									}
 									sync.note_blocks(
 										blocks.into_iter().map(Into::into).collect(),
 										Some(peer)
 									);
 								},
+								RPCEvent::Error(_, RPCError::Codec(reason)) => {
+									if batches.remove(&peer).is_some() {
+										peer_manager.write().expect("Lock is poisoned").note_rpc_failure(&peer);
+									}
+									let ban = peer_manager.write().expect("Lock is poisoned")
+										.downscore(&peer, INVALID_RESPONSE_PENALTY, &reason);
+									if ban {
+										libp2p::Swarm::ban_peer_id(&mut service.swarm, peer.clone());
+										sync.note_disconnected(peer);
+									}
+								},
+								RPCEvent::Error(id, error) => {
+									if batches.remove(&peer).is_some() {
+										peer_manager.write().expect("Lock is poisoned").note_rpc_failure(&peer);
+									}
+									warn!("Unhandled RPC error {:?}, {:?}: {:?}", peer, id, error);
+								},
+								RPCEvent::ChunkSent(request_id) => {
+									if let Some(event) = serve_queue.next_event(
+										&peer, request_id, |root| handler.block(root),
+									) {
+										service.swarm.send_rpc(peer, event);
+									}
+								},
 								event => {
 									warn!("Unhandled RPC message {:?}, {:?}", peer, event);
 								},
@@ -167,12 +482,33 @@ pub fn start_network_simple_sync<C, Ba, I>(
 			}
 		}
 
+		for (peer, request_id) in serve_queue.prune(now) {
+			debug!("Ending range response to {:?}, the peer stopped reading it", peer);
+			service.swarm.send_rpc(peer, RPCEvent::EndStream(request_id));
+		}
+		while let Some((peer, request_id, request)) = serve_queue.pop() {
+			let count = request.count.min(MAX_RANGE_RESPONSE_BLOCKS) as usize;
+			match handler.range_roots(request.head_block_root, request.start_slot, count) {
+				Some(roots) => {
+					serve_queue.start(peer.clone(), request_id, roots);
+					if let Some(event) = serve_queue.next_event(
+						&peer, request_id, |root| handler.block(root),
+					) {
+						service.swarm.send_rpc(peer, event);
+					}
+				},
+				None => service.swarm.send_rpc(peer, RPCEvent::EndStream(request_id)),
+			}
+		}
+
 		loop {
 			match sync.poll_next_unpin(ctx) {
 				Poll::Pending | Poll::Ready(None) => break,
 				Poll::Ready(Some(SyncEvent::QueryStatus)) => {
 					trace!("Sync requested status query");
-					sync.note_status(handler.status());
+					let status = handler.status();
+					sync_state.write().expect("Lock is poisoned").note_head(status.head_slot);
+					sync.note_status(status);
 				},
 				Poll::Ready(Some(SyncEvent::QueryPeerStatus(peer))) => {
 					trace!("Sync requested peer status query to {:?}", peer);
@@ -183,9 +519,30 @@ pub fn start_network_simple_sync<C, Ba, I>(
 				},
 				Poll::Ready(Some(SyncEvent::QueryBlocks(peer))) => {
 					trace!("Sync requested blocks query to {:?}", peer);
+					let request = handler.head_request(50);
+					// Serve the batch from the best idle peer that has it,
+					// rather than the one sync happened to pick.
+					let busy = batches.keys().cloned().collect::<HashSet<_>>();
+					let peer = {
+						let sync_state = sync_state.read().expect("Lock is poisoned");
+						peer_manager.read().expect("Lock is poisoned")
+							.select_sync_peer(
+								sync_state.peers_ahead_of(request.start_slot)
+									.chain(Some(&peer)),
+								&busy,
+							)
+							.unwrap_or(peer)
+					};
+					debug!("Requesting blocks from slot {} from {:?}", request.start_slot, peer);
+					batches.insert(peer.clone(), Instant::now());
+					sync_state.write().expect("Lock is poisoned").note_request(
+						peer.clone(),
+						request.start_slot,
+						request.start_slot + request.count * request.step,
+					);
 					service.swarm.send_rpc(peer, RPCEvent::Request(
 						0,
-						RPCRequest::BeaconBlocks(handler.head_request(50))
+						RPCRequest::BeaconBlocks(request)
 					));
 				},
 			}
@@ -194,7 +551,9 @@ pub fn start_network_simple_sync<C, Ba, I>(
 		Poll::Pending
 	});
 
-	tokio::run(futures::compat::Compat::new(poll));
+	let mut runtime = tokio::runtime::Runtime::new()?;
+	let result = runtime.block_on(futures::compat::Compat::new(poll));
+	let _ = runtime.shutdown_now().wait();
 
-	Err(Error::Other("Shutdown".to_string()))
+	result.map_err(|()| Error::Shutdown)
 }
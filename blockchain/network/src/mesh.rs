@@ -0,0 +1,211 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Health of the gossip topics.
+//!
+//! For every topic, the peers subscribed to it, their churn and the
+//! messages received, dropped as duplicates, rejected and published are
+//! counted, so that under-peered topics, which silently cause missed
+//! attestations, are visible in metrics and the admin API.
+//!
+//! Gossipsub does not expose its mesh, so mesh peers of a topic are
+//! estimated as its subscribed peers, capped at the target mesh size.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use libp2p::gossipsub::TopicHash;
+use serde::Serialize;
+
+/// Window over which message rates are computed.
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Gossip topic statistics shared between the network thread and metrics
+/// writers.
+pub type SharedMeshStats = Arc<RwLock<MeshStats>>;
+
+/// Statistics of a single gossip topic.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct TopicStats {
+	/// Whether we are subscribed to the topic.
+	pub subscribed: bool,
+	/// Connected peers subscribed to the topic.
+	pub peers: u64,
+	/// Estimated mesh peers of the topic.
+	pub mesh_peers: u64,
+	/// Whether we are subscribed to the topic with fewer mesh peers than
+	/// gossipsub aims for.
+	pub under_peered: bool,
+	/// Number of times a peer subscribed to the topic.
+	pub peers_joined: u64,
+	/// Number of times a peer unsubscribed from the topic or disconnected.
+	pub peers_left: u64,
+	/// Messages received on the topic.
+	pub received: u64,
+	/// Received messages dropped as duplicates.
+	pub duplicates: u64,
+	/// Received messages rejected as rate limited, oversized or invalid.
+	pub rejected: u64,
+	/// Own messages published on the topic.
+	pub published: u64,
+	/// Messages received per minute, over the last complete rate window.
+	pub received_per_minute: u64,
+	#[serde(skip)]
+	window_received: u64,
+}
+
+/// Statistics of all gossip topics that we are subscribed to or that
+/// peers are subscribed to.
+#[derive(Clone, Debug)]
+pub struct MeshStats {
+	topics: BTreeMap<String, TopicStats>,
+	mesh_n: u64,
+	mesh_n_low: u64,
+	window_started: Instant,
+}
+
+impl MeshStats {
+	/// Create empty statistics, for gossipsub aiming for `mesh_n` and
+	/// accepting no fewer than `mesh_n_low` mesh peers per topic.
+	pub fn new(mesh_n: usize, mesh_n_low: usize) -> Self {
+		Self {
+			topics: BTreeMap::new(),
+			mesh_n: mesh_n as u64,
+			mesh_n_low: mesh_n_low as u64,
+			window_started: Instant::now(),
+		}
+	}
+
+	/// Statistics of a topic, if it is known.
+	pub fn topic(&self, topic: &str) -> Option<&TopicStats> {
+		self.topics.get(topic)
+	}
+
+	/// Statistics of all known topics, by topic.
+	pub fn topics(&self) -> impl Iterator<Item=(&String, &TopicStats)> {
+		self.topics.iter()
+	}
+
+	/// Topics we are subscribed to that have too few mesh peers.
+	pub fn under_peered(&self) -> impl Iterator<Item=&String> {
+		self.topics.iter()
+			.filter(|(_, stats)| stats.under_peered)
+			.map(|(topic, _)| topic)
+	}
+
+	fn entry(&mut self, topic: &TopicHash) -> &mut TopicStats {
+		self.topics.entry(topic.as_str().to_string()).or_default()
+	}
+
+	/// Note that we subscribed to or unsubscribed from a topic.
+	pub fn note_local_subscription(&mut self, topic: &TopicHash, subscribed: bool) {
+		let (mesh_n, mesh_n_low) = (self.mesh_n, self.mesh_n_low);
+		let stats = self.entry(topic);
+		stats.subscribed = subscribed;
+		update_mesh(stats, mesh_n, mesh_n_low);
+	}
+
+	/// Set the number of peers subscribed to a topic, counting the change as
+	/// churn.
+	pub fn set_peers(&mut self, topic: &TopicHash, peers: usize) {
+		let (mesh_n, mesh_n_low) = (self.mesh_n, self.mesh_n_low);
+		let stats = self.entry(topic);
+		let peers = peers as u64;
+		if peers > stats.peers {
+			stats.peers_joined += peers - stats.peers;
+		} else {
+			stats.peers_left += stats.peers - peers;
+		}
+		stats.peers = peers;
+		update_mesh(stats, mesh_n, mesh_n_low);
+	}
+
+	/// Note a message received on a topic.
+	pub fn note_received(&mut self, topic: &TopicHash, now: Instant) {
+		self.roll(now);
+		let stats = self.entry(topic);
+		stats.received += 1;
+		stats.window_received += 1;
+	}
+
+	/// Note a received message dropped as a duplicate.
+	pub fn note_duplicate(&mut self, topic: &TopicHash) {
+		self.entry(topic).duplicates += 1;
+	}
+
+	/// Note a received message rejected.
+	pub fn note_rejected(&mut self, topic: &TopicHash) {
+		self.entry(topic).rejected += 1;
+	}
+
+	/// Note an own message published on a topic.
+	pub fn note_published(&mut self, topic: &TopicHash) {
+		self.entry(topic).published += 1;
+	}
+
+	/// Complete the rate window if it is over, so that message rates are up
+	/// to date even on topics without recent messages.
+	pub fn roll(&mut self, now: Instant) {
+		let elapsed = now.duration_since(self.window_started);
+		if elapsed < RATE_WINDOW {
+			return
+		}
+		let elapsed_ms = elapsed.as_millis() as u64;
+		for stats in self.topics.values_mut() {
+			stats.received_per_minute = stats.window_received * 60_000 / elapsed_ms;
+			stats.window_received = 0;
+		}
+		self.window_started = now;
+	}
+
+	/// Statistics in the Prometheus text format, labelled by topic.
+	pub fn encode(&self) -> String {
+		let mut out = String::new();
+		let mut metric = |name: &str, kind: &str, help: &str, value: &dyn Fn(&TopicStats) -> u64| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			for (topic, stats) in &self.topics {
+				let _ = writeln!(out, "{}{{topic=\"{}\"}} {}", name, topic, value(stats));
+			}
+		};
+
+		metric("gossip_topic_peers", "gauge",
+			   "Connected peers subscribed to the topic", &|stats: &TopicStats| stats.peers);
+		metric("gossip_topic_mesh_peers", "gauge",
+			   "Estimated mesh peers of the topic", &|stats: &TopicStats| stats.mesh_peers);
+		metric("gossip_topic_under_peered", "gauge",
+			   "Whether the topic is subscribed to with too few mesh peers", &|stats: &TopicStats| stats.under_peered as u64);
+		metric("gossip_topic_peers_joined_total", "counter",
+			   "Peers that subscribed to the topic", &|stats: &TopicStats| stats.peers_joined);
+		metric("gossip_topic_peers_left_total", "counter",
+			   "Peers that unsubscribed from the topic or disconnected", &|stats: &TopicStats| stats.peers_left);
+		metric("gossip_messages_received_total", "counter",
+			   "Gossip messages received on the topic", &|stats: &TopicStats| stats.received);
+		metric("gossip_messages_duplicate_total", "counter",
+			   "Received gossip messages dropped as duplicates", &|stats: &TopicStats| stats.duplicates);
+		metric("gossip_messages_rejected_total", "counter",
+			   "Received gossip messages rejected", &|stats: &TopicStats| stats.rejected);
+		metric("gossip_messages_published_total", "counter",
+			   "Own gossip messages published on the topic", &|stats: &TopicStats| stats.published);
+		out
+	}
+}
+
+fn update_mesh(stats: &mut TopicStats, mesh_n: u64, mesh_n_low: u64) {
+	stats.mesh_peers = std::cmp::min(stats.peers, mesh_n);
+	stats.under_peered = stats.subscribed && stats.mesh_peers < mesh_n_low;
+}
@@ -0,0 +1,140 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! NAT traversal.
+//!
+//! Ports are mapped on the gateway using UPnP IGD, and the external address
+//! is learned either from the gateway, or from addresses observed by peers.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use igd::{PortMappingProtocol, SearchOptions};
+use libp2p::{Multiaddr, PeerId};
+use libp2p::multiaddr::Protocol;
+use log::*;
+
+/// Lease duration of UPnP port mappings, in seconds.
+const UPNP_LEASE_DURATION: u32 = 3600;
+/// Interval between renewals of UPnP port mappings.
+const UPNP_RENEW_INTERVAL: Duration = Duration::from_secs(1800);
+/// Number of peers that must agree on an observed address before it is used.
+const OBSERVED_ADDRESS_THRESHOLD: usize = 3;
+
+/// Spawn a thread mapping the libp2p TCP port and the discovery UDP port on
+/// the gateway, renewing the mappings periodically. The external address of
+/// the gateway is sent on the returned channel after each successful mapping.
+pub fn spawn_upnp(tcp_port: u16, udp_port: u16) -> Receiver<Ipv4Addr> {
+	let (sender, receiver) = channel();
+
+	let spawned = thread::Builder::new()
+		.name("upnp".to_string())
+		.spawn(move || loop {
+			match map_ports(tcp_port, udp_port) {
+				Ok(external_ip) => {
+					if sender.send(external_ip).is_err() {
+						return
+					}
+				},
+				Err(e) => {
+					warn!("UPnP port mapping failed: {}", e);
+					return
+				},
+			}
+
+			thread::sleep(UPNP_RENEW_INTERVAL);
+		});
+	if let Err(e) = spawned {
+		warn!("Spawning UPnP thread failed: {:?}", e);
+	}
+
+	receiver
+}
+
+fn map_ports(tcp_port: u16, udp_port: u16) -> Result<Ipv4Addr, String> {
+	let gateway = igd::search_gateway(SearchOptions::default())
+		.map_err(|e| format!("Gateway not found: {}", e))?;
+	let local_ip = local_ip_towards(gateway.addr)
+		.map_err(|e| format!("Local address unknown: {}", e))?;
+
+	for (protocol, port) in &[(PortMappingProtocol::TCP, tcp_port), (PortMappingProtocol::UDP, udp_port)] {
+		gateway.add_port(
+			*protocol, *port, SocketAddrV4::new(local_ip, *port),
+			UPNP_LEASE_DURATION, "shasper",
+		).map_err(|e| format!("Mapping {:?} port {} failed: {}", protocol, port, e))?;
+		debug!("UPnP mapped {:?} port {}", protocol, port);
+	}
+
+	let external_ip = gateway.get_external_ip()
+		.map_err(|e| format!("External address unknown: {}", e))?;
+	info!("UPnP mapped ports, external address {}", external_ip);
+	Ok(external_ip)
+}
+
+/// Local address of the interface routing to the given address.
+fn local_ip_towards(addr: SocketAddrV4) -> std::io::Result<Ipv4Addr> {
+	let socket = UdpSocket::bind("0.0.0.0:0")?;
+	socket.connect(addr)?;
+	match socket.local_addr()?.ip() {
+		IpAddr::V4(ip) => Ok(ip),
+		IpAddr::V6(_) => Err(std::io::Error::new(
+			std::io::ErrorKind::Other, "IPv6 local address for an IPv4 gateway"
+		)),
+	}
+}
+
+/// Tracks our address as observed by peers through identify.
+#[derive(Default)]
+pub struct ObservedAddresses {
+	votes: HashMap<PeerId, IpAddr>,
+	current: Option<IpAddr>,
+}
+
+impl ObservedAddresses {
+	/// Create a new tracker.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note the address a peer observed for us. Returns the new external
+	/// address if enough peers now agree on a different one.
+	pub fn note(&mut self, peer: PeerId, observed: &Multiaddr) -> Option<IpAddr> {
+		let ip = observed.iter().filter_map(|protocol| match protocol {
+			Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+			Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+			_ => None,
+		}).next()?;
+		if ip.is_loopback() || ip.is_unspecified() {
+			return None
+		}
+
+		self.votes.insert(peer, ip);
+		let agreeing = self.votes.values().filter(|vote| **vote == ip).count();
+		if agreeing >= OBSERVED_ADDRESS_THRESHOLD && self.current != Some(ip) {
+			self.current = Some(ip);
+			Some(ip)
+		} else {
+			None
+		}
+	}
+
+	/// Forget the vote of a disconnected peer.
+	pub fn remove(&mut self, peer: &PeerId) {
+		self.votes.remove(peer);
+	}
+}
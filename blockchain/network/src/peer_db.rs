@@ -0,0 +1,124 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Known peers persisted across restarts.
+//!
+//! On start, the best peers of the previous run are dialed and added to the
+//! discovery routing table, so that a healthy peer count is reached without
+//! waiting for discovery to find peers from scratch.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use parity_codec::{Encode, Decode};
+use log::*;
+use crate::Error;
+
+/// File name of the peer database in the network directory.
+pub const PEER_DB_FILENAME: &str = "peers";
+/// Maximum number of peers persisted.
+pub const MAX_PERSISTED_PEERS: usize = 256;
+/// Maximum number of persisted peers dialed on start.
+pub const MAX_SEEDED_PEERS: usize = 16;
+/// Interval at which the peer database is written.
+pub const PEER_DB_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Peers not seen for longer than this, in seconds, are forgotten.
+pub const PEER_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// Persisted record of a peer.
+#[derive(Encode, Decode, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PeerRecord {
+	/// Base58 peer id.
+	pub peer_id: String,
+	/// Base64 ENR, if the peer was found through discovery.
+	pub enr: Option<String>,
+	/// Known addresses of the peer.
+	pub addresses: Vec<String>,
+	/// Unix time the peer was last connected.
+	pub last_seen: u64,
+	/// Reputation score when last seen.
+	pub score: i32,
+	/// Number of times the peer was dialed.
+	pub dial_attempts: u32,
+	/// Number of times a connection with the peer was established.
+	pub connections: u32,
+}
+
+impl PeerRecord {
+	/// Whether the peer is worth dialing on start.
+	pub fn is_good(&self) -> bool {
+		self.score >= 0 && self.connections > 0
+	}
+
+	/// Ordering key, best peers first.
+	fn rank(&self) -> (bool, i32, u32, u64) {
+		(self.is_good(), self.score, self.connections, self.last_seen)
+	}
+}
+
+/// Current Unix time in seconds.
+pub fn unix_now() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or(0)
+}
+
+/// Peer database file in a network directory.
+pub struct PeerDb {
+	path: PathBuf,
+}
+
+impl PeerDb {
+	/// Peer database in `network_dir`.
+	pub fn new(network_dir: &Path) -> Self {
+		Self { path: network_dir.join(PEER_DB_FILENAME) }
+	}
+
+	/// Load persisted peers, best first. A missing or corrupted database is
+	/// treated as empty.
+	pub fn load(&self) -> Vec<PeerRecord> {
+		let data = match fs::read(&self.path) {
+			Ok(data) => data,
+			Err(_) => return Vec::new(),
+		};
+		match Vec::<PeerRecord>::decode(&mut &data[..]) {
+			Some(mut records) => {
+				sort_and_truncate(&mut records, unix_now());
+				records
+			},
+			None => {
+				warn!("Invalid peer database {:?}, starting with no known peers", self.path);
+				Vec::new()
+			},
+		}
+	}
+
+	/// Persist peers, keeping the best ones. Written to a temporary file
+	/// first, so that a crash never leaves a truncated database.
+	pub fn save(&self, mut records: Vec<PeerRecord>) -> Result<(), Error> {
+		sort_and_truncate(&mut records, unix_now());
+		let tmp = self.path.with_extension("tmp");
+		fs::write(&tmp, records.encode())?;
+		fs::rename(&tmp, &self.path)?;
+		Ok(())
+	}
+}
+
+fn sort_and_truncate(records: &mut Vec<PeerRecord>, now: u64) {
+	records.retain(|record| now.saturating_sub(record.last_seen) <= PEER_EXPIRY_SECS);
+	records.sort_by(|a, b| b.rank().cmp(&a.rank()));
+	records.truncate(MAX_PERSISTED_PEERS);
+}
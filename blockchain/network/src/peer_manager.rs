@@ -0,0 +1,429 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+use core::time::Duration;
+use libp2p::{Multiaddr, PeerId};
+use log::*;
+use crate::peer_db::{PeerRecord, unix_now};
+use crate::subnets::attnets_has_subnet;
+
+/// Score penalty for a response violating request limits, or otherwise
+/// failing to decode.
+pub const INVALID_RESPONSE_PENALTY: i32 = 25;
+/// Score penalty for a message received while over the inbound bandwidth
+/// quota.
+pub const QUOTA_EXCEEDED_PENALTY: i32 = 10;
+/// Score penalty for a gossip message over the per-topic rate limit.
+pub const GOSSIP_RATE_LIMITED_PENALTY: i32 = 10;
+/// Score penalty for an oversized or undecodable gossip message.
+pub const INVALID_GOSSIP_PENALTY: i32 = 25;
+/// Peers with a score at or below this are banned.
+pub const BAN_THRESHOLD: i32 = -100;
+/// Assumed RPC latency of peers not yet measured. Kept moderate so that new
+/// peers get a chance to serve batches.
+pub const DEFAULT_RPC_LATENCY: Duration = Duration::from_millis(500);
+/// Weight of a new sample in the moving average of RPC latency, in percent.
+const RPC_LATENCY_WEIGHT: u32 = 20;
+/// Needed attestation subnets covered by at most this many connected peers
+/// are scarce, and peers covering them are kept when pruning.
+const SCARCE_SUBNET_PEERS: usize = 2;
+
+/// Peer manager shared with other components, such as the node API.
+pub type SharedPeerManager = Arc<RwLock<PeerManager>>;
+
+/// Direction of a connection with a peer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionDirection {
+	/// The peer dialed us.
+	Inbound,
+	/// We dialed the peer.
+	Outbound,
+}
+
+/// Information known about a peer.
+#[derive(Clone, Debug, Default)]
+pub struct PeerInfo {
+	/// Whether the peer is currently connected.
+	pub connected: bool,
+	/// Direction of the current or last connection with the peer.
+	pub direction: Option<ConnectionDirection>,
+	/// When the current connection with the peer was established.
+	pub connected_since: Option<Instant>,
+	/// Attestation subnet bitvector advertised in the ENR of the peer.
+	pub attnets: Vec<u8>,
+	/// Attestation subnets the peer is subscribed to through gossip, while
+	/// connected.
+	pub subnets: HashSet<u64>,
+	/// Reputation score.
+	pub score: i32,
+	/// Agent version reported through identify, such as `shasper/0.1.0`.
+	pub agent_version: Option<String>,
+	/// Protocol version reported through identify.
+	pub protocol_version: Option<String>,
+	/// Listen addresses reported through identify.
+	pub listen_addrs: Vec<Multiaddr>,
+	/// Moving average of the response time of sync requests.
+	pub rpc_latency: Option<Duration>,
+	/// Number of sync requests answered.
+	pub rpc_successes: u32,
+	/// Number of sync requests failed or timed out.
+	pub rpc_failures: u32,
+	/// Base64 ENR, if the peer was found through discovery.
+	pub enr: Option<String>,
+	/// Unix time the peer was last connected.
+	pub last_seen: u64,
+	/// Number of times the peer was dialed from the peer database.
+	pub dial_attempts: u32,
+	/// Number of times a connection with the peer was established.
+	pub connections: u32,
+}
+
+impl PeerInfo {
+	/// Estimated share of sync requests the peer answers, between 0 and 1.
+	/// Smoothed so that a single result does not dominate.
+	pub fn reliability(&self) -> f64 {
+		(self.rpc_successes as f64 + 1.0) /
+			(self.rpc_successes as f64 + self.rpc_failures as f64 + 2.0)
+	}
+
+	/// Whether the peer advertises or is subscribed to an attestation subnet.
+	pub fn covers_subnet(&self, subnet: u64) -> bool {
+		self.subnets.contains(&subnet) || attnets_has_subnet(&self.attnets, subnet)
+	}
+
+	/// Preference of the peer for serving sync batches. Higher is better.
+	pub fn sync_preference(&self) -> f64 {
+		let latency = self.rpc_latency.unwrap_or(DEFAULT_RPC_LATENCY);
+		self.reliability() / (latency.as_millis() as f64 + 1.0)
+	}
+}
+
+/// Database of known peers and their reputation.
+#[derive(Default)]
+pub struct PeerManager {
+	peers: HashMap<PeerId, PeerInfo>,
+	trusted: HashSet<PeerId>,
+}
+
+impl PeerManager {
+	/// Create a new peer manager.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Information about a peer.
+	pub fn peer(&self, peer: &PeerId) -> Option<&PeerInfo> {
+		self.peers.get(peer)
+	}
+
+	/// All known peers.
+	pub fn peers(&self) -> impl Iterator<Item=(&PeerId, &PeerInfo)> {
+		self.peers.iter()
+	}
+
+	/// Currently connected peers.
+	pub fn connected_peers(&self) -> impl Iterator<Item=(&PeerId, &PeerInfo)> {
+		self.peers.iter().filter(|(_, info)| info.connected)
+	}
+
+	/// Set trusted peers, which are never banned.
+	pub fn set_trusted(&mut self, trusted: HashSet<PeerId>) {
+		self.trusted = trusted;
+	}
+
+	/// Whether a peer is trusted.
+	pub fn is_trusted(&self, peer: &PeerId) -> bool {
+		self.trusted.contains(peer)
+	}
+
+	/// Current score of a peer.
+	pub fn score(&self, peer: &PeerId) -> i32 {
+		self.peers.get(peer).map(|info| info.score).unwrap_or(0)
+	}
+
+	/// Number of connected peers in `direction`, excluding trusted peers.
+	pub fn connected_count(&self, direction: ConnectionDirection) -> usize {
+		self.connected_peers()
+			.filter(|(peer, info)| {
+				info.direction == Some(direction) && !self.trusted.contains(peer)
+			})
+			.count()
+	}
+
+	/// Note that a peer has connected.
+	pub fn note_connected(&mut self, peer: &PeerId, direction: ConnectionDirection) {
+		let info = self.peers.entry(peer.clone()).or_default();
+		if !info.connected {
+			info.connected_since = Some(Instant::now());
+		}
+		info.connected = true;
+		info.direction = Some(direction);
+		info.last_seen = unix_now();
+		info.connections = info.connections.saturating_add(1);
+	}
+
+	/// Note that a peer has disconnected.
+	pub fn note_disconnected(&mut self, peer: &PeerId) {
+		if let Some(info) = self.peers.get_mut(peer) {
+			info.connected = false;
+			info.connected_since = None;
+			info.subnets.clear();
+			info.last_seen = unix_now();
+		}
+	}
+
+	/// Note that a peer is being dialed.
+	pub fn note_dialing(&mut self, peer: &PeerId) {
+		let info = self.peers.entry(peer.clone()).or_default();
+		info.dial_attempts = info.dial_attempts.saturating_add(1);
+	}
+
+	/// Note the ENR of a peer found through discovery.
+	pub fn note_enr(&mut self, peer: &PeerId, enr: String) {
+		self.peers.entry(peer.clone()).or_default().enr = Some(enr);
+	}
+
+	/// Note the attestation subnet bitvector advertised by a peer.
+	pub fn note_attnets(&mut self, peer: &PeerId, attnets: Vec<u8>) {
+		self.peers.entry(peer.clone()).or_default().attnets = attnets;
+	}
+
+	/// Note that a peer subscribed to or unsubscribed from an attestation
+	/// subnet through gossip.
+	pub fn note_subnet_subscription(&mut self, peer: &PeerId, subnet: u64, subscribed: bool) {
+		let info = self.peers.entry(peer.clone()).or_default();
+		if subscribed {
+			info.subnets.insert(subnet);
+		} else {
+			info.subnets.remove(&subnet);
+		}
+	}
+
+	/// Records of peers with a known address or ENR, to be persisted.
+	pub fn records(&self) -> Vec<PeerRecord> {
+		self.peers.iter()
+			.filter(|(_, info)| info.enr.is_some() || !info.listen_addrs.is_empty())
+			.map(|(peer, info)| PeerRecord {
+				peer_id: peer.to_base58(),
+				enr: info.enr.clone(),
+				addresses: info.listen_addrs.iter().map(|address| address.to_string()).collect(),
+				last_seen: info.last_seen,
+				score: info.score,
+				dial_attempts: info.dial_attempts,
+				connections: info.connections,
+			})
+			.collect()
+	}
+
+	/// Restore peers persisted by a previous run. Peers already known are
+	/// left untouched.
+	pub fn import(&mut self, records: &[PeerRecord]) {
+		for record in records {
+			let peer = match PeerId::from_str(&record.peer_id) {
+				Ok(peer) => peer,
+				Err(_) => continue,
+			};
+			self.peers.entry(peer).or_insert_with(|| PeerInfo {
+				score: record.score,
+				listen_addrs: record.addresses.iter()
+					.filter_map(|address| Multiaddr::from_str(address).ok())
+					.collect(),
+				enr: record.enr.clone(),
+				last_seen: record.last_seen,
+				dial_attempts: record.dial_attempts,
+				connections: record.connections,
+				..Default::default()
+			});
+		}
+	}
+
+	/// Note information a peer reported through identify.
+	pub fn note_identified(
+		&mut self,
+		peer: &PeerId,
+		agent_version: String,
+		protocol_version: String,
+		listen_addrs: Vec<Multiaddr>,
+	) {
+		debug!("Identified peer {:?} as {} ({})", peer, agent_version, protocol_version);
+		let info = self.peers.entry(peer.clone()).or_default();
+		info.agent_version = Some(agent_version);
+		info.protocol_version = Some(protocol_version);
+		info.listen_addrs = listen_addrs;
+	}
+
+	/// Note that a peer answered a sync request after `latency`.
+	pub fn note_rpc_success(&mut self, peer: &PeerId, latency: Duration) {
+		let info = self.peers.entry(peer.clone()).or_default();
+		info.rpc_successes = info.rpc_successes.saturating_add(1);
+		info.rpc_latency = Some(match info.rpc_latency {
+			Some(average) =>
+				(average * (100 - RPC_LATENCY_WEIGHT) + latency * RPC_LATENCY_WEIGHT) / 100,
+			None => latency,
+		});
+	}
+
+	/// Note that a sync request to a peer failed or timed out.
+	pub fn note_rpc_failure(&mut self, peer: &PeerId) {
+		let info = self.peers.entry(peer.clone()).or_default();
+		info.rpc_failures = info.rpc_failures.saturating_add(1);
+	}
+
+	/// Choose the connected peer to serve a sync batch among `candidates`,
+	/// preferring low latency and high reliability. Peers in `busy` already
+	/// serve a batch and are only chosen if no other candidate is left.
+	pub fn select_sync_peer<'a, I: IntoIterator<Item=&'a PeerId>>(
+		&self,
+		candidates: I,
+		busy: &HashSet<PeerId>,
+	) -> Option<PeerId> {
+		let default = PeerInfo::default();
+		let mut best: Option<(bool, f64, &PeerId)> = None;
+		for peer in candidates {
+			let info = self.peers.get(peer).unwrap_or(&default);
+			if !info.connected {
+				continue
+			}
+			let candidate = (!busy.contains(peer), info.sync_preference(), peer);
+			let better = match best {
+				Some((idle, preference, _)) =>
+					(candidate.0, candidate.1) > (idle, preference),
+				None => true,
+			};
+			if better {
+				best = Some(candidate);
+			}
+		}
+		best.map(|(_, _, peer)| peer.clone())
+	}
+
+	/// Connected peers to disconnect to stay within `max_peers`, of which at
+	/// most `max_peers - min_outbound` may be inbound. Inbound peers are
+	/// pruned first, so that peers able to connect to us cannot take the
+	/// place of peers we chose. Within each direction, peers covering scarce
+	/// `needed_subnets` are kept first, then peers with a good score, then
+	/// peers connected for long. Trusted peers are neither pruned nor counted.
+	pub fn peers_to_prune(
+		&self,
+		max_peers: usize,
+		min_outbound: usize,
+		needed_subnets: &HashSet<u64>,
+		now: Instant,
+	) -> Vec<PeerId> {
+		let mut inbound = Vec::new();
+		let mut outbound = Vec::new();
+		for (peer, info) in self.connected_peers() {
+			if self.trusted.contains(peer) {
+				continue
+			}
+			match info.direction {
+				Some(ConnectionDirection::Outbound) => outbound.push(peer),
+				_ => inbound.push(peer),
+			}
+		}
+
+		let max_inbound = max_peers.saturating_sub(min_outbound);
+		let excess = (inbound.len() + outbound.len()).saturating_sub(max_peers);
+		let inbound_pruned = std::cmp::min(
+			std::cmp::max(excess, inbound.len().saturating_sub(max_inbound)),
+			inbound.len(),
+		);
+		let outbound_pruned = excess.saturating_sub(inbound_pruned);
+
+		// Trusted peers count towards subnet coverage.
+		let mut coverage = needed_subnets.iter()
+			.map(|subnet| {
+				let count = self.connected_peers()
+					.filter(|(_, info)| info.covers_subnet(*subnet))
+					.count();
+				(*subnet, count)
+			})
+			.collect::<HashMap<_, _>>();
+
+		let mut pruned = Vec::new();
+		self.prune_from(inbound, inbound_pruned, &mut coverage, now, &mut pruned);
+		self.prune_from(outbound, outbound_pruned, &mut coverage, now, &mut pruned);
+		pruned
+	}
+
+	/// Move the `count` peers least worth keeping from `candidates` to
+	/// `pruned`, one at a time, so that the subnet coverage left by
+	/// previously pruned peers is taken into account.
+	fn prune_from(
+		&self,
+		mut candidates: Vec<&PeerId>,
+		count: usize,
+		coverage: &mut HashMap<u64, usize>,
+		now: Instant,
+		pruned: &mut Vec<PeerId>,
+	) {
+		let default = PeerInfo::default();
+		for _ in 0..count {
+			let worst = candidates.iter()
+				.enumerate()
+				.min_by_key(|&(_, peer)| {
+					let info = self.peers.get(*peer).unwrap_or(&default);
+					let scarce_subnets = coverage.iter()
+						.filter(|(subnet, count)| {
+							**count <= SCARCE_SUBNET_PEERS && info.covers_subnet(**subnet)
+						})
+						.count();
+					let uptime = info.connected_since
+						.filter(|since| *since < now)
+						.map(|since| now - since)
+						.unwrap_or_default();
+					(scarce_subnets, info.score, uptime)
+				})
+				.map(|(index, _)| index);
+			let peer = match worst {
+				Some(index) => candidates.swap_remove(index),
+				None => break,
+			};
+
+			if let Some(info) = self.peers.get(peer) {
+				for (subnet, count) in coverage.iter_mut() {
+					if info.covers_subnet(*subnet) {
+						*count = count.saturating_sub(1);
+					}
+				}
+			}
+			pruned.push(peer.clone());
+		}
+	}
+
+	/// Lower the score of a peer. Returns whether the peer should be banned.
+	/// Trusted peers are never banned.
+	pub fn downscore(&mut self, peer: &PeerId, penalty: i32, reason: &str) -> bool {
+		let trusted = self.trusted.contains(peer);
+		let info = self.peers.entry(peer.clone()).or_default();
+		info.score -= penalty;
+		debug!("Downscored peer {:?} to {}: {}", peer, info.score, reason);
+
+		if info.score <= BAN_THRESHOLD && !trusted {
+			warn!(
+				"Banning peer {:?} ({}): {}",
+				peer, info.agent_version.as_ref().map(|v| v.as_str()).unwrap_or("unknown agent"), reason,
+			);
+			true
+		} else {
+			false
+		}
+	}
+}
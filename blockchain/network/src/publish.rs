@@ -0,0 +1,241 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Duplicate suppression and retries of published gossip.
+//!
+//! Messages we publish are noted as seen, so that copies echoed back by
+//! peers are dropped before decoding. Messages published to a topic with no
+//! subscribed peer would be lost, so they are queued and published again
+//! once a peer subscribes, or dropped after a timeout.
+//!
+//! Blocks we propose may be flood published, to every peer subscribed to
+//! the block topic rather than only to our mesh, and the fanout of each
+//! publish is counted for metrics.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use libp2p::gossipsub::{Topic, TopicHash};
+
+/// Time a message is remembered as seen.
+pub const SEEN_MESSAGE_TTL: Duration = Duration::from_secs(384);
+/// Maximum number of messages remembered as seen.
+pub const MAX_SEEN_MESSAGES: usize = 16384;
+/// Time a message waits for topic peers before being dropped.
+pub const PUBLISH_QUEUE_TIMEOUT: Duration = Duration::from_secs(12);
+/// Maximum number of messages waiting for topic peers.
+pub const MAX_PUBLISH_QUEUE_LEN: usize = 64;
+/// Interval at which queued messages are retried.
+pub const PUBLISH_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+fn message_key(topic: &TopicHash, data: &[u8]) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	Hash::hash(topic, &mut hasher);
+	data.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Recently seen gossip messages, keyed by topic and content.
+#[derive(Default)]
+pub struct SeenMessages {
+	seen: HashMap<u64, Instant>,
+	order: VecDeque<(u64, Instant)>,
+}
+
+impl SeenMessages {
+	/// Create an empty cache.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note a message as seen. Returns whether it was seen already.
+	pub fn insert(&mut self, topic: &TopicHash, data: &[u8], now: Instant) -> bool {
+		self.expire(now);
+		let key = message_key(topic, data);
+		if self.seen.contains_key(&key) {
+			return true
+		}
+
+		self.seen.insert(key, now);
+		self.order.push_back((key, now));
+		while self.order.len() > MAX_SEEN_MESSAGES {
+			if let Some((oldest, _)) = self.order.pop_front() {
+				self.seen.remove(&oldest);
+			}
+		}
+		false
+	}
+
+	/// Whether a message was seen.
+	pub fn contains(&self, topic: &TopicHash, data: &[u8]) -> bool {
+		self.seen.contains_key(&message_key(topic, data))
+	}
+
+	fn expire(&mut self, now: Instant) {
+		while let Some((key, inserted)) = self.order.front().cloned() {
+			if now.duration_since(inserted) < SEEN_MESSAGE_TTL {
+				break
+			}
+			self.order.pop_front();
+			self.seen.remove(&key);
+		}
+	}
+}
+
+struct QueuedMessage {
+	topic: Topic,
+	data: Vec<u8>,
+	queued: Instant,
+}
+
+/// Messages waiting for peers on their topic, and the peers known to be
+/// subscribed to each topic.
+#[derive(Default)]
+pub struct PublishQueue {
+	topic_peers: HashMap<TopicHash, HashSet<PeerId>>,
+	queue: VecDeque<QueuedMessage>,
+}
+
+impl PublishQueue {
+	/// Create an empty queue.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note that a peer subscribed to a topic.
+	pub fn note_subscribed(&mut self, peer: PeerId, topic: TopicHash) {
+		self.topic_peers.entry(topic).or_default().insert(peer);
+	}
+
+	/// Note that a peer unsubscribed from a topic.
+	pub fn note_unsubscribed(&mut self, peer: &PeerId, topic: &TopicHash) {
+		if let Some(peers) = self.topic_peers.get_mut(topic) {
+			peers.remove(peer);
+		}
+	}
+
+	/// Note that a peer disconnected, leaving all topics.
+	pub fn note_disconnected(&mut self, peer: &PeerId) {
+		for peers in self.topic_peers.values_mut() {
+			peers.remove(peer);
+		}
+	}
+
+	/// Number of peers subscribed to the topic.
+	pub fn peer_count(&self, topic: &TopicHash) -> usize {
+		self.topic_peers.get(topic).map(|peers| peers.len()).unwrap_or(0)
+	}
+
+	/// Whether any peer is subscribed to the topic.
+	pub fn has_peers(&self, topic: &TopicHash) -> bool {
+		self.topic_peers.get(topic).map(|peers| !peers.is_empty()).unwrap_or(false)
+	}
+
+	/// Queue a message until a peer subscribes to its topic, dropping the
+	/// oldest message if the queue is full.
+	pub fn push(&mut self, topic: Topic, data: Vec<u8>, now: Instant) {
+		if self.queue.len() >= MAX_PUBLISH_QUEUE_LEN {
+			self.queue.pop_front();
+		}
+		self.queue.push_back(QueuedMessage { topic, data, queued: now });
+	}
+
+	/// Number of queued messages.
+	pub fn len(&self) -> usize {
+		self.queue.len()
+	}
+
+	/// Whether no message is queued.
+	pub fn is_empty(&self) -> bool {
+		self.queue.is_empty()
+	}
+
+	/// Take messages whose topic now has peers, dropping the expired ones.
+	/// Returns the messages ready to publish and the number dropped.
+	pub fn take_ready(&mut self, now: Instant) -> (Vec<(Topic, Vec<u8>)>, usize) {
+		let mut ready = Vec::new();
+		let mut dropped = 0;
+		let queue = std::mem::replace(&mut self.queue, VecDeque::new());
+		for message in queue {
+			if self.has_peers(&message.topic.hash()) {
+				ready.push((message.topic, message.data));
+			} else if now.duration_since(message.queued) >= PUBLISH_QUEUE_TIMEOUT {
+				dropped += 1;
+			} else {
+				self.queue.push_back(message);
+			}
+		}
+		(ready, dropped)
+	}
+}
+
+/// Publish statistics shared with other components, such as the metrics
+/// writer.
+pub type SharedPublishStats = Arc<RwLock<PublishStats>>;
+
+/// Counters of our own published blocks and the peers they were sent to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PublishStats {
+	/// Number of block publishes, one per gossip encoding.
+	pub blocks: u64,
+	/// Number of block publishes that were flood published.
+	pub flooded: u64,
+	/// Sum of peers block publishes were sent to.
+	pub fanout_total: u64,
+	/// Number of peers the last block publish was sent to.
+	pub last_fanout: u64,
+}
+
+impl PublishStats {
+	/// Create empty statistics.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note a block publish sent to `fanout` peers.
+	pub fn note_block(&mut self, fanout: usize, flooded: bool) {
+		self.blocks += 1;
+		if flooded {
+			self.flooded += 1;
+		}
+		self.fanout_total += fanout as u64;
+		self.last_fanout = fanout as u64;
+	}
+
+	/// Statistics in the Prometheus text format.
+	pub fn encode(&self) -> String {
+		let mut out = String::new();
+		let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			let _ = writeln!(out, "{} {}", name, value);
+		};
+
+		metric("beacon_block_publish_total", "counter",
+			   "Own blocks published, per gossip encoding", self.blocks);
+		metric("beacon_block_flood_publish_total", "counter",
+			   "Own blocks flood published to all topic peers", self.flooded);
+		metric("beacon_block_publish_fanout_total", "counter",
+			   "Peers own blocks were sent to", self.fanout_total);
+		metric("beacon_block_publish_last_fanout", "gauge",
+			   "Peers the last own block was sent to", self.last_fanout);
+		out
+	}
+}
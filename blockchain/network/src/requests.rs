@@ -0,0 +1,172 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use beacon::Config;
+use core::time::Duration;
+use futures::channel::oneshot;
+use libp2p::PeerId;
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::rpc::{RPCError, RPCResponse, RequestId};
+
+/// Time after which a tracked request without response is failed. Slightly
+/// longer than the RPC handler's own response timeout, which drops the
+/// substream silently.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+/// Tracked RPC request errors.
+pub enum RequestError {
+	/// Peer did not respond in time.
+	Timeout,
+	/// Peer disconnected before responding.
+	Disconnected,
+	/// RPC protocol failed, including stream resets.
+	Rpc(RPCError),
+}
+
+impl std::fmt::Display for RequestError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			RequestError::Timeout => write!(f, "Request timed out"),
+			RequestError::Disconnected => write!(f, "Peer disconnected"),
+			RequestError::Rpc(e) => write!(f, "RPC failed: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for RequestError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			RequestError::Rpc(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// Result of a tracked RPC request.
+pub type RequestResult<C> = Result<RPCResponse<C>, RequestError>;
+/// Sending end of a tracked RPC request's response.
+pub type ResponseSender<C> = oneshot::Sender<RequestResult<C>>;
+/// Receiving end of a tracked RPC request's response.
+pub type ResponseReceiver<C> = oneshot::Receiver<RequestResult<C>>;
+
+struct PendingRequest<C: Config> {
+	peer: PeerId,
+	sent: Instant,
+	sender: ResponseSender<C>,
+}
+
+/// Correlates outbound RPC requests with the channels of their requesters.
+///
+/// Request ID `0` is never allocated, and is left for untracked requests
+/// whose responses are handled by the network service itself.
+pub struct RequestManager<C: Config> {
+	next_id: RequestId,
+	timeout: Duration,
+	pending: HashMap<(PeerId, RequestId), PendingRequest<C>>,
+}
+
+impl<C: Config> RequestManager<C> {
+	/// Create a new request manager with the default timeout.
+	pub fn new() -> Self {
+		Self::with_timeout(REQUEST_TIMEOUT)
+	}
+
+	/// Create a new request manager failing requests after `timeout`.
+	pub fn with_timeout(timeout: Duration) -> Self {
+		Self { next_id: 1, timeout, pending: HashMap::new() }
+	}
+
+	/// Track a request to `peer`, returning the request ID to send it with.
+	pub fn register(&mut self, peer: PeerId, sender: ResponseSender<C>, now: Instant) -> RequestId {
+		let id = self.next_id;
+		self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+		self.pending.insert((peer.clone(), id), PendingRequest { peer, sent: now, sender });
+		id
+	}
+
+	/// Route a response to its requester. Returns the response back if the
+	/// request is not tracked.
+	pub fn on_response(
+		&mut self,
+		peer: &PeerId,
+		id: RequestId,
+		response: RPCResponse<C>,
+	) -> Option<RPCResponse<C>> {
+		match self.pending.remove(&(peer.clone(), id)) {
+			Some(pending) => {
+				// The requester may have given up already.
+				let _ = pending.sender.send(Ok(response));
+				None
+			},
+			None => Some(response),
+		}
+	}
+
+	/// Fail a request on an RPC error. Returns the error back if the request
+	/// is not tracked.
+	pub fn on_error(&mut self, peer: &PeerId, id: RequestId, error: RPCError) -> Option<RPCError> {
+		match self.pending.remove(&(peer.clone(), id)) {
+			Some(pending) => {
+				let _ = pending.sender.send(Err(RequestError::Rpc(error)));
+				None
+			},
+			None => Some(error),
+		}
+	}
+
+	/// Fail all requests to a disconnected peer.
+	pub fn on_disconnected(&mut self, peer: &PeerId) {
+		self.fail_where(|pending| &pending.peer == peer, RequestError::Disconnected);
+	}
+
+	/// Fail all requests sent longer than the timeout ago.
+	pub fn expire(&mut self, now: Instant) {
+		let timeout = self.timeout;
+		self.fail_where(|pending| now.duration_since(pending.sent) >= timeout, RequestError::Timeout);
+	}
+
+	/// Number of requests awaiting a response.
+	pub fn len(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Whether no request is awaiting a response.
+	pub fn is_empty(&self) -> bool {
+		self.pending.is_empty()
+	}
+
+	fn fail_where<F: Fn(&PendingRequest<C>) -> bool>(&mut self, f: F, error: RequestError) {
+		let failed = self.pending.iter()
+			.filter(|(_, pending)| f(pending))
+			.map(|(key, _)| key.clone())
+			.collect::<Vec<_>>();
+
+		for key in failed {
+			if let Some(pending) = self.pending.remove(&key) {
+				let _ = pending.sender.send(Err(error.clone()));
+			}
+		}
+	}
+}
+
+impl<C: Config> Default for RequestManager<C> {
+	fn default() -> Self {
+		Self::new()
+	}
+}
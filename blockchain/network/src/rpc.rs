@@ -1,30 +1,81 @@
-use core::marker::PhantomData;
+use std::sync::Arc;
 use libp2p_rpc::RPCProtocol as RPCProtocolT;
-use network_messages::{InboundCodec, OutboundCodec};
+use network_messages::{
+	InboundCodec, OutboundCodec, Encoding, RPCProtocolId, RPCRegistry, RESPONSE_CODE_RATE_LIMITED,
+};
 use beacon::Config;
 
 pub use network_messages::{RPCType, RPCRequest, RPCResponse};
+pub use libp2p_rpc::{RPCError, RequestId};
 pub type RPC<C, TSubstream> = libp2p_rpc::RPC<RPCProtocol<C>, TSubstream>;
 pub type RPCMessage<C> = libp2p_rpc::RPCMessage<RPCRequest, RPCResponse<C>>;
 pub type RPCEvent<C> = libp2p_rpc::RPCEvent<RPCRequest, RPCResponse<C>>;
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct RPCProtocol<C: Config> {
-	_marker: PhantomData<C>,
+	/// Supported encodings, in order of preference.
+	encodings: Vec<Encoding>,
+	/// Supported methods.
+	registry: Arc<RPCRegistry<C>>,
+}
+
+impl<C: Config> RPCProtocol<C> {
+	/// Create a new protocol supporting the beacon chain methods in the
+	/// given encodings, in order of preference.
+	pub fn new(encodings: Vec<Encoding>) -> Self {
+		Self::with_registry(encodings, RPCRegistry::default())
+	}
+
+	/// Create a new protocol supporting the methods of a registry.
+	pub fn with_registry(encodings: Vec<Encoding>, registry: RPCRegistry<C>) -> Self {
+		Self { encodings, registry: Arc::new(registry) }
+	}
+}
+
+impl<C: Config> Default for RPCProtocol<C> {
+	fn default() -> Self {
+		Self::new(Encoding::all())
+	}
 }
 
 impl<C: Config> RPCProtocolT for RPCProtocol<C> {
-	type Type = RPCType;
+	type Type = RPCProtocolId;
 	type Request = RPCRequest;
 	type Response = RPCResponse<C>;
 
 	type InboundCodec = InboundCodec<C>;
-	fn inbound_codec(&self, protocol: RPCType) -> Self::InboundCodec {
-		InboundCodec::new(protocol)
+	fn inbound_codec(&self, protocol: RPCProtocolId) -> Self::InboundCodec {
+		let method = self.registry.method(protocol.typ)
+			.expect("Negotiated protocols are from the registry; qed");
+		InboundCodec::new(method, protocol)
 	}
 
 	type OutboundCodec = OutboundCodec<C>;
-	fn outbound_codec(&self, protocol: RPCType) -> Self::OutboundCodec {
-		OutboundCodec::new(protocol)
+	fn outbound_codec(&self, protocol: RPCProtocolId, request: &RPCRequest) -> Self::OutboundCodec {
+		let method = self.registry.method(protocol.typ)
+			.expect("Negotiated protocols are from the registry; qed");
+		OutboundCodec::new(method, protocol, request)
 	}
+
+	fn inbound_protocols(&self) -> Vec<RPCProtocolId> {
+		self.registry.protocols(&self.encodings)
+	}
+
+	fn outbound_protocols(&self, request: &RPCRequest) -> Vec<RPCProtocolId> {
+		match self.registry.method(request.rpc_type()) {
+			Some(method) => self.encodings.iter()
+				.filter_map(|encoding| method.protocol(*encoding))
+				.collect(),
+			None => Vec::new(),
+		}
+	}
+
+	fn rate_limited_response(&self) -> RPCResponse<C> {
+		rate_limited_response()
+	}
+}
+
+/// Error response to a request rejected for exceeding a rate limit.
+pub fn rate_limited_response<C: Config>() -> RPCResponse<C> {
+	RPCResponse::Unknown(RESPONSE_CODE_RATE_LIMITED, b"rate limited".to_vec())
 }
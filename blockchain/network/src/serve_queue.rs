@@ -0,0 +1,148 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Queue and streaming of inbound block range requests.
+//!
+//! Range requests are the most expensive requests we serve. They are
+//! queued, and only a few are streamed at once. Requests beyond the queue
+//! capacity, or beyond the share of a single peer, are rejected with a rate
+//! limited error response.
+//!
+//! A streamed response reads one block from the store at a time, and only
+//! reads the next one once the previous chunk was written out to the peer.
+//! Slow peers thus hold back reads of their responses, instead of having
+//! whole ranges loaded in memory waiting for them. The roots of the blocks
+//! are fixed when the stream starts, so that a reorg while streaming does
+//! not splice blocks of two chains into a response.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use libp2p::PeerId;
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::types::BeaconBlock;
+use network_messages::BeaconBlocksRequest;
+use crate::rpc::{RPCEvent, RPCResponse, RequestId};
+
+/// Maximum number of range requests queued, from all peers.
+pub const MAX_QUEUED_RANGE_REQUESTS: usize = 32;
+/// Maximum number of range requests queued from a single peer.
+pub const MAX_QUEUED_RANGE_REQUESTS_PER_PEER: usize = 2;
+/// Maximum number of range responses streamed at once.
+pub const MAX_ACTIVE_STREAMS: usize = 4;
+/// Maximum number of blocks in a range response.
+pub const MAX_RANGE_RESPONSE_BLOCKS: u64 = 1024;
+/// Time after which a stream whose last chunk was not written out is
+/// abandoned.
+const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Position of a streamed range response.
+struct RangeCursor {
+	remaining: VecDeque<H256>,
+	last_sent: Instant,
+}
+
+/// Queue of range requests waiting to be served, in arrival order, and the
+/// responses being streamed.
+pub struct ServeQueue {
+	queue: VecDeque<(PeerId, RequestId, BeaconBlocksRequest)>,
+	active: HashMap<(PeerId, RequestId), RangeCursor>,
+}
+
+impl ServeQueue {
+	/// Create an empty queue.
+	pub fn new() -> Self {
+		Self { queue: VecDeque::new(), active: HashMap::new() }
+	}
+
+	/// Queue a request. Returns `false`, dropping the request, if the queue
+	/// or the share of the peer is full.
+	pub fn push(&mut self, peer: PeerId, id: RequestId, request: BeaconBlocksRequest) -> bool {
+		if self.queue.len() >= MAX_QUEUED_RANGE_REQUESTS ||
+			self.queue.iter().filter(|(queued, _, _)| *queued == peer).count() >=
+			MAX_QUEUED_RANGE_REQUESTS_PER_PEER
+		{
+			return false
+		}
+
+		self.queue.push_back((peer, id, request));
+		true
+	}
+
+	/// Take the next request to serve, if fewer than `MAX_ACTIVE_STREAMS`
+	/// responses are being streamed.
+	pub fn pop(&mut self) -> Option<(PeerId, RequestId, BeaconBlocksRequest)> {
+		if self.active.len() >= MAX_ACTIVE_STREAMS {
+			return None
+		}
+		self.queue.pop_front()
+	}
+
+	/// Start streaming the response to a request, made of the blocks of
+	/// `roots`, oldest first.
+	pub fn start(&mut self, peer: PeerId, id: RequestId, mut roots: Vec<H256>) {
+		roots.truncate(MAX_RANGE_RESPONSE_BLOCKS as usize);
+		self.active.insert((peer, id), RangeCursor {
+			remaining: roots.into(),
+			last_sent: Instant::now(),
+		});
+	}
+
+	/// Next event of a streamed response: a chunk with the next block, read
+	/// by `block_at` from its root, or the end of the stream. The stream
+	/// ends early if a block is no longer stored. `None` if no response to
+	/// the request is streamed.
+	pub fn next_event<C: Config, F: Fn(&H256) -> Option<BeaconBlock<C>>>(
+		&mut self,
+		peer: &PeerId,
+		id: RequestId,
+		block_at: F,
+	) -> Option<RPCEvent<C>> {
+		let key = (peer.clone(), id);
+		let cursor = self.active.get_mut(&key)?;
+		let block = cursor.remaining.pop_front().and_then(|root| block_at(&root));
+
+		match block {
+			Some(block) => {
+				cursor.last_sent = Instant::now();
+				Some(RPCEvent::StreamChunk(id, RPCResponse::BeaconBlocks(vec![block])))
+			},
+			None => {
+				self.active.remove(&key);
+				Some(RPCEvent::EndStream(id))
+			},
+		}
+	}
+
+	/// Abandon streams whose peer did not take their last chunk in time.
+	/// Returns the abandoned streams, to be ended.
+	pub fn prune(&mut self, now: Instant) -> Vec<(PeerId, RequestId)> {
+		let expired = self.active.iter()
+			.filter(|(_, cursor)| now.duration_since(cursor.last_sent) >= STREAM_IDLE_TIMEOUT)
+			.map(|(key, _)| key.clone())
+			.collect::<Vec<_>>();
+		for key in &expired {
+			self.active.remove(key);
+		}
+		expired
+	}
+
+	/// Drop the requests and streams of a disconnected peer.
+	pub fn remove_peer(&mut self, peer: &PeerId) {
+		self.queue.retain(|(queued, _, _)| queued != peer);
+		self.active.retain(|(streamed, _), _| streamed != peer);
+	}
+}
@@ -16,14 +16,15 @@
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::behaviour::Behaviour;
+use crate::trusted::TrustedPeers;
+use crate::publish::{PUBLISH_RETRY_INTERVAL, SharedPublishStats};
+use crate::mesh::SharedMeshStats;
+use crate::bandwidth::SharedBandwidth;
 use crate::{NetworkConfig, Error, Libp2pEvent};
-use crate::multiaddr::Protocol;
-use network_messages::PubsubType;
 use futures01::prelude::*;
 use futures01::Stream;
 use libp2p::core::{
     identity::Keypair,
-    multiaddr::Multiaddr,
     muxing::StreamMuxerBox,
     nodes::Substream,
     transport::boxed::Boxed,
@@ -33,7 +34,17 @@ use libp2p::{core, secio, PeerId, Swarm, Transport};
 use libp2p::gossipsub::Topic;
 use beacon::Config;
 use log::*;
-use std::time::Duration;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio_timer::Interval;
+
+/// File name of the persisted network key in the network directory.
+const NETWORK_KEY_FILENAME: &str = "key";
+/// Interval at which trusted peers are checked for redialing.
+const TRUSTED_REDIAL_INTERVAL: Duration = Duration::from_secs(1);
 
 type Libp2pStream = Boxed<(PeerId, StreamMuxerBox), Error>;
 type Libp2pBehaviour<C> = Behaviour<C, Substream<StreamMuxerBox>>;
@@ -44,14 +55,25 @@ pub struct Service<C: Config> {
     pub swarm: Swarm<Libp2pStream, Libp2pBehaviour<C>>,
     /// This node's PeerId.
     pub local_peer_id: PeerId,
+    /// Trusted peers, kept connected.
+    trusted_peers: TrustedPeers,
+    /// Timer for redialing trusted peers.
+    redial_interval: Interval,
+    /// Timer for retrying queued gossip.
+    publish_interval: Interval,
 }
 
 impl<C: Config> Service<C> {
-    pub fn new(config: NetworkConfig) -> Result<Self, Error> {
+    pub fn new(
+        config: NetworkConfig,
+        publish_stats: SharedPublishStats,
+        mesh_stats: SharedMeshStats,
+        bandwidth: SharedBandwidth,
+    ) -> Result<Self, Error> {
         trace!("Libp2p Service starting");
 
         // load the private key from CLI flag, disk or generate a new one
-        let local_private_key = load_private_key();
+        let local_private_key = load_private_key(&config);
         let local_peer_id = PeerId::from(local_private_key.public());
         info!("Libp2p Service {:?}", local_peer_id);
 
@@ -59,31 +81,26 @@ impl<C: Config> Service<C> {
             // Set up the transport - tcp/ws with secio and mplex/yamux
             let transport = build_transport(local_private_key.clone());
             // Lighthouse network behaviour
-            let behaviour = Behaviour::new(&local_private_key, &config)?;
+            let behaviour = Behaviour::new(&local_private_key, &config, publish_stats, mesh_stats, bandwidth)?;
             Swarm::new(transport, behaviour, local_peer_id.clone())
         };
 
-        // listen on the specified address
-        let listen_multiaddr = {
-            let mut m = Multiaddr::from(config.listen_address);
-            m.push(Protocol::Tcp(config.libp2p_port));
-            m
-        };
-
-        match Swarm::listen_on(&mut swarm, listen_multiaddr.clone()) {
-            Ok(_) => {
-                info!("Listening established {}", listen_multiaddr);
-            }
-            Err(err) => {
-                warn!(
-                    "Unable to listen on libp2p address {:?} {}",
-					err,
-                    listen_multiaddr,
-                );
-                return Err("Libp2p was unable to listen on the given listen address."
-						   .to_string().into());
-            }
-        };
+        // listen on the specified addresses
+        for listen_multiaddr in config.listen_multiaddrs() {
+            match Swarm::listen_on(&mut swarm, listen_multiaddr.clone()) {
+                Ok(_) => {
+                    info!("Listening established {}", listen_multiaddr);
+                }
+                Err(err) => {
+                    warn!(
+                        "Unable to listen on libp2p address {:?} {}",
+                        err,
+                        listen_multiaddr,
+                    );
+                    return Err(Error::Listen(listen_multiaddr));
+                }
+            };
+        }
 
         // attempt to connect to user-input libp2p nodes
         for multiaddr in config.libp2p_nodes {
@@ -96,13 +113,9 @@ impl<C: Config> Service<C> {
         }
 
         // subscribe to default gossipsub topics
-        let topics = vec![
-			PubsubType::Block, PubsubType::Attestation,
-			PubsubType::VoluntaryExit, PubsubType::ProposerSlashing,
-			PubsubType::AttesterSlashing,
-		];
-
-		let mut topics = topics.into_iter().map(|v| v.gossipsub_topic()).collect::<Vec<_>>();
+		let mut topics = config.gossip_types.iter().cloned()
+			.flat_map(|typ| config.gossip_encodings.iter().map(move |encoding| typ.gossipsub_topic(*encoding)))
+			.collect::<Vec<_>>();
 
         // Add any topics specified by the user
         topics.append(
@@ -128,8 +141,54 @@ impl<C: Config> Service<C> {
         Ok(Service {
             local_peer_id,
             swarm,
+            trusted_peers: TrustedPeers::new(config.trusted_peers),
+            redial_interval: Interval::new_interval(TRUSTED_REDIAL_INTERVAL),
+            publish_interval: Interval::new_interval(PUBLISH_RETRY_INTERVAL),
         })
     }
+
+    /// Dial trusted peers that are due for a (re)connection attempt.
+    fn redial_trusted_peers(&mut self) {
+        if self.trusted_peers.is_empty() {
+            return
+        }
+
+        loop {
+            match self.redial_interval.poll() {
+                Ok(Async::Ready(Some(_))) => {
+                    for multiaddr in self.trusted_peers.due(Instant::now()) {
+                        match Swarm::dial_addr(&mut self.swarm, multiaddr.clone()) {
+                            Ok(()) => debug!("Dialing trusted peer {}", multiaddr),
+                            Err(err) => debug!(
+                                "Could not connect to trusted peer {}, {:?}", multiaddr, err
+                            ),
+                        }
+                    }
+                },
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => {
+                    warn!("Trusted peer redial timer failed {:?}", e);
+                    break
+                },
+            }
+        }
+    }
+}
+
+impl<C: Config> Service<C> {
+    /// Publish queued gossip whose topic gained peers.
+    fn retry_publish(&mut self) {
+        loop {
+            match self.publish_interval.poll() {
+                Ok(Async::Ready(Some(_))) => self.swarm.retry_publish(Instant::now()),
+                Ok(Async::Ready(None)) | Ok(Async::NotReady) => break,
+                Err(e) => {
+                    warn!("Publish retry timer failed {:?}", e);
+                    break
+                },
+            }
+        }
+    }
 }
 
 impl<C: Config> Stream for Service<C> {
@@ -137,7 +196,18 @@ impl<C: Config> Stream for Service<C> {
     type Error = crate::error::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-		self.swarm.poll().map_err(Into::into)
+        self.redial_trusted_peers();
+        self.retry_publish();
+
+        let event = self.swarm.poll()?;
+        match &event {
+            Async::Ready(Some(Libp2pEvent::PeerDialed(peer))) =>
+                self.trusted_peers.note_connected(peer),
+            Async::Ready(Some(Libp2pEvent::PeerDisconnected(peer))) =>
+                self.trusted_peers.note_disconnected(peer, Instant::now()),
+            _ => (),
+        }
+        Ok(event)
     }
 }
 
@@ -170,8 +240,39 @@ fn build_transport(local_private_key: Keypair) -> Boxed<(PeerId, StreamMuxerBox)
 /// generated and is then saved to disk.
 ///
 /// Currently only secp256k1 keys are allowed, as these are the only keys supported by discv5.
-fn load_private_key() -> Keypair {
+fn load_private_key(config: &NetworkConfig) -> Keypair {
+    let network_key_file = match config.network_dir.as_ref() {
+        Some(network_dir) => network_dir.join(NETWORK_KEY_FILENAME),
+        None => return Keypair::generate_secp256k1(),
+    };
+
+    if let Ok(mut key_bytes) = fs::read(&network_key_file) {
+        if let Ok(secret_key) = core::identity::secp256k1::SecretKey::from_bytes(&mut key_bytes) {
+            let keypair: core::identity::secp256k1::Keypair = secret_key.into();
+            return Keypair::Secp256k1(keypair);
+        }
+        warn!("Invalid network key file {:?}, generating a new key", network_key_file);
+    }
+
     // if a key could not be loaded from disk, generate a new one and save it
     let local_private_key = Keypair::generate_secp256k1();
+    if let Keypair::Secp256k1(keypair) = local_private_key.clone() {
+        if let Err(e) = write_private_key(&network_key_file, &keypair.secret().to_bytes()[..]) {
+            warn!("Could not write network key to {:?}: {:?}", network_key_file, e);
+        }
+    }
     local_private_key
 }
+
+/// Write a private key to a file only readable by its owner. The mode is
+/// set at creation, and restricted again for a replaced invalid key file.
+fn write_private_key(path: &Path, key: &[u8]) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(key)
+}
@@ -0,0 +1,124 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Attestation subnet backbone.
+//!
+//! Independently of validator duties, every node stays subscribed to
+//! `SUBNETS_PER_NODE` attestation subnets derived from its node id, so that
+//! all subnets have long-lived members. The subnets rotate every
+//! `EPOCHS_PER_SUBNET_SUBSCRIPTION` epochs, at an epoch offset also derived
+//! from the node id so that nodes do not all rotate at once.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use beacon::Config;
+use beacon::utils::shuffled_index;
+use crate::discovery::ATTESTATION_SUBNET_COUNT;
+
+/// Number of epochs a backbone subnet subscription lasts.
+pub const EPOCHS_PER_SUBNET_SUBSCRIPTION: u64 = 256;
+/// Number of backbone subnets each node subscribes to.
+pub const SUBNETS_PER_NODE: u64 = 2;
+/// Number of node id bits used to select subnets.
+const ATTESTATION_SUBNET_PREFIX_BITS: u32 = 6;
+
+/// Backbone subnets of a node at an epoch.
+pub fn compute_subscribed_subnets<C: Config>(node_id: &[u8; 32], epoch: u64) -> Vec<u64> {
+	let prefix = (node_id[0] >> (8 - ATTESTATION_SUBNET_PREFIX_BITS)) as u64;
+	let period = (epoch + node_offset(node_id)) / EPOCHS_PER_SUBNET_SUBSCRIPTION;
+	let seed = C::hash(&[&period.to_le_bytes()[..]]);
+	let permutated = shuffled_index::<C>(prefix, 1 << ATTESTATION_SUBNET_PREFIX_BITS, seed)
+		.expect("Prefix is less than the prefix count; qed");
+
+	(0..SUBNETS_PER_NODE)
+		.map(|index| (permutated + index) % ATTESTATION_SUBNET_COUNT)
+		.collect()
+}
+
+/// First epoch after `epoch` at which the backbone subnets of a node rotate.
+pub fn next_rotation_epoch(node_id: &[u8; 32], epoch: u64) -> u64 {
+	let offset = node_offset(node_id);
+	((epoch + offset) / EPOCHS_PER_SUBNET_SUBSCRIPTION + 1) * EPOCHS_PER_SUBNET_SUBSCRIPTION - offset
+}
+
+/// `attnets` ENR bitvector advertising the given subnets.
+pub fn attnets_bitfield(subnets: &[u64]) -> Vec<u8> {
+	let mut bitfield = vec![0u8; (ATTESTATION_SUBNET_COUNT / 8) as usize];
+	for subnet in subnets.iter().filter(|subnet| **subnet < ATTESTATION_SUBNET_COUNT) {
+		bitfield[(subnet / 8) as usize] |= 1 << (subnet % 8);
+	}
+	bitfield
+}
+
+/// Whether an `attnets` bitvector advertises the subnet.
+pub fn attnets_has_subnet(attnets: &[u8], subnet: u64) -> bool {
+	subnet < ATTESTATION_SUBNET_COUNT &&
+		attnets.get((subnet / 8) as usize)
+			.map(|byte| byte & (1 << (subnet % 8)) != 0)
+			.unwrap_or(false)
+}
+
+/// Current epoch by the system clock, zero before genesis.
+pub fn current_epoch<C: Config>(genesis_time: u64) -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH + Duration::from_secs(genesis_time))
+		.map(|elapsed| elapsed.as_secs() / C::seconds_per_slot() / C::slots_per_epoch())
+		.unwrap_or(0)
+}
+
+/// Node id taken modulo `EPOCHS_PER_SUBNET_SUBSCRIPTION`, its last byte.
+fn node_offset(node_id: &[u8; 32]) -> u64 {
+	node_id[31] as u64 % EPOCHS_PER_SUBNET_SUBSCRIPTION
+}
+
+/// Backbone subnets currently subscribed to.
+pub struct SubnetBackbone {
+	node_id: [u8; 32],
+	subnets: Vec<u64>,
+	next_rotation: Option<u64>,
+}
+
+impl SubnetBackbone {
+	/// Create a backbone for a node id, not yet subscribed to any subnet.
+	pub fn new(node_id: [u8; 32]) -> Self {
+		Self { node_id, subnets: Vec::new(), next_rotation: None }
+	}
+
+	/// Subnets currently subscribed to.
+	pub fn subnets(&self) -> &[u64] {
+		&self.subnets
+	}
+
+	/// Move to `epoch`, returning the subnets to leave and to join if the
+	/// subscriptions rotate.
+	pub fn update<C: Config>(&mut self, epoch: u64) -> Option<(Vec<u64>, Vec<u64>)> {
+		if self.next_rotation.map(|next| epoch < next).unwrap_or(false) {
+			return None
+		}
+
+		let subnets = compute_subscribed_subnets::<C>(&self.node_id, epoch);
+		self.next_rotation = Some(next_rotation_epoch(&self.node_id, epoch));
+		let left = self.subnets.iter()
+			.filter(|subnet| !subnets.contains(subnet))
+			.cloned()
+			.collect();
+		let joined = subnets.iter()
+			.filter(|subnet| !self.subnets.contains(subnet))
+			.cloned()
+			.collect();
+		self.subnets = subnets;
+		Some((left, joined))
+	}
+}
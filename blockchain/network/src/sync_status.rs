@@ -0,0 +1,166 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use libp2p::PeerId;
+use log::*;
+
+/// Number of slots the head may be behind the best known peer head without
+/// being considered syncing.
+pub const SYNC_DISTANCE_TOLERANCE: u64 = 8;
+
+/// Sync state shared with other components, such as the node API and the
+/// validator.
+pub type SharedSyncState = Arc<RwLock<SyncState>>;
+
+/// Syncing status of the node.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SyncStatus {
+	/// Whether the node is syncing.
+	pub is_syncing: bool,
+	/// Slot of the local head.
+	pub head_slot: u64,
+	/// Best head slot reported by peers.
+	pub target_slot: u64,
+	/// Slot range of the current block request, start inclusive and end
+	/// exclusive.
+	pub current_batch: Option<(u64, u64)>,
+	/// Peers blocks were requested from during the current sync.
+	pub peers: Vec<PeerId>,
+}
+
+impl SyncStatus {
+	/// Number of slots the head is behind the best known peer head.
+	pub fn sync_distance(&self) -> u64 {
+		self.target_slot.saturating_sub(self.head_slot)
+	}
+}
+
+/// Sync progress events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SyncProgress {
+	/// Started syncing towards a target slot.
+	Started { head_slot: u64, target_slot: u64 },
+	/// The head progressed while syncing.
+	Progress { head_slot: u64, sync_distance: u64 },
+	/// Finished syncing.
+	Completed { head_slot: u64 },
+}
+
+/// Tracks the syncing status, notifying subscribers of progress.
+#[derive(Default)]
+pub struct SyncState {
+	status: SyncStatus,
+	peer_heads: HashMap<PeerId, u64>,
+	peers_used: HashSet<PeerId>,
+	subscribers: Vec<Sender<SyncProgress>>,
+}
+
+impl SyncState {
+	/// Create a new sync state.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Current syncing status.
+	pub fn status(&self) -> &SyncStatus {
+		&self.status
+	}
+
+	/// Whether the node is syncing.
+	pub fn is_syncing(&self) -> bool {
+		self.status.is_syncing
+	}
+
+	/// Head slots most recently reported by connected peers.
+	pub fn peer_head_slots(&self) -> Vec<u64> {
+		self.peer_heads.values().cloned().collect()
+	}
+
+	/// Peers whose reported head is at or after `slot`.
+	pub fn peers_ahead_of(&self, slot: u64) -> impl Iterator<Item=&PeerId> {
+		self.peer_heads.iter()
+			.filter(move |(_, head_slot)| **head_slot >= slot)
+			.map(|(peer, _)| peer)
+	}
+
+	/// Subscribe to sync progress events.
+	pub fn subscribe(&mut self) -> Receiver<SyncProgress> {
+		let (sender, receiver) = channel();
+		self.subscribers.push(sender);
+		receiver
+	}
+
+	/// Note the slot of the local head.
+	pub fn note_head(&mut self, head_slot: u64) {
+		if self.status.head_slot == head_slot {
+			return
+		}
+		self.status.head_slot = head_slot;
+		self.update();
+	}
+
+	/// Note the head slot reported by a peer.
+	pub fn note_peer_head(&mut self, peer: PeerId, head_slot: u64) {
+		self.peer_heads.insert(peer, head_slot);
+		self.update();
+	}
+
+	/// Note that blocks in a slot range were requested from a peer.
+	pub fn note_request(&mut self, peer: PeerId, start_slot: u64, end_slot: u64) {
+		self.status.current_batch = Some((start_slot, end_slot));
+		if self.peers_used.insert(peer.clone()) {
+			self.status.peers.push(peer);
+		}
+	}
+
+	/// Note that a peer has disconnected.
+	pub fn note_disconnected(&mut self, peer: &PeerId) {
+		if self.peer_heads.remove(peer).is_some() {
+			self.update();
+		}
+	}
+
+	fn update(&mut self) {
+		self.status.target_slot = self.peer_heads.values().cloned().max().unwrap_or(0);
+		let was_syncing = self.status.is_syncing;
+		let distance = self.status.sync_distance();
+
+		let event = if !was_syncing && distance > SYNC_DISTANCE_TOLERANCE {
+			info!("Started syncing from slot {} to {}", self.status.head_slot, self.status.target_slot);
+			self.status.is_syncing = true;
+			SyncProgress::Started {
+				head_slot: self.status.head_slot,
+				target_slot: self.status.target_slot,
+			}
+		} else if was_syncing && distance <= SYNC_DISTANCE_TOLERANCE {
+			info!("Finished syncing at slot {}", self.status.head_slot);
+			self.status.is_syncing = false;
+			self.status.current_batch = None;
+			self.status.peers.clear();
+			self.peers_used.clear();
+			SyncProgress::Completed { head_slot: self.status.head_slot }
+		} else if was_syncing {
+			SyncProgress::Progress { head_slot: self.status.head_slot, sync_distance: distance }
+		} else {
+			return
+		};
+
+		self.subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+	}
+}
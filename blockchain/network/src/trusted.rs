@@ -0,0 +1,125 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use libp2p::{Multiaddr, PeerId};
+use libp2p::enr::Enr;
+use libp2p::multiaddr::Protocol;
+use crate::Error;
+
+/// Initial delay before redialing a disconnected trusted peer.
+const INITIAL_REDIAL_DELAY: Duration = Duration::from_secs(1);
+/// Maximum delay between redials of a trusted peer.
+const MAX_REDIAL_DELAY: Duration = Duration::from_secs(300);
+
+/// Peer id contained in the `/p2p` component of an address, if any.
+pub fn peer_id_of(address: &Multiaddr) -> Option<PeerId> {
+	address.iter().filter_map(|protocol| match protocol {
+		Protocol::P2p(hash) => PeerId::from_multihash(hash).ok(),
+		_ => None,
+	}).next()
+}
+
+/// Parse a trusted peer given either as a multiaddr, or as an `enr:` record
+/// with an IPv4 address and a TCP port.
+pub fn parse_trusted_peer(value: &str) -> Result<Multiaddr, Error> {
+	if value.starts_with("enr:") {
+		let enr = Enr::from_str(value).map_err(Error::InvalidPeer)?;
+		let ip = enr.ip().ok_or_else(|| Error::InvalidPeer(format!("ENR {} has no IP address", value)))?;
+		let tcp = enr.tcp().ok_or_else(|| Error::InvalidPeer(format!("ENR {} has no TCP port", value)))?;
+
+		Ok(Multiaddr::from(Protocol::Ip4(ip))
+		   .with(Protocol::Tcp(tcp))
+		   .with(Protocol::P2p(enr.peer_id().into())))
+	} else {
+		Multiaddr::from_str(value)
+			.map_err(|e| Error::InvalidPeer(format!("Invalid multiaddr {}: {}", value, e)))
+	}
+}
+
+/// Peer ids of trusted peers whose addresses contain one.
+pub fn trusted_peer_ids(addresses: &[Multiaddr]) -> HashSet<PeerId> {
+	addresses.iter().filter_map(peer_id_of).collect()
+}
+
+struct TrustedPeer {
+	address: Multiaddr,
+	peer_id: Option<PeerId>,
+	connected: bool,
+	delay: Duration,
+	next_dial: Option<Instant>,
+}
+
+/// Trusted peers, redialed with exponential backoff while disconnected.
+pub struct TrustedPeers {
+	peers: Vec<TrustedPeer>,
+}
+
+impl TrustedPeers {
+	/// Create a new set of trusted peers, all due to be dialed.
+	pub fn new(addresses: Vec<Multiaddr>) -> Self {
+		let now = Instant::now();
+		Self {
+			peers: addresses.into_iter().map(|address| TrustedPeer {
+				peer_id: peer_id_of(&address),
+				address,
+				connected: false,
+				delay: INITIAL_REDIAL_DELAY,
+				next_dial: Some(now),
+			}).collect(),
+		}
+	}
+
+	/// Whether there are no trusted peers.
+	pub fn is_empty(&self) -> bool {
+		self.peers.is_empty()
+	}
+
+	/// Note that a peer has connected.
+	pub fn note_connected(&mut self, peer_id: &PeerId) {
+		for peer in self.peers.iter_mut().filter(|peer| peer.peer_id.as_ref() == Some(peer_id)) {
+			peer.connected = true;
+			peer.delay = INITIAL_REDIAL_DELAY;
+			peer.next_dial = None;
+		}
+	}
+
+	/// Note that a peer has disconnected, scheduling a redial if trusted.
+	pub fn note_disconnected(&mut self, peer_id: &PeerId, now: Instant) {
+		for peer in self.peers.iter_mut().filter(|peer| peer.peer_id.as_ref() == Some(peer_id)) {
+			peer.connected = false;
+			peer.next_dial = Some(now + peer.delay);
+		}
+	}
+
+	/// Addresses due to be dialed at `now`. Each returned peer is scheduled
+	/// for another attempt after a doubled delay, until it connects.
+	pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+		let mut ret = Vec::new();
+		for peer in &mut self.peers {
+			if peer.connected || peer.next_dial.map(|next| next > now).unwrap_or(true) {
+				continue
+			}
+
+			ret.push(peer.address.clone());
+			peer.next_dial = Some(now + peer.delay);
+			peer.delay = std::cmp::min(peer.delay * 2, MAX_REDIAL_DELAY);
+		}
+		ret
+	}
+}
@@ -0,0 +1,145 @@
+//! Iterators over the chains of a store, walking the slot and depth indexes
+//! instead of refetching parents one by one.
+
+use blockchain::Block as BlockT;
+use blockchain::backend::{ChainQuery, Store};
+use beacon::primitives::Slot;
+use core::ops::Range;
+use crate::SlotQuery;
+
+type Identifier<S> = <<S as Store>::Block as BlockT>::Identifier;
+
+/// Roots and slots of a block and its ancestors, newest first.
+pub struct AncestorRoots<'a, S: SlotQuery> {
+	store: &'a S,
+	next: Option<Identifier<S>>,
+}
+
+impl<'a, S: SlotQuery> Iterator for AncestorRoots<'a, S> {
+	type Item = Result<(Identifier<S>, Slot), S::Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let id = self.next.take()?;
+		match self.store.slot_at(&id) {
+			Ok((slot, parent)) => {
+				self.next = parent;
+				Some(Ok((id, slot)))
+			},
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+/// Canonical roots within a slot range, skipping empty slots.
+pub struct CanonRoots<'a, S: SlotQuery> {
+	store: &'a S,
+	slots: Range<Slot>,
+}
+
+impl<'a, S: SlotQuery> CanonRoots<'a, S> {
+	fn lookup(&self, slot: Slot) -> Option<Result<(Slot, Identifier<S>), S::Error>> {
+		match self.store.lookup_canon_slot(slot) {
+			Ok(Some(id)) => Some(Ok((slot, id))),
+			Ok(None) => None,
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+impl<'a, S: SlotQuery> Iterator for CanonRoots<'a, S> {
+	type Item = Result<(Slot, Identifier<S>), S::Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let slot = self.slots.next()?;
+			if let Some(item) = self.lookup(slot) {
+				return Some(item)
+			}
+		}
+	}
+}
+
+impl<'a, S: SlotQuery> DoubleEndedIterator for CanonRoots<'a, S> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		loop {
+			let slot = self.slots.next_back()?;
+			if let Some(item) = self.lookup(slot) {
+				return Some(item)
+			}
+		}
+	}
+}
+
+/// Canonical blocks within a slot range, skipping empty slots.
+pub struct CanonBlocks<'a, S: SlotQuery + ChainQuery> {
+	roots: CanonRoots<'a, S>,
+}
+
+impl<'a, S: SlotQuery + ChainQuery> Iterator for CanonBlocks<'a, S> {
+	type Item = Result<S::Block, S::Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let store = self.roots.store;
+		self.roots.next().map(|root| root.and_then(|(_, id)| store.block_at(&id)))
+	}
+}
+
+impl<'a, S: SlotQuery + ChainQuery> DoubleEndedIterator for CanonBlocks<'a, S> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		let store = self.roots.store;
+		self.roots.next_back().map(|root| root.and_then(|(_, id)| store.block_at(&id)))
+	}
+}
+
+/// Canonical blocks from a depth onwards, ending at the head.
+pub struct CanonBlocksByDepth<'a, S: ChainQuery> {
+	store: &'a S,
+	depths: Range<usize>,
+}
+
+impl<'a, S: ChainQuery> Iterator for CanonBlocksByDepth<'a, S> {
+	type Item = Result<S::Block, S::Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let depth = self.depths.next()?;
+		match self.store.lookup_canon_depth(depth) {
+			Ok(Some(id)) => Some(self.store.block_at(&id)),
+			Ok(None) => {
+				self.depths = 0..0;
+				None
+			},
+			Err(e) => Some(Err(e)),
+		}
+	}
+}
+
+/// Iterators over the chains of a store.
+pub trait StoreIterators: SlotQuery + Sized {
+	/// Roots and slots of `from` and its ancestors, newest first, ending at
+	/// genesis or at the oldest stored block.
+	fn iter_ancestor_roots(&self, from: Identifier<Self>) -> AncestorRoots<'_, Self> {
+		AncestorRoots { store: self, next: Some(from) }
+	}
+
+	/// Slots and roots of the canonical blocks in `slots`. Use `rev` to walk
+	/// the chain backwards.
+	fn iter_canon_roots(&self, slots: Range<Slot>) -> CanonRoots<'_, Self> {
+		CanonRoots { store: self, slots }
+	}
+
+	/// Canonical blocks in `slots`. Use `rev` to walk the chain backwards.
+	fn iter_blocks(&self, slots: Range<Slot>) -> CanonBlocks<'_, Self> where
+		Self: ChainQuery,
+	{
+		CanonBlocks { roots: self.iter_canon_roots(slots) }
+	}
+
+	/// At most `count` canonical blocks starting at `start_depth`.
+	fn iter_blocks_by_depth(&self, start_depth: usize, count: usize) -> CanonBlocksByDepth<'_, Self> where
+		Self: ChainQuery,
+	{
+		CanonBlocksByDepth { store: self, depths: start_depth..start_depth.saturating_add(count) }
+	}
+}
+
+impl<S: SlotQuery> StoreIterators for S { }
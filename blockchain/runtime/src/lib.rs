@@ -1,12 +1,25 @@
 use blockchain::Block as BlockT;
+use blockchain::backend::Store;
 use bm_le::tree_root;
 use beacon::{
 	BeaconState, Config,
-	primitives::H256,
-	types::{BeaconBlock, BeaconBlockHeader, SigningBeaconBlockHeader}
+	primitives::{H256, Uint, Version, Epoch, Slot, Signature},
+	types::{BeaconBlock, UnsealedBeaconBlock, BeaconBlockBody, BeaconBlockHeader, SigningBeaconBlockHeader, Fork}
 };
+use serde::{Serialize, Deserialize};
+use std::sync::{Arc, Mutex, RwLock};
 
-#[derive(Eq, PartialEq, Clone, Debug, parity_codec::Encode, parity_codec::Decode)]
+mod iter;
+
+pub use crate::iter::{
+	StoreIterators, AncestorRoots, CanonRoots, CanonBlocks, CanonBlocksByDepth,
+};
+
+/// Sealed beacon block, signed by its proposer. Blocks are imported and
+/// gossiped only in this form; blocks being built are `UnsealedBeaconBlock`s
+/// until they are finalized and sealed.
+#[derive(Eq, PartialEq, Clone, Debug, parity_codec::Encode, parity_codec::Decode, Serialize, Deserialize)]
+#[serde(transparent, bound = "C: Config")]
 pub struct Block<C: Config>(pub BeaconBlock<C>);
 
 impl<C: Config> ssz::Codec for Block<C> {
@@ -20,32 +33,106 @@ impl<C: Config> ssz::Encode for Block<C> {
 }
 
 impl<C: Config> ssz::Decode for Block<C> {
-	fn decode(value: &[u8]) -> Result<Self, ssz::Error> {
-		Ok(Block(ssz::Decode::decode(value)?))
+	fn decode_with(value: &[u8], context: ssz::DecodeContext) -> Result<Self, ssz::Error> {
+		Ok(Block(ssz::Decode::decode_with(value, context)?))
 	}
 }
 
-impl<C: Config> BlockT for Block<C> {
-	type Identifier = H256;
+/// Whether `root` is the zero root, standing for genesis in parent roots and
+/// checkpoints.
+pub fn is_genesis_root(root: &H256) -> bool {
+	*root == H256::default()
+}
 
-	fn id(&self) -> H256 {
-		let header = BeaconBlockHeader {
+/// Parent of a block or header with the given parent root. Only the genesis
+/// block has no parent, which is encoded as a zero parent root.
+pub fn parent_id_of(parent_root: H256) -> Option<H256> {
+	if is_genesis_root(&parent_root) {
+		None
+	} else {
+		Some(parent_root)
+	}
+}
+
+impl<C: Config> Block<C> {
+	/// Genesis block of a genesis state: an empty body at the genesis slot,
+	/// without parent, committing to the state root.
+	pub fn genesis(state: &BeaconState<C>) -> Self {
+		Block(BeaconBlock {
+			slot: C::genesis_slot(),
+			parent_root: H256::default(),
+			state_root: tree_root::<C::Digest, _>(state),
+			body: BeaconBlockBody::default(),
+			..Default::default()
+		})
+	}
+
+	/// Seal a finalized block with the proposer signature.
+	pub fn seal(block: UnsealedBeaconBlock<C>, signature: Signature) -> Self {
+		Block(block.seal(signature))
+	}
+
+	/// Whether this is a genesis block, without parent.
+	pub fn is_genesis(&self) -> bool {
+		self.parent_id().is_none()
+	}
+
+	/// Whether the block has no parent exactly if it is at the genesis slot.
+	pub fn has_consistent_parent(&self) -> bool {
+		self.is_genesis() == (self.0.slot == C::genesis_slot())
+	}
+
+	/// Slot of the block.
+	pub fn slot(&self) -> Slot {
+		self.0.slot
+	}
+
+	/// Root of the parent block, zero for the genesis block.
+	pub fn parent_root(&self) -> H256 {
+		self.0.parent_root
+	}
+
+	/// Root of the post state of the block.
+	pub fn state_root(&self) -> H256 {
+		self.0.state_root
+	}
+
+	/// Body of the block.
+	pub fn body(&self) -> &BeaconBlockBody<C> {
+		&self.0.body
+	}
+
+	/// Proposer signature of the block.
+	pub fn signature(&self) -> &Signature {
+		&self.0.signature
+	}
+
+	/// Unwrap the beacon block.
+	pub fn into_inner(self) -> BeaconBlock<C> {
+		self.0
+	}
+
+	/// Header of the block, with the body replaced by its root.
+	pub fn header(&self) -> BeaconBlockHeader {
+		BeaconBlockHeader {
 			slot: self.0.slot,
 			parent_root: self.0.parent_root,
 			state_root: self.0.state_root,
 			body_root: tree_root::<C::Digest, _>(&self.0.body),
-			..Default::default()
-		};
+			signature: self.0.signature.clone(),
+		}
+	}
+}
 
-		tree_root::<C::Digest, _>(&SigningBeaconBlockHeader::from(header.clone()))
+impl<C: Config> BlockT for Block<C> {
+	type Identifier = H256;
+
+	fn id(&self) -> H256 {
+		tree_root::<C::Digest, _>(&SigningBeaconBlockHeader::from(self.header()))
 	}
 
 	fn parent_id(&self) -> Option<H256> {
-		if self.0.parent_root == H256::default() {
-			None
-		} else {
-			Some(self.0.parent_root)
-		}
+		parent_id_of(self.0.parent_root)
 	}
 }
 
@@ -61,9 +148,110 @@ impl<C: Config> Into<BeaconBlock<C>> for Block<C> {
 	}
 }
 
+/// Access to a beacon state. Paths that must not mutate the state should
+/// take the externalities by shared reference, which only allows the
+/// read-only accessors.
 pub trait StateExternalities {
 	type Config: Config;
 
 	fn state(&self) -> &BeaconState<Self::Config>;
 	fn state_mut(&mut self) -> &mut BeaconState<Self::Config>;
+
+	/// Root of the state. Implementations may cache it until the state is
+	/// next borrowed mutably.
+	fn state_root(&self) -> H256 {
+		tree_root::<<Self::Config as Config>::Digest, _>(self.state())
+	}
+
+	/// Slot of the state.
+	fn slot(&self) -> Slot {
+		self.state().slot
+	}
+
+	/// Current epoch of the state.
+	fn epoch(&self) -> Epoch {
+		beacon::utils::epoch_of_slot::<Self::Config>(self.state().slot)
+	}
+}
+
+/// Cached state root, to be cleared whenever the state is borrowed mutably.
+#[derive(Default, Debug)]
+pub struct StateRootCache(Mutex<Option<H256>>);
+
+impl StateRootCache {
+	/// Cached root, computing it with `f` if not cached.
+	pub fn get_or_compute<F: FnOnce() -> H256>(&self, f: F) -> H256 {
+		let mut root = self.0.lock().expect("Lock is poisoned");
+		*root.get_or_insert_with(f)
+	}
+
+	/// Clear the cached root.
+	pub fn clear(&mut self) {
+		*self.0.get_mut().expect("Lock is poisoned") = None;
+	}
+}
+
+impl Clone for StateRootCache {
+	fn clone(&self) -> Self {
+		Self(Mutex::new(*self.0.lock().expect("Lock is poisoned")))
+	}
+}
+
+/// Indexed slot lookups, avoiding walking the chain backwards from head.
+pub trait SlotQuery: Store {
+	/// Canonical block at the given slot. `None` if the slot is skipped or
+	/// beyond the head.
+	fn lookup_canon_slot(
+		&self,
+		slot: u64,
+	) -> Result<Option<<Self::Block as BlockT>::Identifier>, Self::Error>;
+	/// Slot and parent of a block.
+	fn slot_at(
+		&self,
+		id: &<Self::Block as BlockT>::Identifier,
+	) -> Result<(u64, Option<<Self::Block as BlockT>::Identifier>), Self::Error>;
+}
+
+/// Header lookups, avoiding deserializing full block bodies.
+pub trait HeaderQuery: Store {
+	/// Header of the block at `id`. `None` if the block does not exist.
+	fn get_header(
+		&self,
+		id: &<Self::Block as BlockT>::Identifier,
+	) -> Result<Option<BeaconBlockHeader>, Self::Error>;
+}
+
+/// Identity of a chain, fixed at genesis except for the current fork.
+#[derive(Eq, PartialEq, Clone, Debug, parity_codec::Encode, parity_codec::Decode)]
+pub struct ChainInfo {
+	/// Genesis time as Unix timestamp.
+	pub genesis_time: u64,
+	/// Root of the genesis validator registry.
+	pub genesis_validators_root: H256,
+	/// Current fork.
+	pub fork: Fork,
+}
+
+/// Chain identity shared between components.
+pub type SharedChainInfo = Arc<RwLock<ChainInfo>>;
+
+impl ChainInfo {
+	/// Chain identity of the given genesis state.
+	pub fn from_genesis<C: Config>(state: &BeaconState<C>) -> Self {
+		Self {
+			genesis_time: state.genesis_time,
+			genesis_validators_root: tree_root::<C::Digest, _>(&state.validators),
+			fork: state.fork.clone(),
+		}
+	}
+
+	/// Fork version in effect at `epoch`.
+	pub fn fork_version(&self, epoch: Epoch) -> Version {
+		beacon::domain::fork_version(&self.fork, epoch)
+	}
+
+	/// Signing domain of `domain_type` at `epoch`.
+	pub fn domain(&self, domain_type: u32, epoch: Epoch) -> Uint {
+		beacon::domain::domain(domain_type, &self.fork, epoch)
+	}
 }
@@ -0,0 +1,69 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Privileged node API.
+//!
+//! Served on the admin address of an API configuration, to requests
+//! bearing its token. Currently exposes the health of the gossip topics, so
+//! that under-peered topics are visible to operators.
+
+use std::sync::RwLock;
+use std::time::Instant;
+use serde::Serialize;
+use shasper_network::{MeshStats, SharedMeshStats, TopicStats};
+use crate::api_auth::ApiConfig;
+use crate::http::{self, Request, Response};
+
+/// Path of the gossip topic statistics.
+pub const GOSSIP_TOPICS_PATH: &str = "/admin/gossip/topics";
+
+/// Statistics of a gossip topic, as served.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct TopicReport {
+	/// Topic name.
+	pub topic: String,
+	/// Statistics of the topic.
+	#[serde(flatten)]
+	pub stats: TopicStats,
+}
+
+/// Statistics of all known gossip topics, with under-peered topics first.
+pub fn gossip_topics(mesh_stats: &RwLock<MeshStats>) -> Vec<TopicReport> {
+	let mut mesh_stats = mesh_stats.write().expect("Lock is poisoned");
+	mesh_stats.roll(Instant::now());
+	let mut reports = mesh_stats.topics()
+		.map(|(topic, stats)| TopicReport { topic: topic.clone(), stats: stats.clone() })
+		.collect::<Vec<_>>();
+	reports.sort_by_key(|report| !report.stats.under_peered);
+	reports
+}
+
+/// Serve the admin endpoints on the admin address of `api`, from a
+/// background thread. Nothing is served if the admin API is disabled.
+pub fn serve(api: ApiConfig, mesh_stats: SharedMeshStats) -> Result<(), http::Error> {
+	http::serve_admin(api, "admin API", move |request| route(&mesh_stats, request))
+}
+
+fn route(mesh_stats: &SharedMeshStats, request: &Request) -> Response {
+	if request.path != GOSSIP_TOPICS_PATH {
+		return http::error(404, "Not found")
+	}
+	let mut topics = gossip_topics(mesh_stats);
+	if request.param("under_peered") == Some("true") {
+		topics.retain(|report| report.stats.under_peered);
+	}
+	(200, serde_json::json!({ "data": topics }).to_string())
+}
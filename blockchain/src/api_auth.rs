@@ -0,0 +1,189 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access policy of the node HTTP APIs.
+//!
+//! Read-only endpoints are served on a public address, while mutating and
+//! admin endpoints are served on a separate privileged address and require
+//! a bearer token. Browsers are only allowed cross-origin access from the
+//! configured origins.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use rand::Rng;
+use crate::tls::ServerTls;
+
+/// Default port of the public read-only API.
+pub const DEFAULT_PUBLIC_API_PORT: u16 = 5052;
+/// Default port of the privileged admin API.
+pub const DEFAULT_ADMIN_API_PORT: u16 = 5053;
+/// Length of generated tokens, in bytes.
+pub const TOKEN_LENGTH: usize = 32;
+
+/// Class of an API endpoint.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+	/// Read-only endpoint, served on the public address.
+	Public,
+	/// Mutating or admin endpoint, served on the admin address with a token.
+	Admin,
+}
+
+/// Reason a request is refused.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Denied {
+	/// Admin endpoint requested on the public address.
+	WrongListener,
+	/// Missing or malformed authorization header.
+	MissingToken,
+	/// The token does not match.
+	InvalidToken,
+}
+
+impl Denied {
+	/// HTTP status to answer with.
+	pub fn status(&self) -> u16 {
+		match self {
+			Denied::WrongListener => 404,
+			Denied::MissingToken => 401,
+			Denied::InvalidToken => 403,
+		}
+	}
+}
+
+impl fmt::Display for Denied {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Denied::WrongListener => write!(f, "Endpoint is not served on this address"),
+			Denied::MissingToken => write!(f, "Missing bearer token"),
+			Denied::InvalidToken => write!(f, "Invalid bearer token"),
+		}
+	}
+}
+
+impl std::error::Error for Denied { }
+
+/// API access configuration.
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+	/// Address of the public read-only API. Disabled if not set.
+	pub public_address: Option<SocketAddr>,
+	/// Address of the privileged API. Disabled if not set.
+	pub admin_address: Option<SocketAddr>,
+	/// Token required by privileged endpoints.
+	pub token: String,
+	/// Origins allowed for cross-origin requests. `*` allows any origin.
+	pub cors_origins: Vec<String>,
+	/// Serve over HTTPS with the given certificate, on both addresses.
+	pub tls: Option<ServerTls>,
+}
+
+impl Default for ApiConfig {
+	fn default() -> Self {
+		Self {
+			public_address: Some((Ipv4Addr::LOCALHOST, DEFAULT_PUBLIC_API_PORT).into()),
+			admin_address: Some((Ipv4Addr::LOCALHOST, DEFAULT_ADMIN_API_PORT).into()),
+			token: generate_token(),
+			cors_origins: Vec::new(),
+			tls: None,
+		}
+	}
+}
+
+impl ApiConfig {
+	/// Check a request for an endpoint of class `access`, received on the
+	/// privileged listener if `admin_listener`, with the given value of the
+	/// `Authorization` header.
+	pub fn authorize(
+		&self,
+		access: Access,
+		admin_listener: bool,
+		authorization: Option<&str>,
+	) -> Result<(), Denied> {
+		if access == Access::Public {
+			return Ok(())
+		}
+		if !admin_listener {
+			return Err(Denied::WrongListener)
+		}
+
+		let token = authorization
+			.filter(|value| value.starts_with("Bearer "))
+			.map(|value| value["Bearer ".len()..].trim())
+			.ok_or(Denied::MissingToken)?;
+		if constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+			Ok(())
+		} else {
+			Err(Denied::InvalidToken)
+		}
+	}
+
+	/// Value of the `Access-Control-Allow-Origin` header for a request from
+	/// `origin`, if it is allowed.
+	pub fn allow_origin(&self, origin: &str) -> Option<String> {
+		self.cors_origins.iter()
+			.find(|allowed| *allowed == "*" || allowed.trim_end_matches('/') == origin.trim_end_matches('/'))
+			.map(|allowed| if allowed == "*" { "*".to_string() } else { origin.to_string() })
+	}
+}
+
+/// Parse comma separated CORS origins.
+pub fn parse_cors_origins(value: &str) -> Vec<String> {
+	value.split(',')
+		.map(|origin| origin.trim())
+		.filter(|origin| !origin.is_empty())
+		.map(|origin| origin.to_string())
+		.collect()
+}
+
+/// Generate a random hex encoded token.
+pub fn generate_token() -> String {
+	let mut bytes = [0u8; TOKEN_LENGTH];
+	rand::thread_rng().fill(&mut bytes[..]);
+	hex::encode(bytes)
+}
+
+/// Load the token stored at `path`, or generate and store one, readable
+/// only by the owner.
+pub fn load_or_create_token(path: &Path) -> io::Result<String> {
+	match fs::read_to_string(path) {
+		Ok(token) => return Ok(token.trim().to_string()),
+		Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+		Err(e) => return Err(e),
+	}
+
+	let token = generate_token();
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	// The mode applies at creation, so the token is never readable by others.
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	options.open(path)?.write_all(token.as_bytes())?;
+	Ok(token)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false
+	}
+	a.iter().zip(b.iter()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
@@ -0,0 +1,152 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Data availability of shard blobs.
+//!
+//! Blocks committing to shard blobs are held until all committed blobs have
+//! been received, and imported then. Blocks without commitments pass
+//! through.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use beacon::Config;
+use beacon::primitives::{H256, Slot};
+use blockchain::Block as BlockT;
+use blockchain::import::BlockImporter;
+use log::{debug, warn};
+use crate::Block;
+
+/// Maximum number of blocks held waiting for their blobs.
+pub const MAX_UNAVAILABLE_BLOCKS: usize = 64;
+
+#[derive(Debug)]
+/// Availability queue errors.
+pub enum Error<E> {
+	/// Too many blocks are already held.
+	Full,
+	/// Importing the block failed.
+	Import(E),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::Full => write!(f, "Availability queue is full"),
+			Error::Import(e) => write!(f, "Importing block failed: {:?}", e),
+		}
+	}
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> { }
+
+/// Root of shard blob data, as committed to in blocks.
+pub fn blob_root<C: Config>(data: &[u8]) -> H256 {
+	C::hash(&[data])
+}
+
+struct Inner<C: Config, I> {
+	importer: I,
+	blobs: HashMap<H256, Slot>,
+	held: Vec<Block<C>>,
+}
+
+impl<C: Config, I> Inner<C, I> {
+	fn is_available(&self, block: &Block<C>) -> bool {
+		block.body().shard_blob_commitments.iter()
+			.all(|commitment| self.blobs.contains_key(&commitment.data_root))
+	}
+}
+
+/// Block importer holding blocks until their committed shard blobs are
+/// available, before passing them to the inner importer.
+pub struct AvailabilityQueue<C: Config, I> {
+	inner: Arc<Mutex<Inner<C, I>>>,
+}
+
+impl<C: Config, I> Clone for AvailabilityQueue<C, I> {
+	fn clone(&self) -> Self {
+		Self { inner: self.inner.clone() }
+	}
+}
+
+impl<C: Config, I: BlockImporter<Block=Block<C>>> AvailabilityQueue<C, I> {
+	/// Create a new availability queue importing into `importer`.
+	pub fn new(importer: I) -> Self {
+		Self {
+			inner: Arc::new(Mutex::new(Inner {
+				importer,
+				blobs: HashMap::new(),
+				held: Vec::new(),
+			})),
+		}
+	}
+
+	/// Whether all shard blobs committed to by the block are available.
+	pub fn is_available(&self, block: &Block<C>) -> bool {
+		self.inner.lock().expect("Lock is poisoned").is_available(block)
+	}
+
+	/// Number of blocks held waiting for their blobs.
+	pub fn held_len(&self) -> usize {
+		self.inner.lock().expect("Lock is poisoned").held.len()
+	}
+
+	/// Record a received shard blob of `slot`, importing held blocks that
+	/// become available.
+	pub fn add_blob(&self, slot: Slot, data: &[u8]) {
+		let mut inner = self.inner.lock().expect("Lock is poisoned");
+		inner.blobs.insert(blob_root::<C>(data), slot);
+
+		let held = std::mem::replace(&mut inner.held, Vec::new());
+		let (ready, held): (Vec<_>, Vec<_>) = held.into_iter()
+			.partition(|block| inner.is_available(block));
+		inner.held = held;
+
+		for block in ready {
+			let id = block.id();
+			if let Err(e) = inner.importer.import_block(block) {
+				warn!("Importing available block {:?} failed: {:?}", id, e);
+			}
+		}
+	}
+
+	/// Drop blobs and held blocks of slots before `slot`.
+	pub fn prune(&self, slot: Slot) {
+		let mut inner = self.inner.lock().expect("Lock is poisoned");
+		inner.blobs.retain(|_, blob_slot| *blob_slot >= slot);
+		inner.held.retain(|block| block.slot() >= slot);
+	}
+}
+
+impl<C: Config, I: BlockImporter<Block=Block<C>>> BlockImporter for AvailabilityQueue<C, I> {
+	type Block = Block<C>;
+	type Error = Error<I::Error>;
+
+	fn import_block(&mut self, block: Block<C>) -> Result<(), Self::Error> {
+		let mut inner = self.inner.lock().expect("Lock is poisoned");
+		if inner.is_available(&block) {
+			return inner.importer.import_block(block).map_err(Error::Import)
+		}
+
+		if inner.held.len() >= MAX_UNAVAILABLE_BLOCKS {
+			return Err(Error::Full)
+		}
+
+		debug!("Holding block {:?} until its shard blobs are available", block.id());
+		inner.held.push(block);
+		Ok(())
+	}
+}
@@ -13,99 +13,375 @@
 
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
-use blockchain::{Block, Auxiliary};
+use std::sync::{Arc, Mutex, RwLock};
+use blockchain::{Block as BlockT, Auxiliary};
 use blockchain::backend::{Store, SharedCommittable, ChainQuery, Operation};
+use blockchain_rocksdb::{RocksBackend, RocksState as RocksStateT};
 use lmd_ghost::archive::{AncestorQuery, NoCacheAncestorQuery};
+use lmd_ghost::persist::ForkChoicePersistence;
+use parity_codec::{Encode, Decode};
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::types::BeaconBlockHeader;
+use log::warn;
+use crate::{Block, SlotQuery, HeaderQuery, StateExternalities, ChainInfo, Error, parent_id_of, is_genesis_root};
+use crate::index::{self, IndexStore, MemoryIndexStore};
+use crate::historical::{self, HistoricalRootProof, RecentRoots};
+use crate::chain_info::{self, NetworkId};
+use crate::state_cache::{self, StateCache, SharedStateCache, DEFAULT_STATE_CACHE_SIZE};
+use crate::checkpoint_state::{self, SharedCheckpointStateCache};
+use crate::validity::ValidityStore;
+use crate::explorer::ExplorerIndex;
 
-pub struct ShasperBackend<Ba>(Ba);
+type StateConfig<Ba> = <<Ba as Store>::State as StateExternalities>::Config;
 
-impl<Ba> ShasperBackend<Ba> {
+pub struct ShasperBackend<Ba: Store> where Ba::State: StateExternalities {
+	backend: Ba,
+	index: Arc<dyn IndexStore + Send + Sync>,
+	state_cache: SharedStateCache<Ba::State>,
+	checkpoint_states: Option<SharedCheckpointStateCache<StateConfig<Ba>>>,
+	recent_roots: Arc<RwLock<Option<RecentRoots>>>,
+	archive: bool,
+	explorer: bool,
+}
+
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C> + Clone,
+	Error: From<Ba::Error>,
+{
+	/// Create a new backend with in-memory block indices.
 	pub fn new(backend: Ba) -> Self {
-		Self(backend)
+		Self::with_index(backend, MemoryIndexStore::default(), false)
+	}
+
+	/// Create a new backend with the given block index storage, bringing the
+	/// indices up to date with the current head. In archive mode, historical
+	/// batches are retained as the chain crosses their boundaries.
+	pub fn with_index<I: IndexStore + Send + Sync + 'static>(
+		backend: Ba,
+		index: I,
+		archive: bool,
+	) -> Self {
+		let backend = Self {
+			backend,
+			index: Arc::new(index),
+			state_cache: Arc::new(Mutex::new(StateCache::new(DEFAULT_STATE_CACHE_SIZE))),
+			checkpoint_states: None,
+			recent_roots: Arc::new(RwLock::new(None)),
+			archive,
+			explorer: false,
+		};
+		backend.update_index();
+		backend.pin_states();
+		backend
+	}
+
+	/// Keep recently used post-states in memory up to `max_bytes`.
+	pub fn with_state_cache_size(self, max_bytes: usize) -> Self {
+		self.state_cache.lock().expect("Lock is poisoned").set_max_bytes(max_bytes);
+		self
+	}
+
+	/// Keep the state of the justified checkpoint of the head in the given
+	/// cache, for fork choice.
+	pub fn with_checkpoint_states(mut self, cache: SharedCheckpointStateCache<C>) -> Self {
+		self.checkpoint_states = Some(cache);
+		self.pin_states();
+		self
+	}
+
+	/// Maintain explorer indices of proposers and attestation inclusions as
+	/// blocks become canonical. Blocks canonical before are not indexed.
+	pub fn with_explorer_index(mut self) -> Self {
+		self.explorer = true;
+		self
+	}
+
+	/// Explorer indices, stored along with block indices.
+	pub fn explorer_index(&self) -> ExplorerIndex {
+		ExplorerIndex::new(self.index.clone())
+	}
+
+	/// Store of block indices, also holding other node data.
+	pub fn index_store(&self) -> Arc<dyn IndexStore + Send + Sync> {
+		self.index.clone()
+	}
+
+	/// Execution validity of blocks, stored along with block indices.
+	pub fn validity_store(&self) -> ValidityStore {
+		ValidityStore::new(self.index.clone())
+	}
+
+	/// Shared cache of recently used post-states.
+	pub fn state_cache(&self) -> &SharedStateCache<Ba::State> {
+		&self.state_cache
+	}
+
+	/// Persisted chain identity, initialized to `genesis` on first start.
+	/// Fails if the store was created for another network or config.
+	pub fn load_chain_info(&self, genesis: ChainInfo, config_name: &str) -> Result<ChainInfo, Error> {
+		chain_info::check_network(self.index.as_ref(), &NetworkId {
+			config_name: config_name.to_string(),
+			genesis_validators_root: genesis.genesis_validators_root,
+		})?;
+		chain_info::load_or_init(self.index.as_ref(), genesis)
+	}
+
+	/// Block root at any slot, from the head state or, in archive mode, from
+	/// retained historical batches.
+	pub fn block_root_at_slot(&self, slot: u64) -> Result<Option<H256>, Error> {
+		Ok(self.historical_roots_at_slot(slot)?.map(|(block_root, _)| block_root))
+	}
+
+	/// State root at any slot, from the head state or, in archive mode, from
+	/// retained historical batches.
+	pub fn state_root_at_slot(&self, slot: u64) -> Result<Option<H256>, Error> {
+		Ok(self.historical_roots_at_slot(slot)?.map(|(_, state_root)| state_root))
+	}
+
+	/// Prove the block root at `slot` against the state root of block `id`.
+	/// `None` if the batch covering the slot is not retained.
+	pub fn historical_root_proof(
+		&self,
+		slot: u64,
+		id: &H256,
+	) -> Result<Option<HistoricalRootProof>, Error> {
+		let batch_index = slot / C::slots_per_historical_root();
+		let batch = match historical::batch::<C>(self.index.as_ref(), batch_index)? {
+			Some(batch) => batch,
+			None => return Ok(None),
+		};
+		let state = self.backend.state_at(id).map_err(Error::from)?;
+
+		Ok(Some(historical::prove_block_root(state.state(), &batch, slot)?))
+	}
+
+	fn historical_roots_at_slot(&self, slot: u64) -> Result<Option<(H256, H256)>, Error> {
+		let slots = C::slots_per_historical_root();
+		let batch_count = {
+			let recent_roots = self.recent_roots.read().expect("Lock is poisoned");
+			let recent_roots = recent_roots.as_ref().ok_or(Error::Missing("head state roots"))?;
+			if let Some(roots) = recent_roots.at_slot(slot) {
+				return Ok(Some(roots))
+			}
+			recent_roots.batch_count
+		};
+
+		// Batches past those of the head state are left from a reorg.
+		if slot / slots >= batch_count {
+			return Ok(None)
+		}
+		let i = (slot % slots) as usize;
+		Ok(historical::batch::<C>(self.index.as_ref(), slot / slots)?
+			.map(|batch| (batch.block_roots[i], batch.state_roots[i])))
+	}
+
+	fn archive_batches(&self, canon: &[H256]) -> Result<(), Error> {
+		let slots = C::slots_per_historical_root();
+
+		for id in canon {
+			let header = self.get_header(id).map_err(Error::from)?
+				.ok_or(Error::Missing("canonical block header"))?;
+			let parent_id = match parent_id_of(header.parent_root) {
+				Some(parent_id) => parent_id,
+				None => continue,
+			};
+			let parent_header = self.get_header(&parent_id).map_err(Error::from)?
+				.ok_or(Error::Missing("canonical block header"))?;
+			if parent_header.slot / slots == header.slot / slots {
+				continue
+			}
+
+			let parent = self.backend.state_at(&parent_id).map_err(Error::from)?;
+			let state = self.backend.state_at(id).map_err(Error::from)?;
+			// Batches are overwritten, so that the ones of a chain reorged
+			// away are replaced, or removed if they cannot be reconstructed.
+			let start = parent.state().historical_roots.len() as u64;
+			for index in start..(state.state().historical_roots.len() as u64) {
+				let batch = if index == start {
+					historical::reconstruct_batch(parent.state(), state.state())
+				} else {
+					None
+				};
+				if batch.is_none() {
+					warn!("Unable to archive historical batch {}", index);
+				}
+				historical::insert_batch(self.index.as_ref(), index, batch.as_ref())?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn index_explorer(&self, canon: &[H256]) -> Result<(), Error> {
+		let explorer = self.explorer_index();
+		for id in canon {
+			let block = self.backend.block_at(id).map_err(Error::from)?;
+			let state = self.state_at(id).map_err(Error::from)?;
+			explorer.index_block(*id, &block.0, state.state())?;
+		}
+
+		Ok(())
+	}
+
+	fn update_index(&self) {
+		let result = index::update_canon(self.index.as_ref(), self.backend.head(), |id| {
+			self.fetch_header(id).map_err(Error::from)
+		}).and_then(|canon| {
+			if self.archive {
+				self.archive_batches(&canon)?;
+			}
+			if self.explorer {
+				self.index_explorer(&canon)?;
+			}
+			Ok(())
+		});
+
+		if let Err(e) = result {
+			warn!("Failed to update block indices: {:?}", e);
+		}
+	}
+
+	/// Pin the head and justified states in the state cache, cache the
+	/// justified checkpoint state, and keep the recent roots of the head.
+	fn pin_states(&self) {
+		let head = self.backend.head();
+		let justified = match self.state_at(&head) {
+			Ok(state) => {
+				*self.recent_roots.write().expect("Lock is poisoned") =
+					Some(RecentRoots::new(head, state.state()));
+				state.state().current_justified_checkpoint.clone()
+			},
+			Err(e) => {
+				warn!("Failed to load head state: {:?}", e);
+				return
+			},
+		};
+		self.state_cache.lock().expect("Lock is poisoned").set_pinned(vec![head, justified.root]);
+
+		if let Some(cache) = self.checkpoint_states.as_ref() {
+			if !is_genesis_root(&justified.root) &&
+				!cache.lock().expect("Lock is poisoned").contains(&justified)
+			{
+				if let Err(e) = checkpoint_state::checkpoint_state(cache, self, &justified) {
+					warn!("Failed to compute justified checkpoint state: {:?}", e);
+				}
+			}
+		}
 	}
 }
 
-impl<Ba: Clone> Clone for ShasperBackend<Ba> {
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C>,
+{
+	fn fetch_header(&self, id: &H256) -> Result<BeaconBlockHeader, Ba::Error> {
+		Ok(self.backend.block_at(id)?.header())
+	}
+}
+
+impl<Ba: Store + Clone> Clone for ShasperBackend<Ba> where Ba::State: StateExternalities {
 	fn clone(&self) -> Self {
-		Self(self.0.clone())
+		Self {
+			backend: self.backend.clone(),
+			index: self.index.clone(),
+			state_cache: self.state_cache.clone(),
+			checkpoint_states: self.checkpoint_states.clone(),
+			recent_roots: self.recent_roots.clone(),
+			archive: self.archive,
+			explorer: self.explorer,
+		}
 	}
 }
 
-impl<Ba: Store> Store for ShasperBackend<Ba> {
+impl<Ba: Store> Store for ShasperBackend<Ba> where Ba::State: StateExternalities {
 	type Block = Ba::Block;
 	type State = Ba::State;
 	type Auxiliary = Ba::Auxiliary;
 	type Error = Ba::Error;
 }
 
-impl<Ba: ChainQuery> ChainQuery for ShasperBackend<Ba> {
-	fn genesis(&self) -> <Self::Block as Block>::Identifier {
-		self.0.genesis()
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> ChainQuery for ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C> + Clone,
+{
+	fn genesis(&self) -> <Self::Block as BlockT>::Identifier {
+		self.backend.genesis()
 	}
-	fn head(&self) -> <Self::Block as Block>::Identifier {
-		self.0.head()
+	fn head(&self) -> <Self::Block as BlockT>::Identifier {
+		self.backend.head()
 	}
 	fn contains(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
+		hash: &<Self::Block as BlockT>::Identifier,
 	) -> Result<bool, Self::Error> {
-		Ok(self.0.contains(hash)?)
+		Ok(self.backend.contains(hash)?)
 	}
 	fn is_canon(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
+		hash: &<Self::Block as BlockT>::Identifier,
 	) -> Result<bool, Self::Error> {
-		Ok(self.0.is_canon(hash)?)
+		Ok(self.backend.is_canon(hash)?)
 	}
 	fn lookup_canon_depth(
 		&self,
 		depth: usize,
-	) -> Result<Option<<Self::Block as Block>::Identifier>, Self::Error> {
-		Ok(self.0.lookup_canon_depth(depth)?)
+	) -> Result<Option<<Self::Block as BlockT>::Identifier>, Self::Error> {
+		Ok(self.backend.lookup_canon_depth(depth)?)
 	}
 	fn auxiliary(
 		&self,
 		key: &<Self::Auxiliary as Auxiliary<Self::Block>>::Key,
 	) -> Result<Option<Self::Auxiliary>, Self::Error> {
-		Ok(self.0.auxiliary(key)?)
+		Ok(self.backend.auxiliary(key)?)
 	}
 	fn depth_at(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
+		hash: &<Self::Block as BlockT>::Identifier,
 	) -> Result<usize, Self::Error> {
-		Ok(self.0.depth_at(hash)?)
+		Ok(self.backend.depth_at(hash)?)
 	}
 	fn children_at(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
-	) -> Result<Vec<<Self::Block as Block>::Identifier>, Self::Error> {
-		Ok(self.0.children_at(hash)?)
+		hash: &<Self::Block as BlockT>::Identifier,
+	) -> Result<Vec<<Self::Block as BlockT>::Identifier>, Self::Error> {
+		Ok(self.backend.children_at(hash)?)
 	}
 	fn state_at(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
+		hash: &<Self::Block as BlockT>::Identifier,
 	) -> Result<Self::State, Self::Error> {
-		Ok(self.0.state_at(hash)?)
+		if let Some(state) = self.state_cache.lock().expect("Lock is poisoned").get(hash) {
+			return Ok(state)
+		}
+
+		let state = self.backend.state_at(hash)?;
+		let size = state_cache::state_size(state.state());
+		self.state_cache.lock().expect("Lock is poisoned").insert(*hash, state.clone(), size);
+		Ok(state)
 	}
 	fn block_at(
 		&self,
-		hash: &<Self::Block as Block>::Identifier,
+		hash: &<Self::Block as BlockT>::Identifier,
 	) -> Result<Self::Block, Self::Error> {
-		Ok(self.0.block_at(hash)?)
+		Ok(self.backend.block_at(hash)?)
 	}
 }
 
-impl<Ba: ChainQuery> AncestorQuery for ShasperBackend<Ba> {
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> AncestorQuery for ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C> + Clone,
+{
 	fn ancestor_at(
 		&self,
-		id: &<Self::Block as Block>::Identifier,
+		id: &<Self::Block as BlockT>::Identifier,
 		depth: usize
-	) -> Result<<Self::Block as Block>::Identifier, Self::Error> {
-		NoCacheAncestorQuery::new(&self.0).ancestor_at(id, depth)
+	) -> Result<<Self::Block as BlockT>::Identifier, Self::Error> {
+		NoCacheAncestorQuery::new(&self.backend).ancestor_at(id, depth)
 	}
 }
 
-impl<Ba> SharedCommittable for ShasperBackend<Ba> where
+impl<C: Config, Ba> SharedCommittable for ShasperBackend<Ba> where
+	Ba: ChainQuery + Store<Block=Block<C>>,
+	Ba::State: StateExternalities<Config=C> + Clone,
+	Error: From<Ba::Error>,
 	Ba: SharedCommittable<Operation=Operation<Self::Block, Self::State, Self::Auxiliary>>
 {
 	type Operation = Operation<Self::Block, Self::State, Self::Auxiliary>;
@@ -114,6 +390,109 @@ impl<Ba> SharedCommittable for ShasperBackend<Ba> where
 		&self,
 		operation: Operation<Self::Block, Self::State, Self::Auxiliary>,
 	) -> Result<(), Self::Error> {
-		self.0.commit(operation)
+		let headers = operation.import_block.iter()
+			.map(|op| (op.block.id(), op.block.header()))
+			.collect::<Vec<_>>();
+		self.backend.commit(operation)?;
+		// Headers are indexed as blocks are imported, so that header queries
+		// do not need to load and decode full blocks.
+		if let Err(e) = index::insert_headers(self.index.as_ref(), &headers) {
+			warn!("Failed to update block indices: {:?}", e);
+		}
+		self.update_index();
+		self.pin_states();
+		Ok(())
+	}
+}
+
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> HeaderQuery for ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C>,
+{
+	fn get_header(
+		&self,
+		id: &H256,
+	) -> Result<Option<BeaconBlockHeader>, Self::Error> {
+		match index::header(self.index.as_ref(), id) {
+			Ok(Some(header)) => return Ok(Some(header)),
+			Ok(None) => (),
+			Err(e) => warn!("Failed to query block indices: {:?}", e),
+		}
+
+		if !self.backend.contains(id)? {
+			return Ok(None)
+		}
+
+		let header = self.fetch_header(id)?;
+		if let Err(e) = index::insert_header(self.index.as_ref(), id, &header) {
+			warn!("Failed to update block indices: {:?}", e);
+		}
+		Ok(Some(header))
+	}
+}
+
+impl<C: Config, Ba: ChainQuery + Store<Block=Block<C>>> SlotQuery for ShasperBackend<Ba> where
+	Ba::State: StateExternalities<Config=C>,
+{
+	fn lookup_canon_slot(
+		&self,
+		slot: u64,
+	) -> Result<Option<H256>, Self::Error> {
+		match index::canon_root(self.index.as_ref(), slot) {
+			Ok(root) => Ok(root),
+			Err(e) => {
+				warn!("Failed to query block indices: {:?}", e);
+				let mut current = self.backend.head();
+				loop {
+					let header = self.fetch_header(&current)?;
+					if header.slot <= slot {
+						return Ok(if header.slot == slot { Some(current) } else { None })
+					}
+					current = match parent_id_of(header.parent_root) {
+						Some(parent) => parent,
+						None => return Ok(None),
+					};
+				}
+			},
+		}
+	}
+
+	fn slot_at(
+		&self,
+		id: &H256,
+	) -> Result<(u64, Option<H256>), Self::Error> {
+		let header = match self.get_header(id)? {
+			Some(header) => header,
+			None => self.fetch_header(id)?,
+		};
+		Ok((header.slot, parent_id_of(header.parent_root)))
+	}
+}
+
+/// Fork choice persistence into the info column of a RocksDB backend.
+pub struct RocksForkChoice<B: BlockT, A: Auxiliary<B>, S>(RocksBackend<B, A, S>);
+
+impl<B: BlockT, A: Auxiliary<B>, S> RocksForkChoice<B, A, S> {
+	pub fn new(backend: RocksBackend<B, A, S>) -> Self {
+		Self(backend)
+	}
+}
+
+impl<B: BlockT, A: Auxiliary<B>, S: RocksStateT> ForkChoicePersistence for RocksForkChoice<B, A, S> where
+	B::Identifier: Encode + Decode,
+	B: Encode + Decode,
+	A: Encode + Decode,
+	A::Key: Encode + Decode,
+{
+	fn load(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+		Ok(self.0.fork_choice()?)
+	}
+
+	fn store(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+		Ok(self.0.set_fork_choice(data)?)
+	}
+
+	fn stage(&self, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+		self.0.stage_fork_choice(data);
+		Ok(())
 	}
 }
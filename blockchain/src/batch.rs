@@ -0,0 +1,249 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Batched block verification for range sync.
+//!
+//! Blocks below the finalized checkpoint are verified by checking that they
+//! are the finalized blocks at their slots, and verifying all proposer
+//! signatures of the chunk at once. Finalized blocks are those committed to
+//! by the block roots of the head state, so a block on any other fork,
+//! including one branching off a finalized block, is never batch verified.
+//! This is the case when history below a checkpoint sync anchor is imported,
+//! while blocks above our finalized checkpoint, as in range sync, are always
+//! verified one by one. Such blocks are then executed without verifying randao and attestation
+//! signatures. Deposit signatures are still verified, as invalid ones are
+//! skipped rather than making the block invalid. If the batch fails, blocks
+//! are fully verified one by one.
+//!
+//! Proposers of a chunk are computed from the state at the start of its
+//! epoch, without waiting for the previous chunk to be imported. If the
+//! parent of the chunk is stored, that is its checkpoint state. Otherwise
+//! the parent is still in the pipeline, and the state used for the previous
+//! chunk is advanced through empty slots instead. It only misses the effect
+//! of the previous epoch on effective balances, which rarely changes
+//! proposers. When it does, the batch fails and blocks are verified one by
+//! one, so it never lets an invalid signature through.
+//!
+//! Block ids of a chunk are tree hashed in parallel, and its proposer
+//! signatures are verified as several aggregates in parallel.
+
+use core::marker::PhantomData;
+use beacon::{BeaconState, BLSConfig, Config};
+use beacon::primitives::H256;
+use beacon::types::Checkpoint;
+use beacon::utils::{epoch_of_slot, start_slot_of_epoch};
+use blockchain::Block as BlockT;
+use blockchain::backend::{ChainQuery, Store};
+use log::{debug, trace};
+use rayon::prelude::*;
+use crate::{Block, BatchVerified, StateExternalities, Error};
+use crate::checkpoint_state::{self, SharedCheckpointStateCache};
+use crate::import_queue::ChunkVerifier;
+
+/// Number of batch verified blocks awaiting execution above which the
+/// leftovers of failed imports are forgotten.
+pub const MAX_BATCH_VERIFIED: usize = 1024;
+
+/// Finalized block roots committed to by a head state.
+struct FinalizedRoots {
+	head: H256,
+	head_slot: u64,
+	finalized_slot: u64,
+	block_roots: Vec<H256>,
+}
+
+impl FinalizedRoots {
+	/// Root of the finalized block at `slot`, or of the last one before it
+	/// for an empty slot, if still covered by the head state.
+	fn root_at(&self, slot: u64) -> Option<H256> {
+		let len = self.block_roots.len() as u64;
+		if slot >= self.finalized_slot || slot >= self.head_slot || slot + len < self.head_slot {
+			return None
+		}
+		Some(self.block_roots[(slot % len) as usize])
+	}
+}
+
+/// Chunk verifier batch-verifying proposer signatures of old blocks.
+pub struct BatchVerifier<Ba, C: Config, BLS> {
+	backend: Ba,
+	batch_verified: BatchVerified,
+	checkpoint_states: SharedCheckpointStateCache<C>,
+	/// Finalized roots of the head, reloaded when the head changes.
+	finalized: Option<FinalizedRoots>,
+	/// Last block of the previous batch verified chunk, and the state its
+	/// proposers were computed from.
+	last: Option<(H256, BeaconState<C>)>,
+	_marker: PhantomData<BLS>,
+}
+
+impl<Ba, C, BLS> BatchVerifier<Ba, C, BLS> where
+	Ba: ChainQuery + Store<Block=Block<C>>,
+	Ba::State: StateExternalities<Config=C>,
+	Error: From<Ba::Error>,
+	C: Config,
+	BLS: BLSConfig,
+{
+	/// Create a new batch verifier, marking verified blocks in
+	/// `batch_verified`, which must be shared with the executor.
+	pub fn new(
+		backend: Ba,
+		batch_verified: BatchVerified,
+		checkpoint_states: SharedCheckpointStateCache<C>,
+	) -> Self {
+		Self {
+			backend, batch_verified, checkpoint_states,
+			finalized: None, last: None, _marker: PhantomData,
+		}
+	}
+
+	/// Finalized roots of the current head, only reading the head state
+	/// when the head changed since the last call.
+	fn finalized_roots(&mut self) -> Option<&FinalizedRoots> {
+		let head = self.backend.head();
+		if self.finalized.as_ref().map(|finalized| finalized.head != head).unwrap_or(true) {
+			self.finalized = match self.backend.state_at(&head) {
+				Ok(state) => {
+					let state = state.state();
+					Some(FinalizedRoots {
+						head,
+						head_slot: state.slot,
+						finalized_slot: start_slot_of_epoch::<C>(
+							state.finalized_checkpoint.epoch
+						),
+						block_roots: state.block_roots.iter().cloned().collect(),
+					})
+				},
+				Err(_) => None,
+			};
+		}
+		self.finalized.as_ref()
+	}
+
+	/// Whether the block, with the given id, is the finalized block at its
+	/// slot.
+	fn is_finalized(&mut self, block: &Block<C>, id: H256) -> bool {
+		let slot = block.slot();
+		self.finalized_roots().and_then(|finalized| finalized.root_at(slot)) == Some(id)
+	}
+
+	/// State at the start of the epoch of a checkpoint, exact if the
+	/// checkpoint block is stored, or else carried forward from the previous
+	/// chunk if it ended with that block.
+	fn epoch_state(&self, checkpoint: &Checkpoint) -> Option<BeaconState<C>> {
+		if self.backend.contains(&checkpoint.root).unwrap_or(false) {
+			return match checkpoint_state::checkpoint_state(
+				&self.checkpoint_states, &self.backend, checkpoint,
+			) {
+				Ok(state) => Some(state),
+				Err(e) => {
+					debug!("Computing checkpoint state failed: {}", e);
+					None
+				},
+			}
+		}
+
+		// Not put in the checkpoint state cache, as it is not exact.
+		let (last, state) = self.last.as_ref()?;
+		if *last != checkpoint.root {
+			return None
+		}
+		let mut state = state.clone();
+		checkpoint_state::advance_to_epoch(&mut state, checkpoint.epoch).ok()?;
+		Some(state)
+	}
+
+	/// Verify proposer signatures of a chain of blocks within one epoch,
+	/// given their ids.
+	fn verify_epoch(&mut self, blocks: &[Block<C>], ids: &[H256]) -> bool {
+		if !blocks.iter().skip(1).zip(ids).all(|(block, parent)| block.0.parent_root == *parent) {
+			debug!("Queued blocks do not form a chain, verifying one by one");
+			return false
+		}
+
+		let checkpoint = Checkpoint {
+			epoch: epoch_of_slot::<C>(blocks[0].slot()),
+			root: blocks[0].0.parent_root,
+		};
+		let state = match self.epoch_state(&checkpoint) {
+			Some(state) => state,
+			None => return false,
+		};
+		let raw_blocks = blocks.iter().map(|block| block.0.clone()).collect::<Vec<_>>();
+		let signatures = match beacon::proposer_signatures(&raw_blocks, &mut state.clone()) {
+			Ok(signatures) => signatures,
+			Err(e) => {
+				debug!("Collecting proposer signatures failed: {:?}", e);
+				return false
+			},
+		};
+
+		let verified = beacon::verify_proposer_signatures::<BLS>(&signatures);
+		if verified {
+			self.last = Some((ids[ids.len() - 1], state));
+		}
+		verified
+	}
+}
+
+/// Ids of blocks, tree hashed in parallel.
+fn block_ids<C: Config>(blocks: &[Block<C>]) -> Vec<H256> {
+	blocks.par_iter().map(|block| block.id()).collect()
+}
+
+impl<Ba, C, BLS> ChunkVerifier<Block<C>> for BatchVerifier<Ba, C, BLS> where
+	Ba: ChainQuery + Store<Block=Block<C>> + Send,
+	Ba::State: StateExternalities<Config=C>,
+	Error: From<Ba::Error>,
+	C: Config,
+	BLS: BLSConfig,
+{
+	fn verify_chunk(&mut self, blocks: &[Block<C>]) -> usize {
+		{
+			let mut batch_verified = self.batch_verified.lock().expect("Lock is poisoned");
+			// Blocks are removed once executed, so anything beyond the blocks
+			// that can be in the pipeline was never executed.
+			if batch_verified.len() > MAX_BATCH_VERIFIED {
+				batch_verified.clear();
+			}
+		}
+
+		let first = match blocks.first() {
+			Some(first) => first,
+			None => return 0,
+		};
+		let epoch = epoch_of_slot::<C>(first.slot());
+		let epoch_len = blocks.iter()
+			.take_while(|block| epoch_of_slot::<C>(block.slot()) == epoch)
+			.count();
+		let ids = block_ids(&blocks[..epoch_len]);
+		let len = blocks.iter().zip(ids.iter())
+			.take_while(|(block, id)| self.is_finalized(block, **id))
+			.count();
+		if len < 2 {
+			return len.max(1)
+		}
+
+		if self.verify_epoch(&blocks[..len], &ids[..len]) {
+			trace!("Batch verified {} blocks of epoch {}", len, epoch);
+			self.batch_verified.lock().expect("Lock is poisoned")
+				.extend(ids[..len].iter().cloned());
+		} else {
+			debug!("Batch verification of epoch {} failed, verifying one by one", epoch);
+		}
+		len
+	}
+}
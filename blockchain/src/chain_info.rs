@@ -0,0 +1,107 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persisted chain identity.
+
+use beacon::primitives::H256;
+use beacon::types::Fork;
+use parity_codec::{Encode, Decode};
+use shasper_runtime::ChainInfo;
+use crate::Error;
+use crate::index::IndexStore;
+
+const KEY_CHAIN_INFO: &[u8] = b"chain_info";
+const KEY_NETWORK: &[u8] = b"network";
+
+/// Network a store belongs to, recorded on first start so that a data
+/// directory cannot be reused with another network or config.
+#[derive(Eq, PartialEq, Clone, Debug, Encode, Decode)]
+pub struct NetworkId {
+	/// Name of the chain config.
+	pub config_name: String,
+	/// Root of the genesis validator registry.
+	pub genesis_validators_root: H256,
+}
+
+/// Stored network, if any.
+pub fn load_network(store: &dyn IndexStore) -> Result<Option<NetworkId>, Error> {
+	match store.get(KEY_NETWORK)? {
+		Some(raw) => Ok(Some(
+			NetworkId::decode(&mut raw.as_ref()).ok_or(Error::Corrupted("network"))?
+		)),
+		None => Ok(None),
+	}
+}
+
+/// Check the store belongs to `network`, recording it on first start.
+pub fn check_network(store: &dyn IndexStore, network: &NetworkId) -> Result<(), Error> {
+	match load_network(store)? {
+		Some(stored) => {
+			if &stored != network {
+				return Err(Error::NetworkMismatch { stored, expected: network.clone() })
+			}
+			Ok(())
+		},
+		None => store.write(vec![(KEY_NETWORK.to_vec(), Some(network.encode()))]),
+	}
+}
+
+/// Stored chain identity, if any.
+pub fn load(store: &dyn IndexStore) -> Result<Option<ChainInfo>, Error> {
+	match store.get(KEY_CHAIN_INFO)? {
+		Some(raw) => Ok(Some(
+			ChainInfo::decode(&mut raw.as_ref()).ok_or(Error::Corrupted("chain info"))?
+		)),
+		None => Ok(None),
+	}
+}
+
+/// Store the chain identity.
+pub fn store(store: &dyn IndexStore, info: &ChainInfo) -> Result<(), Error> {
+	store.write(vec![(KEY_CHAIN_INFO.to_vec(), Some(info.encode()))])
+}
+
+/// Load the stored chain identity, storing `genesis` on first start. Fails
+/// if the store belongs to a chain of another genesis.
+pub fn load_or_init(store: &dyn IndexStore, genesis: ChainInfo) -> Result<ChainInfo, Error> {
+	match load(store)? {
+		Some(info) => {
+			if info.genesis_time != genesis.genesis_time ||
+				info.genesis_validators_root != genesis.genesis_validators_root
+			{
+				return Err(Error::GenesisMismatch)
+			}
+			Ok(info)
+		},
+		None => {
+			self::store(store, &genesis)?;
+			Ok(genesis)
+		},
+	}
+}
+
+/// Note a new fork, persisting it if it changed.
+pub fn update_fork(
+	store: &dyn IndexStore,
+	info: &mut ChainInfo,
+	fork: &Fork,
+) -> Result<(), Error> {
+	if &info.fork != fork {
+		info.fork = fork.clone();
+		self::store(store, info)?;
+	}
+	Ok(())
+}
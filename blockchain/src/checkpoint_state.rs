@@ -0,0 +1,146 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Epoch boundary states of checkpoints.
+//!
+//! Verifying an attestation, and weighing votes in fork choice, needs the
+//! state at a checkpoint: the post-state of the checkpoint block, advanced
+//! through empty slots to the start of the checkpoint epoch. Such states are
+//! cached, and shared between gossip validation and fork choice.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use beacon::{BeaconState, BeaconExecutive, Config, StateReader};
+use beacon::primitives::{Epoch, H256, ValidatorIndex};
+use beacon::types::{Attestation, Checkpoint};
+use beacon::utils;
+use blockchain::backend::{ChainQuery, Store};
+use crate::{Block, StateExternalities, Error};
+
+/// Default number of cached checkpoint states.
+pub const DEFAULT_CHECKPOINT_STATE_CACHE_SIZE: usize = 8;
+
+/// Cache of checkpoint states, evicting the least recently used.
+pub struct CheckpointStateCache<C: Config> {
+	max_entries: usize,
+	states: HashMap<(Epoch, H256), BeaconState<C>>,
+	order: VecDeque<(Epoch, H256)>,
+}
+
+/// Checkpoint state cache shared between gossip validation and fork choice.
+pub type SharedCheckpointStateCache<C> = Arc<Mutex<CheckpointStateCache<C>>>;
+
+impl<C: Config> CheckpointStateCache<C> {
+	/// Create a new cache holding at most `max_entries` states.
+	pub fn new(max_entries: usize) -> Self {
+		Self { max_entries, states: HashMap::new(), order: VecDeque::new() }
+	}
+
+	/// Cached state of a checkpoint.
+	pub fn get(&mut self, checkpoint: &Checkpoint) -> Option<&BeaconState<C>> {
+		let key = (checkpoint.epoch, checkpoint.root);
+		if self.states.contains_key(&key) {
+			self.touch(key);
+		}
+		self.states.get(&key)
+	}
+
+	/// Whether the state of a checkpoint is cached.
+	pub fn contains(&self, checkpoint: &Checkpoint) -> bool {
+		self.states.contains_key(&(checkpoint.epoch, checkpoint.root))
+	}
+
+	/// Cache the state of a checkpoint.
+	pub fn insert(&mut self, checkpoint: &Checkpoint, state: BeaconState<C>) {
+		let key = (checkpoint.epoch, checkpoint.root);
+		self.states.insert(key, state);
+		self.touch(key);
+
+		while self.order.len() > self.max_entries {
+			if let Some(oldest) = self.order.pop_front() {
+				self.states.remove(&oldest);
+			}
+		}
+	}
+
+	/// Number of cached states.
+	pub fn len(&self) -> usize {
+		self.states.len()
+	}
+
+	/// Whether no state is cached.
+	pub fn is_empty(&self) -> bool {
+		self.states.is_empty()
+	}
+
+	fn touch(&mut self, key: (Epoch, H256)) {
+		self.order.retain(|k| k != &key);
+		self.order.push_back(key);
+	}
+}
+
+/// Advance the post-state of a checkpoint block to the start of the
+/// checkpoint epoch.
+pub fn advance_to_epoch<C: Config>(state: &mut BeaconState<C>, epoch: Epoch) -> Result<(), Error> {
+	let slot = utils::start_slot_of_epoch::<C>(epoch);
+	if state.slot < slot {
+		BeaconExecutive::new(state).process_slots(slot)?;
+	}
+	Ok(())
+}
+
+/// State of a checkpoint, from the cache, or otherwise computed from the
+/// post-state of the checkpoint block and cached.
+pub fn checkpoint_state<C, Ba>(
+	cache: &SharedCheckpointStateCache<C>,
+	backend: &Ba,
+	checkpoint: &Checkpoint,
+) -> Result<BeaconState<C>, Error> where
+	C: Config,
+	Ba: ChainQuery + Store<Block=Block<C>>,
+	Ba::State: StateExternalities<Config=C>,
+	Error: From<Ba::Error>,
+{
+	if let Some(state) = cache.lock().expect("Lock is poisoned").get(checkpoint) {
+		return Ok(state.clone())
+	}
+
+	if !backend.contains(&checkpoint.root).map_err(Error::from)? {
+		return Err(Error::Missing("checkpoint block"))
+	}
+	let mut state = backend.state_at(&checkpoint.root).map_err(Error::from)?
+		.state().clone();
+	advance_to_epoch(&mut state, checkpoint.epoch)?;
+	cache.lock().expect("Lock is poisoned").insert(checkpoint, state.clone());
+
+	Ok(state)
+}
+
+/// Committee of an attestation, resolved against the state of its target.
+pub fn attestation_committee<C, Ba>(
+	cache: &SharedCheckpointStateCache<C>,
+	backend: &Ba,
+	attestation: &Attestation<C>,
+) -> Result<Vec<ValidatorIndex>, Error> where
+	C: Config,
+	Ba: ChainQuery + Store<Block=Block<C>>,
+	Ba::State: StateExternalities<Config=C>,
+	Error: From<Ba::Error>,
+{
+	let mut state = checkpoint_state(cache, backend, &attestation.data.target)?;
+	let reader = StateReader::new(&mut state);
+	Ok(reader.beacon_committee(attestation.data.slot, attestation.data.index)?)
+}
@@ -0,0 +1,192 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fetching genesis and checkpoint states from a trusted beacon API.
+//!
+//! States are requested SSZ encoded from the standard debug endpoints. A
+//! downloaded state is only as trustworthy as its provider, so its root is
+//! checked against a value obtained out of band before it is used.
+
+use std::fmt;
+use std::io::Read;
+use std::sync::Arc;
+use core::time::Duration;
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::types::{BeaconBlock, BeaconState, SigningBeaconBlockHeader};
+use bm_le::tree_root;
+use blockchain::Block as BlockT;
+use crate::Block;
+
+/// Timeout of a single state request.
+pub const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Checkpoint sync errors.
+#[derive(Debug)]
+pub enum FetchError {
+	/// The request failed.
+	Http(String),
+	/// The provider answered with an error status.
+	Status(u16),
+	/// Reading the response failed.
+	Io(std::io::Error),
+	/// The response could not be decoded.
+	Decode(String),
+	/// The state root does not match the expected one.
+	StateRootMismatch {
+		/// Expected root.
+		expected: H256,
+		/// Root of the downloaded state.
+		actual: H256,
+	},
+	/// The block root does not match the state's latest block.
+	BlockRootMismatch {
+		/// Root of the state's latest block.
+		expected: H256,
+		/// Root of the downloaded block.
+		actual: H256,
+	},
+}
+
+impl fmt::Display for FetchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			FetchError::Http(e) => write!(f, "Request failed: {}", e),
+			FetchError::Status(status) => write!(f, "Provider returned status {}", status),
+			FetchError::Io(e) => write!(f, "Reading response failed: {}", e),
+			FetchError::Decode(e) => write!(f, "Decoding response failed: {}", e),
+			FetchError::StateRootMismatch { expected, actual } =>
+				write!(f, "State root {} does not match expected {}", actual, expected),
+			FetchError::BlockRootMismatch { expected, actual } =>
+				write!(f, "Block root {} does not match expected {}", actual, expected),
+		}
+	}
+}
+
+impl std::error::Error for FetchError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			FetchError::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// Client of a trusted beacon API.
+pub struct CheckpointProvider {
+	url: String,
+	timeout: Duration,
+	tls_config: Option<Arc<rustls::ClientConfig>>,
+}
+
+impl CheckpointProvider {
+	/// Create a provider for the beacon API at `url`.
+	pub fn new(url: &str) -> Self {
+		Self {
+			url: url.trim_end_matches('/').to_string(),
+			timeout: DEFAULT_FETCH_TIMEOUT,
+			tls_config: None,
+		}
+	}
+
+	/// Set the request timeout.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Only accept an HTTPS provider whose certificate has one of the given
+	/// SHA-256 fingerprints, instead of validating it against CA roots.
+	pub fn with_pinned_certs(mut self, pins: Vec<[u8; 32]>) -> Self {
+		self.tls_config = Some(crate::tls::pinned_client_config(pins));
+		self
+	}
+
+	fn get_ssz(&self, path: &str) -> Result<Vec<u8>, FetchError> {
+		let mut request = ureq::get(&format!("{}{}", self.url, path));
+		request.set("Accept", "application/octet-stream").timeout(self.timeout);
+		if let Some(tls_config) = self.tls_config.as_ref() {
+			request.set_tls_config(tls_config.clone());
+		}
+		let response = request.call();
+		if let Some(e) = response.synthetic_error() {
+			return Err(FetchError::Http(e.to_string()))
+		}
+		if !response.ok() {
+			return Err(FetchError::Status(response.status()))
+		}
+
+		let mut data = Vec::new();
+		response.into_reader().read_to_end(&mut data).map_err(FetchError::Io)?;
+		Ok(data)
+	}
+
+	/// Fetch the state `state_id`, one of `genesis`, `finalized`, a slot or a
+	/// state root. If `expected_root` is given, the state must match it.
+	pub fn state<C: Config>(
+		&self,
+		state_id: &str,
+		expected_root: Option<H256>,
+	) -> Result<BeaconState<C>, FetchError> {
+		let data = self.get_ssz(&format!("/eth/v1/debug/beacon/states/{}", state_id))?;
+		let state: BeaconState<C> = ssz::Decode::decode(&data[..])
+			.map_err(|e| FetchError::Decode(format!("{:?}", e)))?;
+
+		if let Some(expected) = expected_root {
+			let actual = tree_root::<C::Digest, _>(&state);
+			if actual != expected {
+				return Err(FetchError::StateRootMismatch { expected, actual })
+			}
+		}
+		Ok(state)
+	}
+
+	/// Fetch the block `block_id`, one of `genesis`, `finalized`, a slot or a
+	/// block root.
+	pub fn block<C: Config>(&self, block_id: &str) -> Result<BeaconBlock<C>, FetchError> {
+		let data = self.get_ssz(&format!("/eth/v1/beacon/blocks/{}", block_id))?;
+		ssz::Decode::decode(&data[..])
+			.map_err(|e| FetchError::Decode(format!("{:?}", e)))
+	}
+
+	/// Fetch the finalized state and its block, to be used as the anchor of
+	/// a new store. The state must match `expected_root`.
+	pub fn anchor<C: Config>(
+		&self,
+		state_id: &str,
+		expected_root: H256,
+	) -> Result<(Block<C>, BeaconState<C>), FetchError> {
+		let state = self.state::<C>(state_id, Some(expected_root))?;
+		let block_root = anchor_block_root(&state);
+		let block = Block(self.block::<C>(&format!("{:?}", block_root))?);
+
+		let actual = block.id();
+		if actual != block_root {
+			return Err(FetchError::BlockRootMismatch { expected: block_root, actual })
+		}
+		Ok((block, state))
+	}
+}
+
+/// Root of the latest block of a state, filling in the state root if the
+/// state has not been advanced past the block.
+pub fn anchor_block_root<C: Config>(state: &BeaconState<C>) -> H256 {
+	let mut header = state.latest_block_header.clone();
+	if header.state_root == H256::default() {
+		header.state_root = tree_root::<C::Digest, _>(state);
+	}
+	tree_root::<C::Digest, _>(&SigningBeaconBlockHeader::from(header))
+}
@@ -0,0 +1,155 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Clock skew detection.
+//!
+//! Our slot is compared against the median head slot reported by peers, and
+//! the system time against an NTP server. Skew silently breaks attestation
+//! effectiveness, so it is logged and exposed for the node API.
+
+use std::io;
+use std::net::UdpSocket;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use lmd_ghost::clock::SlotClock;
+use log::{debug, warn};
+use shasper_network::SharedSyncState;
+
+/// Maximum number of slots our slot may differ from the median peer head
+/// slot. Peer heads lag behind on missed slots, so this is not zero.
+pub const MAX_SLOT_SKEW: i64 = 2;
+/// Maximum offset of the system time against NTP.
+pub const MAX_NTP_OFFSET: Duration = Duration::from_millis(500);
+/// Minimum number of peers required to estimate skew from head slots.
+pub const MIN_SKEW_PEERS: usize = 3;
+/// Default NTP server.
+pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org:123";
+/// Interval between clock skew checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Timeout of an NTP query.
+const NTP_TIMEOUT: Duration = Duration::from_secs(5);
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Clock skew shared with other components, such as the node API.
+pub type SharedClockSkew = Arc<RwLock<ClockSkew>>;
+
+/// Estimated clock skew.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClockSkew {
+	/// Our slot minus the median head slot of peers.
+	pub slot_skew: Option<i64>,
+	/// System time minus NTP time, in milliseconds.
+	pub ntp_offset_ms: Option<i64>,
+	/// Whether our clock appears skewed.
+	pub is_skewed: bool,
+}
+
+impl ClockSkew {
+	fn update(&mut self) {
+		self.is_skewed =
+			self.slot_skew.map(|skew| skew.abs() > MAX_SLOT_SKEW).unwrap_or(false) ||
+			self.ntp_offset_ms.map(|offset| offset.abs() as u128 > MAX_NTP_OFFSET.as_millis())
+				.unwrap_or(false);
+	}
+}
+
+/// Our slot minus the median of peer head slots. `None` if there are not
+/// enough peers.
+pub fn slot_skew(current_slot: u64, mut peer_head_slots: Vec<u64>) -> Option<i64> {
+	if peer_head_slots.len() < MIN_SKEW_PEERS {
+		return None
+	}
+	peer_head_slots.sort();
+	let median = peer_head_slots[peer_head_slots.len() / 2];
+	Some(current_slot as i64 - median as i64)
+}
+
+/// System time minus the time of an NTP server, in milliseconds.
+pub fn ntp_offset(server: &str) -> io::Result<i64> {
+	let socket = UdpSocket::bind("0.0.0.0:0")?;
+	socket.set_read_timeout(Some(NTP_TIMEOUT))?;
+	socket.connect(server)?;
+
+	// Leap indicator 0, version 3, client mode.
+	let mut packet = [0u8; 48];
+	packet[0] = 0x1b;
+	let sent = Instant::now();
+	socket.send(&packet)?;
+	let len = socket.recv(&mut packet)?;
+	let round_trip = sent.elapsed();
+	if len < 48 {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "NTP response too short"))
+	}
+
+	let mut seconds = [0u8; 4];
+	seconds.copy_from_slice(&packet[40..44]);
+	let mut fraction = [0u8; 4];
+	fraction.copy_from_slice(&packet[44..48]);
+	let ntp_ms = (u64::from(u32::from_be_bytes(seconds)).saturating_sub(NTP_UNIX_OFFSET)) * 1000 +
+		(u64::from(u32::from_be_bytes(fraction)) * 1000 >> 32);
+	// The server time is taken as of the middle of the round trip.
+	let ntp_ms = ntp_ms + round_trip.as_millis() as u64 / 2;
+
+	let local_ms = SystemTime::now().duration_since(UNIX_EPOCH)
+		.map_err(|_| io::Error::new(io::ErrorKind::Other, "System time before Unix epoch"))?
+		.as_millis() as u64;
+	Ok(local_ms as i64 - ntp_ms as i64)
+}
+
+/// Spawn a thread periodically checking clock skew against peer head slots
+/// and, if given, an NTP server.
+pub fn spawn_monitor<S: SlotClock + Send + 'static>(
+	slot_clock: S,
+	sync_state: SharedSyncState,
+	ntp_server: Option<String>,
+) -> SharedClockSkew {
+	let skew = SharedClockSkew::default();
+	let shared = skew.clone();
+
+	thread::Builder::new()
+		.name("clock-skew".to_string())
+		.spawn(move || loop {
+			let peer_head_slots = sync_state.read().expect("Lock is poisoned").peer_head_slots();
+			let slot_skew = slot_clock.current_slot()
+				.and_then(|current_slot| slot_skew(current_slot, peer_head_slots));
+			let ntp_offset_ms = ntp_server.as_ref().and_then(|server| match ntp_offset(server) {
+				Ok(offset) => Some(offset),
+				Err(e) => {
+					debug!("NTP query to {} failed: {}", server, e);
+					None
+				},
+			});
+
+			let mut skew = shared.write().expect("Lock is poisoned");
+			skew.slot_skew = slot_skew;
+			skew.ntp_offset_ms = ntp_offset_ms;
+			skew.update();
+			if skew.is_skewed {
+				warn!(
+					"Clock appears skewed: {:?} slots against peers, {:?} ms against NTP",
+					skew.slot_skew, skew.ntp_offset_ms,
+				);
+			}
+			drop(skew);
+
+			thread::sleep(CHECK_INTERVAL);
+		})
+		.expect("Spawning clock skew thread failed");
+
+	skew
+}
@@ -0,0 +1,252 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Committee assignments of an epoch.
+//!
+//! Memberships of every beacon committee of an epoch, served on the
+//! standard `GET /eth/v1/beacon/states/{state_id}/committees` endpoint and
+//! exported as CSV or JSON for analysis.
+
+use std::fmt::Write;
+use std::str::FromStr;
+use beacon::{BeaconState, BeaconExecutive, Config, StateReader, Error as BeaconError};
+use beacon::primitives::{H256, Epoch, Slot, ValidatorIndex};
+use beacon::utils;
+use blockchain::backend::{Store, ChainQuery};
+use serde::{Serialize, Serializer};
+use crate::{Block, SlotQuery, HeaderQuery, StateExternalities, Error, parent_id_of, is_genesis_root};
+use crate::api_auth::ApiConfig;
+use crate::http::{self, Request, Response};
+
+/// Path prefix of the state endpoints.
+pub const STATES_PATH: &str = "/eth/v1/beacon/states";
+
+/// State identifier of the beacon API.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StateId {
+	/// State of the head block.
+	Head,
+	/// Genesis state.
+	Genesis,
+	/// State of the finalized checkpoint of the head.
+	Finalized,
+	/// State of the justified checkpoint of the head.
+	Justified,
+	/// Canonical state at a slot.
+	Slot(Slot),
+	/// State with the given root.
+	Root(H256),
+}
+
+impl FromStr for StateId {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"head" => Ok(StateId::Head),
+			"genesis" => Ok(StateId::Genesis),
+			"finalized" => Ok(StateId::Finalized),
+			"justified" => Ok(StateId::Justified),
+			s if s.starts_with("0x") => {
+				let raw = hex::decode(&s[2..]).map_err(|e| format!("Invalid state root: {}", e))?;
+				if raw.len() != 32 {
+					return Err(format!("Invalid state root length {}", raw.len()))
+				}
+				Ok(StateId::Root(H256::from_slice(&raw)))
+			},
+			s => u64::from_str(s).map(StateId::Slot).map_err(|_| format!("Invalid state id: {}", s)),
+		}
+	}
+}
+
+fn quoted<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.serialize_str(&value.to_string())
+}
+
+fn quoted_list<S: Serializer>(values: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+	serializer.collect_seq(values.iter().map(|value| value.to_string()))
+}
+
+/// Members of a beacon committee.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Committee {
+	/// Committee index within the slot.
+	#[serde(serialize_with = "quoted")]
+	pub index: u64,
+	/// Slot of the committee.
+	#[serde(serialize_with = "quoted")]
+	pub slot: Slot,
+	/// Validators, in committee order.
+	#[serde(serialize_with = "quoted_list")]
+	pub validators: Vec<ValidatorIndex>,
+}
+
+/// Committees of every slot of `epoch`, in slot and index order. The epoch
+/// must be at most one after the epoch of `state`.
+pub fn epoch_committees<C: Config>(
+	state: &BeaconState<C>,
+	epoch: Epoch,
+) -> Result<Vec<Committee>, BeaconError> {
+	let mut state = state.clone();
+	let reader = StateReader::new(&mut state);
+	let start_slot = utils::start_slot_of_epoch::<C>(epoch);
+
+	let mut committees = Vec::new();
+	for slot in start_slot..(start_slot + C::slots_per_epoch()) {
+		for index in 0..reader.committee_count_at_slot(slot)? {
+			committees.push(Committee {
+				index,
+				slot,
+				validators: reader.beacon_committee(slot, index)?,
+			});
+		}
+	}
+	Ok(committees)
+}
+
+/// Committees as CSV, one row per member.
+pub fn to_csv(committees: &[Committee]) -> String {
+	let mut csv = "slot,index,position,validator_index\n".to_string();
+	for committee in committees {
+		for (position, validator) in committee.validators.iter().enumerate() {
+			writeln!(csv, "{},{},{},{}", committee.slot, committee.index, position, validator)
+				.expect("Writing to a string never fails; qed");
+		}
+	}
+	csv
+}
+
+/// Committees as the JSON document of the beacon API.
+pub fn to_json(committees: &[Committee]) -> String {
+	serde_json::json!({ "data": committees }).to_string()
+}
+
+/// Resolve a state id against the canonical chain of `backend`. States at
+/// skipped slots are advanced from the last canonical block. `None` if the
+/// slot is beyond the head, or the state root is not the post-state of a
+/// canonical block of the last `SLOTS_PER_HISTORICAL_ROOT` slots.
+pub fn resolve_state<B, C: Config>(
+	backend: &B,
+	id: StateId,
+) -> Result<Option<BeaconState<C>>, Error> where
+	B: ChainQuery + SlotQuery + HeaderQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities<Config=C>,
+	Error: From<B::Error>,
+{
+	let head = backend.head();
+	let head_state = backend.state_at(&head).map_err(Error::from)?.state().clone();
+	let checkpoint_root = |root: H256| if is_genesis_root(&root) { backend.genesis() } else { root };
+
+	let (root, slot) = match id {
+		StateId::Head => return Ok(Some(head_state)),
+		StateId::Genesis => (backend.genesis(), None),
+		StateId::Finalized => (checkpoint_root(head_state.finalized_checkpoint.root), None),
+		StateId::Justified => (checkpoint_root(head_state.current_justified_checkpoint.root), None),
+		StateId::Slot(slot) => {
+			if slot > head_state.slot {
+				return Ok(None)
+			}
+			let mut current = slot;
+			loop {
+				if let Some(root) = backend.lookup_canon_slot(current).map_err(Error::from)? {
+					break (root, Some(slot))
+				}
+				match current.checked_sub(1) {
+					Some(previous) => current = previous,
+					None => return Ok(None),
+				}
+			}
+		},
+		StateId::Root(state_root) => {
+			let mut current = Some(head);
+			loop {
+				let root = match current {
+					Some(root) => root,
+					None => return Ok(None),
+				};
+				let header = backend.get_header(&root).map_err(Error::from)?
+					.ok_or(Error::Missing("canonical block header"))?;
+				if header.state_root == state_root {
+					break (root, None)
+				}
+				if header.slot + C::slots_per_historical_root() <= head_state.slot {
+					return Ok(None)
+				}
+				current = parent_id_of(header.parent_root);
+			}
+		},
+	};
+
+	let mut state = backend.state_at(&root).map_err(Error::from)?.state().clone();
+	if let Some(slot) = slot {
+		if state.slot < slot {
+			BeaconExecutive::new(&mut state).process_slots(slot)?;
+		}
+	}
+	Ok(Some(state))
+}
+
+/// Serve `GET /eth/v1/beacon/states/{state_id}/committees` on the public
+/// address of `api`, from a background thread, with the optional `epoch`,
+/// `index` and `slot` filters of the beacon API.
+pub fn serve<B, C: Config>(api: ApiConfig, backend: B) -> Result<(), http::Error> where
+	B: ChainQuery + SlotQuery + HeaderQuery + Store<Block=Block<C>> + Send + Sync + 'static,
+	B::State: StateExternalities<Config=C>,
+	Error: From<B::Error>,
+{
+	http::serve(api, "beacon committees", move |request| route(&backend, request))
+}
+
+fn route<B, C: Config>(backend: &B, request: &Request) -> Response where
+	B: ChainQuery + SlotQuery + HeaderQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities<Config=C>,
+	Error: From<B::Error>,
+{
+	let state_id = match request.segments(STATES_PATH) {
+		Some(ref segments) if segments.len() == 2 && segments[1] == "committees" => segments[0],
+		_ => return http::error(404, "Not found"),
+	};
+	let state_id = match StateId::from_str(state_id) {
+		Ok(state_id) => state_id,
+		Err(e) => return http::error(400, &e),
+	};
+	let mut filters = Vec::new();
+	for name in &["epoch", "index", "slot"] {
+		match request.param(name).map(u64::from_str) {
+			Some(Ok(value)) => filters.push(Some(value)),
+			Some(Err(_)) => return http::error(400, &format!("Invalid {}", name)),
+			None => filters.push(None),
+		}
+	}
+	let (epoch, index, slot) = (filters[0], filters[1], filters[2]);
+
+	let state = match resolve_state(backend, state_id) {
+		Ok(Some(state)) => state,
+		Ok(None) => return http::error(404, "State not found"),
+		Err(e) => return http::error(500, &e.to_string()),
+	};
+	let epoch = epoch.unwrap_or_else(|| utils::epoch_of_slot::<C>(state.slot));
+	match epoch_committees(&state, epoch) {
+		Ok(mut committees) => {
+			committees.retain(|committee| {
+				index.map(|index| committee.index == index).unwrap_or(true) &&
+					slot.map(|slot| committee.slot == slot).unwrap_or(true)
+			});
+			(200, to_json(&committees))
+		},
+		Err(e) => http::error(400, &format!("Committees of epoch {} are not available: {:?}", epoch, e)),
+	}
+}
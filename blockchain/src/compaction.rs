@@ -0,0 +1,123 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Manual and periodic compaction of the persistent store.
+//!
+//! Pruning deletes keys by writing tombstones, which RocksDB only drops when
+//! the affected files are compacted. Until then reads have to skip over them,
+//! so a pruned store gets slower rather than faster. Compaction is run on a
+//! fixed interval by the node, or once through the `compact` subcommand.
+
+use std::fmt::Write as _;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+use blockchain::{Auxiliary, Block};
+use blockchain_rocksdb::{RocksBackend, CompactionStats};
+use log::{info, warn};
+
+/// Statistics of the compactions run by the node.
+#[derive(Clone, Debug, Default)]
+pub struct CompactionMetrics {
+	/// Compactions finished.
+	pub runs: u64,
+	/// Compactions failed.
+	pub failures: u64,
+	/// Duration of the latest compaction.
+	pub last_duration: Duration,
+	/// Total time spent compacting.
+	pub total_duration: Duration,
+	/// Bytes reclaimed by the latest compaction.
+	pub last_reclaimed: u64,
+	/// Total bytes reclaimed.
+	pub total_reclaimed: u64,
+}
+
+/// Compaction metrics shared with the metrics writer.
+pub type SharedCompactionMetrics = Arc<RwLock<CompactionMetrics>>;
+
+impl CompactionMetrics {
+	/// Create empty metrics.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note the outcome of a compaction.
+	pub fn note<E>(&mut self, result: &Result<CompactionStats, E>) {
+		match result {
+			Ok(stats) => {
+				self.runs += 1;
+				self.last_duration = stats.duration;
+				self.total_duration += stats.duration;
+				self.last_reclaimed = stats.reclaimed();
+				self.total_reclaimed += stats.reclaimed();
+			},
+			Err(_) => self.failures += 1,
+		}
+	}
+
+	/// Encode the metrics in the Prometheus text format.
+	pub fn encode(&self) -> String {
+		let mut out = String::new();
+		let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			let _ = writeln!(out, "{} {}", name, value);
+		};
+
+		metric("beacon_db_compactions_total", "counter",
+			   "Database compactions finished", self.runs.to_string());
+		metric("beacon_db_compaction_failures_total", "counter",
+			   "Database compactions failed", self.failures.to_string());
+		metric("beacon_db_compaction_seconds_last", "gauge",
+			   "Duration of the latest database compaction",
+			   self.last_duration.as_secs_f64().to_string());
+		metric("beacon_db_compaction_seconds_total", "counter",
+			   "Time spent compacting the database", self.total_duration.as_secs_f64().to_string());
+		metric("beacon_db_compaction_reclaimed_bytes_last", "gauge",
+			   "Bytes reclaimed by the latest database compaction", self.last_reclaimed.to_string());
+		metric("beacon_db_compaction_reclaimed_bytes_total", "counter",
+			   "Bytes reclaimed by database compactions", self.total_reclaimed.to_string());
+
+		out
+	}
+}
+
+/// Compact the store every `interval` on a background thread, recording the
+/// outcome in `metrics`.
+pub fn spawn_periodic<B, A, S>(
+	backend: RocksBackend<B, A, S>,
+	interval: Duration,
+	metrics: SharedCompactionMetrics,
+) -> thread::JoinHandle<()> where
+	B: Block + Send + Sync + 'static,
+	B::Identifier: Send + Sync,
+	A: Auxiliary<B> + Send + Sync + 'static,
+	S: Send + Sync + 'static,
+{
+	thread::spawn(move || loop {
+		thread::sleep(interval);
+		let result = backend.compact();
+		match &result {
+			Ok(stats) => info!(
+				"Compacted database in {:?}, reclaimed {} bytes ({} bytes left)",
+				stats.duration, stats.reclaimed(), stats.size_after,
+			),
+			Err(e) => warn!("Compacting database failed: {:?}", e),
+		}
+		metrics.write().expect("Lock is poisoned").note(&result);
+	})
+}
@@ -0,0 +1,213 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Data directory layout.
+//!
+//! ```text
+//! <root>/chain       chain database
+//! <root>/freezer     finalized eras of blocks and states
+//! <root>/network     network keys
+//! <root>/validators  validator keys
+//! <root>/traces      block transition traces
+//! <root>/api-token   admin API token
+//! ```
+//!
+//! Older versions kept the chain database directly under the root. Such a
+//! database is moved into `chain` on startup.
+
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use log::info;
+
+/// File present in every chain database.
+const DATABASE_MARKER: &str = "CURRENT";
+
+/// Whether a file of the root directory belongs to a chain database of the
+/// old layout.
+fn is_database_file(name: &str) -> bool {
+	name == DATABASE_MARKER || name == "IDENTITY" || name == "LOCK" ||
+		name.starts_with("LOG") || name.starts_with("MANIFEST-") || name.starts_with("OPTIONS-") ||
+		name.ends_with(".sst") || name.ends_with(".log")
+}
+
+/// Default minimum free disk space required to start, in megabytes.
+pub const DEFAULT_MIN_FREE_SPACE_MB: u64 = 1024;
+
+#[derive(Debug)]
+/// Data directory errors.
+pub enum Error {
+	/// Creating or inspecting the directory failed.
+	Io(io::Error),
+	/// A database exists both under the root, from the old layout, and
+	/// under `chain`.
+	ConflictingDatabases,
+	/// Free disk space is below the required threshold.
+	InsufficientSpace {
+		/// Available bytes.
+		available: u64,
+		/// Required bytes.
+		required: u64,
+	},
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Data directory error: {}", e),
+			Error::ConflictingDatabases => write!(
+				f, "Chain databases found both in the data directory and its chain subdirectory, \
+					remove one of them",
+			),
+			Error::InsufficientSpace { available, required } => write!(
+				f, "Insufficient disk space: {} MB available, at least {} MB required",
+				available / 1024 / 1024, required / 1024 / 1024,
+			),
+		}
+	}
+}
+
+impl std::error::Error for Error { }
+
+impl From<io::Error> for Error {
+	fn from(error: io::Error) -> Error {
+		Error::Io(error)
+	}
+}
+
+/// Node data directory.
+#[derive(Clone, Debug)]
+pub struct DataDir {
+	root: PathBuf,
+}
+
+impl DataDir {
+	/// Data directory at the given root path.
+	pub fn new<P: AsRef<Path>>(root: P) -> Self {
+		Self { root: root.as_ref().to_path_buf() }
+	}
+
+	/// Root path.
+	pub fn root(&self) -> &Path {
+		&self.root
+	}
+
+	/// Chain database path.
+	pub fn chain_db(&self) -> PathBuf {
+		self.root.join("chain")
+	}
+
+	/// Freezer path, holding era files of finalized blocks and states.
+	pub fn freezer(&self) -> PathBuf {
+		self.root.join("freezer")
+	}
+
+	/// Network keys path.
+	pub fn network(&self) -> PathBuf {
+		self.root.join("network")
+	}
+
+	/// Validator keys path.
+	pub fn validators(&self) -> PathBuf {
+		self.root.join("validators")
+	}
+
+	/// Block transition traces path.
+	pub fn traces(&self) -> PathBuf {
+		self.root.join("traces")
+	}
+
+	/// Admin API token path.
+	pub fn api_token(&self) -> PathBuf {
+		self.root.join("api-token")
+	}
+
+	/// Create all subdirectories if missing.
+	pub fn create(&self) -> Result<(), Error> {
+		for path in &[self.chain_db(), self.freezer(), self.network(), self.validators()] {
+			fs::create_dir_all(path)?;
+		}
+		Ok(())
+	}
+
+	/// Move a chain database kept directly under the root, as with the old
+	/// layout, into the chain database path. Returns whether a database was
+	/// moved. Fails without moving anything if both locations hold one.
+	///
+	/// The marker file is moved last, so that a migration interrupted half
+	/// way still leaves the marker under the root, and is resumed on the
+	/// next startup.
+	pub fn migrate_legacy_database(&self) -> Result<bool, Error> {
+		if !self.root.join(DATABASE_MARKER).exists() {
+			return Ok(false)
+		}
+		let chain_db = self.chain_db();
+		if chain_db.join(DATABASE_MARKER).exists() {
+			return Err(Error::ConflictingDatabases)
+		}
+
+		fs::create_dir_all(&chain_db)?;
+		for entry in fs::read_dir(&self.root)? {
+			let entry = entry?;
+			let name = entry.file_name();
+			if name != DATABASE_MARKER && entry.file_type()?.is_file() &&
+				is_database_file(&name.to_string_lossy())
+			{
+				fs::rename(entry.path(), chain_db.join(&name))?;
+			}
+		}
+		fs::rename(self.root.join(DATABASE_MARKER), chain_db.join(DATABASE_MARKER))?;
+		Ok(true)
+	}
+
+	/// Free disk space, in bytes, on the file system holding the directory.
+	pub fn free_space(&self) -> Result<u64, Error> {
+		Ok(free_space(&self.root)?)
+	}
+
+	/// Create the directory layout, moving a database of the old layout, and
+	/// fail if free disk space is below `min_free_space` bytes.
+	pub fn prepare(&self, min_free_space: u64) -> Result<(), Error> {
+		self.create()?;
+		if self.migrate_legacy_database()? {
+			info!("Moved chain database of the old data directory layout to {:?}", self.chain_db());
+		}
+
+		let available = self.free_space()?;
+		if available < min_free_space {
+			return Err(Error::InsufficientSpace { available, required: min_free_space })
+		}
+		Ok(())
+	}
+}
+
+#[cfg(unix)]
+fn free_space(path: &Path) -> io::Result<u64> {
+	use std::ffi::CString;
+	use std::os::unix::ffi::OsStrExt;
+
+	let path = CString::new(path.as_os_str().as_bytes())
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+	if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+		return Err(io::Error::last_os_error())
+	}
+	Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> io::Result<u64> {
+	Ok(u64::max_value())
+}
@@ -0,0 +1,411 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Differential testing of the state transition against a reference.
+//!
+//! Random, structurally plausible blocks are built on top of a state and
+//! run through our transition and through another client's transition tool,
+//! invoked as a subprocess. Any disagreement on block validity or on the
+//! post-state root is reported and saved for reproduction.
+//!
+//! Generated blocks are not signed, so both sides must skip signature
+//! verification.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use beacon::{BeaconState, BeaconExecutive, BLSNoVerification, Config};
+use beacon::primitives::{H256, Signature};
+use beacon::types::{
+	Attestation, AttestationData, BeaconBlock, BeaconBlockBody, BeaconBlockHeader, Checkpoint,
+	Eth1Data, ProposerSlashing, SigningBeaconBlockHeader, VoluntaryExit,
+};
+use beacon::utils::epoch_of_slot;
+use bm_le::tree_root;
+use log::{info, warn};
+use rand::Rng;
+
+/// Maximum number of empty slots before a generated block.
+pub const MAX_SKIPPED_SLOTS: u64 = 2;
+/// Probability that a generated block is mutated after sealing.
+pub const MUTATION_PROBABILITY: f64 = 0.2;
+/// Maximum number of attestations in a generated block.
+pub const MAX_GENERATED_ATTESTATIONS: u64 = 4;
+/// Maximum number of voluntary exits in a generated block.
+pub const MAX_GENERATED_EXITS: u64 = 2;
+
+/// Differential testing errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Reading or writing case files failed.
+	Io(std::io::Error),
+	/// The reference command could not be started.
+	Spawn(std::io::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Case file IO failed: {}", e),
+			Error::Spawn(e) => write!(f, "Starting reference failed: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) | Error::Spawn(e) => Some(e),
+		}
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(error: std::io::Error) -> Error {
+		Error::Io(error)
+	}
+}
+
+/// Result of running a block.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+	/// The block was accepted, with the given post-state root.
+	Valid(H256),
+	/// The block was rejected.
+	Invalid(String),
+}
+
+impl Outcome {
+	/// Whether two outcomes agree. Rejection reasons are not compared.
+	pub fn agrees(&self, other: &Outcome) -> bool {
+		match (self, other) {
+			(Outcome::Valid(a), Outcome::Valid(b)) => a == b,
+			(Outcome::Invalid(_), Outcome::Invalid(_)) => true,
+			_ => false,
+		}
+	}
+}
+
+impl fmt::Display for Outcome {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Outcome::Valid(root) => write!(f, "valid, post-state root {:?}", root),
+			Outcome::Invalid(reason) => write!(f, "invalid ({})", reason),
+		}
+	}
+}
+
+/// External transition tool.
+///
+/// The command is given as a template, where `{pre}`, `{block}` and `{post}`
+/// are replaced by the paths of the ssz encoded pre-state, the block and the
+/// post-state to write. A non-zero exit status means the block is invalid.
+#[derive(Clone, Debug)]
+pub struct Reference {
+	template: Vec<String>,
+}
+
+impl Reference {
+	/// Reference from a whitespace separated command template.
+	pub fn new(template: &str) -> Self {
+		Self { template: template.split_whitespace().map(|s| s.to_string()).collect() }
+	}
+
+	/// Run the reference on a case in `dir`.
+	pub fn run<C: Config>(
+		&self,
+		dir: &Path,
+		pre: &BeaconState<C>,
+		block: &BeaconBlock<C>,
+	) -> Result<(Outcome, Option<BeaconState<C>>), Error> {
+		let pre_path = dir.join("pre.ssz");
+		let block_path = dir.join("block.ssz");
+		let post_path = dir.join("reference-post.ssz");
+		fs::write(&pre_path, ssz::Encode::encode(pre))?;
+		fs::write(&block_path, ssz::Encode::encode(block))?;
+		let _ = fs::remove_file(&post_path);
+
+		let args = self.template.iter().map(|arg| {
+			arg.replace("{pre}", &pre_path.to_string_lossy())
+				.replace("{block}", &block_path.to_string_lossy())
+				.replace("{post}", &post_path.to_string_lossy())
+		}).collect::<Vec<_>>();
+		let (program, args) = match args.split_first() {
+			Some(split) => split,
+			None => return Err(Error::Spawn(
+				std::io::Error::new(std::io::ErrorKind::InvalidInput, "Empty reference command")
+			)),
+		};
+		let status = Command::new(program).args(args).status().map_err(Error::Spawn)?;
+		if !status.success() {
+			return Ok((Outcome::Invalid(format!("exit status {}", status)), None))
+		}
+
+		let data = fs::read(&post_path)?;
+		match <BeaconState<C> as ssz::Decode>::decode(&data[..]) {
+			Ok(post) => Ok((Outcome::Valid(tree_root::<C::Digest, _>(&post)), Some(post))),
+			Err(e) => Ok((Outcome::Invalid(format!("undecodable post-state: {:?}", e)), None)),
+		}
+	}
+}
+
+/// Run a block through our transition, without signature verification.
+pub fn run_ours<C: Config>(
+	pre: &BeaconState<C>,
+	block: &BeaconBlock<C>,
+) -> (Outcome, Option<BeaconState<C>>) {
+	let mut post = pre.clone();
+	match beacon::execute_block::<C, BLSNoVerification>(block, &mut post) {
+		Ok(()) => (Outcome::Valid(tree_root::<C::Digest, _>(&post)), Some(post)),
+		Err(e) => (Outcome::Invalid(format!("{:?}", e)), None),
+	}
+}
+
+fn random_hash<R: Rng>(rng: &mut R) -> H256 {
+	let mut bytes = [0u8; 32];
+	rng.fill(&mut bytes[..]);
+	H256::from_slice(&bytes)
+}
+
+fn random_signature<R: Rng>(rng: &mut R) -> Signature {
+	let mut bytes = [0u8; 96];
+	rng.fill(&mut bytes[..]);
+	Signature::from_slice(&bytes)
+}
+
+/// Build a random block on top of `state`. Operations are built to be
+/// plausible for the state, and the state root is filled in if the block
+/// applies, so that most blocks reach deep into the transition. Blocks are
+/// then mutated with probability `MUTATION_PROBABILITY` to exercise the
+/// rejection paths.
+pub fn random_block<C: Config, R: Rng>(rng: &mut R, state: &BeaconState<C>) -> BeaconBlock<C> {
+	let mut post = state.clone();
+	let slot = state.slot + rng.gen_range(1, MAX_SKIPPED_SLOTS + 2);
+	let _ = beacon::initialize_block(&mut post, slot);
+
+	let parent_root = tree_root::<C::Digest, _>(
+		&SigningBeaconBlockHeader::from(post.latest_block_header.clone())
+	);
+	let eth1_data = if rng.gen_bool(0.5) {
+		post.eth1_data.clone()
+	} else {
+		Eth1Data {
+			deposit_root: random_hash(rng),
+			deposit_count: post.eth1_data.deposit_count,
+			block_hash: random_hash(rng),
+		}
+	};
+
+	let mut attestations = Vec::new();
+	let mut voluntary_exits = Vec::new();
+	let mut proposer_slashings = Vec::new();
+	{
+		let executive = BeaconExecutive::new(&mut post);
+		let validator_count = executive.validators.len() as u64;
+		let current_epoch = executive.current_epoch();
+
+		let earliest = slot.saturating_sub(C::slots_per_epoch());
+		let latest = slot.saturating_sub(C::min_attestation_inclusion_delay());
+		if slot >= C::min_attestation_inclusion_delay() && earliest <= latest {
+			for _ in 0..rng.gen_range(0, MAX_GENERATED_ATTESTATIONS + 1) {
+				let attestation_slot = rng.gen_range(earliest, latest + 1);
+				let index = rng.gen_range(0, executive.committee_count_at_slot(attestation_slot));
+				let committee = match executive.beacon_committee(attestation_slot, index) {
+					Ok(committee) => committee,
+					Err(_) => continue,
+				};
+				let target_epoch = epoch_of_slot::<C>(attestation_slot);
+				let source = if target_epoch == current_epoch {
+					executive.current_justified_checkpoint.clone()
+				} else {
+					executive.previous_justified_checkpoint.clone()
+				};
+				let data = AttestationData {
+					slot: attestation_slot,
+					index,
+					beacon_block_root: executive.block_root_at_slot(attestation_slot)
+						.unwrap_or_default(),
+					source,
+					target: Checkpoint {
+						epoch: target_epoch,
+						root: executive.block_root(target_epoch).unwrap_or_default(),
+					},
+				};
+				let aggregation_bits = committee.iter().map(|_| rng.gen_bool(0.5)).collect::<Vec<_>>();
+				let custody_bits = committee.iter().map(|_| false).collect::<Vec<_>>();
+				attestations.push(Attestation {
+					aggregation_bits: aggregation_bits.into(),
+					data,
+					custody_bits: custody_bits.into(),
+					signature: Signature::default(),
+				});
+			}
+		}
+
+		let mut exiting = HashSet::new();
+		if validator_count > 0 {
+			for _ in 0..rng.gen_range(0, MAX_GENERATED_EXITS + 1) {
+				let validator_index = rng.gen_range(0, validator_count);
+				if exiting.insert(validator_index) {
+					voluntary_exits.push(VoluntaryExit {
+						epoch: current_epoch,
+						validator_index,
+						signature: Signature::default(),
+					});
+				}
+			}
+
+			if rng.gen_bool(0.1) {
+				let header_1 = BeaconBlockHeader {
+					slot: rng.gen_range(earliest, slot + 1),
+					parent_root: random_hash(rng),
+					state_root: random_hash(rng),
+					body_root: random_hash(rng),
+					signature: Signature::default(),
+				};
+				let mut header_2 = header_1.clone();
+				header_2.body_root = random_hash(rng);
+				proposer_slashings.push(ProposerSlashing {
+					proposer_index: rng.gen_range(0, validator_count),
+					header_1,
+					header_2,
+				});
+			}
+		}
+	}
+
+	let mut block = BeaconBlock {
+		slot,
+		parent_root,
+		state_root: H256::default(),
+		body: BeaconBlockBody {
+			randao_reveal: random_signature(rng),
+			eth1_data,
+			graffiti: random_hash(rng),
+			proposer_slashings: proposer_slashings.into(),
+			attestations: attestations.into(),
+			voluntary_exits: voluntary_exits.into(),
+			..Default::default()
+		},
+		signature: Signature::default(),
+	};
+
+	let mut executive = BeaconExecutive::new(&mut post);
+	block.state_root = match executive.process_block::<_, BLSNoVerification>(&block) {
+		Ok(()) => tree_root::<C::Digest, _>(&post),
+		Err(_) => random_hash(rng),
+	};
+
+	if rng.gen_bool(MUTATION_PROBABILITY) {
+		mutate(rng, &mut block);
+	}
+	block
+}
+
+/// Apply a single random mutation to a block.
+pub fn mutate<C: Config, R: Rng>(rng: &mut R, block: &mut BeaconBlock<C>) {
+	match rng.gen_range(0, 6) {
+		0 => block.slot = block.slot.wrapping_add(rng.gen_range(1, 3)),
+		1 => block.parent_root = random_hash(rng),
+		2 => block.state_root = random_hash(rng),
+		3 => if let Some(attestation) = block.body.attestations.last_mut() {
+			attestation.data.index = attestation.data.index.wrapping_add(1);
+		},
+		4 => if let Some(attestation) = block.body.attestations.last_mut() {
+			attestation.data.target.epoch = attestation.data.target.epoch.wrapping_add(1);
+		},
+		_ => if let Some(exit) = block.body.voluntary_exits.last_mut() {
+			exit.validator_index = rng.gen();
+		},
+	}
+}
+
+/// Differential testing session.
+pub struct DiffTest<C: Config> {
+	reference: Reference,
+	dir: PathBuf,
+	state: BeaconState<C>,
+}
+
+/// Summary of a differential testing session.
+#[derive(Clone, Default, Debug)]
+pub struct Report {
+	/// Number of blocks run.
+	pub cases: usize,
+	/// Number of blocks both sides accepted with the same root.
+	pub valid: usize,
+	/// Number of blocks both sides rejected.
+	pub invalid: usize,
+	/// Directories of saved mismatching cases.
+	pub mismatches: Vec<PathBuf>,
+}
+
+impl<C: Config> DiffTest<C> {
+	/// Start a session from `state`, keeping case files in `dir`.
+	pub fn new(reference: Reference, dir: PathBuf, state: BeaconState<C>) -> Self {
+		Self { reference, dir, state }
+	}
+
+	/// Run `iterations` random blocks. Accepted blocks extend the chain that
+	/// following blocks are built on.
+	pub fn run<R: Rng>(&mut self, rng: &mut R, iterations: usize) -> Result<Report, Error> {
+		let mut report = Report::default();
+		let work = self.dir.join("work");
+		fs::create_dir_all(&work)?;
+
+		for iteration in 0..iterations {
+			let block = random_block(rng, &self.state);
+			let (ours, our_post) = run_ours(&self.state, &block);
+			let (theirs, their_post) = self.reference.run(&work, &self.state, &block)?;
+			report.cases += 1;
+
+			if !ours.agrees(&theirs) {
+				let case = self.dir.join(format!("mismatch-{}", iteration));
+				fs::create_dir_all(&case)?;
+				fs::write(case.join("pre.ssz"), ssz::Encode::encode(&self.state))?;
+				fs::write(case.join("block.ssz"), ssz::Encode::encode(&block))?;
+				if let Some(post) = our_post.as_ref() {
+					fs::write(case.join("post.ssz"), ssz::Encode::encode(post))?;
+				}
+				if let Some(post) = their_post.as_ref() {
+					fs::write(case.join("reference-post.ssz"), ssz::Encode::encode(post))?;
+				}
+				warn!(
+					"Mismatch at slot {}: ours {}, reference {}, saved to {:?}",
+					block.slot, ours, theirs, case,
+				);
+				report.mismatches.push(case);
+				continue
+			}
+
+			match our_post {
+				Some(post) => {
+					report.valid += 1;
+					self.state = post;
+				},
+				None => report.invalid += 1,
+			}
+		}
+
+		info!(
+			"Ran {} blocks: {} valid, {} invalid, {} mismatches",
+			report.cases, report.valid, report.invalid, report.mismatches.len(),
+		);
+		Ok(report)
+	}
+}
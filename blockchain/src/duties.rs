@@ -0,0 +1,190 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validator duties with their dependent root.
+//!
+//! The dependent root is the block root duties were computed from. Proposer
+//! duties of an epoch depend on the last block before the epoch, and attester
+//! duties on the last block before the previous epoch. Once the dependent root
+//! changes after a reorg, duties must be recomputed.
+
+use beacon::{Config, BeaconState, BeaconExecutive, StateReader, Error as BeaconError};
+use beacon::primitives::{Epoch, Slot, ValidatorId, ValidatorIndex, H256};
+use beacon::utils;
+use blockchain::backend::{Store, ChainQuery};
+use crate::{Block, StateExternalities, Error};
+
+/// Block proposal duty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProposerDuty {
+	/// Public key of the proposer.
+	pub pubkey: ValidatorId,
+	/// Index of the proposer.
+	pub validator_index: ValidatorIndex,
+	/// Slot to propose at.
+	pub slot: Slot,
+}
+
+/// Attestation duty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttesterDuty {
+	/// Public key of the attester.
+	pub pubkey: ValidatorId,
+	/// Index of the attester.
+	pub validator_index: ValidatorIndex,
+	/// Index of the committee.
+	pub committee_index: u64,
+	/// Number of validators in the committee.
+	pub committee_length: usize,
+	/// Number of committees at the slot.
+	pub committees_at_slot: u64,
+	/// Position of the attester in the committee.
+	pub validator_committee_index: usize,
+	/// Slot to attest at.
+	pub slot: Slot,
+}
+
+/// Duties of an epoch, with the block root they depend on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Duties<D> {
+	/// Root of the block the duties were computed from.
+	pub dependent_root: H256,
+	/// Duties.
+	pub duties: Vec<D>,
+}
+
+/// Proposer duties of `epoch`, given the state of the head block. Only the
+/// epoch of the head state and the next one can be computed.
+pub fn proposer_duties<C: Config>(
+	state: &BeaconState<C>,
+	head_root: H256,
+	genesis_root: H256,
+	epoch: Epoch,
+) -> Result<Duties<ProposerDuty>, Error> {
+	let mut state = state.clone();
+	let start_slot = utils::start_slot_of_epoch::<C>(epoch);
+	let dependent_root = {
+		let reader = StateReader::new(&mut state);
+		if epoch < reader.current_epoch() || epoch > reader.current_epoch() + 1 {
+			return Err(BeaconError::EpochOutOfRange.into())
+		}
+		dependent_root(&reader, head_root, genesis_root, start_slot.checked_sub(1))?
+	};
+
+	if start_slot > state.slot {
+		BeaconExecutive::new(&mut state).process_slots(start_slot)?;
+	}
+
+	let mut duties = Vec::new();
+	for slot in start_slot..(start_slot + C::slots_per_epoch()) {
+		// Proposers of an epoch only depend on the slot, once the state is
+		// within the epoch.
+		state.slot = slot;
+		let reader = StateReader::new(&mut state);
+		let validator_index = reader.beacon_proposer_index()?;
+		duties.push(ProposerDuty {
+			pubkey: reader.validator_pubkey(validator_index)?,
+			validator_index,
+			slot,
+		});
+	}
+
+	Ok(Duties { dependent_root, duties })
+}
+
+/// Attester duties of the given validators at `epoch`, given the state of the
+/// head block. Validators without an assignment are skipped.
+pub fn attester_duties<C: Config>(
+	state: &BeaconState<C>,
+	head_root: H256,
+	genesis_root: H256,
+	epoch: Epoch,
+	validator_indices: &[ValidatorIndex],
+) -> Result<Duties<AttesterDuty>, Error> {
+	let mut state = state.clone();
+	let reader = StateReader::new(&mut state);
+	let dependent_slot = epoch.checked_sub(1)
+		.and_then(|previous_epoch| utils::start_slot_of_epoch::<C>(previous_epoch).checked_sub(1));
+	let dependent_root = dependent_root(&reader, head_root, genesis_root, dependent_slot)?;
+
+	let mut duties = Vec::new();
+	for validator_index in validator_indices {
+		let assignment = match reader.committee_assignment(epoch, *validator_index)? {
+			Some(assignment) => assignment,
+			None => continue,
+		};
+		let validator_committee_index = assignment.validators.iter()
+			.position(|index| index == validator_index)
+			.ok_or(BeaconError::ValidatorAttestationNotFound)?;
+
+		duties.push(AttesterDuty {
+			pubkey: reader.validator_pubkey(*validator_index)?,
+			validator_index: *validator_index,
+			committee_index: assignment.index,
+			committee_length: assignment.validators.len(),
+			committees_at_slot: reader.committee_count_at_slot(assignment.slot)?,
+			validator_committee_index,
+			slot: assignment.slot,
+		});
+	}
+
+	Ok(Duties { dependent_root, duties })
+}
+
+/// Proposer duties of `epoch` at the current head of the backend.
+pub fn head_proposer_duties<C: Config, B>(
+	backend: &B,
+	epoch: Epoch,
+) -> Result<Duties<ProposerDuty>, Error> where
+	B: ChainQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities<Config=C>,
+	Error: From<B::Error>,
+{
+	let head = backend.head();
+	let state = backend.state_at(&head).map_err(Error::from)?;
+	proposer_duties(state.state(), head, backend.genesis(), epoch)
+}
+
+/// Attester duties of the given validators at `epoch`, at the current head of
+/// the backend.
+pub fn head_attester_duties<C: Config, B>(
+	backend: &B,
+	epoch: Epoch,
+	validator_indices: &[ValidatorIndex],
+) -> Result<Duties<AttesterDuty>, Error> where
+	B: ChainQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities<Config=C>,
+	Error: From<B::Error>,
+{
+	let head = backend.head();
+	let state = backend.state_at(&head).map_err(Error::from)?;
+	attester_duties(state.state(), head, backend.genesis(), epoch, validator_indices)
+}
+
+/// Root of the last block at or before `slot`, or the genesis root if there
+/// is no such slot.
+fn dependent_root<C: Config>(
+	reader: &StateReader<C>,
+	head_root: H256,
+	genesis_root: H256,
+	slot: Option<Slot>,
+) -> Result<H256, Error> {
+	match slot {
+		None => Ok(genesis_root),
+		Some(slot) if slot >= reader.slot() => Ok(head_root),
+		Some(slot) => Ok(reader.block_root_at_slot(slot)?),
+	}
+}
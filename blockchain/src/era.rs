@@ -0,0 +1,334 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Flat archive files of finalized blocks.
+//!
+//! Finalized history is exported into one append-only file per era of
+//! `SLOTS_PER_HISTORICAL_ROOT` slots, holding the canonical blocks of the
+//! era in slot order, followed by the post state of its last block. Such
+//! files can be distributed out of band, and imported by another node far
+//! faster than syncing the same blocks over the network.
+//!
+//! A file starts with `ERA_MAGIC` and the era index as little endian `u64`,
+//! followed by records of a kind byte, a little endian `u32` length and the
+//! SSZ encoding of the block or state.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use beacon::{BeaconState, Config};
+use beacon::primitives::H256;
+use blockchain::Block as BlockT;
+use blockchain::backend::{ChainQuery, Store};
+use blockchain::import::BlockImporter;
+use bm_le::tree_root;
+use log::{debug, info, warn};
+use crate::{Block, SlotQuery, StoreIterators, StateExternalities};
+use crate::import_queue::{self, ImportQueue};
+
+/// Magic bytes at the start of an era file.
+pub const ERA_MAGIC: &[u8; 8] = b"shasera1";
+/// Extension of era files.
+pub const ERA_EXTENSION: &str = "era";
+
+const RECORD_BLOCK: u8 = 1;
+const RECORD_STATE: u8 = 2;
+
+/// Era file errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Reading or writing the file failed.
+	Io(io::Error),
+	/// The file does not start with `ERA_MAGIC`.
+	InvalidMagic,
+	/// A record has an unknown kind.
+	UnknownRecord(u8),
+	/// A record could not be decoded.
+	Decode(String),
+	/// The state does not match the state root of the last block.
+	StateMismatch,
+	/// Storage backend failed.
+	Backend(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Era file I/O failed: {}", e),
+			Error::InvalidMagic => write!(f, "Not an era file"),
+			Error::UnknownRecord(kind) => write!(f, "Unknown era record kind {}", kind),
+			Error::Decode(e) => write!(f, "Decoding era record failed: {}", e),
+			Error::StateMismatch => write!(f, "Era state does not match its last block"),
+			Error::Backend(e) => write!(f, "Storage backend failed: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Self {
+		Error::Io(e)
+	}
+}
+
+/// Record of an era file.
+pub enum Record<C: Config> {
+	/// Canonical block.
+	Block(Block<C>),
+	/// Post state of the last block of the era.
+	State(BeaconState<C>),
+}
+
+/// Path of the file of era `index` within `dir`.
+pub fn era_path<P: AsRef<Path>>(dir: P, index: u64) -> PathBuf {
+	dir.as_ref().join(format!("{:06}.{}", index, ERA_EXTENSION))
+}
+
+/// Writer of a single era file. Records are written to a temporary file,
+/// which is moved in place once finished, so that readers never see a
+/// partial era.
+pub struct EraWriter {
+	file: BufWriter<File>,
+	tmp: PathBuf,
+	path: PathBuf,
+}
+
+impl EraWriter {
+	/// Start writing the file of era `index` within `dir`.
+	pub fn create<P: AsRef<Path>>(dir: P, index: u64) -> Result<Self, Error> {
+		let path = era_path(dir, index);
+		let tmp = path.with_extension("tmp");
+		let mut file = BufWriter::new(File::create(&tmp)?);
+		file.write_all(ERA_MAGIC)?;
+		file.write_all(&index.to_le_bytes())?;
+		Ok(Self { file, tmp, path })
+	}
+
+	fn append(&mut self, kind: u8, data: &[u8]) -> Result<(), Error> {
+		self.file.write_all(&[kind])?;
+		self.file.write_all(&(data.len() as u32).to_le_bytes())?;
+		self.file.write_all(data)?;
+		Ok(())
+	}
+
+	/// Append a block.
+	pub fn append_block<C: Config>(&mut self, block: &Block<C>) -> Result<(), Error> {
+		self.append(RECORD_BLOCK, &ssz::Encode::encode(block))
+	}
+
+	/// Append a state.
+	pub fn append_state<C: Config>(&mut self, state: &BeaconState<C>) -> Result<(), Error> {
+		self.append(RECORD_STATE, &ssz::Encode::encode(state))
+	}
+
+	/// Flush the file and move it in place.
+	pub fn finish(mut self) -> Result<PathBuf, Error> {
+		self.file.flush()?;
+		self.file.get_ref().sync_all()?;
+		fs::rename(&self.tmp, &self.path)?;
+		Ok(self.path)
+	}
+}
+
+/// Reader of the records of an era file.
+pub struct EraReader<C: Config> {
+	file: BufReader<File>,
+	index: u64,
+	last_state_root: Option<H256>,
+	_marker: std::marker::PhantomData<C>,
+}
+
+impl<C: Config> EraReader<C> {
+	/// Open an era file, checking its header.
+	pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+		let mut file = BufReader::new(File::open(path)?);
+		let mut magic = [0u8; 8];
+		file.read_exact(&mut magic)?;
+		if &magic != ERA_MAGIC {
+			return Err(Error::InvalidMagic)
+		}
+		let mut index = [0u8; 8];
+		file.read_exact(&mut index)?;
+		Ok(Self {
+			file,
+			index: u64::from_le_bytes(index),
+			last_state_root: None,
+			_marker: std::marker::PhantomData,
+		})
+	}
+
+	/// Index of the era.
+	pub fn index(&self) -> u64 {
+		self.index
+	}
+
+	/// Read the next record. Returns `None` at the end of the file. States
+	/// are checked against the state root of the preceding block.
+	pub fn next_record(&mut self) -> Result<Option<Record<C>>, Error> {
+		let mut kind = [0u8; 1];
+		match self.file.read_exact(&mut kind) {
+			Ok(()) => (),
+			Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+			Err(e) => return Err(e.into()),
+		}
+		let mut len = [0u8; 4];
+		self.file.read_exact(&mut len)?;
+		let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+		self.file.read_exact(&mut data)?;
+
+		match kind[0] {
+			RECORD_BLOCK => {
+				let block: Block<C> = ssz::Decode::decode(&data[..])
+					.map_err(|e| Error::Decode(format!("{:?}", e)))?;
+				self.last_state_root = Some(block.state_root());
+				Ok(Some(Record::Block(block)))
+			},
+			RECORD_STATE => {
+				let state: BeaconState<C> = ssz::Decode::decode(&data[..])
+					.map_err(|e| Error::Decode(format!("{:?}", e)))?;
+				if self.last_state_root != Some(tree_root::<C::Digest, _>(&state)) {
+					return Err(Error::StateMismatch)
+				}
+				Ok(Some(Record::State(state)))
+			},
+			kind => Err(Error::UnknownRecord(kind)),
+		}
+	}
+}
+
+/// Export era `index` of the canonical chain of `backend` into `dir`.
+/// Returns `false` if the era has no stored blocks, such as eras before a
+/// checkpoint sync anchor, in which case no file is written.
+pub fn export_era<B, C: Config>(backend: &B, dir: &Path, index: u64) -> Result<bool, Error> where
+	B: ChainQuery + SlotQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities<Config=C>,
+	B::Error: fmt::Debug,
+{
+	let slots = C::slots_per_historical_root();
+	let ids = backend.iter_canon_roots((index * slots)..((index + 1) * slots))
+		.map(|root| root.map(|(_, id)| id))
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(|e| Error::Backend(format!("{:?}", e)))?;
+	let last = match ids.last() {
+		Some(last) => *last,
+		None => return Ok(false),
+	};
+
+	let mut writer = EraWriter::create(dir, index)?;
+	for id in &ids {
+		let block = backend.block_at(id).map_err(|e| Error::Backend(format!("{:?}", e)))?;
+		writer.append_block(&block)?;
+	}
+	match backend.state_at(&last) {
+		Ok(state) => writer.append_state(state.state())?,
+		Err(e) => warn!("State of era {} is not stored, exporting blocks only: {:?}", index, e),
+	}
+	writer.finish()?;
+	Ok(true)
+}
+
+/// Export finalized eras into `dir` in the background, checking for newly
+/// finalized eras every `interval`. Eras already exported are skipped.
+pub fn spawn_export<B, C: Config>(backend: B, dir: PathBuf, interval: Duration) where
+	B: ChainQuery + SlotQuery + Store<Block=Block<C>> + Send + 'static,
+	B::State: StateExternalities<Config=C>,
+	B::Error: fmt::Debug,
+{
+	thread::Builder::new()
+		.name("era-export".to_string())
+		.spawn(move || {
+			let mut next = 0;
+			loop {
+				let finalized_slot = match backend.state_at(&backend.head()) {
+					Ok(state) => state.state().finalized_checkpoint.epoch * C::slots_per_epoch(),
+					Err(e) => {
+						warn!("Reading head state for era export failed: {:?}", e);
+						0
+					},
+				};
+
+				while (next + 1) * C::slots_per_historical_root() <= finalized_slot {
+					if !era_path(&dir, next).exists() {
+						match export_era(&backend, &dir, next) {
+							Ok(true) => info!("Exported era {}", next),
+							Ok(false) => debug!("Era {} has no stored blocks", next),
+							Err(e) => {
+								warn!("Exporting era {} failed: {}", next, e);
+								break
+							},
+						}
+					}
+					next += 1;
+				}
+				thread::sleep(interval);
+			}
+		})
+		.expect("Spawning era export thread failed");
+}
+
+/// Era files within `dir`, in era order.
+pub fn era_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, Error> {
+	let mut files = fs::read_dir(dir)?
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|path| path.extension().map(|ext| ext == ERA_EXTENSION).unwrap_or(false))
+		.collect::<Vec<_>>();
+	files.sort();
+	Ok(files)
+}
+
+/// Import the blocks of the era files within `dir` into `queue`, waiting
+/// while the queue is full. Returns the number of blocks queued and the
+/// last of them. An era file that fails to read stops the import, as later
+/// blocks would not connect.
+pub fn import_eras<C: Config>(
+	dir: &Path,
+	queue: &mut ImportQueue<Block<C>>,
+) -> Result<(usize, Option<H256>), Error> {
+	let mut queued = 0;
+	let mut last = None;
+	for path in era_files(dir)? {
+		let mut reader = EraReader::<C>::open(&path)?;
+		debug!("Importing era {} from {}", reader.index(), path.display());
+		while let Some(record) = reader.next_record()? {
+			let block = match record {
+				Record::Block(block) => block,
+				Record::State(_) => continue,
+			};
+			let id = block.id();
+			loop {
+				match queue.import_block(block.clone()) {
+					Ok(()) => break,
+					Err(import_queue::Error::Full) => thread::sleep(Duration::from_millis(10)),
+					Err(import_queue::Error::Closed) => return Ok((queued, last)),
+				}
+			}
+			queued += 1;
+			last = Some(id);
+		}
+	}
+	Ok((queued, last))
+}
@@ -0,0 +1,503 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Eth1 deposit tree, mirroring the deposit contract.
+//!
+//! The tree is sparse in the sense of EIP-4881: leaves below the finalized
+//! deposit count are collapsed into the roots of the largest full subtrees
+//! covering them, so only the finalized branch and the pending leaves need to
+//! be kept around.
+//!
+//! The deposit cache follows the deposit contract block by block on top of
+//! the tree. It is persisted in the index store so that log scanning resumes
+//! from the last processed block after a restart, and it can be rolled back
+//! to a common ancestor when the eth1 chain reorganizes.
+
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::consts::DEPOSIT_CONTRACT_TREE_DEPTH;
+use beacon::types::DepositData;
+use parity_codec::{Encode, Decode};
+use bm_le::tree_root;
+use core::marker::PhantomData;
+use crate::index::IndexStore;
+
+const DEPTH: usize = DEPOSIT_CONTRACT_TREE_DEPTH as usize;
+const KEY_DEPOSIT_CACHE: &[u8] = b"deposit_cache";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+/// Deposit tree errors.
+pub enum Error {
+	/// Requested leaf has already been finalized and pruned.
+	Finalized,
+	/// Requested leaf or count is beyond the tree.
+	OutOfRange,
+	/// Deposit tree is full.
+	Full,
+	/// Snapshot does not match its deposit root.
+	InvalidSnapshot,
+	/// Eth1 block is not after the last processed one.
+	OutOfOrder,
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{:?}", self)
+	}
+}
+
+impl std::error::Error for Error { }
+
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+/// Persistable snapshot of the finalized part of a deposit tree.
+pub struct DepositTreeSnapshot {
+	/// Roots of the finalized subtrees, from the leftmost one.
+	pub finalized: Vec<H256>,
+	/// Deposit root at the time of the snapshot.
+	pub deposit_root: H256,
+	/// Number of deposits covered by the snapshot.
+	pub deposit_count: u64,
+	/// Eth1 block hash at which the snapshot was taken.
+	pub execution_block_hash: H256,
+	/// Eth1 block height at which the snapshot was taken.
+	pub execution_block_height: u64,
+}
+
+/// Sparse deposit Merkle tree.
+#[derive(Clone)]
+pub struct DepositTree<C: Config> {
+	finalized: Vec<H256>,
+	finalized_count: u64,
+	leaves: Vec<H256>,
+	zerohashes: Vec<H256>,
+	execution_block_hash: H256,
+	execution_block_height: u64,
+	_marker: PhantomData<C>,
+}
+
+impl<C: Config> DepositTree<C> {
+	/// Create an empty deposit tree.
+	pub fn new() -> Self {
+		let mut zerohashes = vec![H256::default()];
+		for layer in 1..(DEPTH + 1) {
+			zerohashes.push(C::hash(&[
+				zerohashes[layer - 1].as_ref(),
+				zerohashes[layer - 1].as_ref(),
+			]));
+		}
+
+		Self {
+			finalized: Vec::new(),
+			finalized_count: 0,
+			leaves: Vec::new(),
+			zerohashes,
+			execution_block_hash: H256::default(),
+			execution_block_height: 0,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Restore a deposit tree from a snapshot.
+	pub fn from_snapshot(snapshot: &DepositTreeSnapshot) -> Result<Self, Error> {
+		let mut tree = Self::new();
+		if tree.finalized_subtrees(snapshot.deposit_count).len() != snapshot.finalized.len() {
+			return Err(Error::InvalidSnapshot)
+		}
+
+		tree.finalized = snapshot.finalized.clone();
+		tree.finalized_count = snapshot.deposit_count;
+		tree.execution_block_hash = snapshot.execution_block_hash;
+		tree.execution_block_height = snapshot.execution_block_height;
+
+		if tree.root()? != snapshot.deposit_root {
+			return Err(Error::InvalidSnapshot)
+		}
+
+		Ok(tree)
+	}
+
+	/// Take a snapshot of the finalized part of the tree.
+	pub fn snapshot(&self) -> Result<DepositTreeSnapshot, Error> {
+		Ok(DepositTreeSnapshot {
+			finalized: self.finalized.clone(),
+			deposit_root: self.root_at(self.finalized_count)?,
+			deposit_count: self.finalized_count,
+			execution_block_hash: self.execution_block_hash,
+			execution_block_height: self.execution_block_height,
+		})
+	}
+
+	/// Total number of deposits in the tree.
+	pub fn deposit_count(&self) -> u64 {
+		self.finalized_count + self.leaves.len() as u64
+	}
+
+	/// Number of deposits that have been finalized.
+	pub fn finalized_count(&self) -> u64 {
+		self.finalized_count
+	}
+
+	/// Hash and height of the eth1 block the tree was last finalized at.
+	pub fn execution_block(&self) -> (H256, u64) {
+		(self.execution_block_hash, self.execution_block_height)
+	}
+
+	/// Push a new deposit leaf.
+	pub fn push_leaf(&mut self, leaf: H256) -> Result<(), Error> {
+		if self.deposit_count() >= 2u64.pow(DEPTH as u32) - 1 {
+			return Err(Error::Full)
+		}
+
+		self.leaves.push(leaf);
+		Ok(())
+	}
+
+	/// Push a new deposit.
+	pub fn push(&mut self, deposit: &DepositData) -> Result<(), Error> {
+		self.push_leaf(tree_root::<C::Digest, _>(deposit))
+	}
+
+	/// Deposit root of the full tree.
+	pub fn root(&self) -> Result<H256, Error> {
+		self.root_at(self.deposit_count())
+	}
+
+	/// Deposit root of the tree truncated at `deposit_count`, as it would
+	/// appear in `eth1_data.deposit_root` of a state that has seen that many
+	/// deposits.
+	pub fn root_at(&self, deposit_count: u64) -> Result<H256, Error> {
+		if deposit_count > self.deposit_count() || deposit_count < self.finalized_count {
+			return Err(Error::OutOfRange)
+		}
+
+		let layers = self.layers(deposit_count);
+		let root = self.layer_node(&layers, DEPTH, 0)?;
+		Ok(C::hash(&[root.as_ref(), count_node(deposit_count).as_ref()]))
+	}
+
+	/// Merkle branch of deposit `index` against the root of the tree
+	/// truncated at `deposit_count`. The branch includes the length mix-in,
+	/// and is thus `DEPOSIT_CONTRACT_TREE_DEPTH + 1` long.
+	pub fn proof(&self, index: u64, deposit_count: u64) -> Result<Vec<H256>, Error> {
+		if index >= deposit_count || deposit_count > self.deposit_count() {
+			return Err(Error::OutOfRange)
+		}
+		if index < self.finalized_count {
+			return Err(Error::Finalized)
+		}
+
+		let layers = self.layers(deposit_count);
+		self.branch(&layers, index, deposit_count)
+	}
+
+	/// Merkle branches of all unfinalized deposits against the root of the
+	/// tree truncated at `deposit_count`, from deposit `finalized_count`.
+	/// The tree is hashed once for all of them.
+	pub fn proofs(&self, deposit_count: u64) -> Result<Vec<Vec<H256>>, Error> {
+		if deposit_count < self.finalized_count || deposit_count > self.deposit_count() {
+			return Err(Error::OutOfRange)
+		}
+
+		let layers = self.layers(deposit_count);
+		(self.finalized_count..deposit_count)
+			.map(|index| self.branch(&layers, index, deposit_count))
+			.collect()
+	}
+
+	/// Finalize deposits up to `deposit_count`, observed at the given eth1
+	/// block. Leaves before the count are pruned and can no longer be proven.
+	pub fn finalize(
+		&mut self,
+		deposit_count: u64,
+		execution_block_hash: H256,
+		execution_block_height: u64,
+	) -> Result<(), Error> {
+		if deposit_count < self.finalized_count || deposit_count > self.deposit_count() {
+			return Err(Error::OutOfRange)
+		}
+
+		let layers = self.layers(deposit_count);
+		let mut finalized = Vec::new();
+		for (depth, start) in self.finalized_subtrees(deposit_count) {
+			finalized.push(self.layer_node(&layers, depth, start >> depth)?);
+		}
+
+		let pruned = (deposit_count - self.finalized_count) as usize;
+		self.leaves.drain(..pruned);
+		self.finalized = finalized;
+		self.finalized_count = deposit_count;
+		self.execution_block_hash = execution_block_hash;
+		self.execution_block_height = execution_block_height;
+
+		Ok(())
+	}
+
+	/// Remove the deposits after the first `deposit_count`. Finalized
+	/// deposits cannot be removed.
+	pub fn truncate(&mut self, deposit_count: u64) -> Result<(), Error> {
+		if deposit_count < self.finalized_count {
+			return Err(Error::Finalized)
+		}
+		if deposit_count > self.deposit_count() {
+			return Err(Error::OutOfRange)
+		}
+
+		self.leaves.truncate((deposit_count - self.finalized_count) as usize);
+		Ok(())
+	}
+
+	/// Depth and starting leaf of the full subtrees covering the first
+	/// `count` leaves, from the leftmost one.
+	fn finalized_subtrees(&self, count: u64) -> Vec<(usize, u64)> {
+		let mut subtrees = Vec::new();
+		let mut start = 0;
+		for depth in (0..DEPTH).rev() {
+			if count & (1 << depth) != 0 {
+				subtrees.push((depth, start));
+				start += 1 << depth;
+			}
+		}
+		subtrees
+	}
+
+	/// Layers of the tree holding `count` leaves, from the leaves up. The
+	/// layer at `depth` starts at the first node not covered by a finalized
+	/// subtree, index `finalized_count >> depth`, and ends at the last node
+	/// with leaves.
+	fn layers(&self, count: u64) -> Vec<Vec<H256>> {
+		let mut layers = Vec::with_capacity(DEPTH + 1);
+		layers.push(self.leaves[..(count - self.finalized_count) as usize].to_vec());
+		for depth in 0..DEPTH {
+			let first = self.finalized_count >> (depth + 1);
+			let end = (count + (1 << (depth + 1)) - 1) >> (depth + 1);
+			let layer = (first..end)
+				.map(|index| {
+					let left = self.layer_node(&layers, depth, index * 2)?;
+					let right = self.layer_node(&layers, depth, index * 2 + 1)?;
+					Ok(C::hash(&[left.as_ref(), right.as_ref()]))
+				})
+				.collect::<Result<Vec<_>, Error>>()
+				.expect("Nodes left of a layer are roots of finalized subtrees; qed");
+			layers.push(layer);
+		}
+		layers
+	}
+
+	/// Node at `depth` with the given index in its layer, out of `layers`.
+	/// Nodes left of the layer must be roots of finalized subtrees, and
+	/// nodes right of it are empty.
+	fn layer_node(&self, layers: &[Vec<H256>], depth: usize, index: u64) -> Result<H256, Error> {
+		let first = self.finalized_count >> depth;
+		if index < first {
+			return self.finalized_subtrees(self.finalized_count).into_iter()
+				.zip(self.finalized.iter())
+				.find(|((sub_depth, sub_start), _)| *sub_depth == depth && *sub_start == index << depth)
+				.map(|(_, hash)| *hash)
+				.ok_or(Error::Finalized)
+		}
+
+		Ok(layers[depth].get((index - first) as usize).cloned().unwrap_or(self.zerohashes[depth]))
+	}
+
+	/// Merkle branch of leaf `index` out of the layers of the tree holding
+	/// `count` leaves, with the length mix-in.
+	fn branch(&self, layers: &[Vec<H256>], index: u64, count: u64) -> Result<Vec<H256>, Error> {
+		let mut proof = Vec::with_capacity(DEPTH + 1);
+		for depth in 0..DEPTH {
+			proof.push(self.layer_node(layers, depth, (index >> depth) ^ 1)?);
+		}
+		proof.push(count_node(count));
+		Ok(proof)
+	}
+}
+
+fn count_node(count: u64) -> H256 {
+	let mut ret = H256::default();
+	(&mut ret[0..8]).copy_from_slice(&count.to_le_bytes());
+	ret
+}
+
+/// Eth1 block whose deposit logs have been processed.
+#[derive(Debug, Clone, Eq, PartialEq, Encode, Decode)]
+pub struct Eth1Block {
+	/// Block hash.
+	pub hash: H256,
+	/// Block height.
+	pub number: u64,
+	/// Total number of deposits once the block is processed.
+	pub deposit_count: u64,
+}
+
+#[derive(Encode, Decode)]
+struct PersistedDepositCache {
+	snapshot: DepositTreeSnapshot,
+	leaves: Vec<H256>,
+	blocks: Vec<Eth1Block>,
+}
+
+/// Deposit tree along with the eth1 blocks processed since its finalized
+/// snapshot.
+#[derive(Clone)]
+pub struct DepositCache<C: Config> {
+	tree: DepositTree<C>,
+	blocks: Vec<Eth1Block>,
+}
+
+impl<C: Config> DepositCache<C> {
+	/// Create an empty deposit cache.
+	pub fn new() -> Self {
+		Self::from_tree(DepositTree::new())
+	}
+
+	/// Create a deposit cache continuing from the given tree, which must not
+	/// have pending deposits.
+	pub fn from_tree(tree: DepositTree<C>) -> Self {
+		Self { tree, blocks: Vec::new() }
+	}
+
+	/// Deposit tree.
+	pub fn tree(&self) -> &DepositTree<C> {
+		&self.tree
+	}
+
+	/// Latest processed eth1 block, if any.
+	pub fn last_block(&self) -> Option<Eth1Block> {
+		self.blocks.last().cloned().or_else(|| self.finalized_block())
+	}
+
+	/// Height of the eth1 block to resume log scanning from.
+	pub fn next_block_number(&self) -> u64 {
+		self.last_block().map(|block| block.number + 1).unwrap_or(0)
+	}
+
+	/// Process the deposit logs of an eth1 block, in log order. Blocks
+	/// without deposits are recorded too, so that reorgs of them are noticed.
+	pub fn insert_block(
+		&mut self,
+		hash: H256,
+		number: u64,
+		deposits: &[DepositData],
+	) -> Result<(), Error> {
+		if self.last_block().map(|last| number <= last.number).unwrap_or(false) {
+			return Err(Error::OutOfOrder)
+		}
+
+		let count = self.tree.deposit_count();
+		for deposit in deposits {
+			if let Err(e) = self.tree.push(deposit) {
+				self.tree.truncate(count).expect("Count was valid before the pushes");
+				return Err(e)
+			}
+		}
+
+		// Of a run of blocks without deposits, only the first and the last
+		// are needed to roll back to the right deposit count.
+		let len = self.blocks.len();
+		if deposits.is_empty() && len >= 2 &&
+			self.blocks[len - 1].deposit_count == count &&
+			self.blocks[len - 2].deposit_count == count
+		{
+			self.blocks.pop();
+		}
+		self.blocks.push(Eth1Block { hash, number, deposit_count: self.tree.deposit_count() });
+		Ok(())
+	}
+
+	/// Latest processed block still part of the canonical eth1 chain, as
+	/// told by `canonical_hash` returning the canonical block hash at a
+	/// height. `None` if even the finalized block is no longer canonical.
+	pub fn common_ancestor<E, F>(&self, mut canonical_hash: F) -> Result<Option<Eth1Block>, E> where
+		F: FnMut(u64) -> Result<Option<H256>, E>,
+	{
+		for block in self.blocks.iter().rev().cloned().chain(self.finalized_block()) {
+			if canonical_hash(block.number)? == Some(block.hash) {
+				return Ok(Some(block))
+			}
+		}
+		Ok(None)
+	}
+
+	/// Forget the blocks after height `number`, along with their deposits.
+	/// Returns the number of deposits removed.
+	pub fn rollback_to(&mut self, number: u64) -> Result<u64, Error> {
+		if self.finalized_block().map(|block| number < block.number).unwrap_or(false) {
+			return Err(Error::Finalized)
+		}
+
+		self.blocks.retain(|block| block.number <= number);
+		let count = self.blocks.last().map(|block| block.deposit_count)
+			.unwrap_or(self.tree.finalized_count());
+		let removed = self.tree.deposit_count() - count;
+		self.tree.truncate(count)?;
+		Ok(removed)
+	}
+
+	/// Finalize the first `deposit_count` deposits, as of the latest
+	/// processed block holding exactly that many.
+	pub fn finalize(&mut self, deposit_count: u64) -> Result<(), Error> {
+		let position = self.blocks.iter()
+			.rposition(|block| block.deposit_count == deposit_count)
+			.ok_or(Error::OutOfRange)?;
+		let block = self.blocks[position].clone();
+
+		self.tree.finalize(deposit_count, block.hash, block.number)?;
+		self.blocks.drain(..(position + 1));
+		Ok(())
+	}
+
+	/// Load the persisted deposit cache, if any.
+	pub fn load(store: &dyn IndexStore) -> Result<Option<Self>, crate::Error> {
+		let raw = match store.get(KEY_DEPOSIT_CACHE)? {
+			Some(raw) => raw,
+			None => return Ok(None),
+		};
+		let persisted = PersistedDepositCache::decode(&mut raw.as_ref())
+			.ok_or(crate::Error::Corrupted("deposit cache"))?;
+
+		let mut tree = DepositTree::from_snapshot(&persisted.snapshot)
+			.map_err(|_| crate::Error::Corrupted("deposit cache"))?;
+		for leaf in persisted.leaves {
+			tree.push_leaf(leaf).map_err(|_| crate::Error::Corrupted("deposit cache"))?;
+		}
+		if persisted.blocks.last().map(|block| block.deposit_count != tree.deposit_count())
+			.unwrap_or(false)
+		{
+			return Err(crate::Error::Corrupted("deposit cache"))
+		}
+
+		Ok(Some(Self { tree, blocks: persisted.blocks }))
+	}
+
+	/// Persist the deposit cache.
+	pub fn store(&self, store: &dyn IndexStore) -> Result<(), crate::Error> {
+		let snapshot = self.tree.snapshot()
+			.map_err(|_| crate::Error::Corrupted("deposit tree"))?;
+		let persisted = PersistedDepositCache {
+			snapshot,
+			leaves: self.tree.leaves.clone(),
+			blocks: self.blocks.clone(),
+		};
+		store.write(vec![(KEY_DEPOSIT_CACHE.to_vec(), Some(persisted.encode()))])
+	}
+
+	fn finalized_block(&self) -> Option<Eth1Block> {
+		let (hash, number) = self.tree.execution_block();
+		if hash == H256::default() {
+			return None
+		}
+		Some(Eth1Block { hash, number, deposit_count: self.tree.finalized_count() })
+	}
+}
@@ -0,0 +1,532 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Eth1 JSON-RPC endpoints with health checking and fallback.
+//!
+//! Several endpoints can be configured, in order of preference. Each one is
+//! checked to be synced, to serve the expected chain and to hold the deposit
+//! contract code. Requests go to the preferred healthy endpoint, and fall
+//! over to the next one when it fails.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use beacon::primitives::{H256, Signature, ValidatorId};
+use beacon::types::DepositData;
+use serde_json::{json, Value};
+use log::{info, warn};
+
+/// Timeout of a single JSON-RPC request.
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between endpoint health checks.
+pub const DEFAULT_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Topic of `DepositEvent(bytes,bytes,bytes,bytes,bytes)` of the deposit
+/// contract.
+pub const DEPOSIT_EVENT_TOPIC: &str =
+	"0x649bbc62d0e31342afea4e5cd82d4049e7e1ee912fc0889aa790803be39038c5";
+
+/// Eth1 JSON-RPC errors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+	/// The request failed.
+	Http(String),
+	/// The endpoint answered with an error status.
+	Status(u16),
+	/// The endpoint answered with a JSON-RPC error.
+	Rpc(String),
+	/// The response could not be decoded.
+	Decode(String),
+	/// No endpoint answered successfully.
+	AllEndpointsFailed,
+}
+
+impl fmt::Display for RpcError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RpcError::Http(e) => write!(f, "Eth1 request failed: {}", e),
+			RpcError::Status(status) => write!(f, "Eth1 endpoint answered with status {}", status),
+			RpcError::Rpc(e) => write!(f, "Eth1 endpoint returned an error: {}", e),
+			RpcError::Decode(e) => write!(f, "Invalid eth1 response: {}", e),
+			RpcError::AllEndpointsFailed => write!(f, "All eth1 endpoints failed"),
+		}
+	}
+}
+
+impl std::error::Error for RpcError { }
+
+/// Parse a hex encoded deposit contract address.
+pub fn parse_address(value: &str) -> Result<[u8; 20], String> {
+	let raw = hex::decode(value.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+	if raw.len() != 20 {
+		return Err(format!("Address must be 20 bytes, got {}", raw.len()))
+	}
+	let mut address = [0u8; 20];
+	address.copy_from_slice(&raw);
+	Ok(address)
+}
+
+fn parse_quantity(value: &Value) -> Result<u64, RpcError> {
+	let value = value.as_str().ok_or_else(|| RpcError::Decode("Expected a quantity".into()))?;
+	u64::from_str_radix(value.trim_start_matches("0x"), 16)
+		.map_err(|e| RpcError::Decode(e.to_string()))
+}
+
+fn parse_data(value: &Value) -> Result<Vec<u8>, RpcError> {
+	let value = value.as_str().ok_or_else(|| RpcError::Decode("Expected data".into()))?;
+	hex::decode(value.trim_start_matches("0x")).map_err(|e| RpcError::Decode(e.to_string()))
+}
+
+fn parse_hash(value: &Value) -> Result<H256, RpcError> {
+	let raw = parse_data(value)?;
+	if raw.len() != 32 {
+		return Err(RpcError::Decode(format!("Hash must be 32 bytes, got {}", raw.len())))
+	}
+	Ok(H256::from_slice(&raw))
+}
+
+/// Header fields of an eth1 block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Eth1Header {
+	/// Block hash.
+	pub hash: H256,
+	/// Block height.
+	pub number: u64,
+	/// Block timestamp.
+	pub timestamp: u64,
+}
+
+/// Deposit event of the deposit contract.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DepositLog {
+	/// Hash of the block holding the deposit.
+	pub block_hash: H256,
+	/// Height of the block holding the deposit.
+	pub block_number: u64,
+	/// Index of the deposit in the deposit contract.
+	pub index: u64,
+	/// Deposit.
+	pub data: DepositData,
+}
+
+/// Decode the ABI encoded fields of a `DepositEvent`: the pubkey,
+/// withdrawal credentials, little endian amount, signature and little
+/// endian index, each as dynamic `bytes`.
+pub fn decode_deposit_event(data: &[u8]) -> Result<(u64, DepositData), RpcError> {
+	let word = |offset: usize| -> Result<usize, RpcError> {
+		let word = data.get(offset..(offset + 32))
+			.ok_or_else(|| RpcError::Decode("Truncated deposit event".into()))?;
+		if word[..24].iter().any(|byte| *byte != 0) {
+			return Err(RpcError::Decode("Deposit event offset out of range".into()))
+		}
+		let mut raw = [0u8; 8];
+		raw.copy_from_slice(&word[24..]);
+		Ok(u64::from_be_bytes(raw) as usize)
+	};
+	let field = |index: usize, len: usize| -> Result<&[u8], RpcError> {
+		let offset = word(index * 32)?;
+		if word(offset)? != len {
+			return Err(RpcError::Decode(format!("Deposit event field {} is not {} bytes", index, len)))
+		}
+		data.get((offset + 32)..(offset + 32 + len))
+			.ok_or_else(|| RpcError::Decode("Truncated deposit event".into()))
+	};
+	let little_endian = |raw: &[u8]| {
+		let mut bytes = [0u8; 8];
+		bytes.copy_from_slice(raw);
+		u64::from_le_bytes(bytes)
+	};
+
+	let deposit = DepositData {
+		pubkey: ValidatorId::from_slice(field(0, 48)?),
+		withdrawal_credentials: H256::from_slice(field(1, 32)?),
+		amount: little_endian(field(2, 8)?),
+		signature: Signature::from_slice(field(3, 96)?),
+	};
+	Ok((little_endian(field(4, 8)?), deposit))
+}
+
+/// Single eth1 JSON-RPC endpoint.
+#[derive(Clone, Debug)]
+pub struct Eth1Endpoint {
+	url: String,
+	timeout: Duration,
+}
+
+impl Eth1Endpoint {
+	/// Create an endpoint for the JSON-RPC API at `url`.
+	pub fn new(url: &str) -> Self {
+		Self { url: url.to_string(), timeout: DEFAULT_RPC_TIMEOUT }
+	}
+
+	/// Set the request timeout.
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Endpoint URL.
+	pub fn url(&self) -> &str {
+		&self.url
+	}
+
+	/// Call `method` with the given parameters, returning its result.
+	pub fn request(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+		let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+		let mut request = ureq::post(&self.url);
+		request.set("Content-Type", "application/json").timeout(self.timeout);
+		let response = request.send_string(&body.to_string());
+		if let Some(e) = response.synthetic_error() {
+			return Err(RpcError::Http(e.to_string()))
+		}
+		if !response.ok() {
+			return Err(RpcError::Status(response.status()))
+		}
+
+		let response = response.into_string().map_err(|e| RpcError::Http(e.to_string()))?;
+		let mut response: Value = serde_json::from_str(&response)
+			.map_err(|e| RpcError::Decode(e.to_string()))?;
+		if let Some(error) = response.get("error") {
+			return Err(RpcError::Rpc(error.to_string()))
+		}
+		match response.get_mut("result") {
+			Some(result) => Ok(result.take()),
+			None => Err(RpcError::Decode("Missing result".into())),
+		}
+	}
+
+	/// Chain id of the eth1 chain served.
+	pub fn chain_id(&self) -> Result<u64, RpcError> {
+		parse_quantity(&self.request("eth_chainId", json!([]))?)
+	}
+
+	/// Network id of the eth1 chain served.
+	pub fn network_id(&self) -> Result<u64, RpcError> {
+		// Unlike other quantities, the network id is a decimal string.
+		let id = self.request("net_version", json!([]))?;
+		id.as_str()
+			.ok_or_else(|| RpcError::Decode("Expected a network id".into()))?
+			.parse()
+			.map_err(|e: std::num::ParseIntError| RpcError::Decode(e.to_string()))
+	}
+
+	/// Whether the endpoint is still syncing.
+	pub fn is_syncing(&self) -> Result<bool, RpcError> {
+		// Either `false` or an object describing the sync progress.
+		Ok(self.request("eth_syncing", json!([]))? != Value::Bool(false))
+	}
+
+	/// Latest block number.
+	pub fn block_number(&self) -> Result<u64, RpcError> {
+		parse_quantity(&self.request("eth_blockNumber", json!([]))?)
+	}
+
+	/// Whether contract code is deployed at `address`.
+	pub fn has_code(&self, address: &[u8; 20]) -> Result<bool, RpcError> {
+		let code = self.request(
+			"eth_getCode", json!([format!("0x{}", hex::encode(address)), "latest"]),
+		)?;
+		let code = code.as_str().ok_or_else(|| RpcError::Decode("Expected code".into()))?;
+		Ok(!code.trim_start_matches("0x").is_empty())
+	}
+
+	/// Header of the block at height `number`, if the chain is that long.
+	pub fn block_by_number(&self, number: u64) -> Result<Option<Eth1Header>, RpcError> {
+		let block = self.request("eth_getBlockByNumber", json!([format!("0x{:x}", number), false]))?;
+		if block.is_null() {
+			return Ok(None)
+		}
+
+		Ok(Some(Eth1Header {
+			hash: parse_hash(&block["hash"])?,
+			number: parse_quantity(&block["number"])?,
+			timestamp: parse_quantity(&block["timestamp"])?,
+		}))
+	}
+
+	/// Deposit events of the contract at `address` in the blocks `from` to
+	/// `to` included, in log order.
+	pub fn deposit_logs(&self, address: &[u8; 20], from: u64, to: u64) -> Result<Vec<DepositLog>, RpcError> {
+		let logs = self.request("eth_getLogs", json!([{
+			"address": format!("0x{}", hex::encode(address)),
+			"topics": [DEPOSIT_EVENT_TOPIC],
+			"fromBlock": format!("0x{:x}", from),
+			"toBlock": format!("0x{:x}", to),
+		}]))?;
+		let logs = logs.as_array().ok_or_else(|| RpcError::Decode("Expected logs".into()))?;
+
+		logs.iter()
+			.filter(|log| log["removed"] != Value::Bool(true))
+			.map(|log| {
+				let (index, data) = decode_deposit_event(&parse_data(&log["data"])?)?;
+				Ok(DepositLog {
+					block_hash: parse_hash(&log["blockHash"])?,
+					block_number: parse_quantity(&log["blockNumber"])?,
+					index,
+					data,
+				})
+			})
+			.collect()
+	}
+}
+
+/// What an eth1 endpoint is expected to serve.
+#[derive(Clone, Debug)]
+pub struct Eth1Requirements {
+	/// Chain id of the eth1 chain.
+	pub chain_id: u64,
+	/// Network id of the eth1 chain, if it is to be checked.
+	pub network_id: Option<u64>,
+	/// Address of the deposit contract.
+	pub deposit_contract: [u8; 20],
+}
+
+/// Health of an eth1 endpoint, from the most to the least preferred.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EndpointHealth {
+	/// Synced and serving the expected chain.
+	Healthy,
+	/// Not checked yet.
+	Unknown,
+	/// Still syncing.
+	Syncing,
+	/// Not reachable, or failing requests.
+	Offline(String),
+	/// Serving another chain.
+	WrongChainId(u64),
+	/// Serving another network.
+	WrongNetworkId(u64),
+	/// No deposit contract code at the expected address.
+	NoDepositContract,
+}
+
+impl EndpointHealth {
+	/// Whether requests may be sent to the endpoint.
+	pub fn is_usable(&self) -> bool {
+		match self {
+			EndpointHealth::Healthy | EndpointHealth::Unknown => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the endpoint cannot serve the expected chain, as opposed to
+	/// being temporarily unavailable.
+	pub fn is_misconfigured(&self) -> bool {
+		match self {
+			EndpointHealth::WrongChainId(_) | EndpointHealth::WrongNetworkId(_) |
+			EndpointHealth::NoDepositContract => true,
+			_ => false,
+		}
+	}
+}
+
+impl fmt::Display for EndpointHealth {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			EndpointHealth::Healthy => write!(f, "healthy"),
+			EndpointHealth::Unknown => write!(f, "not checked yet"),
+			EndpointHealth::Syncing => write!(f, "syncing"),
+			EndpointHealth::Offline(e) => write!(f, "offline ({})", e),
+			EndpointHealth::WrongChainId(id) => write!(f, "serving chain id {}", id),
+			EndpointHealth::WrongNetworkId(id) => write!(f, "serving network id {}", id),
+			EndpointHealth::NoDepositContract => write!(f, "missing the deposit contract"),
+		}
+	}
+}
+
+/// Health of an endpoint, as last observed.
+#[derive(Clone, Debug)]
+pub struct EndpointStatus {
+	/// Endpoint health.
+	pub health: EndpointHealth,
+	/// Requests failed in a row.
+	pub failures: u32,
+	/// Time of the latest health check.
+	pub checked: Option<Instant>,
+}
+
+/// Endpoint unable to serve the expected chain, found on startup.
+#[derive(Clone, Debug)]
+pub struct Misconfiguration {
+	/// Endpoint URL.
+	pub url: String,
+	/// Endpoint health.
+	pub health: EndpointHealth,
+	/// What the endpoint was expected to serve.
+	pub requirements: Eth1Requirements,
+}
+
+impl fmt::Display for Misconfiguration {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match &self.health {
+			EndpointHealth::WrongChainId(id) => write!(
+				f, "Eth1 endpoint {} serves chain id {}, but the beacon chain expects {}. \
+					Point it to an eth1 node of the right chain, or set --eth1-chain-id",
+				self.url, id, self.requirements.chain_id,
+			),
+			EndpointHealth::WrongNetworkId(id) => write!(
+				f, "Eth1 endpoint {} serves network id {}, but the beacon chain expects {}. \
+					Point it to an eth1 node of the right network, or set --eth1-network-id",
+				self.url, id, self.requirements.network_id.unwrap_or_default(),
+			),
+			EndpointHealth::NoDepositContract => write!(
+				f, "Eth1 endpoint {} has no code at the deposit contract address 0x{}. \
+					Check --deposit-contract, and that the eth1 node is synced past the \
+					contract deployment",
+				self.url, hex::encode(self.requirements.deposit_contract),
+			),
+			health => write!(f, "Eth1 endpoint {} is {}", self.url, health),
+		}
+	}
+}
+
+/// Eth1 endpoints in order of preference, with their health.
+pub struct Eth1Endpoints {
+	endpoints: Vec<Eth1Endpoint>,
+	requirements: Eth1Requirements,
+	status: Mutex<Vec<EndpointStatus>>,
+}
+
+impl Eth1Endpoints {
+	/// Create the endpoint set, most preferred first.
+	pub fn new(endpoints: Vec<Eth1Endpoint>, requirements: Eth1Requirements) -> Self {
+		let status = endpoints.iter().map(|_| EndpointStatus {
+			health: EndpointHealth::Unknown,
+			failures: 0,
+			checked: None,
+		}).collect();
+
+		Self { endpoints, requirements, status: Mutex::new(status) }
+	}
+
+	/// Address of the deposit contract.
+	pub fn deposit_contract(&self) -> [u8; 20] {
+		self.requirements.deposit_contract
+	}
+
+	/// Endpoints along with their status.
+	pub fn status(&self) -> Vec<(String, EndpointStatus)> {
+		let status = self.status.lock().expect("Lock is poisoned");
+		self.endpoints.iter()
+			.map(|endpoint| endpoint.url().to_string())
+			.zip(status.iter().cloned())
+			.collect()
+	}
+
+	/// Check the health of a single endpoint.
+	pub fn check(&self, endpoint: &Eth1Endpoint) -> EndpointHealth {
+		let check = || -> Result<EndpointHealth, RpcError> {
+			let chain_id = endpoint.chain_id()?;
+			if chain_id != self.requirements.chain_id {
+				return Ok(EndpointHealth::WrongChainId(chain_id))
+			}
+			if let Some(expected) = self.requirements.network_id {
+				let network_id = endpoint.network_id()?;
+				if network_id != expected {
+					return Ok(EndpointHealth::WrongNetworkId(network_id))
+				}
+			}
+			if endpoint.is_syncing()? {
+				return Ok(EndpointHealth::Syncing)
+			}
+			if !endpoint.has_code(&self.requirements.deposit_contract)? {
+				return Ok(EndpointHealth::NoDepositContract)
+			}
+			Ok(EndpointHealth::Healthy)
+		};
+
+		check().unwrap_or_else(|e| EndpointHealth::Offline(e.to_string()))
+	}
+
+	/// Check the health of all endpoints, warning when none is healthy.
+	pub fn check_all(&self) {
+		let healths = self.endpoints.iter()
+			.map(|endpoint| self.check(endpoint))
+			.collect::<Vec<_>>();
+
+		let mut status = self.status.lock().expect("Lock is poisoned");
+		for ((endpoint, status), health) in self.endpoints.iter().zip(status.iter_mut()).zip(healths) {
+			if status.health != health {
+				info!("Eth1 endpoint {} is {}", endpoint.url(), health);
+			}
+			if health == EndpointHealth::Healthy {
+				status.failures = 0;
+			}
+			status.health = health;
+			status.checked = Some(Instant::now());
+		}
+
+		if !status.iter().any(|status| status.health == EndpointHealth::Healthy) {
+			warn!("No healthy eth1 endpoint");
+		}
+	}
+
+	/// Check all endpoints on startup, failing with the ones that cannot
+	/// serve the expected chain. Endpoints that are only offline or syncing
+	/// are not an error, as they may recover.
+	pub fn validate(&self) -> Result<(), Vec<Misconfiguration>> {
+		self.check_all();
+
+		let misconfigured = self.status().into_iter()
+			.filter(|(_, status)| status.health.is_misconfigured())
+			.map(|(url, status)| Misconfiguration {
+				url,
+				health: status.health,
+				requirements: self.requirements.clone(),
+			})
+			.collect::<Vec<_>>();
+		if misconfigured.is_empty() {
+			Ok(())
+		} else {
+			Err(misconfigured)
+		}
+	}
+
+	/// Send a request through the preferred usable endpoint, falling over to
+	/// the next one on failure. Endpoints that cannot serve the expected
+	/// chain are never used.
+	pub fn request<T, F>(&self, mut f: F) -> Result<T, RpcError> where
+		F: FnMut(&Eth1Endpoint) -> Result<T, RpcError>,
+	{
+		let order = {
+			let status = self.status.lock().expect("Lock is poisoned");
+			let mut order = (0..self.endpoints.len())
+				.filter(|i| !status[*i].health.is_misconfigured())
+				.collect::<Vec<_>>();
+			// Usable endpoints first, then the ones failing the least.
+			order.sort_by_key(|i| (!status[*i].health.is_usable(), status[*i].failures, *i));
+			order
+		};
+
+		for i in order {
+			let endpoint = &self.endpoints[i];
+			match f(endpoint) {
+				Ok(value) => {
+					self.status.lock().expect("Lock is poisoned")[i].failures = 0;
+					return Ok(value)
+				},
+				Err(e) => {
+					warn!("Eth1 endpoint {} failed, falling over: {}", endpoint.url(), e);
+					let mut status = self.status.lock().expect("Lock is poisoned");
+					status[i].failures += 1;
+					status[i].health = EndpointHealth::Offline(e.to_string());
+				},
+			}
+		}
+
+		warn!("All eth1 endpoints failed");
+		Err(RpcError::AllEndpointsFailed)
+	}
+}
@@ -0,0 +1,304 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Eth1 follower, scanning the deposit contract logs into the deposit cache
+//! and recent eth1 blocks into the chain eth1 votes are chosen from.
+//!
+//! Blocks are only followed up to the eth1 follow distance behind the head,
+//! so reorgs are rare. They are still detected on each update, by checking
+//! the last processed block against the canonical chain, and rolled back.
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::types::Eth1Data;
+use log::{info, warn};
+use crate::eth1::{self, DepositCache};
+use crate::eth1_endpoints::{Eth1Endpoints, Eth1Header, RpcError};
+use crate::eth1_vote::{Eth1BlockData, Eth1Chain, SharedEth1Chain};
+use crate::index::IndexStore;
+
+/// Maximum number of eth1 blocks scanned by a single update.
+pub const MAX_BLOCKS_PER_UPDATE: u64 = 1000;
+
+#[derive(Debug)]
+/// Eth1 follower errors.
+pub enum Error {
+	/// Eth1 request failed.
+	Rpc(RpcError),
+	/// Deposit logs do not fit the deposit tree.
+	Deposit(eth1::Error),
+	/// Deposit cache could not be persisted.
+	Store(crate::Error),
+	/// Deposit log indices are not contiguous.
+	MissingDeposit {
+		/// Index of the next deposit.
+		expected: u64,
+		/// Index of the deposit log found instead.
+		found: u64,
+	},
+	/// Endpoint does not know a block below its head.
+	MissingBlock(u64),
+	/// Block hash changed during the update, as on a reorg.
+	Reorged(u64),
+	/// Even the finalized deposit block is no longer canonical.
+	FinalizedReorged,
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::Rpc(e) => write!(f, "{}", e),
+			Error::Deposit(e) => write!(f, "Invalid deposit logs: {}", e),
+			Error::Store(e) => write!(f, "Persisting the deposit cache failed: {}", e),
+			Error::MissingDeposit { expected, found } => write!(
+				f, "Expected deposit {}, found deposit {}", expected, found,
+			),
+			Error::MissingBlock(number) => write!(f, "Eth1 block {} is not available", number),
+			Error::Reorged(number) => write!(f, "Eth1 block {} was reorged during the update", number),
+			Error::FinalizedReorged => write!(f, "Finalized deposit block is no longer canonical"),
+		}
+	}
+}
+
+impl std::error::Error for Error { }
+
+impl From<RpcError> for Error {
+	fn from(error: RpcError) -> Error {
+		Error::Rpc(error)
+	}
+}
+
+impl From<eth1::Error> for Error {
+	fn from(error: eth1::Error) -> Error {
+		Error::Deposit(error)
+	}
+}
+
+impl From<crate::Error> for Error {
+	fn from(error: crate::Error) -> Error {
+		Error::Store(error)
+	}
+}
+
+/// Follows the deposit contract through eth1 endpoints.
+pub struct Eth1Follower<C: Config> {
+	endpoints: Eth1Endpoints,
+	cache: DepositCache<C>,
+	chain: SharedEth1Chain,
+	store: Arc<dyn IndexStore + Send + Sync>,
+	start_block: u64,
+}
+
+impl<C: Config> Eth1Follower<C> {
+	/// Create a follower resuming from the deposit cache persisted in
+	/// `store`, or else scanning from `start_block`, the block the deposit
+	/// contract was deployed at.
+	pub fn new(
+		endpoints: Eth1Endpoints,
+		store: Arc<dyn IndexStore + Send + Sync>,
+		start_block: u64,
+	) -> Result<Self, crate::Error> {
+		let cache = DepositCache::load(store.as_ref())?.unwrap_or_else(DepositCache::new);
+		let chain = Arc::new(RwLock::new(Eth1Chain::new()));
+		Ok(Self { endpoints, cache, chain, store, start_block })
+	}
+
+	/// Recent eth1 blocks, shared with block proposers.
+	pub fn chain(&self) -> SharedEth1Chain {
+		self.chain.clone()
+	}
+
+	/// Eth1 endpoints followed.
+	pub fn endpoints(&self) -> &Eth1Endpoints {
+		&self.endpoints
+	}
+
+	/// Deposit cache.
+	pub fn cache(&self) -> &DepositCache<C> {
+		&self.cache
+	}
+
+	/// Number of recent blocks whose headers are kept for eth1 votes: the
+	/// blocks of a voting period back to twice the follow distance before its
+	/// start.
+	fn recent_blocks() -> u64 {
+		C::eth1_follow_distance() * 2 +
+			C::slots_per_eth1_voting_period() * C::seconds_per_slot() / C::seconds_per_eth1_block()
+	}
+
+	/// Roll back the deposit cache if its last block was reorged out, and
+	/// scan up to `MAX_BLOCKS_PER_UPDATE` new blocks behind the follow
+	/// distance. Returns the number of blocks scanned. Nothing is changed on
+	/// errors.
+	pub fn update(&mut self) -> Result<u64, Error> {
+		let head = self.endpoints.request(|endpoint| endpoint.block_number())?;
+		let follow_head = match head.checked_sub(C::eth1_follow_distance()) {
+			Some(follow_head) => follow_head,
+			None => return Ok(0),
+		};
+
+		let mut cache = self.cache.clone();
+		let rolled_back = self.rollback_reorged(&mut cache)?;
+
+		let from = std::cmp::max(cache.next_block_number(), self.start_block);
+		if from > follow_head {
+			if rolled_back {
+				self.commit(cache, Vec::new(), None)?;
+			}
+			return Ok(0)
+		}
+		let to = std::cmp::min(follow_head, from + MAX_BLOCKS_PER_UPDATE - 1);
+		let recent_from = follow_head.saturating_sub(Self::recent_blocks());
+
+		let contract = self.endpoints.deposit_contract();
+		let mut logs = self.endpoints.request(|endpoint| endpoint.deposit_logs(&contract, from, to))?
+			.into_iter()
+			.peekable();
+		let mut blocks = Vec::new();
+
+		for number in from..=to {
+			let mut deposits = Vec::new();
+			let mut log_hash = None;
+			while logs.peek().map(|log| log.block_number == number).unwrap_or(false) {
+				let log = logs.next().expect("Peeked above");
+				let expected = cache.tree().deposit_count() + deposits.len() as u64;
+				if log.index != expected {
+					return Err(Error::MissingDeposit { expected, found: log.index })
+				}
+				log_hash = Some(log.block_hash);
+				deposits.push(log.data);
+			}
+
+			// Headers are only needed for vote candidates, and for the last
+			// block so that reorgs of it are noticed.
+			let header = if number >= recent_from || number == to {
+				Some(self.header(number)?)
+			} else {
+				None
+			};
+
+			let hash = match (&header, log_hash) {
+				(Some(header), Some(log_hash)) if header.hash != log_hash =>
+					return Err(Error::Reorged(number)),
+				(Some(header), _) => header.hash,
+				(None, Some(log_hash)) => log_hash,
+				(None, None) => continue,
+			};
+			cache.insert_block(hash, number, &deposits)?;
+
+			if let Some(header) = header {
+				let tree = cache.tree();
+				blocks.push(Eth1BlockData {
+					timestamp: header.timestamp,
+					eth1_data: Eth1Data {
+						deposit_root: tree.root()?,
+						deposit_count: tree.deposit_count(),
+						block_hash: hash,
+					},
+				});
+			}
+		}
+
+		if let Some(log) = logs.next() {
+			return Err(Error::MissingBlock(log.block_number))
+		}
+
+		let keep_after = self.header(recent_from)?.timestamp;
+		self.commit(cache, blocks, Some(keep_after))?;
+		Ok(to - from + 1)
+	}
+
+	fn commit(
+		&mut self,
+		cache: DepositCache<C>,
+		blocks: Vec<Eth1BlockData>,
+		keep_after: Option<u64>,
+	) -> Result<(), Error> {
+		cache.store(self.store.as_ref())?;
+		self.cache = cache;
+
+		let mut chain = self.chain.write().expect("Lock is poisoned");
+		for block in blocks {
+			chain.push(block);
+		}
+		if let Some(keep_after) = keep_after {
+			chain.prune(keep_after);
+		}
+		Ok(())
+	}
+
+	fn header(&self, number: u64) -> Result<Eth1Header, Error> {
+		self.endpoints.request(|endpoint| endpoint.block_by_number(number))?
+			.ok_or(Error::MissingBlock(number))
+	}
+
+	fn canonical_hash(&self, number: u64) -> Result<Option<H256>, Error> {
+		Ok(self.endpoints.request(|endpoint| endpoint.block_by_number(number))?
+			.map(|header| header.hash))
+	}
+
+	/// Roll `cache` back to its latest block still canonical. Returns
+	/// whether anything was rolled back.
+	fn rollback_reorged(&self, cache: &mut DepositCache<C>) -> Result<bool, Error> {
+		let last = match cache.last_block() {
+			Some(last) => last,
+			None => return Ok(false),
+		};
+		let ancestor = cache.common_ancestor(|number| self.canonical_hash(number))?
+			.ok_or(Error::FinalizedReorged)?;
+		if ancestor.number == last.number {
+			return Ok(false)
+		}
+
+		let removed = cache.rollback_to(ancestor.number)?;
+		warn!(
+			"Eth1 reorg past the follow distance, rolled back from block {} to {}, removing {} deposits",
+			last.number, ancestor.number, removed,
+		);
+		Ok(true)
+	}
+}
+
+/// Keep `follower` updated once per eth1 block, without pause while it
+/// catches up, and check the eth1 endpoints every `health_check_interval`.
+pub fn run<C: Config>(mut follower: Eth1Follower<C>, health_check_interval: Duration) {
+	let update_interval = Duration::from_secs(C::seconds_per_eth1_block());
+	let mut last_check: Option<Instant> = None;
+	loop {
+		if last_check.map(|at| at.elapsed() >= health_check_interval).unwrap_or(true) {
+			follower.endpoints().check_all();
+			last_check = Some(Instant::now());
+		}
+
+		match follower.update() {
+			Ok(0) => (),
+			Ok(scanned) => {
+				info!(
+					"Followed {} eth1 blocks, {} deposits known",
+					scanned, follower.cache().tree().deposit_count(),
+				);
+				if scanned == MAX_BLOCKS_PER_UPDATE {
+					continue
+				}
+			},
+			Err(e) => warn!("Eth1 follower update failed: {}", e),
+		}
+		thread::sleep(update_interval);
+	}
+}
@@ -0,0 +1,114 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Eth1 data voting.
+//!
+//! A proposer votes for the eth1 data of a candidate block: old enough to be
+//! at least `ETH1_FOLLOW_DISTANCE` blocks behind the eth1 head at the start
+//! of the voting period, and recent enough not to be twice that. Among the
+//! candidates, votes already cast in the period are joined, so that the
+//! period reaches a majority; otherwise the latest candidate is voted for.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+use beacon::{BeaconState, Config};
+use beacon::types::Eth1Data;
+
+/// Eth1 block, with the deposit contract state as of the block.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Eth1BlockData {
+	/// Block timestamp.
+	pub timestamp: u64,
+	/// Deposit root and count, and block hash.
+	pub eth1_data: Eth1Data,
+}
+
+/// Recent eth1 blocks votes are chosen from, ordered by timestamp.
+#[derive(Clone, Debug, Default)]
+pub struct Eth1Chain {
+	blocks: VecDeque<Eth1BlockData>,
+}
+
+/// Eth1 chain shared between the eth1 follower and block proposers.
+pub type SharedEth1Chain = Arc<RwLock<Eth1Chain>>;
+
+impl Eth1Chain {
+	/// Create an empty eth1 chain.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add a block. Blocks not after the latest one replace the blocks from
+	/// their timestamp on, as after an eth1 reorg.
+	pub fn push(&mut self, block: Eth1BlockData) {
+		while self.blocks.back().map(|last| last.timestamp >= block.timestamp).unwrap_or(false) {
+			self.blocks.pop_back();
+		}
+		self.blocks.push_back(block);
+	}
+
+	/// Forget blocks older than `timestamp`.
+	pub fn prune(&mut self, timestamp: u64) {
+		while self.blocks.front().map(|first| first.timestamp < timestamp).unwrap_or(false) {
+			self.blocks.pop_front();
+		}
+	}
+
+	/// Blocks, from the oldest.
+	pub fn blocks(&self) -> impl Iterator<Item=&Eth1BlockData> {
+		self.blocks.iter()
+	}
+}
+
+/// Start of the eth1 voting period of `state`, as Unix timestamp.
+pub fn voting_period_start_time<C: Config>(state: &BeaconState<C>) -> u64 {
+	let start_slot = state.slot - state.slot % C::slots_per_eth1_voting_period();
+	state.genesis_time + start_slot * C::seconds_per_slot()
+}
+
+/// Whether a block with the given timestamp can be voted for in the period
+/// starting at `period_start`.
+pub fn is_candidate_block<C: Config>(timestamp: u64, period_start: u64) -> bool {
+	let follow_time = C::seconds_per_eth1_block() * C::eth1_follow_distance();
+	timestamp + follow_time <= period_start && timestamp + follow_time * 2 >= period_start
+}
+
+/// Eth1 data to vote for in a block on top of `state`.
+pub fn eth1_vote<C: Config>(state: &BeaconState<C>, chain: &Eth1Chain) -> Eth1Data {
+	let period_start = voting_period_start_time(state);
+	let votes_to_consider = chain.blocks()
+		.filter(|block| is_candidate_block::<C>(block.timestamp, period_start))
+		.filter(|block| block.eth1_data.deposit_count >= state.eth1_data.deposit_count)
+		.map(|block| &block.eth1_data)
+		.collect::<Vec<_>>();
+	let valid_votes = state.eth1_data_votes.iter()
+		.filter(|vote| votes_to_consider.contains(vote))
+		.collect::<Vec<_>>();
+
+	// The most frequent vote, the earliest cast one on ties.
+	let mut best: Option<(&Eth1Data, usize)> = None;
+	for vote in &valid_votes {
+		let count = valid_votes.iter().filter(|other| other == &vote).count();
+		if best.map(|(_, best_count)| count > best_count).unwrap_or(true) {
+			best = Some((*vote, count));
+		}
+	}
+
+	best.map(|(vote, _)| vote)
+		.or_else(|| votes_to_consider.last().cloned())
+		.unwrap_or(&state.eth1_data)
+		.clone()
+}
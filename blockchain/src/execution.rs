@@ -0,0 +1,192 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Execution engine hook.
+//!
+//! The block builder asks the execution engine to fill in the execution part
+//! of new blocks, and the verifier notifies it of imported blocks. Engine
+//! calls are bounded by a timeout. A block whose execution could not be
+//! verified in time is imported optimistically, and marked valid or invalid
+//! once the engine has caught up.
+
+use std::sync::Arc;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use beacon::Config;
+use beacon::primitives::H256;
+use beacon::types::{BeaconBlock, UnsealedBeaconBlock};
+use lmd_ghost::validity::BlockValidity;
+use log::warn;
+use crate::validity::ValidityStore;
+
+/// Default timeout of execution engine calls.
+pub const DEFAULT_EXECUTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Status of the execution part of a block.
+pub enum PayloadStatus {
+	/// Execution is valid.
+	Valid,
+	/// Execution is invalid, and the block must be rejected.
+	Invalid,
+	/// The engine cannot tell yet, and the block is imported optimistically.
+	Syncing,
+}
+
+#[derive(Debug)]
+/// Execution engine errors.
+pub enum ExecutionError {
+	/// The engine did not answer in time.
+	Timeout,
+	/// The engine failed.
+	Engine(String),
+}
+
+impl std::fmt::Display for ExecutionError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			ExecutionError::Timeout => write!(f, "Execution engine timed out"),
+			ExecutionError::Engine(e) => write!(f, "Execution engine failed: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for ExecutionError { }
+
+/// Execution engine, invoked during block building and verification.
+pub trait ExecutionEngine<C: Config>: Send + Sync {
+	/// Fill in the execution part of a block being built, before it is
+	/// finalized.
+	fn get_payload(&self, _block: &mut UnsealedBeaconBlock<C>) -> Result<(), ExecutionError> {
+		Ok(())
+	}
+
+	/// Verify the execution part of a block being imported.
+	fn notify_new_payload(&self, _block: &BeaconBlock<C>) -> Result<PayloadStatus, ExecutionError> {
+		Ok(PayloadStatus::Valid)
+	}
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+/// Execution engine accepting every block, with nothing to execute.
+pub struct NoopExecutionEngine;
+
+impl<C: Config> ExecutionEngine<C> for NoopExecutionEngine { }
+
+/// Execution engine calls with timeouts, and tracking of optimistically
+/// imported blocks.
+pub struct ExecutionHook<C: Config> {
+	engine: Arc<dyn ExecutionEngine<C>>,
+	timeout: Duration,
+	validity: ValidityStore,
+}
+
+impl<C: Config> Clone for ExecutionHook<C> {
+	fn clone(&self) -> Self {
+		Self {
+			engine: self.engine.clone(),
+			timeout: self.timeout,
+			validity: self.validity.clone(),
+		}
+	}
+}
+
+impl<C: Config> ExecutionHook<C> {
+	/// Create a new hook calling `engine`, giving up after `timeout`.
+	pub fn new(engine: Arc<dyn ExecutionEngine<C>>, timeout: Duration) -> Self {
+		Self { engine, timeout, validity: Default::default() }
+	}
+
+	/// Record validity of optimistically imported blocks in `validity`.
+	pub fn with_validity(mut self, validity: ValidityStore) -> Self {
+		self.validity = validity;
+		self
+	}
+
+	/// Ask the engine to fill in the execution part of a block. On error the
+	/// block is left untouched.
+	pub fn get_payload(&self, block: &mut UnsealedBeaconBlock<C>) -> Result<(), ExecutionError> {
+		let mut built = block.clone();
+		*block = self.call(move |engine| engine.get_payload(&mut built).map(|()| built))?;
+		Ok(())
+	}
+
+	/// Notify the engine of a block being imported. Blocks the engine cannot
+	/// verify in time are imported optimistically.
+	pub fn notify_new_payload(&self, block: &BeaconBlock<C>, id: H256) -> PayloadStatus {
+		let block = block.clone();
+		let status = match self.call(move |engine| engine.notify_new_payload(&block)) {
+			Ok(status) => status,
+			Err(e) => {
+				warn!("Importing block {} optimistically: {}", id, e);
+				PayloadStatus::Syncing
+			},
+		};
+
+		if status == PayloadStatus::Syncing {
+			self.set_validity(&id, BlockValidity::Optimistic);
+		}
+		status
+	}
+
+	/// Whether the block was imported optimistically and not yet reported
+	/// valid.
+	pub fn is_optimistic(&self, id: &H256) -> bool {
+		self.validity.get(id).map(|v| v == BlockValidity::Optimistic).unwrap_or(true)
+	}
+
+	/// Mark an optimistically imported block as valid, once the engine has
+	/// caught up.
+	pub fn on_payload_valid(&self, id: &H256) {
+		self.set_validity(id, BlockValidity::Valid);
+	}
+
+	/// Mark an optimistically imported block as invalid, excluding it and
+	/// its descendants from fork choice.
+	pub fn on_payload_invalid(&self, id: &H256) {
+		self.set_validity(id, BlockValidity::Invalid);
+	}
+
+	fn set_validity(&self, id: &H256, validity: BlockValidity) {
+		if let Err(e) = self.validity.set(id, validity) {
+			warn!("Failed to store validity of block {}: {:?}", id, e);
+		}
+	}
+
+	fn call<T: Send + 'static, F>(&self, f: F) -> Result<T, ExecutionError> where
+		F: FnOnce(&dyn ExecutionEngine<C>) -> Result<T, ExecutionError> + Send + 'static,
+	{
+		let engine = self.engine.clone();
+		let (sender, receiver) = channel();
+		thread::spawn(move || {
+			let _ = sender.send(f(engine.as_ref()));
+		});
+
+		match receiver.recv_timeout(self.timeout) {
+			Ok(result) => result,
+			Err(RecvTimeoutError::Timeout) => Err(ExecutionError::Timeout),
+			Err(RecvTimeoutError::Disconnected) =>
+				Err(ExecutionError::Engine("engine call panicked".to_string())),
+		}
+	}
+}
+
+impl<C: Config> Default for ExecutionHook<C> {
+	fn default() -> Self {
+		Self::new(Arc::new(NoopExecutionEngine), DEFAULT_EXECUTION_TIMEOUT)
+	}
+}
@@ -0,0 +1,260 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Explorer queries over secondary block indices.
+//!
+//! Explorers and analytics want to list the blocks of a proposer or the
+//! inclusions of a validator's attestations, which the beacon API has no
+//! efficient way to answer. When enabled, both are indexed along with the
+//! canonical slot mappings as blocks become canonical, and served over HTTP
+//! on non-standard `/explorer` endpoints.
+//!
+//! Entries are keyed by proposer, or by validator and target epoch, so they
+//! are not removed when their block is reorged out. Entries no longer
+//! canonical are pruned when their key is next written, and skipped by
+//! queries.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use beacon::{BeaconState, Config, StateReader};
+use beacon::primitives::{H256, Epoch, ValidatorIndex};
+use beacon::types::BeaconBlock;
+use parity_codec::{Encode, Decode};
+use serde::Serialize;
+use crate::api_auth::ApiConfig;
+use crate::http::{self, Request, Response};
+use crate::index::{self, IndexStore, MemoryIndexStore};
+
+const PREFIX_PROPOSER: u8 = b'p';
+const PREFIX_ATTESTER: u8 = b'a';
+
+/// Path prefix of the explorer endpoints.
+pub const EXPLORER_PATH: &str = "/explorer";
+
+/// Canonical block of a proposer.
+#[derive(Encode, Decode, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct ProposedBlock {
+	/// Slot of the block.
+	pub slot: u64,
+	/// Root of the block.
+	pub root: H256,
+}
+
+/// Inclusion of an attestation of a validator in a canonical block.
+#[derive(Encode, Decode, Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct AttestationInclusion {
+	/// Slot attested to.
+	pub slot: u64,
+	/// Index of the committee of the validator at that slot.
+	pub committee_index: u64,
+	/// Slot of the including block.
+	pub inclusion_slot: u64,
+	/// Root of the including block.
+	pub block_root: H256,
+}
+
+fn proposer_key(proposer: ValidatorIndex) -> Vec<u8> {
+	let mut key = vec![PREFIX_PROPOSER];
+	key.extend_from_slice(&proposer.to_be_bytes());
+	key
+}
+
+fn attester_key(validator: ValidatorIndex, epoch: Epoch) -> Vec<u8> {
+	let mut key = vec![PREFIX_ATTESTER];
+	key.extend_from_slice(&validator.to_be_bytes());
+	key.extend_from_slice(&epoch.to_be_bytes());
+	key
+}
+
+/// Explorer indices stored along with block indices.
+#[derive(Clone)]
+pub struct ExplorerIndex {
+	store: Arc<dyn IndexStore + Send + Sync>,
+}
+
+impl ExplorerIndex {
+	/// Create explorer indices on top of the given index storage.
+	pub fn new(store: Arc<dyn IndexStore + Send + Sync>) -> Self {
+		Self { store }
+	}
+
+	/// Canonical blocks proposed by `proposer`, in slot order.
+	pub fn proposed_blocks(&self, proposer: ValidatorIndex) -> Result<Vec<ProposedBlock>, crate::Error> {
+		let blocks = self.load::<ProposedBlock>(&proposer_key(proposer))?;
+		self.canonical(blocks, |block| (block.slot, block.root))
+	}
+
+	/// Canonical inclusions of attestations of `validator` targeting
+	/// `epoch`, in inclusion order.
+	pub fn attestation_inclusions(
+		&self,
+		validator: ValidatorIndex,
+		epoch: Epoch,
+	) -> Result<Vec<AttestationInclusion>, crate::Error> {
+		let inclusions = self.load::<AttestationInclusion>(&attester_key(validator, epoch))?;
+		self.canonical(inclusions, |inclusion| (inclusion.inclusion_slot, inclusion.block_root))
+	}
+
+	/// Record a block proposed by `proposer`. The block must already be
+	/// canonical.
+	pub fn insert_proposal(
+		&self,
+		proposer: ValidatorIndex,
+		block: ProposedBlock,
+	) -> Result<(), crate::Error> {
+		let change = self.append(proposer_key(proposer), vec![block], |block| (block.slot, block.root))?;
+		self.store.write(vec![change])
+	}
+
+	/// Record inclusions of attestations of validators, by validator and
+	/// target epoch. The including blocks must already be canonical.
+	pub fn insert_inclusions(
+		&self,
+		inclusions: BTreeMap<(ValidatorIndex, Epoch), Vec<AttestationInclusion>>,
+	) -> Result<(), crate::Error> {
+		let mut changes = Vec::new();
+		for ((validator, epoch), new) in inclusions {
+			changes.push(self.append(
+				attester_key(validator, epoch), new,
+				|inclusion| (inclusion.inclusion_slot, inclusion.block_root),
+			)?);
+		}
+		self.store.write(changes)
+	}
+
+	/// Index the proposer of the canonical block at `root` and the
+	/// attestations it includes, given its post-state.
+	pub fn index_block<C: Config>(
+		&self,
+		root: H256,
+		block: &BeaconBlock<C>,
+		state: &BeaconState<C>,
+	) -> Result<(), crate::Error> {
+		let mut state = state.clone();
+		let reader = StateReader::new(&mut state);
+		// The post-state is at the block slot, so its proposer is the one of
+		// the block.
+		let proposer = reader.beacon_proposer_index()?;
+
+		let mut inclusions = BTreeMap::<_, Vec<_>>::new();
+		for attestation in block.body.attestations.iter() {
+			let data = &attestation.data;
+			let committee = reader.beacon_committee(data.slot, data.index)?;
+			for (validator, _) in committee.into_iter()
+				.zip(attestation.aggregation_bits.iter())
+				.filter(|(_, bit)| **bit)
+			{
+				inclusions.entry((validator, data.target.epoch)).or_default().push(AttestationInclusion {
+					slot: data.slot,
+					committee_index: data.index,
+					inclusion_slot: block.slot,
+					block_root: root,
+				});
+			}
+		}
+
+		self.insert_proposal(proposer, ProposedBlock { slot: block.slot, root })?;
+		self.insert_inclusions(inclusions)
+	}
+
+	fn load<T: Decode>(&self, key: &[u8]) -> Result<Vec<T>, crate::Error> {
+		match self.store.get(key)? {
+			Some(raw) => Ok(
+				Vec::<T>::decode(&mut raw.as_ref()).ok_or(crate::Error::Corrupted("explorer index"))?
+			),
+			None => Ok(Vec::new()),
+		}
+	}
+
+	fn canonical<T, F: Fn(&T) -> (u64, H256)>(
+		&self,
+		entries: Vec<T>,
+		block_of: F,
+	) -> Result<Vec<T>, crate::Error> {
+		let mut canonical = Vec::new();
+		for entry in entries {
+			let (slot, root) = block_of(&entry);
+			if index::canon_root(self.store.as_ref(), slot)? == Some(root) {
+				canonical.push(entry);
+			}
+		}
+		Ok(canonical)
+	}
+
+	/// Change appending `new` to the entries at `key`, pruning those no
+	/// longer canonical and those of the blocks of `new`, if indexed before.
+	fn append<T: Encode + Decode, F: Fn(&T) -> (u64, H256)>(
+		&self,
+		key: Vec<u8>,
+		new: Vec<T>,
+		block_of: F,
+	) -> Result<(Vec<u8>, Option<Vec<u8>>), crate::Error> {
+		let roots = new.iter().map(|entry| block_of(entry).1).collect::<Vec<_>>();
+		let mut entries = self.canonical(self.load::<T>(&key)?, &block_of)?;
+		entries.retain(|entry| !roots.contains(&block_of(entry).1));
+		entries.extend(new);
+		Ok((key, Some(entries.encode())))
+	}
+}
+
+impl Default for ExplorerIndex {
+	fn default() -> Self {
+		Self::new(Arc::new(MemoryIndexStore::default()))
+	}
+}
+
+/// Serve the explorer endpoints on the public address of `api`, from a
+/// background thread. Nothing is served if the public API is disabled.
+///
+/// * `GET /explorer/proposers/{index}/blocks` lists the canonical blocks
+///   of a proposer.
+/// * `GET /explorer/validators/{index}/attestations?epoch={epoch}` lists
+///   the inclusions of the attestations of a validator targeting an epoch.
+pub fn serve(api: ApiConfig, explorer: ExplorerIndex) -> Result<(), http::Error> {
+	http::serve(api, "explorer queries", move |request| route(&explorer, request))
+}
+
+fn route(explorer: &ExplorerIndex, request: &Request) -> Response {
+	let segments = match request.segments(EXPLORER_PATH) {
+		Some(segments) => segments,
+		None => return http::error(404, "Not found"),
+	};
+	let index = match segments.get(1).map(|index| u64::from_str(index)) {
+		Some(Ok(index)) => index,
+		Some(Err(_)) => return http::error(400, "Invalid validator index"),
+		None => return http::error(404, "Not found"),
+	};
+
+	let result = match (segments[0], segments.get(2), segments.len()) {
+		("proposers", Some(&"blocks"), 3) => explorer.proposed_blocks(index).map(data_body),
+		("validators", Some(&"attestations"), 3) => match request.param("epoch").map(u64::from_str) {
+			Some(Ok(epoch)) => explorer.attestation_inclusions(index, epoch).map(data_body),
+			Some(Err(_)) => return http::error(400, "Invalid epoch"),
+			None => return http::error(400, "Missing epoch"),
+		},
+		_ => return http::error(404, "Not found"),
+	};
+
+	match result {
+		Ok(body) => (200, body),
+		Err(e) => http::error(500, &e.to_string()),
+	}
+}
+
+fn data_body<T: Serialize>(data: Vec<T>) -> String {
+	serde_json::json!({ "data": data }).to_string()
+}
@@ -0,0 +1,171 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Queue for blocks from the future.
+//!
+//! Blocks arriving slightly before their slot starts, because of clock skew
+//! between nodes, are held until their slot starts and imported then. Blocks
+//! further in the future than the allowed clock disparity are rejected, and
+//! so are blocks arriving while `MAX_FUTURE_BLOCKS` are already held.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant};
+use beacon::Config;
+use blockchain::Block as BlockT;
+use blockchain::import::BlockImporter;
+use lmd_ghost::clock::SlotClock;
+use log::{debug, warn};
+use crate::Block;
+
+/// Default maximum time a block may arrive before its slot starts.
+pub const MAXIMUM_GOSSIP_CLOCK_DISPARITY: Duration = Duration::from_millis(500);
+/// Maximum number of held blocks, including those waiting to be picked up by
+/// the delay thread.
+pub const MAX_FUTURE_BLOCKS: usize = 64;
+
+#[derive(Debug)]
+/// Future block queue errors.
+pub enum Error<E> {
+	/// The block is further in the future than the allowed clock disparity.
+	TooFarInFuture,
+	/// Too many blocks are already held.
+	Full,
+	/// The delay thread has stopped.
+	Closed,
+	/// Importing the block failed.
+	Import(E),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Display for Error<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::TooFarInFuture => write!(f, "Block is too far in the future"),
+			Error::Full => write!(f, "Future block queue is full"),
+			Error::Closed => write!(f, "Future block thread has stopped"),
+			Error::Import(e) => write!(f, "Importing block failed: {:?}", e),
+		}
+	}
+}
+
+impl<E: std::fmt::Debug> std::error::Error for Error<E> { }
+
+/// Block importer holding blocks from the near future until their slot
+/// starts, before passing them to the inner importer.
+pub struct FutureBlockQueue<C: Config, I, S> {
+	importer: I,
+	slot_clock: S,
+	max_clock_disparity: Duration,
+	sender: SyncSender<(Instant, Block<C>)>,
+	held_count: Arc<AtomicUsize>,
+}
+
+impl<C: Config, I: Clone, S: Clone> Clone for FutureBlockQueue<C, I, S> {
+	fn clone(&self) -> Self {
+		Self {
+			importer: self.importer.clone(),
+			slot_clock: self.slot_clock.clone(),
+			max_clock_disparity: self.max_clock_disparity,
+			sender: self.sender.clone(),
+			held_count: self.held_count.clone(),
+		}
+	}
+}
+
+impl<C: Config, I, S> FutureBlockQueue<C, I, S> where
+	I: BlockImporter<Block=Block<C>> + Clone + Send + 'static,
+	S: SlotClock,
+{
+	/// Spawn a thread importing held blocks into `importer` once their slot
+	/// starts. Blocks more than `max_clock_disparity` early are rejected.
+	pub fn spawn(importer: I, slot_clock: S, max_clock_disparity: Duration) -> Self {
+		let (sender, receiver) = sync_channel::<(Instant, Block<C>)>(MAX_FUTURE_BLOCKS);
+		let held_count = Arc::new(AtomicUsize::new(0));
+
+		let mut delayed_importer = importer.clone();
+		let thread_held_count = held_count.clone();
+		thread::Builder::new()
+			.name("future-blocks".to_string())
+			.spawn(move || {
+				let mut held = BTreeMap::<Instant, Vec<Block<C>>>::new();
+				loop {
+					let next = held.keys().next().cloned();
+					let received = match next {
+						Some(due) => {
+							let now = Instant::now();
+							let timeout = if due > now { due - now } else { Duration::from_secs(0) };
+							receiver.recv_timeout(timeout)
+						},
+						None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+					};
+					match received {
+						Ok((due, block)) => held.entry(due).or_insert_with(Vec::new).push(block),
+						Err(RecvTimeoutError::Timeout) => (),
+						Err(RecvTimeoutError::Disconnected) => return,
+					}
+
+					let pending = held.split_off(&Instant::now());
+					let ready = std::mem::replace(&mut held, pending);
+					for block in ready.into_iter().flat_map(|(_, blocks)| blocks) {
+						thread_held_count.fetch_sub(1, Ordering::SeqCst);
+						let id = block.id();
+						if let Err(e) = delayed_importer.import_block(block) {
+							warn!("Importing held block {:?} failed: {:?}", id, e);
+						}
+					}
+				}
+			})
+			.expect("Spawning future block thread failed");
+
+		Self { importer, slot_clock, max_clock_disparity, sender, held_count }
+	}
+}
+
+impl<C: Config, I, S> BlockImporter for FutureBlockQueue<C, I, S> where
+	I: BlockImporter<Block=Block<C>>,
+	S: SlotClock,
+{
+	type Block = Block<C>;
+	type Error = Error<I::Error>;
+
+	fn import_block(&mut self, block: Block<C>) -> Result<(), Self::Error> {
+		let delay = match self.slot_clock.duration_to_slot(block.slot()) {
+			Some(delay) => delay,
+			None => return self.importer.import_block(block).map_err(Error::Import),
+		};
+
+		if delay > self.max_clock_disparity {
+			return Err(Error::TooFarInFuture)
+		}
+
+		if self.held_count.fetch_add(1, Ordering::SeqCst) >= MAX_FUTURE_BLOCKS {
+			self.held_count.fetch_sub(1, Ordering::SeqCst);
+			return Err(Error::Full)
+		}
+
+		debug!("Holding block {:?} for {:?} until slot {}", block.id(), delay, block.slot());
+		self.sender.try_send((Instant::now() + delay, block)).map_err(|e| {
+			self.held_count.fetch_sub(1, Ordering::SeqCst);
+			match e {
+				TrySendError::Full(_) => Error::Full,
+				TrySendError::Disconnected(_) => Error::Closed,
+			}
+		})
+	}
+}
@@ -0,0 +1,326 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Node health summary and probes.
+//!
+//! A single JSON document aggregating the head slot against the wall clock,
+//! the peer count, the finality lag, disk usage and the status of local
+//! validators, refreshed every slot. It is served over HTTP on the public
+//! API address, answering with status 503 while the node is unhealthy, so
+//! that it can back simple dashboards and load balancer health checks.
+//!
+//! Readiness and liveness probes are served next to it for container
+//! orchestration. A node is ready once it is synced, its database is
+//! readable and it has enough peers, and live as long as the summary keeps
+//! being refreshed.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use beacon::{BeaconState, Config};
+use beacon::primitives::ValidatorId;
+use beacon::utils::epoch_of_slot;
+use log::info;
+use serde::Serialize;
+use crate::api_auth::ApiConfig;
+use crate::datadir::DataDir;
+use crate::http::{self, Request, Response};
+
+/// Path of the health endpoint.
+pub const HEALTH_PATH: &str = "/node/health";
+/// Path of the readiness probe.
+pub const READY_PATH: &str = "/health/ready";
+/// Path of the liveness probe.
+pub const LIVE_PATH: &str = "/health/live";
+/// Slots without a refresh of the summary after which the node is no
+/// longer live.
+pub const LIVE_TIMEOUT_SLOTS: u32 = 4;
+/// Maximum slots the head may lag behind the wall clock while healthy.
+pub const MAX_HEALTHY_SYNC_DISTANCE: u64 = 8;
+/// Maximum epochs finality may lag behind the current epoch while healthy.
+pub const MAX_HEALTHY_FINALITY_LAG: u64 = 4;
+/// Minimum connected peers while healthy.
+pub const MIN_HEALTHY_PEERS: usize = 1;
+
+/// Configuration of the health endpoint.
+#[derive(Clone, Debug)]
+pub struct HealthConfig {
+	/// API the endpoint is served on, at its public address.
+	pub api: ApiConfig,
+	/// Data directory to report the disk usage of.
+	pub data_dir: Option<DataDir>,
+	/// Thresholds of the probes.
+	pub probes: Probes,
+}
+
+/// Thresholds of the readiness and liveness probes.
+#[derive(Clone, Debug)]
+pub struct Probes {
+	/// Maximum slots the head may lag behind the wall clock while ready.
+	pub ready_sync_distance: u64,
+	/// Minimum connected peers while ready.
+	pub ready_min_peers: usize,
+	/// Time without a refresh of the summary after which the node is no
+	/// longer live.
+	pub live_timeout: Duration,
+}
+
+impl Default for Probes {
+	fn default() -> Self {
+		Self {
+			ready_sync_distance: MAX_HEALTHY_SYNC_DISTANCE,
+			ready_min_peers: MIN_HEALTHY_PEERS,
+			live_timeout: Duration::from_secs(60),
+		}
+	}
+}
+
+/// Status of the local validators in the head state.
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct ValidatorHealth {
+	/// Number of local validator keys.
+	pub local: usize,
+	/// Active validators.
+	pub active: usize,
+	/// Validators in the registry, not yet active.
+	pub pending: usize,
+	/// Exited validators, including slashed ones.
+	pub exited: usize,
+	/// Slashed validators.
+	pub slashed: usize,
+	/// Keys not in the registry.
+	pub unknown: usize,
+}
+
+impl ValidatorHealth {
+	/// Status of the validators of `keys` in `state`.
+	pub fn new<C: Config>(state: &BeaconState<C>, keys: &[ValidatorId]) -> Self {
+		let epoch = epoch_of_slot::<C>(state.slot);
+		let mut health = Self { local: keys.len(), ..Default::default() };
+		for key in keys {
+			match state.validators.iter().find(|validator| validator.pubkey == *key) {
+				Some(validator) => {
+					if validator.slashed {
+						health.slashed += 1;
+					}
+					if validator.is_active(epoch) {
+						health.active += 1;
+					} else if epoch < validator.activation_epoch {
+						health.pending += 1;
+					} else {
+						health.exited += 1;
+					}
+				},
+				None => health.unknown += 1,
+			}
+		}
+		health
+	}
+}
+
+/// Node health summary.
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct HealthReport {
+	/// Slot of the head block.
+	pub head_slot: u64,
+	/// Current slot by the wall clock, if genesis has happened.
+	pub wall_clock_slot: Option<u64>,
+	/// Slots the head lags behind the wall clock.
+	pub sync_distance: u64,
+	/// Connected peers.
+	pub peer_count: usize,
+	/// Finalized epoch of the head state.
+	pub finalized_epoch: u64,
+	/// Epochs finality lags behind the current epoch.
+	pub finality_lag: u64,
+	/// Bytes used by the data directory, if the node has one.
+	pub disk_usage: Option<u64>,
+	/// Bytes free on the file system of the data directory.
+	pub free_space: Option<u64>,
+	/// Status of the local validators.
+	pub validators: ValidatorHealth,
+	/// Reasons the node is unhealthy.
+	pub problems: Vec<String>,
+	/// Whether the node is healthy.
+	pub healthy: bool,
+}
+
+impl HealthReport {
+	/// Summarize the health of a node with the given head state.
+	pub fn new<C: Config>(
+		head_state: &BeaconState<C>,
+		wall_clock_slot: Option<u64>,
+		peer_count: usize,
+		data_dir: Option<&DataDir>,
+		keys: &[ValidatorId],
+	) -> Self {
+		let head_slot = head_state.slot;
+		let current_slot = wall_clock_slot.unwrap_or(head_slot).max(head_slot);
+		let finalized_epoch = head_state.finalized_checkpoint.epoch;
+
+		let mut report = Self {
+			head_slot,
+			wall_clock_slot,
+			sync_distance: current_slot - head_slot,
+			peer_count,
+			finalized_epoch,
+			finality_lag: epoch_of_slot::<C>(current_slot).saturating_sub(finalized_epoch),
+			disk_usage: data_dir.and_then(|data_dir| disk_usage(data_dir.root()).ok()),
+			free_space: data_dir.and_then(|data_dir| data_dir.free_space().ok()),
+			validators: ValidatorHealth::new(head_state, keys),
+			problems: Vec::new(),
+			healthy: false,
+		};
+
+		if report.sync_distance > MAX_HEALTHY_SYNC_DISTANCE {
+			report.problems.push(format!("Head is {} slots behind", report.sync_distance));
+		}
+		if report.finality_lag > MAX_HEALTHY_FINALITY_LAG {
+			report.problems.push(format!("Finality is {} epochs behind", report.finality_lag));
+		}
+		if report.peer_count < MIN_HEALTHY_PEERS {
+			report.problems.push("No connected peers".to_string());
+		}
+		if report.validators.slashed > 0 {
+			report.problems.push(format!("{} local validators are slashed", report.validators.slashed));
+		}
+		report.healthy = report.problems.is_empty();
+		report
+	}
+
+	/// Report as a JSON document.
+	pub fn to_json(&self) -> String {
+		serde_json::to_string_pretty(self).expect("Report always serializes; qed")
+	}
+}
+
+/// Latest health of the node, shared with the endpoint.
+pub struct HealthState {
+	/// Latest summary.
+	pub report: Option<HealthReport>,
+	/// Error reading the head state on the last refresh.
+	pub database_error: Option<String>,
+	/// When the updater started.
+	pub started: Instant,
+	/// When the summary was last refreshed.
+	pub refreshed: Option<Instant>,
+}
+
+impl Default for HealthState {
+	fn default() -> Self {
+		Self { report: None, database_error: None, started: Instant::now(), refreshed: None }
+	}
+}
+
+impl HealthState {
+	/// Record a refresh, with the new summary or the error reading the head
+	/// state. The previous summary is kept on errors.
+	pub fn refresh(&mut self, result: Result<HealthReport, String>) {
+		match result {
+			Ok(report) => {
+				self.report = Some(report);
+				self.database_error = None;
+			},
+			Err(e) => self.database_error = Some(e),
+		}
+		self.refreshed = Some(Instant::now());
+	}
+
+	/// Check whether the node is ready to serve traffic.
+	pub fn readiness(&self, probes: &Probes) -> Result<(), Vec<String>> {
+		let mut problems = Vec::new();
+		if let Some(e) = self.database_error.as_ref() {
+			problems.push(format!("Reading the database failed: {}", e));
+		}
+		match self.report.as_ref() {
+			Some(report) => {
+				if report.sync_distance > probes.ready_sync_distance {
+					problems.push(format!("Head is {} slots behind", report.sync_distance));
+				}
+				if report.peer_count < probes.ready_min_peers {
+					problems.push(format!("Only {} connected peers", report.peer_count));
+				}
+			},
+			None => problems.push("Health is not yet known".to_string()),
+		}
+
+		if problems.is_empty() { Ok(()) } else { Err(problems) }
+	}
+
+	/// Check whether the node is live, that is its summary is still being
+	/// refreshed.
+	pub fn liveness(&self, probes: &Probes) -> Result<(), String> {
+		let since = self.refreshed.unwrap_or(self.started).elapsed();
+		if since > probes.live_timeout {
+			Err(format!("Health not refreshed for {} seconds", since.as_secs()))
+		} else {
+			Ok(())
+		}
+	}
+}
+
+/// Health of the node, shared between its updater and the endpoint.
+pub type SharedHealth = Arc<RwLock<HealthState>>;
+
+/// Total size of the files under `path`, in bytes.
+pub fn disk_usage(path: &Path) -> io::Result<u64> {
+	let mut total = 0;
+	for entry in fs::read_dir(path)? {
+		let entry = entry?;
+		let file_type = entry.file_type()?;
+		if file_type.is_dir() {
+			total += disk_usage(&entry.path())?;
+		} else if file_type.is_file() {
+			total += entry.metadata()?.len();
+		}
+	}
+	Ok(total)
+}
+
+/// Serve the health summary and probes on the public address of `api`,
+/// from a background thread. Nothing is served if the public API is
+/// disabled.
+pub fn serve(api: ApiConfig, probes: Probes, health: SharedHealth) -> Result<(), http::Error> {
+	if let Some(address) = api.public_address {
+		info!("Serving node health at {}{}, probes at {} and {}", address, HEALTH_PATH, READY_PATH, LIVE_PATH);
+	}
+	http::serve(api, "node health", move |request| route(&probes, &health, request))
+}
+
+fn route(probes: &Probes, health: &SharedHealth, request: &Request) -> Response {
+	let health = health.read().expect("Lock is poisoned");
+	match request.path {
+		READY_PATH => match health.readiness(probes) {
+			Ok(()) => (200, probe_body(&[])),
+			Err(problems) => (503, probe_body(&problems)),
+		},
+		LIVE_PATH => match health.liveness(probes) {
+			Ok(()) => (200, probe_body(&[])),
+			Err(problem) => (503, probe_body(&[problem])),
+		},
+		HEALTH_PATH => match health.report.as_ref() {
+			Some(report) => (if report.healthy { 200 } else { 503 }, report.to_json()),
+			None => http::error(503, "Health is not yet known"),
+		},
+		_ => http::error(404, "Not found"),
+	}
+}
+
+fn probe_body(problems: &[String]) -> String {
+	serde_json::json!({ "ok": problems.is_empty(), "problems": problems }).to_string()
+}
@@ -0,0 +1,260 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Historical batches retained by archive nodes.
+//!
+//! A beacon state only keeps block and state roots of the last
+//! `SLOTS_PER_HISTORICAL_ROOT` slots, and accumulates older ones into
+//! `historical_roots`. Archive nodes keep the full batches around, so roots at
+//! any slot can be served and proven against a recent state root.
+
+use crate::Error;
+use beacon::{Config, BeaconState};
+use beacon::primitives::H256;
+use beacon::types::HistoricalBatch;
+use beacon::utils::is_valid_merkle_branch;
+use bm_le::{tree_root, CompactRef};
+use parity_codec::{Encode, Decode};
+use crate::index::IndexStore;
+
+const PREFIX_BATCH: u8 = b'b';
+/// Position of `historical_roots` in the beacon state container, following
+/// the field order of `BeaconState` as listed in `state_field_roots`.
+const HISTORICAL_ROOTS_FIELD_INDEX: usize = 6;
+
+fn batch_key(index: u64) -> Vec<u8> {
+	let mut key = vec![PREFIX_BATCH];
+	key.extend_from_slice(&index.to_be_bytes());
+	key
+}
+
+/// Stored historical batch at `index`.
+pub fn batch<C: Config>(
+	store: &dyn IndexStore,
+	index: u64,
+) -> Result<Option<HistoricalBatch<C>>, Error> {
+	match store.get(&batch_key(index))? {
+		Some(raw) => Ok(Some(
+			HistoricalBatch::decode(&mut raw.as_ref()).ok_or(Error::Corrupted("historical batch"))?
+		)),
+		None => Ok(None),
+	}
+}
+
+/// Store the historical batch at `index`, replacing the one of a previous
+/// canonical chain, or remove it if `None`.
+pub fn insert_batch<C: Config>(
+	store: &dyn IndexStore,
+	index: u64,
+	batch: Option<&HistoricalBatch<C>>,
+) -> Result<(), Error> {
+	store.write(vec![(batch_key(index), batch.map(Encode::encode))])
+}
+
+/// Roots of the head state, kept so that recent roots can be served without
+/// loading the head state.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct RecentRoots {
+	/// Root of the head block.
+	pub head: H256,
+	/// Slot of the head state.
+	pub slot: u64,
+	/// Block roots of the last `SLOTS_PER_HISTORICAL_ROOT` slots.
+	pub block_roots: Vec<H256>,
+	/// State roots of the last `SLOTS_PER_HISTORICAL_ROOT` slots.
+	pub state_roots: Vec<H256>,
+	/// Number of historical batches accumulated by the head state.
+	pub batch_count: u64,
+}
+
+impl RecentRoots {
+	/// Recent roots of the state of block `head`.
+	pub fn new<C: Config>(head: H256, state: &BeaconState<C>) -> Self {
+		Self {
+			head,
+			slot: state.slot,
+			block_roots: state.block_roots.iter().cloned().collect(),
+			state_roots: state.state_roots.iter().cloned().collect(),
+			batch_count: state.historical_roots.len() as u64,
+		}
+	}
+
+	/// Block and state roots at `slot`, if it is recent enough.
+	pub fn at_slot(&self, slot: u64) -> Option<(H256, H256)> {
+		let slots = self.block_roots.len() as u64;
+		if slot < self.slot && self.slot <= slot + slots {
+			let i = (slot % slots) as usize;
+			Some((self.block_roots[i], self.state_roots[i]))
+		} else {
+			None
+		}
+	}
+}
+
+/// Reconstruct the historical batch accumulated when crossing from `parent`
+/// to `state`. The parent state still holds the roots of the batch up to its
+/// own slot, and the child state holds the remaining ones. Returns `None` if
+/// the two states are too far apart, or the result does not match the
+/// accumulated root.
+pub fn reconstruct_batch<C: Config>(
+	parent: &BeaconState<C>,
+	state: &BeaconState<C>,
+) -> Option<HistoricalBatch<C>> {
+	let slots = C::slots_per_historical_root();
+	let index = parent.historical_roots.len();
+	if state.historical_roots.len() != index + 1 || state.slot > parent.slot + slots {
+		return None
+	}
+
+	let end = (index as u64 + 1) * slots;
+	let mut batch = HistoricalBatch::<C> {
+		block_roots: parent.block_roots.clone(),
+		state_roots: parent.state_roots.clone(),
+	};
+	for slot in state.slot.saturating_sub(slots)..end {
+		let i = (slot % slots) as usize;
+		batch.block_roots[i] = state.block_roots[i];
+		batch.state_roots[i] = state.state_roots[i];
+	}
+
+	if tree_root::<C::Digest, _>(&batch) == state.historical_roots[index] {
+		Some(batch)
+	} else {
+		None
+	}
+}
+
+/// Merkle proof of an old block root against a state root.
+#[derive(Encode, Decode, Clone, Eq, PartialEq, Debug)]
+pub struct HistoricalRootProof {
+	/// Proven block root.
+	pub block_root: H256,
+	/// Merkle branch from the block root up to the state root.
+	pub branch: Vec<H256>,
+	/// Index of the block root among leaves at the depth of the branch.
+	pub index: u64,
+}
+
+impl HistoricalRootProof {
+	/// Verify the proof against the given state root.
+	pub fn verify<C: Config>(&self, state_root: H256) -> bool {
+		is_valid_merkle_branch::<C>(
+			self.block_root, &self.branch, self.branch.len() as u64, self.index, state_root
+		)
+	}
+}
+
+/// Prove the block root at `slot`, in the given historical batch, against the
+/// root of `state`.
+pub fn prove_block_root<C: Config>(
+	state: &BeaconState<C>,
+	batch: &HistoricalBatch<C>,
+	slot: u64,
+) -> Result<HistoricalRootProof, Error> {
+	let slots = C::slots_per_historical_root();
+	let batch_index = slot / slots;
+	if batch_index >= state.historical_roots.len() as u64 {
+		return Err(Error::SlotNotCovered(slot))
+	}
+
+	let block_roots = batch.block_roots.iter().cloned().collect::<Vec<_>>();
+	let historical_roots = state.historical_roots.iter().cloned().collect::<Vec<_>>();
+	let fields = state_field_roots(state);
+
+	let block_roots_depth = depth_of(slots);
+	let historical_roots_depth = depth_of(C::historical_roots_limit());
+	let fields_depth = depth_of(fields.len() as u64);
+
+	let mut branch = merkle_branch::<C>(&block_roots, block_roots_depth, (slot % slots) as usize);
+	branch.push(tree_root::<C::Digest, _>(&batch.state_roots));
+	branch.extend(merkle_branch::<C>(
+		&historical_roots, historical_roots_depth, batch_index as usize
+	));
+	branch.push(length_node(historical_roots.len() as u64));
+	branch.extend(merkle_branch::<C>(&fields, fields_depth, HISTORICAL_ROOTS_FIELD_INDEX));
+
+	let index = (slot % slots) |
+		(batch_index << (block_roots_depth + 1)) |
+		((HISTORICAL_ROOTS_FIELD_INDEX as u64) << (block_roots_depth + historical_roots_depth + 2));
+
+	let proof = HistoricalRootProof {
+		block_root: block_roots[(slot % slots) as usize],
+		branch,
+		index,
+	};
+
+	if !proof.verify::<C>(tree_root::<C::Digest, _>(state)) {
+		return Err(Error::InvalidProof)
+	}
+
+	Ok(proof)
+}
+
+/// Roots of the fields of `state`, in container order.
+fn state_field_roots<C: Config>(state: &BeaconState<C>) -> Vec<H256> {
+	vec![
+		tree_root::<C::Digest, _>(&state.genesis_time),
+		tree_root::<C::Digest, _>(&state.slot),
+		tree_root::<C::Digest, _>(&state.fork),
+		tree_root::<C::Digest, _>(&state.latest_block_header),
+		tree_root::<C::Digest, _>(&state.block_roots),
+		tree_root::<C::Digest, _>(&state.state_roots),
+		tree_root::<C::Digest, _>(&state.historical_roots),
+		tree_root::<C::Digest, _>(&state.eth1_data),
+		tree_root::<C::Digest, _>(&state.eth1_data_votes),
+		tree_root::<C::Digest, _>(&state.eth1_deposit_index),
+		tree_root::<C::Digest, _>(&state.validators),
+		tree_root::<C::Digest, _>(&CompactRef(&state.balances)),
+		tree_root::<C::Digest, _>(&state.randao_mixes),
+		tree_root::<C::Digest, _>(&CompactRef(&state.slashings)),
+		tree_root::<C::Digest, _>(&state.previous_epoch_attestations),
+		tree_root::<C::Digest, _>(&state.current_epoch_attestations),
+		tree_root::<C::Digest, _>(&CompactRef(&state.justification_bits)),
+		tree_root::<C::Digest, _>(&state.previous_justified_checkpoint),
+		tree_root::<C::Digest, _>(&state.current_justified_checkpoint),
+		tree_root::<C::Digest, _>(&state.finalized_checkpoint),
+	]
+}
+
+/// Depth of a Merkle tree with at least `count` leaves.
+fn depth_of(count: u64) -> usize {
+	count.next_power_of_two().trailing_zeros() as usize
+}
+
+fn length_node(length: u64) -> H256 {
+	let mut ret = H256::default();
+	(&mut ret[0..8]).copy_from_slice(&length.to_le_bytes());
+	ret
+}
+
+/// Merkle branch of leaf `index` in a tree of the given depth, with missing
+/// leaves being zero.
+fn merkle_branch<C: Config>(leaves: &[H256], depth: usize, mut index: usize) -> Vec<H256> {
+	let mut zero = H256::default();
+	let mut layer = leaves.to_vec();
+	let mut branch = Vec::new();
+
+	for _ in 0..depth {
+		branch.push(layer.get(index ^ 1).cloned().unwrap_or(zero));
+		layer = layer.chunks(2)
+			.map(|pair| C::hash(&[pair[0].as_ref(), pair.get(1).unwrap_or(&zero).as_ref()]))
+			.collect();
+		zero = C::hash(&[zero.as_ref(), zero.as_ref()]);
+		index /= 2;
+	}
+
+	branch
+}
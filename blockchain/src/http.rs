@@ -0,0 +1,317 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Minimal HTTP server of JSON endpoints.
+//!
+//! Requests are answered on the public or admin address of an API
+//! configuration, with its TLS, authorization and cross-origin policy. Each
+//! connection is handled on its own thread, up to `MAX_CONNECTIONS` at a
+//! time, and must send its request head within `REQUEST_TIMEOUT` and the
+//! size limits below.
+
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, info};
+use crate::api_auth::{Access, ApiConfig};
+use crate::tls;
+
+/// Time allowed to read the whole request head.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum length of the request line, in bytes.
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// Maximum total length of the request headers, in bytes.
+pub const MAX_HEADERS: usize = 16 * 1024;
+/// Maximum connections handled at the same time by one server.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// Endpoint errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Binding the listener failed.
+	Io(io::Error),
+	/// Loading the TLS configuration failed.
+	Tls(tls::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Binding API endpoint failed: {}", e),
+			Error::Tls(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			Error::Tls(e) => Some(e),
+		}
+	}
+}
+
+/// A `GET` request.
+#[derive(Clone, Copy, Debug)]
+pub struct Request<'a> {
+	/// Path, without the query string.
+	pub path: &'a str,
+	/// Query string, without the leading `?`.
+	pub query: &'a str,
+}
+
+impl<'a> Request<'a> {
+	/// Parse a request target.
+	pub fn new(target: &'a str) -> Self {
+		let mut target = target.splitn(2, '?');
+		Self {
+			path: target.next().unwrap_or_default(),
+			query: target.next().unwrap_or_default(),
+		}
+	}
+
+	/// Path segments after `prefix`, or `None` if the path is not under it.
+	pub fn segments(&self, prefix: &str) -> Option<Vec<&'a str>> {
+		if self.path != prefix && !self.path.starts_with(&format!("{}/", prefix)) {
+			return None
+		}
+		Some(self.path[prefix.len()..].split('/').filter(|segment| !segment.is_empty()).collect())
+	}
+
+	/// Value of the query parameter `name`.
+	pub fn param(&self, name: &str) -> Option<&'a str> {
+		self.query.split('&')
+			.filter_map(|pair| {
+				let mut pair = pair.splitn(2, '=');
+				match (pair.next(), pair.next()) {
+					(Some(key), Some(value)) if key == name => Some(value),
+					_ => None,
+				}
+			})
+			.next()
+	}
+}
+
+/// Status and JSON body of a response.
+pub type Response = (u16, String);
+
+/// Response with a JSON error message.
+pub fn error(status: u16, message: &str) -> Response {
+	(status, serde_json::json!({ "message": message }).to_string())
+}
+
+/// Serve `GET` requests with `handler` on the public address of `api`, from
+/// a background thread. Nothing is served if the public API is disabled.
+pub fn serve<H>(api: ApiConfig, name: &'static str, handler: H) -> Result<(), Error> where
+	H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+	match api.public_address {
+		Some(address) => serve_on(address, Access::Public, api, name, handler),
+		None => Ok(()),
+	}
+}
+
+/// Serve `GET` requests with `handler` on the admin address of `api`, to
+/// requests bearing its token, from a background thread. Nothing is served
+/// if the admin API is disabled.
+pub fn serve_admin<H>(api: ApiConfig, name: &'static str, handler: H) -> Result<(), Error> where
+	H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+	match api.admin_address {
+		Some(address) => serve_on(address, Access::Admin, api, name, handler),
+		None => Ok(()),
+	}
+}
+
+fn serve_on<H>(
+	address: SocketAddr,
+	access: Access,
+	api: ApiConfig,
+	name: &'static str,
+	handler: H,
+) -> Result<(), Error> where
+	H: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+	let tls = match api.tls.as_ref() {
+		Some(tls) => Some(tls.load().map_err(Error::Tls)?),
+		None => None,
+	};
+	let listener = TcpListener::bind(address).map_err(Error::Io)?;
+	info!("Serving {} at {}", name, address);
+
+	let api = Arc::new(api);
+	let handler = Arc::new(handler);
+	let connections = Arc::new(AtomicUsize::new(0));
+	thread::spawn(move || for stream in listener.incoming() {
+		let stream = match stream {
+			Ok(stream) => stream,
+			Err(e) => {
+				debug!("Accepting {} connection failed: {}", name, e);
+				continue
+			},
+		};
+		if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+			connections.fetch_sub(1, Ordering::SeqCst);
+			debug!("Dropping {} connection, {} already open", name, MAX_CONNECTIONS);
+			continue
+		}
+
+		let tls = tls.clone();
+		let api = api.clone();
+		let handler = handler.clone();
+		let connections = connections.clone();
+		thread::spawn(move || {
+			let result = Deadline::new(&stream).and_then(|deadline| match tls {
+				Some(config) => respond(
+					rustls::StreamOwned::new(rustls::ServerSession::new(&config), stream),
+					deadline, access, &api, &*handler,
+				),
+				None => respond(stream, deadline, access, &api, &*handler),
+			});
+			if let Err(e) = result {
+				debug!("Answering {} request failed: {}", name, e);
+			}
+			connections.fetch_sub(1, Ordering::SeqCst);
+		});
+	});
+	Ok(())
+}
+
+/// Deadline for reading the request head, applied to the socket before
+/// each read so that a slow client cannot extend it by trickling bytes.
+struct Deadline {
+	socket: TcpStream,
+	end: Instant,
+}
+
+impl Deadline {
+	fn new(stream: &TcpStream) -> io::Result<Self> {
+		Ok(Self { socket: stream.try_clone()?, end: Instant::now() + REQUEST_TIMEOUT })
+	}
+
+	fn arm(&self) -> io::Result<()> {
+		let now = Instant::now();
+		if now >= self.end {
+			return Err(io::Error::new(io::ErrorKind::TimedOut, "Request timed out"))
+		}
+		self.socket.set_read_timeout(Some(self.end - now))
+	}
+}
+
+/// Read a line of at most `limit` bytes into `line`, before the deadline.
+fn read_line<R: BufRead>(
+	reader: &mut R,
+	deadline: &Deadline,
+	limit: usize,
+	line: &mut String,
+) -> io::Result<usize> {
+	let start = line.len();
+	loop {
+		deadline.arm()?;
+		let available = reader.fill_buf()?;
+		if available.is_empty() {
+			break
+		}
+		let (used, done) = match available.iter().position(|byte| *byte == b'\n') {
+			Some(index) => (index + 1, true),
+			None => (available.len(), false),
+		};
+		if line.len() - start + used > limit {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "Request head too large"))
+		}
+		line.push_str(&String::from_utf8_lossy(&available[..used]));
+		reader.consume(used);
+		if done {
+			break
+		}
+	}
+	Ok(line.len() - start)
+}
+
+fn respond<S: Read + Write, H: Fn(&Request) -> Response>(
+	stream: S,
+	deadline: Deadline,
+	access: Access,
+	api: &ApiConfig,
+	handler: &H,
+) -> io::Result<()> {
+	let mut reader = BufReader::new(stream);
+	let mut request_line = String::new();
+	read_line(&mut reader, &deadline, MAX_REQUEST_LINE, &mut request_line)?;
+	let mut origin = None;
+	let mut authorization = None;
+	let mut headers = 0;
+	loop {
+		let mut line = String::new();
+		let read = read_line(&mut reader, &deadline, MAX_HEADERS - headers, &mut line)?;
+		headers += read;
+		if read == 0 || line.trim().is_empty() {
+			break
+		}
+		let mut header = line.splitn(2, ':');
+		if let (Some(name), Some(value)) = (header.next(), header.next()) {
+			if name.trim().eq_ignore_ascii_case("origin") {
+				origin = Some(value.trim().to_string());
+			} else if name.trim().eq_ignore_ascii_case("authorization") {
+				authorization = Some(value.trim().to_string());
+			}
+		}
+	}
+
+	let mut request = request_line.split_whitespace();
+	let method = request.next().unwrap_or_default();
+	let target = request.next().unwrap_or_default();
+	let (status, body) = if method != "GET" {
+		error(405, "Method not allowed")
+	} else if let Err(denied) = api.authorize(
+		access, access == Access::Admin, authorization.as_ref().map(|value| value.as_str()),
+	) {
+		error(denied.status(), &denied.to_string())
+	} else {
+		handler(&Request::new(target))
+	};
+
+	let mut stream = reader.into_inner();
+	write!(
+		stream,
+		"HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+		status, reason(status), body.len(),
+	)?;
+	if let Some(allowed) = origin.and_then(|origin| api.allow_origin(&origin)) {
+		write!(stream, "Access-Control-Allow-Origin: {}\r\n", allowed)?;
+	}
+	write!(stream, "\r\n{}", body)?;
+	stream.flush()
+}
+
+fn reason(status: u16) -> &'static str {
+	match status {
+		200 => "OK",
+		400 => "Bad Request",
+		401 => "Unauthorized",
+		403 => "Forbidden",
+		404 => "Not Found",
+		405 => "Method Not Allowed",
+		500 => "Internal Server Error",
+		503 => "Service Unavailable",
+		_ => "",
+	}
+}
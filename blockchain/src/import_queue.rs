@@ -0,0 +1,198 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block import queue.
+//!
+//! Executing a block runs the state transition, tree hashing and BLS
+//! verification, which must not happen on the networking reactor. Blocks are
+//! instead handed over to a dedicated import thread through a bounded queue.
+//! Queued blocks are passed through a chunk verifier before being imported,
+//! allowing them to be pre-verified together.
+//!
+//! During initial sync, verification and import can run as separate
+//! pipeline stages on their own threads, connected by a bounded channel, so
+//! that verifying queued blocks overlaps with executing earlier ones. The
+//! verifier itself spreads tree hashing and signature verification of a
+//! chunk over the rayon worker pool, while blocks are still imported in
+//! order by a single thread.
+
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+use blockchain::Block;
+use blockchain::import::{BlockImporter, SharedBlockImporter};
+use log::{trace, warn};
+
+/// Default number of blocks that can be queued for import.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// Pre-verification of queued blocks before they are imported.
+pub trait ChunkVerifier<B>: Send {
+	/// Pre-verify leading blocks of the queue, returning the number of them
+	/// that should be imported before the queue is verified again. Must be at
+	/// least one for a non-empty queue.
+	fn verify_chunk(&mut self, blocks: &[B]) -> usize;
+}
+
+/// Chunk verifier that does not verify anything.
+impl<B> ChunkVerifier<B> for () {
+	fn verify_chunk(&mut self, blocks: &[B]) -> usize {
+		blocks.len()
+	}
+}
+
+#[derive(Debug)]
+/// Import queue errors.
+pub enum Error {
+	/// The queue is full, and the block was dropped.
+	Full,
+	/// The import thread has stopped.
+	Closed,
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Error::Full => write!(f, "Import queue is full"),
+			Error::Closed => write!(f, "Import thread has stopped"),
+		}
+	}
+}
+
+impl std::error::Error for Error { }
+
+/// Block importer handing blocks over to a dedicated import thread. When the
+/// queue is full, blocks are rejected instead of blocking the caller, so that
+/// they can be requested again later.
+pub struct ImportQueue<B> {
+	sender: SyncSender<B>,
+}
+
+impl<B> Clone for ImportQueue<B> {
+	fn clone(&self) -> Self {
+		Self { sender: self.sender.clone() }
+	}
+}
+
+impl<B: Block + Send + 'static> ImportQueue<B> {
+	/// Spawn an import thread running `importer`, with room for `capacity`
+	/// queued blocks.
+	pub fn spawn<I>(importer: I, capacity: usize) -> Self where
+		I: SharedBlockImporter<Block=B> + Send + 'static,
+	{
+		Self::spawn_with_verifier(importer, (), capacity)
+	}
+
+	/// Spawn an import thread running `importer`, passing queued blocks
+	/// through `verifier` first, with room for `capacity` queued blocks.
+	pub fn spawn_with_verifier<I, V>(importer: I, mut verifier: V, capacity: usize) -> Self where
+		I: SharedBlockImporter<Block=B> + Send + 'static,
+		V: ChunkVerifier<B> + 'static,
+	{
+		let (sender, receiver) = sync_channel::<B>(capacity);
+
+		thread::Builder::new()
+			.name("block-import".to_string())
+			.spawn(move || {
+				let mut pending = Vec::new();
+				while receive(&receiver, &mut pending, capacity) {
+					let len = verifier.verify_chunk(&pending).max(1).min(pending.len());
+					for block in pending.drain(..len) {
+						let id = block.id();
+						match importer.import_block(block) {
+							Ok(()) => trace!("Imported block {:?}", id),
+							Err(e) => warn!("Importing block {:?} failed: {:?}", id, e),
+						}
+					}
+				}
+			})
+			.expect("Spawning import thread failed");
+
+		Self { sender }
+	}
+
+	/// Spawn a verification thread passing queued blocks through `verifier`,
+	/// and an import thread running `importer`, connected by a channel of
+	/// `capacity` verified blocks. The verification thread blocks while the
+	/// import thread is behind.
+	pub fn spawn_pipelined<I, V>(importer: I, mut verifier: V, capacity: usize) -> Self where
+		I: SharedBlockImporter<Block=B> + Send + 'static,
+		V: ChunkVerifier<B> + 'static,
+	{
+		let (sender, receiver) = sync_channel::<B>(capacity);
+		let (verified_sender, verified_receiver) = sync_channel::<B>(capacity);
+
+		thread::Builder::new()
+			.name("block-import".to_string())
+			.spawn(move || {
+				for block in verified_receiver {
+					let id = block.id();
+					match importer.import_block(block) {
+						Ok(()) => trace!("Imported block {:?}", id),
+						Err(e) => warn!("Importing block {:?} failed: {:?}", id, e),
+					}
+				}
+			})
+			.expect("Spawning import thread failed");
+
+		thread::Builder::new()
+			.name("block-verify".to_string())
+			.spawn(move || {
+				let mut pending = Vec::new();
+				while receive(&receiver, &mut pending, capacity) {
+					let len = verifier.verify_chunk(&pending).max(1).min(pending.len());
+					for block in pending.drain(..len) {
+						if verified_sender.send(block).is_err() {
+							warn!("Import thread has stopped, stopping verification");
+							return
+						}
+					}
+				}
+			})
+			.expect("Spawning verification thread failed");
+
+		Self { sender }
+	}
+}
+
+/// Fill `pending` with queued blocks, waiting for one if there are none.
+/// Returns `false` once the queue is closed and drained.
+fn receive<B>(receiver: &Receiver<B>, pending: &mut Vec<B>, capacity: usize) -> bool {
+	if pending.is_empty() {
+		match receiver.recv() {
+			Ok(block) => pending.push(block),
+			Err(_) => return false,
+		}
+	}
+	while pending.len() < capacity {
+		match receiver.try_recv() {
+			Ok(block) => pending.push(block),
+			Err(_) => break,
+		}
+	}
+	true
+}
+
+impl<B: Block> BlockImporter for ImportQueue<B> {
+	type Block = B;
+	type Error = Error;
+
+	fn import_block(&mut self, block: B) -> Result<(), Error> {
+		self.sender.try_send(block).map_err(|e| match e {
+			TrySendError::Full(_) => Error::Full,
+			TrySendError::Disconnected(_) => Error::Closed,
+		})
+	}
+}
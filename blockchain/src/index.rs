@@ -0,0 +1,187 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use beacon::primitives::H256;
+use beacon::types::BeaconBlockHeader;
+use blockchain::{Block, Auxiliary};
+use blockchain_rocksdb::{RocksBackend, RocksState as RocksStateT};
+use parity_codec::{Encode, Decode};
+use crate::{Error, parent_id_of};
+
+const PREFIX_SLOT: u8 = b's';
+const PREFIX_ROOT: u8 = b'r';
+const KEY_HEAD_SLOT: &[u8] = b"head_slot";
+
+/// Raw key-value storage for block indices.
+pub trait IndexStore {
+	/// Get the value at `key`.
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+	/// Atomically apply changes in order. `None` removes the key.
+	fn write(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<(), Error>;
+}
+
+/// In-memory index storage.
+#[derive(Default)]
+pub struct MemoryIndexStore(RwLock<HashMap<Vec<u8>, Vec<u8>>>);
+
+impl IndexStore for MemoryIndexStore {
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		Ok(self.0.read().expect("Lock is poisoned").get(key).cloned())
+	}
+
+	fn write(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<(), Error> {
+		let mut map = self.0.write().expect("Lock is poisoned");
+		for (key, value) in changes {
+			match value {
+				Some(value) => { map.insert(key, value); },
+				None => { map.remove(&key); },
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Index storage in the indices column of a RocksDB backend.
+pub struct RocksIndexStore<B: Block, A: Auxiliary<B>, S>(RocksBackend<B, A, S>);
+
+impl<B: Block, A: Auxiliary<B>, S> RocksIndexStore<B, A, S> {
+	pub fn new(backend: RocksBackend<B, A, S>) -> Self {
+		Self(backend)
+	}
+}
+
+impl<B: Block, A: Auxiliary<B>, S: RocksStateT> IndexStore for RocksIndexStore<B, A, S> where
+	B::Identifier: Encode + Decode,
+	B: Encode + Decode,
+	A: Encode + Decode,
+	A::Key: Encode + Decode,
+{
+	fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+		Ok(self.0.index(key)?)
+	}
+
+	fn write(&self, changes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> Result<(), Error> {
+		Ok(self.0.write_indices(changes)?)
+	}
+}
+
+fn slot_key(slot: u64) -> Vec<u8> {
+	let mut key = vec![PREFIX_SLOT];
+	key.extend_from_slice(&slot.to_be_bytes());
+	key
+}
+
+fn root_key(root: &H256) -> Vec<u8> {
+	let mut key = vec![PREFIX_ROOT];
+	key.extend_from_slice(root.as_ref());
+	key
+}
+
+fn decode<T: Decode>(raw: Option<Vec<u8>>) -> Result<Option<T>, Error> {
+	match raw {
+		Some(raw) => Ok(Some(
+			T::decode(&mut raw.as_ref()).ok_or(Error::Corrupted("block index"))?
+		)),
+		None => Ok(None),
+	}
+}
+
+/// Canonical block root at `slot`.
+pub fn canon_root(store: &dyn IndexStore, slot: u64) -> Result<Option<H256>, Error> {
+	decode(store.get(&slot_key(slot))?)
+}
+
+/// Stored header of the block at `root`.
+pub fn header(store: &dyn IndexStore, root: &H256) -> Result<Option<BeaconBlockHeader>, Error> {
+	decode(store.get(&root_key(root))?)
+}
+
+/// Store the header of the block at `root`.
+pub fn insert_header(
+	store: &dyn IndexStore,
+	root: &H256,
+	header: &BeaconBlockHeader,
+) -> Result<(), Error> {
+	store.write(vec![(root_key(root), Some(header.encode()))])
+}
+
+/// Store the headers of blocks, by root, at once.
+pub fn insert_headers(
+	store: &dyn IndexStore,
+	headers: &[(H256, BeaconBlockHeader)],
+) -> Result<(), Error> {
+	store.write(
+		headers.iter()
+			.map(|(root, header)| (root_key(root), Some(header.encode())))
+			.collect()
+	)
+}
+
+/// Update canonical slot mappings for a new head, walking back until the
+/// fork point with the previously indexed chain. Headers not yet stored are
+/// fetched with `fetch`. Returns the newly canonical blocks, in slot order.
+pub fn update_canon<F>(
+	store: &dyn IndexStore,
+	head: H256,
+	mut fetch: F,
+) -> Result<Vec<H256>, Error> where
+	F: FnMut(&H256) -> Result<BeaconBlockHeader, Error>,
+{
+	let mut changes = Vec::new();
+	let mut canon = Vec::new();
+	let mut fork_slot = None;
+	let mut current = Some(head);
+
+	while let Some(id) = current {
+		let header = match header(store, &id)? {
+			Some(header) => header,
+			None => {
+				let header = fetch(&id)?;
+				changes.push((root_key(&id), Some(header.encode())));
+				header
+			},
+		};
+
+		if canon_root(store, header.slot)? == Some(id) {
+			fork_slot = Some(header.slot);
+			break
+		}
+
+		canon.push((header.slot, id));
+		current = parent_id_of(header.parent_root);
+	}
+
+	// Remove mappings of the old canonical chain above the fork point.
+	if let Some(old_head_slot) = decode::<u64>(store.get(KEY_HEAD_SLOT)?)? {
+		let from = fork_slot.map(|slot| slot + 1).unwrap_or(0);
+		for slot in from..=old_head_slot {
+			changes.push((slot_key(slot), None));
+		}
+	}
+
+	let head_slot = canon.first().map(|(slot, _)| *slot).or(fork_slot);
+	for (slot, id) in &canon {
+		changes.push((slot_key(*slot), Some(id.encode())));
+	}
+	if let Some(head_slot) = head_slot {
+		changes.push((KEY_HEAD_SLOT.to_vec(), Some(head_slot.encode())));
+	}
+
+	store.write(changes)?;
+	Ok(canon.into_iter().rev().map(|(_, id)| id).collect())
+}
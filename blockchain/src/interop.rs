@@ -0,0 +1,118 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Deterministic interop validator keys.
+//!
+//! The secret key of validator `i` is the SHA-256 hash of `i` as a 32-byte
+//! little-endian integer, read as a little-endian integer and reduced modulo
+//! the BLS12-381 curve order. Every client derives the same keys, so
+//! interop testnets and local tests need no keystore files.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use beacon::primitives::ValidatorId;
+use crypto::bls;
+use sha2::{Digest, Sha256};
+
+/// Order of the BLS12-381 curve, as big-endian 64-bit limbs.
+const CURVE_ORDER: [u64; 4] = [
+	0x73eda753299d7d48, 0x3339d80809a1d805, 0x53bda402fffe5bfe, 0xffffffff00000001,
+];
+/// Length of a serialized secret key.
+const SECRET_KEY_BYTES: usize = 48;
+
+fn to_limbs(bytes: &[u8; 32]) -> [u64; 4] {
+	let mut limbs = [0u64; 4];
+	for (i, limb) in limbs.iter_mut().enumerate() {
+		let mut chunk = [0u8; 8];
+		chunk.copy_from_slice(&bytes[i * 8..(i + 1) * 8]);
+		*limb = u64::from_be_bytes(chunk);
+	}
+	limbs
+}
+
+fn sub_assign(value: &mut [u64; 4], rhs: &[u64; 4]) {
+	let mut borrow = false;
+	for i in (0..4).rev() {
+		let (result, borrow1) = value[i].overflowing_sub(rhs[i]);
+		let (result, borrow2) = result.overflowing_sub(borrow as u64);
+		value[i] = result;
+		borrow = borrow1 || borrow2;
+	}
+}
+
+/// Secret key bytes of interop validator `index`, big-endian.
+pub fn secret_key_bytes(index: u64) -> [u8; 32] {
+	let mut preimage = [0u8; 32];
+	preimage[..8].copy_from_slice(&index.to_le_bytes());
+	let hash = Sha256::digest(&preimage);
+
+	// The hash is little-endian, limbs are compared big-endian.
+	let mut big_endian = [0u8; 32];
+	for (i, byte) in hash.iter().enumerate() {
+		big_endian[31 - i] = *byte;
+	}
+	let mut value = to_limbs(&big_endian);
+	// 2^256 is less than three times the curve order.
+	while value >= CURVE_ORDER {
+		sub_assign(&mut value, &CURVE_ORDER);
+	}
+
+	let mut bytes = [0u8; 32];
+	for (i, limb) in value.iter().enumerate() {
+		bytes[i * 8..(i + 1) * 8].copy_from_slice(&limb.to_be_bytes());
+	}
+	bytes
+}
+
+/// Secret key of interop validator `index`.
+pub fn secret_key(index: u64) -> bls::Secret {
+	let mut bytes = vec![0; SECRET_KEY_BYTES - 32];
+	bytes.extend_from_slice(&secret_key_bytes(index));
+	bls::Secret::from_bytes(&bytes)
+		.expect("Value is reduced modulo the curve order; qed")
+}
+
+/// Public key of a secret key.
+pub fn public_key(secret: &bls::Secret) -> ValidatorId {
+	ValidatorId::from_slice(&bls::Public::from_secret_key(secret).as_bytes()[..])
+}
+
+/// Keys of the interop validators in `range`, by public key.
+pub fn keys(range: Range<u64>) -> HashMap<ValidatorId, bls::Secret> {
+	range.map(|index| {
+		let secret = secret_key(index);
+		(public_key(&secret), secret)
+	}).collect()
+}
+
+/// Parse a validator index range, given as `start..end` or as a count
+/// starting from zero.
+pub fn parse_range(value: &str) -> Result<Range<u64>, String> {
+	let parse = |v: &str| v.trim().parse::<u64>()
+		.map_err(|e| format!("Invalid validator index {}: {}", v, e));
+	match value.find("..") {
+		Some(pos) => {
+			let start = parse(&value[..pos])?;
+			let end = parse(&value[pos + 2..])?;
+			if start > end {
+				return Err(format!("Empty validator range {}", value))
+			}
+			Ok(start..end)
+		},
+		None => Ok(0..parse(value)?),
+	}
+}
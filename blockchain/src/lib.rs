@@ -16,29 +16,84 @@
 mod pool;
 pub mod backend;
 pub mod preset;
+pub mod eth1;
+pub mod eth1_endpoints;
+pub mod eth1_vote;
+pub mod eth1_follow;
+pub mod index;
+pub mod historical;
+pub mod datadir;
+pub mod import_queue;
+pub mod batch;
+pub mod future_blocks;
+pub mod clock_skew;
+pub mod prevalidation;
+pub mod duties;
+pub mod chain_info;
+pub mod state_cache;
+pub mod checkpoint_state;
+pub mod execution;
+pub mod validity;
+pub mod spec;
+pub mod checkpoint_sync;
+pub mod interop;
+pub mod trace;
+pub mod state_diff;
+pub mod difftest;
+pub mod api_auth;
+pub mod tls;
+pub mod validator_metrics;
+pub mod scheduler;
+pub mod migrate;
+pub mod state_root;
+pub mod era;
+pub mod regression;
+pub mod health;
+pub mod http;
+pub mod explorer;
+pub mod committees;
+pub mod compaction;
+pub mod operator;
+pub mod admin;
+pub mod shutdown;
+#[cfg(feature = "shards")]
+pub mod availability;
 
 pub use pool::AttestationPool;
-pub use shasper_runtime::{Block, StateExternalities};
+pub use shasper_runtime::{
+	Block, StateExternalities, StateRootCache, parent_id_of, is_genesis_root, SlotQuery, HeaderQuery, StoreIterators, ChainInfo, SharedChainInfo,
+};
 
 use beacon::primitives::H256;
 use beacon::types::*;
 use beacon::{Error as BeaconError, BeaconState, BeaconExecutive, Config,
-			 BLSConfig, Inherent, Transaction};
-use std::sync::Arc;
+			 BLSConfig, BLSBatchVerified, Inherent, Transaction, BlockLimits, TransactionKind};
+use std::collections::HashSet;
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
 use blockchain::{Block as BlockT, BlockExecutor, AsExternalities};
+use blockchain::backend::MemoryError;
 use lmd_ghost::JustifiableExecutor;
+use lmd_ghost::checkpoint::Checkpoint;
+use crate::checkpoint_state::SharedCheckpointStateCache;
+use crate::execution::{ExecutionHook, ExecutionError, PayloadStatus};
+use crate::trace::BlockTracing;
+use crate::state_root::FieldRootCache;
+use crate::chain_info::NetworkId;
 use core::marker::PhantomData;
+use bm_le::tree_root;
 
 use blockchain_rocksdb::RocksState as RocksStateT;
 
 #[derive(Clone)]
 pub struct MemoryState<C: Config> {
 	state: BeaconState<C>,
+	root: StateRootCache,
 }
 
 impl<C: Config> From<BeaconState<C>> for MemoryState<C> {
 	fn from(state: BeaconState<C>) -> Self {
-		Self { state }
+		Self { state, root: StateRootCache::default() }
 	}
 }
 
@@ -56,8 +111,14 @@ impl<C: Config> StateExternalities for MemoryState<C> {
 	}
 
 	fn state_mut(&mut self) -> &mut BeaconState<C> {
+		self.root.clear();
 		&mut self.state
 	}
+
+	fn state_root(&self) -> H256 {
+		let state = &self.state;
+		self.root.get_or_compute(|| tree_root::<C::Digest, _>(state))
+	}
 }
 
 impl<C: Config> AsExternalities<dyn StateExternalities<Config=C>> for MemoryState<C> {
@@ -69,11 +130,12 @@ impl<C: Config> AsExternalities<dyn StateExternalities<Config=C>> for MemoryStat
 #[derive(Clone)]
 pub struct RocksState<C: Config> {
 	state: BeaconState<C>,
+	root: StateRootCache,
 }
 
 impl<C: Config> From<BeaconState<C>> for RocksState<C> {
 	fn from(state: BeaconState<C>) -> Self {
-		Self { state }
+		Self { state, root: StateRootCache::default() }
 	}
 }
 
@@ -91,8 +153,14 @@ impl<C: Config> StateExternalities for RocksState<C> {
 	}
 
 	fn state_mut(&mut self) -> &mut BeaconState<C> {
+		self.root.clear();
 		&mut self.state
 	}
+
+	fn state_root(&self) -> H256 {
+		let state = &self.state;
+		self.root.get_or_compute(|| tree_root::<C::Digest, _>(state))
+	}
 }
 
 impl<C: Config> AsExternalities<dyn StateExternalities<Config=C>> for RocksState<C> {
@@ -105,7 +173,7 @@ impl<C: Config> RocksStateT for RocksState<C> {
 	type Raw = BeaconState<C>;
 
 	fn from_raw(state: BeaconState<C>, _db: Arc<::rocksdb::DB>) -> Self {
-		Self { state }
+		state.into()
 	}
 
 	fn into_raw(self) -> BeaconState<C> {
@@ -114,17 +182,75 @@ impl<C: Config> RocksStateT for RocksState<C> {
 }
 
 #[derive(Debug)]
+#[non_exhaustive]
+/// Blockchain errors.
 pub enum Error {
+	/// Beacon state transition failed.
 	Beacon(BeaconError),
+	/// In-memory storage failed.
+	Memory(MemoryError),
+	/// RocksDB storage failed.
+	Rocks(blockchain_rocksdb::Error),
+	/// Stored data could not be decoded.
+	Corrupted(&'static str),
+	/// Required data is not stored.
+	Missing(&'static str),
+	/// Slot is not covered by historical roots.
+	SlotNotCovered(u64),
+	/// Generated historical root proof does not verify.
+	InvalidProof,
+	/// Stored chain has a different genesis.
+	GenesisMismatch,
+	/// Store belongs to another network or config.
+	NetworkMismatch {
+		/// Network recorded in the store.
+		stored: NetworkId,
+		/// Network the node is started with.
+		expected: NetworkId,
+	},
+	/// Execution engine failed.
+	Execution(ExecutionError),
+	/// Execution engine rejected the block.
+	InvalidPayload,
+	/// Block has no parent but is not at the genesis slot, or the reverse.
+	InvalidParent,
 }
 
 impl std::fmt::Display for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{:?}", self)
+		match self {
+			Error::Beacon(e) => write!(f, "Beacon state transition failed: {}", e),
+			Error::Memory(e) => write!(f, "In-memory storage failed: {}", e),
+			Error::Rocks(e) => write!(f, "RocksDB storage failed: {}", e),
+			Error::Corrupted(what) => write!(f, "Corrupted {}", what),
+			Error::Missing(what) => write!(f, "Missing {}", what),
+			Error::SlotNotCovered(slot) => write!(f, "Slot {} is not covered by historical roots", slot),
+			Error::InvalidProof => write!(f, "Generated historical root proof is invalid"),
+			Error::GenesisMismatch => write!(f, "Stored chain has a different genesis"),
+			Error::NetworkMismatch { stored, expected } => write!(
+				f, "Database belongs to config {} with genesis validators root {:?}, \
+					but config {} with genesis validators root {:?} is selected",
+				stored.config_name, stored.genesis_validators_root,
+				expected.config_name, expected.genesis_validators_root,
+			),
+			Error::Execution(e) => write!(f, "{}", e),
+			Error::InvalidPayload => write!(f, "Execution engine rejected the block"),
+			Error::InvalidParent => write!(f, "Block parent does not match its slot"),
+		}
 	}
 }
 
-impl std::error::Error for Error { }
+impl StdError for Error {
+	fn source(&self) -> Option<&(dyn StdError + 'static)> {
+		match self {
+			Error::Beacon(e) => Some(e),
+			Error::Memory(e) => Some(e),
+			Error::Rocks(e) => Some(e),
+			Error::Execution(e) => Some(e),
+			_ => None,
+		}
+	}
+}
 
 impl From<BeaconError> for Error {
 	fn from(error: BeaconError) -> Error {
@@ -132,14 +258,111 @@ impl From<BeaconError> for Error {
 	}
 }
 
+impl From<MemoryError> for Error {
+	fn from(error: MemoryError) -> Error {
+		Error::Memory(error)
+	}
+}
+
+impl From<blockchain_rocksdb::Error> for Error {
+	fn from(error: blockchain_rocksdb::Error) -> Error {
+		Error::Rocks(error)
+	}
+}
+
+/// Convert a beacon checkpoint into a fork choice checkpoint, with `None`
+/// standing for genesis.
+fn fork_choice_checkpoint(checkpoint: &beacon::types::Checkpoint) -> Option<Checkpoint<H256>> {
+	if is_genesis_root(&checkpoint.root) {
+		None
+	} else {
+		Some(Checkpoint { epoch: checkpoint.epoch, root: checkpoint.root })
+	}
+}
+
+/// Blocks whose signatures have been verified in batch, shared between the
+/// batch verifier and the executor.
+pub type BatchVerified = Arc<Mutex<HashSet<H256>>>;
+
 #[derive(Clone)]
 pub struct Executor<C: Config, BLS: BLSConfig> {
+	batch_verified: BatchVerified,
+	limits: BlockLimits,
+	checkpoint_states: Option<SharedCheckpointStateCache<C>>,
+	execution: Option<ExecutionHook<C>>,
+	tracing: Option<Arc<BlockTracing>>,
+	root_cache: Option<Arc<Mutex<FieldRootCache<C>>>>,
 	_marker: PhantomData<(C, BLS)>,
 }
 
 impl<C: Config, BLS: BLSConfig> Executor<C, BLS> {
 	pub fn new() -> Self {
-		Self { _marker: PhantomData }
+		Self::with_batch_verified(Default::default())
+	}
+
+	/// Create an executor skipping proposer, randao and attestation signature
+	/// verification of blocks in `batch_verified`, once, as their proposer
+	/// signatures have been verified in batch.
+	pub fn with_batch_verified(batch_verified: BatchVerified) -> Self {
+		Self {
+			batch_verified,
+			limits: BlockLimits::default(),
+			checkpoint_states: None,
+			execution: None,
+			tracing: None,
+			root_cache: None,
+			_marker: PhantomData,
+		}
+	}
+
+	/// Limit transactions applied to built blocks.
+	pub fn with_limits(mut self, limits: BlockLimits) -> Self {
+		self.limits = limits;
+		self
+	}
+
+	/// Resolve justified validator sets against cached checkpoint states,
+	/// shared with gossip validation.
+	pub fn with_checkpoint_states(mut self, cache: SharedCheckpointStateCache<C>) -> Self {
+		self.checkpoint_states = Some(cache);
+		self
+	}
+
+	/// Invoke the execution engine when building and verifying blocks.
+	pub fn with_execution(mut self, execution: ExecutionHook<C>) -> Self {
+		self.execution = Some(execution);
+		self
+	}
+
+	/// Trace state transitions of selected or failing blocks.
+	pub fn with_tracing(mut self, tracing: BlockTracing) -> Self {
+		self.tracing = Some(Arc::new(tracing));
+		self
+	}
+
+	/// Reuse roots of unchanged large state fields between blocks finalized
+	/// by this executor, such as repeated attempts at building a block.
+	pub fn with_root_cache(mut self) -> Self {
+		self.root_cache = Some(Arc::new(Mutex::new(FieldRootCache::new())));
+		self
+	}
+
+	/// Number of transactions of `kind` that can still be applied to the
+	/// block, so that providers can stop early.
+	pub fn remaining(
+		&self,
+		block: &UnsealedBeaconBlock<C>,
+		state: &<Self as BlockExecutor>::Externalities,
+		kind: TransactionKind,
+	) -> usize {
+		match kind {
+			TransactionKind::Deposit => beacon::remaining_deposits(
+				&block.body,
+				state.state().eth1_data.deposit_count,
+				state.state().eth1_deposit_index,
+			),
+			kind => self.limits.remaining(&block.body, kind),
+		}
 	}
 
 	pub fn initialize_block(
@@ -165,7 +388,18 @@ impl<C: Config, BLS: BLSConfig> Executor<C, BLS> {
 		state: &mut <Self as BlockExecutor>::Externalities,
 		extrinsic: Transaction<C>,
 	) -> Result<(), Error> {
-		Ok(beacon::apply_transaction::<C, BLS>(block, state.state_mut(), extrinsic)?)
+		Ok(beacon::apply_transaction_with_limits::<C, BLS>(
+			block, state.state_mut(), extrinsic, &self.limits,
+		)?)
+	}
+
+	/// Ask the execution engine to fill in the execution part of the block,
+	/// before it is finalized.
+	pub fn apply_payload(&self, block: &mut UnsealedBeaconBlock<C>) -> Result<(), Error> {
+		match self.execution.as_ref() {
+			Some(execution) => execution.get_payload(block).map_err(Error::Execution),
+			None => Ok(()),
+		}
 	}
 
 	pub fn finalize_block(
@@ -173,7 +407,15 @@ impl<C: Config, BLS: BLSConfig> Executor<C, BLS> {
 		block: &mut UnsealedBeaconBlock<C>,
 		state: &mut <Self as BlockExecutor>::Externalities,
 	) -> Result<(), Error> {
-		Ok(beacon::finalize_block::<C, BLS>(block, state.state_mut())?)
+		match self.root_cache.as_ref() {
+			Some(cache) => {
+				let mut cache = cache.lock().expect("Lock is poisoned");
+				Ok(beacon::finalize_block_with::<C, BLS, _>(
+					block, state.state_mut(), |state| cache.state_root(state),
+				)?)
+			},
+			None => Ok(beacon::finalize_block::<C, BLS>(block, state.state_mut())?),
+		}
 	}
 }
 
@@ -187,7 +429,43 @@ impl<C: Config, BLS: BLSConfig> BlockExecutor for Executor<C, BLS> {
 		block: &Block<C>,
 		state: &mut Self::Externalities,
 	) -> Result<(), Error> {
-		Ok(beacon::execute_block::<C, BLS>(&block.0, state.state_mut())?)
+		if block.is_genesis() || !block.has_consistent_parent() {
+			return Err(Error::InvalidParent)
+		}
+
+		let id = block.id();
+		let batch_verified = self.batch_verified.lock().expect("Lock is poisoned")
+			.remove(&id);
+		let parent_state = match self.tracing.as_ref() {
+			Some(tracing) if tracing.may_trace(&id) => Some(state.state().clone()),
+			_ => None,
+		};
+
+		let result = if batch_verified {
+			beacon::execute_block::<C, BLSBatchVerified<C, BLS>>(&block.0, state.state_mut())
+		} else {
+			beacon::execute_block::<C, BLS>(&block.0, state.state_mut())
+		};
+
+		if let (Some(tracing), Some(mut parent_state)) = (self.tracing.as_ref(), parent_state) {
+			if tracing.should_trace(&id, result.is_err()) {
+				let trace = if batch_verified {
+					beacon::trace::trace_block::<C, BLSBatchVerified<C, BLS>>(&block.0, &mut parent_state)
+				} else {
+					beacon::trace::trace_block::<C, BLS>(&block.0, &mut parent_state)
+				};
+				tracing.write(&id, block.slot(), &trace);
+			}
+		}
+		result?;
+
+		if let Some(execution) = self.execution.as_ref() {
+			if execution.notify_new_payload(&block.0, block.id()) == PayloadStatus::Invalid {
+				return Err(Error::InvalidPayload)
+			}
+		}
+
+		Ok(())
 	}
 }
 
@@ -198,28 +476,82 @@ impl<C: Config, BLS: BLSConfig> JustifiableExecutor for Executor<C, BLS> {
 		&self,
 		state: &mut Self::Externalities,
 	) -> Result<Vec<Self::ValidatorIndex>, Self::Error> {
+		if let Some(cache) = self.checkpoint_states.as_ref() {
+			let checkpoint = state.state().current_justified_checkpoint.clone();
+			let mut cache = cache.lock().expect("Lock is poisoned");
+			if let Some(checkpoint_state) = cache.get(&checkpoint) {
+				return Ok(checkpoint_state.validators.iter()
+					.enumerate()
+					.filter(|(_, v)| v.is_active(checkpoint.epoch))
+					.map(|(i, _)| i as u64)
+					.collect())
+			}
+		}
+
 		let executive = BeaconExecutive::new(state.state_mut());
 		Ok(executive.justified_active_validators())
 	}
 
-	fn justified_block_id(
+	fn epoch_at_slot(
+		&self,
+		slot: u64,
+	) -> u64 {
+		beacon::utils::epoch_of_slot::<C>(slot)
+	}
+
+	fn checkpoints(
 		&self,
 		state: &mut Self::Externalities,
-	) -> Result<Option<<Self::Block as BlockT>::Identifier>, Self::Error> {
-		let justified_root = state.state().current_justified_checkpoint.root;
-		if justified_root == H256::default() {
-			Ok(None)
-		} else {
-			Ok(Some(justified_root))
-		}
+	) -> Result<(Option<Checkpoint<H256>>, Option<Checkpoint<H256>>), Self::Error> {
+		let state = state.state();
+		Ok((
+			fork_choice_checkpoint(&state.current_justified_checkpoint),
+			fork_choice_checkpoint(&state.finalized_checkpoint),
+		))
+	}
+
+	fn unrealized_checkpoints(
+		&self,
+		state: &mut Self::Externalities,
+	) -> Result<(Option<Checkpoint<H256>>, Option<Checkpoint<H256>>), Self::Error> {
+		let executive = BeaconExecutive::new(state.state_mut());
+		let (justified, finalized) = executive.unrealized_checkpoints()?;
+		Ok((
+			fork_choice_checkpoint(&justified),
+			fork_choice_checkpoint(&finalized),
+		))
 	}
 
 	fn votes(
 		&self,
 		block: &Self::Block,
 		state: &mut Self::Externalities,
-	) -> Result<Vec<(Self::ValidatorIndex, <Self::Block as BlockT>::Identifier)>, Self::Error> {
+	) -> Result<Vec<(u64, Self::ValidatorIndex, <Self::Block as BlockT>::Identifier)>, Self::Error> {
 		let executive = BeaconExecutive::new(state.state_mut());
 		Ok(executive.block_vote_targets(&block.0)?)
 	}
+
+	fn slot(
+		&self,
+		block: &Self::Block,
+	) -> u64 {
+		block.slot()
+	}
+
+	fn proposer_boost_score(
+		&self,
+		state: &mut Self::Externalities,
+	) -> Result<usize, Self::Error> {
+		let executive = BeaconExecutive::new(state.state_mut());
+		Ok(executive.proposer_boost_score() as usize)
+	}
+
+	fn equivocations(
+		&self,
+		block: &Self::Block,
+		state: &mut Self::Externalities,
+	) -> Result<Vec<Self::ValidatorIndex>, Self::Error> {
+		let executive = BeaconExecutive::new(state.state_mut());
+		Ok(executive.block_equivocating_validators(&block.0)?)
+	}
 }
@@ -13,113 +13,136 @@
 
 // You should have received a copy of the GNU General Public License along with
 // Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
-use beacon::{genesis_beacon_state, Config, Inherent, Transaction, BeaconExecutive};
+use beacon::{genesis_beacon_state, domain, Config, Inherent, Transaction, TransactionKind, BlockLimits, StateReader};
 use beacon::primitives::*;
 use beacon::types::*;
 use blockchain::{AsExternalities, Auxiliary, Block as BlockT};
 use blockchain::backend::{SharedMemoryBackend, SharedCommittable, ChainQuery, Store, ImportLock, Operation};
-use blockchain::import::{SharedBlockImporter, MutexImporter};
+use blockchain::import::SharedBlockImporter;
 use blockchain_rocksdb::RocksBackend;
-use shasper_blockchain::{Block, Executor, MemoryState, RocksState, Error, StateExternalities, AttestationPool};
+use shasper_blockchain::{Block, Executor, BatchVerified, MemoryState, RocksState, Error, StateExternalities, AttestationPool, SlotQuery, HeaderQuery, ChainInfo};
+use shasper_blockchain::batch::BatchVerifier;
+use shasper_blockchain::future_blocks::{FutureBlockQueue, MAXIMUM_GOSSIP_CLOCK_DISPARITY};
+use shasper_blockchain::clock_skew::{self, SharedClockSkew, DEFAULT_NTP_SERVER};
+use shasper_blockchain::prevalidation::{CommitteeCache, validate_shallow};
 use shasper_blockchain::preset::Preset;
-use shasper_blockchain::backend::ShasperBackend;
-use shasper_network::NetworkConfig;
-use lmd_ghost::archive::{ArchiveGhostImporter, AncestorQuery};
-use clap::{App, Arg, ArgMatches};
+use shasper_blockchain::backend::{ShasperBackend, RocksForkChoice};
+use shasper_blockchain::index::{RocksIndexStore, MemoryIndexStore};
+use shasper_blockchain::eth1::DepositTree;
+use shasper_blockchain::datadir::{DataDir, DEFAULT_MIN_FREE_SPACE_MB};
+use shasper_blockchain::state_cache::DEFAULT_STATE_CACHE_SIZE;
+use shasper_blockchain::checkpoint_state::{
+	self, CheckpointStateCache, SharedCheckpointStateCache, DEFAULT_CHECKPOINT_STATE_CACHE_SIZE,
+};
+use shasper_blockchain::import_queue::{ImportQueue, DEFAULT_QUEUE_CAPACITY};
+use shasper_blockchain::execution::ExecutionHook;
+use shasper_blockchain::validity::ValidityStore;
+use shasper_blockchain::spec::{self, ChainSpec, CustomConfig};
+use shasper_blockchain::checkpoint_sync::CheckpointProvider;
+use shasper_blockchain::interop;
+use shasper_blockchain::trace::BlockTracing;
+use shasper_blockchain::validator_metrics::ValidatorMetrics;
+use shasper_blockchain::scheduler::{Deadline, Duty, DutyScheduler, SlotOffset, SlotTiming};
+use shasper_blockchain::{state_diff, difftest, migrate, tls, era, regression, operator};
+use shasper_blockchain::api_auth::{self, ApiConfig};
+use shasper_blockchain::{admin, committees, shutdown};
+use shasper_blockchain::explorer::{self, ExplorerIndex};
+use shasper_blockchain::health::{self, HealthConfig, HealthReport, Probes, SharedHealth};
+use shasper_blockchain::compaction::{self, CompactionMetrics, SharedCompactionMetrics};
+use shasper_blockchain::eth1_endpoints::{self, Eth1Endpoint, Eth1Endpoints, Eth1Requirements};
+use shasper_blockchain::eth1_follow::{self, Eth1Follower};
+use shasper_blockchain::eth1_vote::{self, Eth1Chain, SharedEth1Chain};
+use shasper_network::{
+	NetworkConfig, NetworkHandle, PeerManager, Encoding, SyncState, SharedSyncState, ArrivalStats, Enr,
+	PublishStats, MeshStats, PubsubType, GossipSigning, BandwidthTracker, Quota,
+};
+use lmd_ghost::archive::{ArchiveGhostImporter, SharedArchiveGhostImporter, AncestorQuery};
+use lmd_ghost::clock::{SlotClock, SystemSlotClock};
+use lmd_ghost::persist::ForkChoicePersistence;
+use lmd_ghost::reorg::{ReorgStats, DEFAULT_REORG_ALERT_DEPTH};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use libp2p::Multiaddr;
 use std::thread;
 use std::str::FromStr;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::io::{BufReader, Read};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::net::{Ipv6Addr, SocketAddr};
+use std::time::Instant;
 use ssz::Decode;
+use rand::SeedableRng;
 use core::time::Duration;
 use core::convert::TryInto;
 use serde::{Serialize, Deserialize};
-use log::{info, warn, trace};
+use log::{info, warn, trace, debug, error};
 use bm_le::tree_root;
 use crypto::bls;
 
 type BLS = bls::BLSVerification;
 
-fn deposit_tree<C: Config>(deposits: &[DepositData]) -> Vec<Vec<H256>> {
-	let mut zerohashes = vec![H256::default()];
-	for layer in 1..32 {
-		zerohashes.push(C::hash(&[
-			zerohashes[layer - 1].as_ref(),
-			zerohashes[layer - 1].as_ref(),
-		]));
-	}
-
-	let mut values = deposits.iter().map(|d| {
-		tree_root::<C::Digest, _>(d)
-	}).collect::<Vec<_>>();
-	let values_len = values.len();
-	let mut tree = vec![values.clone()];
-
-	for h in 0..(beacon::consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize) {
-		if values.len() % 2 == 1 {
-			values.push(zerohashes[h]);
-		}
-		let mut new_values = Vec::new();
-		for i in 0..(values.len() / 2) {
-			new_values.push(C::hash(&[
-				values[i * 2].as_ref(),
-				values[i * 2 + 1].as_ref()
-			]));
-		}
-		values = new_values;
-		tree.push(values.clone());
-	}
-	assert!(values.len() == 1);
-	values.push({
-		let mut ret = values_len.to_le_bytes().to_vec();
-		while ret.len() < 32 {
-			ret.push(0);
-		}
-		H256::from_slice(&ret[..])
-	});
-	tree[32].push(values[1]);
-	tree.push(vec![C::hash(&[
-		values[0].as_ref(),
-		values[1].as_ref(),
-	])]);
-	assert!(tree.len() == 34);
-
-	tree
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ValidatorKey {
+	pub privkey: String,
+	pub pubkey: String,
 }
 
-fn deposit_root(tree: &Vec<Vec<H256>>) -> H256 {
-	tree.last().expect("Merkle tree cannot be empty; qed")[0]
+fn beacon_api(matches: &ArgMatches, url: &str) -> CheckpointProvider {
+	let provider = CheckpointProvider::new(url);
+	match matches.value_of("beacon-api-cert-pin") {
+		Some(pins) => provider.with_pinned_certs(
+			pins.split(',')
+				.map(|v| tls::parse_fingerprint(v).expect("Invalid certificate fingerprint"))
+				.collect()
+		),
+		None => provider,
+	}
 }
 
-fn deposit_proof<C: Config>(tree: &Vec<Vec<H256>>, item_index: usize) -> Vec<H256> {
-	let mut zerohashes = vec![H256::default()];
-	for layer in 1..32 {
-		zerohashes.push(C::hash(&[
-			zerohashes[layer - 1].as_ref(),
-			zerohashes[layer - 1].as_ref(),
-		]));
+fn parse_root(string: &str) -> Result<H256, String> {
+	let bytes = string_to_bytes(string)?;
+	if bytes.len() != 32 {
+		return Err(format!("Expected 32 bytes, got {}", bytes.len()))
 	}
+	Ok(H256::from_slice(&bytes))
+}
 
-	let mut proof = Vec::new();
-	for i in 0..(beacon::consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize) {
-		let subindex = (item_index / 2usize.pow(i as u32)) ^ 1;
-		if subindex < tree[i].len() {
-			proof.push(tree[i][subindex]);
-		} else {
-			proof.push(zerohashes[i]);
+/// Read validator secret keys from a YAML key file.
+fn read_validator_keys(path: &Path) -> Result<Vec<(ValidatorId, bls::Secret)>, String> {
+	const PRIVATE_KEY_BYTES: usize = 48;
+
+	let file = File::open(path).map_err(|e| format!("Opening key file {:?} failed: {}", path, e))?;
+	let coll = serde_yaml::from_reader::<_, Vec<ValidatorKey>>(BufReader::new(file))
+		.map_err(|e| format!("Parsing key file {:?} failed: {}", path, e))?;
+
+	let mut keys = Vec::new();
+	for key in coll {
+		let privkey = string_to_bytes(&key.privkey)?;
+		if privkey.len() > PRIVATE_KEY_BYTES {
+			return Err(format!("Secret key is longer than {} bytes", PRIVATE_KEY_BYTES))
 		}
+
+		let sk = {
+			let mut bytes = vec![0; PRIVATE_KEY_BYTES - privkey.len()];
+			bytes.extend_from_slice(&privkey);
+			bls::Secret::from_bytes(&bytes)
+				.map_err(|e| format!("Failed to decode bytes into secret key: {:?}", e))?
+		};
+
+		let pubkey = ValidatorId::from_slice(&bls::Public::from_secret_key(&sk).as_bytes()[..]);
+		keys.push((pubkey, sk));
 	}
-	proof.push(tree[32][1]);
-	proof
+	Ok(keys)
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
-struct ValidatorKey {
-	pub privkey: String,
-	pub pubkey: String,
+fn parse_pubkey(value: &str) -> Result<ValidatorId, String> {
+	let bytes = string_to_bytes(value)?;
+	if bytes.len() != 48 {
+		return Err(format!("Public key must be 48 bytes, got {}", bytes.len()))
+	}
+	Ok(ValidatorId::from_slice(&bytes))
 }
 
 fn string_to_bytes(string: &str) -> Result<Vec<u8>, String> {
@@ -132,6 +155,18 @@ fn string_to_bytes(string: &str) -> Result<Vec<u8>, String> {
     hex::decode(string).map_err(|e| format!("Unable to decode public or private key: {}", e))
 }
 
+fn parse_encodings(value: &str) -> Vec<Encoding> {
+	value.split(',')
+		.map(|v| Encoding::from_suffix(v).expect("Invalid encoding"))
+		.collect()
+}
+
+fn parse_gossip_types(value: &str) -> Vec<PubsubType> {
+	value.split(',')
+		.map(|v| PubsubType::from_topic_name(v).expect("Invalid gossip topic"))
+		.collect()
+}
+
 fn main() {
 	pretty_env_logger::init();
 
@@ -142,84 +177,860 @@ fn main() {
 			 .takes_value(true)
 			 .required(true)
 			 .help("Port to listen on"))
+		.arg(Arg::with_name("listen-v6")
+			 .long("listen-v6")
+			 .takes_value(true)
+			 .help("IPv6 address to additionally listen on"))
+		.arg(Arg::with_name("port-v6")
+			 .long("port-v6")
+			 .takes_value(true)
+			 .help("Port to listen on for IPv6"))
+		.arg(Arg::with_name("rpc-encodings")
+			 .long("rpc-encodings")
+			 .takes_value(true)
+			 .help("Comma-separated RPC encodings (ssz, ssz_snappy), in order of preference"))
+		.arg(Arg::with_name("gossip-encodings")
+			 .long("gossip-encodings")
+			 .takes_value(true)
+			 .help("Comma-separated gossip encodings (ssz, ssz_snappy) to subscribe and publish to"))
+		.arg(Arg::with_name("gossip-topics")
+			 .long("gossip-topics")
+			 .takes_value(true)
+			 .help("Comma-separated default gossip topics to subscribe to (beacon_block, beacon_attestation, voluntary_exit, proposer_slashing, attester_slashing), all by default"))
+		.arg(Arg::with_name("exclude-gossip-topics")
+			 .long("exclude-gossip-topics")
+			 .takes_value(true)
+			 .help("Comma-separated default gossip topics not to subscribe to"))
+		.arg(Arg::with_name("extra-topics")
+			 .long("extra-topics")
+			 .takes_value(true)
+			 .help("Comma-separated additional gossip topics to subscribe to"))
+		.arg(Arg::with_name("max-clock-disparity")
+			 .long("max-clock-disparity")
+			 .takes_value(true)
+			 .help("Maximum time in milliseconds a block may arrive before its slot, and be held until then"))
+		.arg(Arg::with_name("ntp-server")
+			 .long("ntp-server")
+			 .takes_value(true)
+			 .help("NTP server used to check the system clock for skew"))
+		.arg(Arg::with_name("no-ntp")
+			 .long("no-ntp")
+			 .help("Do not check the system clock against an NTP server"))
 		.arg(Arg::with_name("data")
 			 .short("d")
 			 .long("data")
 			 .takes_value(true)
-			 .help("Use rocksdb instead of in-memory database"))
+			 .help("Data directory; use rocksdb instead of in-memory database"))
+		.arg(Arg::with_name("min-free-space")
+			 .long("min-free-space")
+			 .takes_value(true)
+			 .help("Minimum free disk space in MB required to start"))
+		.arg(Arg::with_name("state-cache-size")
+			 .long("state-cache-size")
+			 .takes_value(true)
+			 .help("Memory budget in MB for recently used states"))
+		.arg(Arg::with_name("arrival-csv")
+			 .long("arrival-csv")
+			 .takes_value(true)
+			 .help("Write arrival delays of gossiped blocks and attestations to a CSV file"))
+		.arg(Arg::with_name("no-flood-publish")
+			 .long("no-flood-publish")
+			 .help("Publish own blocks to gossip mesh peers only, instead of all block topic peers"))
+		.arg(Arg::with_name("gossip-signing")
+			 .long("gossip-signing")
+			 .takes_value(true)
+			 .possible_values(&["anonymous", "signed"])
+			 .help("Gossip message signing policy, anonymous (StrictNoSign) by default"))
+		.arg(Arg::with_name("no-subnet-backbone")
+			 .long("no-subnet-backbone")
+			 .help("Do not stay subscribed to the attestation subnets derived from the node id"))
+		.arg(Arg::with_name("export-era")
+			 .long("export-era")
+			 .takes_value(true)
+			 .min_values(0)
+			 .help("Directory to export finalized eras of blocks and states into, as they are finalized, \
+					the freezer directory of --data if not given"))
+		.arg(Arg::with_name("import-era")
+			 .long("import-era")
+			 .takes_value(true)
+			 .help("Directory of era files to import blocks from on startup, before syncing the rest"))
+		.arg(Arg::with_name("upnp")
+			 .long("upnp")
+			 .help("Map network ports on the gateway using UPnP"))
+		.arg(Arg::with_name("min-outbound-peers")
+			 .long("min-outbound-peers")
+			 .takes_value(true)
+			 .help("Minimum number of dialed peers to keep, limiting inbound peers to the remaining slots, 3 by default"))
+		.arg(Arg::with_name("idle-timeout")
+			 .long("idle-timeout")
+			 .takes_value(true)
+			 .help("Seconds without RPC substreams after which a peer connection is closed, 30 by default"))
+		.arg(Arg::with_name("substream-timeout")
+			 .long("substream-timeout")
+			 .takes_value(true)
+			 .help("Seconds an RPC substream may make no progress before it is dropped, 10 by default"))
+		.arg(Arg::with_name("ping-interval")
+			 .long("ping-interval")
+			 .takes_value(true)
+			 .help("Seconds between pings sent to each peer, 20 by default"))
+		.arg(Arg::with_name("ping-timeout")
+			 .long("ping-timeout")
+			 .takes_value(true)
+			 .help("Seconds to wait for a ping response, 30 by default"))
+		.arg(Arg::with_name("ping-max-failures")
+			 .long("ping-max-failures")
+			 .takes_value(true)
+			 .help("Consecutive failed pings after which a peer is disconnected, 2 by default"))
 		.arg(Arg::with_name("libp2p-nodes")
 			 .long("libp2p-nodes")
 			 .takes_value(true)
 			 .help("Comma-separated libp2p nodes to initially connect to"))
+		.arg(Arg::with_name("trusted-peers")
+			 .long("trusted-peers")
+			 .takes_value(true)
+			 .help("Comma-separated multiaddrs or ENRs of trusted peers, redialed on disconnect and never banned"))
 		.arg(Arg::with_name("author")
 			 .long("author")
 			 .help("Whether to author blocks"))
+		.arg(Arg::with_name("max-block-attestations")
+			 .long("max-block-attestations")
+			 .takes_value(true)
+			 .help("Maximum attestations included in authored blocks, capped by the protocol maximum"))
 		.arg(Arg::with_name("genesis-state")
 			 .long("genesis-state")
 			 .takes_value(true)
 			 .help("Ssz raw genesis state file"))
+		.arg(Arg::with_name("genesis-state-url")
+			 .long("genesis-state-url")
+			 .takes_value(true)
+			 .conflicts_with("genesis-state")
+			 .help("Beacon API URL to download the genesis state from"))
+		.arg(Arg::with_name("genesis-state-root")
+			 .long("genesis-state-root")
+			 .takes_value(true)
+			 .help("Expected root of the genesis state"))
+		.arg(Arg::with_name("checkpoint-sync-url")
+			 .long("checkpoint-sync-url")
+			 .takes_value(true)
+			 .requires("checkpoint-state-root")
+			 .help("Trusted beacon API URL to download the finalized state from, initializing a new store from it"))
+		.arg(Arg::with_name("beacon-api-cert-pin")
+			 .long("beacon-api-cert-pin")
+			 .takes_value(true)
+			 .help("Comma separated SHA-256 fingerprints of accepted beacon API certificates, replacing CA validation"))
+		.arg(Arg::with_name("checkpoint-state-root")
+			 .long("checkpoint-state-root")
+			 .takes_value(true)
+			 .help("Expected root of the finalized state downloaded for checkpoint sync"))
 		.arg(Arg::with_name("validator-keys")
 			 .long("validator-keys")
 			 .takes_value(true)
 			 .help("Yaml validator keys"))
-		.arg(Arg::with_name("chain")
-			 .long("chain")
+		.arg(Arg::with_name("interop-validators")
+			 .long("interop-validators")
 			 .takes_value(true)
-			 .help("Preset chain to connect to"))
+			 .help("Number of interop validators in a locally generated genesis"))
+		.arg(Arg::with_name("interop-keys")
+			 .long("interop-keys")
+			 .takes_value(true)
+			 .help("Interop validator keys to sign with, as a count or a start..end range"))
+		.arg(Arg::with_name("network")
+			 .long("network")
+			 .alias("chain")
+			 .takes_value(true)
+			 .possible_values(&["sapphire", "mainnet", "pyrmont", "local"])
+			 .help("Named network to join, selecting its config, genesis and boot nodes"))
 		.arg(Arg::with_name("config")
 			 .long("config")
 			 .takes_value(true)
 			 .help("Config to use"))
+		.arg(Arg::with_name("chain-spec")
+			 .long("chain-spec")
+			 .takes_value(true)
+			 .help("Yaml chain spec overriding runtime constants of the config"))
+		.arg(Arg::with_name("archive")
+			 .long("archive")
+			 .help("Retain all historical block and state roots"))
+		.arg(Arg::with_name("slot-timing")
+			 .long("slot-timing")
+			 .takes_value(true)
+			 .help("Points within the slot to act at, as name=fraction overrides of proposal=0,attestation=1/3,aggregation=2/3,gossip-cutoff=1/3,build-deadline=1/6"))
+		.arg(Arg::with_name("reorg-alert-depth")
+			 .long("reorg-alert-depth")
+			 .takes_value(true)
+			 .help("Number of reverted blocks from which a reorg is warned about"))
+		.arg(Arg::with_name("metrics-file")
+			 .long("metrics-file")
+			 .takes_value(true)
+			 .help("File to write node metrics to in the Prometheus text format"))
+		.arg(Arg::with_name("validator-metrics-file")
+			 .long("validator-metrics-file")
+			 .takes_value(true)
+			 .help("File to write validator metrics to in the Prometheus text format"))
+		.arg(Arg::with_name("health-address")
+			 .long("health-address")
+			 .takes_value(true)
+			 .help("Address to serve the node health summary on, as JSON at /node/health, with probes at /health/ready and /health/live"))
+		.arg(Arg::with_name("api-address")
+			 .long("api-address")
+			 .takes_value(true)
+			 .help("Address to serve the read-only beacon API on, currently committees at /eth/v1/beacon/states/{state_id}/committees"))
+		.arg(Arg::with_name("admin-api-address")
+			 .long("admin-api-address")
+			 .takes_value(true)
+			 .help("Address to serve the admin API on, requiring the token in <data>/api-token, currently gossip topic health at /admin/gossip/topics"))
+		.arg(Arg::with_name("explorer-address")
+			 .long("explorer-address")
+			 .takes_value(true)
+			 .help("Index blocks by proposer and attestations by validator as they become canonical, and serve them at /explorer on this address"))
+		.arg(Arg::with_name("ready-sync-distance")
+			 .long("ready-sync-distance")
+			 .takes_value(true)
+			 .help("Maximum slots the head may lag behind the wall clock for the node to be ready"))
+		.arg(Arg::with_name("ready-min-peers")
+			 .long("ready-min-peers")
+			 .takes_value(true)
+			 .help("Minimum connected peers for the node to be ready"))
+		.arg(Arg::with_name("api-cors-origins")
+			 .long("api-cors-origins")
+			 .takes_value(true)
+			 .help("Comma separated origins allowed to access the HTTP APIs from browsers, or *"))
+		.arg(Arg::with_name("api-tls-cert")
+			 .long("api-tls-cert")
+			 .takes_value(true)
+			 .requires("api-tls-key")
+			 .help("PEM certificate chain to serve the HTTP APIs over HTTPS with, leaf first"))
+		.arg(Arg::with_name("api-tls-key")
+			 .long("api-tls-key")
+			 .takes_value(true)
+			 .requires("api-tls-cert")
+			 .help("PEM private key of the HTTP API certificate, in PKCS#8 or RSA format"))
+		.arg(Arg::with_name("eth1-endpoints")
+			 .long("eth1-endpoints")
+			 .takes_value(true)
+			 .help("Comma separated eth1 JSON-RPC endpoints, most preferred first, falling over to the next one on failure"))
+		.arg(Arg::with_name("eth1-chain-id")
+			 .long("eth1-chain-id")
+			 .takes_value(true)
+			 .help("Chain id eth1 endpoints must serve, defaults to the one of the chain spec or network"))
+		.arg(Arg::with_name("eth1-network-id")
+			 .long("eth1-network-id")
+			 .takes_value(true)
+			 .help("Network id eth1 endpoints must serve, defaults to the one of the chain spec"))
+		.arg(Arg::with_name("deposit-contract")
+			 .long("deposit-contract")
+			 .takes_value(true)
+			 .help("Address of the deposit contract, defaults to the one of the chain spec or network"))
+		.arg(Arg::with_name("deposit-contract-block")
+			 .long("deposit-contract-block")
+			 .takes_value(true)
+			 .help("Eth1 block the deposit contract was deployed at, where deposit log scanning starts, defaults to the one of the network"))
+		.arg(Arg::with_name("compact-interval")
+			 .long("compact-interval")
+			 .takes_value(true)
+			 .help("Hours between compactions of the database, no periodic compaction if not set"))
+		.arg(Arg::with_name("trace-block")
+			 .long("trace-block")
+			 .takes_value(true)
+			 .help("Comma separated block roots whose state transition is traced on import"))
+		.arg(Arg::with_name("trace-failed-blocks")
+			 .long("trace-failed-blocks")
+			 .help("Trace the state transition of blocks failing to import"))
+		.setting(AppSettings::SubcommandsNegateReqs)
+		.subcommand(SubCommand::with_name("import")
+			.about("Import blocks from era files into the data directory, without networking")
+			.arg(Arg::with_name("era-dir")
+				 .long("era-dir")
+				 .takes_value(true)
+				 .required(true)
+				 .help("Directory of era files")))
+		.subcommand(SubCommand::with_name("compact")
+			.about("Compact the database in the data directory, then exit"))
+		.subcommand(SubCommand::with_name("validator")
+			.about("Offline tools for validator operators")
+			.subcommand(SubCommand::with_name("exit")
+				.about("Sign a voluntary exit with a validator key, without running a node")
+				.arg(Arg::with_name("validator-keys")
+					 .long("validator-keys")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Key file of the validator, in the format of the node's --validator-keys"))
+				.arg(Arg::with_name("pubkey")
+					 .long("pubkey")
+					 .takes_value(true)
+					 .help("Public key of the validator to exit, required if the key file holds several keys"))
+				.arg(Arg::with_name("state")
+					 .long("state")
+					 .takes_value(true)
+					 .help("Ssz state to read the validator index, current epoch and fork from"))
+				.arg(Arg::with_name("validator-index")
+					 .long("validator-index")
+					 .takes_value(true)
+					 .required_unless("state")
+					 .help("Index of the validator, if no state is given"))
+				.arg(Arg::with_name("epoch")
+					 .long("epoch")
+					 .takes_value(true)
+					 .required_unless("state")
+					 .help("Epoch from which the exit is valid, defaults to the current epoch of the state"))
+				.arg(Arg::with_name("fork-version")
+					 .long("fork-version")
+					 .takes_value(true)
+					 .help("Hex fork version at the exit epoch if no state is given, defaults to the one of the network")))
+			.subcommand(SubCommand::with_name("credentials")
+				.about("Show the withdrawal credentials of validators in a state")
+				.arg(Arg::with_name("state")
+					 .long("state")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Ssz state to read validators from"))
+				.arg(Arg::with_name("index")
+					 .long("index")
+					 .takes_value(true)
+					 .help("Comma separated validator indices, all validators if not given"))
+				.arg(Arg::with_name("withdrawal-pubkey")
+					 .long("withdrawal-pubkey")
+					 .takes_value(true)
+					 .help("BLS withdrawal public key the credentials must commit to"))))
+		.subcommand(SubCommand::with_name("debug")
+			.about("Consensus debugging tools")
+			.setting(AppSettings::SubcommandRequiredElseHelp)
+			.subcommand(SubCommand::with_name("state-diff")
+				.about("Report field level differences between two ssz encoded states")
+				.arg(Arg::with_name("state-a")
+					 .required(true)
+					 .index(1)
+					 .help("First state"))
+				.arg(Arg::with_name("state-b")
+					 .required(true)
+					 .index(2)
+					 .help("Second state")))
+			.subcommand(SubCommand::with_name("transition")
+				.about("Run the state transition of blocks on a state, outside the node")
+				.arg(Arg::with_name("pre")
+					 .long("pre")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Ssz encoded pre-state"))
+				.arg(Arg::with_name("block")
+					 .long("block")
+					 .takes_value(true)
+					 .multiple(true)
+					 .number_of_values(1)
+					 .help("Ssz encoded block, applied in the order given"))
+				.arg(Arg::with_name("slot")
+					 .long("slot")
+					 .takes_value(true)
+					 .help("Process empty slots up to this slot after the blocks"))
+				.arg(Arg::with_name("post")
+					 .long("post")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Path to write the ssz encoded post-state to"))
+				.arg(Arg::with_name("no-verify-signatures")
+					 .long("no-verify-signatures")
+					 .help("Skip BLS signature verification")))
+			.subcommand(SubCommand::with_name("migrate-state")
+				.about("Re-serialize a state of the selected config into the containers of another config")
+				.arg(Arg::with_name("to")
+					 .long("to")
+					 .takes_value(true)
+					 .required(true)
+					 .possible_values(&["minimal", "mainnet", "sapphire"])
+					 .help("Config to migrate the state to"))
+				.arg(Arg::with_name("input")
+					 .required(true)
+					 .index(1)
+					 .help("Ssz encoded state"))
+				.arg(Arg::with_name("output")
+					 .required(true)
+					 .index(2)
+					 .help("Path to write the migrated state to")))
+			.subcommand(SubCommand::with_name("difftest")
+				.about("Run random blocks through our transition and a reference transition tool")
+				.arg(Arg::with_name("pre")
+					 .long("pre")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Ssz encoded state to build blocks on"))
+				.arg(Arg::with_name("reference")
+					 .long("reference")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Reference command, with {pre}, {block} and {post} replaced by file paths"))
+				.arg(Arg::with_name("iterations")
+					 .long("iterations")
+					 .takes_value(true)
+					 .default_value("1000")
+					 .help("Number of blocks to run"))
+				.arg(Arg::with_name("seed")
+					 .long("seed")
+					 .takes_value(true)
+					 .help("Seed of the block generator, random if not given"))
+				.arg(Arg::with_name("out")
+					 .long("out")
+					 .takes_value(true)
+					 .default_value("difftest")
+					 .help("Directory to save mismatching cases to")))
+			.subcommand(SubCommand::with_name("record-vectors")
+				.about("Record regression vectors of blocks crossing epoch boundaries in era files")
+				.arg(Arg::with_name("era-dir")
+					 .long("era-dir")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Directory of era files to replay"))
+				.arg(Arg::with_name("out")
+					 .long("out")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Directory to record vectors into, such as blockchain/res/regression/minimal"))
+				.arg(Arg::with_name("max")
+					 .long("max")
+					 .takes_value(true)
+					 .default_value("8")
+					 .help("Maximum number of vectors to record")))
+			.subcommand(SubCommand::with_name("committees")
+				.about("Dump the committee memberships of an epoch")
+				.arg(Arg::with_name("state")
+					 .long("state")
+					 .takes_value(true)
+					 .required(true)
+					 .help("Ssz encoded state"))
+				.arg(Arg::with_name("epoch")
+					 .long("epoch")
+					 .takes_value(true)
+					 .help("Epoch, at most one after the state epoch. Defaults to the state epoch"))
+				.arg(Arg::with_name("format")
+					 .long("format")
+					 .takes_value(true)
+					 .possible_values(&["csv", "json"])
+					 .default_value("csv")
+					 .help("Output format"))))
 		.get_matches();
 
-	let preset = matches.value_of("chain").map(|name| {
+	let preset = matches.value_of("network").map(|name| {
 		shasper_blockchain::preset::presets().get(&name)
-			.expect("Unknown preset").clone()
+			.expect("Unknown network").clone()
 	});
 
 	let config_name = matches.value_of("config")
-		.unwrap_or(if matches.value_of("chain") == Some("sapphire") {
-			"sapphire"
-		} else {
-			"minimal"
-		});
+		.or_else(|| preset.as_ref().map(|preset| preset.config))
+		.unwrap_or("minimal");
+	if let Some(preset) = preset.as_ref() {
+		if preset.config != config_name {
+			warn!("Network uses config {}, but {} is selected", preset.config, config_name);
+		}
+	}
+
+	let config_name = config_name.to_string();
+	let chain_spec = matches.value_of("chain-spec").map(PathBuf::from);
+
+	if let Some(validator) = matches.subcommand_matches("validator") {
+		let code = match config_name.as_str() {
+			"minimal" => validator_command::<beacon::MinimalConfig>(validator, preset.as_ref()),
+			"mainnet" => validator_command::<beacon::MainnetConfig>(validator, preset.as_ref()),
+			"sapphire" => validator_command::<beacon::SapphireConfig>(validator, preset.as_ref()),
+			e => panic!("Unknown config name: {:?}", e),
+		};
+		std::process::exit(code);
+	}
+
+	if let Some(debug) = matches.subcommand_matches("debug") {
+		let code = match config_name.as_str() {
+			"minimal" => debug_command::<beacon::MinimalConfig>(debug),
+			"mainnet" => debug_command::<beacon::MainnetConfig>(debug),
+			"sapphire" => debug_command::<beacon::SapphireConfig>(debug),
+			e => panic!("Unknown config name: {:?}", e),
+		};
+		std::process::exit(code);
+	}
 
 	info!("Using chain config: {}", config_name);
-	match config_name {
-		"minimal" => main_with_config::<beacon::MinimalConfig>(matches, preset),
-		"mainnet" => main_with_config::<beacon::MainnetConfig>(matches, preset),
-		"sapphire" => main_with_config::<beacon::SapphireConfig>(matches, preset),
+	match config_name.as_str() {
+		"minimal" => main_with_spec::<beacon::MinimalConfig>(matches, preset, &config_name, chain_spec),
+		"mainnet" => main_with_spec::<beacon::MainnetConfig>(matches, preset, &config_name, chain_spec),
+		"sapphire" => main_with_spec::<beacon::SapphireConfig>(matches, preset, &config_name, chain_spec),
 		e => panic!("Unknown config name: {:?}", e),
 	}
 }
 
-fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>) where
-	C: Unpin + Clone + Send + Sync + 'static,
-	Block<C>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
-{
-	let mut keys: HashMap<ValidatorId, bls::Secret> = HashMap::new();
+fn validator_command<C: Config>(matches: &ArgMatches, preset: Option<&Preset>) -> i32 {
+	if let Some(matches) = matches.subcommand_matches("exit") {
+		return match sign_exit::<C>(matches, preset) {
+			Ok(exit) => {
+				println!("{}", serde_json::to_string_pretty(&exit).expect("Exit always serializes; qed"));
+				0
+			},
+			Err(e) => {
+				error!("Signing voluntary exit failed: {}", e);
+				1
+			},
+		}
+	}
+	if let Some(matches) = matches.subcommand_matches("credentials") {
+		return match show_credentials::<C>(matches) {
+			Ok(true) => 0,
+			Ok(false) => 1,
+			Err(e) => {
+				error!("Reading withdrawal credentials failed: {}", e);
+				2
+			},
+		}
+	}
+	0
+}
+
+fn sign_exit<C: Config>(matches: &ArgMatches, preset: Option<&Preset>) -> Result<VoluntaryExit, String> {
+	let keys = read_validator_keys(Path::new(matches.value_of("validator-keys").expect("Required by clap")))?;
+	let (pubkey, secret) = match matches.value_of("pubkey") {
+		Some(pubkey) => {
+			let pubkey = parse_pubkey(pubkey)?;
+			keys.into_iter().find(|(key, _)| key == &pubkey)
+				.ok_or_else(|| format!("No key for {:?} in the key file", pubkey))?
+		},
+		None if keys.len() == 1 => keys.into_iter().next().expect("Checked length"),
+		None => return Err(format!("Key file holds {} keys, select one with --pubkey", keys.len())),
+	};
+	let state = match matches.value_of("state") {
+		Some(path) => Some(state_diff::read_state::<C, _>(path).map_err(|e| e.to_string())?),
+		None => None,
+	};
+
+	let epoch = match (matches.value_of("epoch"), state.as_ref()) {
+		(Some(epoch), _) => u64::from_str(epoch).map_err(|e| format!("Invalid epoch: {}", e))?,
+		(None, Some(state)) => state.slot / C::slots_per_epoch(),
+		(None, None) => unreachable!("Required by clap"),
+	};
+	let validator_index = match (matches.value_of("validator-index"), state.as_ref()) {
+		(Some(index), _) => u64::from_str(index).map_err(|e| format!("Invalid validator index: {}", e))?,
+		(None, Some(state)) => operator::validator_index(state, &pubkey)
+			.ok_or_else(|| format!("Validator {:?} is not in the state", pubkey))?,
+		(None, None) => unreachable!("Required by clap"),
+	};
+	if let Some(state) = state.as_ref() {
+		match state.validators.get(validator_index as usize) {
+			Some(validator) if validator.pubkey == pubkey => (),
+			_ => return Err(format!("Validator {} does not have key {:?}", validator_index, pubkey)),
+		}
+	}
+	let fork = match (matches.value_of("fork-version"), state.as_ref()) {
+		(None, Some(state)) => state.fork.clone(),
+		(version, _) => {
+			let version = match version {
+				Some(version) => {
+					let bytes = string_to_bytes(version)?;
+					if bytes.len() != 4 {
+						return Err("Fork version must be 4 bytes".to_string())
+					}
+					let mut version = [0u8; 4];
+					version.copy_from_slice(&bytes);
+					Version::from(version)
+				},
+				None => preset.and_then(|preset| preset.fork_version(epoch))
+					.ok_or_else(|| "Fork version of the network is unknown, set --fork-version".to_string())?,
+			};
+			Fork { previous_version: version, current_version: version, epoch: 0 }
+		},
+	};
+
+	let exit = operator::sign_voluntary_exit::<C>(&secret, validator_index, epoch, &fork);
+	if !operator::verify_voluntary_exit::<C>(&exit, &pubkey, &fork) {
+		return Err("Signed exit does not verify".to_string())
+	}
+	Ok(exit)
+}
+
+fn show_credentials<C: Config>(matches: &ArgMatches) -> Result<bool, String> {
+	let state = state_diff::read_state::<C, _>(matches.value_of("state").expect("Required by clap"))
+		.map_err(|e| e.to_string())?;
+	let indices = match matches.value_of("index") {
+		Some(indices) => Some(indices.split(',')
+			.map(|index| u64::from_str(index).map_err(|e| format!("Invalid index {}: {}", index, e)))
+			.collect::<Result<Vec<_>, _>>()?),
+		None => None,
+	};
+	let withdrawal_pubkey = match matches.value_of("withdrawal-pubkey") {
+		Some(pubkey) => Some(parse_pubkey(pubkey)?),
+		None => None,
+	};
+
+	let mut all_match = true;
+	for credentials in operator::validator_credentials(&state, indices.as_ref().map(|i| &i[..])) {
+		match withdrawal_pubkey.as_ref() {
+			Some(pubkey) => {
+				let matches = credentials.matches::<C>(pubkey);
+				all_match &= matches;
+				println!("{} {}", credentials, if matches { "matches" } else { "DOES NOT MATCH" });
+			},
+			None => println!("{}", credentials),
+		}
+	}
+	Ok(all_match)
+}
+
+fn debug_command<C: Config>(matches: &ArgMatches) -> i32 {
+	if let Some(matches) = matches.subcommand_matches("state-diff") {
+		let path_a = matches.value_of("state-a").expect("Required by clap");
+		let path_b = matches.value_of("state-b").expect("Required by clap");
+		let (a, b) = match (state_diff::read_state::<C, _>(path_a), state_diff::read_state::<C, _>(path_b)) {
+			(Ok(a), Ok(b)) => (a, b),
+			(Err(e), _) | (_, Err(e)) => {
+				error!("Loading states failed: {}", e);
+				return 2
+			},
+		};
+
+		println!("{}: state root {:?}", path_a, tree_root::<C::Digest, _>(&a));
+		println!("{}: state root {:?}", path_b, tree_root::<C::Digest, _>(&b));
+		let diffs = state_diff::diff_states(&a, &b);
+		for diff in &diffs {
+			println!("{}", diff);
+		}
+		if diffs.is_empty() {
+			println!("States are equal");
+			return 0
+		}
+		return 1
+	}
+	if let Some(matches) = matches.subcommand_matches("migrate-state") {
+		return match matches.value_of("to").expect("Required by clap") {
+			"minimal" => migrate_state::<C, beacon::MinimalConfig>(matches),
+			"mainnet" => migrate_state::<C, beacon::MainnetConfig>(matches),
+			"sapphire" => migrate_state::<C, beacon::SapphireConfig>(matches),
+			e => panic!("Unknown config name: {:?}", e),
+		}
+	}
+	if let Some(matches) = matches.subcommand_matches("difftest") {
+		return match difftest::<C>(matches) {
+			Ok(report) => {
+				for case in &report.mismatches {
+					println!("Mismatch saved to {:?}", case);
+				}
+				if report.mismatches.is_empty() { 0 } else { 1 }
+			},
+			Err(e) => {
+				error!("Differential testing failed: {}", e);
+				2
+			},
+		}
+	}
+	if let Some(matches) = matches.subcommand_matches("record-vectors") {
+		let era_dir = Path::new(matches.value_of("era-dir").expect("Required by clap"));
+		let out = Path::new(matches.value_of("out").expect("Required by clap"));
+		let max = match usize::from_str(matches.value_of("max").expect("Has default")) {
+			Ok(max) => max,
+			Err(e) => {
+				error!("Invalid max: {}", e);
+				return 2
+			},
+		};
+		return match regression::record_from_eras::<C, BLS>(era_dir, out, max) {
+			Ok(recorded) => {
+				println!("Recorded {} regression vectors into {:?}", recorded, out);
+				0
+			},
+			Err(e) => {
+				error!("Recording regression vectors failed: {}", e);
+				1
+			},
+		}
+	}
+	if let Some(matches) = matches.subcommand_matches("committees") {
+		return match dump_committees::<C>(matches) {
+			Ok(output) => {
+				print!("{}", output);
+				0
+			},
+			Err(e) => {
+				error!("Dumping committees failed: {}", e);
+				1
+			},
+		}
+	}
+	if let Some(matches) = matches.subcommand_matches("transition") {
+		return match transition::<C>(matches) {
+			Ok(root) => {
+				println!("Post-state root {:?}", root);
+				0
+			},
+			Err(e) => {
+				error!("Transition failed: {}", e);
+				1
+			},
+		}
+	}
+	0
+}
+
+fn dump_committees<C: Config>(matches: &ArgMatches) -> Result<String, String> {
+	let state = state_diff::read_state::<C, _>(matches.value_of("state").expect("Required by clap"))
+		.map_err(|e| e.to_string())?;
+	let epoch = match matches.value_of("epoch") {
+		Some(epoch) => u64::from_str(epoch).map_err(|e| format!("Invalid epoch: {}", e))?,
+		None => beacon::utils::epoch_of_slot::<C>(state.slot),
+	};
+	let committees = committees::epoch_committees(&state, epoch)
+		.map_err(|e| format!("Committees of epoch {} are not available: {:?}", epoch, e))?;
+	Ok(match matches.value_of("format").expect("Has default") {
+		"json" => committees::to_json(&committees) + "\n",
+		_ => committees::to_csv(&committees),
+	})
+}
+
+fn migrate_state<A: Config, B: Config>(matches: &ArgMatches) -> i32 {
+	let input = matches.value_of("input").expect("Required by clap");
+	let output = matches.value_of("output").expect("Required by clap");
+	let state = match state_diff::read_state::<A, _>(input) {
+		Ok(state) => state,
+		Err(e) => {
+			error!("Loading state failed: {}", e);
+			return 2
+		},
+	};
+
+	match migrate::migrate_state::<A, B>(&state) {
+		Ok((migrated, notes)) => {
+			for note in &notes {
+				warn!("{}", note);
+			}
+			if let Err(e) = std::fs::write(output, ssz::Encode::encode(&migrated)) {
+				error!("Writing migrated state to {} failed: {}", output, e);
+				return 2
+			}
+			println!("Migrated state root {:?}", tree_root::<B::Digest, _>(&migrated));
+			0
+		},
+		Err(incompatibilities) => {
+			for incompatibility in &incompatibilities {
+				println!("Incompatible: {}", incompatibility);
+			}
+			1
+		},
+	}
+}
 
-	if let Some(validator_keys) = matches.value_of("validator-keys") {
-		const PRIVATE_KEY_BYTES: usize = 48;
-		const PUBLIC_KEY_BYTES: usize = 48;
+fn difftest<C: Config>(matches: &ArgMatches) -> Result<difftest::Report, String> {
+	let pre = matches.value_of("pre").expect("Required by clap");
+	let state = state_diff::read_state::<C, _>(pre).map_err(|e| e.to_string())?;
+	let reference = difftest::Reference::new(matches.value_of("reference").expect("Required by clap"));
+	let iterations = usize::from_str(matches.value_of("iterations").expect("Has default"))
+		.map_err(|e| format!("Invalid iterations: {}", e))?;
+	let seed = match matches.value_of("seed") {
+		Some(seed) => u64::from_str(seed).map_err(|e| format!("Invalid seed: {}", e))?,
+		None => rand::random(),
+	};
+	info!("Differential testing with seed {}", seed);
 
-		let file = File::open(validator_keys).unwrap();
-		let coll = serde_yaml::from_reader::<_, Vec<ValidatorKey>>(BufReader::new(file)).unwrap();
+	let out = PathBuf::from(matches.value_of("out").expect("Has default"));
+	let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+	difftest::DiffTest::new(reference, out, state)
+		.run(&mut rng, iterations)
+		.map_err(|e| e.to_string())
+}
 
-		for key in coll {
-			let privkey = string_to_bytes(&key.privkey).unwrap();
+fn transition<C: Config>(matches: &ArgMatches) -> Result<H256, String> {
+	let pre = matches.value_of("pre").expect("Required by clap");
+	let mut state = state_diff::read_state::<C, _>(pre).map_err(|e| e.to_string())?;
+
+	for path in matches.values_of("block").into_iter().flatten() {
+		let data = std::fs::read(path).map_err(|e| format!("Reading block {} failed: {}", path, e))?;
+		let block = BeaconBlock::<C>::decode(&data[..])
+			.map_err(|e| format!("Decoding block {} failed: {:?}", path, e))?;
+		let result = if matches.is_present("no-verify-signatures") {
+			beacon::execute_block::<C, beacon::BLSNoVerification>(&block, &mut state)
+		} else {
+			beacon::execute_block::<C, BLS>(&block, &mut state)
+		};
+		result.map_err(|e| format!("Block {} is invalid: {:?}", path, e))?;
+	}
+
+	if let Some(slot) = matches.value_of("slot") {
+		let slot = u64::from_str(slot).map_err(|e| format!("Invalid slot: {}", e))?;
+		beacon::BeaconExecutive::new(&mut state).process_slots(slot)
+			.map_err(|e| format!("Processing slots failed: {:?}", e))?;
+	}
 
-			let sk = {
-				let mut bytes = vec![0; PRIVATE_KEY_BYTES - privkey.len()];
-				bytes.extend_from_slice(&privkey);
-				bls::Secret::from_bytes(&bytes)
-					.map_err(|e| format!("Failed to decode bytes into secret key: {:?}", e))
-					.unwrap()
+	let post = matches.value_of("post").expect("Required by clap");
+	std::fs::write(post, ssz::Encode::encode(&state))
+		.map_err(|e| format!("Writing post-state to {} failed: {}", post, e))?;
+	Ok(tree_root::<C::Digest, _>(&state))
+}
+
+fn main_with_spec<B: Config>(
+	matches: ArgMatches,
+	preset: Option<Preset>,
+	config_name: &str,
+	chain_spec: Option<PathBuf>,
+) where
+	B: Unpin + Clone + Send + Sync + 'static,
+	Block<B>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
+	Block<CustomConfig<B>>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
+{
+	match chain_spec {
+		Some(path) => {
+			let spec = match ChainSpec::from_file::<B, _>(&path, config_name) {
+				Ok(spec) => spec,
+				Err(e) => {
+					error!("Loading chain spec {:?} failed: {}", path, e);
+					std::process::exit(1);
+				},
 			};
+			// A spec only restating the preset keeps its compile-time
+			// constants, which the state transition reads in hot loops.
+			let custom = !spec.matches_config::<B>();
+			if custom {
+				info!("Using chain spec {:?} on top of {}", path, config_name);
+			} else {
+				info!("Chain spec {:?} matches {}, using compiled constants", path, config_name);
+			}
+			spec::install(spec).expect("Chain spec is installed only once");
+			if custom {
+				main_with_config::<CustomConfig<B>>(matches, preset, config_name)
+			} else {
+				main_with_config::<B>(matches, preset, config_name)
+			}
+		},
+		None => main_with_config::<B>(matches, preset, config_name),
+	}
+}
+
+fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>, config_name: &str) where
+	C: Unpin + Clone + Send + Sync + 'static,
+	Block<C>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
+{
+	let data_dir = matches.value_of("data").map(DataDir::new);
+	if let Some(data_dir) = data_dir.as_ref() {
+		let min_free_space = matches.value_of("min-free-space")
+			.map(|v| u64::from_str(v).expect("Invalid minimum free space"))
+			.unwrap_or(DEFAULT_MIN_FREE_SPACE_MB);
+		if let Err(e) = data_dir.prepare(min_free_space * 1024 * 1024) {
+			error!("Refusing to start with data directory {:?}: {}", data_dir.root(), e);
+			std::process::exit(1);
+		}
+	}
 
-			let pubkey = ValidatorId::from_slice(&bls::Public::from_secret_key(&sk).as_bytes()[..]);
+	let mut keys: HashMap<ValidatorId, bls::Secret> = HashMap::new();
 
-			keys.insert(pubkey, sk);
+	let validator_keys = matches.value_of("validator-keys").map(PathBuf::from).or_else(|| {
+		data_dir.as_ref()
+			.map(|data_dir| data_dir.validators().join("keys.yaml"))
+			.filter(|path| path.exists())
+	});
+	let interop_validators = matches.value_of("interop-validators")
+		.map(|v| u64::from_str(v).expect("Invalid interop validator count"));
+	if let Some(range) = matches.value_of("interop-keys")
+		.map(|v| interop::parse_range(v).expect("Invalid interop key range"))
+	{
+		info!("Using interop keys of validators {:?}", range);
+		keys.extend(interop::keys(range));
+	}
+	if let Some(validator_keys) = validator_keys {
+		match read_validator_keys(&validator_keys) {
+			Ok(validator_keys) => keys.extend(validator_keys),
+			Err(e) => {
+				error!("{}", e);
+				std::process::exit(1);
+			},
 		}
 	}
 
@@ -229,12 +1040,40 @@ fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>) wher
 		file.read_to_end(&mut data).unwrap();
 
 		Decode::decode(&mut &data[..]).unwrap()
-	} else if let Some(preset) = preset.as_ref() {
-		Decode::decode(&mut &preset.genesis_state).unwrap()
+	} else if let Some(url) = matches.value_of("genesis-state-url").or_else(|| {
+		// Genesis is needed for the chain identity even when syncing from a
+		// checkpoint, and is taken from the same provider if not bundled.
+		match preset.as_ref().and_then(|preset| preset.genesis_state.as_ref()) {
+			Some(_) => None,
+			None => matches.value_of("checkpoint-sync-url"),
+		}
+	}) {
+		info!("Downloading genesis state from {}", url);
+		let expected_root = matches.value_of("genesis-state-root")
+			.map(|v| parse_root(v).expect("Invalid genesis state root"))
+			.or_else(|| preset.as_ref().and_then(|preset| preset.genesis_state_root));
+		if expected_root.is_none() {
+			warn!("No expected genesis state root given, trusting the provider");
+		}
+		match beacon_api(&matches, url).state::<C>("genesis", expected_root) {
+			Ok(state) => state,
+			Err(e) => {
+				error!("Downloading genesis state failed: {}", e);
+				std::process::exit(1);
+			},
+		}
+	} else if let Some(genesis_state) = preset.as_ref().and_then(|preset| preset.genesis_state.as_ref()) {
+		Decode::decode(&mut &genesis_state[..]).unwrap()
+	} else if preset.as_ref().map(|preset| preset.config != "minimal").unwrap_or(false) {
+		error!("Network has no bundled genesis state, pass one with --genesis-state");
+		std::process::exit(1);
 	} else {
 		let mut deposit_datas = Vec::new();
-		for i in 0..10 {
-			let seckey = bls::Secret::random(&mut rand::thread_rng());
+		for i in 0..interop_validators.unwrap_or(10) {
+			let seckey = match interop_validators {
+				Some(_) => interop::secret_key(i),
+				None => bls::Secret::random(&mut rand::thread_rng()),
+			};
 			let pubkey = ValidatorId::from_slice(&bls::Public::from_secret_key(&seckey).as_bytes()[..]);
 			let mut data = DepositData {
 				pubkey: pubkey.clone(),
@@ -243,26 +1082,33 @@ fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>) wher
 				signature: Default::default(),
 			};
 			let signature = Signature::from_slice(&bls::Signature::new(
-				&tree_root::<sha2::Sha256, _>(&SigningDepositData::from(data.clone()))[..],
-				beacon::genesis_domain(C::domain_deposit()),
+				&domain::deposit_signing_root::<C>(&data)[..],
+				domain::genesis_domain(C::domain_deposit()),
 				&seckey
 			).as_bytes()[..]);
 			data.signature = signature;
 			deposit_datas.push(data);
-			keys.insert(pubkey, seckey);
+			// With --interop-keys, only the selected interop validators sign.
+			if interop_validators.is_none() || matches.value_of("interop-keys").is_none() {
+				keys.insert(pubkey, seckey);
+			}
 		}
 
-		let deposit_tree = deposit_tree::<C>(&deposit_datas);
+		let mut deposit_tree = DepositTree::<C>::new();
+		for deposit_data in &deposit_datas {
+			deposit_tree.push(deposit_data).unwrap();
+		}
+		let proofs = deposit_tree.proofs(deposit_tree.deposit_count()).unwrap();
 		let deposits = deposit_datas.clone().into_iter()
-			.enumerate()
-			.map(|(i, deposit_data)| {
+			.zip(proofs)
+			.map(|(deposit_data, proof)| {
 				Deposit {
-					proof: deposit_proof::<C>(&deposit_tree, i).try_into().ok().unwrap(),
+					proof: proof.try_into().ok().unwrap(),
 					data: deposit_data,
 				}
 			})
 			.collect::<Vec<_>>();
-		let deposit_root = deposit_root(&deposit_tree);
+		let deposit_root = deposit_tree.root().unwrap();
 		let eth1_data = Eth1Data {
 			deposit_root,
 			deposit_count: deposits.len() as u64,
@@ -275,15 +1121,249 @@ fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>) wher
 
 		genesis_state
 	};
-	let genesis_block = Block(BeaconBlock {
-		state_root: tree_root::<<C as Config>::Digest, _>(&genesis_state),
+	let genesis_block = Block::genesis(&genesis_state);
+	let genesis_info = ChainInfo::from_genesis(&genesis_state);
+	if let Some(expected) = matches.value_of("genesis-state-root")
+		.map(|v| parse_root(v).expect("Invalid genesis state root"))
+		.or_else(|| preset.as_ref().and_then(|preset| preset.genesis_state_root))
+	{
+		if genesis_block.state_root() != expected {
+			error!("Genesis state root {} does not match expected genesis {}",
+				   genesis_block.state_root(), expected);
+			std::process::exit(1);
+		}
+	}
+	if let Some(preset) = preset.as_ref() {
+		if let Some(expected) = preset.fork_version(0) {
+			if genesis_state.fork.current_version != expected {
+				error!("Genesis fork version {} does not match network fork version {}",
+					   genesis_state.fork.current_version, expected);
+				std::process::exit(1);
+			}
+		}
+	}
+
+	let (anchor_block, anchor_state) = if let Some(url) = matches.value_of("checkpoint-sync-url") {
+		let expected_root = parse_root(matches.value_of("checkpoint-state-root").expect("Required by clap"))
+			.expect("Invalid checkpoint state root");
+		info!("Downloading finalized state {} from {}", expected_root, url);
+		match beacon_api(&matches, url).anchor::<C>("finalized", expected_root) {
+			Ok((block, state)) => {
+				info!("Checkpoint sync anchored at slot {}, block {}", state.slot, block.id());
+				(block, state)
+			},
+			Err(e) => {
+				error!("Checkpoint sync failed: {}", e);
+				std::process::exit(1);
+			},
+		}
+	} else {
+		(genesis_block, genesis_state)
+	};
+	if !anchor_block.has_consistent_parent() {
+		error!("Anchor block {} at slot {} has an inconsistent parent {}",
+			   anchor_block.id(), anchor_block.slot(), anchor_block.parent_root());
+		std::process::exit(1);
+	}
+
+	let max_clock_disparity = matches.value_of("max-clock-disparity")
+		.map(|v| Duration::from_millis(u64::from_str(v).expect("Invalid maximum clock disparity")))
+		.unwrap_or(MAXIMUM_GOSSIP_CLOCK_DISPARITY);
+	let state_cache_size = matches.value_of("state-cache-size")
+		.map(|v| usize::from_str(v).expect("Invalid state cache size") * 1024 * 1024)
+		.unwrap_or(DEFAULT_STATE_CACHE_SIZE);
+	let arrival_csv = matches.value_of("arrival-csv").map(PathBuf::from);
+	let export_era = match matches.value_of("export-era") {
+		Some(dir) => Some(PathBuf::from(dir)),
+		None if matches.is_present("export-era") => Some(
+			data_dir.as_ref().expect("--export-era without a directory requires --data").freezer()
+		),
+		None => None,
+	};
+	let import_era = matches.value_of("import-era").map(PathBuf::from);
+	let import_command_dir = matches.subcommand_matches("import")
+		.map(|matches| PathBuf::from(matches.value_of("era-dir").expect("Required by clap")));
+	let mut block_limits = BlockLimits::default();
+	if let Some(max) = matches.value_of("max-block-attestations") {
+		block_limits.attestations = usize::from_str(max).expect("Invalid maximum block attestations");
+	}
+	let ntp_server = if matches.is_present("no-ntp") {
+		None
+	} else {
+		Some(matches.value_of("ntp-server").unwrap_or(DEFAULT_NTP_SERVER).to_string())
+	};
+	let validator_metrics_file = matches.value_of("validator-metrics-file").map(PathBuf::from);
+	let node_metrics_file = matches.value_of("metrics-file").map(PathBuf::from);
+	let api_tls = match (matches.value_of("api-tls-cert"), matches.value_of("api-tls-key")) {
+		(Some(cert), Some(key)) => Some(tls::ServerTls { cert: PathBuf::from(cert), key: PathBuf::from(key) }),
+		_ => None,
+	};
+	let health = matches.value_of("health-address").map(|address| HealthConfig {
+		api: ApiConfig {
+			public_address: Some(SocketAddr::from_str(address).expect("Invalid health address")),
+			admin_address: None,
+			cors_origins: matches.value_of("api-cors-origins")
+				.map(api_auth::parse_cors_origins)
+				.unwrap_or_default(),
+			tls: api_tls.clone(),
+			..Default::default()
+		},
+		data_dir: data_dir.clone(),
+		probes: Probes {
+			ready_sync_distance: matches.value_of("ready-sync-distance")
+				.map(|v| u64::from_str(v).expect("Invalid ready sync distance"))
+				.unwrap_or(health::MAX_HEALTHY_SYNC_DISTANCE),
+			ready_min_peers: matches.value_of("ready-min-peers")
+				.map(|v| usize::from_str(v).expect("Invalid ready minimum peers"))
+				.unwrap_or(health::MIN_HEALTHY_PEERS),
+			live_timeout: Duration::from_secs(C::seconds_per_slot()) * health::LIVE_TIMEOUT_SLOTS,
+		},
+	});
+	let beacon_api = matches.value_of("api-address").map(|address| ApiConfig {
+		public_address: Some(SocketAddr::from_str(address).expect("Invalid API address")),
+		admin_address: None,
+		cors_origins: matches.value_of("api-cors-origins")
+			.map(api_auth::parse_cors_origins)
+			.unwrap_or_default(),
+		tls: api_tls.clone(),
+		..Default::default()
+	});
+	let admin_api = matches.value_of("admin-api-address").map(|address| {
+		let token = match data_dir.as_ref() {
+			Some(data_dir) => api_auth::load_or_create_token(&data_dir.api_token())
+				.expect("Loading admin API token failed"),
+			None => {
+				let token = api_auth::generate_token();
+				info!("Admin API token for this run: {}", token);
+				token
+			},
+		};
+		ApiConfig {
+			public_address: None,
+			admin_address: Some(SocketAddr::from_str(address).expect("Invalid admin API address")),
+			token,
+			cors_origins: matches.value_of("api-cors-origins")
+				.map(api_auth::parse_cors_origins)
+				.unwrap_or_default(),
+			tls: api_tls.clone(),
+			..Default::default()
+		}
+	});
+	let explorer_api = matches.value_of("explorer-address").map(|address| ApiConfig {
+		public_address: Some(SocketAddr::from_str(address).expect("Invalid explorer address")),
+		admin_address: None,
+		cors_origins: matches.value_of("api-cors-origins")
+			.map(api_auth::parse_cors_origins)
+			.unwrap_or_default(),
+		tls: api_tls.clone(),
 		..Default::default()
 	});
-	let eth1_data = genesis_state.eth1_data.clone();
+	let eth1_endpoints = matches.value_of("eth1-endpoints").map(|urls| {
+		let chain_spec = spec::installed();
+		let chain_id = matches.value_of("eth1-chain-id")
+			.map(|v| u64::from_str(v).expect("Invalid eth1 chain id"))
+			.or_else(|| chain_spec.and_then(|spec| spec.deposit_chain_id))
+			.or_else(|| preset.as_ref().and_then(|preset| preset.eth1_chain_id));
+		let network_id = matches.value_of("eth1-network-id")
+			.map(|v| u64::from_str(v).expect("Invalid eth1 network id"))
+			.or_else(|| chain_spec.and_then(|spec| spec.deposit_network_id));
+		let deposit_contract = match matches.value_of("deposit-contract") {
+			Some(v) => Some(eth1_endpoints::parse_address(v).expect("Invalid deposit contract address")),
+			None => chain_spec.and_then(|spec| spec.deposit_contract_address).or_else(|| {
+				preset.as_ref()
+					.and_then(|preset| preset.deposit_contract)
+					.map(|v| eth1_endpoints::parse_address(v).expect("Bundled address is valid"))
+			}),
+		};
+		let requirements = match (chain_id, deposit_contract) {
+			(Some(chain_id), Some(deposit_contract)) => Eth1Requirements {
+				chain_id, network_id, deposit_contract,
+			},
+			_ => {
+				error!("Eth1 endpoints require --eth1-chain-id and --deposit-contract on this network");
+				std::process::exit(1);
+			},
+		};
+		let endpoints = Eth1Endpoints::new(urls.split(',').map(Eth1Endpoint::new).collect(), requirements);
+		if let Err(misconfigured) = endpoints.validate() {
+			for misconfiguration in misconfigured {
+				error!("{}", misconfiguration);
+			}
+			std::process::exit(1);
+		}
+		endpoints
+	});
+	let deposit_contract_block = matches.value_of("deposit-contract-block")
+		.map(|v| u64::from_str(v).expect("Invalid deposit contract block"))
+		.or_else(|| preset.as_ref().and_then(|preset| preset.deposit_contract_block))
+		.unwrap_or(0);
+	let compact_interval = matches.value_of("compact-interval")
+		.map(|v| Duration::from_secs(u64::from_str(v).expect("Invalid compaction interval") * 60 * 60));
+	let reorg_alert_depth = matches.value_of("reorg-alert-depth")
+		.map(|v| usize::from_str(v).expect("Invalid reorg alert depth"))
+		.unwrap_or(DEFAULT_REORG_ALERT_DEPTH);
+	let slot_timing = matches.value_of("slot-timing")
+		.map(|v| SlotTiming::from_str(v).expect("Invalid slot timing"))
+		.unwrap_or_default();
+	info!("Using slot timing {}", slot_timing);
+	let tracing = BlockTracing {
+		blocks: matches.value_of("trace-block")
+			.map(|v| v.split(',').map(|v| parse_root(v).expect("Invalid trace block root")).collect())
+			.unwrap_or_default(),
+		on_failure: matches.is_present("trace-failed-blocks"),
+		dir: data_dir.as_ref().map(|data_dir| data_dir.traces()),
+	};
 
 	let mut network_config = NetworkConfig::default();
-	network_config.libp2p_port = u16::from_str(matches.value_of("port").unwrap()).unwrap();
-	network_config.discovery_port = u16::from_str(matches.value_of("port").unwrap()).unwrap();
+	// Only absent for the import subcommand, which does not start networking.
+	let port = matches.value_of("port").map(|port| u16::from_str(port).unwrap()).unwrap_or_default();
+	network_config.libp2p_port = port;
+	network_config.discovery_port = port;
+	network_config.listen_address_v6 = matches.value_of("listen-v6")
+		.map(|v| Ipv6Addr::from_str(v).expect("Invalid IPv6 listen address"));
+	if let Some(port) = matches.value_of("port-v6") {
+		network_config.libp2p_port_v6 = u16::from_str(port).expect("Invalid IPv6 port");
+	}
+	if let Some(encodings) = matches.value_of("rpc-encodings") {
+		network_config.rpc_encodings = parse_encodings(encodings);
+	}
+	if let Some(encodings) = matches.value_of("gossip-encodings") {
+		network_config.gossip_encodings = parse_encodings(encodings);
+	}
+	if let Some(types) = matches.value_of("gossip-topics") {
+		network_config.gossip_types = parse_gossip_types(types);
+	}
+	if let Some(types) = matches.value_of("exclude-gossip-topics") {
+		let excluded = parse_gossip_types(types);
+		network_config.gossip_types.retain(|typ| !excluded.contains(typ));
+	}
+	if let Some(topics) = matches.value_of("extra-topics") {
+		network_config.topics = topics.split(',').map(String::from).collect();
+	}
+	network_config.upnp = matches.is_present("upnp");
+	if let Some(peers) = matches.value_of("min-outbound-peers") {
+		network_config.min_outbound_peers = usize::from_str(peers).expect("Invalid minimum outbound peers");
+	}
+	if let Some(timeout) = matches.value_of("idle-timeout") {
+		network_config.idle_timeout = u64::from_str(timeout).expect("Invalid idle timeout");
+	}
+	if let Some(timeout) = matches.value_of("substream-timeout") {
+		network_config.substream_timeout = u64::from_str(timeout).expect("Invalid substream timeout");
+	}
+	if let Some(interval) = matches.value_of("ping-interval") {
+		network_config.ping_interval = u64::from_str(interval).expect("Invalid ping interval");
+	}
+	if let Some(timeout) = matches.value_of("ping-timeout") {
+		network_config.ping_timeout = u64::from_str(timeout).expect("Invalid ping timeout");
+	}
+	if let Some(failures) = matches.value_of("ping-max-failures") {
+		network_config.ping_max_failures = u32::from_str(failures).expect("Invalid ping max failures");
+	}
+	network_config.flood_publish = !matches.is_present("no-flood-publish");
+	network_config.subnet_backbone = !matches.is_present("no-subnet-backbone");
+	if let Some(signing) = matches.value_of("gossip-signing") {
+		network_config.gossip_signing = GossipSigning::from_name(signing).expect("Checked by clap");
+	}
 	network_config.libp2p_nodes = if let Some(nodes) = matches.value_of("libp2p-nodes") {
 		nodes.rsplit(',')
 			.map(|v| FromStr::from_str(v).unwrap())
@@ -296,241 +1376,889 @@ fn main_with_config<C: Config>(matches: ArgMatches, preset: Option<Preset>) wher
 	} else {
 		Vec::new()
 	};
+	if let Some(preset) = preset.as_ref() {
+		network_config.boot_nodes = preset.boot_enrs
+			.iter()
+			.map(|v| FromStr::from_str(v).expect("Bundled ENR is valid"))
+			.collect::<Vec<Enr>>();
+	}
+	if let Some(peers) = matches.value_of("trusted-peers") {
+		network_config.trusted_peers = peers.split(',')
+			.map(|v| shasper_network::parse_trusted_peer(v).expect("Invalid trusted peer"))
+			.collect();
+	}
+
+	let checkpoint_states = Arc::new(Mutex::new(
+		CheckpointStateCache::new(DEFAULT_CHECKPOINT_STATE_CACHE_SIZE)
+	));
 
-	if let Some(path) = matches.value_of("data") {
+	if let Some(data_dir) = data_dir {
 		info!("Using RocksDB backend");
-		let backend = ShasperBackend::new(
-			RocksBackend::<_, (), RocksState<C>>::open_or_create(path, |_| {
-				Ok((genesis_block.clone(), genesis_state.into()))
-			}).unwrap()
-		);
+		network_config.network_dir = Some(data_dir.network());
+		let rocks_backend = RocksBackend::<_, (), RocksState<C>>::open_or_create(data_dir.chain_db(), |_| {
+			Ok((anchor_block.clone(), anchor_state.into()))
+		}).unwrap();
+		if matches.subcommand_matches("compact").is_some() {
+			match rocks_backend.compact() {
+				Ok(stats) => {
+					println!(
+						"Compacted database in {:?}, reclaimed {} bytes ({} bytes left)",
+						stats.duration, stats.reclaimed(), stats.size_after,
+					);
+					std::process::exit(0);
+				},
+				Err(e) => {
+					error!("Compacting database failed: {:?}", e);
+					std::process::exit(1);
+				},
+			}
+		}
+		let compaction_metrics = compact_interval.map(|interval| {
+			let metrics = Arc::new(RwLock::new(CompactionMetrics::new()));
+			compaction::spawn_periodic(rocks_backend.clone(), interval, metrics.clone());
+			metrics
+		});
+		let persistence: Box<dyn ForkChoicePersistence + Send + Sync> =
+			Box::new(RocksForkChoice::new(rocks_backend.clone()));
+		let backend = ShasperBackend::with_index(
+			rocks_backend.clone(),
+			RocksIndexStore::new(rocks_backend),
+			matches.is_present("archive"),
+		).with_state_cache_size(state_cache_size)
+			.with_checkpoint_states(checkpoint_states.clone());
+		let backend = if explorer_api.is_some() { backend.with_explorer_index() } else { backend };
+		let chain_info = match backend.load_chain_info(genesis_info, config_name) {
+			Ok(chain_info) => chain_info,
+			Err(e) => {
+				error!("Refusing to open database {:?}: {}", data_dir.chain_db(), e);
+				std::process::exit(1);
+			},
+		};
+		let validity = backend.validity_store();
+		let eth1_follower = eth1_endpoints.map(|endpoints| {
+			Eth1Follower::new(endpoints, backend.index_store(), deposit_contract_block)
+				.expect("Loading deposit cache failed")
+		});
 		let lock = ImportLock::new();
 
+		if let Some(dir) = import_command_dir {
+			std::process::exit(import_command(
+				backend, lock, Some(persistence), chain_info, checkpoint_states, validity, &dir,
+			));
+		}
+		if let Some(api) = explorer_api {
+			serve_explorer(api, backend.explorer_index());
+		}
+		if let Some(api) = beacon_api {
+			if let Err(e) = committees::serve(api, backend.clone()) {
+				warn!("Starting beacon API failed: {}", e);
+			}
+		}
+
 		run(network_config,
 			matches.is_present("author"),
 			backend,
 			lock,
-			eth1_data,
+			Some(persistence),
+			chain_info,
+			checkpoint_states,
+			validity,
+			arrival_csv,
+			export_era,
+			import_era,
+			max_clock_disparity,
+			ntp_server,
+			block_limits,
+			tracing,
+			slot_timing,
+			reorg_alert_depth,
+			node_metrics_file,
+			validator_metrics_file,
+			compaction_metrics,
+			eth1_follower,
+			health,
+			admin_api,
 			keys);
 	} else {
 		info!("Using in-memory backend");
-		let backend = ShasperBackend::new(
+		if matches.subcommand_matches("compact").is_some() {
+			error!("Compacting requires a data directory");
+			std::process::exit(1);
+		}
+		if compact_interval.is_some() {
+			warn!("Periodic compaction is ignored for the in-memory backend");
+		}
+		let backend = ShasperBackend::with_index(
 			SharedMemoryBackend::<_, (), MemoryState<C>>::new_with_genesis(
-				genesis_block.clone(),
-				genesis_state.into(),
-			)
-		);
+				anchor_block.clone(),
+				anchor_state.into(),
+			),
+			MemoryIndexStore::default(),
+			matches.is_present("archive"),
+		).with_state_cache_size(state_cache_size)
+			.with_checkpoint_states(checkpoint_states.clone());
+		let backend = if explorer_api.is_some() { backend.with_explorer_index() } else { backend };
+		let chain_info = backend.load_chain_info(genesis_info, config_name)
+			.expect("Loading chain info failed");
+		let validity = backend.validity_store();
+		let eth1_follower = eth1_endpoints.map(|endpoints| {
+			Eth1Follower::new(endpoints, backend.index_store(), deposit_contract_block)
+				.expect("Loading deposit cache failed")
+		});
 		let lock = ImportLock::new();
 
+		if let Some(dir) = import_command_dir {
+			warn!("Importing era files into an in-memory backend, the result is discarded on exit");
+			std::process::exit(import_command(
+				backend, lock, None, chain_info, checkpoint_states, validity, &dir,
+			));
+		}
+		if let Some(api) = explorer_api {
+			serve_explorer(api, backend.explorer_index());
+		}
+		if let Some(api) = beacon_api {
+			if let Err(e) = committees::serve(api, backend.clone()) {
+				warn!("Starting beacon API failed: {}", e);
+			}
+		}
+
 		run(network_config,
 			matches.is_present("author"),
 			backend,
 			lock,
-			eth1_data,
+			None,
+			chain_info,
+			checkpoint_states,
+			validity,
+			arrival_csv,
+			export_era,
+			import_era,
+			max_clock_disparity,
+			ntp_server,
+			block_limits,
+			tracing,
+			slot_timing,
+			reorg_alert_depth,
+			node_metrics_file,
+			validator_metrics_file,
+			None,
+			eth1_follower,
+			health,
+			admin_api,
 			keys);
 	}
 }
 
+fn serve_explorer(api: ApiConfig, index: ExplorerIndex) {
+	if let Err(e) = explorer::serve(api, index) {
+		warn!("Starting explorer endpoint failed: {}", e);
+	}
+}
+
 fn run<B, C: Config>(
 	config: NetworkConfig,
 	author: bool,
 	backend: B,
 	import_lock: ImportLock,
-	eth1_data: Eth1Data,
+	persistence: Option<Box<dyn ForkChoicePersistence + Send + Sync>>,
+	chain_info: ChainInfo,
+	checkpoint_states: SharedCheckpointStateCache<C>,
+	validity: ValidityStore,
+	arrival_csv: Option<PathBuf>,
+	export_era: Option<PathBuf>,
+	import_era: Option<PathBuf>,
+	max_clock_disparity: Duration,
+	ntp_server: Option<String>,
+	block_limits: BlockLimits,
+	tracing: BlockTracing,
+	slot_timing: SlotTiming,
+	reorg_alert_depth: usize,
+	node_metrics_file: Option<PathBuf>,
+	validator_metrics_file: Option<PathBuf>,
+	compaction_metrics: Option<SharedCompactionMetrics>,
+	eth1_follower: Option<Eth1Follower<C>>,
+	health: Option<HealthConfig>,
+	admin_api: Option<ApiConfig>,
 	keys: HashMap<ValidatorId, bls::Secret>,
 ) where
 	Block<C>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
-	B: ChainQuery + AncestorQuery + Store<Block=Block<C>>,
+	B: ChainQuery + AncestorQuery + SlotQuery + HeaderQuery + Store<Block=Block<C>>,
 	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
 	B::Auxiliary: Auxiliary<Block<C>> + Unpin,
 	B: SharedCommittable<Operation=Operation<<B as Store>::Block, <B as Store>::State, <B as Store>::Auxiliary>>,
+	Error: From<B::Error>,
 	B: Send + Sync + 'static,
 	C: Unpin + Clone + Send + Sync + 'static,
 {
-	let executor = Executor::<C, BLS>::new();
-	let importer = MutexImporter::new(
-		ArchiveGhostImporter::new(executor, backend.clone(), import_lock.clone())
-	);
+	let batch_verified = BatchVerified::default();
+	let execution = ExecutionHook::<C>::default().with_validity(validity.clone());
+	let executor = Executor::<C, BLS>::with_batch_verified(batch_verified.clone())
+		.with_checkpoint_states(checkpoint_states.clone())
+		.with_execution(execution.clone())
+		.with_tracing(tracing);
+	let slot_duration = Duration::from_secs(C::seconds_per_slot());
+	let slot_clock = SystemSlotClock::new(chain_info.genesis_time, slot_duration)
+		.expect("Slot duration must be at least one millisecond")
+		.with_timely_cutoff(slot_timing.gossip_cutoff.within(slot_duration));
+	let mut arrival_stats = ArrivalStats::new(chain_info.genesis_time, slot_duration);
+	if let Some(path) = arrival_csv {
+		arrival_stats = arrival_stats.with_csv(path).expect("Opening arrival CSV failed");
+	}
+	let chain_info = Arc::new(RwLock::new(chain_info));
+	let reorg_stats = Arc::new(Mutex::new(ReorgStats::new()));
+	let publish_stats = Arc::new(RwLock::new(PublishStats::new()));
+	let mesh_stats = Arc::new(RwLock::new(
+		MeshStats::new(config.gs_config.mesh_n, config.gs_config.mesh_n_low)
+	));
+	let bandwidth = Arc::new(RwLock::new(BandwidthTracker::new(Quota {
+		max_bytes: config.inbound_quota,
+		window: Duration::from_secs(config.inbound_quota_window),
+	})));
+	if let Some(api) = admin_api {
+		if let Err(e) = admin::serve(api, mesh_stats.clone()) {
+			warn!("Starting admin API failed: {}", e);
+		}
+	}
+	let mut ghost_importer = ArchiveGhostImporter::new(executor, backend.clone(), import_lock.clone())
+		.with_slot_clock(slot_clock.clone())
+		.with_validity(validity, true)
+		.with_reorg_alerts(reorg_stats.clone(), reorg_alert_depth);
+	if let Some(path) = node_metrics_file {
+		let publish_stats = publish_stats.clone();
+		let mesh_stats = mesh_stats.clone();
+		let bandwidth = bandwidth.clone();
+		thread::spawn(move || loop {
+			thread::sleep(slot_duration);
+			let mut metrics = reorg_stats.lock().expect("Lock is poisoned").encode();
+			metrics.push_str(&publish_stats.read().expect("Lock is poisoned").encode());
+			{
+				let mut mesh_stats = mesh_stats.write().expect("Lock is poisoned");
+				mesh_stats.roll(Instant::now());
+				metrics.push_str(&mesh_stats.encode());
+			}
+			metrics.push_str(&bandwidth.read().expect("Lock is poisoned").encode());
+			if let Some(compaction_metrics) = compaction_metrics.as_ref() {
+				metrics.push_str(&compaction_metrics.read().expect("Lock is poisoned").encode());
+			}
+			let tmp = path.with_extension("prom.tmp");
+			if let Err(e) = std::fs::write(&tmp, metrics).and_then(|()| std::fs::rename(&tmp, &path)) {
+				warn!("Writing node metrics to {:?} failed: {}", path, e);
+			}
+		});
+	}
+	let eth1_chain = eth1_follower.as_ref().map(|follower| follower.chain())
+		.unwrap_or_else(|| Arc::new(RwLock::new(Eth1Chain::new())));
+	if let Some(eth1_follower) = eth1_follower {
+		thread::spawn(move || {
+			eth1_follow::run(eth1_follower, eth1_endpoints::DEFAULT_HEALTH_CHECK_INTERVAL);
+		});
+	}
+	let slot_clock_future = slot_clock.clone();
+	let slot_clock_health = slot_clock.clone();
+	let local_keys = keys.keys().cloned().collect::<Vec<_>>();
+	if let Some(persistence) = persistence {
+		ghost_importer = ghost_importer.with_persistence(persistence)
+			.expect("Restoring fork choice store failed");
+	}
+	let importer = SharedArchiveGhostImporter::new(ghost_importer);
+
+	// Gossip attestations count for fork choice once verified against the
+	// head state, which is only reloaded when the head changes. Attestations
+	// arriving while the queue is full are dropped.
+	let (attestation_sender, attestation_receiver) =
+		std::sync::mpsc::sync_channel::<Attestation<C>>(ATTESTATION_QUEUE_CAPACITY);
+	{
+		let backend = backend.clone();
+		let importer = importer.clone();
+		let mut head_state: Option<(H256, beacon::BeaconState<C>)> = None;
+		thread::spawn(move || while let Ok(attestation) = attestation_receiver.recv() {
+			let attestations = Some(attestation).into_iter()
+				.chain(attestation_receiver.try_iter())
+				.collect::<Vec<_>>();
+			let head = backend.head();
+			if head_state.as_ref().map(|(id, _)| *id != head).unwrap_or(true) {
+				head_state = match backend.state_at(&head) {
+					Ok(mut state) => Some((head, state.as_externalities().state().clone())),
+					Err(e) => {
+						warn!("Loading head state for gossip attestations failed: {}", e);
+						continue
+					},
+				};
+			}
+			let state = match head_state.as_mut() {
+				Some((_, state)) => state,
+				None => continue,
+			};
+			let executive = beacon::BeaconExecutive::new(state);
+			for attestation in attestations {
+				match executive.verified_attestation_vote_targets::<BLS>(&attestation) {
+					Ok(votes) => importer.queue_votes(attestation.data.slot, votes),
+					Err(e) => debug!("Ignoring gossip attestation in fork choice: {:?}", e),
+				}
+			}
+		});
+	}
+	let shutdown = shutdown::install();
+	let shutdown_importer = importer.clone();
+	// Queued votes are applied, and proposer boost reset, at each slot
+	// start.
+	{
+		let importer = importer.clone();
+		let slot_clock = slot_clock.clone();
+		thread::spawn(move || loop {
+			let next_slot = match slot_clock.current_slot() {
+				Some(slot) => slot + 1,
+				None => {
+					thread::sleep(slot_duration);
+					continue
+				},
+			};
+			if let Some(wait) = slot_clock.duration_to_slot(next_slot) {
+				thread::sleep(wait);
+			}
+			if let Err(e) = importer.on_slot(next_slot) {
+				warn!("Updating fork choice at slot {} failed: {}", next_slot, e);
+			}
+		});
+	}
+
+	let sync_state = Arc::new(RwLock::new(SyncState::new()));
+	let clock_skew = clock_skew::spawn_monitor(slot_clock.clone(), sync_state.clone(), ntp_server);
+	let (network, network_receiver) = NetworkHandle::new(importer.clone());
 
 	if author {
 		let backend_build = backend.clone();
-		let importer_build = importer.clone();
+		let slot_clock_build = slot_clock.clone();
+		let sync_state_build = sync_state.clone();
+		let checkpoint_states_build = checkpoint_states.clone();
 		thread::spawn(move || {
-			builder_thread(backend_build, importer_build, eth1_data, keys);
+			builder_thread(
+				backend_build, network, slot_clock_build, sync_state_build, clock_skew,
+				checkpoint_states_build, execution,
+				block_limits, slot_timing, validator_metrics_file, eth1_chain, keys,
+			);
 		});
 	}
 
-	shasper_network::start_network_simple_sync(backend, import_lock, importer, config)
+	let verifier = BatchVerifier::<_, C, BLS>::new(
+		backend.clone(), batch_verified, checkpoint_states.clone(),
+	);
+	let import_queue = ImportQueue::spawn_pipelined(importer, verifier, DEFAULT_QUEUE_CAPACITY);
+	if let Some(dir) = import_era {
+		let mut era_queue = import_queue.clone();
+		thread::spawn(move || match era::import_eras::<C>(&dir, &mut era_queue) {
+			Ok((count, _)) => info!("Queued {} blocks from era files in {}", count, dir.display()),
+			Err(e) => warn!("Importing era files from {} failed: {}", dir.display(), e),
+		});
+	}
+	if let Some(dir) = export_era {
+		std::fs::create_dir_all(&dir).expect("Creating era export directory failed");
+		era::spawn_export(backend.clone(), dir, slot_duration * C::slots_per_epoch() as u32);
+	}
+	#[cfg(feature = "shards")]
+	let import_queue = shasper_blockchain::availability::AvailabilityQueue::new(import_queue);
+	let import_queue = FutureBlockQueue::spawn(import_queue, slot_clock_future, max_clock_disparity);
+	let peer_manager = Arc::new(RwLock::new(PeerManager::new()));
+	if let Some(HealthConfig { api, data_dir, probes }) = health {
+		let shared_health = SharedHealth::default();
+		match health::serve(api, probes, shared_health.clone()) {
+			Ok(()) => {
+				let backend = backend.clone();
+				let peer_manager = peer_manager.clone();
+				thread::spawn(move || loop {
+					let result = backend.state_at(&backend.head())
+						.map(|state| {
+							let peer_count = peer_manager.read().expect("Lock is poisoned")
+								.connected_peers().count();
+							HealthReport::new(
+								state.state(), slot_clock_health.current_slot(), peer_count,
+								data_dir.as_ref(), &local_keys,
+							)
+						})
+						.map_err(|e| {
+							warn!("Loading head state for the health report failed: {}", e);
+							e.to_string()
+						});
+					shared_health.write().expect("Lock is poisoned").refresh(result);
+					thread::sleep(slot_duration);
+				});
+			},
+			Err(e) => warn!("Starting health endpoint failed: {}", e),
+		}
+	}
+	shasper_network::start_network_simple_sync(
+		backend, import_lock, import_queue, config, peer_manager, sync_state, chain_info,
+		Arc::new(RwLock::new(arrival_stats)), publish_stats, mesh_stats, bandwidth, Some(attestation_sender),
+		network_receiver, shutdown,
+	)
 		.expect("Starting networking thread failed");
+	info!("Shutting down");
+	shutdown_importer.persist();
 }
 
-fn builder_thread<B, I, C: Config + Clone>(
+/// Import the blocks of era files in `dir`, with batch signature
+/// verification, and wait for them to be imported. Returns the exit code.
+fn import_command<B, C: Config>(
 	backend: B,
-	importer: I,
-	eth1_data: Eth1Data,
+	import_lock: ImportLock,
+	persistence: Option<Box<dyn ForkChoicePersistence + Send + Sync>>,
+	chain_info: ChainInfo,
+	checkpoint_states: SharedCheckpointStateCache<C>,
+	validity: ValidityStore,
+	dir: &Path,
+) -> i32 where
+	Block<C>: ssz::Encode + ssz::Decode + Unpin + Send + Sync,
+	B: ChainQuery + AncestorQuery + SlotQuery + HeaderQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
+	B::Auxiliary: Auxiliary<Block<C>> + Unpin,
+	B: SharedCommittable<Operation=Operation<<B as Store>::Block, <B as Store>::State, <B as Store>::Auxiliary>>,
+	Error: From<B::Error>,
+	B: Send + Sync + 'static,
+	C: Unpin + Clone + Send + Sync + 'static,
+{
+	let batch_verified = BatchVerified::default();
+	let executor = Executor::<C, BLS>::with_batch_verified(batch_verified.clone())
+		.with_checkpoint_states(checkpoint_states.clone())
+		.with_execution(ExecutionHook::<C>::default().with_validity(validity.clone()));
+	let slot_clock = SystemSlotClock::new(chain_info.genesis_time, Duration::from_secs(C::seconds_per_slot()))
+		.expect("Slot duration must be at least one millisecond");
+	let mut ghost_importer = ArchiveGhostImporter::new(executor, backend.clone(), import_lock)
+		.with_slot_clock(slot_clock)
+		.with_validity(validity, true);
+	if let Some(persistence) = persistence {
+		ghost_importer = ghost_importer.with_persistence(persistence)
+			.expect("Restoring fork choice store failed");
+	}
+	let importer = SharedArchiveGhostImporter::new(ghost_importer);
+	let verifier = BatchVerifier::<_, C, BLS>::new(
+		backend.clone(), batch_verified, checkpoint_states.clone(),
+	);
+	let mut queue = ImportQueue::spawn_pipelined(
+		importer.clone(), verifier, DEFAULT_QUEUE_CAPACITY,
+	);
+
+	let (count, last) = match era::import_eras::<C>(dir, &mut queue) {
+		Ok(queued) => queued,
+		Err(e) => {
+			error!("Reading era files from {} failed: {}", dir.display(), e);
+			return 1
+		},
+	};
+	let last = match last {
+		Some(last) => last,
+		None => {
+			info!("No blocks in era files in {}", dir.display());
+			return 0
+		},
+	};
+	info!("Queued {} blocks from era files, waiting for import", count);
+
+	// The import pipeline has no completion signal, so wait for the last
+	// block, giving up once the head stops advancing.
+	let mut head = backend.head();
+	let mut stalled = 0;
+	while !backend.contains(&last).unwrap_or(false) {
+		thread::sleep(Duration::from_secs(1));
+		let new_head = backend.head();
+		if new_head == head {
+			stalled += 1;
+			if stalled >= ERA_IMPORT_STALL_SECS {
+				error!("Import stalled at head {}, some era blocks failed to import", head);
+				return 1
+			}
+		} else {
+			head = new_head;
+			stalled = 0;
+		}
+	}
+	importer.persist();
+	info!("Imported era files up to block {}", last);
+	0
+}
+
+/// Number of gossip attestations waiting for fork choice above which new
+/// ones are dropped.
+const ATTESTATION_QUEUE_CAPACITY: usize = 4096;
+
+/// Seconds without head progress after which an era import is given up.
+const ERA_IMPORT_STALL_SECS: u32 = 60;
+
+struct ValidatorContext<B, I, C: Config> {
+	backend: B,
+	network: NetworkHandle<C, I>,
+	checkpoint_states: SharedCheckpointStateCache<C>,
+	executor: Executor<C, BLS>,
+	attestations: AttestationPool<C, BLS>,
+	committees: CommitteeCache<C>,
+	metrics: ValidatorMetrics,
+	slot_clock: SystemSlotClock,
+	eth1_chain: SharedEth1Chain,
 	keys: HashMap<ValidatorId, bls::Secret>,
-) where
+}
+
+/// Attest to the head, by default one third into the slot.
+struct AttestationDuty {
+	offset: SlotOffset,
+}
+
+impl<B, I, C: Config + Clone> Duty<ValidatorContext<B, I, C>> for AttestationDuty where
 	B: ChainQuery + Store<Block=Block<C>>,
 	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
 	B::Auxiliary: Auxiliary<Block<C>>,
+	Error: From<B::Error>,
 	I: SharedBlockImporter<Block=Block<C>>
 {
-	let executor = Executor::<C, BLS>::new();
-	let mut attestations = AttestationPool::<C, BLS>::new();
+	fn name(&self) -> &'static str {
+		"attestation"
+	}
 
-	loop {
-		thread::sleep(Duration::new(1, 0));
+	fn offset(&self) -> SlotOffset {
+		self.offset
+	}
 
-		let head = backend.head();
-		info!("Building on top of {}", head);
+	fn perform(&mut self, slot: Slot, ctx: &mut ValidatorContext<B, I, C>) {
+		let head = ctx.backend.head();
+		let head_block = ctx.backend.block_at(&head).unwrap();
+		if head_block.slot() > slot {
+			return
+		}
 
-		let block = {
-			let head_block = backend.block_at(&head).unwrap();
-			let head_state = backend.state_at(&head).unwrap();
-			trace!("Justified epoch {}, finalized epoch {}",
-				   { head_state.state().current_justified_checkpoint.epoch },
-				   { head_state.state().finalized_checkpoint.epoch });
-
-			let mut state = backend.state_at(&head).unwrap();
-			let externalities = state.as_externalities();
-			let current_slot = head_block.0.slot + 1;
-			executor.initialize_block(externalities, current_slot).unwrap();
-			let executive = BeaconExecutive::new(externalities.state_mut());
-			let current_epoch = executive.current_epoch();
-
-			let randao_domain = executive.domain(C::domain_randao(), None);
-			let proposer_domain = executive.domain(C::domain_beacon_proposer(), None);
-			let attestation_domain = executive.domain(C::domain_beacon_attester(), None);
-
-			for (validator_id, validator_seckey) in &keys {
-				let validator_index = executive.validator_index(validator_id);
-
-				if let Some(validator_index) = validator_index {
-					let committee_assignment = executive
-						.committee_assignment(current_epoch, validator_index).unwrap();
-					if let Some(committee_assignment) = committee_assignment {
-						if committee_assignment.slot == current_slot {
-							trace!(
-								"Found validator {} attesting slot {} with index {}",
-								validator_id, current_slot, committee_assignment.index);
-							let committee = committee_assignment.validators;
-
-							let target_epoch = current_epoch;
-							let target_slot = beacon::utils::start_slot_of_epoch::<C>(target_epoch);
-							let target_root = if target_slot == current_slot {
-								head
-							} else {
-								executive.block_root(target_epoch).unwrap()
-							};
-							let source_epoch = executive.current_justified_checkpoint.epoch;
-							let source_root = executive.current_justified_checkpoint.root;
-							trace!(
-								"Casper source {} ({}) to target {} ({})",
-								source_epoch, source_root, target_epoch, target_root,
-							);
-
-							let data = AttestationData {
-								beacon_block_root: head_block.id(),
-								source: Checkpoint {
-									epoch: source_epoch,
-									root: source_root,
-								},
-								target: Checkpoint {
-									epoch: target_epoch,
-									root: target_root,
-								},
-								slot: committee_assignment.slot,
-								index: committee_assignment.index,
-							};
-							let signature = Signature::from_slice(&bls::Signature::new(
-								&tree_root::<C::Digest, _>(&AttestationDataAndCustodyBit {
-									data: data.clone(),
-									custody_bit: false,
-								})[..],
-								attestation_domain,
-								&validator_seckey,
-							).as_bytes()[..]);
-
-							let index_into_committee = committee.iter()
-								.position(|v| *v == validator_index).unwrap();
-							let mut aggregation_bitfield = Vec::new();
-							aggregation_bitfield.resize(committee.len(), false);
-							aggregation_bitfield[index_into_committee] = true;
-							let mut custody_bitfield = Vec::new();
-							custody_bitfield.resize(committee.len(), false);
-
-							let attestation = Attestation {
-								aggregation_bits: aggregation_bitfield.into(),
-								data,
-								custody_bits: custody_bitfield.into(),
-								signature
-							};
-
-							attestations.push(attestation);
-						}
-					}
-				}
-			}
+		let mut state = ctx.backend.state_at(&head).unwrap();
+		trace!("Justified epoch {}, finalized epoch {}",
+			   { state.state().current_justified_checkpoint.epoch },
+			   { state.state().finalized_checkpoint.epoch });
+		let externalities = state.as_externalities();
+		ctx.executor.initialize_block(externalities, slot).unwrap();
+		let reader = StateReader::new(externalities.state_mut());
+		let current_epoch = reader.current_epoch();
+		ctx.committees.update(&reader);
+		ctx.metrics.note_block(head, &head_block.0, |slot| reader.block_root_at_slot(slot).ok());
+		ctx.metrics.prune::<C>(slot);
 
-			let proposer_index = executive.beacon_proposer_index().unwrap();
-			let proposer_pubkey = executive.validator_pubkey(proposer_index).unwrap();
-			trace!("Current proposer {} ({}) on epoch {}", proposer_index, proposer_pubkey, current_epoch);
+		let attestation_domain = reader.domain(C::domain_beacon_attester(), None);
 
-			let seckey = match keys.get(&proposer_pubkey) {
-				Some(value) => value.clone(),
-				None => {
-					warn!("No secret key, skip building block.");
-					continue;
+		for (validator_id, validator_seckey) in &ctx.keys {
+			let validator_index = match reader.validator_index(validator_id) {
+				Some(validator_index) => validator_index,
+				None => continue,
+			};
+			let committee_assignment = match reader
+				.committee_assignment(current_epoch, validator_index).unwrap()
+			{
+				Some(committee_assignment) if committee_assignment.slot == slot =>
+					committee_assignment,
+				_ => continue,
+			};
+			trace!(
+				"Found validator {} attesting slot {} with index {}",
+				validator_id, slot, committee_assignment.index);
+			ctx.metrics.note_attestation_duty();
+			let committee = committee_assignment.validators;
+
+			let target_epoch = current_epoch;
+			let target_slot = beacon::utils::start_slot_of_epoch::<C>(target_epoch);
+			let target_root = if target_slot == slot {
+				head
+			} else {
+				reader.block_root(target_epoch).unwrap()
+			};
+			let source = reader.current_justified_checkpoint();
+			let source_epoch = source.epoch;
+			let source_root = source.root;
+			trace!(
+				"Casper source {} ({}) to target {} ({})",
+				source_epoch, source_root, target_epoch, target_root,
+			);
+
+			let data = AttestationData {
+				beacon_block_root: head_block.id(),
+				source: Checkpoint {
+					epoch: source_epoch,
+					root: source_root,
 				},
+				target: Checkpoint {
+					epoch: target_epoch,
+					root: target_root,
+				},
+				slot: committee_assignment.slot,
+				index: committee_assignment.index,
 			};
-			let randao_reveal = Signature::from_slice(&bls::Signature::new(
-				&tree_root::<C::Digest, _>(&current_epoch)[..],
-				randao_domain,
-				&seckey
-			).as_bytes()[..]);
+			let signature = ctx.metrics.time_signing(|| Signature::from_slice(&bls::Signature::new(
+				&domain::attestation_signing_root::<C>(&data, false)[..],
+				attestation_domain,
+				&validator_seckey,
+			).as_bytes()[..]));
 
-			let mut unsealed_block = executor.apply_inherent(
-				&head_block, state.as_externalities(),
-				Inherent {
-					randao_reveal,
-					eth1_data: eth1_data.clone(),
-				}
-			).unwrap();
+			let index_into_committee = committee.iter()
+				.position(|v| *v == validator_index).unwrap();
+			let mut aggregation_bitfield = Vec::new();
+			aggregation_bitfield.resize(committee.len(), false);
+			aggregation_bitfield[index_into_committee] = true;
+			let mut custody_bitfield = Vec::new();
+			custody_bitfield.resize(committee.len(), false);
 
-			let mut collected_attestations = Vec::new();
-			for (hash, attestation) in attestations.iter() {
-				match executor.apply_extrinsic(
-					&mut unsealed_block, state.as_externalities(),
-					Transaction::Attestation(attestation.clone())
+			let attestation = Attestation {
+				aggregation_bits: aggregation_bitfield.into(),
+				data,
+				custody_bits: custody_bitfield.into(),
+				signature
+			};
+
+			let backend = &ctx.backend;
+			match validate_shallow(
+				&attestation, slot, &ctx.committees,
+				|root| backend.contains(root).unwrap_or(false),
+			) {
+				Ok(()) => match checkpoint_state::attestation_committee(
+					&ctx.checkpoint_states, backend, &attestation,
 				) {
-					Ok(()) => {
-						collected_attestations.push(*hash);
+					Ok(ref target_committee) if target_committee == &committee => {
+						ctx.metrics.note_attestation_produced(
+							validator_index, index_into_committee, attestation.data.clone(),
+						);
+						ctx.attestations.push(attestation);
 					},
-					Err(Error::Beacon(ref err)) if err == &beacon::Error::AttestationSubmittedTooQuickly => {},
-					Err(err) => {
-						warn!("Error when submitting an attestation: {}", err);
+					Ok(_) => {
+						warn!("Rejected attestation: committee differs at target");
+						ctx.metrics.note_attestation_missed();
 					},
-				}
+					Err(e) => {
+						warn!("Rejected attestation: {}", e);
+						ctx.metrics.note_attestation_missed();
+					},
+				},
+				Err(e) => {
+					warn!("Rejected attestation: {}", e);
+					ctx.metrics.note_attestation_missed();
+				},
 			}
-			info!("Pushed {} attestations", collected_attestations.len());
-			for hash in collected_attestations {
-				attestations.pop(&hash);
+		}
+	}
+}
+
+/// Propose a block, by default at the start of the slot.
+struct ProposalDuty {
+	offset: SlotOffset,
+	deadline: SlotOffset,
+}
+
+/// Build a block on top of `head_block`, from a state initialized for the
+/// block's slot. Attestations are packed and the execution payload is
+/// requested only while `deadline` has not passed. Without a deadline, a
+/// minimal block with only the inherent is built.
+fn build_block<B, I, C: Config + Clone>(
+	ctx: &mut ValidatorContext<B, I, C>,
+	head_block: &Block<C>,
+	state: &mut B::State,
+	randao_reveal: Signature,
+	deadline: Option<Deadline>,
+) -> Result<UnsealedBeaconBlock<C>, Error> where
+	B: ChainQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
+{
+	let eth1_data = eth1_vote::eth1_vote(
+		state.as_externalities().state(), &ctx.eth1_chain.read().expect("Lock is poisoned"),
+	);
+	let mut unsealed_block = ctx.executor.apply_inherent(
+		head_block, state.as_externalities(),
+		Inherent {
+			randao_reveal,
+			eth1_data,
+		}
+	)?;
+
+	if let Some(deadline) = deadline {
+		let mut collected_attestations = Vec::new();
+		for (hash, attestation) in ctx.attestations.iter() {
+			if deadline.is_expired() {
+				warn!("Block build deadline passed, packed {} attestations", collected_attestations.len());
+				break
+			}
+			if ctx.executor.remaining(
+				&unsealed_block, state.as_externalities(), TransactionKind::Attestation,
+			) == 0 {
+				break
 			}
+			match ctx.executor.apply_extrinsic(
+				&mut unsealed_block, state.as_externalities(),
+				Transaction::Attestation(attestation.clone())
+			) {
+				Ok(()) => {
+					collected_attestations.push(*hash);
+				},
+				Err(Error::Beacon(ref err)) if err == &beacon::Error::AttestationSubmittedTooQuickly => {},
+				Err(err) => {
+					warn!("Error when submitting an attestation: {}", err);
+				},
+			}
+		}
+		info!("Pushed {} attestations", collected_attestations.len());
+		for hash in collected_attestations {
+			ctx.attestations.pop(&hash);
+		}
 
-			executor.finalize_block(
-				&mut unsealed_block, state.as_externalities()
-			).unwrap();
+		if deadline.is_expired() {
+			warn!("Block build deadline passed, building block without execution payload");
+		} else if let Err(e) = ctx.executor.apply_payload(&mut unsealed_block) {
+			warn!("Building block without execution payload: {}", e);
+		}
+	}
 
-			let mut block = unsealed_block.fake_seal();
-			let signature = Signature::from_slice(&bls::Signature::new(
-				&tree_root::<C::Digest, _>(&UnsealedBeaconBlock::<C>::from(&block))[..],
-				proposer_domain,
-				&seckey
-			).as_bytes()[..]);
-			block.signature = signature;
-			Block(block)
+	ctx.executor.finalize_block(&mut unsealed_block, state.as_externalities())?;
+	Ok(unsealed_block)
+}
+
+impl<B, I, C: Config + Clone> Duty<ValidatorContext<B, I, C>> for ProposalDuty where
+	B: ChainQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
+	B::Auxiliary: Auxiliary<Block<C>>,
+	Error: From<B::Error>,
+	I: SharedBlockImporter<Block=Block<C>>
+{
+	fn name(&self) -> &'static str {
+		"proposal"
+	}
+
+	fn offset(&self) -> SlotOffset {
+		self.offset
+	}
+
+	fn perform(&mut self, slot: Slot, ctx: &mut ValidatorContext<B, I, C>) {
+		let head = ctx.backend.head();
+		let head_block = ctx.backend.block_at(&head).unwrap();
+		if head_block.slot() >= slot {
+			return
+		}
+
+		let mut state = ctx.backend.state_at(&head).unwrap();
+		let externalities = state.as_externalities();
+		ctx.executor.initialize_block(externalities, slot).unwrap();
+		let reader = StateReader::new(externalities.state_mut());
+		let current_epoch = reader.current_epoch();
+		let randao_domain = reader.domain(C::domain_randao(), None);
+		let proposer_domain = reader.domain(C::domain_beacon_proposer(), None);
+
+		let proposer_index = reader.beacon_proposer_index().unwrap();
+		let proposer_pubkey = reader.validator_pubkey(proposer_index).unwrap();
+		trace!("Current proposer {} ({}) on epoch {}", proposer_index, proposer_pubkey, current_epoch);
+
+		let seckey = match ctx.keys.get(&proposer_pubkey) {
+			Some(value) => value.clone(),
+			None => {
+				debug!("No secret key of proposer {}, skip building block.", proposer_index);
+				return
+			},
+		};
+		ctx.metrics.note_proposal_duty();
+		info!("Building on top of {}", head);
+
+		let randao_reveal = ctx.metrics.time_signing(|| Signature::from_slice(&bls::Signature::new(
+			&domain::randao_signing_root::<C>(current_epoch)[..],
+			randao_domain,
+			&seckey
+		).as_bytes()[..]));
+
+		let deadline = Deadline::within_slot(&ctx.slot_clock, self.deadline);
+		let unsealed_block = match build_block(ctx, &head_block, &mut state, randao_reveal.clone(), Some(deadline)) {
+			Ok(block) => block,
+			Err(e) => {
+				warn!("Building block failed, falling back to a minimal block: {}", e);
+				ctx.metrics.note_proposal_fallback();
+				let mut state = ctx.backend.state_at(&head).unwrap();
+				let minimal = ctx.executor.initialize_block(state.as_externalities(), slot)
+					.and_then(|()| build_block(ctx, &head_block, &mut state, randao_reveal, None));
+				match minimal {
+					Ok(block) => block,
+					Err(e) => {
+						warn!("Building minimal block failed: {}", e);
+						ctx.metrics.note_proposal_missed();
+						return
+					},
+				}
+			},
 		};
 
-		importer.import_block(block).unwrap();
+		let signature = ctx.metrics.time_signing(|| Signature::from_slice(&bls::Signature::new(
+			&domain::block_signing_root::<C, _>(&unsealed_block)[..],
+			proposer_domain,
+			&seckey
+		).as_bytes()[..]));
+
+		match ctx.network.publish_block(Block::seal(unsealed_block, signature)) {
+			Ok(_) => ctx.metrics.note_proposal_produced(),
+			Err(e) => {
+				warn!("Publishing block failed: {:?}", e);
+				ctx.metrics.note_proposal_missed();
+			},
+		}
+	}
+}
+
+fn builder_thread<B, I, C: Config + Clone>(
+	backend: B,
+	network: NetworkHandle<C, I>,
+	slot_clock: SystemSlotClock,
+	sync_state: SharedSyncState,
+	clock_skew: SharedClockSkew,
+	checkpoint_states: SharedCheckpointStateCache<C>,
+	execution: ExecutionHook<C>,
+	block_limits: BlockLimits,
+	slot_timing: SlotTiming,
+	metrics_file: Option<PathBuf>,
+	eth1_chain: SharedEth1Chain,
+	keys: HashMap<ValidatorId, bls::Secret>,
+) where
+	B: ChainQuery + Store<Block=Block<C>>,
+	B::State: StateExternalities + AsExternalities<dyn StateExternalities<Config=C>>,
+	B::Auxiliary: Auxiliary<Block<C>>,
+	Error: From<B::Error>,
+	I: SharedBlockImporter<Block=Block<C>>
+{
+	let executor = Executor::<C, BLS>::new()
+		.with_limits(block_limits)
+		.with_execution(execution.clone())
+		.with_root_cache();
+	let mut context = ValidatorContext {
+		backend,
+		network,
+		checkpoint_states,
+		executor,
+		attestations: AttestationPool::<C, BLS>::new(),
+		committees: CommitteeCache::<C>::new(),
+		metrics: ValidatorMetrics::new(),
+		slot_clock: slot_clock.clone(),
+		eth1_chain,
+		keys,
+	};
+	let mut scheduler = DutyScheduler::new(slot_clock)
+		.with_duty(ProposalDuty { offset: slot_timing.proposal, deadline: slot_timing.build_deadline })
+		.with_duty(AttestationDuty { offset: slot_timing.attestation });
+
+	loop {
+		thread::sleep(scheduler.time_to_next().min(Duration::new(1, 0)));
+		if let Some(path) = metrics_file.as_ref() {
+			if let Err(e) = context.metrics.write(path) {
+				warn!("Writing validator metrics to {:?} failed: {}", path, e);
+			}
+		}
+
+		if sync_state.read().expect("Lock is poisoned").is_syncing() {
+			debug!("Syncing, skipping validator duties");
+			context.metrics.note_beacon_node(false);
+			continue
+		}
+		if clock_skew.read().expect("Lock is poisoned").is_skewed {
+			warn!("Performing validator duties with a skewed clock, attestations may be ineffective");
+		}
+
+		let head = context.backend.head();
+		if execution.is_optimistic(&head) {
+			warn!("Head {} is imported optimistically, skipping validator duties", head);
+			context.metrics.note_beacon_node(false);
+			continue
+		}
+		context.metrics.note_beacon_node(true);
+
+		scheduler.tick(&mut context);
 	}
 }
@@ -0,0 +1,241 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Migration of states between config presets.
+//!
+//! A state recorded under one preset is re-serialized into the containers of
+//! another where this keeps its meaning, which is useful to move long
+//! running devnets or to build test fixtures. Circular history buffers are
+//! remapped by slot or epoch, and anything whose interpretation depends on
+//! constants differing between the presets is reported as incompatible.
+
+use core::cmp::min;
+use std::fmt;
+use beacon::{BeaconState, Config};
+use beacon::types::PendingAttestation;
+use beacon::utils::epoch_of_slot;
+use typenum::Unsigned;
+
+/// Reason a state cannot be migrated.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Incompatibility {
+	/// A constant the state depends on differs between the presets.
+	Constant {
+		/// Name of the constant.
+		name: &'static str,
+		/// Value in the source preset.
+		from: u64,
+		/// Value in the target preset.
+		to: u64,
+	},
+	/// A list is longer than the target preset allows.
+	TooLong {
+		/// Name of the field.
+		field: &'static str,
+		/// Length of the list.
+		len: usize,
+		/// Limit in the target preset.
+		limit: u64,
+	},
+}
+
+impl fmt::Display for Incompatibility {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Incompatibility::Constant { name, from, to } =>
+				write!(f, "{} differs ({} to {}), and the state depends on it", name, from, to),
+			Incompatibility::TooLong { field, len, limit } =>
+				write!(f, "{} has {} items, over the limit of {}", field, len, limit),
+		}
+	}
+}
+
+/// Lossy but valid change made by a migration.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Note {
+	/// A history buffer is longer in the target preset. Entries older than
+	/// the source buffer are unknown and left zero.
+	HistoryPadded {
+		/// Name of the field.
+		field: &'static str,
+		/// Number of known entries.
+		known: usize,
+		/// Length of the target buffer.
+		length: usize,
+	},
+	/// A history buffer is shorter in the target preset, and its oldest
+	/// entries were dropped.
+	HistoryTruncated {
+		/// Name of the field.
+		field: &'static str,
+		/// Number of entries kept.
+		kept: usize,
+		/// Length of the source buffer.
+		length: usize,
+	},
+}
+
+impl fmt::Display for Note {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Note::HistoryPadded { field, known, length } =>
+				write!(f, "{}: only {} of {} entries are known, the rest are zero", field, known, length),
+			Note::HistoryTruncated { field, kept, length } =>
+				write!(f, "{}: kept the latest {} of {} entries", field, kept, length),
+		}
+	}
+}
+
+fn constant(
+	incompatibilities: &mut Vec<Incompatibility>,
+	name: &'static str,
+	from: u64,
+	to: u64,
+) {
+	if from != to {
+		incompatibilities.push(Incompatibility::Constant { name, from, to });
+	}
+}
+
+fn limit(
+	incompatibilities: &mut Vec<Incompatibility>,
+	field: &'static str,
+	len: usize,
+	limit: u64,
+) {
+	if len as u64 > limit {
+		incompatibilities.push(Incompatibility::TooLong { field, len, limit });
+	}
+}
+
+/// Copy the entries of a circular buffer indexed by slot or epoch, for the
+/// indices up to `end`, exclusive, known in both buffers.
+fn remap<T: Clone>(notes: &mut Vec<Note>, field: &'static str, from: &[T], to: &mut [T], end: u64) {
+	let window = min(from.len(), to.len()) as u64;
+	for index in end.saturating_sub(window)..end {
+		to[(index % to.len() as u64) as usize] = from[(index % from.len() as u64) as usize].clone();
+	}
+
+	if to.len() > from.len() && end > from.len() as u64 {
+		notes.push(Note::HistoryPadded { field, known: from.len(), length: to.len() });
+	}
+	if to.len() < from.len() && end > to.len() as u64 {
+		notes.push(Note::HistoryTruncated { field, kept: to.len(), length: from.len() });
+	}
+}
+
+/// Migrate a state from preset `A` to preset `B`. Returns the migrated
+/// state and the lossy changes made, or all the reasons it cannot be
+/// migrated.
+pub fn migrate_state<A: Config, B: Config>(
+	state: &BeaconState<A>,
+) -> Result<(BeaconState<B>, Vec<Note>), Vec<Incompatibility>> {
+	let mut incompatibilities = Vec::new();
+
+	// Epochs recorded in checkpoints and validator records change meaning
+	// with the epoch length.
+	constant(&mut incompatibilities, "SLOTS_PER_EPOCH", A::slots_per_epoch(), B::slots_per_epoch());
+	if !state.historical_roots.is_empty() {
+		constant(
+			&mut incompatibilities, "SLOTS_PER_HISTORICAL_ROOT",
+			A::slots_per_historical_root(), B::slots_per_historical_root(),
+		);
+	}
+	if !state.eth1_data_votes.is_empty() {
+		constant(
+			&mut incompatibilities, "SLOTS_PER_ETH1_VOTING_PERIOD",
+			A::slots_per_eth1_voting_period(), B::slots_per_eth1_voting_period(),
+		);
+	}
+	// Aggregation bits of pending attestations refer to committees.
+	if !state.previous_epoch_attestations.is_empty() || !state.current_epoch_attestations.is_empty() {
+		constant(
+			&mut incompatibilities, "SHUFFLE_ROUND_COUNT",
+			A::shuffle_round_count(), B::shuffle_round_count(),
+		);
+		constant(
+			&mut incompatibilities, "TARGET_COMMITTEE_SIZE",
+			A::target_committee_size(), B::target_committee_size(),
+		);
+		constant(
+			&mut incompatibilities, "MAX_COMMITTEES_PER_SLOT",
+			A::max_committees_per_slot(), B::max_committees_per_slot(),
+		);
+	}
+
+	limit(&mut incompatibilities, "historical_roots", state.historical_roots.len(), B::historical_roots_limit());
+	limit(
+		&mut incompatibilities, "eth1_data_votes",
+		state.eth1_data_votes.len(), B::slots_per_eth1_voting_period(),
+	);
+	limit(&mut incompatibilities, "validators", state.validators.len(), B::validator_registry_limit());
+	limit(&mut incompatibilities, "balances", state.balances.len(), B::validator_registry_limit());
+	for (field, attestations) in &[
+		("previous_epoch_attestations", &state.previous_epoch_attestations[..]),
+		("current_epoch_attestations", &state.current_epoch_attestations[..]),
+	] {
+		limit(&mut incompatibilities, *field, attestations.len(), B::MaxAttestationsPerEpoch::to_u64());
+		for attestation in attestations.iter() {
+			limit(
+				&mut incompatibilities, "aggregation_bits",
+				attestation.aggregation_bits.len(), B::max_validators_per_committee(),
+			);
+		}
+	}
+
+	if !incompatibilities.is_empty() {
+		return Err(incompatibilities)
+	}
+
+	let mut notes = Vec::new();
+	let epoch = epoch_of_slot::<A>(state.slot);
+	let mut migrated = BeaconState::<B> {
+		genesis_time: state.genesis_time,
+		slot: state.slot,
+		fork: state.fork.clone(),
+		latest_block_header: state.latest_block_header.clone(),
+		historical_roots: state.historical_roots[..].to_vec().into(),
+		eth1_data: state.eth1_data.clone(),
+		eth1_data_votes: state.eth1_data_votes[..].to_vec().into(),
+		eth1_deposit_index: state.eth1_deposit_index,
+		validators: state.validators[..].to_vec().into(),
+		balances: state.balances[..].to_vec().into(),
+		previous_epoch_attestations: migrate_attestations(&state.previous_epoch_attestations[..]).into(),
+		current_epoch_attestations: migrate_attestations(&state.current_epoch_attestations[..]).into(),
+		justification_bits: state.justification_bits.clone(),
+		previous_justified_checkpoint: state.previous_justified_checkpoint.clone(),
+		current_justified_checkpoint: state.current_justified_checkpoint.clone(),
+		finalized_checkpoint: state.finalized_checkpoint.clone(),
+		..Default::default()
+	};
+	remap(&mut notes, "block_roots", &state.block_roots[..], &mut migrated.block_roots[..], state.slot);
+	remap(&mut notes, "state_roots", &state.state_roots[..], &mut migrated.state_roots[..], state.slot);
+	remap(&mut notes, "randao_mixes", &state.randao_mixes[..], &mut migrated.randao_mixes[..], epoch + 1);
+	remap(&mut notes, "slashings", &state.slashings[..], &mut migrated.slashings[..], epoch + 1);
+
+	Ok((migrated, notes))
+}
+
+fn migrate_attestations<A: Config, B: Config>(
+	attestations: &[PendingAttestation<A>],
+) -> Vec<PendingAttestation<B>> {
+	attestations.iter().map(|attestation| PendingAttestation {
+		aggregation_bits: attestation.aggregation_bits[..].to_vec().into(),
+		data: attestation.data.clone(),
+		inclusion_delay: attestation.inclusion_delay,
+		proposer_index: attestation.proposer_index,
+	}).collect()
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Offline tooling for validator operators.
+//!
+//! Voluntary exits are signed from the validator key alone, given the
+//! validator index and the fork, so that keys kept offline never have to be
+//! loaded into a running node. Withdrawal credentials of validators are read
+//! from a state, and checked against the withdrawal key they commit to.
+
+use std::fmt;
+use beacon::{BeaconState, BLSConfig, Config, domain};
+use beacon::primitives::{Epoch, H256, Signature, ValidatorId};
+use beacon::types::{Fork, VoluntaryExit};
+use crypto::bls::{self, BLSVerification};
+
+/// Sign a voluntary exit of validator `validator_index` at `epoch`.
+pub fn sign_voluntary_exit<C: Config>(
+	secret: &bls::Secret,
+	validator_index: u64,
+	epoch: Epoch,
+	fork: &Fork,
+) -> VoluntaryExit {
+	let mut exit = VoluntaryExit { epoch, validator_index, signature: Default::default() };
+	exit.signature = Signature::from_slice(&bls::Signature::new(
+		&domain::voluntary_exit_signing_root::<C>(&exit)[..],
+		domain::domain(C::domain_voluntary_exit(), fork, epoch),
+		secret,
+	).as_bytes()[..]);
+	exit
+}
+
+/// Whether `exit` is signed by `pubkey` under `fork`.
+pub fn verify_voluntary_exit<C: Config>(
+	exit: &VoluntaryExit,
+	pubkey: &ValidatorId,
+	fork: &Fork,
+) -> bool {
+	BLSVerification::verify(
+		pubkey,
+		&domain::voluntary_exit_signing_root::<C>(exit),
+		&exit.signature,
+		domain::domain(C::domain_voluntary_exit(), fork, exit.epoch),
+	)
+}
+
+/// Withdrawal credentials committing to the BLS withdrawal key `pubkey`.
+pub fn bls_withdrawal_credentials<C: Config>(pubkey: &ValidatorId) -> H256 {
+	let mut credentials = C::hash(&[pubkey.as_ref()]);
+	credentials[0] = C::bls_withdrawal_prefix_byte();
+	credentials
+}
+
+/// Withdrawal credentials of a validator in a state.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidatorCredentials {
+	/// Validator index.
+	pub index: u64,
+	/// Validator signing key.
+	pub pubkey: ValidatorId,
+	/// Withdrawal credentials.
+	pub withdrawal_credentials: H256,
+	/// Whether the credentials commit to a BLS withdrawal key.
+	pub is_bls: bool,
+}
+
+impl ValidatorCredentials {
+	/// Whether the credentials commit to the BLS withdrawal key `pubkey`.
+	pub fn matches<C: Config>(&self, pubkey: &ValidatorId) -> bool {
+		self.withdrawal_credentials == bls_withdrawal_credentials::<C>(pubkey)
+	}
+}
+
+impl fmt::Display for ValidatorCredentials {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f, "{} {:?} {:?} ({})",
+			self.index, self.pubkey, self.withdrawal_credentials,
+			if self.is_bls { "BLS withdrawal key" } else { "unknown prefix" },
+		)
+	}
+}
+
+/// Withdrawal credentials of the validators of `state`, either all of them
+/// or those with the given indices. Unknown indices are skipped.
+pub fn validator_credentials<C: Config>(
+	state: &BeaconState<C>,
+	indices: Option<&[u64]>,
+) -> Vec<ValidatorCredentials> {
+	state.validators.iter()
+		.enumerate()
+		.map(|(index, validator)| (index as u64, validator))
+		.filter(|(index, _)| indices.map(|indices| indices.contains(index)).unwrap_or(true))
+		.map(|(index, validator)| ValidatorCredentials {
+			index,
+			pubkey: validator.pubkey.clone(),
+			withdrawal_credentials: validator.withdrawal_credentials,
+			is_bls: validator.withdrawal_credentials[0] == C::bls_withdrawal_prefix_byte(),
+		})
+		.collect()
+}
+
+/// Index of the validator with signing key `pubkey` in `state`.
+pub fn validator_index<C: Config>(state: &BeaconState<C>, pubkey: &ValidatorId) -> Option<u64> {
+	state.validators.iter()
+		.position(|validator| &validator.pubkey == pubkey)
+		.map(|index| index as u64)
+}
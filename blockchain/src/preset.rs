@@ -1,17 +1,92 @@
 use std::collections::HashMap;
+use beacon::primitives::{H256, Version};
 
+/// Named network preset, bundling everything needed to join a known network.
 #[derive(Debug, Clone)]
 pub struct Preset {
+	/// Name of the compiled config used by the network.
+	pub config: &'static str,
+	/// Libp2p multiaddrs of boot nodes.
 	pub bootnodes: Vec<String>,
-	pub genesis_state: Vec<u8>,
+	/// ENRs of discovery boot nodes.
+	pub boot_enrs: Vec<String>,
+	/// Ssz genesis state. If not bundled, it must be given on the command line.
+	pub genesis_state: Option<Vec<u8>>,
+	/// Expected root of the genesis state.
+	pub genesis_state_root: Option<H256>,
+	/// Fork versions, by activation epoch.
+	pub forks: Vec<(u64, Version)>,
+	/// Chain id of the eth1 chain holding the deposit contract.
+	pub eth1_chain_id: Option<u64>,
+	/// Address of the deposit contract, hex encoded.
+	pub deposit_contract: Option<&'static str>,
+	/// Eth1 block the deposit contract was deployed at.
+	pub deposit_contract_block: Option<u64>,
+}
+
+impl Preset {
+	/// Fork version in effect at `epoch`, if the fork schedule is known.
+	pub fn fork_version(&self, epoch: u64) -> Option<Version> {
+		self.forks.iter()
+			.filter(|(fork_epoch, _)| *fork_epoch <= epoch)
+			.max_by_key(|(fork_epoch, _)| *fork_epoch)
+			.map(|(_, version)| *version)
+	}
 }
 
 pub fn presets() -> HashMap<&'static str, Preset> {
 	let mut presets = HashMap::new();
 
 	presets.insert("sapphire", Preset {
+		config: "sapphire",
 		bootnodes: vec!["/dns4/prylabs.net/tcp/30001".to_string()],
-		genesis_state: include_bytes!("../res/eth2-testnets/prysm/Sapphire(v0.9.0)/genesis.ssz").to_vec(),
+		boot_enrs: Vec::new(),
+		genesis_state: Some(
+			include_bytes!("../res/eth2-testnets/prysm/Sapphire(v0.9.0)/genesis.ssz").to_vec()
+		),
+		genesis_state_root: None,
+		forks: Vec::new(),
+		eth1_chain_id: None,
+		deposit_contract: None,
+		deposit_contract_block: None,
+	});
+
+	// Boot nodes and genesis states of public networks are not bundled yet,
+	// and must be given on the command line.
+	presets.insert("mainnet", Preset {
+		config: "mainnet",
+		bootnodes: Vec::new(),
+		boot_enrs: Vec::new(),
+		genesis_state: None,
+		genesis_state_root: None,
+		forks: vec![(0, Version::from([0x00, 0x00, 0x00, 0x00]))],
+		eth1_chain_id: Some(1),
+		deposit_contract: Some("0x00000000219ab540356cBB839Cbe05303d7705Fa"),
+		deposit_contract_block: Some(11052984),
+	});
+
+	presets.insert("pyrmont", Preset {
+		config: "mainnet",
+		bootnodes: Vec::new(),
+		boot_enrs: Vec::new(),
+		genesis_state: None,
+		genesis_state_root: None,
+		forks: vec![(0, Version::from([0x00, 0x00, 0x20, 0x09]))],
+		eth1_chain_id: Some(5),
+		deposit_contract: Some("0x8c5fecdC472E27Bc447696F431E425D02dd46a8c"),
+		deposit_contract_block: Some(3743587),
+	});
+
+	presets.insert("local", Preset {
+		config: "minimal",
+		bootnodes: Vec::new(),
+		boot_enrs: Vec::new(),
+		genesis_state: None,
+		genesis_state_root: None,
+		forks: Vec::new(),
+		eth1_chain_id: None,
+		deposit_contract: None,
+		deposit_contract_block: None,
 	});
 
 	presets
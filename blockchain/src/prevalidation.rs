@@ -0,0 +1,210 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Shallow attestation validation.
+//!
+//! Cheap checks run before signature verification, so that obviously invalid
+//! attestations are rejected without touching crypto or cloning state.
+
+use std::collections::BTreeMap;
+use core::cmp::{max, min};
+use core::marker::PhantomData;
+use beacon::{Config, StateReader};
+use beacon::primitives::{Epoch, Slot, H256};
+use beacon::types::Attestation;
+use beacon::utils;
+
+/// Number of epochs whose committee shapes are kept.
+const MAX_CACHED_EPOCHS: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Reasons an attestation is rejected by shallow validation.
+pub enum Rejection {
+	/// Attestation slot is after the current slot.
+	FutureSlot {
+		/// Attestation slot.
+		slot: Slot,
+		/// Current slot.
+		current_slot: Slot,
+	},
+	/// Attestation slot is more than an epoch old.
+	ExpiredSlot {
+		/// Attestation slot.
+		slot: Slot,
+		/// Current slot.
+		current_slot: Slot,
+	},
+	/// Target epoch is not the epoch of the attestation slot.
+	TargetEpochMismatch,
+	/// Committees of the epoch are not cached.
+	UnknownEpoch(Epoch),
+	/// Committee index is out of range.
+	CommitteeIndexOutOfRange {
+		/// Committee index.
+		index: u64,
+		/// Committee count at the slot.
+		count: u64,
+	},
+	/// Bitfield length does not match the committee size.
+	BitfieldLengthMismatch {
+		/// Committee size.
+		expected: usize,
+		/// Bitfield length.
+		actual: usize,
+	},
+	/// No aggregation bit is set.
+	EmptyAggregation,
+	/// Beacon block root is unknown.
+	UnknownBlockRoot(H256),
+}
+
+impl std::fmt::Display for Rejection {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Rejection::FutureSlot { slot, current_slot } =>
+				write!(f, "Attestation slot {} is after current slot {}", slot, current_slot),
+			Rejection::ExpiredSlot { slot, current_slot } =>
+				write!(f, "Attestation slot {} is too old at current slot {}", slot, current_slot),
+			Rejection::TargetEpochMismatch =>
+				write!(f, "Attestation target epoch does not match its slot"),
+			Rejection::UnknownEpoch(epoch) =>
+				write!(f, "Committees of epoch {} are not known", epoch),
+			Rejection::CommitteeIndexOutOfRange { index, count } =>
+				write!(f, "Committee index {} out of range, {} committees", index, count),
+			Rejection::BitfieldLengthMismatch { expected, actual } =>
+				write!(f, "Bitfield length {} does not match committee size {}", actual, expected),
+			Rejection::EmptyAggregation =>
+				write!(f, "Attestation has no aggregation bit set"),
+			Rejection::UnknownBlockRoot(root) =>
+				write!(f, "Unknown beacon block root {}", root),
+		}
+	}
+}
+
+impl std::error::Error for Rejection { }
+
+/// Shapes of committees of recent epochs. Committee counts and sizes only
+/// depend on the number of active validators, so that is all that is cached.
+pub struct CommitteeCache<C: Config> {
+	active_validator_counts: BTreeMap<Epoch, usize>,
+	_marker: PhantomData<C>,
+}
+
+impl<C: Config> CommitteeCache<C> {
+	/// Create an empty cache.
+	pub fn new() -> Self {
+		Self {
+			active_validator_counts: BTreeMap::new(),
+			_marker: PhantomData,
+		}
+	}
+
+	/// Note the number of active validators at epoch, evicting the oldest
+	/// epochs.
+	pub fn insert(&mut self, epoch: Epoch, active_validator_count: usize) {
+		self.active_validator_counts.insert(epoch, active_validator_count);
+		while self.active_validator_counts.len() > MAX_CACHED_EPOCHS {
+			let oldest = *self.active_validator_counts.keys().next()
+				.expect("Cache is not empty; qed");
+			self.active_validator_counts.remove(&oldest);
+		}
+	}
+
+	/// Note the current and next epoch of a state.
+	pub fn update(&mut self, reader: &StateReader<C>) {
+		let current_epoch = reader.current_epoch();
+		for epoch in current_epoch..=(current_epoch + 1) {
+			if !self.active_validator_counts.contains_key(&epoch) {
+				if let Ok(count) = reader.active_validator_count(epoch) {
+					self.insert(epoch, count);
+				}
+			}
+		}
+	}
+
+	/// Number of committees per slot at epoch.
+	pub fn committee_count(&self, epoch: Epoch) -> Option<u64> {
+		let active_validator_count = *self.active_validator_counts.get(&epoch)?;
+		Some(max(
+			1,
+			min(
+				C::max_committees_per_slot(),
+				active_validator_count as u64 /
+					C::slots_per_epoch() /
+					C::target_committee_size(),
+			)
+		))
+	}
+
+	/// Size of the committee at slot and index.
+	pub fn committee_size(&self, slot: Slot, index: u64) -> Option<usize> {
+		let epoch = utils::epoch_of_slot::<C>(slot);
+		let active_validator_count = *self.active_validator_counts.get(&epoch)? as u64;
+		let committees_per_slot = self.committee_count(epoch)?;
+		if index >= committees_per_slot {
+			return None
+		}
+
+		let index = (slot % C::slots_per_epoch()) * committees_per_slot + index;
+		let count = committees_per_slot * C::slots_per_epoch();
+		let start = active_validator_count * index / count;
+		let end = active_validator_count * (index + 1) / count;
+		Some((end - start) as usize)
+	}
+}
+
+/// Run cheap checks on an attestation. `is_known_block` is queried last, as
+/// it may touch the backend.
+pub fn validate_shallow<C: Config, F: Fn(&H256) -> bool>(
+	attestation: &Attestation<C>,
+	current_slot: Slot,
+	committees: &CommitteeCache<C>,
+	is_known_block: F,
+) -> Result<(), Rejection> {
+	let data = &attestation.data;
+
+	if data.slot > current_slot {
+		return Err(Rejection::FutureSlot { slot: data.slot, current_slot })
+	}
+	if data.slot + C::slots_per_epoch() < current_slot {
+		return Err(Rejection::ExpiredSlot { slot: data.slot, current_slot })
+	}
+	let epoch = utils::epoch_of_slot::<C>(data.slot);
+	if data.target.epoch != epoch {
+		return Err(Rejection::TargetEpochMismatch)
+	}
+
+	let count = committees.committee_count(epoch).ok_or(Rejection::UnknownEpoch(epoch))?;
+	if data.index >= count {
+		return Err(Rejection::CommitteeIndexOutOfRange { index: data.index, count })
+	}
+	let expected = committees.committee_size(data.slot, data.index)
+		.ok_or(Rejection::UnknownEpoch(epoch))?;
+	for actual in &[attestation.aggregation_bits.len(), attestation.custody_bits.len()] {
+		if *actual != expected {
+			return Err(Rejection::BitfieldLengthMismatch { expected, actual: *actual })
+		}
+	}
+	if !attestation.aggregation_bits.iter().any(|bit| *bit) {
+		return Err(Rejection::EmptyAggregation)
+	}
+
+	if !is_known_block(&data.beacon_block_root) {
+		return Err(Rejection::UnknownBlockRoot(data.beacon_block_root))
+	}
+
+	Ok(())
+}
@@ -0,0 +1,229 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Regression vectors of the state transition.
+//!
+//! A vector is a block and its pre-state, recorded at an interesting height
+//! of a real or simulated chain, together with the post-state root computed
+//! when it was recorded. Vectors are kept under `res/regression/<config>`,
+//! one directory each, and are replayed through the `Executor` by the
+//! regression test, so that any change of a recorded post-state root fails
+//! the build.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use beacon::{BeaconState, BLSConfig, Config};
+use beacon::primitives::H256;
+use beacon::utils::epoch_of_slot;
+use blockchain::{BlockExecutor, AsExternalities};
+use bm_le::tree_root;
+use log::info;
+use crate::{Block, Executor, MemoryState, StateExternalities};
+use crate::era::{self, EraReader, Record};
+
+/// Directory of the regression vectors, relative to the crate root.
+pub const VECTORS_DIR: &str = "res/regression";
+
+const PRE_FILE: &str = "pre.ssz";
+const BLOCK_FILE: &str = "block.ssz";
+const POST_ROOT_FILE: &str = "post_root";
+
+/// Regression vector errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Reading or writing vector files failed.
+	Io(io::Error),
+	/// A vector file could not be decoded.
+	Decode(String),
+	/// A recorded block is invalid on top of its pre-state.
+	InvalidBlock(String),
+	/// Reading the era files to record from failed.
+	Era(era::Error),
+	/// The replayed state differs from the state stored in an era file.
+	StateMismatch(u64),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Vector file IO failed: {}", e),
+			Error::Decode(e) => write!(f, "Decoding vector failed: {}", e),
+			Error::InvalidBlock(e) => write!(f, "Recorded block is invalid: {}", e),
+			Error::Era(e) => write!(f, "{}", e),
+			Error::StateMismatch(index) =>
+				write!(f, "Replayed state differs from the state of era {}", index),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			Error::Era(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Self {
+		Error::Io(e)
+	}
+}
+
+impl From<era::Error> for Error {
+	fn from(e: era::Error) -> Self {
+		Error::Era(e)
+	}
+}
+
+/// Directory of the regression vectors of a config, within `root`.
+pub fn vectors_dir<P: AsRef<Path>>(root: P, config_name: &str) -> PathBuf {
+	root.as_ref().join(VECTORS_DIR).join(config_name)
+}
+
+/// A block, its pre-state and the expected post-state root.
+pub struct Vector<C: Config> {
+	/// Name of the vector, and of its directory.
+	pub name: String,
+	/// State the block is applied on.
+	pub pre: BeaconState<C>,
+	/// Block to apply.
+	pub block: Block<C>,
+	/// Root of the state after applying the block.
+	pub post_root: H256,
+}
+
+impl<C: Config> Vector<C> {
+	/// Read the vector stored in `dir`.
+	pub fn read<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+		let dir = dir.as_ref();
+		let name = dir.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		let pre = ssz::Decode::decode(&fs::read(dir.join(PRE_FILE))?[..])
+			.map_err(|e| Error::Decode(format!("{}: pre-state {:?}", name, e)))?;
+		let block = ssz::Decode::decode(&fs::read(dir.join(BLOCK_FILE))?[..])
+			.map_err(|e| Error::Decode(format!("{}: block {:?}", name, e)))?;
+		let post_root = fs::read_to_string(dir.join(POST_ROOT_FILE))?;
+		let post_root = match hex::decode(post_root.trim().trim_start_matches("0x")) {
+			Ok(ref bytes) if bytes.len() == 32 => H256::from_slice(bytes),
+			_ => return Err(Error::Decode(format!("{}: invalid post-state root", name))),
+		};
+
+		Ok(Self { name, pre, block, post_root })
+	}
+
+	/// Write the vector into its directory within `dir`, replacing any
+	/// vector of the same name.
+	pub fn write<P: AsRef<Path>>(&self, dir: P) -> Result<PathBuf, Error> {
+		let path = dir.as_ref().join(&self.name);
+		fs::create_dir_all(&path)?;
+		fs::write(path.join(PRE_FILE), ssz::Encode::encode(&self.pre))?;
+		fs::write(path.join(BLOCK_FILE), ssz::Encode::encode(&self.block))?;
+		fs::write(path.join(POST_ROOT_FILE), format!("0x{}\n", hex::encode(self.post_root.as_bytes())))?;
+		Ok(path)
+	}
+
+	/// Apply the block on the pre-state through the `Executor`, returning the
+	/// post-state root.
+	pub fn replay<BLS: BLSConfig>(&self) -> Result<H256, crate::Error> {
+		let mut state = MemoryState::from(self.pre.clone());
+		Executor::<C, BLS>::new().execute_block(&self.block, state.as_externalities())?;
+		Ok(state.state_root())
+	}
+}
+
+/// Read all vectors in `dir`, ordered by name. The directory must exist.
+pub fn read_vectors<C: Config, P: AsRef<Path>>(dir: P) -> Result<Vec<Vector<C>>, Error> {
+	let mut paths = Vec::new();
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			paths.push(path);
+		}
+	}
+	paths.sort();
+	paths.into_iter().map(Vector::read).collect()
+}
+
+/// Whether a block at `slot` on a state at `pre_slot` is worth recording.
+/// Such blocks cross an epoch boundary, so their transition includes epoch
+/// processing.
+pub fn is_interesting<C: Config>(pre_slot: u64, slot: u64) -> bool {
+	epoch_of_slot::<C>(pre_slot) != epoch_of_slot::<C>(slot)
+}
+
+/// Record vectors from the era files in `era_dir` into `out`, returning the
+/// number recorded. Blocks are replayed from the state of the first era,
+/// and at most `max` of those crossing an epoch boundary are recorded.
+pub fn record_from_eras<C: Config, BLS: BLSConfig>(
+	era_dir: &Path,
+	out: &Path,
+	max: usize,
+) -> Result<usize, Error> {
+	let mut state: Option<BeaconState<C>> = None;
+	let mut recorded = 0;
+
+	for path in era::era_files(era_dir)? {
+		let mut reader = EraReader::<C>::open(&path)?;
+		while let Some(record) = reader.next_record()? {
+			match record {
+				Record::Block(block) => {
+					let state = match state.as_mut() {
+						Some(state) => state,
+						None => continue,
+					};
+					let pre = if recorded < max && is_interesting::<C>(state.slot, block.0.slot) {
+						Some(state.clone())
+					} else {
+						None
+					};
+
+					beacon::execute_block::<C, BLS>(&block.0, state)
+						.map_err(|e| Error::InvalidBlock(format!("slot {}: {:?}", block.0.slot, e)))?;
+
+					if let Some(pre) = pre {
+						let vector = Vector {
+							name: format!("slot-{:08}", block.0.slot),
+							pre,
+							block,
+							post_root: tree_root::<C::Digest, _>(&*state),
+						};
+						let path = vector.write(out)?;
+						info!("Recorded regression vector {:?}", path);
+						recorded += 1;
+					}
+				},
+				Record::State(era_state) => match state.as_ref() {
+					Some(state) => if tree_root::<C::Digest, _>(state) != tree_root::<C::Digest, _>(&era_state) {
+						return Err(Error::StateMismatch(reader.index()))
+					},
+					None => state = Some(era_state),
+				},
+			}
+		}
+
+		if recorded >= max {
+			break
+		}
+	}
+
+	Ok(recorded)
+}
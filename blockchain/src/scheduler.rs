@@ -0,0 +1,279 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validator duty scheduling.
+//!
+//! Each kind of validator duty implements `Duty`, declaring at which point
+//! within the slot it is performed. The scheduler runs every registered
+//! duty once per slot, in order of their offsets, so that new kinds of
+//! duties can be added without touching the scheduling loop. When duties
+//! happen within the slot is governed by `SlotTiming`, and work that has to
+//! be done by some point of the slot is bounded by a `Deadline`.
+
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+use beacon::primitives::Slot;
+use lmd_ghost::clock::SlotClock;
+use log::trace;
+use std::time::Instant;
+
+/// Point within a slot, as a fraction of the slot duration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlotOffset {
+	numerator: u32,
+	denominator: u32,
+}
+
+impl SlotOffset {
+	/// Start of the slot, when blocks are proposed.
+	pub const START: SlotOffset = SlotOffset::new(0, 1);
+	/// One third into the slot, when attestations are made.
+	pub const ONE_THIRD: SlotOffset = SlotOffset::new(1, 3);
+	/// Two thirds into the slot, when aggregates are made.
+	pub const TWO_THIRDS: SlotOffset = SlotOffset::new(2, 3);
+
+	/// Offset of `numerator / denominator` of the slot.
+	pub const fn new(numerator: u32, denominator: u32) -> Self {
+		Self { numerator, denominator }
+	}
+
+	/// Time since the start of the slot.
+	pub fn within(&self, slot_duration: Duration) -> Duration {
+		slot_duration * self.numerator / self.denominator
+	}
+}
+
+impl fmt::Display for SlotOffset {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}/{}", self.numerator, self.denominator)
+	}
+}
+
+impl FromStr for SlotOffset {
+	type Err = String;
+
+	/// Parse a `numerator/denominator` fraction of the slot, below one.
+	fn from_str(s: &str) -> Result<Self, String> {
+		let mut parts = s.trim().splitn(2, '/');
+		let numerator = parts.next()
+			.and_then(|v| u32::from_str(v.trim()).ok())
+			.ok_or_else(|| format!("Invalid slot offset {}", s))?;
+		let denominator = match parts.next() {
+			Some(v) => u32::from_str(v.trim()).map_err(|_| format!("Invalid slot offset {}", s))?,
+			None => 1,
+		};
+		if denominator == 0 || numerator >= denominator {
+			return Err(format!("Slot offset {} is not within the slot", s))
+		}
+		Ok(Self::new(numerator, denominator))
+	}
+}
+
+/// Points within the slot at which validators act, and until which
+/// messages for the slot are timely.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SlotTiming {
+	/// When blocks are proposed.
+	pub proposal: SlotOffset,
+	/// When attestations are made.
+	pub attestation: SlotOffset,
+	/// When aggregates are made.
+	pub aggregation: SlotOffset,
+	/// Until when blocks received over gossip are timely for fork choice.
+	pub gossip_cutoff: SlotOffset,
+	/// Until when a proposed block may be built, before falling back to a
+	/// minimal block.
+	pub build_deadline: SlotOffset,
+}
+
+impl Default for SlotTiming {
+	fn default() -> Self {
+		Self {
+			proposal: SlotOffset::START,
+			attestation: SlotOffset::ONE_THIRD,
+			aggregation: SlotOffset::TWO_THIRDS,
+			gossip_cutoff: SlotOffset::ONE_THIRD,
+			build_deadline: SlotOffset::new(1, 6),
+		}
+	}
+}
+
+impl FromStr for SlotTiming {
+	type Err = String;
+
+	/// Parse comma separated `name=offset` overrides of the default timing,
+	/// such as `attestation=1/2,aggregation=3/4`. Names are `proposal`,
+	/// `attestation`, `aggregation`, `gossip-cutoff` and `build-deadline`.
+	fn from_str(s: &str) -> Result<Self, String> {
+		let mut timing = Self::default();
+		for part in s.split(',').filter(|part| !part.trim().is_empty()) {
+			let mut kv = part.splitn(2, '=');
+			let name = kv.next().unwrap_or_default().trim();
+			let offset = SlotOffset::from_str(
+				kv.next().ok_or_else(|| format!("Missing offset of {}", name))?
+			)?;
+			match name {
+				"proposal" => timing.proposal = offset,
+				"attestation" => timing.attestation = offset,
+				"aggregation" => timing.aggregation = offset,
+				"gossip-cutoff" => timing.gossip_cutoff = offset,
+				"build-deadline" => timing.build_deadline = offset,
+				_ => return Err(format!("Unknown slot timing {}", name)),
+			}
+		}
+		Ok(timing)
+	}
+}
+
+impl fmt::Display for SlotTiming {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f, "proposal={},attestation={},aggregation={},gossip-cutoff={},build-deadline={}",
+			self.proposal, self.attestation, self.aggregation, self.gossip_cutoff,
+			self.build_deadline,
+		)
+	}
+}
+
+/// Instant by which some work within a slot has to be done.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Deadline {
+	at: Instant,
+}
+
+impl Deadline {
+	/// Deadline at the given instant.
+	pub fn at(at: Instant) -> Self {
+		Self { at }
+	}
+
+	/// Deadline at `offset` into the current slot of `clock`. Already
+	/// expired if the offset has passed, or the clock has not started.
+	pub fn within_slot<S: SlotClock>(clock: &S, offset: SlotOffset) -> Self {
+		let remaining = clock.slot_elapsed()
+			.and_then(|elapsed| offset.within(clock.slot_duration()).checked_sub(elapsed))
+			.unwrap_or_default();
+		Self::at(Instant::now() + remaining)
+	}
+
+	/// Whether the deadline has passed.
+	pub fn is_expired(&self) -> bool {
+		Instant::now() >= self.at
+	}
+
+	/// Time left until the deadline.
+	pub fn remaining(&self) -> Duration {
+		self.at.saturating_duration_since(Instant::now())
+	}
+}
+
+/// A validator duty performed at most once per slot.
+pub trait Duty<Ctx> {
+	/// Name of the duty, for logging.
+	fn name(&self) -> &'static str;
+	/// Point within the slot at which the duty is performed.
+	fn offset(&self) -> SlotOffset;
+	/// Perform the duty for `slot`, if any of the validators has one.
+	fn perform(&mut self, slot: Slot, context: &mut Ctx);
+}
+
+struct ScheduledDuty<Ctx> {
+	duty: Box<dyn Duty<Ctx>>,
+	last_slot: Option<Slot>,
+}
+
+/// Scheduler of validator duties.
+///
+/// A duty is performed once its offset is reached in the current slot. A
+/// duty not performed before its slot ends, because the scheduler was not
+/// ticked in time, is skipped.
+pub struct DutyScheduler<Ctx, S: SlotClock> {
+	clock: S,
+	duties: Vec<ScheduledDuty<Ctx>>,
+}
+
+impl<Ctx, S: SlotClock> DutyScheduler<Ctx, S> {
+	/// Create a scheduler without duties.
+	pub fn new(clock: S) -> Self {
+		Self { clock, duties: Vec::new() }
+	}
+
+	/// Register a duty.
+	pub fn with_duty<D: Duty<Ctx> + 'static>(mut self, duty: D) -> Self {
+		self.register(duty);
+		self
+	}
+
+	/// Register a duty.
+	pub fn register<D: Duty<Ctx> + 'static>(&mut self, duty: D) {
+		let slot_duration = self.clock.slot_duration();
+		let offset = duty.offset().within(slot_duration);
+		let position = self.duties.iter()
+			.position(|scheduled| scheduled.duty.offset().within(slot_duration) > offset)
+			.unwrap_or(self.duties.len());
+		self.duties.insert(position, ScheduledDuty { duty: Box::new(duty), last_slot: None });
+	}
+
+	/// Perform all duties due in the current slot. Returns the number of
+	/// duties performed.
+	pub fn tick(&mut self, context: &mut Ctx) -> usize {
+		let (slot, elapsed) = match (self.clock.current_slot(), self.clock.slot_elapsed()) {
+			(Some(slot), Some(elapsed)) => (slot, elapsed),
+			_ => return 0,
+		};
+		let slot_duration = self.clock.slot_duration();
+
+		let mut performed = 0;
+		for scheduled in &mut self.duties {
+			if scheduled.last_slot.map(|last| last >= slot).unwrap_or(false) ||
+				scheduled.duty.offset().within(slot_duration) > elapsed
+			{
+				continue
+			}
+
+			trace!("Performing {} duty for slot {}", scheduled.duty.name(), slot);
+			scheduled.duty.perform(slot, context);
+			scheduled.last_slot = Some(slot);
+			performed += 1;
+		}
+		performed
+	}
+
+	/// Time until the next duty is due.
+	pub fn time_to_next(&self) -> Duration {
+		let slot_duration = self.clock.slot_duration();
+		let (slot, elapsed) = match (self.clock.current_slot(), self.clock.slot_elapsed()) {
+			(Some(slot), Some(elapsed)) => (slot, elapsed),
+			_ => return slot_duration,
+		};
+
+		self.duties.iter()
+			.map(|scheduled| {
+				let offset = scheduled.duty.offset().within(slot_duration);
+				let done = scheduled.last_slot.map(|last| last >= slot).unwrap_or(false);
+				if !done && offset > elapsed {
+					offset - elapsed
+				} else if !done {
+					Duration::from_secs(0)
+				} else {
+					(slot_duration + offset).checked_sub(elapsed).unwrap_or_default()
+				}
+			})
+			.min()
+			.unwrap_or(slot_duration)
+	}
+}
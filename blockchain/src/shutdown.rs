@@ -0,0 +1,38 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Graceful shutdown on SIGINT and SIGTERM.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+	REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Install SIGINT and SIGTERM handlers that request shutdown. Returns the
+/// flag they set, for the main loop to poll so that it can return and run
+/// destructors rather than exit the process.
+pub fn install() -> &'static AtomicBool {
+	// Safe, as the handler only stores to an atomic.
+	unsafe {
+		libc::signal(libc::SIGINT, on_signal as libc::sighandler_t);
+		libc::signal(libc::SIGTERM, on_signal as libc::sighandler_t);
+	}
+
+	&REQUESTED
+}
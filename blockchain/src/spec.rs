@@ -0,0 +1,433 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Custom chain specs loaded from YAML.
+//!
+//! A spec file uses the standard spec config format, one `KEY: value` per
+//! line. List lengths and other constants used as types are fixed by the
+//! preset the node is compiled with, and a spec may only restate them. All
+//! other constants are read at runtime from the installed spec through
+//! `CustomConfig`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use beacon::Config;
+use beacon::primitives::Uint;
+use typenum::Unsigned;
+use log::warn;
+
+/// Spec loading errors.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SpecError {
+	/// Reading the spec file failed.
+	Io(std::io::Error),
+	/// The spec file is not valid YAML.
+	Yaml(serde_yaml::Error),
+	/// A value could not be parsed.
+	InvalidValue(String),
+	/// The spec is for another preset base.
+	PresetBaseMismatch(String),
+	/// A compile-time constant differs from the preset.
+	PresetMismatch {
+		/// Key of the constant.
+		key: &'static str,
+		/// Value of the preset.
+		expected: Uint,
+		/// Value of the spec.
+		actual: Uint,
+	},
+	/// Constants are inconsistent with each other.
+	Inconsistent(&'static str),
+	/// A spec has already been installed.
+	AlreadyInstalled,
+}
+
+impl fmt::Display for SpecError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			SpecError::Io(e) => write!(f, "Reading spec failed: {}", e),
+			SpecError::Yaml(e) => write!(f, "Parsing spec failed: {}", e),
+			SpecError::InvalidValue(key) => write!(f, "Invalid value of {}", key),
+			SpecError::PresetBaseMismatch(name) => write!(f, "Spec is for preset base {}", name),
+			SpecError::PresetMismatch { key, expected, actual } => write!(
+				f, "{} is {} but the preset is compiled with {}", key, actual, expected,
+			),
+			SpecError::Inconsistent(what) => write!(f, "Inconsistent spec: {}", what),
+			SpecError::AlreadyInstalled => write!(f, "A spec is already installed"),
+		}
+	}
+}
+
+impl std::error::Error for SpecError {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			SpecError::Io(e) => Some(e),
+			SpecError::Yaml(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+macro_rules! chain_spec {
+	( $( $field:ident: $ty:ty = $key:literal, )* ) => {
+		/// Runtime constants of a chain.
+		#[derive(Clone, Debug, Eq, PartialEq)]
+		pub struct ChainSpec {
+			/// Name of the preset providing compile-time constants.
+			pub preset_base: String,
+			/// Chain id of the eth1 chain holding the deposit contract.
+			pub deposit_chain_id: Option<u64>,
+			/// Network id of the eth1 chain holding the deposit contract.
+			pub deposit_network_id: Option<u64>,
+			/// Address of the deposit contract.
+			pub deposit_contract_address: Option<[u8; 20]>,
+			$(
+				#[allow(missing_docs)]
+				pub $field: $ty,
+			)*
+		}
+
+		impl ChainSpec {
+			/// Runtime constants of a compiled config.
+			pub fn from_config<C: Config>(preset_base: &str) -> Self {
+				Self {
+					preset_base: preset_base.to_string(),
+					deposit_chain_id: None,
+					deposit_network_id: None,
+					deposit_contract_address: None,
+					$( $field: C::$field(), )*
+				}
+			}
+
+			/// Whether all constants are those of the compiled config `C`.
+			/// Deposit contract details are not part of a config, and are
+			/// ignored.
+			pub fn matches_config<C: Config>(&self) -> bool {
+				true $( && self.$field == C::$field() )*
+			}
+
+			fn set(&mut self, key: &str, value: &serde_yaml::Value) -> Result<bool, SpecError> {
+				match key {
+					$(
+						$key => {
+							self.$field = parse_uint(key, value)? as $ty;
+							Ok(true)
+						},
+					)*
+					_ => Ok(false),
+				}
+			}
+		}
+
+		impl<B: Config> Config for CustomConfig<B> {
+			type Digest = B::Digest;
+			type MaxValidatorsPerCommittee = B::MaxValidatorsPerCommittee;
+			type SlotsPerHistoricalRoot = B::SlotsPerHistoricalRoot;
+			type MaxProposerSlashings = B::MaxProposerSlashings;
+			type MaxAttesterSlashings = B::MaxAttesterSlashings;
+			type MaxAttestations = B::MaxAttestations;
+			type MaxDeposits = B::MaxDeposits;
+			type MaxVoluntaryExits = B::MaxVoluntaryExits;
+			type HistoricalRootsLimit = B::HistoricalRootsLimit;
+			type ShardCount = B::ShardCount;
+			type SlotsPerEpoch = B::SlotsPerEpoch;
+			type SlotsPerEth1VotingPeriod = B::SlotsPerEth1VotingPeriod;
+			type ValidatorRegistryLimit = B::ValidatorRegistryLimit;
+			type EpochsPerHistoricalVector = B::EpochsPerHistoricalVector;
+			type EpochsPerSlashingsVector = B::EpochsPerSlashingsVector;
+			type MaxAttestationsPerEpoch = B::MaxAttestationsPerEpoch;
+
+			$( fn $field() -> $ty { spec().$field } )*
+		}
+	}
+}
+
+chain_spec! {
+	max_committees_per_slot: Uint = "MAX_COMMITTEES_PER_SLOT",
+	target_committee_size: Uint = "TARGET_COMMITTEE_SIZE",
+	min_per_epoch_churn_limit: Uint = "MIN_PER_EPOCH_CHURN_LIMIT",
+	churn_limit_quotient: Uint = "CHURN_LIMIT_QUOTIENT",
+	shuffle_round_count: Uint = "SHUFFLE_ROUND_COUNT",
+	min_genesis_active_validator_count: Uint = "MIN_GENESIS_ACTIVE_VALIDATOR_COUNT",
+	min_genesis_time: Uint = "MIN_GENESIS_TIME",
+	min_deposit_amount: Uint = "MIN_DEPOSIT_AMOUNT",
+	max_effective_balance: Uint = "MAX_EFFECTIVE_BALANCE",
+	ejection_balance: Uint = "EJECTION_BALANCE",
+	effective_balance_increment: Uint = "EFFECTIVE_BALANCE_INCREMENT",
+	genesis_slot: Uint = "GENESIS_SLOT",
+	genesis_epoch: Uint = "GENESIS_EPOCH",
+	bls_withdrawal_prefix_byte: u8 = "BLS_WITHDRAWAL_PREFIX",
+	seconds_per_slot: Uint = "SECONDS_PER_SLOT",
+	min_attestation_inclusion_delay: Uint = "MIN_ATTESTATION_INCLUSION_DELAY",
+	min_seed_lookahead: Uint = "MIN_SEED_LOOKAHEAD",
+	max_seed_lookahead: Uint = "MAX_SEED_LOOKAHEAD",
+	eth1_follow_distance: Uint = "ETH1_FOLLOW_DISTANCE",
+	seconds_per_eth1_block: Uint = "SECONDS_PER_ETH1_BLOCK",
+	min_validator_withdrawability_delay: Uint = "MIN_VALIDATOR_WITHDRAWABILITY_DELAY",
+	persistent_committee_period: Uint = "PERSISTENT_COMMITTEE_PERIOD",
+	max_epochs_per_crosslink: Uint = "MAX_EPOCHS_PER_CROSSLINK",
+	min_epochs_to_inactivity_penalty: Uint = "MIN_EPOCHS_TO_INACTIVITY_PENALTY",
+	base_reward_factor: Uint = "BASE_REWARD_FACTOR",
+	whistleblower_reward_quotient: Uint = "WHISTLEBLOWER_REWARD_QUOTIENT",
+	proposer_reward_quotient: Uint = "PROPOSER_REWARD_QUOTIENT",
+	inactivity_penalty_quotient: Uint = "INACTIVITY_PENALTY_QUOTIENT",
+	min_slashing_penalty_quotient: Uint = "MIN_SLASHING_PENALTY_QUOTIENT",
+	domain_beacon_proposer: u32 = "DOMAIN_BEACON_PROPOSER",
+	domain_beacon_attester: u32 = "DOMAIN_BEACON_ATTESTER",
+	domain_randao: u32 = "DOMAIN_RANDAO",
+	domain_deposit: u32 = "DOMAIN_DEPOSIT",
+	domain_voluntary_exit: u32 = "DOMAIN_VOLUNTARY_EXIT",
+}
+
+/// Compile-time constants of a config, with their spec keys.
+fn preset_constants<C: Config>() -> Vec<(&'static str, Uint)> {
+	vec![
+		("MAX_VALIDATORS_PER_COMMITTEE", C::MaxValidatorsPerCommittee::to_u64()),
+		("SLOTS_PER_HISTORICAL_ROOT", C::SlotsPerHistoricalRoot::to_u64()),
+		("MAX_PROPOSER_SLASHINGS", C::MaxProposerSlashings::to_u64()),
+		("MAX_ATTESTER_SLASHINGS", C::MaxAttesterSlashings::to_u64()),
+		("MAX_ATTESTATIONS", C::MaxAttestations::to_u64()),
+		("MAX_DEPOSITS", C::MaxDeposits::to_u64()),
+		("MAX_VOLUNTARY_EXITS", C::MaxVoluntaryExits::to_u64()),
+		("HISTORICAL_ROOTS_LIMIT", C::HistoricalRootsLimit::to_u64()),
+		("SHARD_COUNT", C::ShardCount::to_u64()),
+		("SLOTS_PER_EPOCH", C::SlotsPerEpoch::to_u64()),
+		("SLOTS_PER_ETH1_VOTING_PERIOD", C::SlotsPerEth1VotingPeriod::to_u64()),
+		("VALIDATOR_REGISTRY_LIMIT", C::ValidatorRegistryLimit::to_u64()),
+		("EPOCHS_PER_HISTORICAL_VECTOR", C::EpochsPerHistoricalVector::to_u64()),
+		("EPOCHS_PER_SLASHINGS_VECTOR", C::EpochsPerSlashingsVector::to_u64()),
+	]
+}
+
+fn parse_address(key: &str, value: &serde_yaml::Value) -> Result<[u8; 20], SpecError> {
+	match value {
+		// Unquoted hex small enough to fit is read by YAML as an integer.
+		serde_yaml::Value::Number(n) => {
+			let n = n.as_u64().ok_or_else(|| SpecError::InvalidValue(key.to_string()))?;
+			let mut address = [0u8; 20];
+			address[12..].copy_from_slice(&n.to_be_bytes());
+			Ok(address)
+		},
+		serde_yaml::Value::String(s) => crate::eth1_endpoints::parse_address(s)
+			.map_err(|_| SpecError::InvalidValue(key.to_string())),
+		_ => Err(SpecError::InvalidValue(key.to_string())),
+	}
+}
+
+fn parse_uint(key: &str, value: &serde_yaml::Value) -> Result<Uint, SpecError> {
+	let invalid = || SpecError::InvalidValue(key.to_string());
+	match value {
+		serde_yaml::Value::Number(n) => n.as_u64().ok_or_else(invalid),
+		serde_yaml::Value::String(s) if s.starts_with("0x") => {
+			let bytes = hex::decode(&s[2..]).map_err(|_| invalid())?;
+			if bytes.len() > 8 {
+				return Err(invalid())
+			}
+			// Domains and prefixes are little endian byte strings.
+			Ok(bytes.iter().rev().fold(0, |acc, b| (acc << 8) | *b as Uint))
+		},
+		serde_yaml::Value::String(s) => s.parse().map_err(|_| invalid()),
+		_ => Err(invalid()),
+	}
+}
+
+impl ChainSpec {
+	/// Parse a spec from YAML, on top of the runtime constants of the
+	/// compiled config `B` named `preset_base`, and validate it against `B`.
+	pub fn from_yaml<B: Config>(raw: &str, preset_base: &str) -> Result<Self, SpecError> {
+		let values = serde_yaml::from_str::<BTreeMap<String, serde_yaml::Value>>(raw)
+			.map_err(SpecError::Yaml)?;
+		match values.get("PRESET_BASE") {
+			Some(serde_yaml::Value::String(name)) if name == preset_base => (),
+			Some(serde_yaml::Value::String(name)) =>
+				return Err(SpecError::PresetBaseMismatch(name.clone())),
+			Some(_) => return Err(SpecError::InvalidValue("PRESET_BASE".to_string())),
+			None => (),
+		}
+
+		let mut spec = Self::from_config::<B>(preset_base);
+		let preset = preset_constants::<B>();
+		for (key, value) in &values {
+			match key.as_str() {
+				"PRESET_BASE" => continue,
+				"DEPOSIT_CHAIN_ID" => {
+					spec.deposit_chain_id = Some(parse_uint(key, value)?);
+					continue
+				},
+				"DEPOSIT_NETWORK_ID" => {
+					spec.deposit_network_id = Some(parse_uint(key, value)?);
+					continue
+				},
+				"DEPOSIT_CONTRACT_ADDRESS" => {
+					spec.deposit_contract_address = Some(parse_address(key, value)?);
+					continue
+				},
+				_ => (),
+			}
+			if let Some((preset_key, expected)) = preset.iter().find(|(k, _)| k == key) {
+				let actual = parse_uint(key, value)?;
+				if actual != *expected {
+					return Err(SpecError::PresetMismatch { key: *preset_key, expected: *expected, actual })
+				}
+				continue
+			}
+			if !spec.set(key, value)? {
+				warn!("Ignoring unknown spec constant {}", key);
+			}
+		}
+
+		spec.validate::<B>()?;
+		Ok(spec)
+	}
+
+	/// Load a spec from a YAML file. See `from_yaml`.
+	pub fn from_file<B: Config, P: AsRef<Path>>(path: P, preset_base: &str) -> Result<Self, SpecError> {
+		let mut raw = String::new();
+		std::io::Read::read_to_string(
+			&mut BufReader::new(File::open(path).map_err(SpecError::Io)?), &mut raw,
+		).map_err(SpecError::Io)?;
+		Self::from_yaml::<B>(&raw, preset_base)
+	}
+
+	/// Check that constants are consistent with each other, and with the
+	/// compile-time constants of `B`.
+	pub fn validate<B: Config>(&self) -> Result<(), SpecError> {
+		let positive = [
+			(self.max_committees_per_slot, "MAX_COMMITTEES_PER_SLOT must be positive"),
+			(self.target_committee_size, "TARGET_COMMITTEE_SIZE must be positive"),
+			(self.churn_limit_quotient, "CHURN_LIMIT_QUOTIENT must be positive"),
+			(self.effective_balance_increment, "EFFECTIVE_BALANCE_INCREMENT must be positive"),
+			(self.seconds_per_slot, "SECONDS_PER_SLOT must be positive"),
+			(self.seconds_per_eth1_block, "SECONDS_PER_ETH1_BLOCK must be positive"),
+			(self.whistleblower_reward_quotient, "WHISTLEBLOWER_REWARD_QUOTIENT must be positive"),
+			(self.proposer_reward_quotient, "PROPOSER_REWARD_QUOTIENT must be positive"),
+			(self.inactivity_penalty_quotient, "INACTIVITY_PENALTY_QUOTIENT must be positive"),
+			(self.min_slashing_penalty_quotient, "MIN_SLASHING_PENALTY_QUOTIENT must be positive"),
+		];
+		for (value, what) in positive.iter() {
+			if *value == 0 {
+				return Err(SpecError::Inconsistent(what))
+			}
+		}
+
+		if self.min_seed_lookahead > self.max_seed_lookahead {
+			return Err(SpecError::Inconsistent("MIN_SEED_LOOKAHEAD exceeds MAX_SEED_LOOKAHEAD"))
+		}
+		if self.ejection_balance > self.max_effective_balance {
+			return Err(SpecError::Inconsistent("EJECTION_BALANCE exceeds MAX_EFFECTIVE_BALANCE"))
+		}
+		if self.min_deposit_amount > self.max_effective_balance {
+			return Err(SpecError::Inconsistent("MIN_DEPOSIT_AMOUNT exceeds MAX_EFFECTIVE_BALANCE"))
+		}
+		if self.max_effective_balance % self.effective_balance_increment != 0 {
+			return Err(SpecError::Inconsistent(
+				"MAX_EFFECTIVE_BALANCE is not a multiple of EFFECTIVE_BALANCE_INCREMENT"
+			))
+		}
+		if self.target_committee_size > B::MaxValidatorsPerCommittee::to_u64() {
+			return Err(SpecError::Inconsistent(
+				"TARGET_COMMITTEE_SIZE exceeds MAX_VALIDATORS_PER_COMMITTEE"
+			))
+		}
+		if self.genesis_slot % B::SlotsPerEpoch::to_u64() != 0 ||
+			self.genesis_epoch != self.genesis_slot / B::SlotsPerEpoch::to_u64()
+		{
+			return Err(SpecError::Inconsistent("GENESIS_EPOCH does not start at GENESIS_SLOT"))
+		}
+		if self.max_seed_lookahead + 1 >= B::EpochsPerHistoricalVector::to_u64() {
+			return Err(SpecError::Inconsistent(
+				"MAX_SEED_LOOKAHEAD does not fit in EPOCHS_PER_HISTORICAL_VECTOR"
+			))
+		}
+		let domains = [
+			self.domain_beacon_proposer, self.domain_beacon_attester, self.domain_randao,
+			self.domain_deposit, self.domain_voluntary_exit,
+		];
+		for (i, domain) in domains.iter().enumerate() {
+			if domains[..i].contains(domain) {
+				return Err(SpecError::Inconsistent("Signature domains are not distinct"))
+			}
+		}
+
+		Ok(())
+	}
+}
+
+static SPEC: AtomicPtr<ChainSpec> = AtomicPtr::new(std::ptr::null_mut());
+
+/// Install the spec read by `CustomConfig`. Can only be done once per
+/// process, before any `CustomConfig` constant is used.
+pub fn install(spec: ChainSpec) -> Result<(), SpecError> {
+	let spec = Box::into_raw(Box::new(spec));
+	match SPEC.compare_exchange(std::ptr::null_mut(), spec, Ordering::AcqRel, Ordering::Acquire) {
+		Ok(_) => Ok(()),
+		Err(_) => {
+			// Safe, as the pointer was created above and never shared.
+			drop(unsafe { Box::from_raw(spec) });
+			Err(SpecError::AlreadyInstalled)
+		},
+	}
+}
+
+/// The installed spec.
+///
+/// Panics if no spec has been installed.
+pub fn spec() -> &'static ChainSpec {
+	installed().expect("Chain spec is used before being installed")
+}
+
+/// The installed spec, if any.
+pub fn installed() -> Option<&'static ChainSpec> {
+	let spec = SPEC.load(Ordering::Acquire);
+	// Safe, as an installed spec is leaked and never freed.
+	unsafe { spec.as_ref() }
+}
+
+/// Config taking compile-time constants from the preset `B`, and all other
+/// constants from the installed spec.
+pub struct CustomConfig<B: Config>(PhantomData<B>);
+
+impl<B: Config> Default for CustomConfig<B> {
+	fn default() -> Self {
+		CustomConfig(PhantomData)
+	}
+}
+
+impl<B: Config> Clone for CustomConfig<B> {
+	fn clone(&self) -> Self {
+		CustomConfig(PhantomData)
+	}
+}
+
+impl<B: Config> PartialEq for CustomConfig<B> {
+	fn eq(&self, _other: &Self) -> bool {
+		true
+	}
+}
+
+impl<B: Config> Eq for CustomConfig<B> { }
+
+impl<B: Config> fmt::Debug for CustomConfig<B> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "CustomConfig({:?})", B::default())
+	}
+}
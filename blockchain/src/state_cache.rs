@@ -0,0 +1,158 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! In-memory cache of recently used post-states, in front of the store.
+
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+use beacon::primitives::H256;
+use beacon::types::{Validator, PendingAttestation, Eth1Data};
+use beacon::{BeaconState, Config};
+
+/// Default memory budget of the state cache, in bytes.
+pub const DEFAULT_STATE_CACHE_SIZE: usize = 256 * 1024 * 1024;
+
+/// Approximate in-memory size of a state, in bytes.
+pub fn state_size<C: Config>(state: &BeaconState<C>) -> usize {
+	size_of::<BeaconState<C>>() +
+		(state.block_roots.len() + state.state_roots.len() + state.historical_roots.len() +
+		 state.randao_mixes.len()) * size_of::<H256>() +
+		state.eth1_data_votes.len() * size_of::<Eth1Data>() +
+		state.validators.len() * size_of::<Validator>() +
+		(state.balances.len() + state.slashings.len()) * size_of::<u64>() +
+		(state.previous_epoch_attestations.len() + state.current_epoch_attestations.len()) *
+			size_of::<PendingAttestation<C>>()
+}
+
+struct Entry<S> {
+	state: S,
+	size: usize,
+	last_used: u64,
+}
+
+/// Cache of post-states keyed by block root, evicting the least recently
+/// used states once over its memory budget. Pinned states, such as the head
+/// and justified states, are never evicted.
+pub struct StateCache<S> {
+	max_bytes: usize,
+	used_bytes: usize,
+	clock: u64,
+	entries: HashMap<H256, Entry<S>>,
+	pinned: HashSet<H256>,
+	hits: u64,
+	misses: u64,
+}
+
+/// State cache shared between clones of a backend.
+pub type SharedStateCache<S> = Arc<Mutex<StateCache<S>>>;
+
+impl<S: Clone> StateCache<S> {
+	/// Create a new cache with a memory budget of `max_bytes`.
+	pub fn new(max_bytes: usize) -> Self {
+		Self {
+			max_bytes,
+			used_bytes: 0,
+			clock: 0,
+			entries: HashMap::new(),
+			pinned: HashSet::new(),
+			hits: 0,
+			misses: 0,
+		}
+	}
+
+	/// Cached post-state of the block at `root`.
+	pub fn get(&mut self, root: &H256) -> Option<S> {
+		self.clock += 1;
+		match self.entries.get_mut(root) {
+			Some(entry) => {
+				entry.last_used = self.clock;
+				self.hits += 1;
+				Some(entry.state.clone())
+			},
+			None => {
+				self.misses += 1;
+				None
+			},
+		}
+	}
+
+	/// Cache the post-state of the block at `root`, of approximately `size`
+	/// bytes.
+	pub fn insert(&mut self, root: H256, state: S, size: usize) {
+		self.clock += 1;
+		if let Some(old) = self.entries.insert(root, Entry { state, size, last_used: self.clock }) {
+			self.used_bytes -= old.size;
+		}
+		self.used_bytes += size;
+		self.evict();
+	}
+
+	/// Remove the post-state of the block at `root`.
+	pub fn remove(&mut self, root: &H256) {
+		if let Some(old) = self.entries.remove(root) {
+			self.used_bytes -= old.size;
+		}
+	}
+
+	/// Change the memory budget, evicting states if over it.
+	pub fn set_max_bytes(&mut self, max_bytes: usize) {
+		self.max_bytes = max_bytes;
+		self.evict();
+	}
+
+	/// Replace the set of pinned states, evicting formerly pinned ones if
+	/// over budget.
+	pub fn set_pinned<I: IntoIterator<Item=H256>>(&mut self, roots: I) {
+		self.pinned = roots.into_iter().collect();
+		self.evict();
+	}
+
+	/// Number of cached states.
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Whether no state is cached.
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Approximate memory used by cached states, in bytes.
+	pub fn used_bytes(&self) -> usize {
+		self.used_bytes
+	}
+
+	/// Number of cache hits and misses.
+	pub fn hits_and_misses(&self) -> (u64, u64) {
+		(self.hits, self.misses)
+	}
+
+	fn evict(&mut self) {
+		while self.used_bytes > self.max_bytes {
+			let pinned = &self.pinned;
+			let oldest = self.entries.iter()
+				.filter(|(root, _)| !pinned.contains(root))
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(root, _)| *root);
+
+			match oldest {
+				Some(root) => self.remove(&root),
+				None => break,
+			}
+		}
+	}
+}
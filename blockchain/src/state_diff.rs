@@ -0,0 +1,157 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Field level comparison of beacon states.
+//!
+//! When our post-state root disagrees with another client's, both states
+//! can be dumped and compared here to find the fields that differ, down to
+//! single list elements and validator fields.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use beacon::{BeaconState, Config};
+use beacon::types::Validator;
+
+/// A differing field.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Difference {
+	/// Path of the field, such as `validators[3].slashed`.
+	pub path: String,
+	/// Value in the first state.
+	pub left: String,
+	/// Value in the second state.
+	pub right: String,
+}
+
+impl fmt::Display for Difference {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}: {} != {}", self.path, self.left, self.right)
+	}
+}
+
+/// State diff errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Reading a state file failed.
+	Io(std::io::Error),
+	/// A state could not be decoded.
+	Decode(String),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e) => write!(f, "Reading state failed: {}", e),
+			Error::Decode(e) => write!(f, "Decoding state failed: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e) => Some(e),
+			Error::Decode(_) => None,
+		}
+	}
+}
+
+/// Read an ssz encoded state from a file.
+pub fn read_state<C: Config, P: AsRef<Path>>(path: P) -> Result<BeaconState<C>, Error> {
+	let data = fs::read(path).map_err(Error::Io)?;
+	ssz::Decode::decode(&data[..]).map_err(|e| Error::Decode(format!("{:?}", e)))
+}
+
+/// Compare two states field by field. Returns an empty list if they are equal.
+pub fn diff_states<C: Config>(a: &BeaconState<C>, b: &BeaconState<C>) -> Vec<Difference> {
+	let mut diffs = Vec::new();
+
+	value(&mut diffs, "genesis_time", &a.genesis_time, &b.genesis_time);
+	value(&mut diffs, "slot", &a.slot, &b.slot);
+	value(&mut diffs, "fork", &a.fork, &b.fork);
+	value(&mut diffs, "latest_block_header", &a.latest_block_header, &b.latest_block_header);
+	list(&mut diffs, "block_roots", &a.block_roots[..], &b.block_roots[..]);
+	list(&mut diffs, "state_roots", &a.state_roots[..], &b.state_roots[..]);
+	list(&mut diffs, "historical_roots", &a.historical_roots[..], &b.historical_roots[..]);
+	value(&mut diffs, "eth1_data", &a.eth1_data, &b.eth1_data);
+	list(&mut diffs, "eth1_data_votes", &a.eth1_data_votes[..], &b.eth1_data_votes[..]);
+	value(&mut diffs, "eth1_deposit_index", &a.eth1_deposit_index, &b.eth1_deposit_index);
+	validators(&mut diffs, &a.validators[..], &b.validators[..]);
+	list(&mut diffs, "balances", &a.balances[..], &b.balances[..]);
+	list(&mut diffs, "randao_mixes", &a.randao_mixes[..], &b.randao_mixes[..]);
+	list(&mut diffs, "slashings", &a.slashings[..], &b.slashings[..]);
+	list(
+		&mut diffs, "previous_epoch_attestations",
+		&a.previous_epoch_attestations[..], &b.previous_epoch_attestations[..],
+	);
+	list(
+		&mut diffs, "current_epoch_attestations",
+		&a.current_epoch_attestations[..], &b.current_epoch_attestations[..],
+	);
+	list(&mut diffs, "justification_bits", &a.justification_bits[..], &b.justification_bits[..]);
+	value(
+		&mut diffs, "previous_justified_checkpoint",
+		&a.previous_justified_checkpoint, &b.previous_justified_checkpoint,
+	);
+	value(
+		&mut diffs, "current_justified_checkpoint",
+		&a.current_justified_checkpoint, &b.current_justified_checkpoint,
+	);
+	value(&mut diffs, "finalized_checkpoint", &a.finalized_checkpoint, &b.finalized_checkpoint);
+
+	diffs
+}
+
+fn value<T: PartialEq + fmt::Debug>(diffs: &mut Vec<Difference>, path: &str, a: &T, b: &T) {
+	if a != b {
+		diffs.push(Difference {
+			path: path.to_string(),
+			left: format!("{:?}", a),
+			right: format!("{:?}", b),
+		});
+	}
+}
+
+fn list<T: PartialEq + fmt::Debug>(diffs: &mut Vec<Difference>, path: &str, a: &[T], b: &[T]) {
+	value(diffs, &format!("{}.len", path), &a.len(), &b.len());
+	for (index, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+		value(diffs, &format!("{}[{}]", path, index), a, b);
+	}
+}
+
+fn validators(diffs: &mut Vec<Difference>, a: &[Validator], b: &[Validator]) {
+	value(diffs, "validators.len", &a.len(), &b.len());
+	for (index, (a, b)) in a.iter().zip(b.iter()).enumerate() {
+		macro_rules! field {
+			( $name:ident ) => {
+				value(
+					diffs, &format!("validators[{}].{}", index, stringify!($name)),
+					&a.$name, &b.$name,
+				)
+			}
+		}
+
+		field!(pubkey);
+		field!(withdrawal_credentials);
+		field!(effective_balance);
+		field!(slashed);
+		field!(activation_eligibility_epoch);
+		field!(activation_epoch);
+		field!(exit_epoch);
+		field!(withdrawable_epoch);
+	}
+}
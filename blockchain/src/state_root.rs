@@ -0,0 +1,183 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Incremental state root computation for block building.
+//!
+//! The state root of a built block is the Merkle root of the roots of the
+//! state fields. Between attempts at finalizing a block within one slot, the
+//! large fields, such as the validator registry and balances, are mostly
+//! unchanged, so their roots are cached and only recomputed when the field
+//! differs from the one they were computed for. Fields are compared by a
+//! keyed fingerprint of their encoding, which is much cheaper than hashing
+//! them into a tree, and does not keep copies of them around.
+
+use core::marker::PhantomData;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use beacon::{Config, BeaconState};
+use beacon::primitives::{H256, Uint};
+use beacon::types::{Validator, Eth1Data, PendingAttestation};
+use bm_le::{tree_root, CompactRef, MaxVec};
+use vecarray::VecArray;
+
+/// Number of fields of the beacon state container.
+const STATE_FIELD_COUNT: usize = 20;
+
+/// Root of a value, together with the fingerprint of the value it was
+/// computed for.
+struct CachedRoot<T> {
+	fingerprint: Option<u64>,
+	root: H256,
+	_marker: PhantomData<T>,
+}
+
+impl<T> Default for CachedRoot<T> {
+	fn default() -> Self {
+		Self { fingerprint: None, root: H256::default(), _marker: PhantomData }
+	}
+}
+
+impl<T: ssz::Encode> CachedRoot<T> {
+	/// Root of `value`, computed with `f` unless cached. Returns whether the
+	/// root was cached.
+	fn get<F: FnOnce(&T) -> H256>(
+		&mut self,
+		keys: &RandomState,
+		value: &T,
+		f: F,
+	) -> (H256, bool) {
+		let fingerprint = value.using_encoded(|encoded| {
+			let mut hasher = keys.build_hasher();
+			hasher.write(encoded);
+			hasher.finish()
+		});
+		if self.fingerprint == Some(fingerprint) {
+			return (self.root, true)
+		}
+
+		self.root = f(value);
+		self.fingerprint = Some(fingerprint);
+		(self.root, false)
+	}
+}
+
+/// Cache of the roots of the large state fields.
+pub struct FieldRootCache<C: Config> {
+	/// Random keys of the fingerprints, so that collisions cannot be
+	/// crafted.
+	keys: RandomState,
+	block_roots: CachedRoot<VecArray<H256, C::SlotsPerHistoricalRoot>>,
+	state_roots: CachedRoot<VecArray<H256, C::SlotsPerHistoricalRoot>>,
+	historical_roots: CachedRoot<MaxVec<H256, C::HistoricalRootsLimit>>,
+	eth1_data_votes: CachedRoot<MaxVec<Eth1Data, C::SlotsPerEth1VotingPeriod>>,
+	validators: CachedRoot<MaxVec<Validator, C::ValidatorRegistryLimit>>,
+	balances: CachedRoot<MaxVec<Uint, C::ValidatorRegistryLimit>>,
+	randao_mixes: CachedRoot<VecArray<H256, C::EpochsPerHistoricalVector>>,
+	previous_epoch_attestations: CachedRoot<MaxVec<PendingAttestation<C>, C::MaxAttestationsPerEpoch>>,
+	current_epoch_attestations: CachedRoot<MaxVec<PendingAttestation<C>, C::MaxAttestationsPerEpoch>>,
+	hits: u64,
+	misses: u64,
+}
+
+impl<C: Config> Default for FieldRootCache<C> {
+	fn default() -> Self {
+		Self {
+			keys: RandomState::new(),
+			block_roots: Default::default(),
+			state_roots: Default::default(),
+			historical_roots: Default::default(),
+			eth1_data_votes: Default::default(),
+			validators: Default::default(),
+			balances: Default::default(),
+			randao_mixes: Default::default(),
+			previous_epoch_attestations: Default::default(),
+			current_epoch_attestations: Default::default(),
+			hits: 0,
+			misses: 0,
+		}
+	}
+}
+
+impl<C: Config> FieldRootCache<C> {
+	/// Create an empty cache.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Number of field roots reused and recomputed so far.
+	pub fn stats(&self) -> (u64, u64) {
+		(self.hits, self.misses)
+	}
+
+	/// Root of the state, reusing cached roots of unchanged large fields.
+	pub fn state_root(&mut self, state: &BeaconState<C>) -> H256 {
+		let mut cached = Vec::new();
+		let fields = vec![
+			tree_root::<C::Digest, _>(&state.genesis_time),
+			tree_root::<C::Digest, _>(&state.slot),
+			tree_root::<C::Digest, _>(&state.fork),
+			tree_root::<C::Digest, _>(&state.latest_block_header),
+			note(&mut cached, self.block_roots.get(&self.keys, &state.block_roots, root::<C, _>)),
+			note(&mut cached, self.state_roots.get(&self.keys, &state.state_roots, root::<C, _>)),
+			note(&mut cached, self.historical_roots.get(&self.keys, &state.historical_roots, root::<C, _>)),
+			tree_root::<C::Digest, _>(&state.eth1_data),
+			note(&mut cached, self.eth1_data_votes.get(&self.keys, &state.eth1_data_votes, root::<C, _>)),
+			tree_root::<C::Digest, _>(&state.eth1_deposit_index),
+			note(&mut cached, self.validators.get(&self.keys, &state.validators, root::<C, _>)),
+			note(&mut cached, self.balances.get(
+				&self.keys, &state.balances, |balances| tree_root::<C::Digest, _>(&CompactRef(balances)),
+			)),
+			note(&mut cached, self.randao_mixes.get(&self.keys, &state.randao_mixes, root::<C, _>)),
+			tree_root::<C::Digest, _>(&CompactRef(&state.slashings)),
+			note(&mut cached, self.previous_epoch_attestations.get(
+				&self.keys, &state.previous_epoch_attestations, root::<C, _>,
+			)),
+			note(&mut cached, self.current_epoch_attestations.get(
+				&self.keys, &state.current_epoch_attestations, root::<C, _>,
+			)),
+			tree_root::<C::Digest, _>(&CompactRef(&state.justification_bits)),
+			tree_root::<C::Digest, _>(&state.previous_justified_checkpoint),
+			tree_root::<C::Digest, _>(&state.current_justified_checkpoint),
+			tree_root::<C::Digest, _>(&state.finalized_checkpoint),
+		];
+		debug_assert_eq!(fields.len(), STATE_FIELD_COUNT);
+
+		let hits = cached.iter().filter(|hit| **hit).count() as u64;
+		self.hits += hits;
+		self.misses += cached.len() as u64 - hits;
+		merkle_root::<C>(fields)
+	}
+}
+
+fn root<C: Config, T: bm_le::IntoTree>(value: &T) -> H256 {
+	tree_root::<C::Digest, _>(value)
+}
+
+fn note(cached: &mut Vec<bool>, (root, hit): (H256, bool)) -> H256 {
+	cached.push(hit);
+	root
+}
+
+/// Merkle root of `leaves`, padded with zero leaves to a power of two.
+fn merkle_root<C: Config>(mut layer: Vec<H256>) -> H256 {
+	layer.resize(layer.len().next_power_of_two(), H256::default());
+	while layer.len() > 1 {
+		layer = layer.chunks(2)
+			.map(|pair| C::hash(&[pair[0].as_ref(), pair[1].as_ref()]))
+			.collect();
+	}
+	layer[0]
+}
@@ -0,0 +1,156 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! TLS configuration of beacon API servers and clients.
+//!
+//! Servers use operator provided PEM certificates. Clients connecting to a
+//! beacon node on another host can pin its certificate by SHA-256
+//! fingerprint, which then replaces CA based validation, so that
+//! self-signed certificates can be used.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use rustls::{
+	Certificate, ClientConfig, NoClientAuth, PrivateKey, RootCertStore, ServerCertVerified,
+	ServerCertVerifier, ServerConfig, TLSError,
+};
+use rustls::internal::pemfile;
+use sha2::{Digest, Sha256};
+
+/// TLS errors.
+#[derive(Debug)]
+pub enum Error {
+	/// Reading a certificate or key file failed.
+	Io(PathBuf, io::Error),
+	/// A file contains no valid PEM certificate or key.
+	InvalidPem(PathBuf),
+	/// A fingerprint is not 32 hex encoded bytes.
+	InvalidFingerprint(String),
+	/// The certificate and key were rejected.
+	Tls(TLSError),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(path, e) => write!(f, "Reading {:?} failed: {}", path, e),
+			Error::InvalidPem(path) => write!(f, "No valid PEM data in {:?}", path),
+			Error::InvalidFingerprint(value) => write!(f, "Invalid certificate fingerprint {}", value),
+			Error::Tls(e) => write!(f, "Invalid certificate: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(_, e) => Some(e),
+			Error::Tls(e) => Some(e),
+			_ => None,
+		}
+	}
+}
+
+/// Certificate and key of a TLS server.
+#[derive(Clone, Debug)]
+pub struct ServerTls {
+	/// PEM certificate chain, leaf first.
+	pub cert: PathBuf,
+	/// PEM private key, in PKCS#8 or RSA format.
+	pub key: PathBuf,
+}
+
+impl ServerTls {
+	/// Load the server configuration.
+	pub fn load(&self) -> Result<Arc<ServerConfig>, Error> {
+		let certs = pemfile::certs(&mut open(&self.cert)?)
+			.ok()
+			.filter(|certs| !certs.is_empty())
+			.ok_or_else(|| Error::InvalidPem(self.cert.clone()))?;
+		let key = load_key(&self.key)?;
+
+		let mut config = ServerConfig::new(NoClientAuth::new());
+		config.set_single_cert(certs, key).map_err(Error::Tls)?;
+		Ok(Arc::new(config))
+	}
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, Error> {
+	File::open(path)
+		.map(BufReader::new)
+		.map_err(|e| Error::Io(path.to_path_buf(), e))
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, Error> {
+	let pkcs8 = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+	let rsa = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+	pkcs8.into_iter().chain(rsa).next()
+		.ok_or_else(|| Error::InvalidPem(path.to_path_buf()))
+}
+
+/// SHA-256 fingerprint of a DER certificate.
+pub fn fingerprint(cert: &[u8]) -> [u8; 32] {
+	let mut fingerprint = [0u8; 32];
+	fingerprint.copy_from_slice(&Sha256::digest(cert)[..]);
+	fingerprint
+}
+
+/// Parse a hex fingerprint, optionally colon separated as printed by
+/// `openssl x509 -fingerprint -sha256`.
+pub fn parse_fingerprint(value: &str) -> Result<[u8; 32], Error> {
+	let hex_value = value.trim().trim_start_matches("0x").replace(':', "");
+	let bytes = hex::decode(&hex_value)
+		.map_err(|_| Error::InvalidFingerprint(value.to_string()))?;
+	if bytes.len() != 32 {
+		return Err(Error::InvalidFingerprint(value.to_string()))
+	}
+	let mut fingerprint = [0u8; 32];
+	fingerprint.copy_from_slice(&bytes);
+	Ok(fingerprint)
+}
+
+/// Verifier accepting servers whose leaf certificate has a pinned
+/// fingerprint.
+pub struct PinnedCertVerifier {
+	pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+	fn verify_server_cert(
+		&self,
+		_roots: &RootCertStore,
+		presented_certs: &[Certificate],
+		_dns_name: webpki::DNSNameRef,
+		_ocsp_response: &[u8],
+	) -> Result<ServerCertVerified, TLSError> {
+		let leaf = presented_certs.first().ok_or(TLSError::NoCertificatesPresented)?;
+		if self.pins.contains(&fingerprint(&leaf.0)) {
+			Ok(ServerCertVerified::assertion())
+		} else {
+			Err(TLSError::General("Server certificate does not match a pinned fingerprint".to_string()))
+		}
+	}
+}
+
+/// Client configuration only accepting servers with a pinned certificate.
+pub fn pinned_client_config(pins: Vec<[u8; 32]>) -> Arc<ClientConfig> {
+	let mut config = ClientConfig::new();
+	config.dangerous().set_certificate_verifier(Arc::new(PinnedCertVerifier { pins }));
+	Arc::new(config)
+}
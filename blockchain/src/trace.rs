@@ -0,0 +1,92 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Block transition trace reports.
+//!
+//! Blocks selected by root, and optionally all blocks failing to import, are
+//! executed again step by step on a copy of their parent state. The report
+//! lists each applied operation with the resulting state root and balance
+//! changes, and is written to the trace directory or to the log.
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use beacon::primitives::H256;
+use beacon::trace::BlockTrace;
+use log::{info, warn};
+
+/// Which blocks to trace, and where reports go.
+#[derive(Clone, Debug, Default)]
+pub struct BlockTracing {
+	/// Roots of blocks always traced.
+	pub blocks: HashSet<H256>,
+	/// Whether blocks failing to execute are traced.
+	pub on_failure: bool,
+	/// Directory reports are written to. Reports are logged if not set.
+	pub dir: Option<PathBuf>,
+}
+
+impl BlockTracing {
+	/// Whether the parent state must be kept to trace the block.
+	pub fn may_trace(&self, id: &H256) -> bool {
+		self.on_failure || self.blocks.contains(id)
+	}
+
+	/// Whether the block is traced given its execution result.
+	pub fn should_trace(&self, id: &H256, failed: bool) -> bool {
+		(failed && self.on_failure) || self.blocks.contains(id)
+	}
+
+	/// Write the report of a traced block.
+	pub fn write(&self, id: &H256, slot: u64, trace: &BlockTrace) {
+		let report = report(id, slot, trace);
+		match self.dir.as_ref() {
+			Some(dir) => {
+				let path = dir.join(format!("{:x}.trace", id));
+				match fs::create_dir_all(dir).and_then(|()| fs::write(&path, &report)) {
+					Ok(()) => info!("Wrote trace of block {} to {:?}", id, path),
+					Err(e) => warn!("Could not write trace of block {} to {:?}: {}", id, path, e),
+				}
+			},
+			None => info!("Trace of block {}:\n{}", id, report),
+		}
+	}
+}
+
+/// Format a trace as a plain text report.
+pub fn report(id: &H256, slot: u64, trace: &BlockTrace) -> String {
+	let mut report = String::new();
+	let _ = writeln!(report, "block: {:?}", id);
+	let _ = writeln!(report, "slot: {}", slot);
+	let _ = writeln!(report, "pre_state_root: {:?}", trace.pre_state_root);
+	let _ = writeln!(report, "steps:");
+	for step in &trace.steps {
+		let _ = writeln!(report, "  - operation: {:?}", step.operation);
+		let _ = writeln!(report, "    state_root: {:?}", step.state_root);
+		if !step.balance_deltas.is_empty() {
+			let _ = writeln!(report, "    balance_deltas:");
+			for (index, delta) in &step.balance_deltas {
+				let _ = writeln!(report, "      {}: {:+}", index, delta);
+			}
+		}
+	}
+	match trace.error.as_ref() {
+		Some(e) => { let _ = writeln!(report, "error: {:?}", e); },
+		None => { let _ = writeln!(report, "post_state_root: {:?}", trace.post_state_root()); },
+	}
+	report
+}
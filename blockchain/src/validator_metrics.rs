@@ -0,0 +1,237 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Validator duty outcomes and metrics.
+//!
+//! Tracks duties performed by the local validators, whether their
+//! attestations were included on chain and voted for the correct head.
+//! Metrics are exported in the Prometheus text format to a file, to be
+//! picked up by the node exporter textfile collector, so that validators
+//! can be monitored without an HTTP endpoint.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+use beacon::Config;
+use beacon::primitives::{Slot, ValidatorIndex, H256};
+use beacon::types::{AttestationData, BeaconBlock};
+use log::debug;
+
+struct PendingAttestation {
+	validator_index: ValidatorIndex,
+	committee_position: usize,
+	data: AttestationData,
+}
+
+/// Validator metrics.
+#[derive(Default)]
+pub struct ValidatorMetrics {
+	attestation_duties: u64,
+	attestations_produced: u64,
+	attestations_missed: u64,
+	proposal_duties: u64,
+	proposals_produced: u64,
+	proposals_missed: u64,
+	proposals_fallback: u64,
+	signatures: u64,
+	signing_seconds: f64,
+	signing_seconds_max: f64,
+	attestations_included: u64,
+	attestations_not_included: u64,
+	head_votes_correct: u64,
+	head_votes_incorrect: u64,
+	beacon_node_available: bool,
+	beacon_node_unavailable: u64,
+	pending: Vec<PendingAttestation>,
+	last_block: Option<H256>,
+}
+
+impl ValidatorMetrics {
+	/// Create empty metrics.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Note an attestation duty.
+	pub fn note_attestation_duty(&mut self) {
+		self.attestation_duties += 1;
+	}
+
+	/// Note an attestation produced for a duty, to be tracked for inclusion.
+	pub fn note_attestation_produced(
+		&mut self,
+		validator_index: ValidatorIndex,
+		committee_position: usize,
+		data: AttestationData,
+	) {
+		self.attestations_produced += 1;
+		self.pending.push(PendingAttestation { validator_index, committee_position, data });
+	}
+
+	/// Note an attestation duty that could not be performed.
+	pub fn note_attestation_missed(&mut self) {
+		self.attestations_missed += 1;
+	}
+
+	/// Note a proposal duty.
+	pub fn note_proposal_duty(&mut self) {
+		self.proposal_duties += 1;
+	}
+
+	/// Note a block produced and published for a duty.
+	pub fn note_proposal_produced(&mut self) {
+		self.proposals_produced += 1;
+	}
+
+	/// Note a proposal duty that could not be performed.
+	pub fn note_proposal_missed(&mut self) {
+		self.proposals_missed += 1;
+	}
+
+	/// Note a minimal block built because the full one missed its deadline
+	/// or failed.
+	pub fn note_proposal_fallback(&mut self) {
+		self.proposals_fallback += 1;
+	}
+
+	/// Run a signing operation, recording its latency.
+	pub fn time_signing<T, F: FnOnce() -> T>(&mut self, f: F) -> T {
+		let start = Instant::now();
+		let result = f();
+		let elapsed = start.elapsed().as_secs_f64();
+		self.signatures += 1;
+		self.signing_seconds += elapsed;
+		if elapsed > self.signing_seconds_max {
+			self.signing_seconds_max = elapsed;
+		}
+		result
+	}
+
+	/// Note whether the beacon node could serve duties.
+	pub fn note_beacon_node(&mut self, available: bool) {
+		self.beacon_node_available = available;
+		if !available {
+			self.beacon_node_unavailable += 1;
+		}
+	}
+
+	/// Check a block on the canonical chain for tracked attestations.
+	/// `canonical_root` gives the canonical block root at a slot, to judge
+	/// head votes.
+	pub fn note_block<C: Config, F: Fn(Slot) -> Option<H256>>(
+		&mut self,
+		id: H256,
+		block: &BeaconBlock<C>,
+		canonical_root: F,
+	) {
+		if self.last_block == Some(id) {
+			return
+		}
+		self.last_block = Some(id);
+
+		let mut included = Vec::new();
+		for attestation in block.body.attestations.iter() {
+			for (i, pending) in self.pending.iter().enumerate() {
+				if pending.data == attestation.data &&
+					attestation.aggregation_bits.get(pending.committee_position).cloned().unwrap_or(false)
+				{
+					included.push(i);
+				}
+			}
+		}
+		included.sort();
+		included.dedup();
+
+		for i in included.into_iter().rev() {
+			let pending = self.pending.remove(i);
+			self.attestations_included += 1;
+			if canonical_root(pending.data.slot) == Some(pending.data.beacon_block_root) {
+				self.head_votes_correct += 1;
+			} else {
+				debug!(
+					"Validator {} voted for head {} at slot {}, not canonical",
+					pending.validator_index, pending.data.beacon_block_root, pending.data.slot,
+				);
+				self.head_votes_incorrect += 1;
+			}
+		}
+	}
+
+	/// Give up on attestations that can no longer be included at
+	/// `current_slot`.
+	pub fn prune<C: Config>(&mut self, current_slot: Slot) {
+		let before = self.pending.len();
+		self.pending.retain(|pending| pending.data.slot + C::slots_per_epoch() >= current_slot);
+		self.attestations_not_included += (before - self.pending.len()) as u64;
+	}
+
+	/// Metrics in the Prometheus text format.
+	pub fn encode(&self) -> String {
+		let mut out = String::new();
+		let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+			let _ = writeln!(out, "# HELP {} {}", name, help);
+			let _ = writeln!(out, "# TYPE {} {}", name, kind);
+			let _ = writeln!(out, "{} {}", name, value);
+		};
+
+		metric("validator_attestation_duties_total", "counter",
+			   "Attestation duties scheduled", self.attestation_duties.to_string());
+		metric("validator_attestations_produced_total", "counter",
+			   "Attestations produced", self.attestations_produced.to_string());
+		metric("validator_attestations_missed_total", "counter",
+			   "Attestation duties not performed", self.attestations_missed.to_string());
+		metric("validator_proposal_duties_total", "counter",
+			   "Proposal duties scheduled", self.proposal_duties.to_string());
+		metric("validator_proposals_produced_total", "counter",
+			   "Blocks produced and published", self.proposals_produced.to_string());
+		metric("validator_proposals_missed_total", "counter",
+			   "Proposal duties not performed", self.proposals_missed.to_string());
+		metric("validator_proposals_fallback_total", "counter",
+			   "Minimal blocks built as fallback", self.proposals_fallback.to_string());
+		metric("validator_signatures_total", "counter",
+			   "Signing operations", self.signatures.to_string());
+		metric("validator_signing_seconds_total", "counter",
+			   "Time spent signing", self.signing_seconds.to_string());
+		metric("validator_signing_seconds_max", "gauge",
+			   "Longest signing operation", self.signing_seconds_max.to_string());
+		metric("validator_attestations_included_total", "counter",
+			   "Attestations included on the canonical chain", self.attestations_included.to_string());
+		metric("validator_attestations_not_included_total", "counter",
+			   "Attestations not included within their inclusion window",
+			   self.attestations_not_included.to_string());
+		metric("validator_head_votes_correct_total", "counter",
+			   "Included attestations voting for the canonical head", self.head_votes_correct.to_string());
+		metric("validator_head_votes_incorrect_total", "counter",
+			   "Included attestations voting for another head", self.head_votes_incorrect.to_string());
+		metric("validator_beacon_node_available", "gauge",
+			   "Whether the beacon node can serve duties",
+			   (if self.beacon_node_available { 1 } else { 0 }).to_string());
+		metric("validator_beacon_node_unavailable_total", "counter",
+			   "Duty checks skipped because the beacon node was unavailable",
+			   self.beacon_node_unavailable.to_string());
+
+		out
+	}
+
+	/// Write metrics to a file, replacing it atomically.
+	pub fn write(&self, path: &Path) -> io::Result<()> {
+		let tmp = path.with_extension("prom.tmp");
+		fs::write(&tmp, self.encode())?;
+		fs::rename(&tmp, path)
+	}
+}
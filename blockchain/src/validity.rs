@@ -0,0 +1,86 @@
+// Copyright 2019 Parity Technologies (UK) Ltd.
+// This file is part of Parity Shasper.
+
+// Parity Shasper is free software: you can redistribute it and/or modify it
+// under the terms of the GNU General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option) any
+// later version.
+
+// Parity Shasper is distributed in the hope that it will be useful, but WITHOUT
+// ANY WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE.  See the GNU General Public License for more
+// details.
+
+// You should have received a copy of the GNU General Public License along with
+// Parity Shasper.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persisted execution validity of blocks.
+//!
+//! Only blocks imported optimistically, or later found invalid, have an
+//! entry. Blocks without one are valid.
+
+use std::sync::Arc;
+use beacon::primitives::H256;
+use lmd_ghost::validity::{BlockValidity, ValidityQuery};
+use log::warn;
+use parity_codec::{Encode, Decode};
+use crate::Error;
+use crate::index::{IndexStore, MemoryIndexStore};
+
+const PREFIX_VALIDITY: u8 = b'v';
+
+fn validity_key(id: &H256) -> Vec<u8> {
+	let mut key = vec![PREFIX_VALIDITY];
+	key.extend_from_slice(&id[..]);
+	key
+}
+
+/// Block validity stored in a block index storage.
+#[derive(Clone)]
+pub struct ValidityStore {
+	store: Arc<dyn IndexStore + Send + Sync>,
+}
+
+impl ValidityStore {
+	/// Create a validity store on top of the given index storage.
+	pub fn new(store: Arc<dyn IndexStore + Send + Sync>) -> Self {
+		Self { store }
+	}
+
+	/// Validity of a block.
+	pub fn get(&self, id: &H256) -> Result<BlockValidity, Error> {
+		match self.store.get(&validity_key(id))? {
+			Some(raw) => Ok(
+				BlockValidity::decode(&mut raw.as_ref()).ok_or(Error::Corrupted("block validity"))?
+			),
+			None => Ok(BlockValidity::Valid),
+		}
+	}
+
+	/// Set validity of a block.
+	pub fn set(&self, id: &H256, validity: BlockValidity) -> Result<(), Error> {
+		let value = match validity {
+			BlockValidity::Valid => None,
+			validity => Some(validity.encode()),
+		};
+		self.store.write(vec![(validity_key(id), value)])
+	}
+}
+
+impl Default for ValidityStore {
+	fn default() -> Self {
+		Self::new(Arc::new(MemoryIndexStore::default()))
+	}
+}
+
+impl ValidityQuery<H256> for ValidityStore {
+	fn validity(&self, id: &H256) -> BlockValidity {
+		match self.get(id) {
+			Ok(validity) => validity,
+			Err(e) => {
+				warn!("Failed to read validity of block {}: {:?}", id, e);
+				BlockValidity::Optimistic
+			},
+		}
+	}
+}
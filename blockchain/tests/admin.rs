@@ -0,0 +1,55 @@
+use std::sync::RwLock;
+use std::time::Instant;
+use libp2p::gossipsub::TopicHash;
+use shasper_network::MeshStats;
+use shasper_blockchain::admin;
+
+fn topic(name: &str) -> TopicHash {
+	TopicHash::from_raw(format!("/eth2/{}/ssz", name))
+}
+
+#[test]
+fn under_peered_topics_come_first() {
+	let mut stats = MeshStats::new(6, 4);
+	stats.note_local_subscription(&topic("beacon_block"), true);
+	stats.set_peers(&topic("beacon_block"), 8);
+	stats.note_local_subscription(&topic("beacon_attestation_3"), true);
+	stats.set_peers(&topic("beacon_attestation_3"), 2);
+	// Peers on a topic we are not subscribed to do not make it under-peered.
+	stats.set_peers(&topic("beacon_attestation_5"), 1);
+
+	let reports = admin::gossip_topics(&RwLock::new(stats));
+	assert_eq!(reports.len(), 3);
+	assert_eq!(reports[0].topic, "/eth2/beacon_attestation_3/ssz");
+	assert!(reports[0].stats.under_peered);
+	assert_eq!(reports[0].stats.mesh_peers, 2);
+	assert!(reports[1..].iter().all(|report| !report.stats.under_peered));
+}
+
+#[test]
+fn churn_and_messages_are_counted() {
+	let mut stats = MeshStats::new(6, 4);
+	let block = topic("beacon_block");
+	stats.set_peers(&block, 3);
+	stats.set_peers(&block, 1);
+	stats.set_peers(&block, 2);
+	stats.note_received(&block, Instant::now());
+	stats.note_received(&block, Instant::now());
+	stats.note_duplicate(&block);
+	stats.note_rejected(&block);
+	stats.note_published(&block);
+
+	let block_stats = stats.topic(block.as_str()).unwrap();
+	assert_eq!(block_stats.peers, 2);
+	assert_eq!(block_stats.mesh_peers, 2);
+	assert_eq!(block_stats.peers_joined, 4);
+	assert_eq!(block_stats.peers_left, 2);
+	assert_eq!(block_stats.received, 2);
+	assert_eq!(block_stats.duplicates, 1);
+	assert_eq!(block_stats.rejected, 1);
+	assert_eq!(block_stats.published, 1);
+
+	let metrics = stats.encode();
+	assert!(metrics.contains("gossip_topic_peers{topic=\"/eth2/beacon_block/ssz\"} 2\n"));
+	assert!(metrics.contains("gossip_messages_received_total{topic=\"/eth2/beacon_block/ssz\"} 2\n"));
+}
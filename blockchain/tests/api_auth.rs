@@ -0,0 +1,16 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use shasper_blockchain::api_auth::load_or_create_token;
+
+#[test]
+fn created_token_is_owner_only_and_reloaded() {
+	let path = std::env::temp_dir().join(format!("shasper-api-token-{}", std::process::id()));
+	let _ = fs::remove_file(&path);
+
+	let token = load_or_create_token(&path).unwrap();
+	let mode = fs::metadata(&path).unwrap().permissions().mode();
+	assert_eq!(mode & 0o777, 0o600);
+	assert_eq!(load_or_create_token(&path).unwrap(), token);
+
+	fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,38 @@
+use beacon::primitives::H256;
+use shasper_blockchain::Error;
+use shasper_blockchain::chain_info::{self, NetworkId};
+use shasper_blockchain::index::MemoryIndexStore;
+
+fn network(config_name: &str, root: u8) -> NetworkId {
+	NetworkId {
+		config_name: config_name.to_string(),
+		genesis_validators_root: H256::repeat_byte(root),
+	}
+}
+
+#[test]
+fn network_is_recorded_on_first_start() {
+	let store = MemoryIndexStore::default();
+	assert_eq!(chain_info::load_network(&store).unwrap(), None);
+
+	chain_info::check_network(&store, &network("mainnet", 1)).unwrap();
+	assert_eq!(chain_info::load_network(&store).unwrap(), Some(network("mainnet", 1)));
+	chain_info::check_network(&store, &network("mainnet", 1)).unwrap();
+}
+
+#[test]
+fn mismatching_network_is_refused() {
+	let store = MemoryIndexStore::default();
+	chain_info::check_network(&store, &network("mainnet", 1)).unwrap();
+
+	for other in &[network("minimal", 1), network("mainnet", 2)] {
+		match chain_info::check_network(&store, other) {
+			Err(Error::NetworkMismatch { stored, expected }) => {
+				assert_eq!(stored, network("mainnet", 1));
+				assert_eq!(&expected, other);
+			},
+			result => panic!("Expected a network mismatch, got {:?}", result),
+		}
+	}
+	assert_eq!(chain_info::load_network(&store).unwrap(), Some(network("mainnet", 1)));
+}
@@ -0,0 +1,65 @@
+use std::str::FromStr;
+use beacon::{BeaconState, Config, MinimalConfig};
+use beacon::primitives::H256;
+use beacon::types::Validator;
+use shasper_blockchain::committees::{self, StateId};
+
+fn state(validators: usize) -> BeaconState<MinimalConfig> {
+	BeaconState {
+		validators: (0..validators).map(|_| Validator {
+			effective_balance: MinimalConfig::max_effective_balance(),
+			exit_epoch: u64::max_value(),
+			withdrawable_epoch: u64::max_value(),
+			..Default::default()
+		}).collect::<Vec<_>>().into(),
+		balances: vec![MinimalConfig::max_effective_balance(); validators].into(),
+		..Default::default()
+	}
+}
+
+#[test]
+fn state_ids_are_parsed() {
+	assert_eq!(StateId::from_str("head"), Ok(StateId::Head));
+	assert_eq!(StateId::from_str("finalized"), Ok(StateId::Finalized));
+	assert_eq!(StateId::from_str("12"), Ok(StateId::Slot(12)));
+	assert_eq!(
+		StateId::from_str(&format!("0x{}", "11".repeat(32))),
+		Ok(StateId::Root(H256::repeat_byte(0x11))),
+	);
+	assert!(StateId::from_str("0x11").is_err());
+	assert!(StateId::from_str("latest").is_err());
+}
+
+#[test]
+fn every_active_validator_is_in_one_committee() {
+	let state = state(64);
+	let committees = committees::epoch_committees(&state, 0).unwrap();
+	let per_slot = 64 / MinimalConfig::slots_per_epoch() / MinimalConfig::target_committee_size();
+	assert_eq!(committees.len() as u64, MinimalConfig::slots_per_epoch() * per_slot);
+
+	let mut members = committees.iter()
+		.flat_map(|committee| committee.validators.clone())
+		.collect::<Vec<_>>();
+	members.sort();
+	assert_eq!(members, (0..64).collect::<Vec<_>>());
+
+	assert_eq!(committees::epoch_committees(&state, 1).unwrap().len(), committees.len());
+	assert!(committees::epoch_committees(&state, 2).is_err());
+}
+
+#[test]
+fn committees_are_exported() {
+	let committees = committees::epoch_committees(&state(64), 0).unwrap();
+	let csv = committees::to_csv(&committees);
+	let mut lines = csv.lines();
+	assert_eq!(lines.next(), Some("slot,index,position,validator_index"));
+	assert_eq!(lines.count(), 64);
+
+	let json: serde_json::Value = serde_json::from_str(&committees::to_json(&committees)).unwrap();
+	assert_eq!(json["data"][0]["slot"], "0");
+	assert_eq!(json["data"][0]["index"], "0");
+	assert_eq!(
+		json["data"][0]["validators"][0],
+		committees[0].validators[0].to_string().as_str(),
+	);
+}
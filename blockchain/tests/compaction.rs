@@ -0,0 +1,25 @@
+use std::time::Duration;
+use blockchain_rocksdb::CompactionStats;
+use shasper_blockchain::compaction::CompactionMetrics;
+
+#[test]
+fn metrics_accumulate_compactions() {
+	let mut metrics = CompactionMetrics::new();
+	metrics.note::<()>(&Ok(CompactionStats {
+		duration: Duration::from_secs(2), size_before: 1000, size_after: 400,
+	}));
+	metrics.note::<()>(&Ok(CompactionStats {
+		duration: Duration::from_secs(1), size_before: 400, size_after: 500,
+	}));
+	metrics.note(&Err(()));
+
+	assert_eq!(metrics.runs, 2);
+	assert_eq!(metrics.failures, 1);
+	assert_eq!(metrics.last_reclaimed, 0);
+	assert_eq!(metrics.total_reclaimed, 600);
+	assert_eq!(metrics.total_duration, Duration::from_secs(3));
+
+	let encoded = metrics.encode();
+	assert!(encoded.contains("beacon_db_compactions_total 2\n"));
+	assert!(encoded.contains("beacon_db_compaction_reclaimed_bytes_total 600\n"));
+}
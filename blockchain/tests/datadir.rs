@@ -0,0 +1,76 @@
+use std::fs;
+use std::path::PathBuf;
+use shasper_blockchain::datadir::{DataDir, Error};
+
+fn temp_root(name: &str) -> PathBuf {
+	let root = std::env::temp_dir().join(format!("shasper-datadir-{}-{}", name, std::process::id()));
+	let _ = fs::remove_dir_all(&root);
+	fs::create_dir_all(&root).unwrap();
+	root
+}
+
+#[test]
+fn legacy_database_is_moved_into_chain() {
+	let root = temp_root("legacy");
+	fs::write(root.join("CURRENT"), b"MANIFEST-000001\n").unwrap();
+	fs::write(root.join("000003.sst"), b"").unwrap();
+	fs::write(root.join("notes.txt"), b"").unwrap();
+
+	let data_dir = DataDir::new(&root);
+	data_dir.create().unwrap();
+	assert!(data_dir.migrate_legacy_database().unwrap());
+
+	assert!(data_dir.chain_db().join("CURRENT").exists());
+	assert!(data_dir.chain_db().join("000003.sst").exists());
+	assert!(!root.join("CURRENT").exists());
+	assert!(root.join("notes.txt").exists());
+	assert!(!data_dir.migrate_legacy_database().unwrap());
+
+	fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn interrupted_migration_is_resumed() {
+	let root = temp_root("resume");
+	let data_dir = DataDir::new(&root);
+	data_dir.create().unwrap();
+	fs::write(root.join("CURRENT"), b"MANIFEST-000001\n").unwrap();
+	fs::write(root.join("MANIFEST-000001"), b"").unwrap();
+	fs::write(data_dir.chain_db().join("000003.sst"), b"").unwrap();
+
+	assert!(data_dir.migrate_legacy_database().unwrap());
+
+	assert!(data_dir.chain_db().join("CURRENT").exists());
+	assert!(data_dir.chain_db().join("MANIFEST-000001").exists());
+	assert!(data_dir.chain_db().join("000003.sst").exists());
+	assert!(!root.join("MANIFEST-000001").exists());
+
+	fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn freezer_is_created() {
+	let root = temp_root("freezer");
+	let data_dir = DataDir::new(&root);
+	data_dir.create().unwrap();
+
+	assert!(data_dir.freezer().is_dir());
+
+	fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn conflicting_databases_are_refused() {
+	let root = temp_root("conflict");
+	let data_dir = DataDir::new(&root);
+	data_dir.create().unwrap();
+	fs::write(root.join("CURRENT"), b"").unwrap();
+	fs::write(data_dir.chain_db().join("CURRENT"), b"").unwrap();
+
+	match data_dir.migrate_legacy_database() {
+		Err(Error::ConflictingDatabases) => (),
+		other => panic!("Unexpected result {:?}", other),
+	}
+
+	fs::remove_dir_all(&root).unwrap();
+}
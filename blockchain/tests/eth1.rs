@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use beacon::{Config, MinimalConfig};
+use beacon::consts::DEPOSIT_CONTRACT_TREE_DEPTH;
+use beacon::primitives::H256;
+use beacon::types::DepositData;
+use beacon::utils::is_valid_merkle_branch;
+use bm_le::tree_root;
+use parity_codec::{Encode, Decode};
+use shasper_blockchain::eth1::{DepositCache, DepositTree, DepositTreeSnapshot, Error};
+use shasper_blockchain::index::MemoryIndexStore;
+
+fn deposit(amount: u64) -> DepositData {
+	DepositData { amount, ..Default::default() }
+}
+
+fn hash(number: u64, fork: u8) -> H256 {
+	let mut hash = H256::repeat_byte(fork);
+	(&mut hash[0..8]).copy_from_slice(&number.to_le_bytes());
+	hash
+}
+
+fn tree_of(amounts: &[u64]) -> DepositTree<MinimalConfig> {
+	let mut tree = DepositTree::new();
+	for amount in amounts {
+		tree.push(&deposit(*amount)).unwrap();
+	}
+	tree
+}
+
+fn leaf(amount: u64) -> H256 {
+	tree_root::<<MinimalConfig as Config>::Digest, _>(&deposit(amount))
+}
+
+/// Deposit root computed layer by layer over the full list of leaves.
+fn reference_root(amounts: &[u64]) -> H256 {
+	let mut zero = H256::default();
+	let mut layer = amounts.iter().map(|amount| leaf(*amount)).collect::<Vec<_>>();
+	for _ in 0..DEPOSIT_CONTRACT_TREE_DEPTH {
+		if layer.len() % 2 == 1 {
+			layer.push(zero);
+		}
+		layer = layer.chunks(2)
+			.map(|pair| MinimalConfig::hash(&[pair[0].as_ref(), pair[1].as_ref()]))
+			.collect();
+		zero = MinimalConfig::hash(&[zero.as_ref(), zero.as_ref()]);
+	}
+	let root = layer.first().cloned().unwrap_or(zero);
+
+	let mut count = H256::default();
+	(&mut count[0..8]).copy_from_slice(&(amounts.len() as u64).to_le_bytes());
+	MinimalConfig::hash(&[root.as_ref(), count.as_ref()])
+}
+
+fn assert_proofs_valid(tree: &DepositTree<MinimalConfig>, amounts: &[u64], count: u64) {
+	let root = tree.root_at(count).unwrap();
+	let first = tree.finalized_count();
+	let proofs = tree.proofs(count).unwrap();
+	assert_eq!(proofs.len() as u64, count - first);
+	for (index, proof) in (first..count).zip(proofs) {
+		assert_eq!(tree.proof(index, count).unwrap(), proof);
+		assert!(is_valid_merkle_branch::<MinimalConfig>(
+			leaf(amounts[index as usize]), &proof, DEPOSIT_CONTRACT_TREE_DEPTH + 1, index, root,
+		));
+	}
+}
+
+#[test]
+fn roots_and_proofs_match_the_full_tree() {
+	let amounts = (1..=13).collect::<Vec<u64>>();
+	let tree = tree_of(&amounts);
+	for count in 0..=amounts.len() {
+		assert_eq!(tree.root_at(count as u64).unwrap(), reference_root(&amounts[..count]));
+		assert_proofs_valid(&tree, &amounts, count as u64);
+	}
+}
+
+#[test]
+fn proofs_stay_valid_after_finalization() {
+	let amounts = (1..=13).collect::<Vec<u64>>();
+	for finalized in 0..=amounts.len() as u64 {
+		let mut tree = tree_of(&amounts);
+		tree.finalize(finalized, hash(finalized, 1), finalized).unwrap();
+
+		assert_eq!(tree.root().unwrap(), reference_root(&amounts));
+		assert_eq!(tree.proof(0, amounts.len() as u64).is_err(), finalized > 0);
+		for count in finalized..=amounts.len() as u64 {
+			assert_proofs_valid(&tree, &amounts, count);
+		}
+	}
+}
+
+#[test]
+fn snapshot_round_trips() {
+	let amounts = (1..=11).collect::<Vec<u64>>();
+	let mut tree = tree_of(&amounts[..7]);
+	tree.finalize(5, hash(20, 1), 20).unwrap();
+
+	let snapshot = tree.snapshot().unwrap();
+	assert_eq!(snapshot.deposit_count, 5);
+	assert_eq!(snapshot.deposit_root, reference_root(&amounts[..5]));
+	let decoded = DepositTreeSnapshot::decode(&mut snapshot.encode().as_ref()).unwrap();
+	assert_eq!(decoded, snapshot);
+
+	let mut restored = DepositTree::<MinimalConfig>::from_snapshot(&decoded).unwrap();
+	assert_eq!(restored.execution_block(), (hash(20, 1), 20));
+	for amount in &amounts[5..] {
+		restored.push(&deposit(*amount)).unwrap();
+	}
+	assert_eq!(restored.root().unwrap(), reference_root(&amounts));
+	assert_proofs_valid(&restored, &amounts, amounts.len() as u64);
+
+	let mut invalid = decoded;
+	invalid.deposit_count += 1;
+	assert_eq!(DepositTree::<MinimalConfig>::from_snapshot(&invalid).err(), Some(Error::InvalidSnapshot));
+}
+
+#[test]
+fn reorg_rolls_back_to_common_ancestor() {
+	let mut cache = DepositCache::<MinimalConfig>::new();
+	assert_eq!(cache.next_block_number(), 0);
+	cache.insert_block(hash(10, 1), 10, &[deposit(1), deposit(2)]).unwrap();
+	cache.insert_block(hash(11, 1), 11, &[]).unwrap();
+	cache.insert_block(hash(12, 1), 12, &[deposit(3)]).unwrap();
+	cache.insert_block(hash(13, 1), 13, &[deposit(4)]).unwrap();
+	assert_eq!(cache.insert_block(hash(13, 2), 13, &[]), Err(Error::OutOfOrder));
+	assert_eq!(cache.next_block_number(), 14);
+
+	let canonical = (0..20).map(|number| {
+		(number, if number < 12 { hash(number, 1) } else { hash(number, 2) })
+	}).collect::<HashMap<_, _>>();
+	let ancestor = cache.common_ancestor::<(), _>(|number| Ok(canonical.get(&number).cloned()))
+		.unwrap()
+		.unwrap();
+	assert_eq!(ancestor.number, 11);
+	assert_eq!(cache.rollback_to(ancestor.number), Ok(2));
+	assert_eq!(cache.tree().root().unwrap(), tree_of(&[1, 2]).root().unwrap());
+
+	cache.insert_block(hash(12, 2), 12, &[deposit(5)]).unwrap();
+	assert_eq!(cache.tree().root().unwrap(), tree_of(&[1, 2, 5]).root().unwrap());
+	assert_eq!(cache.next_block_number(), 13);
+}
+
+#[test]
+fn finalized_blocks_cannot_be_rolled_back() {
+	let mut cache = DepositCache::<MinimalConfig>::new();
+	cache.insert_block(hash(10, 1), 10, &[deposit(1)]).unwrap();
+	cache.insert_block(hash(11, 1), 11, &[deposit(2)]).unwrap();
+	cache.finalize(1).unwrap();
+
+	assert_eq!(cache.tree().finalized_count(), 1);
+	assert_eq!(cache.rollback_to(9), Err(Error::Finalized));
+	assert_eq!(cache.rollback_to(10), Ok(1));
+	assert_eq!(cache.common_ancestor::<(), _>(|_| Ok(None)), Ok(None));
+}
+
+#[test]
+fn cache_resumes_after_restart() {
+	let store = MemoryIndexStore::default();
+	assert!(DepositCache::<MinimalConfig>::load(&store).unwrap().is_none());
+
+	let mut cache = DepositCache::<MinimalConfig>::new();
+	cache.insert_block(hash(10, 1), 10, &[deposit(1), deposit(2)]).unwrap();
+	cache.insert_block(hash(11, 1), 11, &[deposit(3)]).unwrap();
+	cache.finalize(2).unwrap();
+	cache.insert_block(hash(12, 1), 12, &[]).unwrap();
+	cache.store(&store).unwrap();
+
+	let restored = DepositCache::<MinimalConfig>::load(&store).unwrap().unwrap();
+	assert_eq!(restored.next_block_number(), 13);
+	assert_eq!(restored.last_block(), cache.last_block());
+	assert_eq!(restored.tree().finalized_count(), 2);
+	assert_eq!(restored.tree().root().unwrap(), tree_of(&[1, 2, 3]).root().unwrap());
+}
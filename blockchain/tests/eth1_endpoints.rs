@@ -0,0 +1,162 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use shasper_blockchain::eth1_endpoints::{
+	Eth1Endpoint, Eth1Endpoints, Eth1Requirements, EndpointHealth, RpcError,
+};
+
+const DEPOSIT_CONTRACT: [u8; 20] = [0x42; 20];
+
+/// Minimal JSON-RPC server answering with `result` for each method.
+fn serve(result: fn(&str) -> &'static str) -> String {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}", listener.local_addr().unwrap());
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let stream = stream.unwrap();
+			thread::spawn(move || handle(stream, result));
+		}
+	});
+	url
+}
+
+fn handle(stream: TcpStream, result: fn(&str) -> &'static str) {
+	let mut writer = stream.try_clone().unwrap();
+	let mut reader = BufReader::new(stream);
+	loop {
+		let mut content_length = 0;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line).unwrap_or(0) == 0 {
+				return
+			}
+			let line = line.trim_end().to_lowercase();
+			if line.is_empty() {
+				break
+			}
+			if line.starts_with("content-length:") {
+				content_length = line["content-length:".len()..].trim().parse().unwrap();
+			}
+		}
+
+		let mut body = vec![0u8; content_length];
+		reader.read_exact(&mut body).unwrap();
+		let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+		let response = format!(
+			r#"{{"jsonrpc":"2.0","id":1,"result":{}}}"#,
+			result(request["method"].as_str().unwrap()),
+		);
+		write!(
+			writer, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+			response.len(), response,
+		).unwrap();
+	}
+}
+
+fn mainnet(method: &str) -> &'static str {
+	match method {
+		"eth_chainId" => r#""0x1""#,
+		"net_version" => r#""1""#,
+		"eth_syncing" => "false",
+		"eth_getCode" => r#""0x6080""#,
+		"eth_blockNumber" => r#""0x10""#,
+		_ => "null",
+	}
+}
+
+fn goerli(method: &str) -> &'static str {
+	match method {
+		"eth_chainId" => r#""0x5""#,
+		_ => mainnet(method),
+	}
+}
+
+fn other_network(method: &str) -> &'static str {
+	match method {
+		"net_version" => r#""3""#,
+		_ => mainnet(method),
+	}
+}
+
+fn no_contract(method: &str) -> &'static str {
+	match method {
+		"eth_getCode" => r#""0x""#,
+		_ => mainnet(method),
+	}
+}
+
+fn syncing(method: &str) -> &'static str {
+	match method {
+		"eth_syncing" => r#"{"startingBlock":"0x0","currentBlock":"0x1","highestBlock":"0x10"}"#,
+		_ => mainnet(method),
+	}
+}
+
+fn dead() -> String {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	format!("http://{}", listener.local_addr().unwrap())
+}
+
+fn endpoints(urls: &[String]) -> Eth1Endpoints {
+	Eth1Endpoints::new(
+		urls.iter().map(|url| Eth1Endpoint::new(url)).collect(),
+		Eth1Requirements { chain_id: 1, network_id: Some(1), deposit_contract: DEPOSIT_CONTRACT },
+	)
+}
+
+#[test]
+fn endpoint_health_is_checked() {
+	let endpoints = endpoints(&[serve(mainnet), serve(goerli), serve(syncing), dead()]);
+	endpoints.check_all();
+
+	let healths = endpoints.status().into_iter()
+		.map(|(_, status)| status.health)
+		.collect::<Vec<_>>();
+	assert_eq!(healths[0], EndpointHealth::Healthy);
+	assert_eq!(healths[1], EndpointHealth::WrongChainId(5));
+	assert_eq!(healths[2], EndpointHealth::Syncing);
+	match &healths[3] {
+		EndpointHealth::Offline(_) => (),
+		health => panic!("Expected the endpoint to be offline, got {:?}", health),
+	}
+}
+
+#[test]
+fn requests_fall_over_to_next_endpoint() {
+	let endpoints = endpoints(&[dead(), serve(mainnet)]);
+	assert_eq!(endpoints.request(|endpoint| endpoint.block_number()), Ok(16));
+
+	let status = endpoints.status();
+	assert_eq!(status[0].1.failures, 1);
+	assert_eq!(status[1].1.failures, 0);
+
+	// The failing endpoint is now tried last.
+	assert_eq!(endpoints.request(|endpoint| endpoint.block_number()), Ok(16));
+	assert_eq!(endpoints.status()[0].1.failures, 1);
+}
+
+#[test]
+fn misconfigured_endpoints_fail_validation() {
+	assert!(endpoints(&[serve(mainnet), serve(syncing), dead()]).validate().is_ok());
+
+	let misconfigured = endpoints(&[serve(mainnet), serve(goerli), serve(other_network), serve(no_contract)])
+		.validate()
+		.unwrap_err();
+	let healths = misconfigured.iter().map(|m| m.health.clone()).collect::<Vec<_>>();
+	assert_eq!(healths, vec![
+		EndpointHealth::WrongChainId(5),
+		EndpointHealth::WrongNetworkId(3),
+		EndpointHealth::NoDepositContract,
+	]);
+	assert!(misconfigured[0].to_string().contains("--eth1-chain-id"));
+}
+
+#[test]
+fn wrong_chain_is_never_used() {
+	let endpoints = endpoints(&[serve(goerli), dead()]);
+	endpoints.check_all();
+	assert_eq!(
+		endpoints.request(|endpoint| endpoint.block_number()),
+		Err(RpcError::AllEndpointsFailed),
+	);
+}
@@ -0,0 +1,232 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use beacon::MinimalConfig;
+use beacon::primitives::H256;
+use beacon::types::DepositData;
+use serde_json::{json, Value};
+use shasper_blockchain::eth1_endpoints::{Eth1Endpoint, Eth1Endpoints, Eth1Requirements};
+use shasper_blockchain::eth1_follow::{Eth1Follower, Error};
+use shasper_blockchain::index::MemoryIndexStore;
+
+const DEPOSIT_CONTRACT: [u8; 20] = [0x42; 20];
+const SECONDS_PER_BLOCK: u64 = 14;
+
+/// Eth1 chain served by the mock endpoint. Blocks from `fork_from` on have
+/// the hashes of fork `fork`.
+struct MockChain {
+	head: u64,
+	fork_from: u64,
+	fork: u8,
+	/// Deposit logs, as block height and deposit index.
+	deposits: Vec<(u64, u64)>,
+}
+
+impl MockChain {
+	fn new(head: u64, deposits: Vec<(u64, u64)>) -> Self {
+		Self { head, fork_from: u64::max_value(), fork: 0, deposits }
+	}
+
+	fn hash(&self, number: u64) -> H256 {
+		let fork = if number >= self.fork_from { self.fork } else { 0 };
+		let mut hash = H256::repeat_byte(0x10 + fork);
+		(&mut hash[0..8]).copy_from_slice(&number.to_le_bytes());
+		hash
+	}
+
+	fn result(&self, request: &Value) -> Value {
+		let quantity = |value: &Value| {
+			u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+		};
+		match request["method"].as_str().unwrap() {
+			"eth_blockNumber" => json!(format!("0x{:x}", self.head)),
+			"eth_getBlockByNumber" => {
+				let number = quantity(&request["params"][0]);
+				if number > self.head {
+					return Value::Null
+				}
+				json!({
+					"hash": format!("0x{}", hex::encode(self.hash(number))),
+					"number": format!("0x{:x}", number),
+					"timestamp": format!("0x{:x}", number * SECONDS_PER_BLOCK),
+				})
+			},
+			"eth_getLogs" => {
+				let from = quantity(&request["params"][0]["fromBlock"]);
+				let to = quantity(&request["params"][0]["toBlock"]);
+				Value::Array(self.deposits.iter()
+					.filter(|(number, _)| *number >= from && *number <= to)
+					.map(|(number, index)| json!({
+						"blockHash": format!("0x{}", hex::encode(self.hash(*number))),
+						"blockNumber": format!("0x{:x}", number),
+						"data": format!("0x{}", hex::encode(deposit_event(&deposit(*index), *index))),
+					}))
+					.collect())
+			},
+			_ => Value::Null,
+		}
+	}
+}
+
+fn deposit(index: u64) -> DepositData {
+	DepositData { amount: 32_000_000_000 + index, ..Default::default() }
+}
+
+/// ABI encode a `DepositEvent` with dynamic `bytes` fields.
+fn deposit_event(data: &DepositData, index: u64) -> Vec<u8> {
+	let fields: Vec<Vec<u8>> = vec![
+		data.pubkey[..].to_vec(),
+		data.withdrawal_credentials[..].to_vec(),
+		data.amount.to_le_bytes().to_vec(),
+		data.signature[..].to_vec(),
+		index.to_le_bytes().to_vec(),
+	];
+	let word = |value: usize| {
+		let mut word = vec![0u8; 32];
+		(&mut word[24..]).copy_from_slice(&(value as u64).to_be_bytes());
+		word
+	};
+
+	let mut head = Vec::new();
+	let mut tail = Vec::new();
+	for field in &fields {
+		head.extend(word(fields.len() * 32 + tail.len()));
+		tail.extend(word(field.len()));
+		tail.extend(field);
+		tail.resize((tail.len() + 31) / 32 * 32, 0);
+	}
+	head.extend(tail);
+	head
+}
+
+fn serve(chain: Arc<Mutex<MockChain>>) -> String {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let url = format!("http://{}", listener.local_addr().unwrap());
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let chain = chain.clone();
+			thread::spawn(move || handle(stream.unwrap(), chain));
+		}
+	});
+	url
+}
+
+fn handle(stream: TcpStream, chain: Arc<Mutex<MockChain>>) {
+	let mut writer = stream.try_clone().unwrap();
+	let mut reader = BufReader::new(stream);
+	loop {
+		let mut content_length = 0;
+		loop {
+			let mut line = String::new();
+			if reader.read_line(&mut line).unwrap_or(0) == 0 {
+				return
+			}
+			let line = line.trim_end().to_lowercase();
+			if line.is_empty() {
+				break
+			}
+			if line.starts_with("content-length:") {
+				content_length = line["content-length:".len()..].trim().parse().unwrap();
+			}
+		}
+
+		let mut body = vec![0u8; content_length];
+		reader.read_exact(&mut body).unwrap();
+		let request: Value = serde_json::from_slice(&body).unwrap();
+		let result = chain.lock().unwrap().result(&request);
+		let response = json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string();
+		write!(
+			writer, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+			response.len(), response,
+		).unwrap();
+	}
+}
+
+fn follower(chain: &Arc<Mutex<MockChain>>) -> Eth1Follower<MinimalConfig> {
+	let requirements = Eth1Requirements { chain_id: 1, network_id: None, deposit_contract: DEPOSIT_CONTRACT };
+	let endpoints = Eth1Endpoints::new(vec![Eth1Endpoint::new(&serve(chain.clone()))], requirements);
+	Eth1Follower::new(endpoints, Arc::new(MemoryIndexStore::default()), 0).unwrap()
+}
+
+#[test]
+fn follows_deposits_and_recent_blocks() {
+	let chain = Arc::new(Mutex::new(MockChain::new(100, vec![(10, 0), (50, 1), (50, 2)])));
+	let mut follower = follower(&chain);
+
+	// Minimal follow distance is 16 blocks.
+	assert_eq!(follower.update().unwrap(), 85);
+	assert_eq!(follower.cache().tree().deposit_count(), 3);
+	assert_eq!(follower.cache().last_block().unwrap().hash, chain.lock().unwrap().hash(84));
+
+	let mut tree = shasper_blockchain::eth1::DepositTree::<MinimalConfig>::new();
+	for index in 0..3 {
+		tree.push(&deposit(index)).unwrap();
+	}
+	let eth1_chain = follower.chain();
+	let eth1_chain = eth1_chain.read().unwrap();
+	let last = eth1_chain.blocks().last().unwrap();
+	assert_eq!(last.timestamp, 84 * SECONDS_PER_BLOCK);
+	assert_eq!(last.eth1_data.block_hash, chain.lock().unwrap().hash(84));
+	assert_eq!(last.eth1_data.deposit_count, 3);
+	assert_eq!(last.eth1_data.deposit_root, tree.root().unwrap());
+	assert!(eth1_chain.blocks().count() > 16);
+	assert!(eth1_chain.blocks().zip(eth1_chain.blocks().skip(1)).all(|(a, b)| a.timestamp < b.timestamp));
+}
+
+#[test]
+fn follows_new_blocks_only() {
+	let chain = Arc::new(Mutex::new(MockChain::new(100, vec![(10, 0)])));
+	let mut follower = follower(&chain);
+
+	assert_eq!(follower.update().unwrap(), 85);
+	assert_eq!(follower.update().unwrap(), 0);
+	chain.lock().unwrap().head = 102;
+	assert_eq!(follower.update().unwrap(), 2);
+	assert_eq!(follower.cache().next_block_number(), 87);
+	assert_eq!(follower.cache().tree().deposit_count(), 1);
+}
+
+#[test]
+fn rolls_back_reorged_deposits() {
+	let chain = Arc::new(Mutex::new(MockChain::new(100, vec![(10, 0), (82, 1)])));
+	let mut follower = follower(&chain);
+	follower.update().unwrap();
+	assert_eq!(follower.cache().tree().deposit_count(), 2);
+
+	{
+		let mut chain = chain.lock().unwrap();
+		chain.head = 101;
+		chain.fork_from = 80;
+		chain.fork = 1;
+		chain.deposits.pop();
+	}
+	follower.update().unwrap();
+
+	assert_eq!(follower.cache().tree().deposit_count(), 1);
+	let eth1_chain = follower.chain();
+	let last = eth1_chain.read().unwrap().blocks().last().unwrap().clone();
+	assert_eq!(last.eth1_data.block_hash, chain.lock().unwrap().hash(85));
+	assert_eq!(last.eth1_data.deposit_count, 1);
+}
+
+#[test]
+fn missing_deposit_is_rejected() {
+	let chain = Arc::new(Mutex::new(MockChain::new(100, vec![(10, 0), (20, 2)])));
+	let mut follower = follower(&chain);
+
+	match follower.update() {
+		Err(Error::MissingDeposit { expected: 1, found: 2 }) => (),
+		other => panic!("Unexpected result {:?}", other.map(|_| ())),
+	}
+	assert_eq!(follower.cache().tree().deposit_count(), 0);
+}
+
+#[test]
+fn deposit_event_round_trips() {
+	let data = deposit(7);
+	let (index, decoded) = shasper_blockchain::eth1_endpoints::decode_deposit_event(&deposit_event(&data, 7))
+		.unwrap();
+	assert_eq!(index, 7);
+	assert_eq!(decoded, data);
+}
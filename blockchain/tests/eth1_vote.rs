@@ -0,0 +1,79 @@
+use beacon::{BeaconState, MinimalConfig};
+use beacon::primitives::H256;
+use beacon::types::Eth1Data;
+use shasper_blockchain::eth1_vote::{self, Eth1BlockData, Eth1Chain};
+
+fn eth1_data(n: u8, deposit_count: u64) -> Eth1Data {
+	Eth1Data {
+		deposit_root: H256::repeat_byte(n),
+		deposit_count,
+		block_hash: H256::repeat_byte(n),
+	}
+}
+
+/// Minimal config state in a voting period starting at 10192, whose
+/// candidate blocks are from 9744 to 9968.
+fn state(votes: &[Eth1Data]) -> BeaconState<MinimalConfig> {
+	BeaconState {
+		genesis_time: 10_000,
+		slot: 33,
+		eth1_data: eth1_data(0, 1),
+		eth1_data_votes: votes.to_vec().into(),
+		..Default::default()
+	}
+}
+
+fn chain() -> Eth1Chain {
+	let mut chain = Eth1Chain::new();
+	for (timestamp, n, deposit_count) in &[
+		(9_700, 1, 1), (9_800, 2, 1), (9_900, 3, 2), (9_950, 4, 2), (10_000, 5, 3),
+	] {
+		chain.push(Eth1BlockData { timestamp: *timestamp, eth1_data: eth1_data(*n, *deposit_count) });
+	}
+	chain
+}
+
+#[test]
+fn candidate_window_follows_eth1_head() {
+	let state = state(&[]);
+	let period_start = eth1_vote::voting_period_start_time(&state);
+	assert_eq!(period_start, 10_192);
+	assert!(!eth1_vote::is_candidate_block::<MinimalConfig>(9_743, period_start));
+	assert!(eth1_vote::is_candidate_block::<MinimalConfig>(9_744, period_start));
+	assert!(eth1_vote::is_candidate_block::<MinimalConfig>(9_968, period_start));
+	assert!(!eth1_vote::is_candidate_block::<MinimalConfig>(9_969, period_start));
+}
+
+#[test]
+fn latest_candidate_is_voted_without_votes() {
+	assert_eq!(eth1_vote::eth1_vote(&state(&[]), &chain()), eth1_data(4, 2));
+}
+
+#[test]
+fn most_frequent_valid_vote_is_joined() {
+	let votes = [eth1_data(5, 3), eth1_data(5, 3), eth1_data(3, 2), eth1_data(2, 1), eth1_data(3, 2)];
+	assert_eq!(eth1_vote::eth1_vote(&state(&votes), &chain()), eth1_data(3, 2));
+
+	// Ties go to the earliest vote.
+	let votes = [eth1_data(2, 1), eth1_data(3, 2)];
+	assert_eq!(eth1_vote::eth1_vote(&state(&votes), &chain()), eth1_data(2, 1));
+}
+
+#[test]
+fn state_eth1_data_is_kept_without_candidates() {
+	let mut state = state(&[eth1_data(3, 2)]);
+	state.eth1_data = eth1_data(0, 3);
+	assert_eq!(eth1_vote::eth1_vote(&state, &chain()), eth1_data(0, 3));
+	assert_eq!(eth1_vote::eth1_vote(&state, &Eth1Chain::new()), eth1_data(0, 3));
+}
+
+#[test]
+fn reorged_blocks_are_replaced() {
+	let mut chain = chain();
+	chain.push(Eth1BlockData { timestamp: 9_900, eth1_data: eth1_data(6, 1) });
+	chain.prune(9_800);
+
+	let blocks = chain.blocks().map(|block| block.eth1_data.clone()).collect::<Vec<_>>();
+	assert_eq!(blocks, vec![eth1_data(2, 1), eth1_data(6, 1)]);
+	assert_eq!(eth1_vote::eth1_vote(&state(&[]), &chain), eth1_data(6, 1));
+}
@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use beacon::primitives::H256;
+use beacon::types::BeaconBlockHeader;
+use shasper_blockchain::explorer::{ExplorerIndex, ProposedBlock, AttestationInclusion};
+use shasper_blockchain::index::{self, MemoryIndexStore};
+
+fn root(n: u8) -> H256 {
+	H256::repeat_byte(n)
+}
+
+fn header(slot: u64, parent: Option<u8>) -> BeaconBlockHeader {
+	BeaconBlockHeader {
+		slot,
+		parent_root: parent.map(root).unwrap_or_default(),
+		..Default::default()
+	}
+}
+
+/// Blocks 1 to 3 at slots 0 to 2, and block 4 at slot 2 forking off block 2.
+fn headers() -> HashMap<H256, BeaconBlockHeader> {
+	let mut headers = HashMap::new();
+	headers.insert(root(1), header(0, None));
+	headers.insert(root(2), header(1, Some(1)));
+	headers.insert(root(3), header(2, Some(2)));
+	headers.insert(root(4), header(2, Some(2)));
+	headers
+}
+
+fn set_head(store: &MemoryIndexStore, head: u8) {
+	let headers = headers();
+	index::update_canon(store, root(head), |id| Ok(headers[id].clone())).unwrap();
+}
+
+fn inclusion(validator: u64, epoch: u64, inclusion_slot: u64, block: u8) -> BTreeMap<(u64, u64), Vec<AttestationInclusion>> {
+	let mut inclusions = BTreeMap::new();
+	inclusions.insert((validator, epoch), vec![AttestationInclusion {
+		slot: inclusion_slot - 1,
+		committee_index: 0,
+		inclusion_slot,
+		block_root: root(block),
+	}]);
+	inclusions
+}
+
+#[test]
+fn proposals_are_listed_while_canonical() {
+	let store = Arc::new(MemoryIndexStore::default());
+	let explorer = ExplorerIndex::new(store.clone());
+
+	set_head(&store, 3);
+	explorer.insert_proposal(7, ProposedBlock { slot: 1, root: root(2) }).unwrap();
+	explorer.insert_proposal(7, ProposedBlock { slot: 2, root: root(3) }).unwrap();
+	explorer.insert_proposal(7, ProposedBlock { slot: 2, root: root(3) }).unwrap();
+	assert_eq!(explorer.proposed_blocks(7).unwrap(), vec![
+		ProposedBlock { slot: 1, root: root(2) },
+		ProposedBlock { slot: 2, root: root(3) },
+	]);
+	assert_eq!(explorer.proposed_blocks(8).unwrap(), Vec::new());
+
+	set_head(&store, 4);
+	assert_eq!(explorer.proposed_blocks(7).unwrap(), vec![ProposedBlock { slot: 1, root: root(2) }]);
+
+	set_head(&store, 3);
+	assert_eq!(explorer.proposed_blocks(7).unwrap().len(), 2);
+}
+
+#[test]
+fn inclusions_of_reorged_blocks_are_pruned() {
+	let store = Arc::new(MemoryIndexStore::default());
+	let explorer = ExplorerIndex::new(store.clone());
+
+	set_head(&store, 3);
+	explorer.insert_inclusions(inclusion(5, 0, 2, 3)).unwrap();
+	assert_eq!(explorer.attestation_inclusions(5, 0).unwrap().len(), 1);
+	assert_eq!(explorer.attestation_inclusions(5, 1).unwrap(), Vec::new());
+
+	set_head(&store, 4);
+	assert_eq!(explorer.attestation_inclusions(5, 0).unwrap(), Vec::new());
+	explorer.insert_inclusions(inclusion(5, 0, 2, 4)).unwrap();
+
+	// The inclusion in block 3 was pruned when block 4 was indexed.
+	set_head(&store, 3);
+	assert_eq!(explorer.attestation_inclusions(5, 0).unwrap(), Vec::new());
+	set_head(&store, 4);
+	assert_eq!(explorer.attestation_inclusions(5, 0).unwrap(), inclusion(5, 0, 2, 4)[&(5, 0)]);
+}
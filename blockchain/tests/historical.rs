@@ -0,0 +1,107 @@
+use beacon::{BeaconState, Config, MinimalConfig};
+use beacon::primitives::H256;
+use beacon::types::HistoricalBatch;
+use beacon::utils::is_valid_merkle_branch;
+use bm_le::tree_root;
+use shasper_blockchain::Error;
+use shasper_blockchain::historical::{self, RecentRoots};
+
+type C = MinimalConfig;
+
+fn root(value: u64) -> H256 {
+	let mut root = H256::default();
+	(&mut root[0..8]).copy_from_slice(&value.to_le_bytes());
+	root
+}
+
+fn block_root(slot: u64) -> H256 {
+	root(slot + 1)
+}
+
+fn state_root(slot: u64) -> H256 {
+	root(slot + 1_000)
+}
+
+/// Batch of the first `SLOTS_PER_HISTORICAL_ROOT` slots.
+fn batch() -> HistoricalBatch<C> {
+	let mut batch = HistoricalBatch::<C>::default();
+	for slot in 0..C::slots_per_historical_root() {
+		batch.block_roots[slot as usize] = block_root(slot);
+		batch.state_roots[slot as usize] = state_root(slot);
+	}
+	batch
+}
+
+/// States before and after crossing the first batch boundary, with slots
+/// skipped in between.
+fn states() -> (BeaconState<C>, BeaconState<C>) {
+	let slots = C::slots_per_historical_root();
+	let mut parent = BeaconState::<C>::default();
+	parent.slot = slots - 4;
+	let mut state = BeaconState::<C>::default();
+	state.slot = slots + 2;
+	for slot in 0..(slots + 2) {
+		let i = (slot % slots) as usize;
+		if slot < parent.slot {
+			parent.block_roots[i] = block_root(slot);
+			parent.state_roots[i] = state_root(slot);
+		}
+		if slot >= state.slot - slots {
+			state.block_roots[i] = block_root(slot);
+			state.state_roots[i] = state_root(slot);
+		}
+	}
+	state.historical_roots.push(tree_root::<<C as Config>::Digest, _>(&batch()));
+	(parent, state)
+}
+
+#[test]
+fn reconstructs_batch_across_boundary() {
+	let (parent, state) = states();
+	assert_eq!(historical::reconstruct_batch(&parent, &state), Some(batch()));
+
+	let mut state = state;
+	state.historical_roots[0] = H256::repeat_byte(0xff);
+	assert_eq!(historical::reconstruct_batch(&parent, &state), None);
+}
+
+#[test]
+fn block_root_proof_is_valid_merkle_branch() {
+	let (_, state) = states();
+	let batch = batch();
+	let proof = historical::prove_block_root(&state, &batch, 5).unwrap();
+
+	assert_eq!(proof.block_root, block_root(5));
+	assert!(is_valid_merkle_branch::<C>(
+		proof.block_root,
+		&proof.branch,
+		proof.branch.len() as u64,
+		proof.index,
+		tree_root::<<C as Config>::Digest, _>(&state),
+	));
+	assert!(!is_valid_merkle_branch::<C>(
+		block_root(6),
+		&proof.branch,
+		proof.branch.len() as u64,
+		proof.index,
+		tree_root::<<C as Config>::Digest, _>(&state),
+	));
+
+	match historical::prove_block_root(&state, &batch, C::slots_per_historical_root()) {
+		Err(Error::SlotNotCovered(_)) => (),
+		other => panic!("Unexpected result: {:?}", other),
+	}
+}
+
+#[test]
+fn recent_roots_cover_last_slots() {
+	let (_, state) = states();
+	let recent = RecentRoots::new(H256::repeat_byte(1), &state);
+	let slots = C::slots_per_historical_root();
+
+	assert_eq!(recent.batch_count, 1);
+	assert_eq!(recent.at_slot(slots + 1), Some((block_root(slots + 1), state_root(slots + 1))));
+	assert_eq!(recent.at_slot(3), Some((block_root(3), state_root(3))));
+	assert_eq!(recent.at_slot(1), None);
+	assert_eq!(recent.at_slot(slots + 2), None);
+}
@@ -0,0 +1,42 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use shasper_blockchain::api_auth::ApiConfig;
+use shasper_blockchain::http::{self, MAX_REQUEST_LINE};
+
+/// Serve a constant response on a free local port.
+fn serve() -> SocketAddr {
+	let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+	let api = ApiConfig { public_address: Some(address), admin_address: None, ..Default::default() };
+	http::serve(api, "test", |_| (200, "{}".to_string())).unwrap();
+	address
+}
+
+fn get(address: SocketAddr, target: &str) -> String {
+	let mut stream = TcpStream::connect(address).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+	write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+	let mut response = String::new();
+	let _ = stream.read_to_string(&mut response);
+	response
+}
+
+#[test]
+fn stalled_connection_does_not_block_others() {
+	let address = serve();
+	let mut stalled = TcpStream::connect(address).unwrap();
+	stalled.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+	thread::sleep(Duration::from_millis(100));
+
+	assert!(get(address, "/").starts_with("HTTP/1.1 200 OK"));
+}
+
+#[test]
+fn oversized_request_line_is_rejected() {
+	let address = serve();
+	let target = format!("/{}", "a".repeat(MAX_REQUEST_LINE));
+
+	assert_eq!(get(address, &target), "");
+	assert!(get(address, "/").starts_with("HTTP/1.1 200 OK"));
+}
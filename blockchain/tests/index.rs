@@ -0,0 +1,82 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use beacon::primitives::H256;
+use beacon::types::BeaconBlockHeader;
+use shasper_blockchain::index::{self, MemoryIndexStore};
+
+fn root(n: u8) -> H256 {
+	H256::repeat_byte(n)
+}
+
+fn header(slot: u64, parent: Option<u8>) -> BeaconBlockHeader {
+	BeaconBlockHeader {
+		slot,
+		parent_root: parent.map(root).unwrap_or_default(),
+		..Default::default()
+	}
+}
+
+/// Blocks 1 to 5 at slots 0 to 3 and 5, and blocks 6 and 7 at slots 2 and 4
+/// forking off block 2.
+fn headers() -> HashMap<H256, BeaconBlockHeader> {
+	let mut headers = HashMap::new();
+	headers.insert(root(1), header(0, None));
+	headers.insert(root(2), header(1, Some(1)));
+	headers.insert(root(3), header(2, Some(2)));
+	headers.insert(root(4), header(3, Some(3)));
+	headers.insert(root(5), header(5, Some(4)));
+	headers.insert(root(6), header(2, Some(2)));
+	headers.insert(root(7), header(4, Some(6)));
+	headers
+}
+
+fn canon(store: &MemoryIndexStore) -> Vec<Option<H256>> {
+	(0..6).map(|slot| index::canon_root(store, slot).unwrap()).collect()
+}
+
+#[test]
+fn update_canon_reorgs_across_several_slots() {
+	let store = MemoryIndexStore::default();
+	let headers = headers();
+	let fetched = Cell::new(0);
+	let fetch = |id: &H256| {
+		fetched.set(fetched.get() + 1);
+		Ok(headers[id].clone())
+	};
+
+	assert_eq!(
+		index::update_canon(&store, root(5), fetch).unwrap(),
+		vec![root(1), root(2), root(3), root(4), root(5)],
+	);
+	assert_eq!(canon(&store), vec![
+		Some(root(1)), Some(root(2)), Some(root(3)), Some(root(4)), None, Some(root(5)),
+	]);
+
+	assert_eq!(index::update_canon(&store, root(7), fetch).unwrap(), vec![root(6), root(7)]);
+	assert_eq!(canon(&store), vec![
+		Some(root(1)), Some(root(2)), Some(root(6)), None, Some(root(7)), None,
+	]);
+
+	assert_eq!(
+		index::update_canon(&store, root(5), fetch).unwrap(),
+		vec![root(3), root(4), root(5)],
+	);
+	assert_eq!(canon(&store), vec![
+		Some(root(1)), Some(root(2)), Some(root(3)), Some(root(4)), None, Some(root(5)),
+	]);
+	// Headers are only fetched the first time they are indexed.
+	assert_eq!(fetched.get(), 7);
+	assert_eq!(index::header(&store, &root(6)).unwrap(), Some(header(2, Some(2))));
+}
+
+#[test]
+fn update_canon_uses_inserted_headers() {
+	let store = MemoryIndexStore::default();
+	let headers = headers().into_iter().collect::<Vec<_>>();
+	index::insert_headers(&store, &headers).unwrap();
+
+	assert_eq!(
+		index::update_canon(&store, root(7), |_| panic!("Header is indexed")).unwrap(),
+		vec![root(1), root(2), root(6), root(7)],
+	);
+}
@@ -0,0 +1,56 @@
+use beacon::{BeaconState, MinimalConfig};
+use beacon::primitives::{H256, ValidatorId, Version};
+use beacon::types::{Fork, Validator};
+use crypto::bls;
+use shasper_blockchain::{interop, operator};
+
+fn pubkey(secret: &bls::Secret) -> ValidatorId {
+	ValidatorId::from_slice(&bls::Public::from_secret_key(secret).as_bytes()[..])
+}
+
+fn fork(version: u8) -> Fork {
+	Fork {
+		previous_version: Version::from([version, 0, 0, 0]),
+		current_version: Version::from([version, 0, 0, 0]),
+		epoch: 0,
+	}
+}
+
+#[test]
+fn signed_exit_verifies_under_its_fork_only() {
+	let secret = interop::secret_key(0);
+	let exit = operator::sign_voluntary_exit::<MinimalConfig>(&secret, 3, 10, &fork(1));
+	assert_eq!((exit.validator_index, exit.epoch), (3, 10));
+
+	assert!(operator::verify_voluntary_exit::<MinimalConfig>(&exit, &pubkey(&secret), &fork(1)));
+	assert!(!operator::verify_voluntary_exit::<MinimalConfig>(&exit, &pubkey(&secret), &fork(2)));
+	assert!(!operator::verify_voluntary_exit::<MinimalConfig>(
+		&exit, &pubkey(&interop::secret_key(1)), &fork(1),
+	));
+}
+
+#[test]
+fn withdrawal_credentials_commit_to_withdrawal_key() {
+	let withdrawal_key = pubkey(&interop::secret_key(2));
+	let other_key = pubkey(&interop::secret_key(3));
+	let credentials = operator::bls_withdrawal_credentials::<MinimalConfig>(&withdrawal_key);
+	assert_eq!(credentials[0], 0);
+
+	let state = BeaconState::<MinimalConfig> {
+		validators: vec![
+			Validator { pubkey: other_key.clone(), withdrawal_credentials: credentials, ..Default::default() },
+			Validator { pubkey: withdrawal_key.clone(), withdrawal_credentials: H256::repeat_byte(1), ..Default::default() },
+		].into(),
+		..Default::default()
+	};
+
+	let all = operator::validator_credentials(&state, None);
+	assert_eq!(all.len(), 2);
+	assert!(all[0].is_bls && all[0].matches::<MinimalConfig>(&withdrawal_key));
+	assert!(!all[1].is_bls && !all[1].matches::<MinimalConfig>(&withdrawal_key));
+
+	let selected = operator::validator_credentials(&state, Some(&[1, 5]));
+	assert_eq!(selected.len(), 1);
+	assert_eq!(selected[0].index, 1);
+	assert_eq!(operator::validator_index(&state, &withdrawal_key), Some(1));
+}
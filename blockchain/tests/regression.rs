@@ -0,0 +1,37 @@
+use beacon::{Config, MinimalConfig, MainnetConfig};
+use crypto::bls::BLSVerification;
+use shasper_blockchain::regression;
+
+fn replay_vectors<C: Config>(config_name: &str) {
+	let dir = regression::vectors_dir(env!("CARGO_MANIFEST_DIR"), config_name);
+	let vectors = regression::read_vectors::<C, _>(&dir)
+		.unwrap_or_else(|e| panic!("Reading vectors in {:?} failed: {}", dir, e));
+	assert!(
+		!vectors.is_empty(),
+		"No regression vectors in {:?}, record them with `debug record-vectors`", dir,
+	);
+	assert!(
+		vectors.iter().any(|vector| regression::is_interesting::<C>(vector.pre.slot, vector.block.0.slot)),
+		"No regression vector in {:?} crosses an epoch boundary", dir,
+	);
+
+	for vector in vectors {
+		match vector.replay::<BLSVerification>() {
+			Ok(root) => assert_eq!(
+				root, vector.post_root,
+				"Post-state root of vector {} changed", vector.name,
+			),
+			Err(e) => panic!("Block of vector {} is no longer valid: {:?}", vector.name, e),
+		}
+	}
+}
+
+#[test]
+fn minimal_vectors_replay() {
+	replay_vectors::<MinimalConfig>("minimal");
+}
+
+#[test]
+fn mainnet_vectors_replay() {
+	replay_vectors::<MainnetConfig>("mainnet");
+}
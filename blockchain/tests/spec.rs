@@ -0,0 +1,45 @@
+use beacon::{MinimalConfig, MainnetConfig};
+use shasper_blockchain::spec::ChainSpec;
+
+#[test]
+fn deposit_contract_is_read_from_spec() {
+	let spec = ChainSpec::from_yaml::<MinimalConfig>(
+		"DEPOSIT_CHAIN_ID: 5\n\
+		 DEPOSIT_NETWORK_ID: 5\n\
+		 DEPOSIT_CONTRACT_ADDRESS: 0x8c5fecdC472E27Bc447696F431E425D02dd46a8c\n",
+		"minimal",
+	).unwrap();
+
+	assert_eq!(spec.deposit_chain_id, Some(5));
+	assert_eq!(spec.deposit_network_id, Some(5));
+	assert_eq!(
+		spec.deposit_contract_address.map(hex::encode),
+		Some("8c5fecdc472e27bc447696f431e425d02dd46a8c".to_string()),
+	);
+}
+
+#[test]
+fn short_deposit_contract_address_is_read_as_integer() {
+	let spec = ChainSpec::from_yaml::<MinimalConfig>(
+		"DEPOSIT_CONTRACT_ADDRESS: 0x1234\n", "minimal",
+	).unwrap();
+
+	let mut expected = [0u8; 20];
+	expected[18] = 0x12;
+	expected[19] = 0x34;
+	assert_eq!(spec.deposit_contract_address, Some(expected));
+}
+
+#[test]
+fn spec_restating_preset_matches_config() {
+	let spec = ChainSpec::from_yaml::<MinimalConfig>(
+		"DEPOSIT_CHAIN_ID: 5\n", "minimal",
+	).unwrap();
+	assert!(spec.matches_config::<MinimalConfig>());
+	assert!(!spec.matches_config::<MainnetConfig>());
+
+	let spec = ChainSpec::from_yaml::<MinimalConfig>(
+		"PROPOSER_REWARD_QUOTIENT: 3\n", "minimal",
+	).unwrap();
+	assert!(!spec.matches_config::<MinimalConfig>());
+}
@@ -0,0 +1,54 @@
+use beacon::{BeaconState, Config, MinimalConfig};
+use beacon::consts::FAR_FUTURE_EPOCH;
+use beacon::primitives::H256;
+use beacon::types::Validator;
+use bm_le::tree_root;
+use shasper_blockchain::state_root::FieldRootCache;
+
+type C = MinimalConfig;
+
+fn state() -> BeaconState<C> {
+	let mut state = BeaconState::<C>::default();
+	for i in 0..8u8 {
+		state.validators.push(Validator {
+			withdrawal_credentials: H256::repeat_byte(i),
+			effective_balance: C::max_effective_balance(),
+			exit_epoch: FAR_FUTURE_EPOCH,
+			withdrawable_epoch: FAR_FUTURE_EPOCH,
+			..Default::default()
+		});
+		state.balances.push(C::max_effective_balance() + i as u64);
+	}
+	state.slot = 9;
+	state.genesis_time = 1_000;
+	state
+}
+
+#[test]
+fn cached_root_matches_tree_root() {
+	let mut cache = FieldRootCache::<C>::new();
+	let mut state = state();
+	assert_eq!(cache.state_root(&state), tree_root::<<C as Config>::Digest, _>(&state));
+
+	state.slot += 1;
+	state.balances[3] -= 1_000;
+	state.validators[5].slashed = true;
+	state.block_roots[2] = H256::repeat_byte(0xaa);
+	assert_eq!(cache.state_root(&state), tree_root::<<C as Config>::Digest, _>(&state));
+}
+
+#[test]
+fn unchanged_fields_are_reused() {
+	let mut cache = FieldRootCache::<C>::new();
+	let mut state = state();
+	cache.state_root(&state);
+	let (hits, misses) = cache.stats();
+	assert_eq!(hits, 0);
+
+	state.slot += 1;
+	state.balances[0] += 1;
+	assert_eq!(cache.state_root(&state), tree_root::<<C as Config>::Digest, _>(&state));
+	let (new_hits, new_misses) = cache.stats();
+	assert_eq!(new_misses - misses, 1);
+	assert_eq!(new_hits - hits, misses - 1);
+}
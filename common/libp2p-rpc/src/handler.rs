@@ -9,7 +9,7 @@ use libp2p::swarm::protocols_handler::{
 };
 use futures::prelude::*;
 use tokio_io::{AsyncRead, AsyncWrite};
-use tokio_io::codec::Encoder;
+use tokio_io::codec::{Encoder, Decoder};
 use log::*;
 use crate::{RPCEvent, RPCRequest, RPCError, RequestId};
 use crate::protocol::{InboundFramed, OutboundFramed, RPCProtocol, RPCInbound, RPCOutbound};
@@ -17,6 +17,14 @@ use crate::protocol::{InboundFramed, OutboundFramed, RPCProtocol, RPCInbound, RP
 /// The time (in seconds) before a substream that is awaiting a response from the user times out.
 pub const RESPONSE_TIMEOUT: u64 = 10;
 
+/// The time (in seconds) without any substream after which a connection is
+/// no longer kept alive by the RPC.
+pub const INACTIVE_TIMEOUT: u64 = 30;
+
+/// Maximum number of inbound requests of a peer awaiting a response. Further
+/// requests are answered with the protocol's rate limited response.
+pub const MAX_INBOUND_SUBSTREAMS: usize = 8;
+
 /// Implementation of `ProtocolsHandler` for the RPC protocol.
 pub struct RPCHandler<P: RPCProtocol, TSubstream> where
 	TSubstream: AsyncRead + AsyncWrite
@@ -27,8 +35,9 @@ pub struct RPCHandler<P: RPCProtocol, TSubstream> where
 	/// If `Some`, something bad happened and we should shut down the handler with an error.
 	pending_error: Option<ProtocolsHandlerUpgrErr<RPCError>>,
 
-    /// Queue of events to produce in `poll()`.
-    events_out: SmallVec<[RPCEvent<P::Request, P::Response>; 4]>,
+    /// Queue of events to produce in `poll()`, with the raw bytes of
+    /// inbound requests.
+    events_out: SmallVec<[(RPCEvent<P::Request, P::Response>, usize); 4]>,
 
     /// Queue of outbound substreams to open.
     dial_queue: SmallVec<[RPCEvent<P::Request, P::Response>; 4]>,
@@ -48,12 +57,17 @@ pub struct RPCHandler<P: RPCProtocol, TSubstream> where
     /// Maximum number of concurrent outbound substreams being opened. Value is never modified.
     max_dial_negotiated: u32,
 
-    /// Value to return from `connection_keep_alive`.
-    keep_alive: KeepAlive,
+    /// When the handler last had a substream open or being opened.
+    last_active: Instant,
 
     /// After the given duration has elapsed, an inactive connection will shutdown.
     inactive_timeout: Duration,
 
+    /// Time allowed for a substream to make progress: for the user to
+    /// respond to an inbound request, for the peer to respond to an outbound
+    /// request, or for a response to be written and the substream closed.
+    substream_timeout: Duration,
+
 	/// The protocol handler.
 	protocol: P,
 
@@ -76,6 +90,22 @@ where
     /// A response has been sent, pending writing and flush.
     ResponsePendingSend {
         substream: futures::sink::Send<InboundFramed<P, TSubstream>>,
+        /// The time when the substream is dropped, if the peer does not read.
+        timeout: Instant,
+    },
+    /// A chunk of a streamed response has been sent, pending writing and
+    /// flush. The substream then waits for the next chunk.
+    ChunkPendingSend {
+        id: RequestId,
+        substream: futures::sink::Send<InboundFramed<P, TSubstream>>,
+        /// The time when the substream is dropped, if the peer does not read.
+        timeout: Instant,
+    },
+    /// A streamed response is complete, and the substream is being closed.
+    Closing {
+        substream: InboundFramed<P, TSubstream>,
+        /// The time when the substream is dropped without a clean close.
+        timeout: Instant,
     },
     /// A request has been sent, and we are awaiting a response. This future is driven in the
     /// handler because GOODBYE requests can be handled and responses dropped instantly.
@@ -97,6 +127,7 @@ impl<P, TSubstream> RPCHandler<P, TSubstream> where
 	pub fn new(
 		protocol: P,
         inactive_timeout: Duration,
+        substream_timeout: Duration,
     ) -> Self {
         RPCHandler {
             listen_protocol: SubstreamProtocol::new(RPCInbound(protocol.clone())),
@@ -108,8 +139,9 @@ impl<P, TSubstream> RPCHandler<P, TSubstream> where
             substreams: Vec::new(),
             current_substream_id: 1,
             max_dial_negotiated: 8,
-            keep_alive: KeepAlive::Yes,
+            last_active: Instant::now(),
             inactive_timeout,
+            substream_timeout,
 			protocol,
             _marker: PhantomData,
         }
@@ -138,10 +170,14 @@ impl<P, TSubstream> RPCHandler<P, TSubstream> where
 
     /// Opens an outbound substream with a request.
     pub fn send_request(&mut self, rpc_event: RPCEvent<P::Request, P::Response>) {
-        self.keep_alive = KeepAlive::Yes;
-
         self.dial_queue.push(rpc_event);
     }
+
+    /// Whether any substream is open or being opened.
+    fn is_active(&self) -> bool {
+        self.dial_negotiated > 0 || !self.dial_queue.is_empty() ||
+            !self.waiting_substreams.is_empty() || !self.substreams.is_empty()
+    }
 }
 
 impl<P, TSubstream> Default for RPCHandler<P, TSubstream> where
@@ -149,7 +185,11 @@ impl<P, TSubstream> Default for RPCHandler<P, TSubstream> where
 	TSubstream: AsyncRead + AsyncWrite,
 {
 	fn default() -> Self {
-		RPCHandler::new(P::default(), Duration::from_secs(30))
+		RPCHandler::new(
+			P::default(),
+			Duration::from_secs(INACTIVE_TIMEOUT),
+			Duration::from_secs(RESPONSE_TIMEOUT),
+		)
 	}
 }
 
@@ -157,9 +197,10 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
 	P: RPCProtocol + Clone,
 	TSubstream: AsyncRead + AsyncWrite,
 	<P::OutboundCodec as Encoder>::Error: core::fmt::Debug,
+	<P::OutboundCodec as Decoder>::Error: core::fmt::Debug,
 {
 	type InEvent = RPCEvent<P::Request, P::Response>;
-	type OutEvent = RPCEvent<P::Request, P::Response>;
+	type OutEvent = (RPCEvent<P::Request, P::Response>, usize);
 	type Error = ProtocolsHandlerUpgrErr<RPCError>;
 	type Substream = TSubstream;
 	type InboundProtocol = RPCInbound<P>;
@@ -174,23 +215,32 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
         &mut self,
         out: <RPCInbound<P> as InboundUpgrade<TSubstream>>::Output,
     ) {
-        let (req, substream) = out;
+        let (req, received, substream) = out;
         // drop the stream and return a 0 id for goodbye "requests"
         if req.is_goodbye() {
-            self.events_out.push(RPCEvent::Request(0, req));
+            self.events_out.push((RPCEvent::Request(0, req), received));
+            return;
+        }
+
+        if self.waiting_substreams.len() >= MAX_INBOUND_SUBSTREAMS {
+            debug!("Rejecting inbound request, {} requests awaiting a response", self.waiting_substreams.len());
+            self.substreams.push(SubstreamState::ResponsePendingSend {
+                substream: substream.send(self.protocol.rate_limited_response()),
+                timeout: Instant::now() + self.substream_timeout,
+            });
             return;
         }
 
         // New inbound request. Store the stream and tag the output.
         let awaiting_stream = WaitingResponse {
             substream,
-            timeout: Instant::now() + Duration::from_secs(RESPONSE_TIMEOUT),
+            timeout: Instant::now() + self.substream_timeout,
         };
         self.waiting_substreams
             .insert(self.current_substream_id, awaiting_stream);
 
         self.events_out
-            .push(RPCEvent::Request(self.current_substream_id, req));
+            .push((RPCEvent::Request(self.current_substream_id, req), received));
         self.current_substream_id += 1;
     }
 
@@ -200,15 +250,7 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
         rpc_event: Self::OutboundOpenInfo,
     ) {
         self.dial_negotiated -= 1;
-
-        if self.dial_negotiated == 0
-            && self.dial_queue.is_empty()
-            && self.waiting_substreams.is_empty()
-        {
-            self.keep_alive = KeepAlive::Until(Instant::now() + self.inactive_timeout);
-        } else {
-            self.keep_alive = KeepAlive::Yes;
-        }
+        self.last_active = Instant::now();
 
         // add the stream to substreams if we expect a response, otherwise drop the stream.
         if let RPCEvent::Request(id, req) = rpc_event {
@@ -216,7 +258,7 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
                 let awaiting_stream = SubstreamState::RequestPendingResponse {
                     substream: out,
                     rpc_event: RPCEvent::Request(id, req),
-                    timeout: Instant::now() + Duration::from_secs(RESPONSE_TIMEOUT),
+                    timeout: Instant::now() + self.substream_timeout,
                 };
 
                 self.substreams.push(awaiting_stream);
@@ -235,10 +277,28 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
                     // only send one response per stream. This must be in the waiting state.
                     self.substreams.push(SubstreamState::ResponsePendingSend {
                         substream: waiting_stream.substream.send(res),
+                        timeout: Instant::now() + self.substream_timeout,
+                    });
+                }
+            }
+            RPCEvent::StreamChunk(rpc_id, res) => {
+                if let Some(waiting_stream) = self.waiting_substreams.remove(&rpc_id) {
+                    self.substreams.push(SubstreamState::ChunkPendingSend {
+                        id: rpc_id,
+                        substream: waiting_stream.substream.send(res),
+                        timeout: Instant::now() + self.substream_timeout,
                     });
                 }
             }
-            RPCEvent::Error(_, _) => {}
+            RPCEvent::EndStream(rpc_id) => {
+                if let Some(waiting_stream) = self.waiting_substreams.remove(&rpc_id) {
+                    self.substreams.push(SubstreamState::Closing {
+                        substream: waiting_stream.substream,
+                        timeout: Instant::now() + self.substream_timeout,
+                    });
+                }
+            }
+            RPCEvent::ChunkSent(_) | RPCEvent::Error(_, _) => {}
         }
     }
 
@@ -255,7 +315,11 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
     }
 
     fn connection_keep_alive(&self) -> KeepAlive {
-        self.keep_alive
+        if self.is_active() {
+            KeepAlive::Yes
+        } else {
+            KeepAlive::Until(self.last_active + self.inactive_timeout)
+        }
     }
 
     fn poll(
@@ -282,25 +346,71 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
         }
 
         // remove any streams that have expired
+        let now = Instant::now();
         self.waiting_substreams
-            .retain(|_k, waiting_stream| Instant::now() <= waiting_stream.timeout);
+            .retain(|_k, waiting_stream| now <= waiting_stream.timeout);
+        if self.is_active() {
+            self.last_active = now;
+        }
 
         // drive streams that need to be processed
         for n in (0..self.substreams.len()).rev() {
             let stream = self.substreams.swap_remove(n);
             match stream {
-                SubstreamState::ResponsePendingSend { mut substream } => {
+                SubstreamState::ResponsePendingSend { mut substream, timeout } => {
                     match substream.poll() {
                         Ok(Async::Ready(_substream)) => {} // sent and flushed
                         Ok(Async::NotReady) => {
-                            self.substreams
-                                .push(SubstreamState::ResponsePendingSend { substream });
+                            if now < timeout {
+                                self.substreams
+                                    .push(SubstreamState::ResponsePendingSend { substream, timeout });
+                            } else {
+                                debug!("Dropping response the peer did not read in time");
+                            }
                         }
                         Err(e) => {
 							warn!("Response pending send codec error");
-                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom((
                                 RPCEvent::Error(0, RPCError::Codec("send codec error".to_string())),
-                            )))
+                                0,
+                            ))))
+                        }
+                    }
+                }
+                SubstreamState::ChunkPendingSend { id, mut substream, timeout } => {
+                    match substream.poll() {
+                        Ok(Async::Ready(substream)) => {
+                            self.waiting_substreams.insert(id, WaitingResponse {
+                                substream,
+                                timeout: now + self.substream_timeout,
+                            });
+                            self.events_out.push((RPCEvent::ChunkSent(id), 0));
+                        }
+                        Ok(Async::NotReady) => {
+                            if now < timeout {
+                                self.substreams
+                                    .push(SubstreamState::ChunkPendingSend { id, substream, timeout });
+                            } else {
+                                debug!("Dropping response stream the peer did not read in time");
+                                self.events_out.push((RPCEvent::Error(id, RPCError::StreamTimeout), 0));
+                            }
+                        }
+                        Err(_) => {
+                            warn!("Response chunk pending send codec error");
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom((
+                                RPCEvent::Error(id, RPCError::Codec("send codec error".to_string())),
+                                0,
+                            ))))
+                        }
+                    }
+                }
+                SubstreamState::Closing { mut substream, timeout } => {
+                    match substream.close() {
+                        Ok(Async::Ready(())) | Err(_) => {}
+                        Ok(Async::NotReady) => {
+                            if now < timeout {
+                                self.substreams.push(SubstreamState::Closing { substream, timeout });
+                            }
                         }
                     }
                 }
@@ -311,39 +421,51 @@ impl<P, TSubstream> ProtocolsHandler for RPCHandler<P, TSubstream> where
                 } => match substream.poll() {
                     Ok(Async::Ready(response)) => {
                         if let Some(response) = response {
-                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom((
                                 RPCEvent::Response(rpc_event.id(), response),
-                            )));
+                                0,
+                            ))));
                         } else {
                             // stream closed early or nothing was sent
-                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom((
                                 RPCEvent::Error(
                                     rpc_event.id(),
-                                    RPCError::Custom("Stream closed early. Empty response".into()),
+                                    RPCError::StreamClosed,
                                 ),
-                            )));
+                                0,
+                            ))));
                         }
                     }
                     Ok(Async::NotReady) => {
-                        if Instant::now() < timeout {
+                        if now < timeout {
                             self.substreams
                                 .push(SubstreamState::RequestPendingResponse {
                                     substream,
                                     rpc_event,
                                     timeout,
                                 });
+                        } else {
+                            self.events_out.push((RPCEvent::Error(rpc_event.id(), RPCError::StreamTimeout), 0));
                         }
                     }
                     Err(e) => {
-						warn!("Request pending response codec error");
-                        return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
-                            RPCEvent::Error(rpc_event.id(), RPCError::Codec("response codec error".to_string())),
-                        )))
+						warn!("Request pending response codec error: {:?}", e);
+                        return Ok(Async::Ready(ProtocolsHandlerEvent::Custom((
+                            RPCEvent::Error(rpc_event.id(), RPCError::Codec(format!("response codec error: {:?}", e))),
+                            0,
+                        ))))
                     }
                 },
             }
         }
 
+        // report chunks sent while driving the streams
+        if !self.events_out.is_empty() {
+            return Ok(Async::Ready(ProtocolsHandlerEvent::Custom(
+                self.events_out.remove(0),
+            )));
+        }
+
         // establish outbound substreams
         if !self.dial_queue.is_empty() {
             if self.dial_negotiated < self.max_dial_negotiated {
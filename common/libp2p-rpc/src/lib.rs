@@ -5,7 +5,7 @@ pub use protocol::RPCProtocol;
 
 use futures::prelude::*;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::codec::Encoder;
+use tokio::codec::{Encoder, Decoder};
 use libp2p::{Multiaddr, PeerId};
 use libp2p::core::{ConnectedPoint, ProtocolName};
 use libp2p::swarm::{
@@ -13,19 +13,34 @@ use libp2p::swarm::{
 	PollParameters,
 };
 use core::marker::PhantomData;
+use core::time::Duration;
 
 pub type RequestId = usize;
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum RPCError {
+	/// Encoding or decoding a message failed.
 	Codec(String),
+	/// No request or response arrived in time.
 	StreamTimeout,
+	/// Stream timer failed.
+	Timer,
+	/// Stream closed before a request or response was received.
+	StreamClosed,
+	/// Other error.
 	Custom(String),
 }
 
 impl std::fmt::Display for RPCError {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{:?}", self)
+		match self {
+			RPCError::Codec(reason) => write!(f, "Codec error: {}", reason),
+			RPCError::StreamTimeout => write!(f, "Stream timed out"),
+			RPCError::Timer => write!(f, "Stream timer failed"),
+			RPCError::StreamClosed => write!(f, "Stream closed early"),
+			RPCError::Custom(reason) => write!(f, "{}", reason),
+		}
 	}
 }
 
@@ -35,8 +50,10 @@ impl<T> From<tokio::timer::timeout::Error<T>> for RPCError {
     fn from(err: tokio::timer::timeout::Error<T>) -> Self {
         if err.is_elapsed() {
             RPCError::StreamTimeout
+        } else if err.is_timer() {
+            RPCError::Timer
         } else {
-            RPCError::Custom("Stream timer failed".into())
+            RPCError::Codec("inbound codec error".into())
         }
     }
 }
@@ -44,17 +61,24 @@ impl<T> From<tokio::timer::timeout::Error<T>> for RPCError {
 pub trait RPCRequest<T: RPCType> {
 	fn is_goodbye(&self) -> bool;
 	fn expect_response(&self) -> bool;
-	fn typ(&self) -> T;
 }
 
-pub trait RPCType: ProtocolName + Sized {
-	fn all() -> Vec<Self>;
-}
+/// Protocol an RPC substream is negotiated on. The supported protocols are
+/// listed by the `RPCProtocol`.
+pub trait RPCType: ProtocolName + Sized { }
 
 #[derive(Debug, Clone)]
 pub enum RPCEvent<Req, Res> {
 	Request(RequestId, Req),
 	Response(RequestId, Res),
+	/// Send a chunk of a streamed response, keeping the substream open for
+	/// further chunks.
+	StreamChunk(RequestId, Res),
+	/// Close a streamed response after its last chunk.
+	EndStream(RequestId),
+	/// A streamed response chunk was written out, and the substream is ready
+	/// for the next one.
+	ChunkSent(RequestId),
 	Error(RequestId, RPCError),
 }
 
@@ -63,6 +87,9 @@ impl<Req, Res> RPCEvent<Req, Res> {
 		match self {
 			RPCEvent::Request(id, _) => *id,
 			RPCEvent::Response(id, _) => *id,
+			RPCEvent::StreamChunk(id, _) => *id,
+			RPCEvent::EndStream(id) => *id,
+			RPCEvent::ChunkSent(id) => *id,
 			RPCEvent::Error(id, _) => *id,
 		}
 	}
@@ -70,7 +97,9 @@ impl<Req, Res> RPCEvent<Req, Res> {
 
 #[derive(Debug, Clone)]
 pub enum RPCMessage<Req, Res> {
-	Event(PeerId, RPCEvent<Req, Res>),
+	/// Event of a peer, with the raw bytes an inbound request was read
+	/// from, or zero for other events.
+	Event(PeerId, RPCEvent<Req, Res>, usize),
 	PeerDialed(PeerId),
 	PeerDisconnected(PeerId),
 }
@@ -78,17 +107,37 @@ pub enum RPCMessage<Req, Res> {
 pub struct RPC<P: RPCProtocol, TSubstream> {
 	events: Vec<NetworkBehaviourAction<RPCEvent<P::Request, P::Response>,
 									   RPCMessage<P::Request, P::Response>>>,
+	protocol: P,
+	inactive_timeout: Duration,
+	substream_timeout: Duration,
 	_marker: PhantomData<TSubstream>,
 }
 
 impl<P: RPCProtocol, TSubstream> RPC<P, TSubstream> {
-	pub fn new() -> Self {
+	pub fn new() -> Self where P: Default {
+		Self::with_protocol(P::default())
+	}
+
+	/// Create a new RPC behaviour, with handlers using the given protocol.
+	pub fn with_protocol(protocol: P) -> Self {
         RPC {
             events: Vec::new(),
+            protocol,
+            inactive_timeout: Duration::from_secs(handler::INACTIVE_TIMEOUT),
+            substream_timeout: Duration::from_secs(handler::RESPONSE_TIMEOUT),
             _marker: PhantomData,
         }
     }
 
+    /// Stop keeping connections alive after `inactive_timeout` without any
+    /// substream, and drop substreams making no progress for
+    /// `substream_timeout`.
+    pub fn with_timeouts(mut self, inactive_timeout: Duration, substream_timeout: Duration) -> Self {
+        self.inactive_timeout = inactive_timeout;
+        self.substream_timeout = substream_timeout;
+        self
+    }
+
     /// Submits an RPC request.
     ///
     /// The peer must be connected for this to succeed.
@@ -104,12 +153,15 @@ impl<P, TSubstream> NetworkBehaviour for RPC<P, TSubstream> where
 	P: RPCProtocol + Default + Clone,
 	TSubstream: AsyncRead + AsyncWrite,
 	<P::OutboundCodec as Encoder>::Error: core::fmt::Debug,
+	<P::OutboundCodec as Decoder>::Error: core::fmt::Debug,
 {
 	type ProtocolsHandler = crate::handler::RPCHandler<P, TSubstream>;
     type OutEvent = RPCMessage<P::Request, P::Response>;
 
     fn new_handler(&mut self) -> Self::ProtocolsHandler {
-        Default::default()
+        crate::handler::RPCHandler::new(
+            self.protocol.clone(), self.inactive_timeout, self.substream_timeout,
+        )
     }
 
     // handled by discovery
@@ -136,9 +188,10 @@ impl<P, TSubstream> NetworkBehaviour for RPC<P, TSubstream> where
         event: <Self::ProtocolsHandler as ProtocolsHandler>::OutEvent,
     ) {
         // send the event to the user
+        let (event, received) = event;
         self.events
             .push(NetworkBehaviourAction::GenerateEvent(RPCMessage::Event(
-                source, event,
+                source, event, received,
             )));
     }
 
@@ -24,16 +24,31 @@ pub trait RPCProtocol {
 	type Request: RPCRequest<Self::Type> + Clone;
 	type Response: Clone;
 
-	type InboundCodec: Encoder<Item=Self::Response> + Decoder<Item=Self::Request>;
+	/// Codec of inbound substreams, decoding requests along with the number
+	/// of raw bytes they were read from.
+	type InboundCodec: Encoder<Item=Self::Response> + Decoder<Item=(Self::Request, usize)>;
 	fn inbound_codec(&self, protocol: Self::Type) -> Self::InboundCodec;
 
 	type OutboundCodec: Encoder<Item=Self::Request> + Decoder<Item=Self::Response>;
-	fn outbound_codec(&self, protocol: Self::Type) -> Self::OutboundCodec;
+	/// Codec for an outbound substream. The request being sent is given, so
+	/// that the response can be checked against it.
+	fn outbound_codec(&self, protocol: Self::Type, request: &Self::Request) -> Self::OutboundCodec;
+
+	/// Protocols accepted for inbound substreams.
+	fn inbound_protocols(&self) -> Vec<Self::Type>;
+
+	/// Protocols a request may be sent over, in order of preference. The
+	/// first one supported by the remote is negotiated.
+	fn outbound_protocols(&self, request: &Self::Request) -> Vec<Self::Type>;
+
+	/// Response sent on inbound substreams rejected because the peer has too
+	/// many requests awaiting a response.
+	fn rate_limited_response(&self) -> Self::Response;
 }
 
 pub type InboundFramed<P, TSocket> = Framed<TimeoutStream<Negotiated<TSocket>>,
 											<P as RPCProtocol>::InboundCodec>;
-pub type InboundOutput<P, TSocket> = (<P as RPCProtocol>::Request, InboundFramed<P, TSocket>);
+pub type InboundOutput<P, TSocket> = (<P as RPCProtocol>::Request, usize, InboundFramed<P, TSocket>);
 
 #[derive(Default, Clone)]
 pub struct RPCInbound<P>(pub P);
@@ -43,12 +58,12 @@ impl<P: RPCProtocol> UpgradeInfo for RPCInbound<P> {
 	type InfoIter = Vec<P::Type>;
 
 	fn protocol_info(&self) -> Self::InfoIter {
-		P::Type::all()
+		self.0.inbound_protocols()
 	}
 }
 
 type RPCInboundFnAndThen<P, TSocket> = fn(
-    (Option<<P as RPCProtocol>::Request>, InboundFramed<P, TSocket>),
+    (Option<(<P as RPCProtocol>::Request, usize)>, InboundFramed<P, TSocket>),
 ) -> FutureResult<InboundOutput<P, TSocket>, RPCError>;
 type RPCInboundFnMapErr<P, TSocket> = fn(timeout::Error<(<<P as RPCProtocol>::InboundCodec as Decoder>::Error, InboundFramed<P, TSocket>)>) -> RPCError;
 
@@ -82,10 +97,8 @@ impl<P, TSocket> InboundUpgrade<TSocket> for RPCInbound<P> where
             .map_err(RPCError::from as RPCInboundFnMapErr<P, TSocket>)
             .and_then({
                 |(req, stream)| match req {
-                    Some(req) => futures::future::ok((req, stream)),
-                    None => futures::future::err(RPCError::Custom(
-                        "Stream terminated early".into(),
-                    )),
+                    Some((req, received)) => futures::future::ok((req, received, stream)),
+                    None => futures::future::err(RPCError::StreamClosed),
                 }
             } as RPCInboundFnAndThen<P, TSocket>)
 	}
@@ -101,7 +114,7 @@ impl<P: RPCProtocol> UpgradeInfo for RPCOutbound<P> {
 	type InfoIter = Vec<P::Type>;
 
 	fn protocol_info(&self) -> Self::InfoIter {
-		vec![self.0.typ()]
+		self.1.outbound_protocols(&self.0)
 	}
 }
 
@@ -124,7 +137,7 @@ impl<P, TSocket> OutboundUpgrade<TSocket> for RPCOutbound<P> where
         socket: Negotiated<TSocket>,
         protocol: P::Type,
     ) -> Self::Future {
-		let codec = self.1.outbound_codec(protocol);
+		let codec = self.1.outbound_codec(protocol, &self.0);
 		Framed::new(socket, codec).send(self.0)
 			.map_err(|e| {
 				warn!("Outbound upgrade codec error: {:?}", e);
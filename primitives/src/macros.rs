@@ -72,8 +72,8 @@ macro_rules! impl_beacon_fixed_hash {
 		}
 
 		impl ssz::Decode for $t {
-			fn decode(value: &[u8]) -> Result<Self, ssz::Error> {
-				Ok(<$t>::from_slice(Compact::<GenericArray<u8, $size_t>>::decode(value)?.0.as_slice()))
+			fn decode_with(value: &[u8], context: ssz::DecodeContext) -> Result<Self, ssz::Error> {
+				Ok(<$t>::from_slice(Compact::<GenericArray<u8, $size_t>>::decode_with(value, context)?.0.as_slice()))
 			}
 		}
 
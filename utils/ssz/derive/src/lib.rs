@@ -176,11 +176,11 @@ pub fn decode_derive(input: TokenStream) -> TokenStream {
 
 			let decode = if has_attribute("bm", &f.attrs, "compact") {
 				quote_spanned! { f.span() => {
-					<ssz::Compact<#ty> as ssz::Decode>::decode(item)?.0
+					<ssz::Compact<#ty> as ssz::Decode>::decode_with(item, context)?.0
 				} }
 			} else {
 				quote_spanned! { f.span() => {
-					<#ty as ssz::Decode>::decode(item)?
+					<#ty as ssz::Decode>::decode_with(item, context)?
 				} }
 			};
 
@@ -207,7 +207,11 @@ pub fn decode_derive(input: TokenStream) -> TokenStream {
 
 	let expanded = quote! {
 		impl #impl_generics ssz::Decode for #name #ty_generics where #where_clause #(#where_fields),* {
-			fn decode(value: &[u8]) -> Result<Self, ssz::Error> {
+			fn decode_with(
+				value: &[u8],
+				context: ssz::DecodeContext,
+			) -> Result<Self, ssz::Error> {
+				let context = context.enter(value)?;
 				let types = [#(#size_fields),*];
 				let series = ssz::Series::decode_vector(value, &types)?;
 				Ok(Self {
@@ -1,4 +1,4 @@
-use crate::{Encode, Decode, Error, Codec};
+use crate::{Encode, Decode, DecodeContext, Error, Codec};
 
 macro_rules! impl_builtin_uint {
 	( $t:ty, $len:ty ) => {
@@ -14,7 +14,8 @@ macro_rules! impl_builtin_uint {
 		}
 
 		impl Decode for $t {
-			fn decode(value: &[u8]) -> Result<Self, Error> {
+			fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+				context.enter(value)?;
 				let mut bytes = <$t>::default().to_le_bytes();
 				if value.len() != bytes.len() {
 					return Err(Error::IncorrectSize)
@@ -47,8 +48,8 @@ impl Encode for bool {
 }
 
 impl Decode for bool {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let value = u8::decode(value)?;
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let value = u8::decode_with(value, context)?;
 		match value {
 			0x01 => Ok(true),
 			0x00 => Ok(false),
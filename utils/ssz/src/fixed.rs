@@ -1,4 +1,4 @@
-use crate::{Encode, Error, Decode, Compact, CompactRef, Add, Mul, Div, Codec};
+use crate::{Encode, Error, Decode, DecodeContext, Compact, CompactRef, Add, Mul, Div, Codec};
 use crate::utils::{encode_list, decode_list};
 use generic_array::{GenericArray, ArrayLength};
 use vecarray::VecArray;
@@ -40,8 +40,8 @@ macro_rules! impl_builtin_fixed_uint_vector {
 		impl<L: ArrayLength<$t>> Decode for Compact<GenericArray<$t, L>> where
 			Compact<GenericArray<$t, L>>: Codec,
 		{
-			fn decode(value: &[u8]) -> Result<Self, Error> {
-				let decoded = decode_list::<$t>(value)?;
+			fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+				let decoded = decode_list::<$t>(value, Some(L::to_usize()), context)?;
 				if decoded.len() != L::to_usize() {
 					return Err(Error::InvalidLength)
 				}
@@ -84,8 +84,8 @@ macro_rules! impl_builtin_fixed_uint_vector {
 		impl<L: Unsigned> Decode for Compact<VecArray<$t, L>> where
 			Compact<VecArray<$t, L>>: Codec,
 		{
-			fn decode(value: &[u8]) -> Result<Self, Error> {
-				let decoded = decode_list::<$t>(value)?;
+			fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+				let decoded = decode_list::<$t>(value, Some(L::to_usize()), context)?;
 				if decoded.len() != L::to_usize() {
 					return Err(Error::InvalidLength)
 				}
@@ -137,7 +137,8 @@ impl<L: ArrayLength<bool>> Encode for Compact<GenericArray<bool, L>> where
 impl<L: ArrayLength<bool>> Decode for Compact<GenericArray<bool, L>> where
 	Compact<GenericArray<bool, L>>: Codec,
 {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		context.enter(value)?;
 		let len = L::to_usize();
 		let mut ret = GenericArray::default();
 		for i in 0..len {
@@ -165,8 +166,8 @@ impl<T: Encode, L: ArrayLength<T>> Encode for GenericArray<T, L> where
 impl<T: Decode, L: ArrayLength<T>> Decode for GenericArray<T, L> where
 	GenericArray<T, L>: Codec
 {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let decoded = decode_list::<T>(value)?;
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let decoded = decode_list::<T>(value, Some(L::to_usize()), context)?;
 
 		GenericArray::from_exact_iter(decoded).ok_or(Error::InvalidLength)
 	}
@@ -208,7 +209,8 @@ impl<L: Unsigned> Encode for Compact<VecArray<bool, L>> where
 impl<L: Unsigned> Decode for Compact<VecArray<bool, L>> where
 	Compact<VecArray<bool, L>>: Codec,
 {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		context.enter(value)?;
 		let len = L::to_usize();
 		let mut ret = VecArray::default();
 		for i in 0..len {
@@ -236,8 +238,8 @@ impl<T: Encode, L: Unsigned> Encode for VecArray<T, L> where
 impl<T: Decode, L: Unsigned> Decode for VecArray<T, L> where
 	VecArray<T, L>: Codec
 {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let decoded = decode_list::<T>(value)?;
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let decoded = decode_list::<T>(value, Some(L::to_usize()), context)?;
 
 		VecArray::try_from(decoded).map_err(|_| Error::InvalidLength)
 	}
@@ -254,8 +256,8 @@ impl Encode for H256 {
 }
 
 impl Decode for H256 {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let decoded = Compact::<GenericArray<u8, typenum::U32>>::decode(value)?;
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let decoded = Compact::<GenericArray<u8, typenum::U32>>::decode_with(value, context)?;
 		Ok(H256::from_slice(decoded.0.as_slice()))
 	}
 }
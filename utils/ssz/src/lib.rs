@@ -47,6 +47,12 @@ pub enum Error {
 	InvalidLength,
 	/// List length is too large.
 	ListTooLarge,
+	/// Input exceeds the maximum size of the decode context.
+	TooLarge,
+	/// Nesting exceeds the maximum depth of the decode context.
+	TooDeep,
+	/// Variable-sized item offset is out of range.
+	InvalidOffset,
 	/// Other errors.
 	Other(&'static str),
 }
@@ -83,10 +89,63 @@ pub trait Encode: Codec {
 	}
 }
 
+/// Limits enforced when decoding untrusted input.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct DecodeContext {
+	max_size: usize,
+	max_depth: usize,
+	depth: usize,
+}
+
+impl Default for DecodeContext {
+	fn default() -> Self {
+		Self::new(usize::max_value(), usize::max_value())
+	}
+}
+
+impl DecodeContext {
+	/// Create a new decode context, with the maximum total input size in
+	/// bytes, and the maximum nesting depth of composite values.
+	pub const fn new(max_size: usize, max_depth: usize) -> Self {
+		Self { max_size, max_depth, depth: 0 }
+	}
+
+	/// Maximum total input size in bytes.
+	pub fn max_size(&self) -> usize {
+		self.max_size
+	}
+
+	/// Maximum nesting depth.
+	pub fn max_depth(&self) -> usize {
+		self.max_depth
+	}
+
+	/// Check `value` against the limits, and return the context for decoding
+	/// its items.
+	pub fn enter(&self, value: &[u8]) -> Result<Self, Error> {
+		if value.len() > self.max_size {
+			return Err(Error::TooLarge)
+		}
+		if self.depth >= self.max_depth {
+			return Err(Error::TooDeep)
+		}
+		Ok(Self { depth: self.depth + 1, ..*self })
+	}
+}
+
 /// Trait that allows zero-copy read of value-references from slices in ssz format.
+///
+/// Implementations check `value` against the context, and composite types
+/// pass the context on to their items.
 pub trait Decode: Codec + Sized {
-	/// Attempt to deserialise the value from input.
-	fn decode(value: &[u8]) -> Result<Self, Error>;
+	/// Attempt to deserialise the value from input, within default limits.
+	fn decode(value: &[u8]) -> Result<Self, Error> {
+		Self::decode_with(value, DecodeContext::default())
+	}
+
+	/// Attempt to deserialise the value from input, within limits of the
+	/// given context.
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error>;
 }
 
 /// Type for length offset used for variable-sized item placeholder.
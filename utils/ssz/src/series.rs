@@ -58,6 +58,14 @@ impl Series {
 	/// Decode raw bytes as a ssz vector, with given types. The length of types
 	/// must equal to the length of values in the vector.
 	pub fn decode_vector(value: &[u8], typs: &[Option<usize>]) -> Result<Self, Error> {
+		let offset_len = LengthOffset::default().using_encoded(|buf| buf.len());
+		let fixed_parts_size = typs.iter().fold(0, |acc, typ| {
+			acc + typ.unwrap_or(offset_len)
+		});
+		if fixed_parts_size > value.len() {
+			return Err(Error::IncorrectSize)
+		}
+
 		let mut ret = Vec::new();
 		let mut variable_offsets = VecDeque::new();
 
@@ -72,13 +80,24 @@ impl Series {
 				},
 				None => {
 					ret.push(SeriesItem::Variable(Default::default()));
-					let len = LengthOffset::default().using_encoded(|buf| buf.len());
-					variable_offsets.push_back(LengthOffset::decode(&value[pos..(pos + len)])? as usize);
-					pos += len;
+					let offset = LengthOffset::decode(&value[pos..(pos + offset_len)])? as usize;
+					let valid = match variable_offsets.back() {
+						Some(last) => offset >= *last && offset <= value.len(),
+						None => offset == fixed_parts_size,
+					};
+					if !valid {
+						return Err(Error::InvalidOffset)
+					}
+					variable_offsets.push_back(offset);
+					pos += offset_len;
 				},
 			}
 		}
 
+		if variable_offsets.is_empty() && fixed_parts_size != value.len() {
+			return Err(Error::IncorrectSize)
+		}
+
 		for part in &mut ret {
 			match part {
 				SeriesItem::Fixed(_) => (),
@@ -97,45 +116,66 @@ impl Series {
 		Ok(Self(ret))
 	}
 
-	/// Decode raw bytes as a ssz list, with the given type.
-	pub fn decode_list(value: &[u8], typ: Option<usize>) -> Result<Self, Error> {
-		let mut ret = Vec::new();
+	/// Decode raw bytes as a ssz list, with the given type. Fails without
+	/// decoding any items if the list has more than `max_len` items.
+	pub fn decode_list(
+		value: &[u8],
+		typ: Option<usize>,
+		max_len: Option<usize>,
+	) -> Result<Self, Error> {
+		let check_len = |len: usize| match max_len {
+			Some(max_len) if len > max_len => Err(Error::ListTooLarge),
+			_ => Ok(()),
+		};
 
 		match typ {
+			Some(0) => Err(Error::InvalidType),
 			Some(fixed_len) => {
-				let mut pos = 0;
-
-				while pos + fixed_len <= value.len() {
-					ret.push(
-						SeriesItem::Fixed(value[pos..(pos + fixed_len)].to_vec())
-					);
-					pos += fixed_len;
+				if value.len() % fixed_len != 0 {
+					return Err(Error::IncorrectSize)
 				}
+				check_len(value.len() / fixed_len)?;
+
+				Ok(Self(value.chunks(fixed_len)
+					.map(|chunk| SeriesItem::Fixed(chunk.to_vec()))
+					.collect()))
 			},
 			None => {
-				let mut pos = 0;
-				let mut variable_offsets = VecDeque::new();
-				let fixed_len = LengthOffset::default().using_encoded(|buf| buf.len());
+				if value.is_empty() {
+					return Ok(Self(Vec::new()))
+				}
 
-				while pos + fixed_len <= value.len() &&
-					variable_offsets.front().map(|f| pos + fixed_len <= *f).unwrap_or(true)
+				let offset_len = LengthOffset::default().using_encoded(|buf| buf.len());
+				if value.len() < offset_len {
+					return Err(Error::IncorrectSize)
+				}
+				let first_offset = LengthOffset::decode(&value[0..offset_len])? as usize;
+				if first_offset == 0 || first_offset % offset_len != 0 ||
+					first_offset > value.len()
 				{
-					variable_offsets.push_back(LengthOffset::decode(&value[pos..(pos + fixed_len)])? as usize);
-					pos += fixed_len;
+					return Err(Error::InvalidOffset)
+				}
+				let len = first_offset / offset_len;
+				check_len(len)?;
+
+				let mut offsets = Vec::with_capacity(len);
+				for i in 0..len {
+					let pos = i * offset_len;
+					let offset = LengthOffset::decode(&value[pos..(pos + offset_len)])? as usize;
+					if offset > value.len() || offsets.last().map(|last| offset < *last).unwrap_or(false) {
+						return Err(Error::InvalidOffset)
+					}
+					offsets.push(offset);
 				}
 
-				while let Some(offset) = variable_offsets.pop_front() {
-					let offset = offset as usize;
-					let next_offset = variable_offsets.front().map(|v| *v as usize)
-						.unwrap_or(value.len());
-
-					ret.push(
-						SeriesItem::Variable(value[offset..next_offset].to_vec())
-					);
+				let mut ret = Vec::with_capacity(len);
+				for i in 0..len {
+					let next_offset = offsets.get(i + 1).cloned().unwrap_or(value.len());
+					ret.push(SeriesItem::Variable(value[offsets[i]..next_offset].to_vec()));
 				}
+
+				Ok(Self(ret))
 			},
 		}
-
-		Ok(Self(ret))
 	}
 }
@@ -1,4 +1,4 @@
-use crate::{Codec, Encode, Decode, Error, SeriesItem, Series, Size};
+use crate::{Codec, Encode, Decode, DecodeContext, Error, SeriesItem, Series, Size};
 use alloc::vec::Vec;
 
 pub fn encode_list<T: Encode>(
@@ -17,23 +17,26 @@ pub fn encode_list<T: Encode>(
 
 pub fn decode_list<T: Decode>(
 	value: &[u8],
+	max_len: Option<usize>,
+	context: DecodeContext,
 ) -> Result<Vec<T>, Error> {
+	let context = context.enter(value)?;
 	let value_typ = <T as Codec>::Size::size();
-	let series = Series::decode_list(value, value_typ)?;
-	let mut ret = Vec::new();
+	let series = Series::decode_list(value, value_typ, max_len)?;
+	let mut ret = Vec::with_capacity(series.0.len());
 
 	for part in series.0 {
 		match part {
 			SeriesItem::Fixed(fixed) => {
 				if <T as Codec>::Size::is_fixed() {
-					ret.push(T::decode(&fixed)?);
+					ret.push(T::decode_with(&fixed, context)?);
 				} else {
 					return Err(Error::InvalidType)
 				}
 			},
 			SeriesItem::Variable(variable) => {
 				if <T as Codec>::Size::is_variable() {
-					ret.push(T::decode(&variable)?);
+					ret.push(T::decode_with(&variable, context)?);
 				} else {
 					return Err(Error::InvalidType)
 				}
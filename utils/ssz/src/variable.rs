@@ -1,4 +1,4 @@
-use crate::{Encode, Decode, Error, Codec, VariableSize, MaxVec, Compact, CompactRef};
+use crate::{Encode, Decode, DecodeContext, Error, Codec, VariableSize, MaxVec, Compact, CompactRef};
 use crate::utils::{encode_list, decode_list};
 use typenum::Unsigned;
 
@@ -29,11 +29,8 @@ macro_rules! impl_builtin_variable_uint_list {
 		}
 
 		impl<ML: Unsigned> Decode for Compact<MaxVec<$t, ML>> {
-			fn decode(value: &[u8]) -> Result<Self, Error> {
-				let decoded = decode_list(value)?;
-				if decoded.len() > ML::to_usize() {
-					return Err(Error::ListTooLarge)
-				}
+			fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+				let decoded = decode_list(value, Some(ML::to_usize()), context)?;
 				Ok(Compact(MaxVec::from(decoded)))
 			}
 		}
@@ -55,10 +52,14 @@ impl<ML> Encode for Compact<MaxVec<bool, ML>> {
 }
 
 impl<ML: Unsigned> Decode for Compact<MaxVec<bool, ML>> {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let decoded = <Compact<Vec<bool>>>::decode(value)?.0;
-
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		context.enter(value)?;
 		let max_len = ML::to_usize();
+		if value.len() > (max_len + 1 + 7) / 8 {
+			return Err(Error::ListTooLarge)
+		}
+
+		let decoded = <Compact<Vec<bool>>>::decode(value)?.0;
 		if decoded.len() > max_len {
 			return Err(Error::ListTooLarge)
 		}
@@ -74,12 +75,8 @@ impl<T: Encode, ML> Encode for MaxVec<T, ML> {
 }
 
 impl<T: Decode, ML: Unsigned> Decode for MaxVec<T, ML> {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let ret = decode_list::<T>(value)?;
-
-		if ret.len() > ML::to_usize() {
-			return Err(Error::ListTooLarge)
-		}
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let ret = decode_list::<T>(value, Some(ML::to_usize()), context)?;
 		Ok(MaxVec::from(ret))
 	}
 }
@@ -111,8 +108,8 @@ macro_rules! impl_builtin_variable_uint_list {
 		}
 
 		impl Decode for Compact<Vec<$t>> {
-			fn decode(value: &[u8]) -> Result<Self, Error> {
-				let decoded = decode_list(value)?;
+			fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+				let decoded = decode_list(value, None, context)?;
 				Ok(Compact(decoded))
 			}
 		}
@@ -141,8 +138,13 @@ impl Encode for Compact<Vec<bool>> {
 }
 
 impl Decode for Compact<Vec<bool>> {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let len = (value.len() - 1) * 8 + (7 - value[value.len() - 1].leading_zeros() as usize);
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		context.enter(value)?;
+		let last = match value.last() {
+			Some(last) if *last != 0 => *last,
+			_ => return Err(Error::IncorrectSize),
+		};
+		let len = (value.len() - 1) * 8 + (7 - last.leading_zeros() as usize);
 		let mut ret = Vec::new();
 		for i in 0..len {
 			if i / 8 >= value.len() {
@@ -161,8 +163,8 @@ impl<T: Encode> Encode for Vec<T> {
 }
 
 impl<T: Decode> Decode for Vec<T> {
-	fn decode(value: &[u8]) -> Result<Self, Error> {
-		let ret = decode_list::<T>(value)?;
+	fn decode_with(value: &[u8], context: DecodeContext) -> Result<Self, Error> {
+		let ret = decode_list::<T>(value, None, context)?;
 		Ok(ret)
 	}
 }
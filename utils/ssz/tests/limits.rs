@@ -0,0 +1,48 @@
+use ssz::{Encode, Decode, DecodeContext, Error, MaxVec, Compact};
+use typenum::*;
+
+#[test]
+fn list_limit_is_enforced() {
+	let value = (0..4u64).collect::<Vec<_>>().encode();
+	assert!(<MaxVec<u64, U4>>::decode(&value).is_ok());
+	match <MaxVec<u64, U3>>::decode(&value) {
+		Err(Error::ListTooLarge) => (),
+		other => panic!("Unexpected result {:?}", other),
+	}
+
+	let value = vec![vec![1u8], vec![2u8], vec![3u8]].encode();
+	match <MaxVec<Vec<u8>, U2>>::decode(&value) {
+		Err(Error::ListTooLarge) => (),
+		other => panic!("Unexpected result {:?}", other),
+	}
+}
+
+#[test]
+fn context_limits_are_enforced() {
+	let value = vec![vec![1u8, 2u8], vec![3u8]].encode();
+
+	assert!(<Vec<Vec<u8>>>::decode_with(&value, DecodeContext::new(value.len(), 3)).is_ok());
+	match <Vec<Vec<u8>>>::decode_with(&value, DecodeContext::new(value.len() - 1, 3)) {
+		Err(Error::TooLarge) => (),
+		other => panic!("Unexpected result {:?}", other),
+	}
+	match <Vec<Vec<u8>>>::decode_with(&value, DecodeContext::new(value.len(), 2)) {
+		Err(Error::TooDeep) => (),
+		other => panic!("Unexpected result {:?}", other),
+	}
+}
+
+#[test]
+fn malformed_input_is_rejected() {
+	// Offset pointing past the end of input.
+	assert!(<Vec<Vec<u8>>>::decode(&[0x04, 0x00, 0x00, 0xff]).is_err());
+	// Offset not aligned to the offset size.
+	assert!(<Vec<Vec<u8>>>::decode(&[0x03, 0x00, 0x00, 0x00]).is_err());
+	// Trailing bytes of a fixed-sized list.
+	assert!(<Vec<u16>>::decode(&[0x01, 0x00, 0x02]).is_err());
+	// Bitlist without the length bit.
+	assert!(<Compact<Vec<bool>>>::decode(&[]).is_err());
+	assert!(<Compact<Vec<bool>>>::decode(&[0x00]).is_err());
+	// Bitlist longer than its limit.
+	assert!(<Compact<MaxVec<bool, U8>>>::decode(&[0xff, 0xff, 0x01]).is_err());
+}